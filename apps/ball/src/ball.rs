@@ -41,6 +41,7 @@ impl Ball {
             gotinput_id: None,
             audioframe_id: None,
             focuschange_id: Some(AppOp::FocusChange.to_u32().unwrap()),
+            lifecycle_id: None,
             rawkeys_id: Some(AppOp::Rawkeys.to_u32().unwrap()),
         }).expect("couldn't register Ux context for shellchat");
 
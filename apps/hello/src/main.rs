@@ -47,6 +47,7 @@ impl Hello {
                 audioframe_id: None,
                 rawkeys_id: None,
                 focuschange_id: None,
+                lifecycle_id: None,
             })
             .expect("Could not register GAM UX")
             .unwrap();
@@ -43,7 +43,7 @@ use trng::*;
 pub struct CommonEnv {
     llio: llio::Llio,
     com: com::Com,
-    codec: codec::Codec,
+    mixer: mixer::Mixer,
     ticktimer: ticktimer_server::Ticktimer,
     gam: gam::Gam,
     cb_registrations: HashMap::<u32, String::<256>>,
@@ -93,7 +93,7 @@ impl CmdEnv {
         let common = CommonEnv {
             llio: llio::Llio::new(&xns),
             com: com::Com::new(&xns).expect("could't connect to COM"),
-            codec: codec::Codec::new(&xns).expect("couldn't connect to CODEC"),
+            mixer: mixer::Mixer::new(&xns).expect("couldn't connect to the mixer"),
             ticktimer,
             gam: gam::Gam::new(&xns).expect("couldn't connect to GAM"),
             cb_registrations: HashMap::new(),
@@ -185,6 +185,17 @@ impl CmdEnv {
     }
 }
 
+/// lets scripts, aliases and pipelines run REPL commands
+impl shell_script::Host for CmdEnv {
+    fn run(&mut self, line: &str) -> Result<Option<std::string::String>, xous::Error> {
+        let ret = self.dispatch(Some(&mut String::<1024>::from_str(line)), None)?;
+        Ok(ret.map(|r| std::string::String::from(r.as_str().unwrap_or("UTF-8 Error"))))
+    }
+    fn sleep_ms(&mut self, ms: usize) {
+        self.common_env.ticktimer.sleep_ms(ms).unwrap();
+    }
+}
+
 /// extract the first token, as delimited by spaces
 /// modifies the incoming line by removing the token and returning the remainder
 /// returns the found token
@@ -1,12 +1,13 @@
 use crate::{ShellCmdApi,CommonEnv};
 use xous_ipc::String;
 use xous::{MessageEnvelope, Message};
-use codec::*;
+use mixer::{Channels, FIFO_DEPTH, MixerStream, Priority, SampleFormat, StreamFormat};
 use core::fmt::Write;
 use locales::t;
 
 #[derive(Debug)]
 pub struct Audio {
+    stream: Option<MixerStream>,
     callback_id: Option<u32>,
     callback_conn: u32,
     framecount: u32,
@@ -17,6 +18,7 @@ impl Audio {
     pub fn new(xns: &xous_names::XousNames) -> Self {
         let callback_conn = xns.request_connection_blocking(crate::SERVER_NAME_REPL).unwrap();
         Audio {
+            stream: None,
             callback_id: None,
             callback_conn,
             framecount: 0,
@@ -26,7 +28,7 @@ impl Audio {
     }
 }
 
-const STOP_ID: usize = 1;
+const STOP_ID: usize = 2; // distinct from mixer::MIXER_CB_ROUTING_ID
 const SAMPLE_RATE_HZ: f32 = 8000.0;
 // note to self: A4 = 440.0, E4 = 329.63, C4 = 261.63
 
@@ -58,22 +60,22 @@ impl<'a> ShellCmdApi<'a> for Audio {
                         duration = 10.0; // sanity check the duration so we don't go nuts
                     }
 
-                    env.codec.setup_8k_stream().expect("couldn't set the CODEC to expected defaults");
-                    env.ticktimer.sleep_ms(50).unwrap();
-
-                    env.codec.set_speaker_volume(VolumeOps::RestoreDefault, None).unwrap();
-                    env.codec.set_headphone_volume(VolumeOps::RestoreDefault, None).unwrap();
-
-                    if self.callback_id.is_none() {
-                        let cb_id = env.register_handler(String::<256>::from_str(self.verb()));
+                    if self.stream.is_none() {
+                        let mut stream = env.mixer.open_stream(StreamFormat::new(SAMPLE_RATE_HZ as u32, Channels::Stereo, SampleFormat::I16), Priority::Normal, 100)
+                            .expect("couldn't open a mixer stream");
+                        let cb_id = match self.callback_id {
+                            Some(id) => id,
+                            None => env.register_handler(String::<256>::from_str(self.verb())),
+                        };
                         log::trace!("hooking frame callback with ID {}", cb_id);
-                        env.codec.hook_frame_callback(cb_id, self.callback_conn).unwrap(); // any non-handled IDs get routed to our callback port
+                        stream.hook_frame_callback(cb_id, self.callback_conn).unwrap(); // any non-handled IDs get routed to our callback port
                         self.callback_id = Some(cb_id);
+                        self.stream = Some(stream);
                     }
 
                     self.play_sample = 0.0;
 
-                    env.codec.resume().unwrap();
+                    self.stream.as_ref().unwrap().resume().unwrap();
 
                     // kick off a thread that stops the playback, after the designated delay
                     std::thread::spawn({
@@ -97,13 +99,17 @@ impl<'a> ShellCmdApi<'a> for Audio {
         Ok(Some(ret))
     }
 
-    fn callback(&mut self, msg: &MessageEnvelope, env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+    fn callback(&mut self, msg: &MessageEnvelope, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
         const AMPLITUDE: f32 = 0.8;
 
         match &msg.body {
-            Message::Scalar(xous::ScalarMessage{id: _, arg1: free_play, arg2: _avail_rec, arg3: _, arg4: routing_id}) => {
-                if *routing_id == codec::AUDIO_CB_ROUTING_ID {
-                    let mut frames: FrameRing = FrameRing::new();
+            Message::Scalar(xous::ScalarMessage{id: _, arg1: free_play, arg2: _, arg3: _, arg4: routing_id}) => {
+                if *routing_id == mixer::MIXER_CB_ROUTING_ID {
+                    let stream = match self.stream.as_mut() {
+                        Some(stream) => stream,
+                        None => return Ok(None), // a callback that was in flight when the stream was closed
+                    };
+                    let mut frames = stream.frame_ring();
                     let frames_to_push = if frames.writeable_count() < *free_play {
                         frames.writeable_count()
                     } else {
@@ -113,7 +119,7 @@ impl<'a> ShellCmdApi<'a> for Audio {
 
                     log::debug!("f{} p{}", self.framecount, frames_to_push);
                     for _ in 0..frames_to_push {
-                        let mut frame: [u32; codec::FIFO_DEPTH] = [ZERO_PCM as u32 | (ZERO_PCM as u32) << 16; codec::FIFO_DEPTH];
+                        let mut frame: [u32; FIFO_DEPTH] = [0; FIFO_DEPTH];
                         // put the "expensive" f32 comparison outside the cosine wave table computation loop
                         let omega = self.freq * 2.0 * std::f32::consts::PI / SAMPLE_RATE_HZ;
                         for sample in frame.iter_mut() {
@@ -127,10 +133,10 @@ impl<'a> ShellCmdApi<'a> for Audio {
                         frames.nq_frame(frame).unwrap();
 
                     }
-                    env.codec.swap_frames(&mut frames).unwrap();
+                    stream.write_frames(&mut frames).unwrap();
                 } else if *routing_id == STOP_ID {
                     let mut ret = String::<1024>::new();
-                    env.codec.abort().unwrap(); // this should stop callbacks from occurring too.
+                    self.stream.take(); // closing the stream stops callbacks from occurring too.
                     write!(ret, "{} {} {}.",
                         t!("replapp.audio.completion_a", xous::LANG),
                         self.framecount,
@@ -138,7 +144,6 @@ impl<'a> ShellCmdApi<'a> for Audio {
                     ).unwrap();
                     self.framecount = 0;
                     self.play_sample = 0.0;
-                    env.codec.power_off().unwrap();
                     return Ok(Some(ret));
                 }
             },
@@ -16,6 +16,8 @@ pub(crate) enum ReplOp {
     Redraw,
     /// change focus
     ChangeFocus,
+    /// app lifecycle events from the GAM
+    Lifecycle,
     /// exit the application
     Quit,
 }
@@ -66,6 +68,18 @@ fn xmain() -> ! {
                     }
                 }
             }),
+            Some(ReplOp::Lifecycle) => xous::msg_scalar_unpack!(msg, event_code, _, _, _, {
+                match gam::LifecycleEvent::from_code(event_code) {
+                    Some(gam::LifecycleEvent::Background) => {
+                        repl.save_state().unwrap_or_else(|e| log::warn!("couldn't save repl state: {:?}", e));
+                    }
+                    Some(gam::LifecycleEvent::Terminate) => {
+                        repl.save_state().unwrap_or_else(|e| log::warn!("couldn't save repl state: {:?}", e));
+                        break;
+                    }
+                    _ => (),
+                }
+            }),
             Some(ReplOp::Quit) => {
                 log::error!("got Quit");
                 break;
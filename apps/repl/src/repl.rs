@@ -38,6 +38,8 @@ pub(crate) struct Repl {
 
     // command environment
     env: CmdEnv,
+    // sequencing, pipes, variables, loops, aliases and scripts on top of the commands
+    script: shell_script::Shell,
 
     // our security token for making changes to our record on the GAM
     token: [u32; 4],
@@ -46,24 +48,37 @@ impl Repl{
     pub(crate) fn new(xns: &xous_names::XousNames, sid: xous::SID) -> Self {
         let gam = gam::Gam::new(xns).expect("can't connect to GAM");
 
+        // CJK text is typed as pinyin or romaji, and converted from the prediction bar; otherwise
+        // the prediction bar recalls earlier commands
+        let predictor = match xous::LANG {
+            "zh" => ime_plugin_cjk::SERVER_NAME_IME_PLUGIN_PINYIN,
+            "ja" => ime_plugin_cjk::SERVER_NAME_IME_PLUGIN_KANA,
+            _ => ime_plugin_shell::SERVER_NAME_IME_PLUGIN_SHELL,
+        };
         let token = gam.register_ux(UxRegistration {
             app_name: xous_ipc::String::<128>::from_str(gam::APP_NAME_REPL),
             ux_type: gam::UxType::Chat,
-            predictor: Some(xous_ipc::String::<64>::from_str(ime_plugin_shell::SERVER_NAME_IME_PLUGIN_SHELL)),
+            predictor: Some(xous_ipc::String::<64>::from_str(predictor)),
             listener: sid.to_array(), // note disclosure of our SID to the GAM -- the secret is now shared with the GAM!
             redraw_id: ReplOp::Redraw.to_u32().unwrap(),
             gotinput_id: Some(ReplOp::Line.to_u32().unwrap()),
             audioframe_id: None,
             rawkeys_id: None,
             focuschange_id: Some(ReplOp::ChangeFocus.to_u32().unwrap()),
+            lifecycle_id: Some(ReplOp::Lifecycle.to_u32().unwrap()),
         }).expect("couldn't register Ux context for repl");
 
         let content = gam.request_content_canvas(token.unwrap()).expect("couldn't get content canvas");
         let screensize = gam.get_canvas_bounds(content).expect("couldn't get dimensions of content canvas");
+        // if we were terminated and relaunched, pick up where we left off
+        let history = match gam.restore_app_state(token.unwrap()) {
+            Ok(Some(state)) => Repl::history_from_state(&state),
+            _ => vec![History{text: String::from(t!("replapp.greeting", xous::LANG)), is_input: false}],
+        };
         Repl {
             input: None,
             msg: None,
-            history: vec![History{text: String::from(t!("replapp.greeting", xous::LANG)), is_input: false}],
+            history,
             history_len: 10,
             content,
             gam,
@@ -74,6 +89,7 @@ impl Repl{
             bubble_radius: 4,
             bubble_space: 4,
             env: CmdEnv::new(xns),
+            script: shell_script::Shell::new(),
             token: token.unwrap(),
         }
     }
@@ -89,6 +105,32 @@ impl Repl{
         self.msg = Some(message);
     }
 
+    /// Parks the history with the GAM. Each entry is stored as a line, prefixed with '>' for input or '<' for output;
+    /// the oldest entries are dropped if the history doesn't fit.
+    pub(crate) fn save_state(&self) -> Result<(), xous::Error> {
+        let mut state = String::new();
+        for h in self.history.iter().rev() {
+            let line = format!("{}{}\n", if h.is_input {'>'} else {'<'}, h.text.replace('\n', " "));
+            if state.len() + line.len() > gam::MAX_APP_STATE_LEN {
+                break;
+            }
+            state.insert_str(0, &line);
+        }
+        self.gam.save_app_state(self.token, state.as_bytes())
+    }
+    fn history_from_state(state: &[u8]) -> Vec::<History> {
+        let mut history = Vec::new();
+        for line in String::from_utf8_lossy(state).lines() {
+            let mut chars = line.chars();
+            match chars.next() {
+                Some('>') => history.push(History{text: String::from(chars.as_str()), is_input: true}),
+                Some('<') => history.push(History{text: String::from(chars.as_str()), is_input: false}),
+                _ => (),
+            }
+        }
+        history
+    }
+
     fn circular_push(&mut self, item: History) {
         if self.history.len() >= self.history_len {
             self.history.remove(0);
@@ -119,9 +161,9 @@ impl Repl{
         let mut dirty = true;
         // take the input and pass it on to the various command parsers, and attach result
         if let Some(local) = &self.input {
-            if let Some(res) = self.env.dispatch(Some(&mut xous_ipc::String::<1024>::from_str(&local)), None).expect("command dispatch failed") {
+            if let Some(res) = self.script.run(local, &mut self.env) {
                 let output_history = History {
-                    text: String::from(res.as_str().unwrap_or("UTF-8 Error")),
+                    text: res,
                     is_input: false
                 };
                 self.circular_push(output_history);
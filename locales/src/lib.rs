@@ -1,3 +1,6 @@
 #![cfg_attr(target_os = "none", no_std)]
 pub mod generated;
 pub use generated::*;
+
+#[cfg(feature = "wordlists")]
+pub mod wordlists;
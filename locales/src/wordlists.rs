@@ -0,0 +1,11 @@
+//! Seed word lists for predictive text, one word per line, most common first.
+//!
+//! These only get a predictor started: the user's own words soon outrank them.
+
+/// Returns the seed word list for `lang`, if there is one.
+pub fn seed_words(lang: &str) -> Option<&'static str> {
+    match lang {
+        "en" | "en-tts" => Some(include_str!("wordlists/en.txt")),
+        _ => None,
+    }
+}
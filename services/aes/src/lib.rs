@@ -11,6 +11,7 @@
   eliminates another foreign build.rs script that runs on the local build machine.
 */
 
+pub mod modes;
 mod soft;
 mod vex;
 
@@ -24,8 +25,27 @@ pub use soft::Aes128Soft as Aes128;
 #[cfg(not(any(target_os = "none", target_os = "xous")))]
 pub use soft::Aes256Soft as Aes256;
 
-#[cfg(feature = "ctr")]
-pub use soft::{Aes128Ctr, Aes192Ctr, Aes256Ctr};
+pub use modes::{Cbc, Ctr, Gcm, GcmSiv};
+
+/// AES-128 in CTR mode. The counter is the whole block; these were `ctr::Ctr64BE`, which only
+/// counts in the low 64 bits.
+pub type Aes128Ctr = Ctr<Aes128>;
+/// AES-192 in CTR mode
+pub type Aes192Ctr = Ctr<Aes192>;
+/// AES-256 in CTR mode
+pub type Aes256Ctr = Ctr<Aes256>;
+/// AES-128 in CBC mode with PKCS#7 padding
+pub type Aes128Cbc = Cbc<Aes128>;
+/// AES-256 in CBC mode with PKCS#7 padding
+pub type Aes256Cbc = Cbc<Aes256>;
+/// AES-128 in Galois/Counter Mode
+pub type Aes128Gcm = Gcm<Aes128>;
+/// AES-256 in Galois/Counter Mode
+pub type Aes256Gcm = Gcm<Aes256>;
+/// AES-GCM-SIV with a 128-bit key
+pub type Aes128GcmSiv = GcmSiv<Aes128>;
+/// AES-GCM-SIV with a 256-bit key
+pub type Aes256GcmSiv = GcmSiv<Aes256>;
 
 pub use cipher::{self, BlockCipher, BlockDecrypt, BlockEncrypt, NewBlockCipher};
 
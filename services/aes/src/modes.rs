@@ -0,0 +1,79 @@
+//! Block cipher modes and AEADs built on the AES block ciphers in this crate.
+//!
+//! The modes are generic over the block cipher, so with `Aes128`/`Aes256` they run on the Vex
+//! instructions on hardware and on the fixsliced soft cipher in hosted mode. Keystreams and CBC
+//! decryption are generated eight blocks at a time through `encrypt_par_blocks` and
+//! `decrypt_par_blocks`. Tags are always checked in constant time, and nothing is decrypted
+//! until the tag has been checked, except in GCM-SIV, which can only check the tag after
+//! decrypting and wipes the buffer if the check fails.
+
+mod cbc;
+mod ctr;
+mod gcm;
+mod gcm_siv;
+mod polyval;
+pub mod selftest;
+
+pub use cbc::Cbc;
+pub use ctr::Ctr;
+pub use gcm::Gcm;
+pub use gcm_siv::GcmSiv;
+
+use crate::{Block, ParBlocks, BLOCK_SIZE};
+use cipher::consts::{U16, U8};
+use cipher::{BlockCipher, BlockEncrypt};
+use zeroize::Zeroize;
+
+/// 128-bit authentication tag
+pub type Tag = [u8; 16];
+
+/// Opaque error: a tag or the padding didn't check out, or a length is out of range
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Error;
+
+/// Compares `a` and `b` in time that only depends on their lengths
+pub(crate) fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    // a volatile read keeps the compiler from turning the fold into an early exit
+    unsafe { core::ptr::read_volatile(&diff) == 0 }
+}
+
+/// XORs `data` with the encryption of successive counter blocks, starting at `counter`, eight
+/// blocks at a time. `step` advances the counter; on return `counter` is the next unused block.
+pub(crate) fn apply_keystream<C>(cipher: &C, counter: &mut Block, step: fn(&mut Block), data: &mut [u8])
+where
+    C: BlockEncrypt + BlockCipher<BlockSize = U16, ParBlocks = U8>,
+{
+    let mut blocks = ParBlocks::default();
+    for chunk in data.chunks_mut(BLOCK_SIZE * 8) {
+        let count = (chunk.len() + BLOCK_SIZE - 1) / BLOCK_SIZE;
+        for block in blocks[..count].iter_mut() {
+            *block = *counter;
+            step(counter);
+        }
+        if count == 1 {
+            cipher.encrypt_block(&mut blocks[0]);
+        } else {
+            cipher.encrypt_par_blocks(&mut blocks);
+        }
+        for (d, k) in chunk.iter_mut().zip(blocks.iter().flat_map(|b| b.iter())) {
+            *d ^= k;
+        }
+    }
+    for block in blocks.iter_mut() {
+        block.as_mut_slice().zeroize();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    #[test]
+    fn constant_time_compare() {
+        assert!(super::ct_eq(b"tag", b"tag"));
+        assert!(!super::ct_eq(b"tag", b"tab"));
+        assert!(!super::ct_eq(b"tag", b"tags"));
+    }
+}
@@ -0,0 +1,166 @@
+//! Cipher block chaining (NIST SP 800-38A) with PKCS#7 padding
+//!
+//! CBC has no integrity of its own: whether the padding checks out after a decryption leaks to
+//! whoever sent the ciphertext, so it should only see data that's been authenticated already.
+
+use super::Error;
+use crate::{Block, ParBlocks, BLOCK_SIZE};
+use cipher::consts::{U16, U8};
+use cipher::generic_array::GenericArray;
+use cipher::{BlockCipher, BlockDecrypt, BlockEncrypt, NewBlockCipher};
+
+pub struct Cbc<C> {
+    cipher: C,
+}
+
+fn xor(block: &mut [u8], other: &[u8]) {
+    for (b, o) in block.iter_mut().zip(other.iter()) {
+        *b ^= o;
+    }
+}
+
+impl<C: BlockEncrypt + BlockDecrypt + BlockCipher<BlockSize = U16, ParBlocks = U8>> Cbc<C> {
+    pub fn new(key: &GenericArray<u8, C::KeySize>) -> Self
+    where
+        C: NewBlockCipher,
+    {
+        Cbc { cipher: C::new(key) }
+    }
+
+    pub fn from_cipher(cipher: C) -> Self {
+        Cbc { cipher }
+    }
+
+    /// Pads the first `msg_len` bytes of `buffer` out to a whole number of blocks and encrypts
+    /// them in place. `buffer` needs room for the padding, which is 1 to 16 bytes. Returns the
+    /// ciphertext.
+    pub fn encrypt_padded<'a>(&self, iv: &Block, buffer: &'a mut [u8], msg_len: usize) -> Result<&'a [u8], Error> {
+        let padded_len = (msg_len / BLOCK_SIZE + 1) * BLOCK_SIZE;
+        if msg_len > buffer.len() || padded_len > buffer.len() {
+            return Err(Error);
+        }
+        let pad = (padded_len - msg_len) as u8;
+        for b in buffer[msg_len..padded_len].iter_mut() {
+            *b = pad;
+        }
+        // each block depends on the last, so encryption can't be batched
+        let mut chain = *iv;
+        for chunk in buffer[..padded_len].chunks_exact_mut(BLOCK_SIZE) {
+            xor(&mut chain, chunk);
+            self.cipher.encrypt_block(&mut chain);
+            chunk.copy_from_slice(&chain);
+        }
+        Ok(&buffer[..padded_len])
+    }
+
+    /// Decrypts `buffer` in place and checks and strips the padding. Returns the plaintext.
+    pub fn decrypt_padded<'a>(&self, iv: &Block, buffer: &'a mut [u8]) -> Result<&'a [u8], Error> {
+        let len = buffer.len();
+        if len == 0 || len % BLOCK_SIZE != 0 {
+            return Err(Error);
+        }
+        let mut chain = *iv;
+        let mut blocks = ParBlocks::default();
+        for chunk in buffer.chunks_mut(BLOCK_SIZE * 8) {
+            let count = chunk.len() / BLOCK_SIZE;
+            for (block, ct) in blocks.iter_mut().zip(chunk.chunks_exact(BLOCK_SIZE)) {
+                block.copy_from_slice(ct);
+            }
+            let next_chain = blocks[count - 1];
+            if count == 8 {
+                self.cipher.decrypt_par_blocks(&mut blocks);
+            } else {
+                for block in blocks[..count].iter_mut() {
+                    self.cipher.decrypt_block(block);
+                }
+            }
+            // XOR each block with the ciphertext block before it, back to front so the
+            // ciphertext is still in `chunk` when it's needed
+            for i in (0..count).rev() {
+                let (before, this) = chunk.split_at_mut(i * BLOCK_SIZE);
+                let previous = if i == 0 { &chain[..] } else { &before[(i - 1) * BLOCK_SIZE..] };
+                xor(&mut blocks[i], previous);
+                this[..BLOCK_SIZE].copy_from_slice(&blocks[i]);
+            }
+            chain = next_chain;
+        }
+
+        // check the padding without branching on where it ends
+        let pad = buffer[len - 1];
+        let mut bad = ((pad as u16).wrapping_sub(1) >> 8) as u8; // pad == 0
+        bad |= (16u16.wrapping_sub(pad as u16) >> 8) as u8; // pad > 16
+        for i in 0..BLOCK_SIZE {
+            let in_padding = ((i as u16).wrapping_sub(pad as u16) >> 8) as u8;
+            bad |= in_padding & (buffer[len - 1 - i] ^ pad);
+        }
+        if bad != 0 {
+            return Err(Error);
+        }
+        Ok(&buffer[..len - pad as usize])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wycheproof_records::{records, unhex};
+    use crate::{Aes128, Aes192, Aes256};
+
+    #[test]
+    fn round_trips() {
+        let key = GenericArray::clone_from_slice(&[3u8; 16]);
+        let iv = Block::clone_from_slice(&[9u8; 16]);
+        let cbc = Cbc::<Aes128>::new(&key);
+        // every padding length, and both sides of the eight-block batches
+        for len in (0..40).chain(120..140).chain(250..260) {
+            let msg: Vec<u8> = (0..len as u32).map(|i| (i * 7) as u8).collect();
+            let mut buffer = msg.clone();
+            buffer.resize(len + BLOCK_SIZE, 0);
+            let ct_len = cbc.encrypt_padded(&iv, &mut buffer, len).unwrap().len();
+            assert_eq!(ct_len, (len / BLOCK_SIZE + 1) * BLOCK_SIZE);
+            assert_eq!(cbc.decrypt_padded(&iv, &mut buffer[..ct_len]).unwrap(), &msg[..]);
+        }
+        let mut short = [0u8; 16];
+        assert_eq!(cbc.encrypt_padded(&iv, &mut short, 16), Err(Error));
+        assert!(cbc.encrypt_padded(&iv, &mut short, 15).is_ok());
+    }
+
+    #[test]
+    fn bad_padding() {
+        let key = GenericArray::clone_from_slice(&[3u8; 16]);
+        let iv = Block::clone_from_slice(&[9u8; 16]);
+        let cbc = Cbc::<Aes128>::new(&key);
+        let mut empty = [0u8; 0];
+        assert_eq!(cbc.decrypt_padded(&iv, &mut empty), Err(Error));
+        let mut ragged = [0u8; 17];
+        assert_eq!(cbc.decrypt_padded(&iv, &mut ragged), Err(Error));
+        // encrypt blocks that end in the wrong padding without adding any
+        for last in [&[0u8; 16][..], &[17u8; 16][..], &unhex("0102030405060708090a0b0c0d0e0303")[..]].iter() {
+            let mut chain = iv;
+            xor(&mut chain, last);
+            Aes128::new(&key).encrypt_block(&mut chain);
+            let mut buffer = chain.to_vec();
+            assert_eq!(cbc.decrypt_padded(&iv, &mut buffer), Err(Error));
+        }
+    }
+
+    /// Records are the result byte, then the key, IV, message and ciphertext.
+    #[test]
+    fn wycheproof() {
+        for (i, (valid, record)) in records(env!("CARGO_MANIFEST_DIR"), "aes_cbc_pkcs5_test.bin", 4).iter().enumerate() {
+            let (key, iv, msg, ct) = (&record[0], &record[1], &record[2], &record[3]);
+            let iv = Block::clone_from_slice(iv);
+            let mut buffer = ct.clone();
+            let result = match key.len() {
+                16 => Cbc::<Aes128>::new(GenericArray::from_slice(key)).decrypt_padded(&iv, &mut buffer).map(|pt| pt.to_vec()),
+                24 => Cbc::<Aes192>::new(GenericArray::from_slice(key)).decrypt_padded(&iv, &mut buffer).map(|pt| pt.to_vec()),
+                32 => Cbc::<Aes256>::new(GenericArray::from_slice(key)).decrypt_padded(&iv, &mut buffer).map(|pt| pt.to_vec()),
+                _ => panic!("record {} has a {}-byte key", i, key.len()),
+            };
+            match result {
+                Ok(pt) => assert!(*valid && pt == *msg, "record {}", i),
+                Err(Error) => assert!(!*valid, "record {}", i),
+            }
+        }
+    }
+}
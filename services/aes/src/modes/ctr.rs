@@ -0,0 +1,133 @@
+//! Counter mode (NIST SP 800-38A), with the whole block as a big-endian counter.
+//!
+//! The `Aes*Ctr` types were the `ctr` crate's `Ctr64BE`, which counts in the low 64 bits
+//! only and wraps there, leaving the high half as a fixed nonce. The two give the same keystream
+//! unless the low 64 bits of the IV overflow; from there on, this carries into the high half.
+
+use super::apply_keystream;
+use crate::{Block, BLOCK_SIZE};
+use cipher::consts::{U16, U8};
+use cipher::errors::LoopError;
+use cipher::generic_array::GenericArray;
+use cipher::{BlockCipher, BlockEncrypt, FromBlockCipher, NewBlockCipher, StreamCipher};
+use zeroize::Zeroize;
+
+pub struct Ctr<C> {
+    cipher: C,
+    counter: Block,
+    /// the keystream block that's partly been used up, if the last call ended mid-block
+    keystream: Block,
+    used: usize,
+}
+
+pub(crate) fn increment(counter: &mut Block) {
+    for b in counter.iter_mut().rev() {
+        *b = b.wrapping_add(1);
+        if *b != 0 {
+            break;
+        }
+    }
+}
+
+impl<C: BlockEncrypt + BlockCipher<BlockSize = U16, ParBlocks = U8>> Ctr<C> {
+    pub fn new(key: &GenericArray<u8, C::KeySize>, iv: &Block) -> Self
+    where
+        C: NewBlockCipher,
+    {
+        Self::from_cipher(C::new(key), iv)
+    }
+
+    pub fn from_cipher(cipher: C, iv: &Block) -> Self {
+        Ctr { cipher, counter: *iv, keystream: Block::default(), used: BLOCK_SIZE }
+    }
+
+    /// Encrypts or decrypts `data` in place. Calls continue where the last one left off.
+    pub fn apply_keystream(&mut self, data: &mut [u8]) {
+        let leftover = core::cmp::min(BLOCK_SIZE - self.used, data.len());
+        for (d, k) in data[..leftover].iter_mut().zip(self.keystream[self.used..].iter()) {
+            *d ^= k;
+        }
+        self.used += leftover;
+        let data = &mut data[leftover..];
+
+        let whole = data.len() - data.len() % BLOCK_SIZE;
+        let (blocks, tail) = data.split_at_mut(whole);
+        apply_keystream(&self.cipher, &mut self.counter, increment, blocks);
+        if !tail.is_empty() {
+            self.keystream = self.counter;
+            increment(&mut self.counter);
+            self.cipher.encrypt_block(&mut self.keystream);
+            for (d, k) in tail.iter_mut().zip(self.keystream.iter()) {
+                *d ^= k;
+            }
+            self.used = tail.len();
+        }
+    }
+}
+
+impl<C> Drop for Ctr<C> {
+    fn drop(&mut self) {
+        self.keystream.as_mut_slice().zeroize();
+    }
+}
+
+/// Lets `Ctr` stand in for the `ctr` crate's types, e.g. through `NewCipher`
+impl<C: BlockEncrypt + BlockCipher<BlockSize = U16, ParBlocks = U8>> FromBlockCipher for Ctr<C> {
+    type BlockCipher = C;
+    type NonceSize = U16;
+
+    fn from_block_cipher(cipher: C, nonce: &Block) -> Self {
+        Self::from_cipher(cipher, nonce)
+    }
+}
+
+impl<C: BlockEncrypt + BlockCipher<BlockSize = U16, ParBlocks = U8>> StreamCipher for Ctr<C> {
+    /// A 128-bit counter doesn't run out in practice, so this never fails
+    fn try_apply_keystream(&mut self, data: &mut [u8]) -> Result<(), LoopError> {
+        Ctr::apply_keystream(self, data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wycheproof_records::unhex;
+    use crate::Aes128;
+
+    #[test]
+    fn counter_carries() {
+        let mut counter = Block::clone_from_slice(&unhex("000000000000000000000000ffffffff"));
+        increment(&mut counter);
+        assert_eq!(counter.to_vec(), unhex("00000000000000000000000100000000"));
+        // past the 64 bits `Ctr64BE` counted in, which would have wrapped to ...0001_0000...
+        let mut counter = Block::clone_from_slice(&unhex("0000000000000001ffffffffffffffff"));
+        increment(&mut counter);
+        assert_eq!(counter.to_vec(), unhex("00000000000000020000000000000000"));
+        let mut counter = Block::clone_from_slice(&[0xff; 16]);
+        increment(&mut counter);
+        assert_eq!(counter.to_vec(), vec![0u8; 16]);
+    }
+
+    #[test]
+    fn split_calls() {
+        let key = GenericArray::clone_from_slice(&[7u8; 16]);
+        let iv = Block::clone_from_slice(&unhex("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff"));
+        let data: Vec<u8> = (0..500u32).map(|i| i as u8).collect();
+        let mut whole = data.clone();
+        Ctr::<Aes128>::new(&key, &iv).apply_keystream(&mut whole);
+        // odd sizes on both sides of the eight-block batches
+        for split in [1usize, 15, 16, 17, 127, 128, 129, 300].iter() {
+            let mut pieces = data.clone();
+            let mut ctr = Ctr::<Aes128>::new(&key, &iv);
+            let (a, b) = pieces.split_at_mut(*split);
+            ctr.apply_keystream(a);
+            let (b, c) = b.split_at_mut(b.len() / 3);
+            ctr.apply_keystream(b);
+            ctr.apply_keystream(c);
+            assert_eq!(pieces, whole, "split at {}", split);
+        }
+        Ctr::<Aes128>::new(&key, &iv).apply_keystream(&mut whole);
+        assert_eq!(whole, data);
+    }
+}
@@ -0,0 +1,234 @@
+//! Galois/Counter Mode (NIST SP 800-38D)
+
+use super::polyval::Polyval;
+use super::{apply_keystream, ct_eq, Error, Tag};
+use crate::Block;
+use cipher::consts::{U16, U8};
+use cipher::generic_array::GenericArray;
+use cipher::{BlockCipher, BlockEncrypt, NewBlockCipher};
+use zeroize::Zeroize;
+
+/// longest message GCM can take, 2^39 - 256 bits
+const MAX_MESSAGE_LEN: u64 = (1 << 36) - 32;
+
+/// GHASH on top of POLYVAL, following RFC 8452 appendix A: byte-reversed blocks, and a key
+/// that's been byte-reversed and multiplied by x.
+struct Ghash(Polyval);
+
+impl Ghash {
+    fn new(h: &[u8; 16]) -> Ghash {
+        let mut key = *h;
+        key.reverse();
+        let mut v = u128::from_le_bytes(key);
+        let top = v >> 127;
+        v <<= 1;
+        v ^= top ^ (top << 127) ^ (top << 126) ^ (top << 121);
+        let mut key = v.to_le_bytes();
+        let ghash = Ghash(Polyval::new(&key));
+        key.zeroize();
+        v.zeroize();
+        ghash
+    }
+
+    fn update_padded(&mut self, data: &[u8]) {
+        let mut block = [0u8; 16];
+        for chunk in data.chunks(16) {
+            block = [0u8; 16];
+            block[..chunk.len()].copy_from_slice(chunk);
+            block.reverse();
+            self.0.update_block(&block);
+        }
+        block.zeroize();
+    }
+
+    /// Hashes the bit lengths of the two inputs, as two big-endian u64s
+    fn update_lengths(&mut self, a: usize, b: usize) {
+        let mut block = [0u8; 16];
+        block[..8].copy_from_slice(&((a as u64) * 8).to_be_bytes());
+        block[8..].copy_from_slice(&((b as u64) * 8).to_be_bytes());
+        self.update_padded(&block);
+    }
+
+    fn finalize(&self) -> [u8; 16] {
+        let mut out = self.0.finalize();
+        out.reverse();
+        out
+    }
+}
+
+/// Steps the low 32 bits of the counter, big-endian
+fn inc32(counter: &mut Block) {
+    let mut low = [0u8; 4];
+    low.copy_from_slice(&counter[12..]);
+    counter[12..].copy_from_slice(&u32::from_be_bytes(low).wrapping_add(1).to_be_bytes());
+}
+
+pub struct Gcm<C> {
+    cipher: C,
+    /// the hash key, the encryption of the zero block
+    h: [u8; 16],
+}
+
+impl<C: BlockEncrypt + BlockCipher<BlockSize = U16, ParBlocks = U8>> Gcm<C> {
+    pub fn new(key: &GenericArray<u8, C::KeySize>) -> Self
+    where
+        C: NewBlockCipher,
+    {
+        Self::from_cipher(C::new(key))
+    }
+
+    pub fn from_cipher(cipher: C) -> Self {
+        let mut block = Block::default();
+        cipher.encrypt_block(&mut block);
+        let mut h = [0u8; 16];
+        h.copy_from_slice(&block);
+        block.as_mut_slice().zeroize();
+        Gcm { cipher, h }
+    }
+
+    /// The pre-counter block: the nonce and a counter of 1 for the usual 96-bit nonces, and a
+    /// hash of the nonce for any other length.
+    fn j0(&self, nonce: &[u8]) -> Result<Block, Error> {
+        let mut j0 = Block::default();
+        match nonce.len() {
+            0 => return Err(Error),
+            12 => {
+                j0[..12].copy_from_slice(nonce);
+                j0[15] = 1;
+            }
+            _ => {
+                let mut ghash = Ghash::new(&self.h);
+                ghash.update_padded(nonce);
+                ghash.update_lengths(0, nonce.len());
+                j0.copy_from_slice(&ghash.finalize());
+            }
+        }
+        Ok(j0)
+    }
+
+    fn tag(&self, j0: &Block, aad: &[u8], ciphertext: &[u8]) -> Tag {
+        let mut ghash = Ghash::new(&self.h);
+        ghash.update_padded(aad);
+        ghash.update_padded(ciphertext);
+        ghash.update_lengths(aad.len(), ciphertext.len());
+        let mut tag = ghash.finalize();
+        let mut mask = *j0;
+        self.cipher.encrypt_block(&mut mask);
+        for (t, m) in tag.iter_mut().zip(mask.iter()) {
+            *t ^= m;
+        }
+        mask.as_mut_slice().zeroize();
+        tag
+    }
+
+    /// Encrypts `buffer` in place and returns the tag over it and `aad`. Any nonce length but
+    /// zero is allowed, but 96 bits is the one to use. A nonce must never be used twice with the
+    /// same key: that gives away the authentication key.
+    pub fn encrypt_in_place_detached(&self, nonce: &[u8], aad: &[u8], buffer: &mut [u8]) -> Result<Tag, Error> {
+        if buffer.len() as u64 > MAX_MESSAGE_LEN {
+            return Err(Error);
+        }
+        let j0 = self.j0(nonce)?;
+        let mut counter = j0;
+        inc32(&mut counter);
+        apply_keystream(&self.cipher, &mut counter, inc32, buffer);
+        Ok(self.tag(&j0, aad, buffer))
+    }
+
+    /// Checks `tag` over `buffer` and `aad`, then decrypts `buffer` in place. `buffer` is left
+    /// alone if the tag doesn't match.
+    pub fn decrypt_in_place_detached(&self, nonce: &[u8], aad: &[u8], buffer: &mut [u8], tag: &Tag) -> Result<(), Error> {
+        if buffer.len() as u64 > MAX_MESSAGE_LEN {
+            return Err(Error);
+        }
+        let j0 = self.j0(nonce)?;
+        if !ct_eq(&self.tag(&j0, aad, buffer), tag) {
+            return Err(Error);
+        }
+        let mut counter = j0;
+        inc32(&mut counter);
+        apply_keystream(&self.cipher, &mut counter, inc32, buffer);
+        Ok(())
+    }
+}
+
+impl<C> Drop for Gcm<C> {
+    fn drop(&mut self) {
+        self.h.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wycheproof_records::{records, unhex};
+    use crate::{Aes128, Aes192, Aes256};
+
+    #[test]
+    fn counter_wraps_in_32_bits() {
+        let mut counter = Block::clone_from_slice(&unhex("00000000000000000000000affffffff"));
+        inc32(&mut counter);
+        assert_eq!(counter.to_vec(), unhex("00000000000000000000000a00000000"));
+    }
+
+    #[test]
+    fn rejects_tampering() {
+        let gcm = Gcm::<Aes128>::new(&GenericArray::clone_from_slice(&[1u8; 16]));
+        let nonce = [2u8; 12];
+        let msg: Vec<u8> = (0..200u32).map(|i| i as u8).collect();
+        let mut buffer = msg.clone();
+        let tag = gcm.encrypt_in_place_detached(&nonce, b"header", &mut buffer).unwrap();
+        let ciphertext = buffer.clone();
+
+        let mut bad_tag = tag;
+        bad_tag[15] ^= 1;
+        assert_eq!(gcm.decrypt_in_place_detached(&nonce, b"header", &mut buffer, &bad_tag), Err(Error));
+        assert_eq!(gcm.decrypt_in_place_detached(&nonce, b"Header", &mut buffer, &tag), Err(Error));
+        assert_eq!(gcm.decrypt_in_place_detached(&[2u8; 13], b"header", &mut buffer, &tag), Err(Error));
+        buffer[100] ^= 0x40;
+        assert_eq!(gcm.decrypt_in_place_detached(&nonce, b"header", &mut buffer, &tag), Err(Error));
+        buffer[100] ^= 0x40;
+        // nothing is decrypted until the tag checks out
+        assert_eq!(buffer, ciphertext);
+        gcm.decrypt_in_place_detached(&nonce, b"header", &mut buffer, &tag).unwrap();
+        assert_eq!(buffer, msg);
+        assert_eq!(gcm.encrypt_in_place_detached(&[], b"", &mut buffer), Err(Error));
+    }
+
+    fn check<C>(gcm: Gcm<C>, i: usize, valid: bool, record: &[Vec<u8>])
+    where
+        C: BlockEncrypt + BlockCipher<BlockSize = U16, ParBlocks = U8>,
+    {
+        let (iv, aad, msg, ct, tag) = (&record[1], &record[2], &record[3], &record[4], &record[5]);
+        if tag.len() != 16 {
+            assert!(!valid, "record {}", i);
+            return;
+        }
+        let mut expected = [0u8; 16];
+        expected.copy_from_slice(tag);
+        let mut buffer = ct.clone();
+        match gcm.decrypt_in_place_detached(iv, aad, &mut buffer, &expected) {
+            Ok(()) => assert!(valid && buffer == *msg, "record {}", i),
+            Err(Error) => assert!(!valid, "record {}", i),
+        }
+        if valid {
+            let mut buffer = msg.clone();
+            assert_eq!(gcm.encrypt_in_place_detached(iv, aad, &mut buffer), Ok(expected), "record {}", i);
+            assert_eq!(buffer, *ct, "record {}", i);
+        }
+    }
+
+    /// Records are the result byte, then the key, IV, AAD, message, ciphertext and tag.
+    #[test]
+    fn wycheproof() {
+        for (i, (valid, record)) in records(env!("CARGO_MANIFEST_DIR"), "aes_gcm_test.bin", 6).iter().enumerate() {
+            let key = &record[0];
+            match key.len() {
+                16 => check(Gcm::<Aes128>::new(GenericArray::from_slice(key)), i, *valid, record),
+                24 => check(Gcm::<Aes192>::new(GenericArray::from_slice(key)), i, *valid, record),
+                32 => check(Gcm::<Aes256>::new(GenericArray::from_slice(key)), i, *valid, record),
+                _ => panic!("record {} has a {}-byte key", i, key.len()),
+            }
+        }
+    }
+}
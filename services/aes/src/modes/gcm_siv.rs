@@ -0,0 +1,195 @@
+//! AES-GCM-SIV (RFC 8452), the nonce-misuse-resistant AEAD
+//!
+//! Each nonce gets its own authentication and encryption keys, derived from the key-generating
+//! key. Repeating a nonce only gives away whether the same message was encrypted twice.
+
+use super::polyval::Polyval;
+use super::{apply_keystream, ct_eq, Error, Tag};
+use crate::{Block, ParBlocks};
+use cipher::consts::{U16, U8};
+use cipher::generic_array::typenum::Unsigned;
+use cipher::generic_array::GenericArray;
+use cipher::{BlockCipher, BlockEncrypt, NewBlockCipher};
+use zeroize::Zeroize;
+
+/// longest message or AAD, 2^36 bytes
+const MAX_LEN: u64 = 1 << 36;
+
+/// Steps the first 32 bits of the counter, little-endian
+fn inc32_le(counter: &mut Block) {
+    let mut low = [0u8; 4];
+    low.copy_from_slice(&counter[..4]);
+    counter[..4].copy_from_slice(&u32::from_le_bytes(low).wrapping_add(1).to_le_bytes());
+}
+
+pub struct GcmSiv<C> {
+    /// the key-generating key
+    cipher: C,
+}
+
+impl<C: NewBlockCipher + BlockEncrypt + BlockCipher<BlockSize = U16, ParBlocks = U8>> GcmSiv<C> {
+    pub fn new(key: &GenericArray<u8, C::KeySize>) -> Self {
+        GcmSiv { cipher: C::new(key) }
+    }
+
+    /// The message authentication key and the message encryption cipher for `nonce`
+    fn derive_keys(&self, nonce: &[u8; 12]) -> ([u8; 16], C) {
+        let halves = 2 + C::KeySize::USIZE / 8;
+        let mut blocks = ParBlocks::default();
+        for (i, block) in blocks[..halves].iter_mut().enumerate() {
+            block[..4].copy_from_slice(&(i as u32).to_le_bytes());
+            block[4..].copy_from_slice(nonce);
+        }
+        self.cipher.encrypt_par_blocks(&mut blocks);
+
+        let mut auth_key = [0u8; 16];
+        let mut enc_key = GenericArray::<u8, C::KeySize>::default();
+        for (i, block) in blocks[..halves].iter().enumerate() {
+            if i < 2 {
+                auth_key[i * 8..i * 8 + 8].copy_from_slice(&block[..8]);
+            } else {
+                enc_key[(i - 2) * 8..(i - 1) * 8].copy_from_slice(&block[..8]);
+            }
+        }
+        let enc = C::new(&enc_key);
+        enc_key.as_mut_slice().zeroize();
+        for block in blocks.iter_mut() {
+            block.as_mut_slice().zeroize();
+        }
+        (auth_key, enc)
+    }
+
+    fn tag(auth_key: &[u8; 16], enc: &C, nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Tag {
+        let mut polyval = Polyval::new(auth_key);
+        polyval.update_padded(aad);
+        polyval.update_padded(plaintext);
+        let mut lengths = [0u8; 16];
+        lengths[..8].copy_from_slice(&((aad.len() as u64) * 8).to_le_bytes());
+        lengths[8..].copy_from_slice(&((plaintext.len() as u64) * 8).to_le_bytes());
+        polyval.update_block(&lengths);
+
+        let mut s = polyval.finalize();
+        for (s, n) in s.iter_mut().zip(nonce.iter()) {
+            *s ^= n;
+        }
+        s[15] &= 0x7f;
+        let mut block = Block::clone_from_slice(&s);
+        enc.encrypt_block(&mut block);
+        s.zeroize();
+        let mut tag = [0u8; 16];
+        tag.copy_from_slice(&block);
+        tag
+    }
+
+    /// Encrypts `buffer` in place and returns the tag over it and `aad`
+    pub fn encrypt_in_place_detached(&self, nonce: &[u8; 12], aad: &[u8], buffer: &mut [u8]) -> Result<Tag, Error> {
+        if buffer.len() as u64 > MAX_LEN || aad.len() as u64 > MAX_LEN {
+            return Err(Error);
+        }
+        let (mut auth_key, enc) = self.derive_keys(nonce);
+        let tag = Self::tag(&auth_key, &enc, nonce, aad, buffer);
+        auth_key.zeroize();
+        let mut counter = Block::clone_from_slice(&tag);
+        counter[15] |= 0x80;
+        apply_keystream(&enc, &mut counter, inc32_le, buffer);
+        Ok(tag)
+    }
+
+    /// Decrypts `buffer` in place and checks `tag` over the result and `aad`. The plaintext is
+    /// wiped if the tag doesn't match.
+    pub fn decrypt_in_place_detached(&self, nonce: &[u8; 12], aad: &[u8], buffer: &mut [u8], tag: &Tag) -> Result<(), Error> {
+        if buffer.len() as u64 > MAX_LEN || aad.len() as u64 > MAX_LEN {
+            return Err(Error);
+        }
+        let (mut auth_key, enc) = self.derive_keys(nonce);
+        let mut counter = Block::clone_from_slice(tag);
+        counter[15] |= 0x80;
+        apply_keystream(&enc, &mut counter, inc32_le, buffer);
+        let expected = Self::tag(&auth_key, &enc, nonce, aad, buffer);
+        auth_key.zeroize();
+        if ct_eq(&expected, tag) {
+            Ok(())
+        } else {
+            buffer.zeroize();
+            Err(Error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wycheproof_records::{records, unhex};
+    use crate::{Aes128, Aes256};
+
+    #[test]
+    fn rejects_tampering() {
+        let siv = GcmSiv::<Aes256>::new(&GenericArray::clone_from_slice(&[1u8; 32]));
+        let nonce = [2u8; 12];
+        let msg: Vec<u8> = (0..200u32).map(|i| i as u8).collect();
+        let mut buffer = msg.clone();
+        let tag = siv.encrypt_in_place_detached(&nonce, b"header", &mut buffer).unwrap();
+        let ciphertext = buffer.clone();
+
+        let mut bad_tag = tag;
+        bad_tag[0] ^= 1;
+        assert_eq!(siv.decrypt_in_place_detached(&nonce, b"header", &mut buffer, &bad_tag), Err(Error));
+        // the failed decryption doesn't leave plaintext behind
+        assert_eq!(buffer, vec![0u8; 200]);
+        buffer.copy_from_slice(&ciphertext);
+        assert_eq!(siv.decrypt_in_place_detached(&[3u8; 12], b"header", &mut buffer, &tag), Err(Error));
+        buffer.copy_from_slice(&ciphertext);
+        siv.decrypt_in_place_detached(&nonce, b"header", &mut buffer, &tag).unwrap();
+        assert_eq!(buffer, msg);
+    }
+
+    /// The same key, nonce and message always seal the same way; that's the point of SIV.
+    #[test]
+    fn deterministic() {
+        let siv = GcmSiv::<Aes128>::new(&GenericArray::clone_from_slice(&unhex("ee8e1ed9ff2540ae8f2ba9f50bc2f27c")));
+        let nonce = [0x75u8; 12];
+        let mut a = *b"Hello world";
+        let mut b = a;
+        let tag_a = siv.encrypt_in_place_detached(&nonce, b"example", &mut a).unwrap();
+        let tag_b = siv.encrypt_in_place_detached(&nonce, b"example", &mut b).unwrap();
+        assert_eq!((a, tag_a), (b, tag_b));
+    }
+
+    fn check<C>(siv: GcmSiv<C>, i: usize, valid: bool, record: &[Vec<u8>])
+    where
+        C: NewBlockCipher + BlockEncrypt + BlockCipher<BlockSize = U16, ParBlocks = U8>,
+    {
+        let (iv, aad, msg, ct, tag) = (&record[1], &record[2], &record[3], &record[4], &record[5]);
+        if iv.len() != 12 || tag.len() != 16 {
+            assert!(!valid, "record {}", i);
+            return;
+        }
+        let mut nonce = [0u8; 12];
+        nonce.copy_from_slice(iv);
+        let mut expected = [0u8; 16];
+        expected.copy_from_slice(tag);
+        let mut buffer = ct.clone();
+        match siv.decrypt_in_place_detached(&nonce, aad, &mut buffer, &expected) {
+            Ok(()) => assert!(valid && buffer == *msg, "record {}", i),
+            Err(Error) => assert!(!valid, "record {}", i),
+        }
+        if valid {
+            let mut buffer = msg.clone();
+            assert_eq!(siv.encrypt_in_place_detached(&nonce, aad, &mut buffer), Ok(expected), "record {}", i);
+            assert_eq!(buffer, *ct, "record {}", i);
+        }
+    }
+
+    /// Records are the result byte, then the key, IV, AAD, message, ciphertext and tag.
+    #[test]
+    fn wycheproof() {
+        for (i, (valid, record)) in records(env!("CARGO_MANIFEST_DIR"), "aes_gcm_siv_test.bin", 6).iter().enumerate() {
+            let key = &record[0];
+            match key.len() {
+                16 => check(GcmSiv::<Aes128>::new(GenericArray::from_slice(key)), i, *valid, record),
+                32 => check(GcmSiv::<Aes256>::new(GenericArray::from_slice(key)), i, *valid, record),
+                _ => panic!("record {} has a {}-byte key", i, key.len()),
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! POLYVAL (RFC 8452), the universal hash in GCM-SIV. GHASH is the same field in reversed bit
+//! order, so GCM hashes through here too.
+//!
+//! The field multiply uses integer multiplies on operands with every fourth bit masked off, so
+//! carries can't spill into the bits that are kept and the timing doesn't depend on the data.
+//! After BearSSL's `ghash_ctmul64`.
+
+use zeroize::Zeroize;
+
+/// Carry-less multiply, keeping the low 64 bits of the product
+fn bmul64(x: u64, y: u64) -> u64 {
+    let x0 = x & 0x1111_1111_1111_1111;
+    let x1 = x & 0x2222_2222_2222_2222;
+    let x2 = x & 0x4444_4444_4444_4444;
+    let x3 = x & 0x8888_8888_8888_8888;
+    let y0 = y & 0x1111_1111_1111_1111;
+    let y1 = y & 0x2222_2222_2222_2222;
+    let y2 = y & 0x4444_4444_4444_4444;
+    let y3 = y & 0x8888_8888_8888_8888;
+    let m = |a: u64, b: u64| a.wrapping_mul(b);
+    let z0 = m(x0, y0) ^ m(x1, y3) ^ m(x2, y2) ^ m(x3, y1);
+    let z1 = m(x0, y1) ^ m(x1, y0) ^ m(x2, y3) ^ m(x3, y2);
+    let z2 = m(x0, y2) ^ m(x1, y1) ^ m(x2, y0) ^ m(x3, y3);
+    let z3 = m(x0, y3) ^ m(x1, y2) ^ m(x2, y1) ^ m(x3, y0);
+    (z0 & 0x1111_1111_1111_1111)
+        | (z1 & 0x2222_2222_2222_2222)
+        | (z2 & 0x4444_4444_4444_4444)
+        | (z3 & 0x8888_8888_8888_8888)
+}
+
+fn halves(block: &[u8]) -> (u64, u64) {
+    let mut lo = [0u8; 8];
+    let mut hi = [0u8; 8];
+    lo.copy_from_slice(&block[..8]);
+    hi.copy_from_slice(&block[8..16]);
+    (u64::from_le_bytes(lo), u64::from_le_bytes(hi))
+}
+
+pub(crate) struct Polyval {
+    h: (u64, u64),
+    s: (u64, u64),
+}
+
+impl Polyval {
+    pub(crate) fn new(h: &[u8; 16]) -> Polyval {
+        Polyval { h: halves(h), s: (0, 0) }
+    }
+
+    /// S = (S + X) * H, in POLYVAL's field
+    pub(crate) fn update_block(&mut self, block: &[u8; 16]) {
+        let (x0, x1) = halves(block);
+        let (h0, h1) = self.h;
+        let (y0, y1) = (self.s.0 ^ x0, self.s.1 ^ x1);
+
+        // Karatsuba, once on the operands and once on their bit reversals to get the high halves
+        let h0r = h0.reverse_bits();
+        let h1r = h1.reverse_bits();
+        let h2 = h0 ^ h1;
+        let h2r = h0r ^ h1r;
+        let y0r = y0.reverse_bits();
+        let y1r = y1.reverse_bits();
+        let y2 = y0 ^ y1;
+        let y2r = y0r ^ y1r;
+
+        let z0 = bmul64(y0, h0);
+        let z1 = bmul64(y1, h1);
+        let mut z2 = bmul64(y2, h2);
+        let mut z0h = bmul64(y0r, h0r);
+        let mut z1h = bmul64(y1r, h1r);
+        let mut z2h = bmul64(y2r, h2r);
+        z2 ^= z0 ^ z1;
+        z2h ^= z0h ^ z1h;
+        z0h = z0h.reverse_bits() >> 1;
+        z1h = z1h.reverse_bits() >> 1;
+        z2h = z2h.reverse_bits() >> 1;
+
+        let v0 = z0;
+        let mut v1 = z0h ^ z2;
+        let mut v2 = z1 ^ z2h;
+        let mut v3 = z1h;
+
+        // reduce by x^128 + x^127 + x^126 + x^121 + 1
+        v2 ^= v0 ^ (v0 >> 1) ^ (v0 >> 2) ^ (v0 >> 7);
+        v1 ^= (v0 << 63) ^ (v0 << 62) ^ (v0 << 57);
+        v3 ^= v1 ^ (v1 >> 1) ^ (v1 >> 2) ^ (v1 >> 7);
+        v2 ^= (v1 << 63) ^ (v1 << 62) ^ (v1 << 57);
+
+        self.s = (v2, v3);
+    }
+
+    /// Hashes `data` as whole blocks, zero-padding the last one
+    pub(crate) fn update_padded(&mut self, data: &[u8]) {
+        let mut block = [0u8; 16];
+        for chunk in data.chunks(16) {
+            block = [0u8; 16];
+            block[..chunk.len()].copy_from_slice(chunk);
+            self.update_block(&block);
+        }
+        block.zeroize();
+    }
+
+    pub(crate) fn finalize(&self) -> [u8; 16] {
+        let mut out = [0u8; 16];
+        out[..8].copy_from_slice(&self.s.0.to_le_bytes());
+        out[8..].copy_from_slice(&self.s.1.to_le_bytes());
+        out
+    }
+}
+
+impl Drop for Polyval {
+    fn drop(&mut self) {
+        self.h.0.zeroize();
+        self.h.1.zeroize();
+        self.s.0.zeroize();
+        self.s.1.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wycheproof_records::unhex;
+
+    fn block(s: &str) -> [u8; 16] {
+        let mut b = [0u8; 16];
+        b.copy_from_slice(&unhex(s));
+        b
+    }
+
+    /// RFC 8452, appendix A
+    #[test]
+    fn rfc8452_example() {
+        let mut polyval = Polyval::new(&block("25629347589242761d31f826ba4b757b"));
+        polyval.update_block(&block("4f4f95668c83dfb6401762bb2d01a262"));
+        polyval.update_block(&block("d1a24ddd2721d006bbe45f20d3c9f362"));
+        assert_eq!(polyval.finalize().to_vec(), unhex("f7a3b47b846119fae5b7866cf5e5b77e"));
+    }
+}
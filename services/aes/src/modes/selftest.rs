@@ -0,0 +1,216 @@
+//! Known answers for the modes. The unit tests run them on the soft cipher, and shellchat's
+//! `aes checkmodes` runs them on the Vex instructions on hardware.
+//!
+//! The ECB, CBC and CTR vectors are from NIST SP 800-38A; they use the same keys and plaintext
+//! as the ECB vectors in `aes-test`. The GCM vectors are test cases from McGrew and Viega's GCM
+//! specification, and the GCM-SIV ones are from RFC 8452, appendix C.
+
+use super::{Cbc, Ctr, Gcm, GcmSiv};
+use crate::{Block, BLOCK_SIZE};
+use cipher::consts::{U16, U32, U8};
+use cipher::generic_array::GenericArray;
+use cipher::{BlockCipher, BlockDecrypt, BlockEncrypt, NewBlockCipher};
+use hex_literal::hex;
+
+const SP800_38A_PLAINTEXT: [u8; 64] = hex!("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e5130c81c46a35ce411e5fbc1191a0a52eff69f2445df4f9b17ad2b417be66c3710");
+const SP800_38A_IV: [u8; 16] = hex!("000102030405060708090a0b0c0d0e0f");
+const SP800_38A_COUNTER: [u8; 16] = hex!("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff");
+
+const GCM_KEY: [u8; 16] = hex!("feffe9928665731c6d6a8f9467308308");
+const GCM_NONCE: [u8; 12] = hex!("cafebabefacedbaddecaf888");
+const GCM_AAD: [u8; 20] = hex!("feedfacedeadbeeffeedfacedeadbeefabaddad2");
+const GCM_PLAINTEXT: [u8; 60] = hex!("d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a721c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39");
+
+const SIV_NONCE: [u8; 12] = hex!("030000000000000000000000");
+
+fn check_ecb<C: BlockEncrypt + BlockDecrypt + BlockCipher<BlockSize = U16, ParBlocks = U8>>(
+    cipher: &C,
+    expected: &[u8; 64],
+) -> Result<(), &'static str> {
+    for (pt, ct) in SP800_38A_PLAINTEXT.chunks(BLOCK_SIZE).zip(expected.chunks(BLOCK_SIZE)) {
+        let mut block = Block::clone_from_slice(pt);
+        cipher.encrypt_block(&mut block);
+        if block.as_slice() != ct {
+            return Err("ECB encrypt");
+        }
+        cipher.decrypt_block(&mut block);
+        if block.as_slice() != pt {
+            return Err("ECB decrypt");
+        }
+    }
+    Ok(())
+}
+
+fn check_cbc<C: BlockEncrypt + BlockDecrypt + BlockCipher<BlockSize = U16, ParBlocks = U8>>(
+    cipher: C,
+    expected: &[u8; 64],
+) -> Result<(), &'static str> {
+    let cbc = Cbc::from_cipher(cipher);
+    let iv = Block::clone_from_slice(&SP800_38A_IV);
+    // the vectors are whole blocks, so they come out with a block of padding on the end
+    let mut buffer = [0u8; 80];
+    buffer[..64].copy_from_slice(&SP800_38A_PLAINTEXT);
+    let ct = cbc.encrypt_padded(&iv, &mut buffer, 64).map_err(|_| "CBC encrypt")?;
+    if ct.len() != 80 || ct[..64] != expected[..] {
+        return Err("CBC encrypt");
+    }
+    match cbc.decrypt_padded(&iv, &mut buffer) {
+        Ok(pt) if pt == &SP800_38A_PLAINTEXT[..] => Ok(()),
+        _ => Err("CBC decrypt"),
+    }
+}
+
+fn check_ctr<C: BlockEncrypt + BlockCipher<BlockSize = U16, ParBlocks = U8>>(
+    cipher: C,
+    expected: &[u8; 64],
+) -> Result<(), &'static str> {
+    let mut ctr = Ctr::from_cipher(cipher, &Block::clone_from_slice(&SP800_38A_COUNTER));
+    let mut buffer = SP800_38A_PLAINTEXT;
+    // uneven pieces, to cross block boundaries
+    ctr.apply_keystream(&mut buffer[..5]);
+    ctr.apply_keystream(&mut buffer[5..37]);
+    ctr.apply_keystream(&mut buffer[37..]);
+    if buffer[..] != expected[..] {
+        return Err("CTR");
+    }
+    Ok(())
+}
+
+fn check_gcm<C: BlockEncrypt + BlockCipher<BlockSize = U16, ParBlocks = U8>>(
+    gcm: &Gcm<C>,
+    nonce: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+    expected: &[u8],
+    tag: &[u8; 16],
+) -> Result<(), &'static str> {
+    let mut buffer = [0u8; 64];
+    let buffer = &mut buffer[..plaintext.len()];
+    buffer.copy_from_slice(plaintext);
+    match gcm.encrypt_in_place_detached(nonce, aad, buffer) {
+        Ok(t) if t == *tag && buffer == expected => (),
+        _ => return Err("GCM encrypt"),
+    }
+    let mut bad_tag = *tag;
+    bad_tag[0] ^= 1;
+    if gcm.decrypt_in_place_detached(nonce, aad, buffer, &bad_tag).is_ok() {
+        return Err("GCM accepted a bad tag");
+    }
+    match gcm.decrypt_in_place_detached(nonce, aad, buffer, tag) {
+        Ok(()) if buffer == plaintext => Ok(()),
+        _ => Err("GCM decrypt"),
+    }
+}
+
+/// `expected` is the ciphertext followed by the tag, as RFC 8452 gives them
+fn check_gcm_siv<C: NewBlockCipher + BlockEncrypt + BlockCipher<BlockSize = U16, ParBlocks = U8>>(
+    siv: &GcmSiv<C>,
+    aad: &[u8],
+    plaintext: &[u8],
+    expected: &[u8],
+) -> Result<(), &'static str> {
+    let mut buffer = [0u8; 64];
+    let buffer = &mut buffer[..plaintext.len()];
+    buffer.copy_from_slice(plaintext);
+    let (ct, tag) = expected.split_at(plaintext.len());
+    match siv.encrypt_in_place_detached(&SIV_NONCE, aad, buffer) {
+        Ok(t) if t[..] == *tag && buffer == ct => (),
+        _ => return Err("GCM-SIV encrypt"),
+    }
+    let mut tag_array = [0u8; 16];
+    tag_array.copy_from_slice(tag);
+    let mut bad_tag = tag_array;
+    bad_tag[0] ^= 1;
+    if siv.decrypt_in_place_detached(&SIV_NONCE, aad, buffer, &bad_tag).is_ok() {
+        return Err("GCM-SIV accepted a bad tag");
+    }
+    buffer.copy_from_slice(ct);
+    match siv.decrypt_in_place_detached(&SIV_NONCE, aad, buffer, &tag_array) {
+        Ok(()) if buffer == plaintext => Ok(()),
+        _ => Err("GCM-SIV decrypt"),
+    }
+}
+
+/// Checks every mode over the AES-128 cipher `C`. Returns what failed, if anything.
+pub fn check_aes128<C>() -> Result<(), &'static str>
+where
+    C: NewBlockCipher<KeySize = U16> + BlockEncrypt + BlockDecrypt + BlockCipher<BlockSize = U16, ParBlocks = U8>,
+{
+    let key = GenericArray::clone_from_slice(&hex!("2b7e151628aed2a6abf7158809cf4f3c"));
+    check_ecb(&C::new(&key), &hex!("3ad77bb40d7a3660a89ecaf32466ef97f5d3d58503b9699de785895a96fdbaaf43b1cd7f598ece23881b00e3ed0306887b0c785e27e8ad3f8223207104725dd4"))?;
+    check_cbc(C::new(&key), &hex!("7649abac8119b246cee98e9b12e9197d5086cb9b507219ee95db113a917678b273bed6b8e3c1743b7116e69e222295163ff1caa1681fac09120eca307586e1a7"))?;
+    check_ctr(C::new(&key), &hex!("874d6191b620e3261bef6864990db6ce9806f66b7970fdff8617187bb9fffdff5ae4df3edbd5d35e5b4f09020db03eab1e031dda2fbe03d1792170a0f3009cee"))?;
+
+    let gcm = Gcm::<C>::new(&GenericArray::default());
+    check_gcm(&gcm, &[0u8; 12], &[], &[], &[], &hex!("58e2fccefa7e3061367f1d57a4e7455a"))?;
+    check_gcm(&gcm, &[0u8; 12], &[], &[0u8; 16], &hex!("0388dace60b6a392f328c2b971b2fe78"), &hex!("ab6e47d42cec13bdf53a67b21257bddf"))?;
+    let gcm = Gcm::<C>::new(GenericArray::from_slice(&GCM_KEY));
+    check_gcm(
+        &gcm,
+        &GCM_NONCE,
+        &GCM_AAD,
+        &GCM_PLAINTEXT,
+        &hex!("42831ec2217774244b7221b784d0d49ce3aa212f2c02a4e035c17e2329aca12e21d514b25466931c7d8f6a5aac84aa051ba30b396a0aac973d58e091"),
+        &hex!("5bc94fbc3221a5db94fae95ae7121a47"),
+    )?;
+    // a 64-bit nonce, which goes through GHASH
+    check_gcm(
+        &gcm,
+        &GCM_NONCE[..8],
+        &GCM_AAD,
+        &GCM_PLAINTEXT,
+        &hex!("61353b4c2806934a777ff51fa22a4755699b2a714fcdc6f83766e5f97b6c742373806900e49f24b22b097544d4896b424989b5e1ebac0f07c23f4598"),
+        &hex!("3612d2e79e3b0785561be14aaca2fccb"),
+    )?;
+
+    let siv = GcmSiv::<C>::new(GenericArray::from_slice(&hex!("01000000000000000000000000000000")));
+    check_gcm_siv(&siv, &[], &[], &hex!("dc20e2d83f25705bb49e439eca56de25"))?;
+    check_gcm_siv(&siv, &[], &hex!("0100000000000000"), &hex!("b5d839330ac7b786578782fff6013b815b287c22493a364c"))?;
+    check_gcm_siv(&siv, &[], &hex!("010000000000000000000000"), &hex!("7323ea61d05932260047d942a4978db357391a0bc4fdec8b0d106639"))?;
+    check_gcm_siv(&siv, &hex!("01"), &hex!("0200000000000000"), &hex!("1e6daba35669f4273b0a1a2560969cdf790d99759abd1508"))?;
+    Ok(())
+}
+
+/// Checks every mode over the AES-256 cipher `C`. Returns what failed, if anything.
+pub fn check_aes256<C>() -> Result<(), &'static str>
+where
+    C: NewBlockCipher<KeySize = U32> + BlockEncrypt + BlockDecrypt + BlockCipher<BlockSize = U16, ParBlocks = U8>,
+{
+    let key = GenericArray::clone_from_slice(&hex!("603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4"));
+    check_ecb(&C::new(&key), &hex!("f3eed1bdb5d2a03c064b5a7e3db181f8591ccb10d410ed26dc5ba74a31362870b6ed21b99ca6f4f9f153e7b1beafed1d23304b7a39f9f3ff067d8d8f9e24ecc7"))?;
+    check_cbc(C::new(&key), &hex!("f58c4c04d6e5f1ba779eabfb5f7bfbd69cfc4e967edb808d679f777bc6702c7d39f23369a9d9bacfa530e26304231461b2eb05e2c39be9fcda6c19078c6a9d1b"))?;
+    check_ctr(C::new(&key), &hex!("601ec313775789a5b7a7f504bbf3d228f443e3ca4d62b59aca84e990cacaf5c52b0930daa23de94ce87017ba2d84988ddfc9c58db67aada613c2dd08457941a6"))?;
+
+    let gcm = Gcm::<C>::new(&GenericArray::default());
+    check_gcm(&gcm, &[0u8; 12], &[], &[], &[], &hex!("530f8afbc74536b9a963b4f1c4cb738b"))?;
+    check_gcm(&gcm, &[0u8; 12], &[], &[0u8; 16], &hex!("cea7403d4d606b6e074ec5d3baf39d18"), &hex!("d0d1c8a799996bf0265b98b5d48ab919"))?;
+    let mut key = [0u8; 32];
+    key[..16].copy_from_slice(&GCM_KEY);
+    key[16..].copy_from_slice(&GCM_KEY);
+    check_gcm(
+        &Gcm::<C>::new(GenericArray::from_slice(&key)),
+        &GCM_NONCE,
+        &GCM_AAD,
+        &GCM_PLAINTEXT,
+        &hex!("522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662"),
+        &hex!("76fc6ece0f4e1768cddf8853bb2d551b"),
+    )?;
+
+    let siv = GcmSiv::<C>::new(GenericArray::from_slice(&hex!("0100000000000000000000000000000000000000000000000000000000000000")));
+    check_gcm_siv(&siv, &[], &[], &hex!("07f5f4169bbf55a8400cd47ea6fd400f"))?;
+    check_gcm_siv(&siv, &[], &hex!("0100000000000000"), &hex!("c2ef328e5c71c83b843122130f7364b761e0b97427e3df28"))?;
+    check_gcm_siv(&siv, &[], &hex!("010000000000000000000000"), &hex!("9aab2aeb3faa0a34aea8e2b18ca50da9ae6559e48fd10f6e5c9ca17e"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Aes128Soft, Aes256Soft};
+
+    #[test]
+    fn known_answers() {
+        assert_eq!(check_aes128::<Aes128Soft>(), Ok(()));
+        assert_eq!(check_aes256::<Aes256Soft>(), Ok(()));
+    }
+}
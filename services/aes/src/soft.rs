@@ -11,12 +11,6 @@
 pub(crate) mod fixslice32;
 use fixslice32 as fixslice;
 
-#[cfg(feature = "ctr")]
-mod ctr;
-
-#[cfg(feature = "ctr")]
-pub use self::ctr::{Aes128Ctr, Aes192Ctr, Aes256Ctr};
-
 use crate::{Block, ParBlocks};
 use cipher::{
     consts::{U16, U24, U32, U8},
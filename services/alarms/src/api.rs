@@ -0,0 +1,123 @@
+pub(crate) const SERVER_NAME_ALARMS: &str = "_Alarm scheduler_";
+
+/// longest tag; tags name the app an alarm is for
+pub const MAX_TAG_LEN: usize = 64;
+/// longest label on an alarm
+pub const MAX_LABEL_LEN: usize = 128;
+/// most alarms kept at once
+pub const MAX_ALARMS: usize = 256;
+/// most alarms returned by one `ListAlarms`
+pub const MAX_LISTED: usize = 16;
+
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+pub(crate) enum Opcode {
+    /// set an alarm, described by an `AlarmRequest`; the server fills in its ID
+    SetAlarm,
+    /// cancel one of the caller's alarms. arg1: its ID. Returns `CANCELLED` if the caller had such an
+    /// alarm, `NOT_CANCELLED` if not, or `UNIDENTIFIED` if the caller has no name.
+    CancelAlarm,
+    /// the caller's alarms under a tag, in an `AlarmList`
+    ListAlarms,
+    /// hook a callback server up to the caller's alarms under a tag, with a `ListenHook`
+    Listen,
+    /// unhook one of the caller's callback servers. arg1-4: its SID
+    Unlisten,
+    /// the clock was set, or the time zone changed; sent by the status time server
+    ClockChanged,
+    /// internal, from the ticktimer: the next alarm may be due
+    TimerFired,
+    /// internal, from the mount watcher: the PDDB is up. arg1: a connection to the status time server
+    PddbMounted,
+    /// SuspendResume callback
+    SuspendResume,
+    /// exit the server
+    Quit,
+}
+
+/// `CancelAlarm` results
+pub(crate) const NOT_CANCELLED: usize = 0;
+pub(crate) const CANCELLED: usize = 1;
+pub(crate) const UNIDENTIFIED: usize = 2;
+
+/// Messages from the server to the callback server in a listener's process
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+pub(crate) enum AlarmCallback {
+    /// arg1: the CID to forward to, arg2: the opcode, arg3: the alarm's ID
+    Fired,
+    Drop,
+}
+
+#[allow(dead_code)] // `Ipc` is only produced by the library
+#[derive(Debug, Copy, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum AlarmError {
+    /// the tag is empty, too long, or has control characters in it
+    BadTag,
+    /// the label is too long
+    BadLabel,
+    /// a one-shot alarm in the past
+    BadTime,
+    /// there are already `MAX_ALARMS` alarms
+    Full,
+    /// the alarm couldn't be read from or written to the PDDB
+    Storage,
+    /// the request didn't make it to the server and back
+    Ipc,
+    /// the caller has no name registered with xous-names, so it can't own alarms
+    Unidentified,
+}
+
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct AlarmRequest {
+    pub tag: xous_ipc::String::</* MAX_TAG_LEN */ 64>, // pending https://github.com/rust-lang/rust/issues/90195
+    pub label: xous_ipc::String::</* MAX_LABEL_LEN */ 128>,
+    /// ms since the epoch; UTC, or local time if `local`
+    pub when_ms: i64,
+    pub local: bool,
+    /// 0 for a one-shot alarm
+    pub repeat_ms: u64,
+    /// filled in by the server
+    pub id: u32,
+    pub result: Option<AlarmError>,
+}
+
+/// One page of the caller's alarms under a tag, starting after `after` (an alarm ID), in ID order
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct AlarmList {
+    pub tag: xous_ipc::String::</* MAX_TAG_LEN */ 64>,
+    pub after: u32,
+    pub count: u32,
+    pub ids: [u32; MAX_LISTED],
+    pub when_ms: [i64; MAX_LISTED],
+    pub local: [bool; MAX_LISTED],
+    pub repeat_ms: [u64; MAX_LISTED],
+    pub labels: [xous_ipc::String::</* MAX_LABEL_LEN */ 128>; MAX_LISTED],
+    /// there are more alarms after these
+    pub more: bool,
+    pub result: Option<AlarmError>,
+}
+
+/*
+Same two-stage scheme as the LLIO's ScalarHook: alarms are delivered to a one-time server in the
+listener's process, which forwards them on, so the listener's own SID is never handed out.
+*/
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub(crate) struct ListenHook {
+    pub sid: (u32, u32, u32, u32),
+    pub tag: xous_ipc::String::</* MAX_TAG_LEN */ 64>,
+    /// caller-side connection and opcode the alarms are forwarded to
+    pub cid: xous::CID,
+    pub opcode: u32,
+    pub result: Option<AlarmError>,
+}
+
+/// An alarm, as listed by `list_alarms()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlarmInfo {
+    pub id: u32,
+    pub label: std::string::String,
+    /// ms since the epoch; UTC, or local time if `local`
+    pub when_ms: i64,
+    pub local: bool,
+    /// 0 for a one-shot alarm
+    pub repeat_ms: u64,
+}
@@ -0,0 +1,203 @@
+#![cfg_attr(target_os = "none", no_std)]
+
+//! Detailed docs are parked under Structs/Alarms down below
+
+pub mod api;
+pub use api::*;
+use xous::{send_message, Message, CID};
+use xous_ipc::Buffer;
+use num_traits::*;
+
+#[doc = include_str!("../README.md")]
+#[derive(Debug)]
+pub struct Alarms {
+    conn: CID,
+    /// the one-time server that alarms for this object's tags arrive at
+    cb_sid: Option<xous::SID>,
+}
+impl Alarms {
+    pub fn new(xns: &xous_names::XousNames) -> Result<Self, xous::Error> {
+        REFCOUNT.fetch_add(1, Ordering::Relaxed);
+        let conn = xns.request_connection_blocking(api::SERVER_NAME_ALARMS).expect("Can't connect to alarm scheduler");
+        Ok(Alarms {
+            conn,
+            cb_sid: None,
+        })
+    }
+
+    fn set_alarm(&self, tag: &str, label: &str, when_ms: i64, local: bool, repeat_ms: u64) -> Result<u32, AlarmError> {
+        if tag.len() > MAX_TAG_LEN {
+            return Err(AlarmError::BadTag);
+        }
+        if label.len() > MAX_LABEL_LEN {
+            return Err(AlarmError::BadLabel);
+        }
+        let request = AlarmRequest {
+            tag: xous_ipc::String::from_str(tag),
+            label: xous_ipc::String::from_str(label),
+            when_ms,
+            local,
+            repeat_ms,
+            id: 0,
+            result: Some(AlarmError::Ipc),
+        };
+        let mut buf = Buffer::into_buf(request).or(Err(AlarmError::Ipc))?;
+        buf.lend_mut(self.conn, Opcode::SetAlarm.to_u32().unwrap()).or(Err(AlarmError::Ipc))?;
+        let response = buf.to_original::<AlarmRequest, _>().or(Err(AlarmError::Ipc))?;
+        match response.result {
+            None => Ok(response.id),
+            Some(e) => Err(e),
+        }
+    }
+
+    /// Sets an alarm for `utc_ms` (ms since the epoch, UTC) under `tag`, repeating every `repeat_ms`
+    /// if that isn't 0. Returns the alarm's ID.
+    pub fn set_alarm_utc(&self, tag: &str, label: &str, utc_ms: i64, repeat_ms: u64) -> Result<u32, AlarmError> {
+        self.set_alarm(tag, label, utc_ms, false, repeat_ms)
+    }
+
+    /// Sets an alarm for a local time, as ms since the epoch. The alarm stays at the same time on the
+    /// clock if the time zone changes.
+    pub fn set_alarm_local(&self, tag: &str, label: &str, local_ms: i64, repeat_ms: u64) -> Result<u32, AlarmError> {
+        self.set_alarm(tag, label, local_ms, true, repeat_ms)
+    }
+
+    /// Returns false if this process had no such alarm, e.g. because it was a one-shot that already
+    /// went off.
+    pub fn cancel_alarm(&self, id: u32) -> Result<bool, AlarmError> {
+        match send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::CancelAlarm.to_usize().unwrap(), id as usize, 0, 0, 0)
+        ) {
+            Ok(xous::Result::Scalar1(CANCELLED)) => Ok(true),
+            Ok(xous::Result::Scalar1(NOT_CANCELLED)) => Ok(false),
+            Ok(xous::Result::Scalar1(UNIDENTIFIED)) => Err(AlarmError::Unidentified),
+            _ => Err(AlarmError::Ipc),
+        }
+    }
+
+    /// All of this process's alarms under `tag`, in ID order
+    pub fn list_alarms(&self, tag: &str) -> Result<Vec<AlarmInfo>, AlarmError> {
+        if tag.len() > MAX_TAG_LEN {
+            return Err(AlarmError::BadTag);
+        }
+        let mut alarms = Vec::new();
+        let mut after = 0;
+        loop {
+            let request = AlarmList {
+                tag: xous_ipc::String::from_str(tag),
+                after,
+                count: 0,
+                ids: [0; MAX_LISTED],
+                when_ms: [0; MAX_LISTED],
+                local: [false; MAX_LISTED],
+                repeat_ms: [0; MAX_LISTED],
+                labels: [xous_ipc::String::new(); MAX_LISTED],
+                more: false,
+                result: Some(AlarmError::Ipc),
+            };
+            let mut buf = Buffer::into_buf(request).or(Err(AlarmError::Ipc))?;
+            buf.lend_mut(self.conn, Opcode::ListAlarms.to_u32().unwrap()).or(Err(AlarmError::Ipc))?;
+            let list = buf.to_original::<AlarmList, _>().or(Err(AlarmError::Ipc))?;
+            if let Some(e) = list.result {
+                return Err(e);
+            }
+            for i in 0..(list.count as usize).min(MAX_LISTED) {
+                alarms.push(AlarmInfo {
+                    id: list.ids[i],
+                    label: std::string::String::from(list.labels[i].as_str().unwrap_or("")),
+                    when_ms: list.when_ms[i],
+                    local: list.local[i],
+                    repeat_ms: list.repeat_ms[i],
+                });
+            }
+            match alarms.last() {
+                Some(last) if list.more => after = last.id,
+                _ => return Ok(alarms),
+            }
+        }
+    }
+
+    /// Has this process's alarms under `tag` sent to `cid` as scalar messages with `opcode`, with the alarm's ID in
+    /// arg1. Alarms that went off while nothing was listening for the tag are sent right away. One
+    /// object can listen for any number of tags.
+    pub fn hook_alarm_callback(&mut self, tag: &str, opcode: u32, cid: CID) -> Result<(), AlarmError> {
+        if tag.len() > MAX_TAG_LEN {
+            return Err(AlarmError::BadTag);
+        }
+        if self.cb_sid.is_none() {
+            let sid = xous::create_server().or(Err(AlarmError::Ipc))?;
+            self.cb_sid = Some(sid);
+            let sid_tuple = sid.to_u32();
+            xous::create_thread_4(alarm_cb_server, sid_tuple.0 as usize, sid_tuple.1 as usize, sid_tuple.2 as usize, sid_tuple.3 as usize).unwrap();
+        }
+        let hook = ListenHook {
+            sid: self.cb_sid.unwrap().to_u32(),
+            tag: xous_ipc::String::from_str(tag),
+            cid,
+            opcode,
+            result: Some(AlarmError::Ipc),
+        };
+        let mut buf = Buffer::into_buf(hook).or(Err(AlarmError::Ipc))?;
+        buf.lend_mut(self.conn, Opcode::Listen.to_u32().unwrap()).or(Err(AlarmError::Ipc))?;
+        let response = buf.to_original::<ListenHook, _>().or(Err(AlarmError::Ipc))?;
+        match response.result {
+            None => Ok(()),
+            Some(e) => Err(e),
+        }
+    }
+}
+
+/// For the status time server: tells the scheduler the clock was set or the time zone changed, so
+/// it can reschedule. Does nothing if the scheduler isn't running.
+pub fn notify_clock_changed(xns: &xous_names::XousNames) {
+    if let Ok(conn) = xns.request_connection(api::SERVER_NAME_ALARMS) {
+        xous::try_send_message(conn,
+            Message::new_scalar(Opcode::ClockChanged.to_usize().unwrap(), 0, 0, 0, 0)
+        ).ok();
+    }
+}
+
+use core::sync::atomic::{AtomicU32, Ordering};
+static REFCOUNT: AtomicU32 = AtomicU32::new(0);
+impl Drop for Alarms {
+    fn drop(&mut self) {
+        if let Some(sid) = self.cb_sid.take() {
+            let s = sid.to_u32();
+            send_message(self.conn,
+                Message::new_blocking_scalar(Opcode::Unlisten.to_usize().unwrap(),
+                s.0 as usize, s.1 as usize, s.2 as usize, s.3 as usize)
+            ).expect("couldn't unhook alarms");
+            let cid = xous::connect(sid).unwrap();
+            send_message(cid,
+                Message::new_blocking_scalar(AlarmCallback::Drop.to_usize().unwrap(), 0, 0, 0, 0)).unwrap();
+            unsafe{xous::disconnect(cid).ok();}
+        }
+        // the connection to the server side must be reference counted, so that multiple instances of this object within
+        // a single process do not end up de-allocating the CID on other threads before they go out of scope.
+        if REFCOUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
+            unsafe{xous::disconnect(self.conn).unwrap();}
+        }
+    }
+}
+
+/// handles callback messages that indicate an alarm went off, in the library user's process space.
+fn alarm_cb_server(sid0: usize, sid1: usize, sid2: usize, sid3: usize) {
+    let sid = xous::SID::from_u32(sid0 as u32, sid1 as u32, sid2 as u32, sid3 as u32);
+    loop {
+        let msg = xous::receive_message(sid).unwrap();
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(AlarmCallback::Fired) => xous::msg_scalar_unpack!(msg, cid, opcode, id, _, {
+                // pass the alarm ID on to the CID and opcode memorized in the original hook
+                send_message(cid as u32,
+                    Message::new_scalar(opcode, id, 0, 0, 0)
+                ).ok();
+            }),
+            Some(AlarmCallback::Drop) => {
+                xous::return_scalar(msg.sender, 1).unwrap();
+                break; // this exits the loop and kills the thread
+            }
+            None => (),
+        }
+    }
+    xous::destroy_server(sid).unwrap();
+}
@@ -0,0 +1,477 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+mod api;
+use api::*;
+mod schedule;
+use schedule::Alarm;
+
+use num_traits::*;
+use xous_ipc::Buffer;
+use xous::{send_message, Message, MessageEnvelope, CID};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use std::thread;
+
+/// all alarms live in this dictionary, one PDDB key per alarm ID
+const ALARM_DICT: &str = "sys.alarms";
+/// how late the ticktimer may wake us for an alarm
+const TIMER_SLACK_MS: u64 = 250;
+
+/// The status time server's public opcodes; the numbering is fixed, because `libstd` uses it too
+const TIME_OP_GET_UTC_TIME_MS: usize = 3;
+const TIME_OP_GET_LOCAL_TIME_MS: usize = 4;
+
+/// Reads the wall clock from the status time server
+struct WallClock {
+    conn: CID,
+}
+impl WallClock {
+    /// Waits for the status time server to come up
+    fn connect(tt: &ticktimer_server::Ticktimer) -> CID {
+        let sid = xous::SID::from_bytes(b"timeserverpublic").unwrap();
+        loop {
+            match xous::connect(sid) {
+                Ok(conn) => return conn,
+                Err(_) => tt.sleep_ms(100).unwrap(),
+            }
+        }
+    }
+    fn query(&self, op: usize) -> i64 {
+        match send_message(self.conn, Message::new_blocking_scalar(op, 0, 0, 0, 0)) {
+            Ok(xous::Result::Scalar2(hi, lo)) => (((hi as u64) << 32) | lo as u64) as i64,
+            e => panic!("couldn't read the time: {:?}", e),
+        }
+    }
+    /// The current time in UTC ms since the epoch, and the offset from UTC to local time. The offset
+    /// comes from two readings a moment apart, so it's rounded to the second.
+    fn now(&self) -> (i64, i64) {
+        let utc = self.query(TIME_OP_GET_UTC_TIME_MS);
+        let local = self.query(TIME_OP_GET_LOCAL_TIME_MS);
+        (utc, (local - utc + 500).div_euclid(1000) * 1000)
+    }
+}
+
+struct Listener {
+    /// the name of the process that hooked it
+    owner: String,
+    /// the callback server that alarms are sent through
+    sid: (u32, u32, u32, u32),
+    cb_conn: CID,
+    cid: CID,
+    opcode: u32,
+}
+
+struct Scheduler {
+    pddb: pddb::Pddb,
+    alarms: BTreeMap<u32, Alarm>,
+    loaded: bool,
+    /// by tag; each listener only hears about its owner's alarms
+    listeners: HashMap<String, Vec<Listener>>,
+    clock: Option<WallClock>,
+    tt: ticktimer_server::Ticktimer,
+    /// the ticktimer timer waiting for the next alarm
+    timer: Option<u32>,
+    /// when the next alarm goes off, in ticktimer ms
+    next_alarm_tt: Option<u64>,
+    self_cid: CID,
+}
+
+impl Scheduler {
+    /// Reads the alarms in, once the mount watcher says the PDDB is up
+    fn load(&mut self, clock: WallClock) {
+        if self.loaded {
+            return;
+        }
+        self.clock = Some(clock);
+        for name in self.pddb.list_keys(ALARM_DICT, None).unwrap_or(Vec::new()) {
+            let id = match name.parse::<u32>() {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+            let mut record = Vec::new();
+            let alarm = self.pddb.get(ALARM_DICT, &name, None, false, false, None, None::<fn()>)
+                .and_then(|mut key| key.read_to_end(&mut record))
+                .ok()
+                .and_then(|_| Alarm::from_bytes(&record));
+            match alarm {
+                Some(alarm) => {
+                    self.alarms.insert(id, alarm);
+                }
+                None => log::warn!("alarm {} is damaged, skipping it", name),
+            }
+        }
+        log::info!("{} alarms loaded", self.alarms.len());
+        self.loaded = true;
+        self.rearm();
+    }
+
+    fn now(&self) -> (i64, i64) {
+        self.clock.as_ref().unwrap().now()
+    }
+
+    fn store(&mut self, id: u32) -> Result<(), AlarmError> {
+        let record = self.alarms[&id].to_bytes();
+        let name = id.to_string();
+        // records only change length if the alarm is replaced; start from scratch anyway
+        self.pddb.delete_key(ALARM_DICT, &name, None).ok();
+        let mut key = self.pddb.get(ALARM_DICT, &name, None, true, true, Some(record.len()), None::<fn()>)
+            .or(Err(AlarmError::Storage))?;
+        key.write_all(&record).or(Err(AlarmError::Storage))?;
+        self.pddb.sync().or(Err(AlarmError::Storage))
+    }
+
+    fn remove(&mut self, id: u32) -> bool {
+        if self.alarms.remove(&id).is_none() {
+            return false;
+        }
+        self.pddb.delete_key(ALARM_DICT, &id.to_string(), None).ok();
+        self.pddb.sync().ok();
+        true
+    }
+
+    fn set(&mut self, owner: &str, request: &AlarmRequest) -> Result<u32, AlarmError> {
+        let tag = request.tag.as_str().unwrap_or("");
+        let label = request.label.as_str().or(Err(AlarmError::BadLabel))?;
+        if !schedule::tag_ok(tag) {
+            return Err(AlarmError::BadTag);
+        }
+        if label.len() > MAX_LABEL_LEN {
+            return Err(AlarmError::BadLabel);
+        }
+        if self.alarms.len() >= MAX_ALARMS {
+            return Err(AlarmError::Full);
+        }
+        let alarm = Alarm {
+            owner: String::from(owner),
+            tag: String::from(tag),
+            label: String::from(label),
+            when_ms: request.when_ms,
+            local: request.local,
+            repeat_ms: request.repeat_ms,
+            missed: false,
+        };
+        let (now, tz_offset) = self.now();
+        if alarm.repeat_ms == 0 && alarm.due_utc(tz_offset) <= now {
+            return Err(AlarmError::BadTime);
+        }
+        let id = self.alarms.keys().next_back().map_or(1, |last| last + 1);
+        self.alarms.insert(id, alarm);
+        if let Err(e) = self.store(id) {
+            self.alarms.remove(&id);
+            return Err(e);
+        }
+        log::info!("alarm {} set for {}/{}", id, owner, tag);
+        self.rearm();
+        Ok(id)
+    }
+
+    /// Sends alarm `id` to everything its owner has listening for its tag. Returns false if nothing
+    /// took it.
+    fn deliver(&self, id: u32, alarm: &Alarm) -> bool {
+        let mut delivered = false;
+        let listeners = self.listeners.get(&alarm.tag).map(|l| l.as_slice()).unwrap_or(&[]);
+        for l in listeners.iter().filter(|l| l.owner == alarm.owner) {
+            // never block on a listener; one that can't take the alarm now gets it later
+            if xous::try_send_message(l.cb_conn,
+                Message::new_scalar(AlarmCallback::Fired.to_usize().unwrap(), l.cid as usize, l.opcode as usize, id as usize, 0)
+            ).is_ok() {
+                delivered = true;
+            }
+        }
+        delivered
+    }
+
+    /// Sets off every alarm that's due, and retries the ones that went off while nothing was listening.
+    fn check(&mut self, now: i64, tz_offset: i64) {
+        let ids: Vec<u32> = self.alarms.keys().copied().collect();
+        for id in ids {
+            let mut alarm = self.alarms[&id].clone();
+            let due = alarm.due_utc(tz_offset) <= now && !(alarm.missed && alarm.repeat_ms == 0);
+            if !due && !alarm.missed {
+                continue;
+            }
+            let delivered = self.deliver(id, &alarm);
+            if delivered && alarm.repeat_ms == 0 {
+                log::info!("alarm {} went off", id);
+                self.remove(id);
+                continue;
+            }
+            if due {
+                alarm.advance(now, tz_offset);
+            }
+            // a repeating alarm that was missed is delivered once, whenever its owner next listens for its tag
+            alarm.missed = !delivered;
+            if alarm != self.alarms[&id] {
+                self.alarms.insert(id, alarm);
+                if self.store(id).is_err() {
+                    log::error!("couldn't update alarm {}", id);
+                }
+            }
+        }
+    }
+
+    /// When the next alarm goes off, in UTC ms
+    fn next_due(&self, tz_offset: i64) -> Option<i64> {
+        self.alarms.values()
+            .filter(|a| !(a.missed && a.repeat_ms == 0))
+            .map(|a| a.due_utc(tz_offset))
+            .min()
+    }
+
+    /// Sets off anything that's due, then points the ticktimer at the next alarm. Called whenever the
+    /// alarms or the clock change.
+    fn rearm(&mut self) {
+        if !self.loaded {
+            return;
+        }
+        let (now, tz_offset) = self.now();
+        self.check(now, tz_offset);
+        if let Some(id) = self.timer.take() {
+            self.tt.cancel_timer(id).ok();
+        }
+        self.next_alarm_tt = None;
+        if let Some(due) = self.next_due(tz_offset) {
+            let delay = (due - now).max(0) as u64;
+            self.next_alarm_tt = Some(self.tt.elapsed_ms() + delay);
+            match self.tt.register_timer(self.self_cid, Opcode::TimerFired.to_usize().unwrap(), 0, delay, 0, TIMER_SLACK_MS) {
+                Ok(id) => self.timer = Some(id),
+                Err(e) => log::error!("couldn't start the alarm timer: {:?}", e),
+            }
+        }
+    }
+
+    /// Before a suspend: has the RTC wake the device on the way to the next alarm. The time server
+    /// suspends before we do, so this goes by the ticktimer.
+    fn set_wakeup(&self, llio: &llio::Llio) {
+        if let Some(due) = self.next_alarm_tt {
+            let (count, units) = schedule::hop(due as i64 - self.tt.elapsed_ms() as i64);
+            log::info!("waking up in {} {:?} for the next alarm", count, units);
+            match llio.set_wakeup_alarm_units(count, units) {
+                Ok(true) => (),
+                Ok(false) => log::info!("a sooner wakeup was already set"),
+                Err(e) => log::error!("couldn't set the wakeup alarm: {:?}", e),
+            }
+        }
+    }
+}
+
+/// The name the sender's alarms are kept under; processes that haven't registered a name can't own alarms
+fn owner(xns: &xous_names::XousNames, sender: xous::MessageSender) -> Result<String, AlarmError> {
+    let pid = sender.pid().ok_or(AlarmError::Unidentified)?;
+    xns.owner_name(pid).ok().flatten().ok_or(AlarmError::Unidentified)
+}
+
+/// Handles the requests that need the alarms loaded
+fn handle_request(scheduler: &mut Scheduler, xns: &xous_names::XousNames, mut msg: MessageEnvelope) {
+    let owner = owner(xns, msg.sender);
+    match FromPrimitive::from_usize(msg.body.id()) {
+        Some(Opcode::SetAlarm) => {
+            let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+            let mut request = buffer.to_original::<AlarmRequest, _>().unwrap();
+            match owner.and_then(|owner| scheduler.set(&owner, &request)) {
+                Ok(id) => {
+                    request.id = id;
+                    request.result = None;
+                }
+                Err(e) => {
+                    log::debug!("couldn't set alarm: {:?}", e);
+                    request.result = Some(e);
+                }
+            }
+            buffer.replace(request).unwrap();
+        }
+        Some(Opcode::CancelAlarm) => xous::msg_blocking_scalar_unpack!(msg, id, _, _, _, {
+            let id = id as u32;
+            let result = match owner {
+                Ok(owner) if scheduler.alarms.get(&id).map_or(false, |a| a.owner == owner) => {
+                    scheduler.remove(id);
+                    CANCELLED
+                }
+                Ok(_) => NOT_CANCELLED,
+                Err(_) => UNIDENTIFIED,
+            };
+            xous::return_scalar(msg.sender, result).unwrap();
+            if result == CANCELLED {
+                log::info!("alarm {} cancelled", id);
+                scheduler.rearm();
+            }
+        }),
+        Some(Opcode::ListAlarms) => {
+            let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+            let mut list = buffer.to_original::<AlarmList, _>().unwrap();
+            list.count = 0;
+            list.more = false;
+            match owner {
+                Ok(owner) => {
+                    let tag = list.tag.as_str().unwrap_or("");
+                    let mut matching = scheduler.alarms.range(list.after.saturating_add(1)..)
+                        .filter(|(_, a)| a.is_for(&owner, tag));
+                    for (i, (id, alarm)) in matching.by_ref().take(MAX_LISTED).enumerate() {
+                        list.ids[i] = *id;
+                        list.when_ms[i] = alarm.when_ms;
+                        list.local[i] = alarm.local;
+                        list.repeat_ms[i] = alarm.repeat_ms;
+                        list.labels[i] = xous_ipc::String::from_str(&alarm.label);
+                        list.count += 1;
+                    }
+                    list.more = matching.next().is_some();
+                    list.result = None;
+                }
+                Err(e) => list.result = Some(e),
+            }
+            buffer.replace(list).unwrap();
+        }
+        _ => log::error!("not a request for the alarms: {:?}", msg),
+    }
+}
+
+#[xous::xous_main]
+fn xmain() -> ! {
+    log_server::init_wait().unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+    log::info!("my PID is {}", xous::process::id());
+
+    let xns = xous_names::XousNames::new().unwrap();
+    let alarms_sid = xns.register_name(api::SERVER_NAME_ALARMS, None).expect("can't register server");
+    log::trace!("registered with NS -- {:?}", alarms_sid);
+
+    let llio = llio::Llio::new(&xns);
+    let sr_cid = xous::connect(alarms_sid).expect("couldn't create suspend callback connection");
+    let mut susres = susres::Susres::new(None, &xns, Opcode::SuspendResume as u32, sr_cid).expect("couldn't create suspend/resume object");
+
+    let mut scheduler = Scheduler {
+        pddb: pddb::Pddb::new(),
+        alarms: BTreeMap::new(),
+        loaded: false,
+        listeners: HashMap::new(),
+        clock: None,
+        tt: ticktimer_server::Ticktimer::new().unwrap(),
+        timer: None,
+        next_alarm_tt: None,
+        self_cid: xous::connect(alarms_sid).unwrap(),
+    };
+    // the callback server connections, by SID
+    let mut callbacks: HashMap<(u32, u32, u32, u32), CID> = HashMap::new();
+
+    // requests that came in before the alarms were loaded, answered once they are
+    let mut waiting: Vec<MessageEnvelope> = Vec::new();
+
+    // load the alarms as soon as the PDDB is mounted and the clock can be read, so they're running
+    // before anyone asks about them. Waiting is done here, so the main loop keeps taking messages.
+    thread::spawn({
+        let self_cid = scheduler.self_cid;
+        move || {
+            let pddb = pddb::Pddb::new();
+            pddb.is_mounted_blocking(None);
+            let clock_conn = WallClock::connect(&ticktimer_server::Ticktimer::new().unwrap());
+            send_message(self_cid,
+                Message::new_scalar(Opcode::PddbMounted.to_usize().unwrap(), clock_conn as usize, 0, 0, 0)
+            ).expect("couldn't report the PDDB mount");
+        }
+    });
+
+    log::trace!("ready to accept requests");
+    loop {
+        let mut msg = xous::receive_message(alarms_sid).unwrap();
+        log::trace!("Message: {:?}", msg);
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(Opcode::SetAlarm) | Some(Opcode::CancelAlarm) | Some(Opcode::ListAlarms) => {
+                if scheduler.loaded {
+                    handle_request(&mut scheduler, &xns, msg);
+                } else {
+                    // holding on to the envelope holds the caller until it's answered
+                    waiting.push(msg);
+                }
+            }
+            Some(Opcode::Listen) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut hook = buffer.to_original::<ListenHook, _>().unwrap();
+                let tag = String::from(hook.tag.as_str().unwrap_or(""));
+                if !schedule::tag_ok(&tag) {
+                    hook.result = Some(AlarmError::BadTag);
+                    buffer.replace(hook).unwrap();
+                    continue;
+                }
+                let owner = match owner(&xns, msg.sender) {
+                    Ok(owner) => owner,
+                    Err(e) => {
+                        hook.result = Some(e);
+                        buffer.replace(hook).unwrap();
+                        continue;
+                    }
+                };
+                let cb_conn = match callbacks.get(&hook.sid) {
+                    Some(conn) => *conn,
+                    None => {
+                        let conn = xous::connect(xous::SID::from_u32(hook.sid.0, hook.sid.1, hook.sid.2, hook.sid.3)).unwrap();
+                        callbacks.insert(hook.sid, conn);
+                        conn
+                    }
+                };
+                scheduler.listeners.entry(tag).or_default().push(Listener {
+                    owner,
+                    sid: hook.sid,
+                    cb_conn,
+                    cid: hook.cid,
+                    opcode: hook.opcode,
+                });
+                hook.result = None;
+                buffer.replace(hook).unwrap();
+                // hand over anything that went off while nobody was listening
+                scheduler.rearm();
+            }
+            Some(Opcode::Unlisten) => xous::msg_blocking_scalar_unpack!(msg, s0, s1, s2, s3, {
+                let sid = (s0 as u32, s1 as u32, s2 as u32, s3 as u32);
+                // a process that never listened has nothing to take down
+                if let Ok(owner) = owner(&xns, msg.sender) {
+                    for listeners in scheduler.listeners.values_mut() {
+                        listeners.retain(|l| !(l.sid == sid && l.owner == owner));
+                    }
+                    scheduler.listeners.retain(|_, l| !l.is_empty());
+                    let in_use = scheduler.listeners.values().flatten().any(|l| l.sid == sid);
+                    if !in_use {
+                        if let Some(conn) = callbacks.remove(&sid) {
+                            unsafe { xous::disconnect(conn).ok() };
+                        }
+                    }
+                }
+                xous::return_scalar(msg.sender, 1).unwrap();
+            }),
+            Some(Opcode::ClockChanged) => {
+                log::info!("clock changed, rescheduling");
+                scheduler.rearm();
+            }
+            Some(Opcode::TimerFired) => {
+                // the timer is one-shot, so it's gone now
+                scheduler.timer = None;
+                scheduler.rearm();
+            }
+            Some(Opcode::PddbMounted) => xous::msg_scalar_unpack!(msg, clock_conn, _, _, _, {
+                scheduler.load(WallClock { conn: clock_conn as CID });
+                for request in waiting.drain(..) {
+                    handle_request(&mut scheduler, &xns, request);
+                }
+            }),
+            Some(Opcode::SuspendResume) => xous::msg_scalar_unpack!(msg, token, _, _, _, {
+                scheduler.set_wakeup(&llio);
+                susres.suspend_until_resume(token).expect("couldn't execute suspend/resume");
+                // the ticktimer stood still while we were out, but the wall clock didn't
+                scheduler.rearm();
+            }),
+            Some(Opcode::Quit) => {
+                log::warn!("Quit received, goodbye world!");
+                break;
+            }
+            None => {
+                log::error!("couldn't convert opcode: {:?}", msg);
+            }
+        }
+    }
+    // clean up our program
+    log::trace!("main loop exit, destroying servers");
+    xns.unregister_server(alarms_sid).unwrap();
+    xous::destroy_server(alarms_sid).unwrap();
+    log::trace!("quitting");
+    xous::terminate_process(0)
+}
@@ -0,0 +1,206 @@
+//! The alarm records kept in the PDDB, and the arithmetic of when they're due.
+//!
+//! Each alarm is one PDDB key, named after its ID, holding a version byte, flags, the time and
+//! repeat interval, and the owner, tag and label as length-prefixed UTF-8.
+
+use llio::TimeUnits;
+use std::convert::TryInto;
+
+const RECORD_VERSION: u8 = 1;
+const FLAG_LOCAL: u8 = 1;
+const FLAG_MISSED: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Alarm {
+    /// the name of the process that set it, as registered with xous-names
+    pub owner: String,
+    pub tag: String,
+    pub label: String,
+    /// ms since the epoch; UTC, or local time if `local`
+    pub when_ms: i64,
+    pub local: bool,
+    /// 0 for a one-shot alarm
+    pub repeat_ms: u64,
+    /// went off while nothing was listening for its tag, and is waiting to be delivered
+    pub missed: bool,
+}
+
+impl Alarm {
+    /// Whether the alarm is one of `owner`'s, under `tag`
+    pub fn is_for(&self, owner: &str, tag: &str) -> bool {
+        self.owner == owner && self.tag == tag
+    }
+
+    /// When the alarm goes off, in UTC ms, given the current offset from UTC to local time
+    pub fn due_utc(&self, tz_offset_ms: i64) -> i64 {
+        if self.local {
+            self.when_ms.saturating_sub(tz_offset_ms)
+        } else {
+            self.when_ms
+        }
+    }
+
+    /// Moves a repeating alarm on to its first time after `now_utc`, skipping any it slept through.
+    /// Returns false for a one-shot alarm, which is done.
+    pub fn advance(&mut self, now_utc: i64, tz_offset_ms: i64) -> bool {
+        if self.repeat_ms == 0 {
+            return false;
+        }
+        let due = self.due_utc(tz_offset_ms);
+        if due <= now_utc {
+            let periods = (now_utc - due) as u64 / self.repeat_ms + 1;
+            self.when_ms = self.when_ms.saturating_add(periods.saturating_mul(self.repeat_ms) as i64);
+        }
+        true
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut record = Vec::with_capacity(21 + self.owner.len() + self.tag.len() + self.label.len());
+        record.push(RECORD_VERSION);
+        let mut flags = 0;
+        if self.local {
+            flags |= FLAG_LOCAL;
+        }
+        if self.missed {
+            flags |= FLAG_MISSED;
+        }
+        record.push(flags);
+        record.extend_from_slice(&self.when_ms.to_le_bytes());
+        record.extend_from_slice(&self.repeat_ms.to_le_bytes());
+        record.push(self.owner.len() as u8);
+        record.extend_from_slice(self.owner.as_bytes());
+        record.push(self.tag.len() as u8);
+        record.extend_from_slice(self.tag.as_bytes());
+        record.push(self.label.len() as u8);
+        record.extend_from_slice(self.label.as_bytes());
+        record
+    }
+
+    pub fn from_bytes(record: &[u8]) -> Option<Alarm> {
+        if record.len() < 18 || record[0] != RECORD_VERSION {
+            return None;
+        }
+        let flags = record[1];
+        let when_ms = i64::from_le_bytes(record[2..10].try_into().unwrap());
+        let repeat_ms = u64::from_le_bytes(record[10..18].try_into().unwrap());
+        let (owner, rest) = take_str(&record[18..])?;
+        let (tag, rest) = take_str(rest)?;
+        let (label, rest) = take_str(rest)?;
+        if !rest.is_empty() {
+            return None;
+        }
+        Some(Alarm {
+            owner,
+            tag,
+            label,
+            when_ms,
+            local: flags & FLAG_LOCAL != 0,
+            repeat_ms,
+            missed: flags & FLAG_MISSED != 0,
+        })
+    }
+}
+
+fn take_str(data: &[u8]) -> Option<(String, &[u8])> {
+    let len = *data.first()? as usize;
+    let s = data.get(1..1 + len)?;
+    Some((String::from(std::str::from_utf8(s).ok()?), &data[1 + len..]))
+}
+
+/// Tags name the app an alarm is for; they're limited to printable text
+pub(crate) fn tag_ok(tag: &str) -> bool {
+    !tag.is_empty() && tag.len() <= crate::api::MAX_TAG_LEN && tag.chars().all(|c| !c.is_control())
+}
+
+/// The next RTC wakeup on the way to an alarm `remaining_ms` away. The RTC counts at most 255
+/// ticks, and a coarse countdown's first tick comes whenever its clock next ticks over, so it's
+/// one unit short of the remaining time: the hop lands early, and the last stretch is done in
+/// seconds.
+pub(crate) fn hop(remaining_ms: i64) -> (u8, TimeUnits) {
+    let ms = remaining_ms.max(0) as u64;
+    let secs = ms / 1000;
+    if secs >= 2 * 3600 {
+        ((secs / 3600 - 1).min(255) as u8, TimeUnits::Hours)
+    } else if secs >= 2 * 60 {
+        ((secs / 60 - 1) as u8, TimeUnits::Minutes)
+    } else {
+        // round up, so the last hop doesn't land just short
+        (((ms + 999) / 1000).max(1) as u8, TimeUnits::Seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = 3600 * 1000;
+    const DAY: i64 = 24 * HOUR;
+
+    fn alarm(when_ms: i64, local: bool, repeat_ms: u64) -> Alarm {
+        Alarm {
+            owner: String::from("calendar"),
+            tag: String::from("reminders"),
+            label: String::from("stand-up ☕"),
+            when_ms,
+            local,
+            repeat_ms,
+            missed: false,
+        }
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let mut a = alarm(1_650_000_000_000, true, DAY as u64);
+        a.missed = true;
+        let bytes = a.to_bytes();
+        assert_eq!(Alarm::from_bytes(&bytes), Some(a));
+        assert_eq!(Alarm::from_bytes(&bytes[..bytes.len() - 1]), None);
+        let mut newer = bytes.clone();
+        newer[0] = RECORD_VERSION + 1;
+        assert_eq!(Alarm::from_bytes(&newer), None);
+    }
+
+    #[test]
+    fn alarms_belong_to_their_owner() {
+        let a = alarm(0, false, 0);
+        assert!(a.is_for("calendar", "reminders"));
+        // another app can't reach it by using the same tag
+        assert!(!a.is_for("messenger", "reminders"));
+        assert!(!a.is_for("calendar", "birthdays"));
+    }
+
+    #[test]
+    fn local_alarms_follow_the_time_zone() {
+        let a = alarm(7 * HOUR + HOUR / 2, true, 0);
+        // 07:30 in UTC+2 is 05:30 UTC
+        assert_eq!(a.due_utc(2 * HOUR), 5 * HOUR + HOUR / 2);
+        assert_eq!(a.due_utc(-5 * HOUR), 12 * HOUR + HOUR / 2);
+        let u = alarm(7 * HOUR, false, 0);
+        assert_eq!(u.due_utc(2 * HOUR), 7 * HOUR);
+    }
+
+    #[test]
+    fn repeats_skip_what_was_slept_through() {
+        let mut a = alarm(7 * HOUR, false, DAY as u64);
+        // three days later, just after the alarm time: the next one is tomorrow
+        assert!(a.advance(3 * DAY + 7 * HOUR + 1, 0));
+        assert_eq!(a.when_ms, 4 * DAY + 7 * HOUR);
+        // not due yet: left alone
+        assert!(a.advance(4 * DAY, 0));
+        assert_eq!(a.when_ms, 4 * DAY + 7 * HOUR);
+        assert!(!alarm(0, false, 0).advance(1, 0));
+    }
+
+    #[test]
+    fn hops() {
+        assert_eq!(hop(-5), (1, TimeUnits::Seconds));
+        assert_eq!(hop(1), (1, TimeUnits::Seconds));
+        assert_eq!(hop(119_000), (119, TimeUnits::Seconds));
+        assert_eq!(hop(120_000), (1, TimeUnits::Minutes));
+        assert_eq!(hop(2 * HOUR - 1), (118, TimeUnits::Minutes));
+        assert_eq!(hop(2 * HOUR), (1, TimeUnits::Hours));
+        // 07:30 tomorrow from 09:00 today
+        assert_eq!(hop(22 * HOUR + HOUR / 2), (21, TimeUnits::Hours));
+        assert_eq!(hop(30 * DAY), (255, TimeUnits::Hours));
+    }
+}
@@ -0,0 +1,302 @@
+//! A container for a clip of coded audio: a short header saying how it was coded, then the
+//! coded stream. It only needs `Read`, or `Write` and `Seek`, so a clip goes straight into a
+//! PDDB key:
+//!
+//! ```ignore
+//! let key = pddb.get("voice.memos", "groceries", None, true, true, None, None::<fn()>)?;
+//! let mut clip = ClipWriter::new(key, Encoding::ImaAdpcm, format)?;
+//! // ...then, every time the CODEC hands over recorded frames:
+//! clip.write_frames(&mut frames)?;
+//! // ...and once recording stops:
+//! clip.finish()?;
+//! ```
+//!
+//! The header is 20 bytes, little-endian:
+//!
+//! | offset | size | field |
+//! |--------|------|-------|
+//! | 0      | 4    | magic, `XCLP` |
+//! | 4      | 1    | version, 1 |
+//! | 5      | 1    | encoding: 0 PCM, 1 IMA ADPCM, 2 G.726 at 2 bits, 3 G.726 at 3 bits; 4 is set aside for Opus |
+//! | 6      | 1    | channels, 1 or 2 |
+//! | 7      | 1    | reserved, 0 |
+//! | 8      | 4    | sample rate, in Hz |
+//! | 12     | 4    | length, in samples of each channel |
+//! | 16     | 2    | block size of the coded stream, in bytes |
+//! | 18     | 2    | reserved, 0 |
+
+use std::convert::TryInto;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use codec::{Channels, FrameRing, SampleFormat, StreamFormat};
+
+use crate::{Decoder, Encoder, Encoding, SAMPLES_PER_FRAME};
+
+pub const CLIP_MAGIC: [u8; 4] = *b"XCLP";
+pub const CLIP_VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 20;
+const LENGTH_OFFSET: u64 = 12;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ClipHeader {
+    pub encoding: Encoding,
+    pub format: StreamFormat,
+    /// samples of each channel
+    pub samples: u32,
+    pub block_align: u16,
+}
+
+impl ClipHeader {
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut b = [0u8; HEADER_LEN];
+        b[0..4].copy_from_slice(&CLIP_MAGIC);
+        b[4] = CLIP_VERSION;
+        b[5] = self.encoding.id();
+        b[6] = self.format.channels.count() as u8;
+        b[8..12].copy_from_slice(&self.format.rate.to_le_bytes());
+        b[12..16].copy_from_slice(&self.samples.to_le_bytes());
+        b[16..18].copy_from_slice(&self.block_align.to_le_bytes());
+        b
+    }
+    pub fn from_bytes(b: &[u8]) -> io::Result<ClipHeader> {
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+        if b.len() < HEADER_LEN || b[0..4] != CLIP_MAGIC {
+            return Err(invalid("not an audio clip"));
+        }
+        if b[4] != CLIP_VERSION {
+            return Err(invalid("unknown clip version"));
+        }
+        let encoding = Encoding::from_id(b[5]).ok_or_else(|| invalid("unsupported clip encoding"))?;
+        let channels = match b[6] {
+            1 => Channels::Mono,
+            2 => Channels::Stereo,
+            _ => return Err(invalid("clips are mono or stereo")),
+        };
+        let rate = u32::from_le_bytes(b[8..12].try_into().unwrap());
+        let block_align = u16::from_le_bytes(b[16..18].try_into().unwrap());
+        let block_ok = match (encoding, encoding.g726()) {
+            (Encoding::ImaAdpcm, _) => block_align as usize >= 8 * channels.count(),
+            // G.726 codes can't straddle blocks
+            (_, Some(g726)) => block_align as usize % g726.bits == 0,
+            _ => true,
+        };
+        if rate == 0 || block_align == 0 || !block_ok {
+            return Err(invalid("corrupt clip header"));
+        }
+        Ok(ClipHeader {
+            encoding,
+            format: StreamFormat::new(rate, channels, SampleFormat::I16),
+            samples: u32::from_le_bytes(b[12..16].try_into().unwrap()),
+            block_align,
+        })
+    }
+    pub fn duration_ms(&self) -> u32 {
+        (self.samples as u64 * 1000 / self.format.rate as u64) as u32
+    }
+}
+
+/// Writes a clip as it is recorded. The length in the header is filled in by `finish()`; a clip
+/// that was never finished reads as empty.
+pub struct ClipWriter<W: Write + Seek> {
+    out: W,
+    header: ClipHeader,
+    encoder: Encoder,
+    buf: Vec<u8>,
+}
+
+impl<W: Write + Seek> ClipWriter<W> {
+    pub fn new(mut out: W, encoding: Encoding, format: StreamFormat) -> io::Result<Self> {
+        let encoder = Encoder::new(encoding, format);
+        let header = ClipHeader {
+            encoding,
+            format,
+            samples: 0,
+            block_align: encoder.block_align() as u16,
+        };
+        out.write_all(&header.to_bytes())?;
+        Ok(ClipWriter { out, header, encoder, buf: Vec::new() })
+    }
+    pub fn header(&self) -> &ClipHeader {
+        &self.header
+    }
+    /// Adds `samples`, interleaved if stereo
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        self.header.samples += (samples.len() / self.header.format.channels.count()) as u32;
+        self.encoder.encode(samples, &mut self.buf);
+        self.out.write_all(&self.buf)?;
+        self.buf.clear();
+        Ok(())
+    }
+    /// Adds every frame queued in `frames`, leaving it empty. Returns the number of frames.
+    pub fn write_frames(&mut self, frames: &mut FrameRing) -> io::Result<usize> {
+        let mut samples = Vec::with_capacity(SAMPLES_PER_FRAME);
+        let mut count = 0;
+        while let Some(frame) = frames.dq_frame() {
+            samples.clear();
+            frames.format().unpack(&frame, &mut samples);
+            self.write_samples(&samples)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+    /// Writes out the last of the audio and the clip's length
+    pub fn finish(mut self) -> io::Result<W> {
+        self.encoder.finish(&mut self.buf);
+        self.out.write_all(&self.buf)?;
+        let end = self.out.stream_position()?;
+        self.out.seek(SeekFrom::Start(LENGTH_OFFSET))?;
+        self.out.write_all(&self.header.samples.to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(end))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Reads a clip back as samples or frames.
+pub struct ClipReader<R: Read> {
+    input: R,
+    header: ClipHeader,
+    decoder: Decoder,
+    /// samples, counting each channel's, still to hand out
+    remaining: u64,
+    at_end: bool,
+}
+
+impl<R: Read> ClipReader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut b = [0u8; HEADER_LEN];
+        input.read_exact(&mut b)?;
+        let header = ClipHeader::from_bytes(&b)?;
+        Ok(ClipReader {
+            input,
+            header,
+            decoder: Decoder::with_block_align(header.encoding, header.format, header.block_align as usize),
+            remaining: header.samples as u64 * header.format.channels.count() as u64,
+            at_end: false,
+        })
+    }
+    pub fn header(&self) -> &ClipHeader {
+        &self.header
+    }
+    pub fn is_finished(&self) -> bool {
+        self.remaining == 0
+    }
+    /// Decodes until at least `wanted` samples are waiting, or the input runs out
+    fn fill(&mut self, wanted: usize) -> io::Result<()> {
+        let mut buf = vec![0u8; (self.header.block_align as usize).max(256)];
+        while self.decoder.available() < wanted && !self.at_end {
+            let n = self.input.read(&mut buf)?;
+            if n == 0 {
+                self.at_end = true;
+                self.decoder.finish();
+            } else {
+                self.decoder.decode(&buf[..n]);
+            }
+        }
+        Ok(())
+    }
+    /// Appends up to `max` samples (counting each channel's) to `out`, interleaved if stereo.
+    /// Returns the number appended, which is 0 at the end of the clip.
+    pub fn read_samples(&mut self, max: usize, out: &mut Vec<i16>) -> io::Result<usize> {
+        let wanted = (max as u64).min(self.remaining) as usize;
+        self.fill(wanted)?;
+        let n = self.decoder.take_samples(wanted, out);
+        self.remaining -= n as u64;
+        if n < wanted {
+            log::warn!("clip ended {} samples short", self.remaining);
+            self.remaining = 0;
+        }
+        Ok(n)
+    }
+    /// Fills `frames` with as much of the clip as fits, padding the last frame with silence.
+    /// Returns the number of frames filled, which is 0 at the end of the clip.
+    pub fn read_frames(&mut self, frames: &mut FrameRing) -> io::Result<usize> {
+        let mut count = 0;
+        let mut samples = Vec::with_capacity(SAMPLES_PER_FRAME);
+        let mut words = Vec::new();
+        while !frames.is_full() && !self.is_finished() {
+            samples.clear();
+            self.read_samples(SAMPLES_PER_FRAME, &mut samples)?;
+            samples.resize(SAMPLES_PER_FRAME, 0);
+            words.clear();
+            self.header.format.pack(&samples, &mut words);
+            let mut frame = [0u32; codec::FIFO_DEPTH];
+            frame.copy_from_slice(&words);
+            frames.nq_frame(frame).unwrap(); // we checked it isn't full
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn header_round_trip() {
+        let header = ClipHeader {
+            encoding: Encoding::ImaAdpcm,
+            format: StreamFormat::new(16000, Channels::Stereo, SampleFormat::I16),
+            samples: 123_456,
+            block_align: 1024,
+        };
+        let bytes = header.to_bytes();
+        assert_eq!(&bytes[..6], b"XCLP\x01\x01");
+        assert_eq!(ClipHeader::from_bytes(&bytes).unwrap(), header);
+        assert_eq!(header.duration_ms(), 7716);
+        let mut opus = bytes;
+        opus[5] = 4;
+        assert!(ClipHeader::from_bytes(&opus).is_err());
+        assert!(ClipHeader::from_bytes(&bytes[..12]).is_err());
+    }
+
+    #[test]
+    fn clip_round_trip() {
+        let format = StreamFormat::new(8000, Channels::Mono, SampleFormat::I16);
+        let input: Vec<i16> = (0..3000).map(|i| ((i % 100) * 50) as i16).collect();
+        for &encoding in [Encoding::Pcm16, Encoding::ImaAdpcm, Encoding::G726_16, Encoding::G726_24].iter() {
+            let mut w = ClipWriter::new(Cursor::new(Vec::new()), encoding, format).unwrap();
+            // written in pieces that don't line up with anything
+            for chunk in input.chunks(333) {
+                w.write_samples(chunk).unwrap();
+            }
+            let file = w.finish().unwrap().into_inner();
+
+            let mut r = ClipReader::new(Cursor::new(file)).unwrap();
+            assert_eq!(r.header().samples, 3000);
+            assert_eq!(r.header().encoding, encoding);
+            let mut output = Vec::new();
+            while r.read_samples(700, &mut output).unwrap() > 0 {}
+            // padding at the end of the last block isn't handed out
+            assert_eq!(output.len(), input.len());
+            assert!(r.is_finished());
+        }
+    }
+
+    #[test]
+    fn frames_in_and_out() {
+        let format = StreamFormat::new(8000, Channels::Stereo, SampleFormat::I16);
+        let mut ring = FrameRing::with_format(format);
+        for f in 0..3u32 {
+            ring.nq_frame([f | (f + 100) << 16; codec::FIFO_DEPTH]).unwrap();
+        }
+        let mut w = ClipWriter::new(Cursor::new(Vec::new()), Encoding::Pcm16, format).unwrap();
+        assert_eq!(w.write_frames(&mut ring).unwrap(), 3);
+        w.write_samples(&[7, 8]).unwrap();
+        let file = w.finish().unwrap().into_inner();
+
+        let mut r = ClipReader::new(Cursor::new(file)).unwrap();
+        assert_eq!(r.header().samples, 3 * codec::FIFO_DEPTH as u32 + 1);
+        assert_eq!(r.read_frames(&mut ring).unwrap(), 4);
+        assert_eq!(r.read_frames(&mut ring).unwrap(), 0);
+        assert_eq!(ring.dq_frame().unwrap()[0], 0 | 100 << 16);
+        ring.dq_frame();
+        assert_eq!(ring.dq_frame().unwrap()[5], 2 | 102 << 16);
+        // the odd sample frame at the end, then silence
+        let last = ring.dq_frame().unwrap();
+        assert_eq!(last[0], 7 | 8 << 16);
+        assert!(last[1..].iter().all(|&w| w == 0));
+    }
+}
@@ -0,0 +1,490 @@
+//! G.726 ADPCM at 2 and 3 bits a sample: 16 and 24kbit/s at 8kHz, an eighth and three
+//! sixteenths the size of PCM. Built for speech, which it keeps intelligible at a fraction of
+//! what IMA ADPCM takes, where music comes out rough.
+//!
+//! The arithmetic follows the ITU reference: a two-pole, six-zero predictor, adapted sample by
+//! sample, in the same fixed point. Unlike IMA ADPCM there are no headers; the state carries on
+//! from the start of the stream. Codes are packed least significant bits first, as RFC 3551 has
+//! them, eight codes to a block, so a block is two bytes at 16kbit/s and three at 24kbit/s.
+//! Stereo interleaves the channels' codes.
+
+/// A G.726 bit rate, by its code tables
+#[derive(Debug)]
+pub struct Rate {
+    /// bits a code
+    pub bits: usize,
+    /// decision levels of the quantizer, in the log domain
+    quantizer: &'static [i32],
+    /// whether the smallest positive difference gets a code of its own. At 16kbit/s there are
+    /// too few codes to spare one.
+    zero_code: bool,
+    /// by code: the quantized difference, in the log domain
+    dqln: &'static [i32],
+    /// by code: the scale factor multiplier
+    wi: &'static [i32],
+    /// by code: the transition detector's input
+    fi: &'static [i32],
+}
+
+pub static RATE_16K: Rate = Rate {
+    bits: 2,
+    quantizer: &[261],
+    zero_code: false,
+    dqln: &[116, 365, 365, 116],
+    wi: &[-704, 14048, 14048, -704],
+    fi: &[0, 0xE00, 0xE00, 0],
+};
+
+pub static RATE_24K: Rate = Rate {
+    bits: 3,
+    quantizer: &[8, 218, 331],
+    zero_code: true,
+    dqln: &[-2048, 135, 273, 373, 373, 273, 135, -2048],
+    wi: &[-128, 960, 4384, 18624, 18624, 4384, 960, -128],
+    fi: &[0, 0x200, 0x400, 0xE00, 0xE00, 0x400, 0x200, 0],
+};
+
+/// Codes in a block, whatever the rate; always a whole number of bytes
+pub const CODES_PER_BLOCK: usize = 8;
+
+const POWER2: [i32; 15] = [1, 2, 4, 8, 0x10, 0x20, 0x40, 0x80, 0x100, 0x200, 0x400, 0x800, 0x1000, 0x2000, 0x4000];
+
+/// The number of entries in `table` that `val` is at or above
+fn quan(val: i32, table: &[i32]) -> i32 {
+    table.iter().take_while(|&&t| val >= t).count() as i32
+}
+
+/// Multiplies a predictor coefficient by a difference or sample held in the reference's
+/// 4-bit exponent, 6-bit mantissa floating point
+fn fmult(an: i32, srn: i32) -> i32 {
+    let anmag = if an > 0 { an } else { (-an) & 0x1FFF };
+    let anexp = quan(anmag, &POWER2) - 6;
+    let anmant = if anmag == 0 {
+        32
+    } else if anexp >= 0 {
+        anmag >> anexp
+    } else {
+        anmag << -anexp
+    };
+    let wanexp = anexp + ((srn >> 6) & 0xF) - 13;
+    let wanmant = (anmant * (srn & 0o77) + 0x30) >> 4;
+    let retval = if wanexp >= 0 { (wanmant << wanexp) & 0x7FFF } else { wanmant >> -wanexp };
+    if (an ^ srn) < 0 {
+        -retval
+    } else {
+        retval
+    }
+}
+
+/// A magnitude in the reference's floating point, for the predictor's history. Negative values
+/// are stored as the magnitude less 0x400.
+fn to_float(mag: i32) -> i32 {
+    if mag == 0 {
+        return 0x20;
+    }
+    let exp = quan(mag, &POWER2);
+    (exp << 6) + ((mag << 6) >> exp)
+}
+
+/// The predictor and quantizer adaptation for one channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelState {
+    /// locked (slow) scale factor
+    yl: i32,
+    /// unlocked (fast) scale factor
+    yu: i32,
+    /// short and long term averages of the transition detector's input
+    dms: i32,
+    dml: i32,
+    /// how far to lean on the fast scale factor
+    ap: i32,
+    /// pole and zero coefficients
+    a: [i32; 2],
+    b: [i32; 6],
+    /// signs of the last two partial reconstructions
+    pk: [i32; 2],
+    /// the last six quantized differences and two reconstructed samples, in floating point
+    dq: [i32; 6],
+    sr: [i32; 2],
+    /// a tone was detected
+    td: bool,
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        ChannelState {
+            yl: 34816,
+            yu: 544,
+            dms: 0,
+            dml: 0,
+            ap: 0,
+            a: [0; 2],
+            b: [0; 6],
+            pk: [0; 2],
+            dq: [32; 6],
+            sr: [32; 2],
+            td: false,
+        }
+    }
+}
+
+/// What goes into coding a sample, from the state before it
+struct Prediction {
+    /// the signal estimate
+    se: i32,
+    /// its zero (moving average) part
+    sez: i32,
+    /// the quantizer's scale factor
+    y: i32,
+}
+
+impl ChannelState {
+    fn predict(&self) -> Prediction {
+        let sezi: i32 = self.b.iter().zip(self.dq.iter()).map(|(&b, &dq)| fmult(b >> 2, dq)).sum();
+        let sei = sezi + fmult(self.a[1] >> 2, self.sr[1]) + fmult(self.a[0] >> 2, self.sr[0]);
+        let y = if self.ap >= 256 {
+            self.yu
+        } else {
+            let y = self.yl >> 6;
+            let dif = self.yu - y;
+            let al = self.ap >> 2;
+            if dif > 0 {
+                y + ((dif * al) >> 6)
+            } else if dif < 0 {
+                y + ((dif * al + 0x3F) >> 6)
+            } else {
+                y
+            }
+        };
+        Prediction { se: sei >> 1, sez: sezi >> 1, y }
+    }
+
+    fn quantize(rate: &Rate, d: i32, y: i32) -> u8 {
+        let dqm = d.abs();
+        let exp = quan(dqm >> 1, &POWER2);
+        let mant = ((dqm << 7) >> exp) & 0x7F;
+        let dln = (exp << 7) + mant - (y >> 2);
+        let i = quan(dln, rate.quantizer);
+        let size = rate.quantizer.len() as i32;
+        let code = if d < 0 {
+            (size << 1) + 1 - i
+        } else if i == 0 && rate.zero_code {
+            (size << 1) + 1
+        } else {
+            i
+        };
+        code as u8
+    }
+
+    /// The quantized difference for `code`, as sign and magnitude
+    fn reconstruct(rate: &Rate, code: u8, y: i32) -> i32 {
+        let negative = code as usize >= rate.dqln.len() / 2;
+        let dql = rate.dqln[code as usize] + (y >> 2);
+        if dql < 0 {
+            return if negative { -0x8000 } else { 0 };
+        }
+        let dex = (dql >> 7) & 15;
+        let dqt = 128 + (dql & 127);
+        let dq = (dqt << 7) >> (14 - dex);
+        if negative {
+            dq - 0x8000
+        } else {
+            dq
+        }
+    }
+
+    /// Moves the state on past a sample coded as `code`, and returns the sample as the decoder
+    /// reconstructs it, at 14 bits
+    fn step(&mut self, rate: &Rate, p: &Prediction, code: u8) -> i32 {
+        let dq = Self::reconstruct(rate, code, p.y);
+        let sr = if dq < 0 { p.se - (dq & 0x3FFF) } else { p.se + dq };
+        let dqsez = sr + p.sez - p.se;
+        self.update(rate.wi[code as usize], rate.fi[code as usize], p.y, dq, sr, dqsez);
+        sr
+    }
+
+    fn update(&mut self, wi: i32, fi: i32, y: i32, dq: i32, sr: i32, dqsez: i32) {
+        let pk0 = (dqsez < 0) as i32;
+        let mag = dq & 0x7FFF;
+
+        // a transition out of a tone resets the predictor
+        let ylint = self.yl >> 15;
+        let ylfrac = (self.yl >> 10) & 0x1F;
+        let thr2 = if ylint > 9 { 31 << 10 } else { (32 + ylfrac) << ylint };
+        let dqthr = (thr2 + (thr2 >> 1)) >> 1;
+        let tr = self.td && mag > dqthr;
+
+        self.yu = (y + ((wi - y) >> 5)).clamp(544, 5120);
+        self.yl += self.yu + ((-self.yl) >> 6);
+
+        let mut a2p = 0;
+        if tr {
+            self.a = [0; 2];
+            self.b = [0; 6];
+        } else {
+            let pks1 = pk0 ^ self.pk[0];
+            a2p = self.a[1] - (self.a[1] >> 7);
+            if dqsez != 0 {
+                let fa1 = if pks1 != 0 { self.a[0] } else { -self.a[0] };
+                if fa1 < -8191 {
+                    a2p -= 0x100;
+                } else if fa1 > 8191 {
+                    a2p += 0xFF;
+                } else {
+                    a2p += fa1 >> 5;
+                }
+                if pk0 ^ self.pk[1] != 0 {
+                    if a2p <= -12160 {
+                        a2p = -12288;
+                    } else if a2p >= 12416 {
+                        a2p = 12288;
+                    } else {
+                        a2p -= 0x80;
+                    }
+                } else if a2p <= -12416 {
+                    a2p = -12288;
+                } else if a2p >= 12160 {
+                    a2p = 12288;
+                } else {
+                    a2p += 0x80;
+                }
+            }
+            self.a[1] = a2p;
+
+            self.a[0] -= self.a[0] >> 8;
+            if dqsez != 0 {
+                if pks1 == 0 {
+                    self.a[0] += 192;
+                } else {
+                    self.a[0] -= 192;
+                }
+            }
+            let a1ul = 15360 - a2p;
+            self.a[0] = self.a[0].clamp(-a1ul, a1ul);
+
+            for (b, &dqn) in self.b.iter_mut().zip(self.dq.iter()) {
+                *b -= *b >> 8;
+                if mag != 0 {
+                    if (dq ^ dqn) >= 0 {
+                        *b += 128;
+                    } else {
+                        *b -= 128;
+                    }
+                }
+            }
+        }
+
+        self.dq.copy_within(0..5, 1);
+        self.dq[0] = if dq >= 0 { to_float(mag) } else { to_float(mag) - 0x400 };
+        self.sr[1] = self.sr[0];
+        self.sr[0] = if sr >= 0 {
+            to_float(sr)
+        } else if sr > -32768 {
+            to_float(-sr) - 0x400
+        } else {
+            0x20 - 0x400
+        };
+        self.pk[1] = self.pk[0];
+        self.pk[0] = pk0;
+
+        self.td = !tr && a2p < -11776;
+
+        self.dms += (fi - self.dms) >> 5;
+        self.dml += ((fi << 2) - self.dml) >> 7;
+        if tr {
+            self.ap = 256;
+        } else if y < 1536 || self.td || ((self.dms << 2) - self.dml).abs() >= (self.dml >> 3) {
+            self.ap += (0x200 - self.ap) >> 4;
+        } else {
+            self.ap += (-self.ap) >> 4;
+        }
+    }
+
+    /// The code for `sample`. The state moves on as the decoder's will.
+    pub fn encode(&mut self, rate: &Rate, sample: i16) -> u8 {
+        let p = self.predict();
+        // the reference works on 14-bit samples
+        let d = (sample as i32 >> 2) - p.se;
+        let code = Self::quantize(rate, d, p.y);
+        self.step(rate, &p, code);
+        code
+    }
+
+    pub fn decode(&mut self, rate: &Rate, code: u8) -> i16 {
+        let p = self.predict();
+        let code = code & ((1 << rate.bits) - 1) as u8;
+        let sr = self.step(rate, &p, code);
+        (sr << 2).clamp(i16::MIN as i32, i16::MAX as i32) as i16
+    }
+}
+
+/// Encodes `samples`, interleaved, appending a block for every `CODES_PER_BLOCK` of them.
+/// `samples` has to be a whole number of blocks.
+pub fn encode_blocks(rate: &Rate, samples: &[i16], channels: usize, state: &mut [ChannelState], out: &mut Vec<u8>) {
+    for block in samples.chunks(CODES_PER_BLOCK) {
+        let mut packed = 0u32;
+        for (i, &s) in block.iter().enumerate() {
+            packed |= (state[i % channels].encode(rate, s) as u32) << (i * rate.bits);
+        }
+        out.extend_from_slice(&packed.to_le_bytes()[..rate.bits]);
+    }
+}
+
+/// Decodes whole blocks from `bytes`, appending the samples to `out`, interleaved. Anything
+/// after the last whole block is left alone.
+pub fn decode_blocks(rate: &Rate, bytes: &[u8], channels: usize, state: &mut [ChannelState], out: &mut Vec<i16>) {
+    let mask = (1u32 << rate.bits) - 1;
+    for block in bytes.chunks_exact(rate.bits) {
+        let mut word = [0u8; 4];
+        word[..rate.bits].copy_from_slice(block);
+        let packed = u32::from_le_bytes(word);
+        for i in 0..CODES_PER_BLOCK {
+            let code = ((packed >> (i * rate.bits)) & mask) as u8;
+            out.push(state[i % channels].decode(rate, code));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voice(len: usize) -> Vec<i16> {
+        // a vowel-ish mix of harmonics, with a slow swell
+        (0..len).map(|i| {
+            let t = i as f64 / 8000.0;
+            let swell = 0.5 + 0.5 * (2.0 * std::f64::consts::PI * 3.0 * t).sin();
+            let s: f64 = [(150.0, 1.0), (300.0, 0.6), (750.0, 0.4), (1200.0, 0.2)].iter()
+                .map(|&(f, a)| a * (2.0 * std::f64::consts::PI * f * t).sin())
+                .sum();
+            (6000.0 * swell * s) as i16
+        }).collect()
+    }
+
+    fn snr(input: &[i16], output: &[i16]) -> f64 {
+        let (mut signal, mut noise) = (0f64, 0f64);
+        // give the predictor a moment to adapt
+        for (&a, &b) in input.iter().zip(output.iter()).skip(400) {
+            signal += (a as f64).powi(2);
+            noise += (a as f64 - b as f64).powi(2);
+        }
+        10.0 * (signal / noise).log10()
+    }
+
+    /// G.711 mu-law, expanded to 16 bits as the reference does before it takes the top 14
+    fn ulaw(byte: u8) -> i16 {
+        let u = !byte;
+        let t = ((((u & 0x0F) as i32) << 3) + 0x84) << ((u >> 4) & 7);
+        (if u & 0x80 != 0 { 0x84 - t } else { t - 0x84 }) as i16
+    }
+
+    /// G.711 A-law, expanded to 16 bits
+    fn alaw(byte: u8) -> i16 {
+        let a = byte ^ 0x55;
+        let mut t = ((a & 0x0F) as i32) << 4;
+        match (a >> 4) & 7 {
+            0 => t += 8,
+            1 => t += 0x108,
+            seg => t = (t + 0x108) << (seg - 1),
+        }
+        (if a & 0x80 != 0 { t } else { -t }) as i16
+    }
+
+    /// A test sequence from `src/g726-vectors/`, which holds one sample or code a byte
+    fn sequence(name: &str) -> Vec<u8> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/g726-vectors").join(name);
+        std::fs::read(&path).unwrap_or_else(|_| panic!("{} not found; it's one of the ITU-T G.726 test sequences", path.display()))
+    }
+
+    #[test]
+    fn g711_expansion() {
+        assert_eq!((ulaw(0xFF), ulaw(0x7F), ulaw(0x80), ulaw(0x00)), (0, 0, 32124, -32124));
+        assert_eq!((alaw(0xD5), alaw(0x55), alaw(0xAA), alaw(0x2A)), (8, -8, 32256, -32256));
+    }
+
+    /// The encoder half of the ITU-T G.726 test sequences at 16 and 24kbit/s: the normal (`nrm`)
+    /// and overload (`ovr`) inputs, in both laws, have to code to the reference's codes exactly,
+    /// starting from reset.
+    #[test]
+    fn itu_sequences() {
+        for &(rate, kbits) in [(&RATE_16K, 16), (&RATE_24K, 24)].iter() {
+            for &(input, tag) in [("nrm", 'n'), ("ovr", 'v')].iter() {
+                for &(law, expand) in [('a', alaw as fn(u8) -> i16), ('m', ulaw)].iter() {
+                    let name = format!("r{}{}f{}.i", tag, kbits, law);
+                    let samples = sequence(&format!("{}.{}", input, law));
+                    let codes = sequence(&name);
+                    assert_eq!(samples.len(), codes.len(), "{}", name);
+                    let mut state = ChannelState::default();
+                    for (i, (&sample, &code)) in samples.iter().zip(codes.iter()).enumerate() {
+                        assert_eq!(state.encode(rate, expand(sample)), code, "{}, sample {}", name, i);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn silence_from_rest() {
+        // nothing to code: the smallest positive code, or the zero code where there is one
+        assert_eq!(ChannelState::default().encode(&RATE_16K, 0), 0);
+        assert_eq!(ChannelState::default().encode(&RATE_24K, 0), 7);
+        let mut d = ChannelState::default();
+        assert_eq!(d.decode(&RATE_24K, 7), 0);
+    }
+
+    #[test]
+    fn decoder_follows_encoder() {
+        for rate in [&RATE_16K, &RATE_24K].iter() {
+            let mut enc = ChannelState::default();
+            let mut dec = ChannelState::default();
+            for &s in voice(2000).iter() {
+                let code = enc.encode(rate, s);
+                dec.decode(rate, code);
+                // the encoder runs the decoder's half of the arithmetic, so they stay in step
+                assert_eq!(enc, dec);
+            }
+        }
+    }
+
+    #[test]
+    fn speech_survives() {
+        let input = voice(8000);
+        for &(rate, least) in [(&RATE_16K, 15.0), (&RATE_24K, 24.0)].iter() {
+            let mut state = [ChannelState::default(); 2];
+            let mut encoded = Vec::new();
+            encode_blocks(rate, &input, 1, &mut state, &mut encoded);
+            assert_eq!(encoded.len(), input.len() * rate.bits / 8);
+            let mut state = [ChannelState::default(); 2];
+            let mut decoded = Vec::new();
+            decode_blocks(rate, &encoded, 1, &mut state, &mut decoded);
+            assert_eq!(decoded.len(), input.len());
+            let snr = snr(&input, &decoded);
+            assert!(snr > least, "{} bits: SNR {}dB", rate.bits, snr);
+        }
+    }
+
+    #[test]
+    fn packing() {
+        // each code in its own bits, lowest first
+        let mut state = [ChannelState::default(); 2];
+        let mut out = Vec::new();
+        encode_blocks(&RATE_24K, &[0; 8], 1, &mut state, &mut out);
+        assert_eq!(out, [0xFF, 0xFF, 0xFF]);
+        // stereo channels keep their own state
+        let mut stereo = Vec::new();
+        for &s in voice(800).iter() {
+            stereo.push(s);
+            stereo.push(-s);
+        }
+        let mut state = [ChannelState::default(); 2];
+        let mut encoded = Vec::new();
+        encode_blocks(&RATE_16K, &stereo, 2, &mut state, &mut encoded);
+        let mut state = [ChannelState::default(); 2];
+        let mut decoded = Vec::new();
+        decode_blocks(&RATE_16K, &encoded, 2, &mut state, &mut decoded);
+        let left: Vec<i16> = decoded.iter().step_by(2).copied().collect();
+        let right: Vec<i16> = decoded.iter().skip(1).step_by(2).map(|&s| -s).collect();
+        assert!(snr(&voice(800), &left) > 6.0);
+        assert!(snr(&voice(800), &right) > 6.0);
+    }
+}
@@ -0,0 +1,224 @@
+//! IMA ADPCM, in the block layout WAV files use (format tag 0x11), so clips can be exported to
+//! anything that plays WAV.
+//!
+//! Each block starts with a 4-byte header per channel: the first sample as-is, and the step
+//! index to carry on from. The rest of the samples follow as 4-bit codes, low nibble first.
+//! Stereo interleaves the channels four bytes (eight samples) at a time.
+
+const INDEX_TABLE: [i8; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+
+const STEP_TABLE: [u16; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408,
+    449, 494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066,
+    2272, 2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630,
+    9493, 10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794,
+    32767,
+];
+
+/// The predictor for one channel
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelState {
+    predictor: i32,
+    index: i32,
+}
+
+impl ChannelState {
+    fn step(&self) -> i32 {
+        STEP_TABLE[self.index as usize] as i32
+    }
+    fn apply(&mut self, code: u8) -> i16 {
+        let step = self.step();
+        let mut diff = step >> 3;
+        if code & 4 != 0 {
+            diff += step;
+        }
+        if code & 2 != 0 {
+            diff += step >> 1;
+        }
+        if code & 1 != 0 {
+            diff += step >> 2;
+        }
+        if code & 8 != 0 {
+            self.predictor -= diff;
+        } else {
+            self.predictor += diff;
+        }
+        self.predictor = self.predictor.max(i16::MIN as i32).min(i16::MAX as i32);
+        self.index = (self.index + INDEX_TABLE[code as usize & 0xF] as i32).max(0).min(88);
+        self.predictor as i16
+    }
+    /// The 4-bit code that gets closest to `sample`. The state moves on as the decoder's will.
+    pub fn encode(&mut self, sample: i16) -> u8 {
+        let step = self.step();
+        let mut delta = sample as i32 - self.predictor;
+        let mut code = 0;
+        if delta < 0 {
+            code = 8;
+            delta = -delta;
+        }
+        if delta >= step {
+            code |= 4;
+            delta -= step;
+        }
+        if delta >= step >> 1 {
+            code |= 2;
+            delta -= step >> 1;
+        }
+        if delta >= step >> 2 {
+            code |= 1;
+        }
+        self.apply(code);
+        code
+    }
+    pub fn decode(&mut self, code: u8) -> i16 {
+        self.apply(code)
+    }
+}
+
+/// The block size for `channels` at `rate`: about 32ms of audio, as encoders commonly pick
+pub fn block_align(rate: u32, channels: usize) -> usize {
+    let per_channel = if rate <= 11025 {
+        256
+    } else if rate <= 22050 {
+        512
+    } else {
+        1024
+    };
+    per_channel * channels
+}
+
+/// How many samples of each channel fit in a block
+pub fn samples_per_block(block_align: usize, channels: usize) -> usize {
+    (block_align / channels - 4) * 2 + 1
+}
+
+/// Encodes one block from `samples`, interleaved, which holds exactly `samples_per_block()`
+/// samples for each channel.
+pub fn encode_block(samples: &[i16], channels: usize, state: &mut [ChannelState], out: &mut Vec<u8>) {
+    let per_block = samples.len() / channels;
+    for ch in 0..channels {
+        let first = samples[ch];
+        state[ch].predictor = first as i32;
+        out.extend_from_slice(&first.to_le_bytes());
+        out.push(state[ch].index as u8);
+        out.push(0);
+    }
+    // after the header, each channel's codes go out eight at a time
+    for group in (1..per_block).step_by(8) {
+        for ch in 0..channels {
+            let mut bytes = [0u8; 4];
+            for i in 0..8 {
+                let code = match samples.get((group + i) * channels + ch) {
+                    Some(&s) if group + i < per_block => state[ch].encode(s),
+                    _ => 0,
+                };
+                bytes[i / 2] |= code << ((i & 1) * 4);
+            }
+            out.extend_from_slice(&bytes);
+        }
+    }
+}
+
+/// Decodes one block, appending its samples to `out`, interleaved. Returns `None` if the block
+/// is too short to hold a header.
+pub fn decode_block(block: &[u8], channels: usize, out: &mut Vec<i16>) -> Option<()> {
+    if block.len() < 4 * channels {
+        return None;
+    }
+    let mut state = [ChannelState::default(); 2];
+    let start = out.len();
+    for ch in 0..channels {
+        let h = &block[ch * 4..ch * 4 + 4];
+        state[ch].predictor = i16::from_le_bytes([h[0], h[1]]) as i32;
+        state[ch].index = (h[2] as i32).min(88);
+        out.push(state[ch].predictor as i16);
+    }
+    let body = &block[4 * channels..];
+    let groups = body.len() / (4 * channels);
+    out.resize(start + (1 + groups * 8) * channels, 0);
+    for group in 0..groups {
+        for ch in 0..channels {
+            let bytes = &body[(group * channels + ch) * 4..(group * channels + ch) * 4 + 4];
+            for i in 0..8 {
+                let code = (bytes[i / 2] >> ((i & 1) * 4)) & 0xF;
+                out[start + (1 + group * 8 + i) * channels + ch] = state[ch].decode(code);
+            }
+        }
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(len: usize, channels: usize) -> Vec<i16> {
+        (0..len * channels).map(|i| {
+            let t = (i / channels) as f64 / 8000.0;
+            let f = if i % channels == 0 { 440.0 } else { 1000.0 };
+            (8000.0 * (2.0 * std::f64::consts::PI * f * t).sin()) as i16
+        }).collect()
+    }
+
+    #[test]
+    fn block_layout() {
+        assert_eq!(block_align(8000, 1), 256);
+        assert_eq!(samples_per_block(256, 1), 505);
+        assert_eq!(samples_per_block(512, 2), 505);
+        for &channels in [1usize, 2].iter() {
+            let align = block_align(8000, channels);
+            let per_block = samples_per_block(align, channels);
+            let mut out = Vec::new();
+            let mut state = [ChannelState::default(); 2];
+            encode_block(&tone(per_block, channels), channels, &mut state, &mut out);
+            assert_eq!(out.len(), align);
+            let mut decoded = Vec::new();
+            decode_block(&out, channels, &mut decoded).unwrap();
+            assert_eq!(decoded.len(), per_block * channels);
+        }
+        assert!(decode_block(&[0, 0, 0], 1, &mut Vec::new()).is_none());
+    }
+
+    #[test]
+    fn round_trip_tracks_the_signal() {
+        for &channels in [1usize, 2].iter() {
+            let align = block_align(8000, channels);
+            let per_block = samples_per_block(align, channels);
+            let input = tone(per_block * 4, channels);
+            let mut state = [ChannelState::default(); 2];
+            let mut encoded = Vec::new();
+            for block in input.chunks(per_block * channels) {
+                encode_block(block, channels, &mut state, &mut encoded);
+            }
+            let mut decoded = Vec::new();
+            for block in encoded.chunks(align) {
+                decode_block(block, channels, &mut decoded).unwrap();
+            }
+            assert_eq!(decoded.len(), input.len());
+            // the first sample of each block is exact
+            assert_eq!(decoded[0], input[0]);
+            assert_eq!(decoded[per_block * channels], input[per_block * channels]);
+            // and the rest is within a few percent, once the step size has adapted
+            let (mut signal, mut noise) = (0f64, 0f64);
+            for (&a, &b) in input.iter().zip(decoded.iter()).skip(64 * channels) {
+                signal += (a as f64).powi(2);
+                noise += (a as f64 - b as f64).powi(2);
+            }
+            let snr = 10.0 * (signal / noise).log10();
+            assert!(snr > 20.0, "{} channels: SNR {}dB", channels, snr);
+        }
+    }
+
+    #[test]
+    fn known_codes() {
+        let mut s = ChannelState::default();
+        // from rest, a big positive jump saturates the code and grows the step
+        assert_eq!(s.encode(1000), 7);
+        assert_eq!(s, ChannelState { predictor: 11, index: 8 });
+        assert_eq!(s.encode(-1000), 15);
+        let mut d = ChannelState::default();
+        assert_eq!(d.decode(7), 11);
+        assert_eq!(d.decode(15), 11 - 30);
+    }
+}
@@ -0,0 +1,347 @@
+//! Compresses audio for storage and transport, a `FrameRing` at a time.
+//!
+//! Raw PCM from the CODEC takes 16kB a second at 8kHz mono. IMA ADPCM gets that down to a
+//! quarter, and copes with any kind of sound. For voice memos and calls, G.726 gets it down to
+//! an eighth (2kB a second) or three sixteenths, and stays intelligible. `Encoder` and
+//! `Decoder` work on streams in any of these; `clip` stores a stream along with its format,
+//! e.g. in a PDDB key.
+//!
+//! This runs in the caller's process: it is pure computation, and cheap enough that a round
+//! trip through a server would cost more than the coding does.
+
+pub mod ima_adpcm;
+pub mod g726;
+pub mod clip;
+
+use std::collections::VecDeque;
+
+use codec::{FrameRing, StreamFormat, FIFO_DEPTH};
+use ima_adpcm::ChannelState;
+use g726::Rate;
+
+/// 16-bit samples in a `FrameRing` frame, whatever the channel count
+const SAMPLES_PER_FRAME: usize = FIFO_DEPTH * 2;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Encoding {
+    /// 16-bit little-endian PCM, as is
+    Pcm16,
+    /// IMA ADPCM, 4 bits a sample, in WAV-style blocks
+    ImaAdpcm,
+    /// G.726 ADPCM, 2 bits a sample: 16kbit/s at 8kHz, for speech
+    G726_16,
+    /// G.726 ADPCM, 3 bits a sample: 24kbit/s at 8kHz, for speech
+    G726_24,
+}
+impl Encoding {
+    /// The identifier stored in a clip header
+    pub fn id(&self) -> u8 {
+        match self {
+            Encoding::Pcm16 => 0,
+            Encoding::ImaAdpcm => 1,
+            Encoding::G726_16 => 2,
+            Encoding::G726_24 => 3,
+        }
+    }
+    pub fn from_id(id: u8) -> Option<Encoding> {
+        match id {
+            0 => Some(Encoding::Pcm16),
+            1 => Some(Encoding::ImaAdpcm),
+            2 => Some(Encoding::G726_16),
+            3 => Some(Encoding::G726_24),
+            _ => None,
+        }
+    }
+    /// The number of bytes coded together, and that a stream has to be cut on, for `format`
+    pub fn block_align(&self, format: &StreamFormat) -> usize {
+        let channels = format.channels.count();
+        match self {
+            Encoding::Pcm16 => 2 * channels,
+            Encoding::ImaAdpcm => ima_adpcm::block_align(format.rate, channels),
+            Encoding::G726_16 | Encoding::G726_24 => self.g726().unwrap().bits,
+        }
+    }
+    /// Sample frames (one sample for each channel) in a block
+    pub fn samples_per_block(&self, block_align: usize, channels: usize) -> usize {
+        match self {
+            Encoding::Pcm16 => block_align / (2 * channels),
+            Encoding::ImaAdpcm => ima_adpcm::samples_per_block(block_align, channels),
+            Encoding::G726_16 | Encoding::G726_24 => block_align * 8 / self.g726().unwrap().bits / channels,
+        }
+    }
+    /// The code tables, for the G.726 encodings
+    pub fn g726(&self) -> Option<&'static Rate> {
+        match self {
+            Encoding::G726_16 => Some(&g726::RATE_16K),
+            Encoding::G726_24 => Some(&g726::RATE_24K),
+            _ => None,
+        }
+    }
+}
+
+/// Encodes a stream. Output comes out a block at a time, so bytes can be held back until
+/// `finish()` if the input doesn't end on a block.
+pub struct Encoder {
+    encoding: Encoding,
+    format: StreamFormat,
+    block_align: usize,
+    /// interleaved samples that don't yet make up a block
+    pending: Vec<i16>,
+    adpcm: [ChannelState; 2],
+    g726: [g726::ChannelState; 2],
+}
+
+impl Encoder {
+    pub fn new(encoding: Encoding, format: StreamFormat) -> Encoder {
+        Encoder {
+            encoding,
+            format,
+            block_align: encoding.block_align(&format),
+            pending: Vec::new(),
+            adpcm: [ChannelState::default(); 2],
+            g726: [g726::ChannelState::default(); 2],
+        }
+    }
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+    pub fn format(&self) -> StreamFormat {
+        self.format
+    }
+    pub fn block_align(&self) -> usize {
+        self.block_align
+    }
+    fn block_samples(&self) -> usize {
+        let channels = self.format.channels.count();
+        self.encoding.samples_per_block(self.block_align, channels) * channels
+    }
+    fn encode_block(&mut self, samples: &[i16], out: &mut Vec<u8>) {
+        match self.encoding {
+            Encoding::Pcm16 => {
+                for s in samples.iter() {
+                    out.extend_from_slice(&s.to_le_bytes());
+                }
+            }
+            Encoding::ImaAdpcm => ima_adpcm::encode_block(samples, self.format.channels.count(), &mut self.adpcm, out),
+            Encoding::G726_16 | Encoding::G726_24 => {
+                g726::encode_blocks(self.encoding.g726().unwrap(), samples, self.format.channels.count(), &mut self.g726, out)
+            }
+        }
+    }
+    /// Encodes `samples`, interleaved if stereo, appending whole blocks to `out`
+    pub fn encode(&mut self, samples: &[i16], out: &mut Vec<u8>) {
+        let block = self.block_samples();
+        let mut samples = samples;
+        if !self.pending.is_empty() {
+            let take = (block - self.pending.len()).min(samples.len());
+            self.pending.extend_from_slice(&samples[..take]);
+            samples = &samples[take..];
+            if self.pending.len() < block {
+                return;
+            }
+            let pending = std::mem::take(&mut self.pending);
+            self.encode_block(&pending, out);
+        }
+        let whole = samples.len() - samples.len() % block;
+        for chunk in samples[..whole].chunks(block) {
+            self.encode_block(chunk, out);
+        }
+        self.pending.extend_from_slice(&samples[whole..]);
+    }
+    /// Encodes every frame queued in `frames`, leaving it empty. Returns the number of frames.
+    pub fn encode_frames(&mut self, frames: &mut FrameRing, out: &mut Vec<u8>) -> usize {
+        let mut samples = Vec::with_capacity(SAMPLES_PER_FRAME);
+        let mut count = 0;
+        while let Some(frame) = frames.dq_frame() {
+            samples.clear();
+            self.format.unpack(&frame, &mut samples);
+            self.encode(&samples, out);
+            count += 1;
+        }
+        count
+    }
+    /// Encodes whatever is left over as a last, short block. ADPCM pads it out to a whole
+    /// block; a clip's sample count says where the audio really ends.
+    pub fn finish(&mut self, out: &mut Vec<u8>) {
+        if !self.pending.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            if self.encoding != Encoding::Pcm16 {
+                let mut padded = pending.clone();
+                let channels = self.format.channels.count();
+                let last = pending[pending.len() - channels..].to_vec();
+                while padded.len() < self.block_samples() {
+                    padded.extend_from_slice(&last);
+                }
+                self.encode_block(&padded, out);
+            } else {
+                self.encode_block(&pending, out);
+            }
+        }
+    }
+}
+
+/// Decodes a stream. Bytes can be fed in however they arrive; samples come out once there is
+/// a whole block of them.
+pub struct Decoder {
+    encoding: Encoding,
+    format: StreamFormat,
+    block_align: usize,
+    /// bytes that don't yet make up a block
+    input: Vec<u8>,
+    /// decoded samples, interleaved
+    decoded: VecDeque<i16>,
+    g726: [g726::ChannelState; 2],
+}
+
+impl Decoder {
+    pub fn new(encoding: Encoding, format: StreamFormat) -> Decoder {
+        Decoder::with_block_align(encoding, format, encoding.block_align(&format))
+    }
+    /// For streams that were encoded with a block size other than the default, e.g. by another
+    /// encoder
+    pub fn with_block_align(encoding: Encoding, format: StreamFormat, block_align: usize) -> Decoder {
+        Decoder {
+            encoding,
+            format,
+            block_align,
+            input: Vec::new(),
+            decoded: VecDeque::new(),
+            g726: [g726::ChannelState::default(); 2],
+        }
+    }
+    pub fn format(&self) -> StreamFormat {
+        self.format
+    }
+    fn decode_block(&mut self, block: &[u8]) {
+        match self.encoding {
+            Encoding::Pcm16 => self.decoded.extend(block.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]]))),
+            Encoding::ImaAdpcm => {
+                let mut samples = Vec::with_capacity(block.len() * 2);
+                if ima_adpcm::decode_block(block, self.format.channels.count(), &mut samples).is_none() {
+                    log::warn!("dropping a {}-byte ADPCM block, too short for a header", block.len());
+                }
+                self.decoded.extend(samples);
+            }
+            Encoding::G726_16 | Encoding::G726_24 => {
+                let mut samples = Vec::with_capacity(block.len() * 4);
+                g726::decode_blocks(self.encoding.g726().unwrap(), block, self.format.channels.count(), &mut self.g726, &mut samples);
+                self.decoded.extend(samples);
+            }
+        }
+    }
+    /// Decodes as much of `bytes` as makes up whole blocks, keeping the rest for next time
+    pub fn decode(&mut self, bytes: &[u8]) {
+        self.input.extend_from_slice(bytes);
+        let whole = self.input.len() - self.input.len() % self.block_align;
+        let input = std::mem::take(&mut self.input);
+        for block in input[..whole].chunks(self.block_align) {
+            self.decode_block(block);
+        }
+        self.input.extend_from_slice(&input[whole..]);
+    }
+    /// Decodes a last, short block, once the stream has ended
+    pub fn finish(&mut self) {
+        if !self.input.is_empty() {
+            let input = std::mem::take(&mut self.input);
+            self.decode_block(&input);
+        }
+    }
+    /// Decoded samples waiting to be taken, counting each channel's separately
+    pub fn available(&self) -> usize {
+        self.decoded.len()
+    }
+    /// Moves up to `max` decoded samples to `out`. Returns how many were moved.
+    pub fn take_samples(&mut self, max: usize, out: &mut Vec<i16>) -> usize {
+        let n = max.min(self.decoded.len());
+        out.extend(self.decoded.drain(..n));
+        n
+    }
+    /// Forgets anything decoded but not taken, e.g. to skip ahead
+    pub fn clear(&mut self) {
+        self.input.clear();
+        self.decoded.clear();
+    }
+    /// Fills `frames` with as many whole frames as have been decoded and fit. With `pad`, what's
+    /// left after that is padded out with silence into one more frame, for the end of a stream.
+    /// Returns the number of frames filled.
+    pub fn decode_frames(&mut self, frames: &mut FrameRing, pad: bool) -> usize {
+        let mut count = 0;
+        let mut samples = Vec::with_capacity(SAMPLES_PER_FRAME);
+        let mut words = Vec::with_capacity(FIFO_DEPTH);
+        while !frames.is_full() && (self.decoded.len() >= SAMPLES_PER_FRAME || (pad && !self.decoded.is_empty())) {
+            samples.clear();
+            self.take_samples(SAMPLES_PER_FRAME, &mut samples);
+            samples.resize(SAMPLES_PER_FRAME, 0);
+            words.clear();
+            self.format.pack(&samples, &mut words);
+            let mut frame = [0u32; FIFO_DEPTH];
+            frame.copy_from_slice(&words);
+            frames.nq_frame(frame).unwrap(); // we checked it isn't full
+            count += 1;
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec::{Channels, SampleFormat};
+
+    #[test]
+    fn frames_round_trip() {
+        for &encoding in [Encoding::Pcm16, Encoding::ImaAdpcm, Encoding::G726_16, Encoding::G726_24].iter() {
+            for &channels in [Channels::Mono, Channels::Stereo].iter() {
+                let format = StreamFormat::new(8000, channels, SampleFormat::I16);
+                let mut ring = FrameRing::with_format(format);
+                let mut input = Vec::new();
+                for f in 0..5u32 {
+                    let frame: [u32; FIFO_DEPTH] = [(f * 1000) | (f * 1000 + 500) << 16; FIFO_DEPTH];
+                    format.unpack(&frame, &mut input);
+                    ring.nq_frame(frame).unwrap();
+                }
+                let mut encoder = Encoder::new(encoding, format);
+                let mut encoded = Vec::new();
+                assert_eq!(encoder.encode_frames(&mut ring, &mut encoded), 5);
+                assert!(ring.is_empty());
+                encoder.finish(&mut encoded);
+
+                let mut decoder = Decoder::new(encoding, format);
+                // fed in awkward pieces
+                for chunk in encoded.chunks(77) {
+                    decoder.decode(chunk);
+                }
+                decoder.finish();
+                assert!(decoder.available() >= input.len());
+                assert_eq!(decoder.decode_frames(&mut ring, false), 5);
+                let mut output = Vec::new();
+                while let Some(frame) = ring.dq_frame() {
+                    format.unpack(&frame, &mut output);
+                }
+                let worst = input.iter().zip(output.iter()).map(|(&a, &b)| (a as i32 - b as i32).abs()).max().unwrap();
+                let allowed = match encoding {
+                    Encoding::Pcm16 => 0,
+                    // ADPCM takes a little while to step up to a jump
+                    Encoding::ImaAdpcm => 1000,
+                    // and G.726, tuned for speech rather than steps, longer still
+                    Encoding::G726_16 | Encoding::G726_24 => 3000,
+                };
+                assert!(worst <= allowed, "{:?} {:?}: off by {}", encoding, channels, worst);
+            }
+        }
+    }
+
+    #[test]
+    fn compression() {
+        let format = StreamFormat::new(8000, Channels::Mono, SampleFormat::I16);
+        let samples = vec![0i16; 8000];
+        for &(encoding, bytes) in [(Encoding::Pcm16, 16000), (Encoding::ImaAdpcm, 4096), (Encoding::G726_24, 3000), (Encoding::G726_16, 2000)].iter() {
+            let mut encoder = Encoder::new(encoding, format);
+            let mut out = Vec::new();
+            encoder.encode(&samples, &mut out);
+            encoder.finish(&mut out);
+            // ADPCM comes out in whole 256-byte blocks
+            assert_eq!(out.len(), bytes, "{:?}", encoding);
+        }
+    }
+}
@@ -24,6 +24,12 @@ pub(crate) enum Opcode {
     /// Powers on the CODEC, sets up 8k stereo streaming; puts audio in "paused" state
     Setup8kStereo,
 
+    /// Powers on the CODEC and sets up streaming in the negotiated format; puts audio in "paused" state
+    SetupStream,
+
+    /// the sample rates, channel counts and sample formats the CODEC can stream
+    GetCapabilities,
+
     /// Pause the stream without powering anything off. Will wait until the current playback frames in process are finished.
     PauseStream,
     /// Pause the stream without powering anything off. Clears the buffer immediately, losing any frames in playback.
@@ -74,6 +80,114 @@ pub(crate) struct ScalarHook {
 
 //////////////////////////////////////////////////////////////////////////////////////
 
+/// The sample rates in common use. The CODEC clocks some of them natively; see `Capabilities`.
+pub const STANDARD_RATES: [u32; 7] = [8000, 11025, 16000, 22050, 32000, 44100, 48000];
+
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone, PartialEq, Eq)]
+pub enum Channels {
+    Mono,
+    Stereo,
+}
+impl Channels {
+    pub fn count(&self) -> usize {
+        match self {
+            Channels::Mono => 1,
+            Channels::Stereo => 2,
+        }
+    }
+}
+
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// signed 16-bit PCM
+    I16,
+}
+
+/// The format of the samples in a `FrameRing`.
+///
+/// Frames are always `FIFO_DEPTH` u32 words. With stereo samples, each word is one left/right
+/// pair, as `|31 right 16|15 left 0|`. With mono samples, each word is two consecutive samples,
+/// the earlier one in the lower half.
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct StreamFormat {
+    /// in Hz
+    pub rate: u32,
+    pub channels: Channels,
+    pub format: SampleFormat,
+}
+impl StreamFormat {
+    pub fn new(rate: u32, channels: Channels, format: SampleFormat) -> StreamFormat {
+        StreamFormat { rate, channels, format }
+    }
+    /// The format the CODEC originally streamed in, and the one a `FrameRing` starts out with
+    pub fn default_8k_stereo() -> StreamFormat {
+        StreamFormat { rate: 8000, channels: Channels::Stereo, format: SampleFormat::I16 }
+    }
+    /// Number of sample frames (one sample for each channel) in a u32 word
+    pub fn frames_per_word(&self) -> usize {
+        match self.channels {
+            Channels::Mono => 2,
+            Channels::Stereo => 1,
+        }
+    }
+    /// Milliseconds of audio in one `FrameRing` frame
+    pub fn frame_ms(&self) -> u32 {
+        (FIFO_DEPTH * self.frames_per_word()) as u32 * 1000 / self.rate
+    }
+    /// Appends the samples packed in `words` to `samples`, interleaved if stereo
+    pub fn unpack(&self, words: &[u32], samples: &mut Vec<i16>) {
+        for &w in words.iter() {
+            samples.push((w & 0xFFFF) as u16 as i16);
+            samples.push((w >> 16) as u16 as i16);
+        }
+    }
+    /// Packs `samples` into words, as many as there are whole words for. Returns the number of
+    /// samples used.
+    pub fn pack(&self, samples: &[i16], words: &mut Vec<u32>) -> usize {
+        for pair in samples.chunks_exact(2) {
+            words.push((pair[0] as u16 as u32) | (pair[1] as u16 as u32) << 16);
+        }
+        samples.len() & !1
+    }
+}
+
+/// What the CODEC can stream. Rates that it can't clock itself are resampled in software, at
+/// some loss of quality.
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// bit n is set if `STANDARD_RATES[n]` is clocked natively by the hardware
+    pub native_rates: u32,
+    /// the range of rates that can be streamed, natively or resampled, in Hz
+    pub min_rate: u32,
+    pub max_rate: u32,
+    pub max_channels: u32,
+    /// bit n is set if `SampleFormat` with discriminant n is supported
+    pub formats: u32,
+}
+impl Capabilities {
+    pub fn is_native(&self, rate: u32) -> bool {
+        STANDARD_RATES.iter().enumerate().any(|(i, &r)| r == rate && self.native_rates & (1 << i) != 0)
+    }
+    pub fn native_rates(&self) -> impl Iterator<Item = u32> + '_ {
+        STANDARD_RATES.iter().enumerate().filter(move |(i, _)| self.native_rates & (1 << i) != 0).map(|(_, &r)| r)
+    }
+    pub fn supports(&self, format: &StreamFormat) -> bool {
+        format.rate >= self.min_rate && format.rate <= self.max_rate
+            && format.channels.count() <= self.max_channels as usize
+            && self.formats & (1 << format.format as u32) != 0
+    }
+}
+
+/// A request to set up a stream. `granted` is filled in with the closest format the CODEC can
+/// do, which the caller then has to supply its frames in.
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub struct StreamSetup {
+    pub requested: StreamFormat,
+    pub granted: Option<StreamFormat>,
+    /// the rate the hardware was clocked at to play the stream
+    pub hardware_rate: u32,
+}
+
 pub const ZERO_PCM: u16 = 0x0; // assumes 2's compliment. 0x8000 otherwise.
 pub const FIFO_DEPTH: usize = 256;
 /*
@@ -106,17 +220,28 @@ pub struct FrameRing {
     rec_ptr: usize,
     // authenication token authorizing playback
     auth_token: Option<[u32; 4]>,
+    // the format of the samples in the frames
+    format: StreamFormat,
 }
 impl FrameRing {
+    /// A ring of 8kHz stereo frames
     pub fn new() -> FrameRing {
+        FrameRing::with_format(StreamFormat::default_8k_stereo())
+    }
+    /// A ring of frames in the format granted by `Codec::setup_stream()`
+    pub fn with_format(format: StreamFormat) -> FrameRing {
         FrameRing {
             buffer: [[(ZERO_PCM as u32 | (ZERO_PCM as u32) << 16); FIFO_DEPTH]; FRAMES],
             rd_frame: 0,
             wr_frame: 0,
             rec_ptr: 0,
             auth_token: None,
+            format,
         }
     }
+    pub fn format(&self) -> StreamFormat {
+        self.format
+    }
     pub fn clear(&mut self) {
         self.buffer = [[(ZERO_PCM as u32 | (ZERO_PCM as u32) << 16); FIFO_DEPTH]; FRAMES];
         self.rd_frame = 0;
@@ -1,96 +1,419 @@
 #![cfg_attr(not(target_os = "none"), allow(dead_code))]
 
-use codec::FIFO_DEPTH;
+//! Hosted-mode stand-in for the CODEC. A thread plays the part of the audio interrupt: every
+//! frame period it takes a frame off the play queue and appends it to a WAV file, fills a record
+//! frame from another WAV file, and asks for more frames when the play queue runs low.
+//!
+//! It is set up with environment variables, which the hosted kernel passes on to the codec
+//! server. Relative paths are relative to the directory the kernel runs in.
+//!
+//! - `XOUS_AUDIO_OUT`: WAV file that played frames are written to, as 16-bit stereo at the
+//!   hardware rate. Only frames played while the stream is live are written, so pauses don't
+//!   show up as silence. If the rate changes, a new file is started with the rate in its name.
+//! - `XOUS_AUDIO_IN`: 16-bit PCM WAV file that recording reads from, converted to the hardware
+//!   rate. Recording reads silence once it runs out, or throughout if this isn't set.
+//! - `XOUS_AUDIO_PACE`: `realtime` (the default) paces frames like the hardware does. `fast`
+//!   plays each frame as soon as it is queued, so a test runs as fast as its client can keep up,
+//!   and the output doesn't depend on scheduling. If the client stops supplying frames, one
+//!   frame period of silence is played and recorded as usual.
+
+use codec::{FIFO_DEPTH, FrameRing, Capabilities, SampleFormat, STANDARD_RATES, ZERO_PCM};
+use num_traits::ToPrimitive;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::wav::{WavReader, WavWriter};
+use crate::api::Opcode;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Pacing {
+    RealTime,
+    FastForward,
+}
+
+struct State {
+    play_buffer: FrameRing,
+    rec_buffer: FrameRing,
+    live: bool,
+    drain: bool,
+    /// bumped every time the stream starts, so a frame thread left over from before knows to quit
+    generation: u32,
+    play_frames_dropped: u32,
+    rec_frames_dropped: u32,
+    sample_rate: u32,
+    output: Option<WavWriter<std::fs::File>>,
+    /// the rate `output` was started at
+    output_rate: u32,
+    input: Option<WavReader>,
+}
+
+impl State {
+    fn new() -> State {
+        State {
+            play_buffer: FrameRing::new(),
+            rec_buffer: FrameRing::new(),
+            live: false,
+            drain: false,
+            generation: 0,
+            play_frames_dropped: 0,
+            rec_frames_dropped: 0,
+            sample_rate: 8000,
+            output: None,
+            output_rate: 0,
+            input: None,
+        }
+    }
+}
 
 pub struct Codec {
+    conn: xous::CID,
+    state: Arc<(Mutex<State>, Condvar)>,
+    pacing: Pacing,
+    output_path: Option<String>,
+    input_path: Option<String>,
+    powered_on: bool,
+    initialized: bool,
 }
 
+static SILENCE: [u32; FIFO_DEPTH] = [ZERO_PCM as u32 | (ZERO_PCM as u32) << 16; FIFO_DEPTH];
+
 impl Codec {
-    pub fn new(_conn: xous::CID, _xns: &xous_names::XousNames) -> Codec {
+    pub fn new(conn: xous::CID, _xns: &xous_names::XousNames) -> Codec {
+        let pacing = match std::env::var("XOUS_AUDIO_PACE").as_deref() {
+            Ok("fast") => Pacing::FastForward,
+            Ok("realtime") | Err(_) => Pacing::RealTime,
+            Ok(other) => {
+                log::warn!("unknown XOUS_AUDIO_PACE {}, pacing in real time", other);
+                Pacing::RealTime
+            }
+        };
+        let output_path = std::env::var("XOUS_AUDIO_OUT").ok();
+        let input_path = std::env::var("XOUS_AUDIO_IN").ok();
+        log::info!("hosted audio: playing to {:?}, recording from {:?}, {:?} pacing", output_path, input_path, pacing);
         Codec {
+            conn,
+            state: Arc::new((Mutex::new(State::new()), Condvar::new())),
+            pacing,
+            output_path,
+            input_path,
+            powered_on: false,
+            initialized: false,
+        }
+    }
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            native_rates: (1 << STANDARD_RATES.len()) - 1,
+            min_rate: 4000,
+            max_rate: 48000,
+            max_channels: 2,
+            formats: 1 << SampleFormat::I16 as u32,
         }
     }
-    pub fn suspend(&self) {
+    /// Takes effect on the next `init()`
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.state.0.lock().unwrap().sample_rate = rate;
+    }
+    pub fn sample_rate(&self) -> u32 {
+        self.state.0.lock().unwrap().sample_rate
+    }
+
+    pub fn suspend(&mut self) {
     }
-    pub fn resume(&self) {
+    pub fn resume(&mut self) {
     }
     pub fn init(&mut self) {
+        let mut state = self.state.0.lock().unwrap();
+        let rate = state.sample_rate;
+        if let Some(path) = &self.output_path {
+            if state.output_rate != rate {
+                // a WAV file has just the one rate; any later one goes to a file of its own
+                let path = if state.output_rate == 0 {
+                    path.clone()
+                } else {
+                    match path.rfind('.') {
+                        Some(dot) => format!("{}-{}{}", &path[..dot], rate, &path[dot..]),
+                        None => format!("{}-{}", path, rate),
+                    }
+                };
+                match WavWriter::create(&path, rate) {
+                    Ok(w) => {
+                        state.output = Some(w);
+                        state.output_rate = rate;
+                    }
+                    Err(e) => log::error!("couldn't create audio output {}: {:?}", path, e),
+                }
+            }
+        }
+        if let Some(path) = &self.input_path {
+            match WavReader::open(path, rate) {
+                Ok(r) => state.input = Some(r),
+                Err(e) => log::error!("couldn't open audio input {}: {:?}", path, e),
+            }
+        }
+        self.initialized = true;
     }
 
-    pub fn nq_play_frame(&mut self, _frame: [u32; FIFO_DEPTH]) -> Result<(), [u32; FIFO_DEPTH]> {
-        Ok(())
+    pub fn nq_play_frame(&mut self, frame: [u32; FIFO_DEPTH]) -> Result<(), [u32; FIFO_DEPTH]> {
+        let (lock, cvar) = &*self.state;
+        let result = lock.lock().unwrap().play_buffer.nq_frame(frame);
+        cvar.notify_all();
+        result
     }
     pub fn dq_rec_frame(&mut self) -> Option<[u32; FIFO_DEPTH]> {
-        None
+        self.state.0.lock().unwrap().rec_buffer.dq_frame()
     }
     pub fn free_play_frames(&self) -> usize {
-        0
+        self.state.0.lock().unwrap().play_buffer.writeable_count()
     }
-
     pub fn can_play(&self) -> bool {
-        false
+        !self.state.0.lock().unwrap().play_buffer.is_empty()
     }
-
     pub fn drain(&mut self) {
+        self.state.0.lock().unwrap().drain = true;
     }
-
     pub fn available_rec_frames(&self) -> usize {
-        0
+        self.state.0.lock().unwrap().rec_buffer.readable_count()
     }
 
-    pub fn power(&mut self, _state: bool) {
+    pub fn power(&mut self, state: bool) {
+        self.powered_on = state;
+        if !state {
+            self.initialized = false;
+        }
     }
-
     pub fn is_on(&self) -> bool {
-        false
+        self.powered_on
     }
     pub fn is_init(&self) -> bool {
-        true
+        self.initialized
     }
     pub fn is_live(&self) -> bool {
-        false
+        self.state.0.lock().unwrap().live
     }
 
     pub fn get_headset_code(&mut self) -> u8 {
         0
     }
-
     pub fn get_dacflag_code(&mut self) -> u8 {
         0
     }
-
     pub fn get_hp_status(&mut self) -> u8 {
         0
     }
-
     pub fn get_i2s_config(&mut self) -> [u8; 4] {
         [0, 0, 0, 0]
     }
-
     pub fn audio_clocks(&mut self) {
     }
-
     pub fn audio_ports(&mut self) {
     }
-
     pub fn audio_loopback(&mut self, _do_loop:bool) {
     }
-
     /// set up the audio mixer to sane defaults
     pub fn audio_mixer(&mut self) {
     }
 
-    /// set up the betrusted-side signals
+    /// starts the thread that stands in for the audio interrupt
     pub fn audio_i2s_start(&mut self) {
+        let mut state = self.state.0.lock().unwrap();
+        state.drain = false;
+        if state.live {
+            return;
+        }
+        state.live = true;
+        state.generation = state.generation.wrapping_add(1);
+        let generation = state.generation;
+        let shared = Arc::clone(&self.state);
+        let conn = self.conn;
+        let pacing = self.pacing;
+        std::thread::spawn(move || {
+            frame_thread(shared, pacing, generation, || {
+                xous::try_send_message(conn,
+                    xous::Message::new_scalar(Opcode::AnotherFrame.to_usize().unwrap(), 0, 0, 0, 0)).ok();
+            });
+        });
     }
 
     pub fn audio_i2s_stop(&mut self) {
+        let (lock, cvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        log::info!("playback stopped. frames dropped: p{} r{}", state.play_frames_dropped, state.rec_frames_dropped);
+        state.play_frames_dropped = 0;
+        state.rec_frames_dropped = 0;
+        state.live = false;
+        state.drain = true;
+        state.play_buffer.clear();
+        state.rec_buffer.clear();
+        // the thread notices it's no longer live and exits
+        cvar.notify_all();
     }
 
+    // gains are ignored, so the played WAV file holds exactly the frames that were sent, and tests can compare them
     pub fn set_speaker_gain_db(&mut self, _gain_db: f32) {
     }
-
     pub fn set_headphone_gain_db(&mut self, _gain_db_l: f32, _gain_db_r: f32) {
     }
+}
+
+/// Runs one frame period at a time while the stream is live, doing what `audio_handler()` does on hardware.
+/// `another_frame` asks the server for more frames.
+fn frame_thread<F: Fn()>(state: Arc<(Mutex<State>, Condvar)>, pacing: Pacing, generation: u32, another_frame: F) {
+    let (lock, cvar) = &*state;
+    let mut next_frame = Instant::now();
+    loop {
+        let mut s = lock.lock().unwrap();
+        let period = Duration::from_micros(FIFO_DEPTH as u64 * 1_000_000 / s.sample_rate as u64);
+        match pacing {
+            Pacing::RealTime => {
+                next_frame += period;
+                let now = Instant::now();
+                if next_frame > now {
+                    drop(s);
+                    std::thread::sleep(next_frame - now);
+                    s = lock.lock().unwrap();
+                } else if now - next_frame > period * 4 {
+                    // we fell well behind, e.g. the host was busy; don't try to catch up
+                    next_frame = now;
+                }
+            }
+            Pacing::FastForward => {
+                // wait for the client to queue a frame, but not forever
+                if s.play_buffer.is_empty() && s.live {
+                    s = cvar.wait_timeout(s, period).unwrap().0;
+                }
+            }
+        }
+        if !s.live || s.generation != generation {
+            break;
+        }
+
+        let frame = match s.play_buffer.dq_frame() {
+            Some(frame) => frame,
+            None => {
+                s.play_frames_dropped += 1;
+                SILENCE
+            }
+        };
+        let mut write_failed = false;
+        if let Some(output) = s.output.as_mut() {
+            if let Err(e) = output.write_frames(&frame) {
+                log::error!("couldn't write audio output: {:?}", e);
+                write_failed = true;
+            }
+        }
+        if write_failed {
+            s.output = None;
+        }
+
+        let mut rec = SILENCE;
+        if let Some(input) = s.input.as_mut() {
+            input.read_frames(&mut rec);
+        }
+        if s.rec_buffer.nq_frame(rec).is_err() {
+            s.rec_frames_dropped += 1;
+        }
+
+        // if the buffer is low, let the audio handler know we used up another frame!
+        if s.play_buffer.readable_count() < 6 && !s.drain {
+            another_frame();
+        }
+    }
+    log::debug!("frame thread exiting");
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn frame(n: u32) -> [u32; FIFO_DEPTH] {
+        let mut frame = [0u32; FIFO_DEPTH];
+        for (i, sample) in frame.iter_mut().enumerate() {
+            *sample = (n * 7 + i as u32) & 0xFFFF | (0xFFFF - n) << 16;
+        }
+        frame
+    }
+
+    #[test]
+    fn fast_pacing_round_trip() {
+        const FRAMES: u32 = 40;
+        let dir = std::env::temp_dir();
+        let out_path = dir.join(format!("hostaudio-out-{}.wav", std::process::id()));
+        let in_path = dir.join(format!("hostaudio-in-{}.wav", std::process::id()));
+        let mut input = WavWriter::create(&in_path, 8000).unwrap();
+        for n in 0..FRAMES {
+            input.write_frames(&frame(1000 + n)).unwrap();
+        }
+        drop(input);
+
+        let shared = Arc::new((Mutex::new(State::new()), Condvar::new()));
+        let mut sent = 0;
+        {
+            let mut s = shared.0.lock().unwrap();
+            s.output = Some(WavWriter::create(&out_path, 8000).unwrap());
+            s.input = Some(WavReader::open(&in_path, 8000).unwrap());
+            s.live = true;
+            s.generation = 1;
+            // queued up front, so the thread doesn't start out playing silence
+            while sent < FRAMES && s.play_buffer.nq_frame(frame(sent)).is_ok() {
+                sent += 1;
+            }
+        }
+        let (more_tx, more_rx) = mpsc::channel();
+        let thread_state = Arc::clone(&shared);
+        let thread = std::thread::spawn(move || {
+            frame_thread(thread_state, Pacing::FastForward, 1, move || {
+                more_tx.send(()).ok();
+            });
+        });
+
+        // play the client's part: top up the play queue and take the recording whenever asked
+        let mut recorded = Vec::new();
+        loop {
+            more_rx.recv_timeout(Duration::from_secs(5)).expect("frame thread stopped asking for frames");
+            let (lock, cvar) = &*shared;
+            let mut s = lock.lock().unwrap();
+            while sent < FRAMES && s.play_buffer.nq_frame(frame(sent)).is_ok() {
+                sent += 1;
+            }
+            while let Some(rec) = s.rec_buffer.dq_frame() {
+                recorded.push(rec);
+            }
+            cvar.notify_all();
+            if sent == FRAMES && s.play_buffer.is_empty() {
+                s.live = false;
+                break;
+            }
+        }
+        shared.1.notify_all();
+        thread.join().unwrap();
+        let mut s = shared.0.lock().unwrap();
+        while let Some(rec) = s.rec_buffer.dq_frame() {
+            recorded.push(rec);
+        }
+        assert_eq!(s.rec_frames_dropped, 0);
+        s.output = None;
+
+        // every frame was played in order; anything after them is the silence played while the
+        // queue sat empty at the end
+        let mut played = WavReader::open(&out_path, 8000).unwrap();
+        for n in 0..FRAMES {
+            let mut out = [0u32; FIFO_DEPTH];
+            played.read_frames(&mut out);
+            assert!(out[..] == frame(n)[..], "played frame {} differs", n);
+        }
+        while !played.is_finished() {
+            let mut out = [0u32; FIFO_DEPTH];
+            played.read_frames(&mut out);
+            assert!(out[..] == SILENCE[..]);
+        }
+        // and a frame was recorded for each one played, from the input file
+        assert!(recorded.len() >= FRAMES as usize);
+        for (n, rec) in recorded.iter().enumerate() {
+            let expected = if n < FRAMES as usize { frame(1000 + n as u32) } else { SILENCE };
+            assert!(rec[..] == expected[..], "recorded frame {} differs", n);
+        }
+        std::fs::remove_file(&out_path).ok();
+        std::fs::remove_file(&in_path).ok();
+    }
 }
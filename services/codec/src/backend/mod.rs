@@ -1,6 +1,8 @@
 #[cfg(not(any(target_os = "none", target_os = "xous")))]
 mod hostaudio;
 #[cfg(not(any(target_os = "none", target_os = "xous")))]
+mod wav;
+#[cfg(not(any(target_os = "none", target_os = "xous")))]
 pub use crate::backend::hostaudio::*;
 
 #[cfg(any(target_os = "none", target_os = "xous"))]
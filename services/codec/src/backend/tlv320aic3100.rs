@@ -5,11 +5,35 @@ use xous::MemoryRange;
 use susres::{RegManager, RegOrField, SuspendResume};
 use llio::I2cStatus;
 use crate::api::*;
+use codec::{Capabilities, SampleFormat, STANDARD_RATES};
 use num_traits::*;
 
 pub const TLV320AIC3100_I2C_ADR: u8 = 0b0011_000;
 const I2C_TIMEOUT: u32 = 50;
 
+/// Clock tree settings for one sample rate, from a 12MHz MCLK. P = R = 1, and DOSR = AOSR = 128 throughout,
+/// so DAC_MOD_CLK is always 128 * fs and BCLK can stay at DAC_MOD_CLK / 2.
+struct ClockConfig {
+    rate: u32,
+    pll_j: u8,
+    pll_d: u16,
+    ndac: u8,
+    mdac: u8,
+    nadc: u8,
+    madc: u8,
+}
+/// PLL_CLK = 86.016MHz covers the 48kHz family, 90.3168MHz the 44.1kHz family. Per page 68 of the datasheet,
+/// with NDAC * MDAC = NADC * MADC = PLL_CLK / (128 * fs).
+const CLOCK_CONFIGS: [ClockConfig; 7] = [
+    ClockConfig { rate: 8000,  pll_j: 7, pll_d: 1680, ndac: 12, mdac: 7, nadc: 42, madc: 2 },
+    ClockConfig { rate: 11025, pll_j: 7, pll_d: 5264, ndac: 32, mdac: 2, nadc: 32, madc: 2 },
+    ClockConfig { rate: 16000, pll_j: 7, pll_d: 1680, ndac: 6,  mdac: 7, nadc: 21, madc: 2 },
+    ClockConfig { rate: 22050, pll_j: 7, pll_d: 5264, ndac: 16, mdac: 2, nadc: 16, madc: 2 },
+    ClockConfig { rate: 32000, pll_j: 7, pll_d: 1680, ndac: 3,  mdac: 7, nadc: 3,  madc: 7 },
+    ClockConfig { rate: 44100, pll_j: 7, pll_d: 5264, ndac: 8,  mdac: 2, nadc: 8,  madc: 2 },
+    ClockConfig { rate: 48000, pll_j: 7, pll_d: 1680, ndac: 2,  mdac: 7, nadc: 7,  madc: 2 },
+];
+
 pub struct Codec {
     csr: utralib::CSR<u32>,
     fifo: MemoryRange,
@@ -32,6 +56,7 @@ pub struct Codec {
     speaker_gain: f32,
     headphone_left_gain: f32,
     headphone_right_gain: f32,
+    sample_rate: u32,
 }
 
 static SILENCE: [u32; FIFO_DEPTH] = [ZERO_PCM as u32 | (ZERO_PCM as u32) << 16; FIFO_DEPTH];
@@ -145,6 +170,7 @@ impl Codec {
             speaker_gain: -6.0,
             headphone_left_gain: -15.0,
             headphone_right_gain: -15.0,
+            sample_rate: 8000,
         };
 
         xous::claim_interrupt(
@@ -207,6 +233,30 @@ impl Codec {
         self.initialized = true;
     }
 
+    pub fn capabilities(&self) -> Capabilities {
+        let mut native_rates = 0;
+        for (i, rate) in STANDARD_RATES.iter().enumerate() {
+            if CLOCK_CONFIGS.iter().any(|c| c.rate == *rate) {
+                native_rates |= 1 << i;
+            }
+        }
+        Capabilities {
+            native_rates,
+            min_rate: 4000,
+            max_rate: 48000,
+            max_channels: 2,
+            formats: 1 << SampleFormat::I16 as u32,
+        }
+    }
+    /// Takes effect on the next `init()`. `rate` has to be one of the native rates in `capabilities()`.
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        assert!(CLOCK_CONFIGS.iter().any(|c| c.rate == rate), "sample rate {} can't be clocked", rate);
+        self.sample_rate = rate;
+    }
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
     pub fn nq_play_frame(&mut self, frame: [u32; FIFO_DEPTH]) -> Result<(), [u32; FIFO_DEPTH]> {
         self.play_buffer.nq_frame(frame)
     }
@@ -334,21 +384,19 @@ impl Codec {
         code
     }
 
-    /// audio_clocks() sets up the default clocks for 8kHz sampling rate, assuming a 12MHz MCLK input
+    /// audio_clocks() sets up the clocks for the selected sampling rate, assuming a 12MHz MCLK input
     ///
+    /// e.g. for 8kHz:
     /// fIN = 12 MHz
-    /// M = 2.5
-    /// N = 32   (PLL freq = 153.6MHz)
-    /// N_MOD = 0
-    /// P = 12.5
-    /// fOUT = 12_288_000 Hz
-    ///
-    /// sample rate = 8_000
-    /// oversampling rate (OSR) = 128
-    /// local divider = 12
-    /// 8_000 * 128 * 12 = 12_288_000 Hz
+    /// P = 1, R = 1, J = 7, D = 1680  (PLL freq = 86.016MHz)
+    /// NDAC = 12, MDAC = 7, DOSR = 128
+    /// 8_000 * 128 * 7 * 12 = 86_016_000 Hz
     ///
+    /// The other rates are in CLOCK_CONFIGS.
     fn audio_clocks(&mut self) {
+        let config = CLOCK_CONFIGS.iter().find(|c| c.rate == self.sample_rate).unwrap_or(&CLOCK_CONFIGS[0]);
+        log::debug!("setting up clocks for {}Hz", config.rate);
+
         self.w(0, &[0]);  // select page 0
         self.w(1, &[1]);  // software reset
         self.ticktimer.sleep_ms(2).unwrap(); // reset happens in 1 ms; +1 ms due to timing jitter uncertainty
@@ -358,27 +406,23 @@ impl Codec {
         // select PLL_CLKIN = MCLK; CODEC_CLKIN = PLL_CLK
         self.w(4, &[0b0000_0011]);
 
-        // fs = 8kHz
-        // PLL_CLKIN = 12MHz
-        // PLLP = 1, PLLR = 1, PLLJ = 7, PLLD = 1680, NDAC = *12*, MDAC = 7, DOSR = 128, MADC = 2 , NADC = *42*
-        // ^^ from page 68 of datasheet, fs=48kHz/12MHz clkin line, with *bold* items multiplied by 6 to get to 8kHz
         self.w(5, &[
             0b1001_0001,  // P, R = 1, 1 and pll powered up
-            7,            // PLLJ = 7
-            ((1680 >> 8) & 0xFF) as u8, // D MSB of 1680
-            (1680 & 0xFF) as u8,        // D LSB of 1680
+            config.pll_j,
+            ((config.pll_d >> 8) & 0xFF) as u8, // D MSB
+            (config.pll_d & 0xFF) as u8,        // D LSB
             ]);
 
         self.w(11, &[
-            0x80 | 12,  // NADC = 12 (set to 2 for 48kHz)
-            0x80 | 7,   // MDAC = 7
+            0x80 | config.ndac,
+            0x80 | config.mdac,
             0,   // DOSR = MSB of 128
             128, // DOSR = LSB of 128
         ]);
 
         self.w(18, &[
-            0x80 | 42,  // NADC = 42 (set to 7 for 48kHz)
-            0x80 | 2,   // MADC = 2
+            0x80 | config.nadc,
+            0x80 | config.madc,
             128, // AOSR = 128
         ]);
     }
@@ -390,8 +434,8 @@ impl Codec {
     fn audio_ports(&mut self) {
         self.w(0, &[0]); // select page 0
 
-        // 32 bits/word * 2 channels * 8000 samples/s = 512_000 = BCLK
-        // pick off of DAC_MOD_CLK = 1.024MHz
+        // 32 bits/word * 2 channels * fs = 64 * fs = BCLK
+        // pick off of DAC_MOD_CLK = 128 * fs (1.024MHz at 8kHz)
         self.w(27, &[
             0b00_00_1_1_0_1, // I2S standard, 16 bits per sample, BCLK output, WCLK output, DOUT is Hi-Z when unused
             0b0,           // no offset on left justification
@@ -0,0 +1,178 @@
+//! Just enough of the WAV format to stand in for the CODEC in hosted mode: 16-bit PCM, mono or
+//! stereo, in and out.
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use codec::resample::Resampler;
+
+const HEADER_LEN: u64 = 44;
+
+/// Writes stereo 16-bit frames to a WAV file. The header is kept up to date as frames are
+/// written, so the file is complete even if the process is killed.
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    data_bytes: u32,
+}
+
+impl WavWriter<File> {
+    pub fn create<P: AsRef<Path>>(path: P, rate: u32) -> io::Result<Self> {
+        WavWriter::new(File::create(path)?, rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, rate: u32) -> io::Result<Self> {
+        let channels: u16 = 2;
+        let block_align = channels * 2;
+        out.write_all(b"RIFF")?;
+        out.write_all(&36u32.to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&channels.to_le_bytes())?;
+        out.write_all(&rate.to_le_bytes())?;
+        out.write_all(&(rate * block_align as u32).to_le_bytes())?;
+        out.write_all(&block_align.to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?; // bits per sample
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.flush()?;
+        Ok(WavWriter { out, data_bytes: 0 })
+    }
+    /// Appends frames in the CODEC's format, `|31 right 16|15 left 0|`
+    pub fn write_frames(&mut self, frame: &[u32]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(frame.len() * 4);
+        for &sample in frame.iter() {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        self.out.seek(SeekFrom::Start(HEADER_LEN + self.data_bytes as u64))?;
+        self.out.write_all(&bytes)?;
+        self.data_bytes += bytes.len() as u32;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(36 + self.data_bytes).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&self.data_bytes.to_le_bytes())?;
+        self.out.flush()
+    }
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Reads a 16-bit PCM WAV file as stereo frames at the rate the CODEC is running at. Once the
+/// file runs out, it reads as silence.
+pub struct WavReader {
+    samples: Vec<i16>,
+    /// next sample to read
+    pos: usize,
+}
+
+impl WavReader {
+    pub fn open<P: AsRef<Path>>(path: P, rate: u32) -> io::Result<Self> {
+        WavReader::new(BufReader::new(File::open(path)?), rate)
+    }
+    pub fn new<R: Read>(mut input: R, rate: u32) -> io::Result<Self> {
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+            return Err(invalid("not a WAV file"));
+        }
+        let mut format: Option<(u16, u32)> = None;
+        let mut pcm: Option<&[u8]> = None;
+        let mut chunk = 12;
+        while chunk + 8 <= data.len() {
+            let id = &data[chunk..chunk + 4];
+            let len = u32::from_le_bytes(data[chunk + 4..chunk + 8].try_into().unwrap()) as usize;
+            let body = &data[chunk + 8..(chunk + 8 + len).min(data.len())];
+            if id == b"fmt " {
+                if body.len() < 16 {
+                    return Err(invalid("truncated fmt chunk"));
+                }
+                let tag = u16::from_le_bytes(body[0..2].try_into().unwrap());
+                let channels = u16::from_le_bytes(body[2..4].try_into().unwrap());
+                let file_rate = u32::from_le_bytes(body[4..8].try_into().unwrap());
+                let bits = u16::from_le_bytes(body[14..16].try_into().unwrap());
+                if tag != 1 || bits != 16 || !(channels == 1 || channels == 2) || file_rate == 0 {
+                    return Err(invalid("only 16-bit PCM, mono or stereo, is supported"));
+                }
+                format = Some((channels, file_rate));
+            } else if id == b"data" {
+                pcm = Some(body);
+            }
+            // chunks are padded to an even length
+            chunk += 8 + len + (len & 1);
+        }
+        let (channels, file_rate) = format.ok_or_else(|| invalid("no fmt chunk"))?;
+        let pcm = pcm.ok_or_else(|| invalid("no data chunk"))?;
+        let decoded: Vec<i16> = pcm.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect();
+        let mut resampled = Vec::with_capacity(decoded.len() * rate as usize / file_rate as usize + 2);
+        Resampler::new(file_rate, rate, channels as usize).process(&decoded, &mut resampled);
+        let samples = if channels == 1 {
+            resampled.iter().flat_map(|&s| std::iter::repeat(s).take(2)).collect()
+        } else {
+            resampled
+        };
+        Ok(WavReader { samples, pos: 0 })
+    }
+    pub fn is_finished(&self) -> bool {
+        self.pos >= self.samples.len()
+    }
+    /// Fills `frame` in the CODEC's format
+    pub fn read_frames(&mut self, frame: &mut [u32]) {
+        for word in frame.iter_mut() {
+            let left = self.samples.get(self.pos).copied().unwrap_or(0);
+            let right = self.samples.get(self.pos + 1).copied().unwrap_or(0);
+            self.pos += 2;
+            *word = left as u16 as u32 | (right as u16 as u32) << 16;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trip() {
+        let mut w = WavWriter::new(Cursor::new(Vec::new()), 8000).unwrap();
+        let frames: Vec<u32> = (0..100u32).map(|i| i | (0xFFFF - i) << 16).collect();
+        w.write_frames(&frames[..60]).unwrap();
+        w.write_frames(&frames[60..]).unwrap();
+        let file = w.into_inner().into_inner();
+        assert_eq!(file.len(), 44 + 400);
+        assert_eq!(u32::from_le_bytes(file[40..44].try_into().unwrap()), 400);
+
+        let mut r = WavReader::new(Cursor::new(file), 8000).unwrap();
+        let mut read = [0u32; 128];
+        r.read_frames(&mut read);
+        assert_eq!(&read[..100], &frames[..]);
+        // and silence once the file runs out
+        assert!(read[100..].iter().all(|&s| s == 0));
+        assert!(r.is_finished());
+    }
+
+    #[test]
+    fn mono_resampled() {
+        let mut file = Vec::new();
+        file.extend_from_slice(b"RIFF\x00\x00\x00\x00WAVE");
+        file.extend_from_slice(b"LIST\x03\x00\x00\x00abc\x00"); // odd-length chunk, padded
+        file.extend_from_slice(b"fmt \x10\x00\x00\x00\x01\x00\x01\x00");
+        file.extend_from_slice(&4000u32.to_le_bytes());
+        file.extend_from_slice(&8000u32.to_le_bytes());
+        file.extend_from_slice(b"\x02\x00\x10\x00data\x04\x00\x00\x00");
+        file.extend_from_slice(&100i16.to_le_bytes());
+        file.extend_from_slice(&200i16.to_le_bytes());
+        let mut r = WavReader::new(Cursor::new(file), 8000).unwrap();
+        let mut read = [0u32; 4];
+        r.read_frames(&mut read);
+        let expect = |s: i16| s as u16 as u32 | (s as u16 as u32) << 16;
+        assert_eq!(read, [expect(0), expect(50), expect(100), expect(150)]);
+
+        assert!(WavReader::new(Cursor::new(b"RIFF\x00\x00\x00\x00WAVE".to_vec()), 8000).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 #![cfg_attr(target_os = "none", no_std)]
 
 pub mod api;
+pub mod resample;
 use xous::{CID, send_message, Message};
 use num_traits::{ToPrimitive, FromPrimitive};
 use xous_ipc::Buffer;
@@ -45,6 +46,33 @@ impl Codec {
             Message::new_scalar(Opcode::Setup8kStereo.to_usize().unwrap(), 0, 0, 0, 0)
         ).map(|_| ())
     }
+    /// Powers on the CODEC and sets it up to stream `format`, or the closest format it can do,
+    /// which is returned. Frames must then be supplied in the returned format, in a
+    /// `FrameRing::with_format()`.
+    pub fn setup_stream(&mut self, format: StreamFormat) -> Result<StreamFormat, xous::Error> {
+        let setup = StreamSetup {
+            requested: format,
+            granted: None,
+            hardware_rate: 0,
+        };
+        let mut buf = Buffer::into_buf(setup).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::SetupStream.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        let setup = buf.to_original::<StreamSetup, _>().unwrap();
+        log::debug!("requested {:?}, granted {:?} at a hardware rate of {}", setup.requested, setup.granted, setup.hardware_rate);
+        setup.granted.ok_or(xous::Error::InvalidLimit)
+    }
+    pub fn capabilities(&self) -> Result<Capabilities, xous::Error> {
+        let caps = Capabilities {
+            native_rates: 0,
+            min_rate: 0,
+            max_rate: 0,
+            max_channels: 0,
+            formats: 0,
+        };
+        let mut buf = Buffer::into_buf(caps).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::GetCapabilities.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        Ok(buf.to_original::<Capabilities, _>().unwrap())
+    }
     pub fn power_off(&mut self) -> Result<(), xous::Error> {
         send_message(self.conn,
             Message::new_scalar(Opcode::PowerOff.to_usize().unwrap(), 0, 0, 0, 0)
@@ -4,6 +4,8 @@
 mod api;
 mod backend;
 use backend::Codec;
+mod stream;
+use stream::Stream;
 
 use num_traits::{ToPrimitive, FromPrimitive};
 use xous_ipc::Buffer;
@@ -51,6 +53,8 @@ fn xmain() -> ! {
     let mut speaker_analog_gain_db: f32 = -6.0;
     let mut headphone_analog_gain_db: f32 = -15.0;
     let mut audio_cb_conns: [Option<ScalarCallback>; 32] = [None; 32];
+    // converts frames between the format clients stream in and what the hardware is clocked for
+    let mut stream = Stream::new(codec::StreamFormat::default_8k_stereo(), 8000);
     loop {
         let mut msg = xous::receive_message(codec_sid).unwrap();
         //log::trace!("got message {:?}", msg);
@@ -64,13 +68,27 @@ fn xmain() -> ! {
                 codec.power(false);
             }),
             Some(api::Opcode::Setup8kStereo) => xous::msg_scalar_unpack!(msg, _, _, _, _, {
-                log::trace!("turning on codec power");
-                codec.power(true);
-                log::trace!("waiting for power up");
-                ticktimer.sleep_ms(2).unwrap();
-                log::trace!("initializing codec");
-                codec.init();
+                stream = Stream::new(codec::StreamFormat::default_8k_stereo(), 8000);
+                setup_codec(&mut codec, &ticktimer, 8000);
             }),
+            Some(api::Opcode::SetupStream) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut setup = buffer.to_original::<codec::api::StreamSetup, _>().unwrap();
+                let (granted, hardware_rate) = stream::negotiate(&codec.capabilities(), setup.requested);
+                log::info!("stream requested {:?}, granted {:?} at {}Hz", setup.requested, granted, hardware_rate);
+                if codec.is_live() {
+                    codec.audio_i2s_stop();
+                }
+                stream = Stream::new(granted, hardware_rate);
+                setup_codec(&mut codec, &ticktimer, hardware_rate);
+                setup.granted = Some(granted);
+                setup.hardware_rate = hardware_rate;
+                buffer.replace(setup).unwrap();
+            }
+            Some(api::Opcode::GetCapabilities) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                buffer.replace(codec.capabilities()).unwrap();
+            }
             Some(api::Opcode::ResumeStream) => xous::msg_scalar_unpack!(msg, _, _, _, _, {
                 if codec.is_on() && codec.is_init() {
                     codec.audio_i2s_start();
@@ -85,6 +103,7 @@ fn xmain() -> ! {
                         xous::yield_slice();
                     }
                     codec.audio_i2s_stop();
+                    stream.clear();
                 } else {
                     log::error!("attempted to pause a stream on an uninitialized codec, ignoring!")
                 }
@@ -92,6 +111,7 @@ fn xmain() -> ! {
             Some(api::Opcode::AbortStream) => xous::msg_scalar_unpack!(msg, _, _, _, _, {
                 if codec.is_on() && codec.is_init() && codec.is_live() {
                     codec.audio_i2s_stop();
+                    stream.clear();
                 } else {
                     log::error!("attempted to abort a stream on an uninitialized codec, ignoring!")
                 }
@@ -105,16 +125,22 @@ fn xmain() -> ! {
                 xous::return_scalar(msg.sender, ret).expect("couldn't return if codec is live");
             }),
             Some(api::Opcode::FreeFrames) => xous::msg_blocking_scalar_unpack!(msg, _, _, _, _, {
-                let play_free = codec.free_play_frames();
-                let rec_avail = codec.available_rec_frames();
+                let play_free = stream.client_frames(codec.free_play_frames());
+                let rec_avail = stream.client_frames(codec.available_rec_frames());
                 xous::return_scalar2(msg.sender, play_free, rec_avail).expect("couldn't return FreeFrames");
             }),
             Some(api::Opcode::SwapFrames) => {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let mut framering = buffer.to_original::<codec::api::FrameRing, _>().unwrap();
+                if framering.format() != stream.client_format() {
+                    log::warn!("got frames in {:?}, but the stream was set up for {:?}; converting from the frames' format",
+                        framering.format(), stream.client_format());
+                    stream = Stream::new(framering.format(), stream.hardware_rate());
+                }
 
-                loop {
-                    if let Some(frame) = framering.dq_frame() {
+                while let Some(client_frame) = framering.dq_frame() {
+                    stream.put_play_frame(&client_frame);
+                    while let Some(frame) = stream.take_play_frame() {
                         let mut printed = false;
                         while codec.free_play_frames() == 0 {
                             if !printed {
@@ -134,19 +160,15 @@ fn xmain() -> ! {
                             // - wait until we can play the frame?
                             // - throw away the frame?
                         }
-                    } else {
-                        break;
                     }
                 }
 
                 framering.reset_ptrs();
-                loop {
-                    if let Some(frame) = codec.dq_rec_frame() {
-                        if !framering.is_full() {
-                            framering.nq_frame(frame).unwrap(); // always succeeds because we checked if we're full first
-                        } else {
-                            break;
-                        }
+                while !framering.is_full() {
+                    if let Some(frame) = stream.take_rec_frame() {
+                        framering.nq_frame(frame).unwrap(); // always succeeds because we checked if we're full first
+                    } else if let Some(frame) = codec.dq_rec_frame() {
+                        stream.put_rec_frame(&frame);
                     } else {
                         break;
                     }
@@ -163,7 +185,7 @@ fn xmain() -> ! {
             }
             Some(api::Opcode::AnotherFrame) => xous::msg_scalar_unpack!(msg, _rdcount, _wrcount, _, _, {
                 //log::trace!("A rd {} wr {}", rdcount, wrcount);
-                send_event(&audio_cb_conns, codec.free_play_frames(), codec.available_rec_frames());
+                send_event(&audio_cb_conns, stream.client_frames(codec.free_play_frames()), stream.client_frames(codec.available_rec_frames()));
             }),
             Some(api::Opcode::SetSpeakerVolume) => xous::msg_scalar_unpack!(msg, op, gain_code, _, _, {
                 match FromPrimitive::from_usize(op) {
@@ -235,6 +257,16 @@ fn xmain() -> ! {
 }
 
 
+fn setup_codec(codec: &mut Codec, ticktimer: &ticktimer_server::Ticktimer, rate: u32) {
+    codec.set_sample_rate(rate);
+    log::trace!("turning on codec power");
+    codec.power(true);
+    log::trace!("waiting for power up");
+    ticktimer.sleep_ms(2).unwrap();
+    log::trace!("initializing codec at {}Hz", rate);
+    codec.init();
+}
+
 fn do_hook(hookdata: ScalarHook, cb_conns: &mut [Option<ScalarCallback>; 32]) {
     let (s0, s1, s2, s3) = hookdata.sid;
     let sid = xous::SID::from_u32(s0, s1, s2, s3);
@@ -0,0 +1,114 @@
+//! Software sample-rate conversion, for streams whose rate the CODEC can't clock itself.
+//!
+//! This is plain linear interpolation: cheap enough to run on every frame, and good enough for
+//! voice and UI sounds. It does no low-pass filtering, so converting down to a much lower rate
+//! will alias anything above the new Nyquist frequency.
+
+/// Converts interleaved i16 samples from one rate to another, keeping its place between calls
+/// so a stream can be fed through it a frame at a time.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    from: u32,
+    to: u32,
+    channels: usize,
+    /// the last input sample of each channel
+    prev: [i32; 2],
+    /// where the next output sample falls after `prev`, in 1/`to`ths of an input sample
+    phase: u32,
+}
+
+impl Resampler {
+    /// `channels` is 1 or 2.
+    pub fn new(from: u32, to: u32, channels: usize) -> Resampler {
+        assert!(from > 0 && to > 0, "sample rates must be non-zero");
+        assert!(channels == 1 || channels == 2, "only mono and stereo streams are supported");
+        Resampler {
+            from,
+            to,
+            channels,
+            prev: [0; 2],
+            phase: 0,
+        }
+    }
+    pub fn from_rate(&self) -> u32 {
+        self.from
+    }
+    pub fn to_rate(&self) -> u32 {
+        self.to
+    }
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+    /// Forgets the stream so far, e.g. after it was stopped
+    pub fn reset(&mut self) {
+        self.prev = [0; 2];
+        self.phase = 0;
+    }
+    /// Converts `input`, appending the result to `output`. A trailing partial sample frame in
+    /// `input` is ignored.
+    pub fn process(&mut self, input: &[i16], output: &mut Vec<i16>) {
+        if self.from == self.to {
+            output.extend_from_slice(&input[..input.len() - input.len() % self.channels]);
+            return;
+        }
+        for frame in input.chunks_exact(self.channels) {
+            while self.phase < self.to {
+                for (ch, &cur) in frame.iter().enumerate() {
+                    let prev = self.prev[ch];
+                    let delta = (cur as i32 - prev) as i64 * self.phase as i64 / self.to as i64;
+                    output.push((prev + delta as i32) as i16);
+                }
+                self.phase += self.from;
+            }
+            self.phase -= self.to;
+            for (ch, &cur) in frame.iter().enumerate() {
+                self.prev[ch] = cur as i32;
+            }
+        }
+    }
+    /// The most sample frames `process()` can produce from `frames` input sample frames
+    pub fn max_output(&self, frames: usize) -> usize {
+        (frames as u64 * self.to as u64 / self.from as u64) as usize + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_ratios() {
+        let input: Vec<i16> = (0..800).map(|i| (i * 10) as i16).collect();
+        for &(from, to) in [(8000, 16000), (8000, 48000), (44100, 48000), (48000, 8000), (22050, 8000)].iter() {
+            let mut r = Resampler::new(from, to, 1);
+            let mut out = Vec::new();
+            // feed it in uneven pieces; the result should not depend on the frame boundaries
+            for chunk in input.chunks(77) {
+                r.process(chunk, &mut out);
+            }
+            let expected = input.len() as u64 * to as u64 / from as u64;
+            assert!((out.len() as i64 - expected as i64).abs() <= 1, "{}->{}: {} samples", from, to, out.len());
+            assert!(out.len() <= r.max_output(input.len()));
+            // a ramp stays a ramp, one input sample late
+            let step = 10.0 * from as f64 / to as f64;
+            for (i, &s) in out.iter().enumerate().skip(to as usize / from as usize + 1) {
+                let want = (i as f64 * step) - 10.0;
+                assert!((s as f64 - want).abs() <= 1.0, "{}->{}: sample {} is {}, wanted {}", from, to, i, s, want);
+            }
+        }
+    }
+
+    #[test]
+    fn stereo_and_passthrough() {
+        let mut r = Resampler::new(8000, 16000, 2);
+        let mut out = Vec::new();
+        r.process(&[100, -100, 200, -200, 300], &mut out);
+        // the odd sample at the end isn't a whole frame
+        assert_eq!(out, [0, 0, 50, -50, 100, -100, 150, -150]);
+
+        let mut r = Resampler::new(48000, 48000, 2);
+        out.clear();
+        r.process(&[1, 2, 3, 4, 5], &mut out);
+        assert_eq!(out, [1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,152 @@
+use codec::resample::Resampler;
+use codec::{Capabilities, Channels, SampleFormat, StreamFormat, FIFO_DEPTH, STANDARD_RATES};
+use std::collections::VecDeque;
+
+/// Recorded audio the client hasn't picked up is dropped beyond this many words
+const MAX_REC_PENDING: usize = FIFO_DEPTH * 16;
+
+/// Picks the closest format to `requested` that can be streamed, and the rate to clock the
+/// hardware at for it.
+pub(crate) fn negotiate(caps: &Capabilities, requested: StreamFormat) -> (StreamFormat, u32) {
+    let rate = requested.rate.max(caps.min_rate).min(caps.max_rate);
+    let channels = if requested.channels.count() <= caps.max_channels as usize { requested.channels } else { Channels::Mono };
+    let format = if caps.formats & (1 << requested.format as u32) != 0 { requested.format } else { SampleFormat::I16 };
+    let hardware_rate = if caps.is_native(rate) {
+        rate
+    } else {
+        // resample up rather than down, so nothing gets filtered out
+        caps.native_rates().find(|&r| r >= rate)
+            .or_else(|| caps.native_rates().last())
+            .unwrap_or(STANDARD_RATES[0])
+    };
+    (StreamFormat::new(rate, channels, format), hardware_rate)
+}
+
+/// Converts between the format a client streams in and the stereo frames the hardware takes.
+pub(crate) struct Stream {
+    client: StreamFormat,
+    hardware_rate: u32,
+    play: Resampler,
+    rec: Resampler,
+    /// converted words waiting to make up a whole frame
+    play_pending: VecDeque<u32>,
+    rec_pending: VecDeque<u32>,
+    /// a mono sample recorded that didn't fill a word on its own
+    rec_odd: Option<i16>,
+}
+
+impl Stream {
+    pub(crate) fn new(client: StreamFormat, hardware_rate: u32) -> Stream {
+        let channels = client.channels.count();
+        Stream {
+            client,
+            hardware_rate,
+            play: Resampler::new(client.rate, hardware_rate, channels),
+            rec: Resampler::new(hardware_rate, client.rate, channels),
+            play_pending: VecDeque::new(),
+            rec_pending: VecDeque::new(),
+            rec_odd: None,
+        }
+    }
+    pub(crate) fn client_format(&self) -> StreamFormat {
+        self.client
+    }
+    pub(crate) fn hardware_rate(&self) -> u32 {
+        self.hardware_rate
+    }
+    fn is_passthrough(&self) -> bool {
+        self.client.rate == self.hardware_rate && self.client.channels == Channels::Stereo
+    }
+    pub(crate) fn clear(&mut self) {
+        self.play.reset();
+        self.rec.reset();
+        self.play_pending.clear();
+        self.rec_pending.clear();
+        self.rec_odd = None;
+    }
+
+    /// Takes in a frame from the client.
+    pub(crate) fn put_play_frame(&mut self, frame: &[u32; FIFO_DEPTH]) {
+        if self.is_passthrough() {
+            self.play_pending.extend(frame.iter());
+            return;
+        }
+        let mut samples = Vec::with_capacity(FIFO_DEPTH * 2);
+        self.client.unpack(frame, &mut samples);
+        let mut resampled = Vec::with_capacity(self.play.max_output(FIFO_DEPTH * self.client.frames_per_word()) * 2);
+        self.play.process(&samples, &mut resampled);
+        match self.client.channels {
+            Channels::Stereo => {
+                for pair in resampled.chunks_exact(2) {
+                    self.play_pending.push_back(pair[0] as u16 as u32 | (pair[1] as u16 as u32) << 16);
+                }
+            }
+            Channels::Mono => {
+                for &s in resampled.iter() {
+                    self.play_pending.push_back(s as u16 as u32 | (s as u16 as u32) << 16);
+                }
+            }
+        }
+    }
+    /// A frame for the hardware, once enough has been put in to make one.
+    pub(crate) fn take_play_frame(&mut self) -> Option<[u32; FIFO_DEPTH]> {
+        take_frame(&mut self.play_pending)
+    }
+    /// Takes in a frame recorded by the hardware.
+    pub(crate) fn put_rec_frame(&mut self, frame: &[u32; FIFO_DEPTH]) {
+        if self.is_passthrough() {
+            self.rec_pending.extend(frame.iter());
+        } else {
+            let mut samples = Vec::with_capacity(FIFO_DEPTH * 2);
+            for &w in frame.iter() {
+                let (left, right) = ((w & 0xFFFF) as u16 as i16, (w >> 16) as u16 as i16);
+                match self.client.channels {
+                    Channels::Stereo => {
+                        samples.push(left);
+                        samples.push(right);
+                    }
+                    Channels::Mono => samples.push(((left as i32 + right as i32) / 2) as i16),
+                }
+            }
+            let mut resampled = Vec::with_capacity(self.rec.max_output(FIFO_DEPTH) * 2 + 1);
+            if let Some(s) = self.rec_odd.take() {
+                resampled.push(s);
+            }
+            self.rec.process(&samples, &mut resampled);
+            let mut words = Vec::with_capacity(resampled.len() / 2);
+            let used = self.client.pack(&resampled, &mut words);
+            if used < resampled.len() {
+                self.rec_odd = Some(resampled[used]);
+            }
+            self.rec_pending.extend(words);
+        }
+        while self.rec_pending.len() > MAX_REC_PENDING {
+            self.rec_pending.pop_front();
+        }
+    }
+    /// A frame for the client, once enough has been recorded to make one.
+    pub(crate) fn take_rec_frame(&mut self) -> Option<[u32; FIFO_DEPTH]> {
+        take_frame(&mut self.rec_pending)
+    }
+
+    /// How many client frames take as long to play as `hardware_frames` frames do on the hardware
+    pub(crate) fn client_frames(&self, hardware_frames: usize) -> usize {
+        if self.is_passthrough() {
+            hardware_frames
+        } else {
+            (hardware_frames as u64 * self.client.rate as u64
+                / (self.hardware_rate as u64 * self.client.frames_per_word() as u64)) as usize
+        }
+    }
+}
+
+fn take_frame(pending: &mut VecDeque<u32>) -> Option<[u32; FIFO_DEPTH]> {
+    if pending.len() < FIFO_DEPTH {
+        return None;
+    }
+    let mut frame = [0u32; FIFO_DEPTH];
+    for (dst, src) in frame.iter_mut().zip(pending.drain(..FIFO_DEPTH)) {
+        *dst = src;
+    }
+    Some(frame)
+}
@@ -12,7 +12,6 @@ use std::collections::HashMap;
 use std::convert::TryInto;
 use std::io::ErrorKind;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
-use std::thread;
 use xous_ipc::{Buffer, String};
 
 // KISS DNS
@@ -498,28 +497,19 @@ fn xmain() -> ! {
 
     let mut dns_cache = HashMap::<std::string::String, HashMap<IpAddr, u32>>::new();
 
-    // build a thread that pings the UpdateTtl function once every few minutes to expire the DNS cache
-    thread::spawn({
-        let local_cid = xous::connect(dns_sid).unwrap();
-        move || {
-            const TTL_INTERVAL_SECS: usize = 300; // every 5 minutes update the map
-            let tt = ticktimer_server::Ticktimer::new().unwrap();
-            loop {
-                tt.sleep_ms(TTL_INTERVAL_SECS * 1000).unwrap();
-                xous::send_message(
-                    local_cid,
-                    xous::Message::new_scalar(
-                        Opcode::UpdateTtl.to_usize().unwrap(),
-                        TTL_INTERVAL_SECS,
-                        0,
-                        0,
-                        0,
-                    ),
-                )
-                .expect("couldn't increment DNS cache");
-            }
-        }
-    });
+    // a periodic timer pings the UpdateTtl function once every few minutes to expire the DNS cache.
+    // Exact timing doesn't matter here, so give the ticktimer lots of room to batch it.
+    const TTL_INTERVAL_SECS: u64 = 300; // every 5 minutes update the map
+    let mut tt = ticktimer_server::Ticktimer::new().unwrap();
+    tt.register_timer(
+        xous::connect(dns_sid).unwrap(),
+        Opcode::UpdateTtl.to_usize().unwrap(),
+        TTL_INTERVAL_SECS as u32,
+        TTL_INTERVAL_SECS * 1000,
+        TTL_INTERVAL_SECS * 1000,
+        10_000,
+    )
+    .expect("couldn't start the DNS cache timer");
 
     log::trace!("ready to accept requests");
     loop {
@@ -619,12 +609,9 @@ fn xmain() -> ! {
                     }
                 }
             }
-            Some(Opcode::UpdateTtl) => msg_scalar_unpack!(msg, incr_secs, _, _, _, {
-                let increment = if incr_secs < u32::MAX as usize {
-                    incr_secs as u32
-                } else {
-                    u32::MAX
-                };
+            Some(Opcode::UpdateTtl) => msg_scalar_unpack!(msg, incr_secs, count, _, _, {
+                // the timer reports more than one interval at once if it fell behind
+                let increment = incr_secs.saturating_mul(count.max(1)).min(u32::MAX as usize) as u32;
                 if !resolver.get_freeze() {
                     let mut expired_names = Vec::<std::string::String>::new();
                     for (name, cache_map) in dns_cache.iter_mut() {
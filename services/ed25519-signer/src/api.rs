@@ -0,0 +1,93 @@
+pub(crate) const SERVER_NAME_ED25519: &str = "_Ed25519 signing service_";
+
+/// longest key label; labels become PDDB key names
+pub const MAX_LABEL_LEN: usize = 64;
+/// longest message that can be signed or verified in one go
+pub const MAX_MESSAGE_LEN: usize = 2048;
+/// most signatures in one batch verification
+pub const MAX_BATCH: usize = 16;
+/// total length of all the messages in one batch verification
+pub const MAX_BATCH_DATA: usize = 2048;
+
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+pub(crate) enum Opcode {
+    /// create a key under a new label, and return its public key
+    GenerateKey,
+    /// the public key for a label
+    PublicKey,
+    /// sign a message with the key under a label
+    Sign,
+    /// check one signature against a public key
+    Verify,
+    /// check several signatures at once
+    BatchVerify,
+    /// destroy the key under a label
+    DeleteKey,
+    /// exit the server
+    Quit,
+}
+
+#[allow(dead_code)] // `Ipc` is only produced by the library
+#[derive(Debug, Copy, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum SignerError {
+    /// the label is empty, too long, or has control characters in it
+    BadLabel,
+    /// there's no key under the label
+    NoSuchKey,
+    /// there's already a key under the label
+    KeyExists,
+    /// the message is longer than `MAX_MESSAGE_LEN`, or a batch is too big
+    TooLong,
+    /// `root-keys` wouldn't wrap or unwrap the key, usually because the user didn't unlock it
+    Locked,
+    /// the key couldn't be read from or written to the PDDB
+    Storage,
+    /// the stored key is damaged, or doesn't match its public key
+    Corrupt,
+    /// the request didn't make it to the server and back
+    Ipc,
+    /// the caller's token isn't held by any app, so it can't own keys
+    Unidentified,
+}
+
+/// Used by `GenerateKey`, `PublicKey` and `DeleteKey`
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct KeyRequest {
+    /// the caller's GAM token, which says whose keys these are
+    pub token: [u32; 4],
+    pub label: xous_ipc::String::</* MAX_LABEL_LEN */ 64>, // pending https://github.com/rust-lang/rust/issues/90195
+    pub public: [u8; 32],
+    pub result: Option<SignerError>,
+}
+
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct SignRequest {
+    pub token: [u32; 4],
+    pub label: xous_ipc::String::</* MAX_LABEL_LEN */ 64>, // pending https://github.com/rust-lang/rust/issues/90195
+    pub msg: [u8; MAX_MESSAGE_LEN],
+    pub len: u32,
+    pub signature: [u8; 64],
+    pub result: Option<SignerError>,
+}
+
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct VerifyRequest {
+    pub public: [u8; 32],
+    pub msg: [u8; MAX_MESSAGE_LEN],
+    pub len: u32,
+    pub signature: [u8; 64],
+    pub valid: bool,
+}
+
+/// The messages are packed end to end in `data`, in the order of `lens`
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct BatchVerifyRequest {
+    pub count: u32,
+    pub publics: [[u8; 32]; MAX_BATCH],
+    pub signatures: [[u8; 64]; MAX_BATCH],
+    pub lens: [u32; MAX_BATCH],
+    pub data: [u8; MAX_BATCH_DATA],
+    /// bit `n` is set if signature `n` is valid
+    pub valid: u32,
+    pub result: Option<SignerError>,
+}
@@ -0,0 +1,227 @@
+//! How keys are kept in the PDDB, and the signing and verification themselves.
+//!
+//! Each key is one PDDB key, named after its label, holding a version byte, the public key,
+//! and the 32-byte secret seed as wrapped by `root-keys`. The seed is only ever unwrapped for
+//! the length of a signing operation.
+//!
+//! Signing and verification follow RFC 8032. The scalar multiplications, which is where nearly
+//! all the time goes, are done by a `ScalarMul`: `engine-25519` on a device. Everything else
+//! (hashing, scalar arithmetic, decompressing the points that come back) is done in software.
+
+use curve25519_dalek::constants::ED25519_BASEPOINT_COMPRESSED;
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use curve25519_dalek::scalar::Scalar;
+use sha2::{Digest, Sha512};
+use zeroize::Zeroizing;
+
+const RECORD_VERSION: u8 = 1;
+/// AES-KWP adds 8 bytes to a 32-byte seed
+pub const WRAPPED_SEED_LEN: usize = 40;
+pub const RECORD_LEN: usize = 1 + 32 + WRAPPED_SEED_LEN;
+
+pub struct KeyRecord {
+    pub public: [u8; 32],
+    pub wrapped_seed: [u8; WRAPPED_SEED_LEN],
+}
+
+impl KeyRecord {
+    pub fn to_bytes(&self) -> [u8; RECORD_LEN] {
+        let mut record = [0u8; RECORD_LEN];
+        record[0] = RECORD_VERSION;
+        record[1..33].copy_from_slice(&self.public);
+        record[33..].copy_from_slice(&self.wrapped_seed);
+        record
+    }
+    pub fn from_bytes(record: &[u8]) -> Option<KeyRecord> {
+        if record.len() != RECORD_LEN || record[0] != RECORD_VERSION {
+            return None;
+        }
+        let mut public = [0u8; 32];
+        public.copy_from_slice(&record[1..33]);
+        let mut wrapped_seed = [0u8; WRAPPED_SEED_LEN];
+        wrapped_seed.copy_from_slice(&record[33..]);
+        Some(KeyRecord { public, wrapped_seed })
+    }
+}
+
+/// Multiplies points on the curve by scalars. Points are compressed Edwards Y, as in keys and
+/// signatures; scalars are little-endian, and less than 2^255.
+pub trait ScalarMul {
+    /// `[scalar]point`, or `None` if `point` doesn't decompress or the multiplication couldn't be done
+    fn mul(&mut self, scalar: &[u8; 32], point: &[u8; 32]) -> Option<[u8; 32]>;
+}
+
+/// Does the multiplications with `curve25519-dalek`, to check the engine against
+#[cfg(test)]
+pub struct Software;
+
+#[cfg(test)]
+impl ScalarMul for Software {
+    fn mul(&mut self, scalar: &[u8; 32], point: &[u8; 32]) -> Option<[u8; 32]> {
+        let point = CompressedEdwardsY(*point).decompress()?;
+        Some((Scalar::from_bits(*scalar) * point).compress().to_bytes())
+    }
+}
+
+/// The secret scalar, clamped, and the nonce prefix: the two halves of the seed's hash
+fn expand(seed: &[u8]) -> Option<Zeroizing<[u8; 64]>> {
+    if seed.len() != 32 {
+        return None;
+    }
+    let mut expanded = Zeroizing::new([0u8; 64]);
+    expanded.copy_from_slice(&Sha512::digest(seed));
+    expanded[0] &= 248;
+    expanded[31] &= 127;
+    expanded[31] |= 64;
+    Some(expanded)
+}
+
+fn secret_scalar(expanded: &[u8; 64]) -> Zeroizing<[u8; 32]> {
+    let mut scalar = Zeroizing::new([0u8; 32]);
+    scalar.copy_from_slice(&expanded[..32]);
+    scalar
+}
+
+pub fn public_from_seed(curve: &mut impl ScalarMul, seed: &[u8]) -> Option<[u8; 32]> {
+    let expanded = expand(seed)?;
+    curve.mul(&secret_scalar(&expanded), ED25519_BASEPOINT_COMPRESSED.as_bytes())
+}
+
+/// Signs `msg` with the key from `seed`. Returns `None` if the seed doesn't belong to `public`,
+/// so a damaged record can't produce signatures under the wrong key.
+pub fn sign(curve: &mut impl ScalarMul, seed: &[u8], public: &[u8; 32], msg: &[u8]) -> Option<[u8; 64]> {
+    let expanded = expand(seed)?;
+    let a = secret_scalar(&expanded);
+    if curve.mul(&a, ED25519_BASEPOINT_COMPRESSED.as_bytes())? != *public {
+        return None;
+    }
+    let r = Zeroizing::new(Scalar::from_hash(Sha512::new().chain(&expanded[32..]).chain(msg)));
+    let big_r = curve.mul(r.as_bytes(), ED25519_BASEPOINT_COMPRESSED.as_bytes())?;
+    let k = Scalar::from_hash(Sha512::new().chain(big_r).chain(public).chain(msg));
+    let s = k * Scalar::from_bits(*a) + *r;
+    let mut signature = [0u8; 64];
+    signature[..32].copy_from_slice(&big_r);
+    signature[32..].copy_from_slice(s.as_bytes());
+    Some(signature)
+}
+
+/// Strict verification, as `verify_strict` in `ed25519-dalek`: rejects small-order keys and
+/// non-canonical signatures
+pub fn verify(curve: &mut impl ScalarMul, public: &[u8; 32], msg: &[u8], signature: &[u8; 64]) -> bool {
+    let mut big_r = [0u8; 32];
+    big_r.copy_from_slice(&signature[..32]);
+    let mut s = [0u8; 32];
+    s.copy_from_slice(&signature[32..]);
+    let s = match Scalar::from_canonical_bytes(s) {
+        Some(s) => s,
+        None => return false,
+    };
+    let (public_point, r_point) = match (CompressedEdwardsY(*public).decompress(), CompressedEdwardsY(big_r).decompress()) {
+        (Some(a), Some(r)) => (a, r),
+        _ => return false,
+    };
+    if public_point.is_small_order() || r_point.is_small_order() {
+        return false;
+    }
+    let k = Scalar::from_hash(Sha512::new().chain(big_r).chain(public).chain(msg));
+    // [s]B - [k]A has to come out as R
+    let sb = curve.mul(s.as_bytes(), ED25519_BASEPOINT_COMPRESSED.as_bytes()).and_then(|p| CompressedEdwardsY(p).decompress());
+    let ka = curve.mul(k.as_bytes(), public).and_then(|p| CompressedEdwardsY(p).decompress());
+    match (sb, ka) {
+        (Some(sb), Some(ka)) => sb - ka == r_point,
+        _ => false,
+    }
+}
+
+/// Labels name PDDB keys, so they're limited to printable text that fits in a key name
+pub fn label_ok(label: &str) -> bool {
+    !label.is_empty() && label.len() <= crate::api::MAX_LABEL_LEN && label.chars().all(|c| !c.is_control())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    /// the order of the base point, little-endian
+    const ORDER: [u8; 32] = [
+        0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10,
+    ];
+
+    /// RFC 8032, section 7.1, tests 1 and 2
+    #[test]
+    fn rfc8032_vectors() {
+        let cases = [
+            (
+                "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+                "",
+                "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+            ),
+            (
+                "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+                "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+                "72",
+                "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+            ),
+        ];
+        for (seed, public, msg, signature) in cases.iter() {
+            let seed = unhex(seed);
+            let mut pk = [0u8; 32];
+            pk.copy_from_slice(&unhex(public));
+            let msg = unhex(msg);
+            let mut sig = [0u8; 64];
+            sig.copy_from_slice(&unhex(signature));
+
+            assert_eq!(public_from_seed(&mut Software, &seed), Some(pk));
+            assert_eq!(sign(&mut Software, &seed, &pk, &msg).unwrap()[..], sig[..]);
+            assert!(verify(&mut Software, &pk, &msg, &sig));
+            let mut tampered = sig;
+            tampered[5] ^= 1;
+            assert!(!verify(&mut Software, &pk, &msg, &tampered));
+            assert!(!verify(&mut Software, &pk, b"something else", &sig));
+            // a seed that doesn't match the stored public key is refused
+            assert!(sign(&mut Software, &[7u8; 32], &pk, &msg).is_none());
+            // s + l is the same signature, but not in canonical form
+            let mut malleated = sig;
+            let mut carry = 0u16;
+            for (b, l) in malleated[32..].iter_mut().zip(ORDER.iter()) {
+                let sum = *b as u16 + *l as u16 + carry;
+                *b = sum as u8;
+                carry = sum >> 8;
+            }
+            assert!(!verify(&mut Software, &pk, &msg, &malleated));
+        }
+    }
+
+    #[test]
+    fn small_order_keys() {
+        // the identity signs anything, with R = identity and s = 0
+        let mut identity = [0u8; 32];
+        identity[0] = 1;
+        let mut sig = [0u8; 64];
+        sig[..32].copy_from_slice(&identity);
+        assert!(!verify(&mut Software, &identity, b"anything", &sig));
+    }
+
+    #[test]
+    fn records() {
+        let record = KeyRecord { public: [1; 32], wrapped_seed: [2; WRAPPED_SEED_LEN] };
+        let bytes = record.to_bytes();
+        let parsed = KeyRecord::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.public, [1; 32]);
+        assert_eq!(parsed.wrapped_seed, [2; WRAPPED_SEED_LEN]);
+        assert!(KeyRecord::from_bytes(&bytes[1..]).is_none());
+        let mut future = bytes;
+        future[0] = RECORD_VERSION + 1;
+        assert!(KeyRecord::from_bytes(&future).is_none());
+
+        assert!(label_ok("messenger.identity"));
+        assert!(!label_ok(""));
+        assert!(!label_ok("tab\there"));
+    }
+}
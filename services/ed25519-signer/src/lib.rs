@@ -0,0 +1,161 @@
+#![cfg_attr(target_os = "none", no_std)]
+
+//! Detailed docs are parked under Structs/Ed25519Signer down below
+
+pub mod api;
+pub use api::*;
+use xous::CID;
+use xous_ipc::Buffer;
+use num_traits::*;
+
+#[doc = include_str!("../README.md")]
+pub struct Ed25519Signer {
+    conn: CID,
+    token: [u32; 4],
+}
+impl Ed25519Signer {
+    /// `token` is the one the GAM gave the app when it registered its UX; keys belong to the app it
+    /// was issued to.
+    pub fn new(xns: &xous_names::XousNames, token: [u32; 4]) -> Result<Self, xous::Error> {
+        REFCOUNT.fetch_add(1, Ordering::Relaxed);
+        let conn = xns.request_connection_blocking(api::SERVER_NAME_ED25519).expect("Can't connect to Ed25519 signing server");
+        Ok(Ed25519Signer {
+            conn,
+            token,
+        })
+    }
+
+    fn key_op(&self, op: Opcode, label: &str) -> Result<[u8; 32], SignerError> {
+        if label.len() > MAX_LABEL_LEN {
+            return Err(SignerError::BadLabel);
+        }
+        let request = KeyRequest {
+            token: self.token,
+            label: xous_ipc::String::from_str(label),
+            public: [0; 32],
+            result: Some(SignerError::Ipc),
+        };
+        let mut buf = Buffer::into_buf(request).or(Err(SignerError::Ipc))?;
+        buf.lend_mut(self.conn, op.to_u32().unwrap()).or(Err(SignerError::Ipc))?;
+        let response = buf.to_original::<KeyRequest, _>().or(Err(SignerError::Ipc))?;
+        match response.result {
+            None => Ok(response.public),
+            Some(e) => Err(e),
+        }
+    }
+
+    /// Creates a new key under `label`, and returns its public key. The secret half is wrapped by
+    /// `root-keys` before it's stored, so the user may be asked for their password.
+    pub fn generate_key(&self, label: &str) -> Result<[u8; 32], SignerError> {
+        self.key_op(Opcode::GenerateKey, label)
+    }
+
+    pub fn public_key(&self, label: &str) -> Result<[u8; 32], SignerError> {
+        self.key_op(Opcode::PublicKey, label)
+    }
+
+    /// Destroys the key under `label`. Signatures made with it can still be verified with its public key.
+    pub fn delete_key(&self, label: &str) -> Result<(), SignerError> {
+        self.key_op(Opcode::DeleteKey, label).map(|_| ())
+    }
+
+    /// Signs `msg` with the key under `label`. The user may be asked for their password, to unwrap the key.
+    pub fn sign(&self, label: &str, msg: &[u8]) -> Result<[u8; 64], SignerError> {
+        if label.len() > MAX_LABEL_LEN {
+            return Err(SignerError::BadLabel);
+        }
+        if msg.len() > MAX_MESSAGE_LEN {
+            return Err(SignerError::TooLong);
+        }
+        let mut request = SignRequest {
+            token: self.token,
+            label: xous_ipc::String::from_str(label),
+            msg: [0; MAX_MESSAGE_LEN],
+            len: msg.len() as u32,
+            signature: [0; 64],
+            result: Some(SignerError::Ipc),
+        };
+        request.msg[..msg.len()].copy_from_slice(msg);
+        let mut buf = Buffer::into_buf(request).or(Err(SignerError::Ipc))?;
+        buf.lend_mut(self.conn, Opcode::Sign.to_u32().unwrap()).or(Err(SignerError::Ipc))?;
+        let response = buf.to_original::<SignRequest, _>().or(Err(SignerError::Ipc))?;
+        match response.result {
+            None => Ok(response.signature),
+            Some(e) => Err(e),
+        }
+    }
+
+    /// Checks `signature` over `msg` against `public`. Verification is strict: small-order keys and
+    /// non-canonical signatures are rejected.
+    pub fn verify(&self, public: &[u8; 32], msg: &[u8], signature: &[u8; 64]) -> Result<bool, SignerError> {
+        if msg.len() > MAX_MESSAGE_LEN {
+            return Err(SignerError::TooLong);
+        }
+        let mut request = VerifyRequest {
+            public: *public,
+            msg: [0; MAX_MESSAGE_LEN],
+            len: msg.len() as u32,
+            signature: *signature,
+            valid: false,
+        };
+        request.msg[..msg.len()].copy_from_slice(msg);
+        let mut buf = Buffer::into_buf(request).or(Err(SignerError::Ipc))?;
+        buf.lend_mut(self.conn, Opcode::Verify.to_u32().unwrap()).or(Err(SignerError::Ipc))?;
+        let response = buf.to_original::<VerifyRequest, _>().or(Err(SignerError::Ipc))?;
+        Ok(response.valid)
+    }
+
+    /// Checks up to `MAX_BATCH` `(public key, message, signature)` triples in one round trip, and
+    /// returns whether each one is valid. The messages can total at most `MAX_BATCH_DATA` bytes.
+    pub fn verify_batch(&self, batch: &[(&[u8; 32], &[u8], &[u8; 64])]) -> Result<Vec<bool>, SignerError> {
+        if batch.len() > MAX_BATCH || batch.iter().map(|(_, m, _)| m.len()).sum::<usize>() > MAX_BATCH_DATA {
+            return Err(SignerError::TooLong);
+        }
+        let mut request = BatchVerifyRequest {
+            count: batch.len() as u32,
+            publics: [[0; 32]; MAX_BATCH],
+            signatures: [[0; 64]; MAX_BATCH],
+            lens: [0; MAX_BATCH],
+            data: [0; MAX_BATCH_DATA],
+            valid: 0,
+            result: Some(SignerError::Ipc),
+        };
+        let mut offset = 0;
+        for (i, (public, msg, signature)) in batch.iter().enumerate() {
+            request.publics[i] = **public;
+            request.signatures[i] = **signature;
+            request.lens[i] = msg.len() as u32;
+            request.data[offset..offset + msg.len()].copy_from_slice(msg);
+            offset += msg.len();
+        }
+        let mut buf = Buffer::into_buf(request).or(Err(SignerError::Ipc))?;
+        buf.lend_mut(self.conn, Opcode::BatchVerify.to_u32().unwrap()).or(Err(SignerError::Ipc))?;
+        let response = buf.to_original::<BatchVerifyRequest, _>().or(Err(SignerError::Ipc))?;
+        match response.result {
+            None => Ok((0..batch.len()).map(|i| response.valid & (1 << i) != 0).collect()),
+            Some(e) => Err(e),
+        }
+    }
+}
+
+// the keys never leave the server, but the token is as good as the app's identity, so keep it out of logs
+impl core::fmt::Debug for Ed25519Signer {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("Ed25519Signer").field("conn", &self.conn).finish()
+    }
+}
+
+use core::sync::atomic::{AtomicU32, Ordering};
+static REFCOUNT: AtomicU32 = AtomicU32::new(0);
+impl Drop for Ed25519Signer {
+    fn drop(&mut self) {
+        // the connection to the server side must be reference counted, so that multiple instances of this object within
+        // a single process do not end up de-allocating the CID on other threads before they go out of scope.
+        // Note to future me: you want this. Don't get rid of it because you think, "nah, nobody will ever make more than one copy of this object".
+        if REFCOUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
+            unsafe{xous::disconnect(self.conn).unwrap();}
+        }
+        // if there was object-specific state (such as a one-time use server for async callbacks, specific to the object instance),
+        // de-allocate those items here. They don't need a reference count because they are object-specific
+    }
+}
@@ -0,0 +1,227 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+mod api;
+use api::*;
+mod keys;
+mod store;
+
+use num_traits::*;
+use xous_ipc::Buffer;
+use std::io::{Read, Write};
+use zeroize::Zeroize;
+
+/// each owner's keys live in a dictionary of their own, named this plus `.` and the owner
+const KEY_DICT: &str = "ed25519.keys";
+
+/// Keys in the PDDB, with seeds wrapped by `root-keys`
+struct PddbVault {
+    pddb: pddb::Pddb,
+    rootkeys: root_keys::RootKeys,
+    trng: trng::Trng,
+    mounted: bool,
+}
+
+impl PddbVault {
+    /// Keys can't be touched until the PDDB is up; the first request waits for it, rather than boot.
+    fn ensure_mounted(&mut self) {
+        if !self.mounted {
+            self.pddb.is_mounted_blocking(None);
+            self.mounted = true;
+        }
+    }
+}
+
+fn dict(owner: &str) -> String {
+    format!("{}.{}", KEY_DICT, owner)
+}
+
+impl store::Vault for PddbVault {
+    fn read(&mut self, owner: &str, label: &str) -> Result<Option<Vec<u8>>, SignerError> {
+        self.ensure_mounted();
+        let mut key = match self.pddb.get(&dict(owner), label, None, false, false, None, None::<fn()>) {
+            Ok(key) => key,
+            Err(_) => return Ok(None),
+        };
+        let mut record = Vec::new();
+        key.read_to_end(&mut record).or(Err(SignerError::Storage))?;
+        Ok(Some(record))
+    }
+
+    fn write(&mut self, owner: &str, label: &str, record: &[u8]) -> Result<(), SignerError> {
+        self.ensure_mounted();
+        let mut key = self.pddb.get(&dict(owner), label, None, true, true, Some(record.len()), None::<fn()>)
+            .or(Err(SignerError::Storage))?;
+        key.write_all(record).or(Err(SignerError::Storage))?;
+        self.pddb.sync().or(Err(SignerError::Storage))
+    }
+
+    fn delete(&mut self, owner: &str, label: &str) -> Result<(), SignerError> {
+        self.ensure_mounted();
+        self.pddb.delete_key(&dict(owner), label, None).or(Err(SignerError::Storage))?;
+        self.pddb.sync().or(Err(SignerError::Storage))
+    }
+
+    fn random_seed(&mut self) -> Result<[u8; 32], SignerError> {
+        let mut words = [0u32; 8];
+        self.trng.fill_buf(&mut words).or(Err(SignerError::Ipc))?;
+        let mut seed = [0u8; 32];
+        for (dst, src) in seed.chunks_exact_mut(4).zip(words.iter()) {
+            dst.copy_from_slice(&src.to_le_bytes());
+        }
+        words.zeroize();
+        Ok(seed)
+    }
+
+    fn wrap(&mut self, seed: &[u8]) -> Result<Vec<u8>, SignerError> {
+        self.rootkeys.wrap_key(seed).or(Err(SignerError::Locked))
+    }
+
+    fn unwrap(&mut self, wrapped: &[u8]) -> Result<Vec<u8>, SignerError> {
+        self.rootkeys.unwrap_key(wrapped, 32).or(Err(SignerError::Locked))
+    }
+}
+
+/// Runs the scalar multiplications on `engine-25519`
+struct Engine(engine_25519::Engine25519);
+
+impl keys::ScalarMul for Engine {
+    fn mul(&mut self, scalar: &[u8; 32], point: &[u8; 32]) -> Option<[u8; 32]> {
+        match self.0.edwards_job(engine_25519::EdwardsJob { point: *point, scalar: *scalar }) {
+            Ok(product) => product,
+            Err(e) => {
+                log::error!("engine-25519 couldn't do a scalar multiplication: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+/// The name the caller's keys are kept under: that of the app its GAM token was issued to
+fn owner(gam: &gam::Gam, token: [u32; 4]) -> Result<String, SignerError> {
+    gam.token_owner(token).ok().flatten().ok_or(SignerError::Unidentified)
+}
+
+fn verify_batch(curve: &mut Engine, request: &BatchVerifyRequest) -> Result<u32, SignerError> {
+    let count = request.count as usize;
+    if count > MAX_BATCH {
+        return Err(SignerError::TooLong);
+    }
+    let mut valid = 0;
+    let mut offset = 0;
+    for i in 0..count {
+        let len = request.lens[i] as usize;
+        let msg = request.data.get(offset..offset + len).ok_or(SignerError::TooLong)?;
+        if keys::verify(curve, &request.publics[i], msg, &request.signatures[i]) {
+            valid |= 1 << i;
+        }
+        offset += len;
+    }
+    Ok(valid)
+}
+
+#[xous::xous_main]
+fn xmain() -> ! {
+    log_server::init_wait().unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+    log::info!("my PID is {}", xous::process::id());
+
+    let xns = xous_names::XousNames::new().unwrap();
+    let signer_sid = xns.register_name(api::SERVER_NAME_ED25519, None).expect("can't register server");
+    log::trace!("registered with NS -- {:?}", signer_sid);
+
+    let gam = gam::Gam::new(&xns).expect("couldn't connect to GAM");
+    let mut signer = store::KeyStore {
+        vault: PddbVault {
+            pddb: pddb::Pddb::new(),
+            rootkeys: root_keys::RootKeys::new(&xns, Some(root_keys::api::AesRootkeyType::User0)).expect("couldn't connect to root keys"),
+            trng: trng::Trng::new(&xns).expect("couldn't connect to the TRNG"),
+            mounted: false,
+        },
+        curve: Engine(engine_25519::Engine25519::new()),
+    };
+
+    log::trace!("ready to accept requests");
+    loop {
+        let mut msg = xous::receive_message(signer_sid).unwrap();
+        log::trace!("Message: {:?}", msg);
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(Opcode::GenerateKey) | Some(Opcode::PublicKey) | Some(Opcode::DeleteKey) => {
+                let op: Option<Opcode> = FromPrimitive::from_usize(msg.body.id());
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut request = buffer.to_original::<KeyRequest, _>().unwrap();
+                let label = request.label.as_str().unwrap_or("").to_string();
+                let result = owner(&gam, request.token).and_then(|owner| match op {
+                    Some(Opcode::GenerateKey) => signer.generate(&owner, &label),
+                    Some(Opcode::PublicKey) => signer.record(&owner, &label).map(|r| r.public),
+                    _ => signer.delete(&owner, &label).map(|_| [0u8; 32]),
+                });
+                match result {
+                    Ok(public) => {
+                        request.public = public;
+                        request.result = None;
+                    }
+                    Err(e) => {
+                        log::debug!("{:?} on {} failed: {:?}", op, label, e);
+                        request.result = Some(e);
+                    }
+                }
+                buffer.replace(request).unwrap();
+            }
+            Some(Opcode::Sign) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut request = buffer.to_original::<SignRequest, _>().unwrap();
+                let label = request.label.as_str().unwrap_or("").to_string();
+                let len = request.len as usize;
+                let result = if len > MAX_MESSAGE_LEN {
+                    Err(SignerError::TooLong)
+                } else {
+                    owner(&gam, request.token).and_then(|owner| signer.sign(&owner, &label, &request.msg[..len]))
+                };
+                match result {
+                    Ok(signature) => {
+                        request.signature = signature;
+                        request.result = None;
+                    }
+                    Err(e) => {
+                        log::debug!("signing with {} failed: {:?}", label, e);
+                        request.result = Some(e);
+                    }
+                }
+                buffer.replace(request).unwrap();
+            }
+            Some(Opcode::Verify) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut request = buffer.to_original::<VerifyRequest, _>().unwrap();
+                let len = request.len as usize;
+                request.valid = len <= MAX_MESSAGE_LEN && keys::verify(&mut signer.curve, &request.public, &request.msg[..len], &request.signature);
+                buffer.replace(request).unwrap();
+            }
+            Some(Opcode::BatchVerify) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut request = buffer.to_original::<BatchVerifyRequest, _>().unwrap();
+                match verify_batch(&mut signer.curve, &request) {
+                    Ok(valid) => {
+                        request.valid = valid;
+                        request.result = None;
+                    }
+                    Err(e) => request.result = Some(e),
+                }
+                buffer.replace(request).unwrap();
+            }
+            Some(Opcode::Quit) => {
+                log::warn!("Quit received, goodbye world!");
+                break;
+            }
+            None => {
+                log::error!("couldn't convert opcode: {:?}", msg);
+            }
+        }
+    }
+    // clean up our program
+    log::trace!("main loop exit, destroying servers");
+    xns.unregister_server(signer_sid).unwrap();
+    xous::destroy_server(signer_sid).unwrap();
+    log::trace!("quitting");
+    xous::terminate_process(0)
+}
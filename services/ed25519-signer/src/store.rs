@@ -0,0 +1,146 @@
+//! Key handling, scoped by owner.
+//!
+//! Every key belongs to the app that made it, and a label only means something within its
+//! owner's keys: two apps can each have a key called `identity`, and neither can sign with, look
+//! up or delete the other's. The owner is the name of the app whose GAM token the caller presents;
+//! see `Gam::token_owner()`.
+
+use crate::api::SignerError;
+use crate::keys::{self, KeyRecord, ScalarMul};
+use zeroize::Zeroize;
+
+/// Where keys are kept and how their seeds are wrapped: the PDDB and `root-keys` on a device
+pub(crate) trait Vault {
+    /// The record stored under `label` for `owner`, if there is one
+    fn read(&mut self, owner: &str, label: &str) -> Result<Option<Vec<u8>>, SignerError>;
+    fn write(&mut self, owner: &str, label: &str, record: &[u8]) -> Result<(), SignerError>;
+    fn delete(&mut self, owner: &str, label: &str) -> Result<(), SignerError>;
+    fn random_seed(&mut self) -> Result<[u8; 32], SignerError>;
+    fn wrap(&mut self, seed: &[u8]) -> Result<Vec<u8>, SignerError>;
+    fn unwrap(&mut self, wrapped: &[u8]) -> Result<Vec<u8>, SignerError>;
+}
+
+pub(crate) struct KeyStore<V: Vault, C: ScalarMul> {
+    pub vault: V,
+    pub curve: C,
+}
+
+impl<V: Vault, C: ScalarMul> KeyStore<V, C> {
+    pub fn record(&mut self, owner: &str, label: &str) -> Result<KeyRecord, SignerError> {
+        if !keys::label_ok(label) {
+            return Err(SignerError::BadLabel);
+        }
+        let record = self.vault.read(owner, label)?.ok_or(SignerError::NoSuchKey)?;
+        KeyRecord::from_bytes(&record).ok_or(SignerError::Corrupt)
+    }
+
+    pub fn generate(&mut self, owner: &str, label: &str) -> Result<[u8; 32], SignerError> {
+        match self.record(owner, label) {
+            Err(SignerError::NoSuchKey) => (),
+            Ok(_) | Err(SignerError::Corrupt) => return Err(SignerError::KeyExists),
+            Err(e) => return Err(e),
+        }
+        let mut seed = self.vault.random_seed()?;
+        let public = keys::public_from_seed(&mut self.curve, &seed);
+        let wrapped = self.vault.wrap(&seed);
+        seed.zeroize();
+        let public = public.ok_or(SignerError::Corrupt)?;
+        let wrapped = wrapped?;
+        if wrapped.len() != keys::WRAPPED_SEED_LEN {
+            log::error!("key wrapper returned {} bytes, expected {}", wrapped.len(), keys::WRAPPED_SEED_LEN);
+            return Err(SignerError::Corrupt);
+        }
+        let mut wrapped_seed = [0u8; keys::WRAPPED_SEED_LEN];
+        wrapped_seed.copy_from_slice(&wrapped);
+        self.vault.write(owner, label, &KeyRecord { public, wrapped_seed }.to_bytes())?;
+        log::info!("generated key {} for {}", label, owner);
+        Ok(public)
+    }
+
+    pub fn sign(&mut self, owner: &str, label: &str, msg: &[u8]) -> Result<[u8; 64], SignerError> {
+        let record = self.record(owner, label)?;
+        let mut seed = self.vault.unwrap(&record.wrapped_seed)?;
+        let signature = keys::sign(&mut self.curve, &seed, &record.public, msg);
+        seed.zeroize();
+        signature.ok_or(SignerError::Corrupt)
+    }
+
+    pub fn delete(&mut self, owner: &str, label: &str) -> Result<(), SignerError> {
+        self.record(owner, label)?;
+        self.vault.delete(owner, label)?;
+        log::info!("deleted key {} for {}", label, owner);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Keeps records in memory, and "wraps" seeds by padding them out
+    #[derive(Default)]
+    struct TestVault {
+        records: HashMap<(String, String), Vec<u8>>,
+        next_seed: u8,
+    }
+    impl Vault for TestVault {
+        fn read(&mut self, owner: &str, label: &str) -> Result<Option<Vec<u8>>, SignerError> {
+            Ok(self.records.get(&(owner.to_string(), label.to_string())).cloned())
+        }
+        fn write(&mut self, owner: &str, label: &str, record: &[u8]) -> Result<(), SignerError> {
+            self.records.insert((owner.to_string(), label.to_string()), record.to_vec());
+            Ok(())
+        }
+        fn delete(&mut self, owner: &str, label: &str) -> Result<(), SignerError> {
+            self.records.remove(&(owner.to_string(), label.to_string()));
+            Ok(())
+        }
+        fn random_seed(&mut self) -> Result<[u8; 32], SignerError> {
+            self.next_seed += 1;
+            Ok([self.next_seed; 32])
+        }
+        fn wrap(&mut self, seed: &[u8]) -> Result<Vec<u8>, SignerError> {
+            let mut wrapped = seed.to_vec();
+            wrapped.resize(keys::WRAPPED_SEED_LEN, 0);
+            Ok(wrapped)
+        }
+        fn unwrap(&mut self, wrapped: &[u8]) -> Result<Vec<u8>, SignerError> {
+            Ok(wrapped[..32].to_vec())
+        }
+    }
+
+    fn store() -> KeyStore<TestVault, keys::Software> {
+        KeyStore { vault: TestVault::default(), curve: keys::Software }
+    }
+
+    #[test]
+    fn keys_are_private_to_their_owner() {
+        let mut s = store();
+        let public = s.generate("messenger", "identity").unwrap();
+        let signature = s.sign("messenger", "identity", b"hello").unwrap();
+        assert!(keys::verify(&mut s.curve, &public, b"hello", &signature));
+
+        // another process can't sign with it, see it, or delete it, even knowing the label
+        assert_eq!(s.sign("wallet", "identity", b"hello"), Err(SignerError::NoSuchKey));
+        assert_eq!(s.record("wallet", "identity").err(), Some(SignerError::NoSuchKey));
+        assert_eq!(s.delete("wallet", "identity"), Err(SignerError::NoSuchKey));
+        assert_eq!(s.sign("messenger", "identity", b"hello"), Ok(signature));
+
+        // and the same label is free for it to use with a key of its own
+        let other = s.generate("wallet", "identity").unwrap();
+        assert_ne!(other, public);
+        assert_eq!(s.generate("messenger", "identity"), Err(SignerError::KeyExists));
+
+        s.delete("messenger", "identity").unwrap();
+        assert_eq!(s.sign("messenger", "identity", b"hello"), Err(SignerError::NoSuchKey));
+        assert!(s.sign("wallet", "identity", b"hello").is_ok());
+    }
+
+    #[test]
+    fn bad_labels() {
+        let mut s = store();
+        assert_eq!(s.generate("messenger", ""), Err(SignerError::BadLabel));
+        assert_eq!(s.sign("messenger", "a\nb", b""), Err(SignerError::BadLabel));
+    }
+}
@@ -20,7 +20,6 @@ pub(crate) const RF_TOTAL_U8_SIZE: usize = 0x4000;
 
 pub(crate) const NUM_REGS: usize = 32;
 pub(crate) const BITWIDTH: usize = 256;
-#[allow(dead_code)] // not used in hosted
 pub(crate) const NUM_WINDOWS: usize = 16;
 pub const RF_SIZE_IN_U32: usize = NUM_REGS*(BITWIDTH/32); // 32 registers, 256 bits/register/32 bits per u32
 #[allow(dead_code)] // not used in hosted
@@ -53,6 +52,16 @@ pub struct MontgomeryJob {
     pub scalar: [u8; 32],
 }
 
+/// `[scalar]point` on the Edwards form of Curve25519, which is what Ed25519 signing and
+/// verification spend their time on
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Clone, Copy)]
+pub struct EdwardsJob {
+    /// compressed Edwards Y, the encoding of Ed25519 public keys and signatures
+    pub point: [u8; 32],
+    /// little-endian; bit 255 is ignored
+    pub scalar: [u8; 32],
+}
+
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
 pub(crate) enum Opcode {
     /// Runs a job, if the server is not already occupied
@@ -61,9 +70,18 @@ pub(crate) enum Opcode {
     /// MontgomeryJob
     MontgomeryJob,
 
-    /// a function that can be polled to determine if the block has been currently acquired
+    /// EdwardsJob
+    EdwardsJob,
+
+    /// a function that can be polled to determine if the engine is idle, with no async jobs queued
     IsFree,
 
+    /// depth and client counts for the async job queue
+    QueueStatus,
+
+    /// drop a queued async job that hasn't started
+    CancelJob,
+
     /// IRQ handler feedback
     EngineDone,
     IllegalOpcode,
@@ -77,6 +95,43 @@ pub(crate) enum Opcode {
     // interrupt the main thread and store the state
 }
 
+/// A snapshot of the async job queue
+#[derive(Debug, Default, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Clone, Copy)]
+pub struct QueueStatus {
+    /// filled in by the caller: the callback SID to report `pending` for
+    pub id: Option<[u32; 4]>,
+    /// jobs waiting to run, across all clients
+    pub depth: u32,
+    /// jobs waiting to run for `id`
+    pub pending: u32,
+    /// an async job is on the engine
+    pub running: bool,
+    /// clients with jobs outstanding; each one holds a register window
+    pub clients: u32,
+    /// register windows that can still be leased to new clients
+    pub free_windows: u32,
+    /// totals since boot
+    pub completed: u64,
+    pub cancelled: u64,
+    pub rejected: u64,
+}
+
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Clone, Copy)]
+pub(crate) struct CancelRequest {
+    /// the callback SID the job was queued with, which proves ownership of the job
+    pub id: [u32; 4],
+    pub ticket: u32,
+    /// set by the server
+    pub cancelled: bool,
+}
+
+/// Sent to an async job's callback SID when it finishes
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct Completion {
+    pub ticket: u32,
+    pub result: JobResult,
+}
+
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
 pub(crate) enum Return {
     Result,
@@ -90,10 +145,14 @@ pub enum JobResult {
     Result([u32; RF_SIZE_IN_U32]),
     SingleResult([u8; 32]),
     Started,
+    /// an async job was queued; its result will be sent with this ticket
+    Queued(u32),
     EngineUnavailable,
     NotAsyncObject, // attempt to run an async job on an object that was setup for sync jobs
     IllegalOpcodeException,
     SuspendError,
+    /// the point of an `EdwardsJob` doesn't decompress
+    InvalidPoint,
 }
 
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
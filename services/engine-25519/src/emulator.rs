@@ -0,0 +1,475 @@
+//! A software model of the Curve25519 engine, used in hosted mode so that `Job`s,
+//! `MontgomeryJob`s and `EdwardsJob`s give the same results they would on hardware.
+//!
+//! It models the microcode memory, the 16 register windows of 32 x 256-bit registers, and the
+//! instruction set as described in the engine datasheet. Instructions are 32 bits:
+//!
+//! | 31..23    | 22..18 | 17 | 16..12 | 11 | 10..6 | 5..0   |
+//! |-----------|--------|----|--------|----|-------|--------|
+//! | immediate | wd     | cb | rb     | ca | ra    | opcode |
+//!
+//! `ca`/`cb` select an entry of the constant ROM in place of register `ra`/`rb`. Timing is
+//! not modelled: a job runs to completion as soon as it's started.
+
+use crate::api::{NUM_REGS, NUM_WINDOWS, RF_SIZE_IN_U32, UCODE_U32_SIZE};
+
+/// A 256-bit register, as eight little-endian words (the same layout as the register file)
+type Reg = [u32; 8];
+
+/// 2^255 - 19
+const FIELD_PRIME: Reg = [
+    0xffff_ffed, 0xffff_ffff, 0xffff_ffff, 0xffff_ffff,
+    0xffff_ffff, 0xffff_ffff, 0xffff_ffff, 0x7fff_ffff,
+];
+
+/// A runaway program is stopped after this many instructions. Real hardware would spin
+/// forever; a hosted server is better off reporting it. An X25519 ladder is ~17k instructions.
+const MAX_STEPS: usize = 1 << 24;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Op {
+    /// Wd <- Ra
+    Psa = 0,
+    /// Wd <- Rb
+    Psb = 1,
+    /// Wd <- Ra[0] ? Rb : 0, for constant-time swaps
+    Msk = 2,
+    /// Wd <- Ra ^ Rb
+    Xor = 3,
+    /// Wd <- ~Ra
+    Not = 4,
+    /// Wd <- Ra + Rb, 256-bit binary add; follow with TRD, SUB to reduce
+    Add = 5,
+    /// Wd <- Ra - Rb, 256-bit binary subtract
+    Sub = 6,
+    /// Wd <- Ra * Rb, in GF(2^255-19), fully reduced
+    Mul = 7,
+    /// Wd <- (Ra >= 2^255-19) ? 2^255-19 : 0, a test for reduction
+    Trd = 8,
+    /// if Ra == 0, mpc <- mpc + immediate + 1
+    Brz = 9,
+    /// stop, and signal completion
+    Fin = 10,
+    /// Wd <- Ra << 1
+    Shl = 11,
+    /// Wd <- Ra[254], in bit 0
+    Xbt = 12,
+}
+
+impl Op {
+    fn decode(code: u32) -> Option<Op> {
+        Some(match code {
+            0 => Op::Psa,
+            1 => Op::Psb,
+            2 => Op::Msk,
+            3 => Op::Xor,
+            4 => Op::Not,
+            5 => Op::Add,
+            6 => Op::Sub,
+            7 => Op::Mul,
+            8 => Op::Trd,
+            9 => Op::Brz,
+            10 => Op::Fin,
+            11 => Op::Shl,
+            12 => Op::Xbt,
+            _ => return None,
+        })
+    }
+}
+
+/// The constant ROM, addressed by `ra`/`rb` when `ca`/`cb` is set
+fn constant(index: usize) -> Reg {
+    let small = |v: u32| [v, 0, 0, 0, 0, 0, 0, 0];
+    match index {
+        0 => small(0),
+        1 => small(1),
+        2 => small(121665), // (A - 2) / 4
+        3 => FIELD_PRIME,
+        4 => small(121666), // (A + 2) / 4
+        5 => small(5),
+        6 => small(10),
+        7 => small(20),
+        8 => small(50),
+        9 => small(100),
+        _ => small(0),
+    }
+}
+
+fn add(a: &Reg, b: &Reg) -> Reg {
+    let mut r = [0u32; 8];
+    let mut carry = 0u64;
+    for i in 0..8 {
+        let s = a[i] as u64 + b[i] as u64 + carry;
+        r[i] = s as u32;
+        carry = s >> 32;
+    }
+    r
+}
+
+fn sub(a: &Reg, b: &Reg) -> Reg {
+    let mut r = [0u32; 8];
+    let mut borrow = 0i64;
+    for i in 0..8 {
+        let d = a[i] as i64 - b[i] as i64 - borrow;
+        r[i] = d as u32;
+        borrow = if d < 0 { 1 } else { 0 };
+    }
+    r
+}
+
+fn geq(a: &Reg, b: &Reg) -> bool {
+    for i in (0..8).rev() {
+        if a[i] != b[i] {
+            return a[i] > b[i];
+        }
+    }
+    true
+}
+
+fn mul(a: &Reg, b: &Reg) -> Reg {
+    // schoolbook product in 32-bit limbs
+    let mut wide = [0u64; 16];
+    for i in 0..8 {
+        let mut carry = 0u64;
+        for j in 0..8 {
+            let t = wide[i + j] + (a[i] as u64) * (b[j] as u64) + carry;
+            wide[i + j] = t & 0xffff_ffff;
+            carry = t >> 32;
+        }
+        wide[i + 8] = carry;
+    }
+    // 2^256 = 38 (mod p): lo + 38 * hi fits in nine limbs
+    let mut folded = [0u64; 9];
+    let mut carry = 0u64;
+    for i in 0..8 {
+        let t = wide[i] + 38 * wide[i + 8] + carry;
+        folded[i] = t & 0xffff_ffff;
+        carry = t >> 32;
+    }
+    folded[8] = carry;
+    // 2^255 = 19 (mod p): twice is enough to get below 2^255 + 19
+    for _ in 0..2 {
+        let top = (folded[7] >> 31) + (folded[8] << 1);
+        folded[7] &= 0x7fff_ffff;
+        folded[8] = 0;
+        let mut carry = top * 19;
+        for limb in folded.iter_mut() {
+            let t = *limb + carry;
+            *limb = t & 0xffff_ffff;
+            carry = t >> 32;
+        }
+    }
+    let mut r = [0u32; 8];
+    for (d, &s) in r.iter_mut().zip(folded.iter()) {
+        *d = s as u32;
+    }
+    if geq(&r, &FIELD_PRIME) {
+        r = sub(&r, &FIELD_PRIME);
+    }
+    r
+}
+
+/// Why a run stopped
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Exit {
+    /// reached `FIN`, or ran off the end of the program
+    Finished,
+    /// an opcode outside the instruction set, at the given address
+    IllegalOpcode(usize),
+    /// still going after `MAX_STEPS` instructions
+    Runaway,
+}
+
+pub struct Engine {
+    ucode: [u32; UCODE_U32_SIZE],
+    rf: Vec<u32>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine {
+            ucode: [0; UCODE_U32_SIZE],
+            rf: vec![0; RF_SIZE_IN_U32 * NUM_WINDOWS],
+        }
+    }
+
+    pub fn ucode_mut(&mut self) -> &mut [u32] {
+        &mut self.ucode
+    }
+
+    pub fn window(&self, window: usize) -> &[u32] {
+        &self.rf[window * RF_SIZE_IN_U32..(window + 1) * RF_SIZE_IN_U32]
+    }
+
+    pub fn window_mut(&mut self, window: usize) -> &mut [u32] {
+        &mut self.rf[window * RF_SIZE_IN_U32..(window + 1) * RF_SIZE_IN_U32]
+    }
+
+    fn reg(&self, window: usize, r: usize) -> Reg {
+        let base = window * RF_SIZE_IN_U32 + r * 8;
+        let mut reg = [0u32; 8];
+        reg.copy_from_slice(&self.rf[base..base + 8]);
+        reg
+    }
+
+    fn set_reg(&mut self, window: usize, r: usize, value: Reg) {
+        let base = window * RF_SIZE_IN_U32 + r * 8;
+        self.rf[base..base + 8].copy_from_slice(&value);
+    }
+
+    /// Runs the `len` instructions of microcode at `start` against register window `window`
+    pub fn run(&mut self, window: usize, start: usize, len: usize) -> Exit {
+        let end = (start + len).min(UCODE_U32_SIZE);
+        let mut mpc = start;
+        for _ in 0..MAX_STEPS {
+            if mpc < start || mpc >= end {
+                return Exit::Finished;
+            }
+            let insn = self.ucode[mpc];
+            let op = match Op::decode(insn & 0x3f) {
+                Some(op) => op,
+                None => return Exit::IllegalOpcode(mpc),
+            };
+            let ra_index = ((insn >> 6) & 0x1f) as usize;
+            let ra = if (insn >> 11) & 1 != 0 { constant(ra_index) } else { self.reg(window, ra_index) };
+            let rb_index = ((insn >> 12) & 0x1f) as usize;
+            let rb = if (insn >> 17) & 1 != 0 { constant(rb_index) } else { self.reg(window, rb_index) };
+            let wd = ((insn >> 18) & 0x1f) as usize;
+            debug_assert!(wd < NUM_REGS);
+            // 9-bit, two's complement
+            let immediate = (((insn >> 23) as i32) << 23) >> 23;
+
+            let result = match op {
+                Op::Psa => ra,
+                Op::Psb => rb,
+                Op::Msk => if ra[0] & 1 != 0 { rb } else { [0; 8] },
+                Op::Xor => {
+                    let mut r = ra;
+                    for (d, s) in r.iter_mut().zip(rb.iter()) {
+                        *d ^= s;
+                    }
+                    r
+                }
+                Op::Not => {
+                    let mut r = ra;
+                    for d in r.iter_mut() {
+                        *d = !*d;
+                    }
+                    r
+                }
+                Op::Add => add(&ra, &rb),
+                Op::Sub => sub(&ra, &rb),
+                Op::Mul => mul(&ra, &rb),
+                Op::Trd => if geq(&ra, &FIELD_PRIME) { FIELD_PRIME } else { [0; 8] },
+                Op::Shl => {
+                    let mut r = [0u32; 8];
+                    for i in (0..8).rev() {
+                        r[i] = (ra[i] << 1) | if i > 0 { ra[i - 1] >> 31 } else { 0 };
+                    }
+                    r
+                }
+                Op::Xbt => [(ra[7] >> 30) & 1, 0, 0, 0, 0, 0, 0, 0],
+                Op::Brz => {
+                    if ra.iter().all(|&w| w == 0) {
+                        mpc = (mpc as i64 + immediate as i64 + 1) as usize % UCODE_U32_SIZE;
+                    } else {
+                        mpc += 1;
+                    }
+                    continue;
+                }
+                Op::Fin => return Exit::Finished,
+            };
+            self.set_reg(window, wd, result);
+            mpc += 1;
+        }
+        Exit::Runaway
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VECTORS: &[u8] = include_bytes!("../../shellchat/src/cmds/engine25519_vectors.bin");
+    const WYCHEPROOF: &[u8] = include_bytes!("../../shellchat/src/cmds/x25519_test.bin");
+
+    fn vector_read(word_offset: usize) -> u32 {
+        u32::from_le_bytes([
+            VECTORS[word_offset * 4],
+            VECTORS[word_offset * 4 + 1],
+            VECTORS[word_offset * 4 + 2],
+            VECTORS[word_offset * 4 + 3],
+        ])
+    }
+
+    /// Runs the vectors the same way `shellchat engine check` does on hardware
+    #[test]
+    fn engine_vectors() {
+        let mut engine = Engine::new();
+        let mut offset = 0;
+        let (mut passes, mut fails) = (0, 0);
+        while vector_read(offset) == 0x5645_4354 {
+            offset += 1;
+            let load_addr = ((vector_read(offset) >> 16) & 0xFFFF) as usize;
+            let code_len = (vector_read(offset) & 0xFFFF) as usize;
+            offset += 1;
+            let num_args = ((vector_read(offset) >> 27) & 0x1F) as usize;
+            let window = ((vector_read(offset) >> 23) & 0xF) as usize;
+            let num_vectors = (vector_read(offset) & 0x3F_FFFF) as usize;
+            offset += 1;
+            for i in load_addr..load_addr + code_len {
+                engine.ucode_mut()[i] = vector_read(offset);
+                offset += 1;
+            }
+            offset += 8 - (offset % 8);
+            for _ in 0..num_vectors {
+                for w in engine.window_mut(window).iter_mut() {
+                    *w = 0;
+                }
+                for word in 0..num_args * 8 {
+                    engine.window_mut(window)[word] = vector_read(offset);
+                    offset += 1;
+                }
+                assert_eq!(engine.run(window, load_addr, code_len), Exit::Finished);
+                let mut ok = true;
+                for word in 0..8 {
+                    if engine.window(window)[31 * 8 + word] != vector_read(offset) {
+                        ok = false;
+                    }
+                    offset += 1;
+                }
+                if ok { passes += 1 } else { fails += 1 }
+            }
+        }
+        assert!(passes > 0);
+        assert_eq!(fails, 0, "{} of {} vectors failed", fails, passes + fails);
+    }
+
+    #[test]
+    fn montgomery_ladder_wycheproof() {
+        let ucode: Vec<u32> = montgomery_ucode!().iter().map(|&w| w as u32).collect();
+        let mut engine = Engine::new();
+        engine.ucode_mut()[..ucode.len()].copy_from_slice(&ucode);
+        let to_reg = |bytes: &[u8]| {
+            let mut r = [0u32; 8];
+            for (w, b) in r.iter_mut().zip(bytes.chunks_exact(4)) {
+                *w = u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+            }
+            r
+        };
+        let mut one = [0u8; 32];
+        one[0] = 1;
+        let mut counter = [0u8; 32];
+        counter[0] = 254;
+        for case in WYCHEPROOF.chunks_exact(96) {
+            let mut public = [0u8; 32];
+            public.copy_from_slice(&case[0..32]);
+            public[31] &= 0x7f; // the top bit of a u-coordinate is ignored
+            let mut scalar = [0u8; 32];
+            scalar.copy_from_slice(&case[32..64]);
+            scalar[0] &= 248;
+            scalar[31] &= 127;
+            scalar[31] |= 64;
+            // x0 is the identity (1 : 0), x1 is (u : 1); see `MontgomeryJob`
+            engine.set_reg(0, 25, to_reg(&one));
+            engine.set_reg(0, 26, [0; 8]);
+            engine.set_reg(0, 27, to_reg(&public));
+            engine.set_reg(0, 28, to_reg(&one));
+            engine.set_reg(0, 24, to_reg(&public));
+            engine.set_reg(0, 31, to_reg(&scalar));
+            engine.set_reg(0, 19, to_reg(&counter));
+            assert_eq!(engine.run(0, 0, ucode.len()), Exit::Finished);
+            assert_eq!(engine.reg(0, 31), to_reg(&case[64..96]));
+        }
+    }
+
+    #[test]
+    fn edwards_scalar_mul() {
+        let ucode: Vec<u32> = edwards_ucode!().iter().map(|&w| w as u32).collect();
+        let start = crate::EDWARDS_MPSTART;
+        let mut engine = Engine::new();
+        engine.ucode_mut()[start..start + ucode.len()].copy_from_slice(&ucode);
+        let unhex = |s: &str| {
+            let mut r = [0u8; 32];
+            for (i, b) in r.iter_mut().enumerate() {
+                *b = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+            }
+            r
+        };
+        let to_reg = |bytes: [u8; 32]| {
+            let mut r = [0u32; 8];
+            for (w, b) in r.iter_mut().zip(bytes.chunks_exact(4)) {
+                *w = u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+            }
+            r
+        };
+        let base = "5866666666666666666666666666666666666666666666666666666666666666";
+        // (point, scalar, result); no result if the point doesn't decompress
+        let cases = [
+            // RFC 8032 section 7.1 test 1: the public key, from the clamped secret scalar
+            (base, "307c83864f2833cb427a2ef1c00a013cfdff2768d980c0a3a520f006904de94f",
+                Some("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a")),
+            // [l]B is the identity
+            (base, "edd3f55c1a631258d69cf7a2def9de1400000000000000000000000000000010",
+                Some("0100000000000000000000000000000000000000000000000000000000000000")),
+            ("11f4510078ad540e962d0095dc0adcf85664140106076e6d7045149f02ddb898",
+                "fb1b7d3e3f73f414af6e0d935520dd4c2147738606f2bf7ec70209e0cd05ee57",
+                Some("985a6c01fbee0284ca38d9ef4f32aa4583a42219cae8918a26a64ccb2840d15c")),
+            // these two take the sqrt(-1) branch of decompression, with either sign of x
+            ("0300000000000000000000000000000000000000000000000000000000000000",
+                "5b4bae6b016dbfd3d2c4efea4cabcfe0b5106c807db3792156ae2588dbbfde26",
+                Some("e3366ebb07b8d8d092e51b8df2041f2047bd72f27291ff0dbdb8e864af8ebf55")),
+            ("0300000000000000000000000000000000000000000000000000000000000080",
+                "4170318ed144847bb214c6c82b0ec8c699fc291bca371be25f916f8f94ec8b0e",
+                Some("9d4cd7f074400bc2d3de2cd4e3ee98950a9583c75624b3cecef6122c14b2657f")),
+            // y = p
+            ("edffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff7f",
+                "0500000000000000000000000000000000000000000000000000000000000000", None),
+            // x = 0 with the sign bit set
+            ("0100000000000000000000000000000000000000000000000000000000000080",
+                "0500000000000000000000000000000000000000000000000000000000000000", None),
+            // not on the curve
+            ("0200000000000000000000000000000000000000000000000000000000000000",
+                "0500000000000000000000000000000000000000000000000000000000000000", None),
+        ];
+        for (point, scalar, result) in cases.iter() {
+            // the same arguments `edwards()` sets up
+            let mut y = unhex(point);
+            let mut sign = [0u8; 32];
+            sign[0] = y[31] >> 7;
+            y[31] &= 0x7f;
+            engine.set_reg(0, 20, to_reg(y));
+            engine.set_reg(0, 21, to_reg(sign));
+            engine.set_reg(0, 22, to_reg(crate::ED25519_D));
+            engine.set_reg(0, 23, to_reg(crate::SQRT_M1));
+            engine.set_reg(0, 31, to_reg(unhex(scalar)));
+            assert_eq!(engine.run(0, start, ucode.len()), Exit::Finished);
+            match result {
+                Some(result) => {
+                    assert_eq!(engine.reg(0, 29), [0; 8], "{}", point);
+                    let mut compressed = engine.reg(0, 31);
+                    compressed[7] |= (engine.reg(0, 30)[0] & 1) << 31;
+                    assert_eq!(compressed, to_reg(unhex(result)), "{}", point);
+                }
+                None => assert_eq!(engine.reg(0, 29), constant(1), "{}", point),
+            }
+        }
+    }
+
+    #[test]
+    fn field_arithmetic_edges() {
+        let p_minus_1 = sub(&FIELD_PRIME, &constant(1));
+        // (p-1)^2 = 1
+        assert_eq!(mul(&p_minus_1, &p_minus_1), constant(1));
+        // all ones is 2^256 - 1 = 37 (mod p)
+        assert_eq!(mul(&[0xffff_ffff; 8], &constant(1)), [37, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(mul(&FIELD_PRIME, &[0xffff_ffff; 8]), [0; 8]);
+        assert_eq!(sub(&constant(0), &constant(1)), [0xffff_ffff; 8]);
+    }
+}
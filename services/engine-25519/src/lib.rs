@@ -16,7 +16,14 @@ use xous::{CID, Message};
 use num_traits::*;
 use xous_ipc::Buffer;
 
-static mut ENGINE_CB: Option<fn(JobResult)> = None;
+/// Where results of async jobs are delivered, in the library user's process space
+#[derive(Copy, Clone)]
+enum ResultCallback {
+    Plain(fn(JobResult)),
+    /// also given the ticket the job was queued with
+    Ticketed(fn(u32, JobResult)),
+}
+static mut ENGINE_CB: Option<ResultCallback> = None;
 
 #[doc = include_str!("../README.md")]
 pub struct Engine25519 {
@@ -56,13 +63,13 @@ impl Engine25519 {
     /// we always blindly replace result_callback!
     pub unsafe fn spawn_async_job(&mut self, job: &mut Job, result_callback: fn(JobResult)) -> Result<bool, xous::Error> {
         if let Some(cb_sid) = self.cb_sid {
-            ENGINE_CB = Some(result_callback); // this is the unsafe bit!
+            ENGINE_CB = Some(ResultCallback::Plain(result_callback)); // this is the unsafe bit!
             job.id = Some(cb_sid);
             let mut buf = Buffer::into_buf(*job).or(Err(xous::Error::InternalError))?;
             buf.lend_mut(self.conn, Opcode::RunJob.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
 
             match buf.to_original().unwrap() {
-                JobResult::Started => Ok(true),
+                JobResult::Started | JobResult::Queued(_) => Ok(true),
                 _ => Ok(false)
             }
         } else {
@@ -70,6 +77,52 @@ impl Engine25519 {
         }
     }
 
+    /// Queues an async job behind any others, and returns the ticket its result will be delivered with.
+    /// The server serves its clients round-robin, and each client's jobs run in a register window of
+    /// their own, so `job.window` is ignored. Fails with `ServerQueueFull` if the queue, or this
+    /// object's share of it, is full.
+    ///
+    /// This is unsafe for the same reason as `spawn_async_job`: `result_callback` is shared by every
+    /// async `Engine25519` in the process, and is replaced on every call.
+    pub unsafe fn queue_async_job(&mut self, job: &mut Job, result_callback: fn(u32, JobResult)) -> Result<u32, xous::Error> {
+        if let Some(cb_sid) = self.cb_sid {
+            ENGINE_CB = Some(ResultCallback::Ticketed(result_callback));
+            job.id = Some(cb_sid);
+            let mut buf = Buffer::into_buf(*job).or(Err(xous::Error::InternalError))?;
+            buf.lend_mut(self.conn, Opcode::RunJob.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+
+            match buf.to_original().unwrap() {
+                JobResult::Queued(ticket) => Ok(ticket),
+                JobResult::EngineUnavailable => Err(xous::Error::ServerQueueFull),
+                _ => Err(xous::Error::InternalError),
+            }
+        } else {
+            Err(xous::Error::InvalidSyscall)
+        }
+    }
+
+    /// Drops a job queued by this object, if it hasn't started yet. Returns `false` if the job is
+    /// running or has already finished, in which case its result will still be delivered.
+    pub fn cancel_job(&self, ticket: u32) -> Result<bool, xous::Error> {
+        if let Some(cb_sid) = self.cb_sid {
+            let request = CancelRequest { id: cb_sid, ticket, cancelled: false };
+            let mut buf = Buffer::into_buf(request).or(Err(xous::Error::InternalError))?;
+            buf.lend_mut(self.conn, Opcode::CancelJob.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+            let request = buf.to_original::<CancelRequest, _>().or(Err(xous::Error::InternalError))?;
+            Ok(request.cancelled)
+        } else {
+            Err(xous::Error::InvalidSyscall)
+        }
+    }
+
+    /// The state of the async job queue. `pending` counts this object's jobs, if it's async.
+    pub fn queue_status(&self) -> Result<QueueStatus, xous::Error> {
+        let status = QueueStatus { id: self.cb_sid, ..Default::default() };
+        let mut buf = Buffer::into_buf(status).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::QueueStatus.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        buf.to_original::<QueueStatus, _>().or(Err(xous::Error::InternalError))
+    }
+
     pub fn montgomery_job(&mut self, job: MontgomeryJob) -> Result<[u8; 32], xous::Error> {
         let mut buf = Buffer::into_buf(job).or(Err(xous::Error::OutOfMemory))?;
         match buf.lend_mut(self.conn, Opcode::MontgomeryJob.to_u32().unwrap()) {
@@ -103,6 +156,39 @@ impl Engine25519 {
         }
     }
 
+    /// Returns `[job.scalar]job.point`, compressed, or `None` if `job.point` doesn't decompress.
+    pub fn edwards_job(&mut self, job: EdwardsJob) -> Result<Option<[u8; 32]>, xous::Error> {
+        let mut buf = Buffer::into_buf(job).or(Err(xous::Error::OutOfMemory))?;
+        match buf.lend_mut(self.conn, Opcode::EdwardsJob.to_u32().unwrap()) {
+            Ok(_) => (),
+            Err(e) => {
+                if e == xous::Error::ServerNotFound {
+                    log::error!("Looks like another thread called disconnect() on us while we weren't looking: {:?}", e);
+                } else {
+                    log::error!("couldn't lend buffer: {:?}", e);
+                }
+                return Err(e);
+            }
+        }
+
+        match buf.to_original().unwrap() {
+            JobResult::SingleResult(r) => Ok(Some(r)),
+            JobResult::InvalidPoint => Ok(None),
+            JobResult::EngineUnavailable => {
+                log::debug!("spawn job: engine unavailable");
+                Err(xous::Error::ServerQueueFull)
+            },
+            JobResult::IllegalOpcodeException => {
+                log::error!("spawn job: illegal opcode");
+                Err(xous::Error::InvalidString)
+            },
+            _ => {
+                log::error!("spawn job: other error");
+                Err(xous::Error::UnknownError)
+            }
+        }
+    }
+
     /// this is a blocking version of spawn_async_job.
     /// if the engine is free, it will block until a result is returned
     /// if the engine is busy, it will return an EngineUnavailable result.
@@ -170,10 +256,12 @@ fn engine_cb_server(sid0: usize, sid1: usize, sid2: usize, sid3: usize) {
         match FromPrimitive::from_usize(msg.body.id()) {
             Some(Return::Result) => {
                 let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
-                let job_result = buffer.to_original::<JobResult, _>().unwrap();
+                let completion = buffer.to_original::<Completion, _>().unwrap();
                 unsafe {
-                    if let Some(cb) = ENGINE_CB {
-                        cb(job_result)
+                    match ENGINE_CB {
+                        Some(ResultCallback::Plain(cb)) => cb(completion.result),
+                        Some(ResultCallback::Ticketed(cb)) => cb(completion.ticket, completion.result),
+                        None => (),
                     }
                 }
             },
@@ -5,13 +5,15 @@
 
 mod api;
 use api::*;
+mod queue;
+use queue::{ClientId, JobQueue};
+use std::collections::HashMap;
 
 use num_traits::*;
 use core::sync::atomic::{AtomicBool, Ordering};
 use xous::msg_blocking_scalar_unpack;
 use xous_ipc::Buffer;
 
-#[cfg(any(target_os = "none", target_os = "xous"))]
 #[macro_use]
 extern crate engine25519_as;
 
@@ -19,6 +21,706 @@ static RUN_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
 static DISALLOW_SUSPEND: AtomicBool = AtomicBool::new(false);
 static SUSPEND_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
 
+/// The X25519 Montgomery ladder microcode. It expects the operands of a `MontgomeryJob` in
+/// window 0 (see `montgomery()`), and leaves the affine u-coordinate of the result in %31.
+macro_rules! montgomery_ucode {
+    () => {
+        assemble_engine25519!(
+            start:
+                // P.U in %20
+                // P.W in %21
+                // Q.U in %22
+                // Q.W in %23
+                // affine_PmQ in %24
+                // %30 is the TRD scratch register and cswap dummy
+                // %29 is the subtraction temporary value register and k_t
+                // x0.U in %25
+                // x0.W in %26
+                // x1.U in %27
+                // x1.W in %28
+                // %19 is the loop counter, starts with 254 (if 0, loop runs exactly once)
+                // %31 is the scalar
+                // %18 is the swap variable
+                psa %18, #0
+
+                // for i in (0..255).rev()
+            mainloop:
+                // let choice: u8 = (bits[i + 1] ^ bits[i]) as u8;
+                // ProjectivePoint::conditional_swap(&mut x0, &mut x1, choice.into());
+                xbt %29, %31        // orignally[k_t = (k>>t) & 1] now[k_t = k[254]]
+                shl %31, %31        // k = k<<1
+                xor %18, %18, %29   // swap ^= k_t
+
+                // cswap x0.U (%25), x1.U (%27)
+                xor %30, %25, %27
+                msk %30, %18, %30
+                xor %25, %30, %25
+                xor %27, %30, %27
+                // cswap x0.W (%26), x1.W (%28)
+                xor %30, %26, %28
+                msk %30, %18, %30
+                xor %26, %30, %26
+                xor %28, %30, %28
+
+                psa %18, %29  // swap = k_t
+
+                    // differential_add_and_double(&mut x0, &mut x1, &affine_u);
+                    psa %20, %25
+                    psa %21, %26
+                    psa %22, %27
+                    psa %23, %28
+                    // affine_u is already in %24
+
+                    // let t0 = &P.U + &P.W;
+                    add %0, %20, %21
+                    trd %30, %0
+                    sub %0, %0, %30
+                    // let t1 = &P.U - &P.W;
+                    sub %21, #3, %21    // negate &P.W using #FIELDPRIME (#3)
+                    add %1, %20, %21
+                    trd %30, %1
+                    sub %1, %1, %30
+                    // let t2 = &Q.U + &Q.W;
+                    add %2, %22, %23
+                    trd %30, %2
+                    sub %2, %2, %30
+                    // let t3 = &Q.U - &Q.W;
+                    sub %23, #3, %23
+                    add %3, %22, %23
+                    trd %30, %3
+                    sub %3, %3, %30
+                    // let t4 = t0.square();   // (U_P + W_P)^2 = U_P^2 + 2 U_P W_P + W_P^2
+                    mul %4, %0, %0
+                    // let t5 = t1.square();   // (U_P - W_P)^2 = U_P^2 - 2 U_P W_P + W_P^2
+                    mul %5, %1, %1
+                    // let t6 = &t4 - &t5;     // 4 U_P W_P
+                    sub %29, #3, %5
+                    add %6, %4, %29
+                    trd %30, %6
+                    sub %6, %6, %30
+                    // let t7 = &t0 * &t3;     // (U_P + W_P) (U_Q - W_Q) = U_P U_Q + W_P U_Q - U_P W_Q - W_P W_Q
+                    mul %7, %0, %3
+                    // let t8 = &t1 * &t2;     // (U_P - W_P) (U_Q + W_Q) = U_P U_Q - W_P U_Q + U_P W_Q - W_P W_Q
+                    mul %8, %1, %2
+                    // let t9  = &t7 + &t8;    // 2 (U_P U_Q - W_P W_Q)
+                    add %9, %7, %8
+                    trd %30, %9
+                    sub %9, %9, %30
+                    // let t10 = &t7 - &t8;    // 2 (W_P U_Q - U_P W_Q)
+                    sub %29, #3, %8
+                    add %10, %7, %29
+                    trd %30, %10
+                    sub %10, %10, %30
+                    // let t11 =  t9.square(); // 4 (U_P U_Q - W_P W_Q)^2
+                    mul %11, %9, %9
+                    // let t12 = t10.square(); // 4 (W_P U_Q - U_P W_Q)^2
+                    mul %12, %10, %10
+                    // let t13 = &APLUS2_OVER_FOUR * &t6; // (A + 2) U_P U_Q
+                    mul %13, #4, %6   // #4 is A+2/4
+                    // let t14 = &t4 * &t5;    // ((U_P + W_P)(U_P - W_P))^2 = (U_P^2 - W_P^2)^2
+                    mul %14, %4, %5
+                    // let t15 = &t13 + &t5;   // (U_P - W_P)^2 + (A + 2) U_P W_P
+                    add %15, %13, %5
+                    trd %30, %15
+                    sub %15, %15, %30
+                    // let t16 = &t6 * &t15;   // 4 (U_P W_P) ((U_P - W_P)^2 + (A + 2) U_P W_P)
+                    mul %16, %6, %15
+                    // let t17 = affine_PmQ * &t12; // U_D * 4 (W_P U_Q - U_P W_Q)^2
+                    mul %17, %24, %12    // affine_PmQ loaded into %24
+
+                    ///// these can be eliminated down the road, but included for 1:1 algorithm correspodence to reference in early testing
+                    // P.U = t14;  // U_{P'} = (U_P + W_P)^2 (U_P - W_P)^2
+                    psa %20, %14
+                    // P.W = t16;  // W_{P'} = (4 U_P W_P) ((U_P - W_P)^2 + ((A + 2)/4) 4 U_P W_P)
+                    psa %21, %16
+                    // let t18 = t11;               // W_D * 4 (U_P U_Q - W_P W_Q)^2
+                    // Q.U = t18;  // U_{Q'} = W_D * 4 (U_P U_Q - W_P W_Q)^2
+                    psa %22, %11   // collapsed two to save a register
+                    // Q.W = t17;  // W_{Q'} = U_D * 4 (W_P U_Q - U_P W_Q)^2
+                    psa %23, %17
+
+                    ///// 'return' arguments for next iteration, can be optimized out later
+                    psa %25, %20
+                    psa %26, %21
+                    psa %27, %22
+                    psa %28, %23
+
+                brz end, %19     // if loop counter is 0, quit
+                sub %19, %19, #1 // subtract one from the loop counter and run again
+                brz mainloop, #0    // go back to the top
+            end:
+                // ProjectivePoint::conditional_swap(&mut x0, &mut x1, Choice::from(bits[0] as u8));
+                // cswap x0.U (%25), x1.U (%27)
+                xor %30, %25, %27
+                msk %30, %18, %30
+                xor %25, %30, %25
+                xor %27, %30, %27
+                // cswap x0.W (%26), x1.W (%28)
+                xor %30, %26, %28
+                msk %30, %18, %30
+                xor %26, %30, %26
+                xor %28, %30, %28
+
+                // AFFINE SPLICE -- pass arguments to the affine block
+                psa %29, %25
+                psa %30, %26
+                // W.invert() in %21
+                // U in %29
+                // W in %30
+                // result in %31
+                // loop counter in %28
+
+                // from FieldElement.invert()
+                    // let (t19, t3) = self.pow22501();   // t19: 249..0 ; t3: 3,1,0
+                    // let t0  = self.square();           // 1         e_0 = 2^1
+                    mul %0, %30, %30  // self is W, e.g. %30
+                    // let t1  = t0.square().square();    // 3         e_1 = 2^3
+                    mul %1, %0, %0
+                    mul %1, %1, %1
+                    // let t2  = self * &t1;              // 3,0       e_2 = 2^3 + 2^0
+                    mul %2, %30, %1
+                    // let t3  = &t0 * &t2;               // 3,1,0
+                    mul %3, %0, %2
+                    // let t4  = t3.square();             // 4,2,1
+                    mul %4, %3, %3
+                    // let t5  = &t2 * &t4;               // 4,3,2,1,0
+                    mul %5, %2, %4
+
+                    // let t6  = t5.pow2k(5);             // 9,8,7,6,5
+                    psa %28, #5       // coincidentally, constant #5 is the number 5
+                    mul %6, %5, %5
+                pow2k_5:
+                    sub %28, %28, #1  // %28 = %28 - 1
+                    brz pow2k_5_exit, %28
+                    mul %6, %6, %6
+                    brz pow2k_5, #0
+                pow2k_5_exit:
+                    // let t7  = &t6 * &t5;               // 9,8,7,6,5,4,3,2,1,0
+                    mul %7, %6, %5
+
+                    // let t8  = t7.pow2k(10);            // 19..10
+                    psa %28, #6        // constant #6 is the number 10
+                    mul %8, %7, %7
+                pow2k_10:
+                    sub %28, %28, #1
+                    brz pow2k_10_exit, %28
+                    mul %8, %8, %8
+                    brz pow2k_10, #0
+                pow2k_10_exit:
+                    // let t9  = &t8 * &t7;               // 19..0
+                    mul %9, %8, %7
+
+                    // let t10 = t9.pow2k(20);            // 39..20
+                    psa %28, #7         // constant #7 is the number 20
+                    mul %10, %9, %9
+                pow2k_20:
+                    sub %28, %28, #1
+                    brz pow2k_20_exit, %28
+                    mul %10, %10, %10
+                    brz pow2k_20, #0
+                pow2k_20_exit:
+                    // let t11 = &t10 * &t9;              // 39..0
+                    mul %11, %10, %9
+
+                    // let t12 = t11.pow2k(10);           // 49..10
+                    psa %28, #6         // constant #6 is the number 10
+                    mul %12, %11, %11
+                pow2k_10b:
+                    sub %28, %28, #1
+                    brz pow2k_10b_exit, %28
+                    mul %12, %12, %12
+                    brz pow2k_10b, #0
+                pow2k_10b_exit:
+                    // let t13 = &t12 * &t7;              // 49..0
+                    mul %13, %12, %7
+
+                    // let t14 = t13.pow2k(50);           // 99..50
+                    psa %28, #8         // constant #8 is the number 50
+                    mul %14, %13, %13
+                pow2k_50a:
+                    sub %28, %28, #1
+                    brz pow2k_50a_exit, %28
+                    mul %14, %14, %14
+                    brz pow2k_50a, #0
+                pow2k_50a_exit:
+                    // let t15 = &t14 * &t13;             // 99..0
+                    mul %15, %14, %13
+
+                    // let t16 = t15.pow2k(100);          // 199..100
+                    psa %28, #9         // constant #9 is the number 100
+                    mul %16, %15, %15
+                pow2k_100:
+                    sub %28, %28, #1
+                    brz pow2k_100_exit, %28
+                    mul %16, %16, %16
+                    brz pow2k_100, #0
+                pow2k_100_exit:
+                    // let t17 = &t16 * &t15;             // 199..0
+                    mul %17, %16, %15
+
+                    // let t18 = t17.pow2k(50);           // 249..50
+                    psa %28, #8         // constant #8 is the number 50
+                    mul %18, %17, %17
+                pow2k_50b:
+                    sub %28, %28, #1
+                    brz pow2k_50b_exit, %28
+                    mul %18, %18, %18
+                    brz pow2k_50b, #0
+                pow2k_50b_exit:
+                    // let t19 = &t18 * &t13;             // 249..0
+                    mul %19, %18, %13
+                    //(t19, t3) // just a return value, values are already there, do nothing
+
+                    //let t20 = t19.pow2k(5);            // 254..5
+                    psa %28, #5
+                    mul %20, %19, %19
+                pow2k_5_last:
+                    sub %28, %28, #1
+                    brz pow2k_5_last_exit, %28
+                    mul %20, %20, %20
+                    brz pow2k_5_last, #0
+                pow2k_5_last_exit:
+
+                    //let t21 = &t20 * &t3;              // 254..5,3,1,0
+                    mul %21, %20, %3
+
+                // u = &self.U * &self.W.invert()
+                mul %31, %29, %21
+                fin  // finish execution
+        )
+    };
+}
+
+/// Where `edwards_ucode!()` is loaded, clear of `montgomery_ucode!()` at 0 so neither has to be
+/// reloaded when jobs alternate between them
+const EDWARDS_MPSTART: usize = 256;
+/// The Edwards curve constant d = -121665/121666, little-endian
+const ED25519_D: [u8; 32] = [
+    0xa3, 0x78, 0x59, 0x13, 0xca, 0x4d, 0xeb, 0x75,
+    0xab, 0xd8, 0x41, 0x41, 0x4d, 0x0a, 0x70, 0x00,
+    0x98, 0xe8, 0x79, 0x77, 0x79, 0x40, 0xc7, 0x8c,
+    0x73, 0xfe, 0x6f, 0x2b, 0xee, 0x6c, 0x03, 0x52,
+];
+/// sqrt(-1), little-endian
+const SQRT_M1: [u8; 32] = [
+    0xb0, 0xa0, 0x0e, 0x4a, 0x27, 0x1b, 0xee, 0xc4,
+    0x78, 0xe4, 0x2f, 0xad, 0x06, 0x18, 0x43, 0x2f,
+    0xa7, 0xd7, 0xfb, 0x3d, 0x99, 0x00, 0x4d, 0x2b,
+    0x0b, 0xdf, 0xc1, 0x4f, 0x80, 0x24, 0x83, 0x2b,
+];
+
+/// Edwards scalar multiplication for Ed25519, as a Montgomery ladder over extended coordinates.
+/// It expects the operands of an `EdwardsJob` in window 0 (see `edwards()`), and leaves the affine
+/// x and y of the result in %30 and %31, with %29 set to 0. If the point doesn't decompress, %29 is
+/// set to 1 instead. Decompression branches on the point, which is public; the ladder itself runs
+/// the same way for every scalar.
+macro_rules! edwards_ucode {
+    () => {
+        assemble_engine25519!(
+            start:
+                // y in %20, with its top bit cleared
+                // the sign of x in %21, 0 or 1
+                // d in %22
+                // sqrt(-1) in %23
+                // %31 is the scalar
+                // %30 is the TRD scratch register and cswap dummy
+                // %29 is the subtraction temporary value register and k_t
+
+                // decompression, as in RFC 8032 section 5.1.3
+                // y has to be less than p
+                trd %30, %20
+                brz y_ok, %30
+                brz invalid, #0
+            y_ok:
+                // u = y^2 - 1 in %25
+                mul %0, %20, %20
+                sub %29, #3, #1
+                add %25, %0, %29
+                trd %30, %25
+                sub %25, %25, %30
+                // v = d y^2 + 1 in %26
+                mul %1, %22, %0
+                add %26, %1, #1
+                trd %30, %26
+                sub %26, %26, %30
+                // x = u v^3 (u v^7)^((p-5)/8)
+                mul %1, %26, %26    // v^2
+                mul %2, %1, %26     // v^3
+                mul %27, %25, %2    // u v^3
+                mul %3, %1, %1      // v^4
+                mul %30, %27, %3    // u v^7
+                // (u v^7)^(2^250 - 1) in %19, the same chain as the inversion in montgomery_ucode
+                    mul %0, %30, %30
+                    mul %1, %0, %0
+                    mul %1, %1, %1
+                    mul %2, %30, %1
+                    mul %3, %0, %2
+                    mul %4, %3, %3
+                    mul %5, %2, %4
+                    psa %28, #5
+                    mul %6, %5, %5
+                pow2k_5_d:
+                    sub %28, %28, #1
+                    brz pow2k_5_d_exit, %28
+                    mul %6, %6, %6
+                    brz pow2k_5_d, #0
+                pow2k_5_d_exit:
+                    mul %7, %6, %5
+                    psa %28, #6
+                    mul %8, %7, %7
+                pow2k_10_d:
+                    sub %28, %28, #1
+                    brz pow2k_10_d_exit, %28
+                    mul %8, %8, %8
+                    brz pow2k_10_d, #0
+                pow2k_10_d_exit:
+                    mul %9, %8, %7
+                    psa %28, #7
+                    mul %10, %9, %9
+                pow2k_20_d:
+                    sub %28, %28, #1
+                    brz pow2k_20_d_exit, %28
+                    mul %10, %10, %10
+                    brz pow2k_20_d, #0
+                pow2k_20_d_exit:
+                    mul %11, %10, %9
+                    psa %28, #6
+                    mul %12, %11, %11
+                pow2k_10b_d:
+                    sub %28, %28, #1
+                    brz pow2k_10b_d_exit, %28
+                    mul %12, %12, %12
+                    brz pow2k_10b_d, #0
+                pow2k_10b_d_exit:
+                    mul %13, %12, %7
+                    psa %28, #8
+                    mul %14, %13, %13
+                pow2k_50a_d:
+                    sub %28, %28, #1
+                    brz pow2k_50a_d_exit, %28
+                    mul %14, %14, %14
+                    brz pow2k_50a_d, #0
+                pow2k_50a_d_exit:
+                    mul %15, %14, %13
+                    psa %28, #9
+                    mul %16, %15, %15
+                pow2k_100_d:
+                    sub %28, %28, #1
+                    brz pow2k_100_d_exit, %28
+                    mul %16, %16, %16
+                    brz pow2k_100_d, #0
+                pow2k_100_d_exit:
+                    mul %17, %16, %15
+                    psa %28, #8
+                    mul %18, %17, %17
+                pow2k_50b_d:
+                    sub %28, %28, #1
+                    brz pow2k_50b_d_exit, %28
+                    mul %18, %18, %18
+                    brz pow2k_50b_d, #0
+                pow2k_50b_d_exit:
+                    mul %19, %18, %13
+                    // (u v^7)^(2^252 - 3) in %18
+                    mul %18, %19, %19
+                    mul %18, %18, %18
+                    mul %18, %18, %30
+                mul %27, %27, %18   // the candidate x
+                // v x^2 is u if x is a root, or -u if x sqrt(-1) is one
+                mul %0, %27, %27
+                mul %0, %0, %26
+                xor %1, %0, %25
+                brz root_ok, %1
+                sub %1, #3, %25
+                xor %1, %0, %1
+                brz root_i, %1
+                brz invalid, #0
+            root_i:
+                mul %27, %27, %23
+            root_ok:
+                // x = 0 can't have its sign bit set
+                brz x_zero, %27
+                brz x_sign, #0
+            x_zero:
+                brz x_sign, %21
+                brz invalid, #0
+            x_sign:
+                // x = -x if its low bit doesn't match the sign
+                msk %0, %27, #1
+                xor %0, %0, %21
+                sub %1, #3, %27
+                xor %1, %1, %27
+                msk %1, %0, %1
+                xor %27, %27, %1
+                brz ladder, #0
+            invalid:
+                psa %29, #1
+                fin
+
+            ladder:
+                // R1 = (X, Y, Z, T) in %20..%23, starts as the point (x, y, 1, xy)
+                // R0 in %24..%27, starts as the identity (0, 1, 1, 0)
+                // 2d in %28
+                // %19 is the loop counter, starts with 254 (if 0, loop runs exactly once)
+                // %18 is the swap variable
+                add %28, %22, %22
+                trd %30, %28
+                sub %28, %28, %30
+                psa %21, %20
+                psa %20, %27
+                psa %22, #1
+                mul %23, %20, %21
+                psa %24, #0
+                psa %25, #1
+                psa %26, #1
+                psa %27, #0
+                add %19, #9, #9     // 254 = 100 + 100 + 50 + 5 - 1
+                add %19, %19, #8
+                add %19, %19, #5
+                sub %19, %19, #1
+                psa %18, #0
+
+            mainloop:
+                xbt %29, %31        // k_t = k[254]
+                shl %31, %31        // k = k<<1
+                xor %18, %18, %29   // swap ^= k_t
+
+                // cswap R0, R1
+                xor %30, %24, %20
+                msk %30, %18, %30
+                xor %24, %30, %24
+                xor %20, %30, %20
+                xor %30, %25, %21
+                msk %30, %18, %30
+                xor %25, %30, %25
+                xor %21, %30, %21
+                xor %30, %26, %22
+                msk %30, %18, %30
+                xor %26, %30, %26
+                xor %22, %30, %22
+                xor %30, %27, %23
+                msk %30, %18, %30
+                xor %27, %30, %27
+                xor %23, %30, %23
+
+                psa %18, %29  // swap = k_t
+                    // R1 = R0 + R1 in %10..%13
+                    // A = (Y1 - X1) (Y2 - X2)
+                    sub %30, #3, %24
+                    add %0, %25, %30
+                    trd %30, %0
+                    sub %0, %0, %30
+                    sub %30, #3, %20
+                    add %1, %21, %30
+                    trd %30, %1
+                    sub %1, %1, %30
+                    mul %0, %0, %1
+                    // B = (Y1 + X1) (Y2 + X2)
+                    add %1, %25, %24
+                    trd %30, %1
+                    sub %1, %1, %30
+                    add %2, %21, %20
+                    trd %30, %2
+                    sub %2, %2, %30
+                    mul %1, %1, %2
+                    // C = T1 2d T2
+                    mul %2, %27, %28
+                    mul %2, %2, %23
+                    // D = 2 Z1 Z2
+                    mul %3, %26, %22
+                    add %3, %3, %3
+                    trd %30, %3
+                    sub %3, %3, %30
+                    // E = B - A, F = D - C, G = D + C, H = B + A
+                    sub %30, #3, %0
+                    add %4, %1, %30
+                    trd %30, %4
+                    sub %4, %4, %30
+                    sub %30, #3, %2
+                    add %5, %3, %30
+                    trd %30, %5
+                    sub %5, %5, %30
+                    add %6, %3, %2
+                    trd %30, %6
+                    sub %6, %6, %30
+                    add %7, %1, %0
+                    trd %30, %7
+                    sub %7, %7, %30
+                    mul %10, %4, %5    // X3 = E F
+                    mul %11, %6, %7    // Y3 = G H
+                    mul %12, %5, %6    // Z3 = F G
+                    mul %13, %4, %7    // T3 = E H
+                    // R0 = R0 + R0 in %14..%17
+                    // A = (Y1 - X1) (Y2 - X2)
+                    sub %30, #3, %24
+                    add %0, %25, %30
+                    trd %30, %0
+                    sub %0, %0, %30
+                    sub %30, #3, %24
+                    add %1, %25, %30
+                    trd %30, %1
+                    sub %1, %1, %30
+                    mul %0, %0, %1
+                    // B = (Y1 + X1) (Y2 + X2)
+                    add %1, %25, %24
+                    trd %30, %1
+                    sub %1, %1, %30
+                    add %2, %25, %24
+                    trd %30, %2
+                    sub %2, %2, %30
+                    mul %1, %1, %2
+                    // C = T1 2d T2
+                    mul %2, %27, %28
+                    mul %2, %2, %27
+                    // D = 2 Z1 Z2
+                    mul %3, %26, %26
+                    add %3, %3, %3
+                    trd %30, %3
+                    sub %3, %3, %30
+                    // E = B - A, F = D - C, G = D + C, H = B + A
+                    sub %30, #3, %0
+                    add %4, %1, %30
+                    trd %30, %4
+                    sub %4, %4, %30
+                    sub %30, #3, %2
+                    add %5, %3, %30
+                    trd %30, %5
+                    sub %5, %5, %30
+                    add %6, %3, %2
+                    trd %30, %6
+                    sub %6, %6, %30
+                    add %7, %1, %0
+                    trd %30, %7
+                    sub %7, %7, %30
+                    mul %14, %4, %5    // X3 = E F
+                    mul %15, %6, %7    // Y3 = G H
+                    mul %16, %5, %6    // Z3 = F G
+                    mul %17, %4, %7    // T3 = E H
+                    psa %20, %10
+                    psa %21, %11
+                    psa %22, %12
+                    psa %23, %13
+                    psa %24, %14
+                    psa %25, %15
+                    psa %26, %16
+                    psa %27, %17
+
+                brz end, %19     // if loop counter is 0, quit
+                sub %19, %19, #1 // subtract one from the loop counter and run again
+                brz mainloop, #0    // go back to the top
+            end:
+                // cswap R0, R1
+                xor %30, %24, %20
+                msk %30, %18, %30
+                xor %24, %30, %24
+                xor %20, %30, %20
+                xor %30, %25, %21
+                msk %30, %18, %30
+                xor %25, %30, %25
+                xor %21, %30, %21
+                xor %30, %26, %22
+                msk %30, %18, %30
+                xor %26, %30, %26
+                xor %22, %30, %22
+                xor %30, %27, %23
+                msk %30, %18, %30
+                xor %27, %30, %27
+                xor %23, %30, %23
+
+                // 1/Z in %21, the same chain as the inversion in montgomery_ucode
+                psa %30, %26
+                    mul %0, %30, %30
+                    mul %1, %0, %0
+                    mul %1, %1, %1
+                    mul %2, %30, %1
+                    mul %3, %0, %2
+                    mul %4, %3, %3
+                    mul %5, %2, %4
+                    psa %28, #5
+                    mul %6, %5, %5
+                pow2k_5_i:
+                    sub %28, %28, #1
+                    brz pow2k_5_i_exit, %28
+                    mul %6, %6, %6
+                    brz pow2k_5_i, #0
+                pow2k_5_i_exit:
+                    mul %7, %6, %5
+                    psa %28, #6
+                    mul %8, %7, %7
+                pow2k_10_i:
+                    sub %28, %28, #1
+                    brz pow2k_10_i_exit, %28
+                    mul %8, %8, %8
+                    brz pow2k_10_i, #0
+                pow2k_10_i_exit:
+                    mul %9, %8, %7
+                    psa %28, #7
+                    mul %10, %9, %9
+                pow2k_20_i:
+                    sub %28, %28, #1
+                    brz pow2k_20_i_exit, %28
+                    mul %10, %10, %10
+                    brz pow2k_20_i, #0
+                pow2k_20_i_exit:
+                    mul %11, %10, %9
+                    psa %28, #6
+                    mul %12, %11, %11
+                pow2k_10b_i:
+                    sub %28, %28, #1
+                    brz pow2k_10b_i_exit, %28
+                    mul %12, %12, %12
+                    brz pow2k_10b_i, #0
+                pow2k_10b_i_exit:
+                    mul %13, %12, %7
+                    psa %28, #8
+                    mul %14, %13, %13
+                pow2k_50a_i:
+                    sub %28, %28, #1
+                    brz pow2k_50a_i_exit, %28
+                    mul %14, %14, %14
+                    brz pow2k_50a_i, #0
+                pow2k_50a_i_exit:
+                    mul %15, %14, %13
+                    psa %28, #9
+                    mul %16, %15, %15
+                pow2k_100_i:
+                    sub %28, %28, #1
+                    brz pow2k_100_i_exit, %28
+                    mul %16, %16, %16
+                    brz pow2k_100_i, #0
+                pow2k_100_i_exit:
+                    mul %17, %16, %15
+                    psa %28, #8
+                    mul %18, %17, %17
+                pow2k_50b_i:
+                    sub %28, %28, #1
+                    brz pow2k_50b_i_exit, %28
+                    mul %18, %18, %18
+                    brz pow2k_50b_i, #0
+                pow2k_50b_i_exit:
+                    mul %19, %18, %13
+                    psa %28, #5
+                    mul %20, %19, %19
+                pow2k_5_last:
+                    sub %28, %28, #1
+                    brz pow2k_5_last_exit, %28
+                    mul %20, %20, %20
+                    brz pow2k_5_last, #0
+                pow2k_5_last_exit:
+                    mul %21, %20, %3
+
+                // x = X/Z, y = Y/Z
+                mul %30, %24, %21
+                mul %31, %25, %21
+                psa %29, #0
+                fin  // finish execution
+        )
+    };
+}
+
+// after the microcode macros, so the emulator's tests can run the real microcode
+#[cfg(not(any(target_os = "none", target_os = "xous")))]
+mod emulator;
+
 #[cfg(any(target_os = "none", target_os = "xous"))]
 mod implementation {
     use utralib::generated::*;
@@ -45,6 +747,7 @@ mod implementation {
         do_notify: bool,
         illegal_opcode: bool,
         montgomery_len: Option<usize>,
+        edwards_len: Option<usize>,
     }
     fn handle_engine_irq(_irq_no: usize, arg: *mut usize) {
         let engine = unsafe { &mut *(arg as *mut Engine25519Hw) };
@@ -124,6 +827,7 @@ mod implementation {
                 do_notify: false,
                 illegal_opcode: false,
                 montgomery_len: None,
+                edwards_len: None,
             };
 
             log::trace!("claiming interrupt");
@@ -283,6 +987,7 @@ mod implementation {
         }
         pub fn run(&mut self, job: Job) {
             self.montgomery_len = None;
+            self.edwards_len = None;
             log::trace!("entering run");
             // block any suspends from happening while we set up the engine
             DISALLOW_SUSPEND.store(true, Ordering::Relaxed);
@@ -347,269 +1052,7 @@ mod implementation {
             }
         }
         fn load_montgomery(&mut self, mpstart: u32) -> u32 {
-            let mcode = assemble_engine25519!(
-                start:
-                    // P.U in %20
-                    // P.W in %21
-                    // Q.U in %22
-                    // Q.W in %23
-                    // affine_PmQ in %24
-                    // %30 is the TRD scratch register and cswap dummy
-                    // %29 is the subtraction temporary value register and k_t
-                    // x0.U in %25
-                    // x0.W in %26
-                    // x1.U in %27
-                    // x1.W in %28
-                    // %19 is the loop counter, starts with 254 (if 0, loop runs exactly once)
-                    // %31 is the scalar
-                    // %18 is the swap variable
-                    psa %18, #0
-
-                    // for i in (0..255).rev()
-                mainloop:
-                    // let choice: u8 = (bits[i + 1] ^ bits[i]) as u8;
-                    // ProjectivePoint::conditional_swap(&mut x0, &mut x1, choice.into());
-                    xbt %29, %31        // orignally[k_t = (k>>t) & 1] now[k_t = k[254]]
-                    shl %31, %31        // k = k<<1
-                    xor %18, %18, %29   // swap ^= k_t
-
-                    // cswap x0.U (%25), x1.U (%27)
-                    xor %30, %25, %27
-                    msk %30, %18, %30
-                    xor %25, %30, %25
-                    xor %27, %30, %27
-                    // cswap x0.W (%26), x1.W (%28)
-                    xor %30, %26, %28
-                    msk %30, %18, %30
-                    xor %26, %30, %26
-                    xor %28, %30, %28
-
-                    psa %18, %29  // swap = k_t
-
-                        // differential_add_and_double(&mut x0, &mut x1, &affine_u);
-                        psa %20, %25
-                        psa %21, %26
-                        psa %22, %27
-                        psa %23, %28
-                        // affine_u is already in %24
-
-                        // let t0 = &P.U + &P.W;
-                        add %0, %20, %21
-                        trd %30, %0
-                        sub %0, %0, %30
-                        // let t1 = &P.U - &P.W;
-                        sub %21, #3, %21    // negate &P.W using #FIELDPRIME (#3)
-                        add %1, %20, %21
-                        trd %30, %1
-                        sub %1, %1, %30
-                        // let t2 = &Q.U + &Q.W;
-                        add %2, %22, %23
-                        trd %30, %2
-                        sub %2, %2, %30
-                        // let t3 = &Q.U - &Q.W;
-                        sub %23, #3, %23
-                        add %3, %22, %23
-                        trd %30, %3
-                        sub %3, %3, %30
-                        // let t4 = t0.square();   // (U_P + W_P)^2 = U_P^2 + 2 U_P W_P + W_P^2
-                        mul %4, %0, %0
-                        // let t5 = t1.square();   // (U_P - W_P)^2 = U_P^2 - 2 U_P W_P + W_P^2
-                        mul %5, %1, %1
-                        // let t6 = &t4 - &t5;     // 4 U_P W_P
-                        sub %29, #3, %5
-                        add %6, %4, %29
-                        trd %30, %6
-                        sub %6, %6, %30
-                        // let t7 = &t0 * &t3;     // (U_P + W_P) (U_Q - W_Q) = U_P U_Q + W_P U_Q - U_P W_Q - W_P W_Q
-                        mul %7, %0, %3
-                        // let t8 = &t1 * &t2;     // (U_P - W_P) (U_Q + W_Q) = U_P U_Q - W_P U_Q + U_P W_Q - W_P W_Q
-                        mul %8, %1, %2
-                        // let t9  = &t7 + &t8;    // 2 (U_P U_Q - W_P W_Q)
-                        add %9, %7, %8
-                        trd %30, %9
-                        sub %9, %9, %30
-                        // let t10 = &t7 - &t8;    // 2 (W_P U_Q - U_P W_Q)
-                        sub %29, #3, %8
-                        add %10, %7, %29
-                        trd %30, %10
-                        sub %10, %10, %30
-                        // let t11 =  t9.square(); // 4 (U_P U_Q - W_P W_Q)^2
-                        mul %11, %9, %9
-                        // let t12 = t10.square(); // 4 (W_P U_Q - U_P W_Q)^2
-                        mul %12, %10, %10
-                        // let t13 = &APLUS2_OVER_FOUR * &t6; // (A + 2) U_P U_Q
-                        mul %13, #4, %6   // #4 is A+2/4
-                        // let t14 = &t4 * &t5;    // ((U_P + W_P)(U_P - W_P))^2 = (U_P^2 - W_P^2)^2
-                        mul %14, %4, %5
-                        // let t15 = &t13 + &t5;   // (U_P - W_P)^2 + (A + 2) U_P W_P
-                        add %15, %13, %5
-                        trd %30, %15
-                        sub %15, %15, %30
-                        // let t16 = &t6 * &t15;   // 4 (U_P W_P) ((U_P - W_P)^2 + (A + 2) U_P W_P)
-                        mul %16, %6, %15
-                        // let t17 = affine_PmQ * &t12; // U_D * 4 (W_P U_Q - U_P W_Q)^2
-                        mul %17, %24, %12    // affine_PmQ loaded into %24
-
-                        ///// these can be eliminated down the road, but included for 1:1 algorithm correspodence to reference in early testing
-                        // P.U = t14;  // U_{P'} = (U_P + W_P)^2 (U_P - W_P)^2
-                        psa %20, %14
-                        // P.W = t16;  // W_{P'} = (4 U_P W_P) ((U_P - W_P)^2 + ((A + 2)/4) 4 U_P W_P)
-                        psa %21, %16
-                        // let t18 = t11;               // W_D * 4 (U_P U_Q - W_P W_Q)^2
-                        // Q.U = t18;  // U_{Q'} = W_D * 4 (U_P U_Q - W_P W_Q)^2
-                        psa %22, %11   // collapsed two to save a register
-                        // Q.W = t17;  // W_{Q'} = U_D * 4 (W_P U_Q - U_P W_Q)^2
-                        psa %23, %17
-
-                        ///// 'return' arguments for next iteration, can be optimized out later
-                        psa %25, %20
-                        psa %26, %21
-                        psa %27, %22
-                        psa %28, %23
-
-                    brz end, %19     // if loop counter is 0, quit
-                    sub %19, %19, #1 // subtract one from the loop counter and run again
-                    brz mainloop, #0    // go back to the top
-                end:
-                    // ProjectivePoint::conditional_swap(&mut x0, &mut x1, Choice::from(bits[0] as u8));
-                    // cswap x0.U (%25), x1.U (%27)
-                    xor %30, %25, %27
-                    msk %30, %18, %30
-                    xor %25, %30, %25
-                    xor %27, %30, %27
-                    // cswap x0.W (%26), x1.W (%28)
-                    xor %30, %26, %28
-                    msk %30, %18, %30
-                    xor %26, %30, %26
-                    xor %28, %30, %28
-
-                    // AFFINE SPLICE -- pass arguments to the affine block
-                    psa %29, %25
-                    psa %30, %26
-                    // W.invert() in %21
-                    // U in %29
-                    // W in %30
-                    // result in %31
-                    // loop counter in %28
-
-                    // from FieldElement.invert()
-                        // let (t19, t3) = self.pow22501();   // t19: 249..0 ; t3: 3,1,0
-                        // let t0  = self.square();           // 1         e_0 = 2^1
-                        mul %0, %30, %30  // self is W, e.g. %30
-                        // let t1  = t0.square().square();    // 3         e_1 = 2^3
-                        mul %1, %0, %0
-                        mul %1, %1, %1
-                        // let t2  = self * &t1;              // 3,0       e_2 = 2^3 + 2^0
-                        mul %2, %30, %1
-                        // let t3  = &t0 * &t2;               // 3,1,0
-                        mul %3, %0, %2
-                        // let t4  = t3.square();             // 4,2,1
-                        mul %4, %3, %3
-                        // let t5  = &t2 * &t4;               // 4,3,2,1,0
-                        mul %5, %2, %4
-
-                        // let t6  = t5.pow2k(5);             // 9,8,7,6,5
-                        psa %28, #5       // coincidentally, constant #5 is the number 5
-                        mul %6, %5, %5
-                    pow2k_5:
-                        sub %28, %28, #1  // %28 = %28 - 1
-                        brz pow2k_5_exit, %28
-                        mul %6, %6, %6
-                        brz pow2k_5, #0
-                    pow2k_5_exit:
-                        // let t7  = &t6 * &t5;               // 9,8,7,6,5,4,3,2,1,0
-                        mul %7, %6, %5
-
-                        // let t8  = t7.pow2k(10);            // 19..10
-                        psa %28, #6        // constant #6 is the number 10
-                        mul %8, %7, %7
-                    pow2k_10:
-                        sub %28, %28, #1
-                        brz pow2k_10_exit, %28
-                        mul %8, %8, %8
-                        brz pow2k_10, #0
-                    pow2k_10_exit:
-                        // let t9  = &t8 * &t7;               // 19..0
-                        mul %9, %8, %7
-
-                        // let t10 = t9.pow2k(20);            // 39..20
-                        psa %28, #7         // constant #7 is the number 20
-                        mul %10, %9, %9
-                    pow2k_20:
-                        sub %28, %28, #1
-                        brz pow2k_20_exit, %28
-                        mul %10, %10, %10
-                        brz pow2k_20, #0
-                    pow2k_20_exit:
-                        // let t11 = &t10 * &t9;              // 39..0
-                        mul %11, %10, %9
-
-                        // let t12 = t11.pow2k(10);           // 49..10
-                        psa %28, #6         // constant #6 is the number 10
-                        mul %12, %11, %11
-                    pow2k_10b:
-                        sub %28, %28, #1
-                        brz pow2k_10b_exit, %28
-                        mul %12, %12, %12
-                        brz pow2k_10b, #0
-                    pow2k_10b_exit:
-                        // let t13 = &t12 * &t7;              // 49..0
-                        mul %13, %12, %7
-
-                        // let t14 = t13.pow2k(50);           // 99..50
-                        psa %28, #8         // constant #8 is the number 50
-                        mul %14, %13, %13
-                    pow2k_50a:
-                        sub %28, %28, #1
-                        brz pow2k_50a_exit, %28
-                        mul %14, %14, %14
-                        brz pow2k_50a, #0
-                    pow2k_50a_exit:
-                        // let t15 = &t14 * &t13;             // 99..0
-                        mul %15, %14, %13
-
-                        // let t16 = t15.pow2k(100);          // 199..100
-                        psa %28, #9         // constant #9 is the number 100
-                        mul %16, %15, %15
-                    pow2k_100:
-                        sub %28, %28, #1
-                        brz pow2k_100_exit, %28
-                        mul %16, %16, %16
-                        brz pow2k_100, #0
-                    pow2k_100_exit:
-                        // let t17 = &t16 * &t15;             // 199..0
-                        mul %17, %16, %15
-
-                        // let t18 = t17.pow2k(50);           // 249..50
-                        psa %28, #8         // constant #8 is the number 50
-                        mul %18, %17, %17
-                    pow2k_50b:
-                        sub %28, %28, #1
-                        brz pow2k_50b_exit, %28
-                        mul %18, %18, %18
-                        brz pow2k_50b, #0
-                    pow2k_50b_exit:
-                        // let t19 = &t18 * &t13;             // 249..0
-                        mul %19, %18, %13
-                        //(t19, t3) // just a return value, values are already there, do nothing
-
-                        //let t20 = t19.pow2k(5);            // 254..5
-                        psa %28, #5
-                        mul %20, %19, %19
-                    pow2k_5_last:
-                        sub %28, %28, #1
-                        brz pow2k_5_last_exit, %28
-                        mul %20, %20, %20
-                        brz pow2k_5_last, #0
-                    pow2k_5_last_exit:
-
-                        //let t21 = &t20 * &t3;              // 254..5,3,1,0
-                        mul %21, %20, %3
-
-                    // u = &self.U * &self.W.invert()
-                    mul %31, %29, %21
-                    fin  // finish execution
-            );
+            let mcode = montgomery_ucode!();
             for (&src, dst) in mcode.iter().zip(self.ucode_hw[mpstart as usize..].iter_mut()) {
                 unsafe { (dst as *mut u32).write_volatile(src as u32) };
             }
@@ -658,6 +1101,50 @@ mod implementation {
             DISALLOW_SUSPEND.store(false, Ordering::Relaxed);
         }
 
+        fn load_edwards(&mut self, mpstart: usize) -> usize {
+            let mcode = edwards_ucode!();
+            for (&src, dst) in mcode.iter().zip(self.ucode_hw[mpstart..].iter_mut()) {
+                unsafe { (dst as *mut u32).write_volatile(src as u32) };
+            }
+            mcode.len()
+        }
+        pub fn edwards(&mut self, job: EdwardsJob) {
+            log::trace!("entering edwards");
+            // block any suspends from happening while we set up the engine
+            DISALLOW_SUSPEND.store(true, Ordering::Relaxed);
+
+            let window: usize = 0;
+
+            // the microcode takes y and the sign of x separately
+            let mut y = job.point;
+            let mut sign = [0u8; 32];
+            sign[0] = y[31] >> 7;
+            y[31] &= 0x7f;
+            self.copy_reg(y, 20, window);
+            self.copy_reg(sign, 21, window);
+            self.copy_reg(crate::ED25519_D, 22, window);
+            self.copy_reg(crate::SQRT_M1, 23, window);
+            self.copy_reg(job.scalar, 31, window);
+
+            let mpstart = crate::EDWARDS_MPSTART;
+            if self.edwards_len.is_none() {
+                self.edwards_len = Some(self.load_edwards(mpstart));
+            }
+            self.csr.wfo(utra::engine::WINDOW_WINDOW, window as u32);
+            self.csr.wfo(utra::engine::MPSTART_MPSTART, mpstart as u32);
+            self.csr.wfo(utra::engine::MPLEN_MPLEN, self.edwards_len.unwrap() as u32);
+
+            // sync calls poll a state variable, and thus no message is sent
+            self.do_notify = false;
+
+            // setup the sync polling variable
+            RUN_IN_PROGRESS.store(true, Ordering::Relaxed);
+            self.csr.wfo(utra::engine::CONTROL_GO, 1);
+
+            // we are now in a stable config, suspends are allowed
+            DISALLOW_SUSPEND.store(false, Ordering::Relaxed);
+        }
+
         pub fn get_result(&mut self) -> JobResult {
             if let Some(clean_resume) = self.clean_resume {
                 if !clean_resume {
@@ -707,34 +1194,136 @@ mod implementation {
     }
 }
 
-// a stub to try to avoid breaking hosted mode for as long as possible.
+// hosted mode runs jobs on a software model of the engine; see emulator.rs
 #[cfg(not(any(target_os = "none", target_os = "xous")))]
 mod implementation {
     use crate::api::*;
+    use crate::emulator::{Engine, Exit};
+    use num_traits::*;
+    use core::convert::TryInto;
 
     pub struct Engine25519Hw {
+        engine: Engine,
+        handler_conn: xous::CID,
+        window: usize,
+        illegal_opcode: bool,
+        montgomery_len: Option<usize>,
+        edwards_len: Option<usize>,
     }
 
     impl Engine25519Hw {
-        pub fn new(_handler_conn: xous::CID) -> Engine25519Hw {
+        pub fn new(handler_conn: xous::CID) -> Engine25519Hw {
             Engine25519Hw {
+                engine: Engine::new(),
+                handler_conn,
+                window: 0,
+                illegal_opcode: false,
+                montgomery_len: None,
+                edwards_len: None,
             }
         }
         pub fn suspend(&self) {
         }
         pub fn resume(&self) {
         }
-        pub fn run(&mut self, _job: Job) {
+        pub fn power_on(&mut self, _on: bool) {
         }
-        pub fn get_result(&mut self) -> JobResult {
-            JobResult::IllegalOpcodeException
+        /// Runs the microcode to completion, then reports the way the hardware's interrupt would.
+        /// `RUN_IN_PROGRESS` is never set, as the job is done by the time this returns.
+        fn execute(&mut self, start: usize, len: usize, notify: bool) {
+            let exit = self.engine.run(self.window, start, len);
+            self.illegal_opcode = match exit {
+                Exit::Finished => false,
+                Exit::IllegalOpcode(mpc) => {
+                    log::warn!("illegal opcode at mpc 0x{:03x}", mpc);
+                    true
+                }
+                Exit::Runaway => {
+                    log::warn!("microcode at 0x{:03x} didn't finish; is it missing a fin?", start);
+                    true
+                }
+            };
+            if notify {
+                let op = if self.illegal_opcode { Opcode::IllegalOpcode } else { Opcode::EngineDone };
+                xous::send_message(self.handler_conn,
+                    xous::Message::new_scalar(op.to_usize().unwrap(), 0, 0, 0, 0)).map(|_|()).unwrap();
+            }
         }
-        pub fn power_on(&mut self, _on: bool) {
+        pub fn run(&mut self, job: Job) {
+            self.montgomery_len = None;
+            self.edwards_len = None;
+            // this should "just panic" if we have a bad window arg, which is the desired behavior
+            self.window = job.window.unwrap_or(0) as usize;
+            self.engine.window_mut(self.window).copy_from_slice(&job.rf);
+            self.engine.ucode_mut().copy_from_slice(&job.ucode);
+            self.execute(job.uc_start as usize, job.uc_len as usize, job.id.is_some());
+        }
+        fn copy_reg(&mut self, r: [u8; 32], ra: usize) {
+            for (src, dst) in r.chunks_exact(4).zip(self.engine.window_mut(self.window)[ra * 8..(ra+1) * 8].iter_mut()) {
+                *dst = u32::from_le_bytes(src[0..4].try_into().unwrap());
+            }
+        }
+        pub fn montgomery(&mut self, job: MontgomeryJob) {
+            self.window = 0;
+            self.copy_reg(job.x0_u, 25);
+            self.copy_reg(job.x0_w, 26);
+            self.copy_reg(job.x1_u, 27);
+            self.copy_reg(job.x1_w, 28);
+            self.copy_reg(job.affine_u, 24);
+            self.copy_reg(job.scalar, 31);
+            let mut counter = [0u8; 32];
+            counter[0] = 254; // loop counter
+            self.copy_reg(counter, 19);
+
+            if self.montgomery_len.is_none() {
+                let mcode = montgomery_ucode!();
+                for (&src, dst) in mcode.iter().zip(self.engine.ucode_mut().iter_mut()) {
+                    *dst = src as u32;
+                }
+                self.montgomery_len = Some(mcode.len());
+            }
+            self.execute(0, self.montgomery_len.unwrap(), false);
         }
-        pub fn montgomery(&mut self, _job: MontgomeryJob) {
+        pub fn edwards(&mut self, job: EdwardsJob) {
+            self.window = 0;
+            // the microcode takes y and the sign of x separately
+            let mut y = job.point;
+            let mut sign = [0u8; 32];
+            sign[0] = y[31] >> 7;
+            y[31] &= 0x7f;
+            self.copy_reg(y, 20);
+            self.copy_reg(sign, 21);
+            self.copy_reg(crate::ED25519_D, 22);
+            self.copy_reg(crate::SQRT_M1, 23);
+            self.copy_reg(job.scalar, 31);
+
+            let mpstart = crate::EDWARDS_MPSTART;
+            if self.edwards_len.is_none() {
+                let mcode = edwards_ucode!();
+                for (&src, dst) in mcode.iter().zip(self.engine.ucode_mut()[mpstart..].iter_mut()) {
+                    *dst = src as u32;
+                }
+                self.edwards_len = Some(mcode.len());
+            }
+            self.execute(mpstart, self.edwards_len.unwrap(), false);
         }
-        pub fn get_single_result(&mut self, _r: usize) -> JobResult {
-            JobResult::EngineUnavailable
+        pub fn get_result(&mut self) -> JobResult {
+            if self.illegal_opcode {
+                return JobResult::IllegalOpcodeException;
+            }
+            let mut ret_rf: [u32; RF_SIZE_IN_U32] = [0; RF_SIZE_IN_U32];
+            ret_rf.copy_from_slice(self.engine.window(self.window));
+            JobResult::Result(ret_rf)
+        }
+        pub fn get_single_result(&mut self, r: usize) -> JobResult {
+            if self.illegal_opcode {
+                return JobResult::IllegalOpcodeException;
+            }
+            let mut ret_r: [u8; 32] = [0; 32];
+            for (&src, dst) in self.engine.window(self.window)[r * 8..(r+1) * 8].iter().zip(ret_r.chunks_exact_mut(4)) {
+                dst.copy_from_slice(&src.to_le_bytes());
+            }
+            JobResult::SingleResult(ret_r)
         }
     }
 }
@@ -790,6 +1379,76 @@ fn susres_thread(engine_arg: usize) {
     xous::destroy_server(susres_sid).unwrap();
 }
 
+/// Collects the result of the running async job, if it's done, and sends it to its client
+fn retire_running(engine25519: &mut implementation::Engine25519Hw, queue: &mut JobQueue<Job>, clients: &mut HashMap<ClientId, xous::CID>) {
+    if !queue.is_running() || RUN_IN_PROGRESS.load(Ordering::Relaxed) {
+        // a stale notification, for a job already retired by `wait_for_engine`
+        return;
+    }
+    let result = engine25519.get_result();
+    let retired = queue.complete().unwrap();
+    if let Some(&cid) = clients.get(&retired.client) {
+        let buf = Buffer::into_buf(Completion { ticket: retired.ticket, result }).or(Err(xous::Error::InternalError)).unwrap();
+        if buf.send(cid, Return::Result.to_u32().unwrap()).is_err() {
+            log::warn!("couldn't return the result of job {} to its caller", retired.ticket);
+        }
+    }
+    if retired.released {
+        release_client(clients, &retired.client);
+    }
+}
+
+fn release_client(clients: &mut HashMap<ClientId, xous::CID>, client: &ClientId) {
+    if let Some(cid) = clients.remove(client) {
+        unsafe{xous::disconnect(cid).expect("couldn't disconnect from the caller");}
+    }
+}
+
+/// The result of `edwards_ucode!()`, compressed from the affine x and y it leaves in %30 and %31
+fn edwards_result(engine25519: &mut implementation::Engine25519Hw) -> JobResult {
+    match engine25519.get_single_result(29) {
+        JobResult::SingleResult(status) if status[0] != 0 => return JobResult::InvalidPoint,
+        JobResult::SingleResult(_) => (),
+        other => return other,
+    }
+    match (engine25519.get_single_result(30), engine25519.get_single_result(31)) {
+        (JobResult::SingleResult(x), JobResult::SingleResult(mut y)) => {
+            y[31] |= (x[0] & 1) << 7;
+            JobResult::SingleResult(y)
+        }
+        (other, _) => other,
+    }
+}
+
+/// Lets the running async job finish, so the engine can be used for something else
+fn wait_for_engine(engine25519: &mut implementation::Engine25519Hw, queue: &mut JobQueue<Job>, clients: &mut HashMap<ClientId, xous::CID>) {
+    while RUN_IN_PROGRESS.load(Ordering::Relaxed) {
+        xous::yield_slice();
+    }
+    retire_running(engine25519, queue, clients);
+}
+
+/// Starts the next queued async job, if the engine is free
+fn dispatch_next(engine25519: &mut implementation::Engine25519Hw, queue: &mut JobQueue<Job>) {
+    if queue.is_running() || RUN_IN_PROGRESS.load(Ordering::Relaxed) {
+        return;
+    }
+    // don't start a new job if a suspend is in progress
+    while SUSPEND_IN_PROGRESS.load(Ordering::Relaxed) {
+        log::trace!("waiting for suspend to finish");
+        xous::yield_slice();
+    }
+    if let Some(dispatch) = queue.dispatch() {
+        log::trace!("running async job {} for {:x?} in window {}", dispatch.ticket, dispatch.client, dispatch.window);
+        let mut job = dispatch.job;
+        job.window = Some(dispatch.window);
+        engine25519.power_on(true);
+        engine25519.run(job);
+    } else {
+        engine25519.power_on(false);
+    }
+}
+
 #[xous::xous_main]
 fn xmain() -> ! {
     use crate::implementation::Engine25519Hw;
@@ -811,7 +1470,10 @@ fn xmain() -> ! {
     // register a suspend/resume listener
     xous::create_thread_1(susres_thread, (&mut engine25519) as *mut Engine25519Hw as usize).expect("couldn't start susres handler thread");
 
-    let mut client_cid: Option<xous::CID> = None;
+    // async jobs wait here for the engine; window 0 is kept for sync, Montgomery and Edwards jobs
+    let mut queue: JobQueue<Job> = JobQueue::new(1..NUM_WINDOWS as u8);
+    // connections to the callback servers of clients with async jobs outstanding
+    let mut clients: HashMap<ClientId, xous::CID> = HashMap::new();
     let mut job_count = 0;
     let mut mont_count = 0;
     loop {
@@ -823,6 +1485,7 @@ fn xmain() -> ! {
                     log::info!("montgomery job {}", mont_count); // leave this here for now so we can confirm that HW acceleration is being selected when we think it is!
                 }
                 mont_count += 1;
+                wait_for_engine(&mut engine25519, &mut queue, &mut clients);
                 // don't start a new job if a suspend is in progress
                 while SUSPEND_IN_PROGRESS.load(Ordering::Relaxed) {
                     log::trace!("waiting for suspend to finish");
@@ -839,78 +1502,117 @@ fn xmain() -> ! {
                 let result = engine25519.get_single_result(31); // return the result
                 engine25519.power_on(false);
                 buffer.replace(result).unwrap();
+                dispatch_next(&mut engine25519, &mut queue);
             }
-            Some(Opcode::RunJob) => {
-                if job_count % 100 == 0 {
-                    log::info!("engine job {}", job_count); // leave this here for now so we can confirm that HW acceleration is being selected when we think it is!
-                }
-                job_count += 1;
+            Some(Opcode::EdwardsJob) => {
+                wait_for_engine(&mut engine25519, &mut queue, &mut clients);
                 // don't start a new job if a suspend is in progress
                 while SUSPEND_IN_PROGRESS.load(Ordering::Relaxed) {
                     log::trace!("waiting for suspend to finish");
                     xous::yield_slice();
                 }
                 engine25519.power_on(true);
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let edwards_job = buffer.to_original::<EdwardsJob, _>().unwrap();
+                engine25519.edwards(edwards_job);
+                while RUN_IN_PROGRESS.load(Ordering::Relaxed) {
+                    // block until the job is done
+                    xous::yield_slice();
+                }
+                let result = edwards_result(&mut engine25519);
+                engine25519.power_on(false);
+                buffer.replace(result).unwrap();
+                dispatch_next(&mut engine25519, &mut queue);
+            }
+            Some(Opcode::RunJob) => {
+                if job_count % 100 == 0 {
+                    log::info!("engine job {}", job_count); // leave this here for now so we can confirm that HW acceleration is being selected when we think it is!
+                }
+                job_count += 1;
 
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let job = buffer.to_original::<Job, _>().unwrap();
 
-                let response = if client_cid.is_none() {
-                    if let Some(job_id) = job.id {
-                        log::trace!("running async job");
-                        // async job
-                        // the presence of an ID indicates we are doing an async method
-                        client_cid = Some(xous::connect(xous::SID::from_array(job_id)).expect("couldn't connect to the caller's server"));
-                        engine25519.run(job);
-                        // just let the caller know we started a job, but don't return any results
-                        JobResult::Started
-                    } else {
-                        // log::trace!("running sync job {:?}", job);
-                        // sync job
-                        // start the job, which should set RUN_IN_PROGRESS to true
-                        engine25519.run(job);
-                        while RUN_IN_PROGRESS.load(Ordering::Relaxed) {
-                            // block until the job is done
-                            xous::yield_slice();
+                let response = if let Some(job_id) = job.id {
+                    // async job: the presence of an ID indicates we are doing an async method
+                    match queue.submit(job_id, job) {
+                        Ok(ticket) => {
+                            log::trace!("queued async job {}", ticket);
+                            if !clients.contains_key(&job_id) {
+                                clients.insert(job_id, xous::connect(xous::SID::from_array(job_id)).expect("couldn't connect to the caller's server"));
+                            }
+                            // just let the caller know we queued the job, but don't return any results
+                            JobResult::Queued(ticket)
+                        }
+                        Err(e) => {
+                            log::debug!("async job rejected: {:?}", e);
+                            JobResult::EngineUnavailable
                         }
-                        let result = engine25519.get_result(); // return the result
-                        engine25519.power_on(false);
-                        result
                     }
                 } else {
-                    JobResult::EngineUnavailable
+                    // sync job: wait for any async job that's running, then run it ahead of the queue
+                    wait_for_engine(&mut engine25519, &mut queue, &mut clients);
+                    while SUSPEND_IN_PROGRESS.load(Ordering::Relaxed) {
+                        log::trace!("waiting for suspend to finish");
+                        xous::yield_slice();
+                    }
+                    engine25519.power_on(true);
+                    // start the job, which should set RUN_IN_PROGRESS to true
+                    engine25519.run(job);
+                    while RUN_IN_PROGRESS.load(Ordering::Relaxed) {
+                        // block until the job is done
+                        xous::yield_slice();
+                    }
+                    let result = engine25519.get_result(); // return the result
+                    engine25519.power_on(false);
+                    result
                 };
                 buffer.replace(response).unwrap();
+                dispatch_next(&mut engine25519, &mut queue);
             },
             Some(Opcode::IsFree) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
-                if client_cid.is_none() {
+                if queue.is_idle() {
                     xous::return_scalar(msg.sender, 1).expect("couldn't return IsIdle query");
                 } else {
                     xous::return_scalar(msg.sender, 0).expect("couldn't return IsIdle query");
                 }
             }),
-            Some(Opcode::EngineDone) => {
-                if let Some(cid) = client_cid {
-                    let result = engine25519.get_result();
-                    let buf = Buffer::into_buf(result).or(Err(xous::Error::InternalError)).unwrap();
-                    buf.send(cid, Return::Result.to_u32().unwrap()).expect("couldn't return result to caller");
-
-                    // this simultaneously releases the lock and disconnects from the caller
-                    unsafe{xous::disconnect(client_cid.take().unwrap()).expect("couldn't disconnect from the caller");}
-                } else {
-                    log::error!("illegal state: got a result, but no client was registered. Did we forget to disable interrupts on a synchronous call??");
-                }
-                engine25519.power_on(false);
-            },
-            Some(Opcode::IllegalOpcode) => {
-                if let Some(cid) = client_cid {
-                    let buf = Buffer::into_buf(JobResult::IllegalOpcodeException).or(Err(xous::Error::InternalError)).unwrap();
-                    buf.send(cid, Return::Result.to_u32().unwrap()).expect("couldn't return result to caller");
-                } else {
-                    log::error!("illegal state: got a result, but no client was registered. Did we forget to disable interrupts on a synchronous call??");
+            Some(Opcode::QueueStatus) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut status = buffer.to_original::<QueueStatus, _>().unwrap();
+                let stats = queue.stats();
+                status.depth = queue.depth() as u32;
+                status.pending = status.id.map(|id| queue.pending_for(&id) as u32).unwrap_or(0);
+                status.running = queue.is_running();
+                status.clients = queue.clients() as u32;
+                status.free_windows = queue.free_windows() as u32;
+                status.completed = stats.completed;
+                status.cancelled = stats.cancelled;
+                status.rejected = stats.rejected;
+                buffer.replace(status).unwrap();
+            }
+            Some(Opcode::CancelJob) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut request = buffer.to_original::<CancelRequest, _>().unwrap();
+                match queue.cancel(&request.id, request.ticket) {
+                    Ok(retired) => {
+                        request.cancelled = true;
+                        if retired.released {
+                            release_client(&mut clients, &retired.client);
+                        }
+                    }
+                    Err(e) => {
+                        log::debug!("couldn't cancel job {}: {:?}", request.ticket, e);
+                        request.cancelled = false;
+                    }
                 }
-                engine25519.power_on(false);
+                buffer.replace(request).unwrap();
             }
+            // an illegal opcode is reported to the client through the job's result
+            Some(Opcode::EngineDone) | Some(Opcode::IllegalOpcode) => {
+                retire_running(&mut engine25519, &mut queue, &mut clients);
+                dispatch_next(&mut engine25519, &mut queue);
+            },
             Some(Opcode::Quit) => {
                 log::info!("Received quit opcode, exiting!");
                 engine25519.power_on(false);
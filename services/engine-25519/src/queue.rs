@@ -0,0 +1,344 @@
+//! Scheduling for async jobs, so that several clients can share the engine.
+//!
+//! A client is identified by the SID it wants its results sent to (`Job.id`). While a client
+//! has jobs outstanding it leases one of the engine's register windows, and all of its jobs run
+//! in that window; a `Job`'s register file is window-relative, so this is invisible to the
+//! client. Window 0 is never leased: sync jobs and `MontgomeryJob`s run there.
+//!
+//! Clients are served round-robin, one job per turn, so a client with a deep queue can't starve
+//! the others.
+
+use std::collections::VecDeque;
+
+pub type ClientId = [u32; 4];
+
+/// How many async jobs can wait at once, across all clients. Each one holds a whole `Job`
+/// (about 5 KiB), which is what limits this.
+pub const MAX_QUEUE_DEPTH: usize = 16;
+/// How many jobs one client can have waiting
+pub const MAX_JOBS_PER_CLIENT: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SubmitError {
+    /// the queue is at `MAX_QUEUE_DEPTH`
+    QueueFull,
+    /// the client is at `MAX_JOBS_PER_CLIENT`
+    ClientLimit,
+    /// every register window is leased to another client
+    NoWindow,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CancelError {
+    /// no such job for this client; it may have already finished
+    NotFound,
+    /// the job is on the engine, which can't be stopped part way
+    Running,
+}
+
+/// A job that's been taken off the queue to run
+pub struct Dispatch<J> {
+    pub ticket: u32,
+    pub client: ClientId,
+    pub window: u8,
+    pub job: J,
+}
+
+/// What happened to the client of a job that's left the queue
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Retired {
+    pub ticket: u32,
+    pub client: ClientId,
+    /// the client has nothing else outstanding, and its window has been given back
+    pub released: bool,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub completed: u64,
+    pub cancelled: u64,
+    pub rejected: u64,
+}
+
+struct Client<J> {
+    id: ClientId,
+    window: u8,
+    pending: VecDeque<(u32, J)>,
+}
+
+pub struct JobQueue<J> {
+    /// in round-robin order: the client at the front is served next
+    clients: VecDeque<Client<J>>,
+    /// the client that owns the running job, which keeps its lease until the job retires
+    running: Option<(u32, Client<J>)>,
+    free_windows: Vec<u8>,
+    next_ticket: u32,
+    stats: Stats,
+}
+
+impl<J> JobQueue<J> {
+    /// `windows` are the register windows that can be leased to clients
+    pub fn new(windows: core::ops::Range<u8>) -> Self {
+        JobQueue {
+            clients: VecDeque::new(),
+            running: None,
+            // handed out from the back, so reverse to hand out the lowest first
+            free_windows: windows.rev().collect(),
+            next_ticket: 1,
+            stats: Stats::default(),
+        }
+    }
+
+    fn client_mut(&mut self, id: &ClientId) -> Option<&mut Client<J>> {
+        if let Some((_, client)) = self.running.as_mut() {
+            if &client.id == id {
+                return Some(client);
+            }
+        }
+        self.clients.iter_mut().find(|c| &c.id == id)
+    }
+
+    /// Queues `job` for `client`, and returns the ticket that identifies it
+    pub fn submit(&mut self, client: ClientId, job: J) -> Result<u32, SubmitError> {
+        let result = self.try_submit(client, job);
+        if result.is_err() {
+            self.stats.rejected += 1;
+        }
+        result
+    }
+
+    fn try_submit(&mut self, client: ClientId, job: J) -> Result<u32, SubmitError> {
+        if self.depth() >= MAX_QUEUE_DEPTH {
+            return Err(SubmitError::QueueFull);
+        }
+        let ticket = self.next_ticket;
+        if let Some(existing) = self.client_mut(&client) {
+            if existing.pending.len() >= MAX_JOBS_PER_CLIENT {
+                return Err(SubmitError::ClientLimit);
+            }
+            existing.pending.push_back((ticket, job));
+        } else {
+            let window = self.free_windows.pop().ok_or(SubmitError::NoWindow)?;
+            let mut pending = VecDeque::new();
+            pending.push_back((ticket, job));
+            self.clients.push_back(Client { id: client, window, pending });
+        }
+        self.next_ticket = self.next_ticket.wrapping_add(1).max(1);
+        Ok(ticket)
+    }
+
+    /// Takes the next job to run, if the engine isn't already busy with one of ours
+    pub fn dispatch(&mut self) -> Option<Dispatch<J>> {
+        if self.running.is_some() {
+            return None;
+        }
+        let mut client = self.clients.pop_front()?;
+        let (ticket, job) = client.pending.pop_front().expect("idle client left in the queue");
+        let dispatch = Dispatch { ticket, client: client.id, window: client.window, job };
+        self.running = Some((ticket, client));
+        Some(dispatch)
+    }
+
+    /// Marks the running job as done. Returns `None` if nothing was running.
+    pub fn complete(&mut self) -> Option<Retired> {
+        let (ticket, client) = self.running.take()?;
+        self.stats.completed += 1;
+        Some(self.requeue(ticket, client))
+    }
+
+    /// Puts a client at the back of the rotation, or lets it go if it has nothing left
+    fn requeue(&mut self, ticket: u32, client: Client<J>) -> Retired {
+        let id = client.id;
+        if client.pending.is_empty() {
+            self.free_windows.push(client.window);
+            Retired { ticket, client: id, released: true }
+        } else {
+            self.clients.push_back(client);
+            Retired { ticket, client: id, released: false }
+        }
+    }
+
+    /// Drops a job that hasn't started yet. Only the client that submitted it can cancel it.
+    pub fn cancel(&mut self, client: &ClientId, ticket: u32) -> Result<Retired, CancelError> {
+        if let Some((running, owner)) = self.running.as_mut() {
+            if *running == ticket && &owner.id == client {
+                return Err(CancelError::Running);
+            }
+            if &owner.id == client {
+                // the client keeps its lease regardless, as its running job still needs it
+                if let Some(pos) = owner.pending.iter().position(|(t, _)| *t == ticket) {
+                    owner.pending.remove(pos);
+                    self.stats.cancelled += 1;
+                    return Ok(Retired { ticket, client: *client, released: false });
+                }
+                return Err(CancelError::NotFound);
+            }
+        }
+        let index = self.clients.iter().position(|c| &c.id == client).ok_or(CancelError::NotFound)?;
+        let pos = self.clients[index].pending.iter().position(|(t, _)| *t == ticket).ok_or(CancelError::NotFound)?;
+        self.clients[index].pending.remove(pos);
+        self.stats.cancelled += 1;
+        if self.clients[index].pending.is_empty() {
+            let c = self.clients.remove(index).unwrap();
+            self.free_windows.push(c.window);
+            Ok(Retired { ticket, client: *client, released: true })
+        } else {
+            Ok(Retired { ticket, client: *client, released: false })
+        }
+    }
+
+    /// Jobs waiting to run, not counting the running one
+    pub fn depth(&self) -> usize {
+        self.clients.iter().map(|c| c.pending.len()).sum::<usize>()
+            + self.running.as_ref().map(|(_, c)| c.pending.len()).unwrap_or(0)
+    }
+    /// Jobs `client` has waiting to run
+    pub fn pending_for(&self, client: &ClientId) -> usize {
+        self.running.iter().map(|(_, c)| c).chain(self.clients.iter())
+            .find(|c| &c.id == client)
+            .map(|c| c.pending.len())
+            .unwrap_or(0)
+    }
+    /// Clients with jobs outstanding, each of which holds a window
+    pub fn clients(&self) -> usize {
+        self.clients.len() + if self.running.is_some() { 1 } else { 0 }
+    }
+    pub fn free_windows(&self) -> usize {
+        self.free_windows.len()
+    }
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+    pub fn is_idle(&self) -> bool {
+        self.running.is_none() && self.clients.is_empty()
+    }
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: ClientId = [1, 0, 0, 0];
+    const B: ClientId = [2, 0, 0, 0];
+    const C: ClientId = [3, 0, 0, 0];
+
+    fn run_all(q: &mut JobQueue<&'static str>) -> Vec<&'static str> {
+        let mut order = Vec::new();
+        while let Some(d) = q.dispatch() {
+            order.push(d.job);
+            q.complete();
+        }
+        order
+    }
+
+    #[test]
+    fn round_robin_between_clients() {
+        let mut q = JobQueue::new(1..16);
+        for job in ["a1", "a2", "a3"] {
+            q.submit(A, job).unwrap();
+        }
+        q.submit(B, "b1").unwrap();
+        q.submit(C, "c1").unwrap();
+        q.submit(C, "c2").unwrap();
+        assert_eq!(q.depth(), 6);
+        assert_eq!(q.clients(), 3);
+        assert_eq!(run_all(&mut q), ["a1", "b1", "c1", "a2", "c2", "a3"]);
+        assert!(q.is_idle());
+        assert_eq!(q.free_windows(), 15);
+        assert_eq!(q.stats().completed, 6);
+    }
+
+    #[test]
+    fn windows_are_leased_per_client() {
+        let mut q = JobQueue::new(1..3);
+        q.submit(A, "a1").unwrap();
+        q.submit(B, "b1").unwrap();
+        assert_eq!(q.submit(C, "c1"), Err(SubmitError::NoWindow));
+        // a client keeps its window from submission until its last job retires
+        let a = q.dispatch().unwrap();
+        assert_eq!(a.window, 1);
+        q.submit(A, "a2").unwrap();
+        assert_eq!(q.complete(), Some(Retired { ticket: a.ticket, client: A, released: false }));
+        let b = q.dispatch().unwrap();
+        assert_eq!(b.window, 2);
+        assert!(q.complete().unwrap().released);
+        q.submit(C, "c1").unwrap();
+        assert_eq!(q.dispatch().unwrap().window, 1);
+        q.complete();
+        assert_eq!(q.dispatch().unwrap().window, 2);
+        assert!(q.complete().unwrap().released);
+        assert_eq!(q.stats().rejected, 1);
+    }
+
+    #[test]
+    fn limits() {
+        let mut q = JobQueue::new(1..16);
+        for _ in 0..MAX_JOBS_PER_CLIENT {
+            q.submit(A, "a").unwrap();
+        }
+        assert_eq!(q.submit(A, "a"), Err(SubmitError::ClientLimit));
+        for i in 0..(MAX_QUEUE_DEPTH - MAX_JOBS_PER_CLIENT) {
+            q.submit([10 + (i / MAX_JOBS_PER_CLIENT) as u32, 0, 0, 0], "x").unwrap();
+        }
+        assert_eq!(q.submit(B, "b"), Err(SubmitError::QueueFull));
+        // taking one off to run makes room
+        q.dispatch().unwrap();
+        q.submit(B, "b").unwrap();
+    }
+
+    #[test]
+    fn cancellation() {
+        let mut q = JobQueue::new(1..16);
+        let a1 = q.submit(A, "a1").unwrap();
+        let a2 = q.submit(A, "a2").unwrap();
+        let b1 = q.submit(B, "b1").unwrap();
+        // only the owner can cancel
+        assert_eq!(q.cancel(&B, a2), Err(CancelError::NotFound));
+        assert_eq!(q.dispatch().unwrap().ticket, a1);
+        assert_eq!(q.cancel(&A, a1), Err(CancelError::Running));
+        assert_eq!(q.cancel(&A, a2), Ok(Retired { ticket: a2, client: A, released: false }));
+        assert_eq!(q.pending_for(&A), 0);
+        assert!(q.complete().unwrap().released);
+        assert_eq!(q.cancel(&B, b1), Ok(Retired { ticket: b1, client: B, released: true }));
+        assert!(q.is_idle());
+        assert_eq!(q.free_windows(), 15);
+        assert_eq!(q.stats().cancelled, 2);
+    }
+
+    /// Two clients sharing the emulated engine, each in its own window
+    #[test]
+    fn jobs_on_the_emulator() {
+        use crate::api::RF_SIZE_IN_U32;
+        use crate::emulator::{Engine, Exit};
+
+        // add %31, %0, %1 ; fin
+        let add = 5 | (1 << 12) | (31 << 18);
+        let fin = 10;
+        let mut engine = Engine::new();
+        engine.ucode_mut()[0] = add;
+        engine.ucode_mut()[1] = fin;
+
+        let mut q = JobQueue::new(1..16);
+        let mut expected = std::collections::HashMap::new();
+        for i in 0..3u32 {
+            for client in [A, B] {
+                let mut rf = [0u32; RF_SIZE_IN_U32];
+                rf[0] = client[0] * 100;
+                rf[8] = i;
+                let ticket = q.submit(client, rf).unwrap();
+                expected.insert(ticket, client[0] * 100 + i);
+            }
+        }
+        while let Some(d) = q.dispatch() {
+            assert!(d.window != 0);
+            engine.window_mut(d.window as usize).copy_from_slice(&d.job);
+            assert_eq!(engine.run(d.window as usize, 0, 2), Exit::Finished);
+            assert_eq!(engine.window(d.window as usize)[31 * 8], expected[&d.ticket]);
+            q.complete();
+        }
+        assert_eq!(q.stats().completed, 6);
+    }
+}
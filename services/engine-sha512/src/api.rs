@@ -16,7 +16,7 @@ pub(crate) struct Sha2Finalize {
     pub length_in_bits: Option<u64>,
 }
 
-#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug, Copy, Clone)]
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug, Copy, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub(crate) enum Sha2Config {
     Sha512,
     Sha512Trunc256,
@@ -29,6 +29,54 @@ pub(crate) struct Sha2Update {
     pub len: u16,           // length of just this buffer, fits in 16 bits
 }
 
+/// low bits of the `AcquireExclusive` flags word carry the `Sha2Config`
+#[allow(dead_code)]
+pub(crate) const ACQUIRE_CONFIG_MASK: usize = 0xFF;
+/// if the hardware is busy, the server may keep the hash in one of its software contexts instead
+pub(crate) const ACQUIRE_FLAG_PARK: usize = 0x100;
+/// `AcquireExclusive` return codes
+pub(crate) const ACQUIRED_NONE: usize = 0;
+#[allow(dead_code)] // clients only need to tell a refusal apart
+pub(crate) const ACQUIRED_HARDWARE: usize = 1;
+#[allow(dead_code)]
+pub(crate) const ACQUIRED_PARKED: usize = 2;
+
+/// A SHA-512 (or SHA-512/256) hash in progress, as plain data. The hardware can't load or
+/// export a partial hash, so this is how a hash is carried between connections, or moved
+/// out of the server: see `save()` and `restore()` on the hashers.
+#[derive(Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct Sha2Context {
+    pub(crate) config: Sha2Config,
+    pub(crate) state: [u64; 8],
+    /// bytes that don't yet make up a whole block
+    pub(crate) block: [u8; 128],
+    pub(crate) filled: u8,
+    pub(crate) length_in_bits: u64,
+}
+
+/// Why a hash context couldn't be saved or restored
+#[derive(Debug, Copy, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum ContextError {
+    /// The hash is running on the hardware and has been fed data. The engine can neither hand out
+    /// nor take in a partial hash, so the hash has to be finished where it is. Hashers made with
+    /// `FallbackStrategy::SoftwareOnly`, or parked by the server, can always be saved.
+    InHardware,
+    /// the context was saved from a different kind of hash
+    WrongKind,
+    /// the context is damaged: its buffered bytes don't add up with its length
+    Invalid,
+}
+
+/// Used by `SaveContext` and `RestoreContext`
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct Sha2Park {
+    pub id: [u32; 3],
+    /// `SaveContext` fills this in if it can; `RestoreContext` empties it if the server took the context
+    pub context: Option<Sha2Context>,
+    /// why `SaveContext` couldn't hand the context out, or `RestoreContext` wouldn't take it in
+    pub error: Option<ContextError>,
+}
+
 #[allow(dead_code)]
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
 pub(crate) enum Opcode {
@@ -38,7 +86,10 @@ pub(crate) enum Opcode {
     /// pcollision=10^-15 for 13,000,000 concurrent hash requests.
     /// for context, a typical consumer SSD has an uncorrectable bit error rate of 10^-15,
     /// and we probably expect about 3-4 concurrent hash requests in the worst case.
-    /// Acquisition will always fail if a Suspend request is pending.
+    /// Acquisition of the hardware will always fail if a Suspend request is pending.
+    /// With `ACQUIRE_FLAG_PARK`, a busy engine doesn't fail the request: the server
+    /// hashes the stream in one of its software contexts instead, up to `MAX_PARKED`
+    /// of them. Returns one of the `ACQUIRED_*` codes.
     AcquireExclusive,
 
     /// Used by higher level coordination processes to acquire a lock on the hardware unit
@@ -63,6 +114,15 @@ pub(crate) enum Opcode {
     /// a function that can be polled to determine if the block has been currently acquired
     IsIdle,
 
+    /// moves a hash out of the server, as a `Sha2Context`. Works for contexts parked in
+    /// software, and for hardware contexts that haven't been fed any data yet; anything else
+    /// fails with `ContextError::InHardware`.
+    SaveContext,
+    /// parks a `Sha2Context` in the server under a new ID, so its hash can carry on from there.
+    /// Restored hashes always run in software, since the engine can't be loaded with a partial
+    /// hash. Contexts that don't check out fail with `ContextError::Invalid`.
+    RestoreContext,
+
     /// exit the server
     Quit,
 }
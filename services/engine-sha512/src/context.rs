@@ -0,0 +1,173 @@
+//! Software SHA-512 over a `Sha2Context`.
+//!
+//! This is the same arithmetic as the `Engine512` software fallback, but the whole state lives in
+//! plain data, so a hash in progress can be handed to the server, or to another process, and
+//! picked up again later. It is shared by the client library (for its own fallback) and by the
+//! server (for the contexts it parks while the hardware is busy).
+
+use crate::api::{Sha2Config, Sha2Context};
+use crate::consts::{H512, H512_TRUNC_256};
+use crate::soft512::compress;
+
+impl Sha2Context {
+    pub(crate) fn new(config: Sha2Config) -> Sha2Context {
+        Sha2Context {
+            config,
+            state: initial_state(config),
+            block: [0; 128],
+            filled: 0,
+            length_in_bits: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut input: &[u8]) {
+        self.length_in_bits += (input.len() as u64) << 3;
+        let filled = self.filled as usize;
+        if filled != 0 {
+            let take = core::cmp::min(128 - filled, input.len());
+            self.block[filled..filled + take].copy_from_slice(&input[..take]);
+            input = &input[take..];
+            if filled + take < 128 {
+                self.filled = (filled + take) as u8;
+                return;
+            }
+            compress(&mut self.state, core::slice::from_ref(&self.block));
+            self.filled = 0;
+        }
+        let whole = input.len() - input.len() % 128;
+        for block in input[..whole].chunks_exact(128) {
+            let mut b = [0u8; 128];
+            b.copy_from_slice(block);
+            compress(&mut self.state, core::slice::from_ref(&b));
+        }
+        let rest = &input[whole..];
+        self.block[..rest.len()].copy_from_slice(rest);
+        self.filled = rest.len() as u8;
+    }
+
+    /// Pads and finishes the hash, returning the full 64-byte state. SHA-512/256 callers take the
+    /// first 32 bytes. The context is spent afterwards; `reset()` it to start over.
+    pub(crate) fn finalize(&mut self) -> [u8; 64] {
+        let filled = self.filled as usize;
+        self.block[filled] = 0x80;
+        for b in self.block[filled + 1..].iter_mut() {
+            *b = 0;
+        }
+        // the length field is 128 bits, but the top half is always zero for a u64 bit count
+        if filled >= 112 {
+            compress(&mut self.state, core::slice::from_ref(&self.block));
+            self.block = [0; 128];
+        }
+        self.block[120..].copy_from_slice(&self.length_in_bits.to_be_bytes());
+        compress(&mut self.state, core::slice::from_ref(&self.block));
+        self.filled = 0;
+
+        let mut hash = [0u8; 64];
+        for (dst, word) in hash.chunks_exact_mut(8).zip(self.state.iter()) {
+            dst.copy_from_slice(&word.to_be_bytes());
+        }
+        hash
+    }
+
+    pub(crate) fn reset(&mut self) {
+        *self = Sha2Context::new(self.config);
+    }
+
+    /// Whether the context is one that `update()` and `finalize()` can work with. Contexts
+    /// that come from another process are checked before they're used, since a bad one would
+    /// have the arithmetic index past the end of the block. (`config` needs no check here: a
+    /// bad tag doesn't get past deserialization.)
+    pub(crate) fn is_valid(&self) -> bool {
+        (self.filled as usize) < self.block.len()
+            && self.length_in_bits & 7 == 0
+            && (self.length_in_bits / 8) % 128 == self.filled as u64
+    }
+
+    /// How much has been hashed so far
+    pub fn length_in_bits(&self) -> u64 {
+        self.length_in_bits
+    }
+}
+
+fn initial_state(config: Sha2Config) -> [u64; 8] {
+    match config {
+        Sha2Config::Sha512 => H512,
+        Sha2Config::Sha512Trunc256 => H512_TRUNC_256,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn hash(config: Sha2Config, data: &[u8]) -> [u8; 64] {
+        let mut ctx = Sha2Context::new(config);
+        ctx.update(data);
+        ctx.finalize()
+    }
+
+    /// FIPS 180-2 examples
+    #[test]
+    fn known_answers() {
+        assert_eq!(
+            hash(Sha2Config::Sha512, b"abc").to_vec(),
+            unhex("ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f")
+        );
+        assert_eq!(
+            hash(Sha2Config::Sha512, b"").to_vec(),
+            unhex("cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e")
+        );
+        assert_eq!(
+            hash(Sha2Config::Sha512Trunc256, b"abc")[..32].to_vec(),
+            unhex("53048e2681941ef99b2e29b76b4c7dabe4c2d0c634fc6d46e0e2f13107e7af23")
+        );
+    }
+
+    #[test]
+    fn split_updates_and_saved_copies() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7 + 3) as u8).collect();
+        let whole = hash(Sha2Config::Sha512, &data);
+        // straddle the block boundary and the padding boundary in every way
+        for split in [0, 1, 111, 112, 127, 128, 129, 240, 256, 999, 1000].iter() {
+            let mut ctx = Sha2Context::new(Sha2Config::Sha512);
+            ctx.update(&data[..*split]);
+            let mut saved = ctx.clone();
+            ctx.update(&data[*split..]);
+            saved.update(&data[*split..]);
+            assert_eq!(ctx.finalize()[..], whole[..]);
+            assert_eq!(saved.finalize()[..], whole[..]);
+        }
+        for len in 110..=130 {
+            let mut ctx = Sha2Context::new(Sha2Config::Sha512);
+            for b in data[..len].iter() {
+                ctx.update(core::slice::from_ref(b));
+            }
+            assert_eq!(ctx.length_in_bits(), len as u64 * 8);
+            assert_eq!(ctx.finalize()[..], hash(Sha2Config::Sha512, &data[..len])[..]);
+        }
+    }
+
+    #[test]
+    fn validity() {
+        let mut ctx = Sha2Context::new(Sha2Config::Sha512);
+        assert!(ctx.is_valid());
+        for len in [1usize, 127, 128, 300].iter() {
+            ctx.update(&vec![0x5a; *len]);
+            assert!(ctx.is_valid());
+        }
+        let mut full = ctx.clone();
+        full.filled = 128;
+        full.length_in_bits = 128 * 8;
+        assert!(!full.is_valid());
+        let mut mismatched = ctx.clone();
+        mismatched.filled = (mismatched.filled + 1) % 128;
+        assert!(!mismatched.is_valid());
+        let mut odd_bits = ctx;
+        odd_bits.length_in_bits += 1;
+        assert!(!odd_bits.is_valid());
+    }
+}
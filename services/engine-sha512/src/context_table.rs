@@ -0,0 +1,259 @@
+//! Book-keeping for the hashes the server is running on behalf of its clients.
+//!
+//! At most one hash runs on the hardware at a time. The engine can't load or export a partial
+//! hash, so once a stream is on the hardware it stays there until it is finalized or reset. While
+//! it's busy, other streams can be parked in software contexts held by the server; those advance
+//! with every `Update` just like the hardware one, so any number of streams can interleave, and
+//! they can be moved out of the server (`save`) and back in (`restore`) at any point. Parked
+//! contexts are plain memory, so unlike the hardware hash they don't hold up a suspend.
+
+use crate::api::{ContextError, Sha2Config, Sha2Context};
+
+/// most hashes the server keeps in software at once
+pub(crate) const MAX_PARKED: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Grant {
+    Hardware,
+    Parked,
+}
+
+struct HardwareContext {
+    id: [u32; 3],
+    config: Sha2Config,
+    length_in_bits: u64,
+}
+
+pub(crate) struct ContextTable {
+    hardware: Option<HardwareContext>,
+    parked: Vec<([u32; 3], Sha2Context)>,
+}
+
+impl ContextTable {
+    pub(crate) fn new() -> ContextTable {
+        ContextTable { hardware: None, parked: Vec::new() }
+    }
+
+    /// Finds a home for a new stream: the hardware if it's free and `hardware_ok`, otherwise a
+    /// parked context if `may_park` and there's room. A stream that already has a home keeps it.
+    pub(crate) fn acquire(&mut self, id: [u32; 3], config: Sha2Config, may_park: bool, hardware_ok: bool) -> Option<Grant> {
+        if let Some(grant) = self.grant(&id) {
+            return Some(grant);
+        }
+        if self.hardware.is_none() && hardware_ok {
+            self.hardware = Some(HardwareContext { id, config, length_in_bits: 0 });
+            Some(Grant::Hardware)
+        } else if may_park && self.parked.len() < MAX_PARKED {
+            self.parked.push((id, Sha2Context::new(config)));
+            Some(Grant::Parked)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn grant(&self, id: &[u32; 3]) -> Option<Grant> {
+        if matches!(&self.hardware, Some(hw) if hw.id == *id) {
+            Some(Grant::Hardware)
+        } else if self.parked.iter().any(|(pid, _)| pid == id) {
+            Some(Grant::Parked)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn hardware_busy(&self) -> bool {
+        self.hardware.is_some()
+    }
+
+    pub(crate) fn hardware_config(&self) -> Option<Sha2Config> {
+        self.hardware.as_ref().map(|hw| hw.config)
+    }
+
+    pub(crate) fn parked_count(&self) -> usize {
+        self.parked.len()
+    }
+
+    fn parked_mut(&mut self, id: &[u32; 3]) -> Option<&mut Sha2Context> {
+        self.parked.iter_mut().find(|(pid, _)| pid == id).map(|(_, ctx)| ctx)
+    }
+
+    /// Hashes `data` into a parked stream, or notes its length for the hardware one. Returns
+    /// where the stream lives, so the caller knows whether to feed the hardware too.
+    pub(crate) fn update(&mut self, id: &[u32; 3], data: &[u8]) -> Option<Grant> {
+        if let Some(hw) = self.hardware.as_mut().filter(|hw| hw.id == *id) {
+            hw.length_in_bits += (data.len() as u64) << 3;
+            return Some(Grant::Hardware);
+        }
+        let ctx = self.parked_mut(id)?;
+        ctx.update(data);
+        Some(Grant::Parked)
+    }
+
+    /// Finishes a parked stream: its config, the full 64-byte state, and the number of bits hashed.
+    /// The stream keeps its context until it's released, same as the hardware.
+    pub(crate) fn finalize_parked(&mut self, id: &[u32; 3]) -> Option<(Sha2Config, [u8; 64], u64)> {
+        let ctx = self.parked_mut(id)?;
+        let length_in_bits = ctx.length_in_bits;
+        Some((ctx.config, ctx.finalize(), length_in_bits))
+    }
+
+    /// Drops a stream. Returns where it was, so the caller can reset the hardware if need be.
+    pub(crate) fn release(&mut self, id: &[u32; 3]) -> Option<Grant> {
+        let grant = self.grant(id)?;
+        match grant {
+            Grant::Hardware => self.hardware = None,
+            Grant::Parked => self.parked.retain(|(pid, _)| pid != id),
+        }
+        Some(grant)
+    }
+
+    /// Takes a stream out of the server. A hardware stream can only be taken before it has been
+    /// fed anything, since the engine can't give up a partial hash; the caller then resets it.
+    /// Returns `None` if there's no such stream.
+    pub(crate) fn save(&mut self, id: &[u32; 3]) -> Option<Result<(Sha2Context, Grant), ContextError>> {
+        match self.grant(id)? {
+            Grant::Hardware => {
+                let hw = self.hardware.as_ref().unwrap();
+                if hw.length_in_bits != 0 {
+                    return Some(Err(ContextError::InHardware));
+                }
+                let ctx = Sha2Context::new(hw.config);
+                self.hardware = None;
+                Some(Ok((ctx, Grant::Hardware)))
+            }
+            Grant::Parked => {
+                let index = self.parked.iter().position(|(pid, _)| pid == id)?;
+                Some(Ok((self.parked.remove(index).1, Grant::Parked)))
+            }
+        }
+    }
+
+    /// Parks a saved stream under `id`, taking it out of `context`. It's left where it is if the
+    /// ID is taken or there's no room, and refused outright if it doesn't check out.
+    pub(crate) fn restore(&mut self, id: [u32; 3], context: &mut Option<Sha2Context>) -> Result<(), ContextError> {
+        match context {
+            Some(ctx) if !ctx.is_valid() => return Err(ContextError::Invalid),
+            Some(_) if self.grant(&id).is_none() && self.parked.len() < MAX_PARKED => (),
+            _ => return Ok(()),
+        }
+        self.parked.push((id, context.take().unwrap()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn one_shot(config: Sha2Config, data: &[u8]) -> [u8; 64] {
+        let mut ctx = Sha2Context::new(config);
+        ctx.update(data);
+        ctx.finalize()
+    }
+
+    #[test]
+    fn hardware_then_parking() {
+        let mut table = ContextTable::new();
+        assert_eq!(table.acquire([1, 0, 0], Sha2Config::Sha512, true, true), Some(Grant::Hardware));
+        // asking again doesn't take a second home
+        assert_eq!(table.acquire([1, 0, 0], Sha2Config::Sha512, true, true), Some(Grant::Hardware));
+        assert_eq!(table.acquire([2, 0, 0], Sha2Config::Sha512, false, true), None);
+        for i in 0..MAX_PARKED as u32 {
+            assert_eq!(table.acquire([2, i, 0], Sha2Config::Sha512, true, true), Some(Grant::Parked));
+        }
+        assert_eq!(table.acquire([3, 0, 0], Sha2Config::Sha512, true, true), None);
+        assert_eq!(table.release(&[1, 0, 0]), Some(Grant::Hardware));
+        assert!(!table.hardware_busy());
+        // a suspend is pending: no hardware, and no room to park
+        assert_eq!(table.acquire([3, 0, 0], Sha2Config::Sha512, true, false), None);
+        assert_eq!(table.release(&[2, 0, 0]), Some(Grant::Parked));
+        assert_eq!(table.acquire([3, 0, 0], Sha2Config::Sha512, true, false), Some(Grant::Parked));
+        assert_eq!(table.release(&[9, 9, 9]), None);
+    }
+
+    #[test]
+    fn interleaved_streams() {
+        let mut table = ContextTable::new();
+        let a: Vec<u8> = (0..700u32).map(|i| i as u8).collect();
+        let b: Vec<u8> = (0..900u32).map(|i| (i * 3) as u8).collect();
+        assert_eq!(table.acquire([1, 1, 1], Sha2Config::Sha512, true, true), Some(Grant::Hardware));
+        assert_eq!(table.acquire([2, 2, 2], Sha2Config::Sha512, true, true), Some(Grant::Parked));
+        assert_eq!(table.acquire([3, 3, 3], Sha2Config::Sha512Trunc256, true, true), Some(Grant::Parked));
+        // both split into eight pieces, so the streams finish together
+        for (ca, cb) in a.chunks(97).zip(b.chunks(113)) {
+            assert_eq!(table.update(&[1, 1, 1], ca), Some(Grant::Hardware));
+            assert_eq!(table.update(&[2, 2, 2], ca), Some(Grant::Parked));
+            assert_eq!(table.update(&[3, 3, 3], cb), Some(Grant::Parked));
+        }
+        assert_eq!(table.update(&[4, 4, 4], &a), None);
+
+        let (config, hash, bits) = table.finalize_parked(&[2, 2, 2]).unwrap();
+        assert_eq!(config, Sha2Config::Sha512);
+        assert_eq!(bits, 700 * 8);
+        assert_eq!(hash[..], one_shot(Sha2Config::Sha512, &a)[..]);
+        let (config, hash, _) = table.finalize_parked(&[3, 3, 3]).unwrap();
+        assert_eq!(config, Sha2Config::Sha512Trunc256);
+        assert_eq!(hash[..32], one_shot(Sha2Config::Sha512Trunc256, &b)[..32]);
+        // the hardware stream is finalized by the engine, not here
+        assert!(table.finalize_parked(&[1, 1, 1]).is_none());
+    }
+
+    #[test]
+    fn save_and_restore() {
+        let mut table = ContextTable::new();
+        let data = b"the quick brown fox jumps over the lazy dog, again and again and again";
+        assert_eq!(table.acquire([1, 0, 0], Sha2Config::Sha512, true, true), Some(Grant::Hardware));
+        assert_eq!(table.acquire([2, 0, 0], Sha2Config::Sha512, true, true), Some(Grant::Parked));
+        table.update(&[1, 0, 0], &data[..10]);
+        table.update(&[2, 0, 0], &data[..10]);
+
+        // the hardware can't give up a partial hash
+        assert_eq!(table.save(&[1, 0, 0]).unwrap().err(), Some(ContextError::InHardware));
+        assert!(table.save(&[7, 0, 0]).is_none());
+        let (ctx, from) = table.save(&[2, 0, 0]).unwrap().unwrap();
+        assert_eq!(from, Grant::Parked);
+        assert_eq!(table.grant(&[2, 0, 0]), None);
+
+        // picked up again under another ID, e.g. by another process
+        let mut taken = Some(ctx.clone());
+        table.restore([1, 0, 0], &mut taken).unwrap();
+        assert!(taken.is_some());
+        let mut ctx = Some(ctx);
+        table.restore([5, 0, 0], &mut ctx).unwrap();
+        assert!(ctx.is_none());
+        table.update(&[5, 0, 0], &data[10..]);
+        let (_, hash, bits) = table.finalize_parked(&[5, 0, 0]).unwrap();
+        assert_eq!(bits, data.len() as u64 * 8);
+        assert_eq!(hash[..], one_shot(Sha2Config::Sha512, data)[..]);
+
+        // a hardware stream that hasn't started can be moved out
+        table.release(&[1, 0, 0]);
+        table.acquire([6, 0, 0], Sha2Config::Sha512Trunc256, true, true);
+        let (ctx, from) = table.save(&[6, 0, 0]).unwrap().unwrap();
+        assert_eq!(from, Grant::Hardware);
+        assert_eq!(ctx.config, Sha2Config::Sha512Trunc256);
+        assert!(!table.hardware_busy());
+        assert_eq!(table.parked_count(), 1);
+    }
+
+    #[test]
+    fn bad_contexts_are_refused() {
+        let mut table = ContextTable::new();
+        let mut ctx = Sha2Context::new(Sha2Config::Sha512);
+        ctx.update(&[1; 200]);
+        // anything past the end of the block would panic the server in `update()` or `finalize()`
+        for filled in [128u8, 200, 255].iter() {
+            let mut bad = ctx.clone();
+            bad.filled = *filled;
+            assert_eq!(table.restore([1, 0, 0], &mut Some(bad)), Err(ContextError::Invalid));
+        }
+        let mut bad = ctx.clone();
+        bad.length_in_bits = 3;
+        assert_eq!(table.restore([1, 0, 0], &mut Some(bad)), Err(ContextError::Invalid));
+        assert_eq!(table.parked_count(), 0);
+        table.restore([1, 0, 0], &mut Some(ctx)).unwrap();
+        table.update(&[1, 0, 0], &[1; 56]);
+        let (_, hash, _) = table.finalize_parked(&[1, 0, 0]).unwrap();
+        assert_eq!(hash[..], one_shot(Sha2Config::Sha512, &[1; 256])[..]);
+    }
+}
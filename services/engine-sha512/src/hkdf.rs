@@ -0,0 +1,152 @@
+//! HKDF (RFC 5869) over the hashes in this crate.
+
+use crate::hmac::{wipe, Hmac};
+use crate::{Sha256, Sha512};
+use digest::generic_array::typenum::Unsigned;
+use digest::generic_array::GenericArray;
+use digest::{BlockInput, FixedOutput, Update};
+
+pub type HkdfSha256 = Hkdf<Sha256>;
+pub type HkdfSha512 = Hkdf<Sha512>;
+
+/// The requested output is longer than 255 hash blocks, or a PRK isn't one hash long
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvalidLength;
+
+pub struct Hkdf<D: Update + FixedOutput + BlockInput> {
+    prk: GenericArray<u8, D::OutputSize>,
+    hasher: fn() -> D,
+}
+
+impl<D: Update + FixedOutput + BlockInput + Default> Hkdf<D> {
+    /// Extracts a PRK from `ikm`. A missing salt is a hash-length string of zeros.
+    pub fn new(salt: Option<&[u8]>, ikm: &[u8]) -> Self {
+        Self::new_with(salt, ikm, D::default)
+    }
+
+    pub fn from_prk(prk: &[u8]) -> Result<Self, InvalidLength> {
+        Self::from_prk_with(prk, D::default)
+    }
+}
+
+impl<D: Update + FixedOutput + BlockInput> Hkdf<D> {
+    /// Like `new()`, with hashers from `hasher`, e.g. to pick a `FallbackStrategy`.
+    pub fn new_with(salt: Option<&[u8]>, ikm: &[u8], hasher: fn() -> D) -> Self {
+        let zeros = GenericArray::<u8, D::OutputSize>::default();
+        let mut hmac = Hmac::new_with(salt.unwrap_or(&zeros), hasher);
+        hmac.update(ikm);
+        Hkdf { prk: hmac.finalize(), hasher }
+    }
+
+    /// Skips the extract step, for a PRK that's already uniformly random; it must be one hash long.
+    pub fn from_prk_with(prk: &[u8], hasher: fn() -> D) -> Result<Self, InvalidLength> {
+        if prk.len() != D::OutputSize::USIZE {
+            return Err(InvalidLength);
+        }
+        let mut key = GenericArray::<u8, D::OutputSize>::default();
+        key.copy_from_slice(prk);
+        Ok(Hkdf { prk: key, hasher })
+    }
+
+    pub fn prk(&self) -> &[u8] {
+        &self.prk
+    }
+
+    /// Fills `okm` with key material bound to `info`
+    pub fn expand(&self, info: &[u8], okm: &mut [u8]) -> Result<(), InvalidLength> {
+        let hash_len = D::OutputSize::USIZE;
+        if okm.len() > 255 * hash_len {
+            return Err(InvalidLength);
+        }
+        let mut previous = GenericArray::<u8, D::OutputSize>::default();
+        for (i, chunk) in okm.chunks_mut(hash_len).enumerate() {
+            let mut hmac = Hmac::new_with(&self.prk, self.hasher);
+            if i != 0 {
+                hmac.update(&previous);
+            }
+            hmac.update(info);
+            hmac.update(&[i as u8 + 1]);
+            previous = hmac.finalize();
+            chunk.copy_from_slice(&previous[..chunk.len()]);
+        }
+        wipe(&mut previous);
+        Ok(())
+    }
+}
+
+impl<D: Update + FixedOutput + BlockInput> Drop for Hkdf<D> {
+    fn drop(&mut self) {
+        wipe(&mut self.prk);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hmac::tests::soft512;
+    use wycheproof_records::{records, unhex};
+
+    /// RFC 5869 test cases 1 and 3 (SHA-256)
+    #[test]
+    fn rfc5869() {
+        let ikm = [0x0bu8; 22];
+        let hkdf = HkdfSha256::new(Some(&unhex("000102030405060708090a0b0c")), &ikm);
+        assert_eq!(hkdf.prk().to_vec(), unhex("077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5"));
+        let mut okm = [0u8; 42];
+        hkdf.expand(&unhex("f0f1f2f3f4f5f6f7f8f9"), &mut okm).unwrap();
+        assert_eq!(
+            okm.to_vec(),
+            unhex("3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865")
+        );
+
+        let hkdf = HkdfSha256::new(None, &ikm);
+        assert_eq!(hkdf.prk().to_vec(), unhex("19ef24a32c717b167f33a91d6f648bdf96596776afdb6377ac434c1c293ccb04"));
+        hkdf.expand(&[], &mut okm).unwrap();
+        assert_eq!(
+            okm.to_vec(),
+            unhex("8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d9d201395faa4b61a96c8")
+        );
+    }
+
+    #[test]
+    fn lengths() {
+        let hkdf = Hkdf::new_with(Some(b"salt"), b"input key material", soft512);
+        let mut long = vec![0u8; 255 * 64];
+        assert!(hkdf.expand(b"info", &mut long).is_ok());
+        let mut short = [0u8; 100];
+        hkdf.expand(b"info", &mut short).unwrap();
+        // expanding is a stream: shorter outputs are prefixes of longer ones
+        assert_eq!(short[..], long[..100]);
+        let mut too_long = vec![0u8; 255 * 64 + 1];
+        assert_eq!(hkdf.expand(b"info", &mut too_long), Err(InvalidLength));
+
+        let again = Hkdf::from_prk_with(hkdf.prk(), soft512).unwrap();
+        hkdf.expand(b"other", &mut short).unwrap();
+        let mut copy = [0u8; 100];
+        again.expand(b"other", &mut copy).unwrap();
+        assert_eq!(short, copy);
+        assert!(Hkdf::from_prk_with(&[0u8; 32], soft512).is_err());
+    }
+
+    /// Records are the result byte, then the IKM, salt, info, requested size (as a little-endian
+    /// u32) and OKM, each preceded by its length.
+    fn check_wycheproof<D: Update + FixedOutput + BlockInput>(name: &str, hasher: fn() -> D) {
+        for (i, (valid, record)) in records(env!("CARGO_MANIFEST_DIR"), name, 5).iter().enumerate() {
+            let (ikm, salt, info, okm) = (&record[0], &record[1], &record[2], &record[4]);
+            let mut size = [0u8; 4];
+            size.copy_from_slice(&record[3]);
+            let mut out = vec![0u8; u32::from_le_bytes(size) as usize];
+            let hkdf = Hkdf::new_with(Some(salt), ikm, hasher);
+            match hkdf.expand(info, &mut out) {
+                Ok(()) => assert!(*valid && out == *okm, "{} record {}", name, i),
+                Err(InvalidLength) => assert!(!*valid, "{} record {}", name, i),
+            }
+        }
+    }
+
+    #[test]
+    fn wycheproof() {
+        check_wycheproof("hkdf_sha256_test.bin", Sha256::default);
+        check_wycheproof("hkdf_sha512_test.bin", soft512);
+    }
+}
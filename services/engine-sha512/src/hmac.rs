@@ -0,0 +1,182 @@
+//! HMAC (RFC 2104) over the hashes in this crate.
+//!
+//! `HmacSha512` runs on the Sha512 engine when it can. Each MAC uses two fresh hashers rather than
+//! cloning a keyed one, since a hasher that holds the hardware can't be duplicated.
+
+use crate::{Sha256, Sha512};
+use digest::generic_array::GenericArray;
+use digest::{BlockInput, FixedOutput, Update};
+
+pub type HmacSha256 = Hmac<Sha256>;
+pub type HmacSha512 = Hmac<Sha512>;
+
+pub struct Hmac<D: Update + FixedOutput + BlockInput> {
+    inner: D,
+    outer_key: GenericArray<u8, D::BlockSize>,
+    hasher: fn() -> D,
+}
+
+impl<D: Update + FixedOutput + BlockInput + Default> Hmac<D> {
+    pub fn new(key: &[u8]) -> Self {
+        Self::new_with(key, D::default)
+    }
+
+    /// One-shot MAC of `data`
+    pub fn mac(key: &[u8], data: &[u8]) -> GenericArray<u8, D::OutputSize> {
+        let mut hmac = Self::new(key);
+        hmac.update(data);
+        hmac.finalize()
+    }
+}
+
+impl<D: Update + FixedOutput + BlockInput> Hmac<D> {
+    /// Keys a MAC whose hashers come from `hasher`, e.g. to pick a `FallbackStrategy`.
+    pub fn new_with(key: &[u8], hasher: fn() -> D) -> Self {
+        let mut block = GenericArray::<u8, D::BlockSize>::default();
+        if key.len() > block.len() {
+            let mut h = hasher();
+            h.update(key);
+            let digest = h.finalize_fixed();
+            block[..digest.len()].copy_from_slice(&digest);
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+        let mut inner = hasher();
+        for b in block.iter_mut() {
+            *b ^= 0x36;
+        }
+        inner.update(&block);
+        // flip from the inner pad to the outer pad without keeping the plain key around
+        for b in block.iter_mut() {
+            *b ^= 0x36 ^ 0x5c;
+        }
+        Hmac { inner, outer_key: block, hasher }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    pub fn finalize(mut self) -> GenericArray<u8, D::OutputSize> {
+        let inner = core::mem::replace(&mut self.inner, (self.hasher)()).finalize_fixed();
+        let mut outer = (self.hasher)();
+        outer.update(&self.outer_key);
+        outer.update(&inner);
+        outer.finalize_fixed()
+    }
+
+    /// Checks `tag` in constant time. Truncated tags are accepted down to half the output
+    /// length, and never below 80 bits (RFC 2104, section 5).
+    pub fn verify(self, tag: &[u8]) -> bool {
+        let full = self.finalize();
+        let shortest = core::cmp::max(10, full.len() / 2);
+        if tag.len() < shortest || tag.len() > full.len() {
+            return false;
+        }
+        let diff = full.iter().zip(tag.iter()).fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        diff == 0
+    }
+}
+
+impl<D: Update + FixedOutput + BlockInput> Drop for Hmac<D> {
+    fn drop(&mut self) {
+        wipe(&mut self.outer_key);
+    }
+}
+
+pub(crate) fn wipe(bytes: &mut [u8]) {
+    for b in bytes.iter_mut() {
+        unsafe { core::ptr::write_volatile(b, 0) };
+    }
+    core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use wycheproof_records::{records, unhex};
+    use crate::FallbackStrategy;
+
+    /// the unit tests don't have a server to talk to
+    pub(crate) fn soft512() -> Sha512 {
+        Sha512::new_with_strategy(FallbackStrategy::SoftwareOnly)
+    }
+
+    fn hmac256(key: &[u8], data: &[u8]) -> Vec<u8> {
+        HmacSha256::mac(key, data).to_vec()
+    }
+
+    fn hmac512(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut hmac = HmacSha512::new_with(key, soft512);
+        hmac.update(data);
+        hmac.finalize().to_vec()
+    }
+
+    /// RFC 4231 test cases 1, 2, 6 and 7
+    #[test]
+    fn rfc4231() {
+        let big_key = [0xaau8; 131];
+        let cases: [(&[u8], &[u8], &str, &str); 4] = [
+            (
+                &[0x0b; 20],
+                b"Hi There",
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+                "87aa7cdea5ef619d4ff0b4241a1d6cb02379f4e2ce4ec2787ad0b30545e17cdedaa833b7d6b8a702038b274eaea3f4e4be9d914eeb61f1702e696c203a126854",
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+                "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737",
+            ),
+            (
+                &big_key,
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+                "80b24263c7c1a3ebb71493c1dd7be8b49b46d1f41b4aeec1121b013783f8f3526b56d037e05f2598bd0fd2215d6a1e5295e64f73f63f0aec8b915a985d786598",
+            ),
+            (
+                &big_key,
+                b"This is a test using a larger than block-size key and a larger than block-size data. The key needs to be hashed before being used by the HMAC algorithm.",
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+                "e37b6a775dc87dbaa4dfa9f96e5e3ffddebd71f8867289865df5a32d20cdc944b6022cac3c4982b10d5eeb55c3e4de15134676fb6de0446065c97440fa8c6a58",
+            ),
+        ];
+        for (key, data, mac256, mac512) in cases.iter() {
+            assert_eq!(hmac256(key, data), unhex(mac256));
+            assert_eq!(hmac512(key, data), unhex(mac512));
+        }
+    }
+
+    #[test]
+    fn verify_and_truncation() {
+        let tag = hmac256(b"Jefe", b"what do ya want for nothing?");
+        let check = |t: &[u8]| {
+            let mut hmac = HmacSha256::new(b"Jefe");
+            hmac.update(b"what do ya want for nothing?");
+            hmac.verify(t)
+        };
+        assert!(check(&tag));
+        assert!(check(&tag[..16]));
+        assert!(!check(&tag[..15]));
+        let mut bad = tag.clone();
+        bad[31] ^= 0x80;
+        assert!(!check(&bad));
+    }
+
+    /// Records are the result byte, then the key, message and tag, each preceded by its length.
+    fn check_wycheproof<D: Update + FixedOutput + BlockInput>(name: &str, hasher: fn() -> D) {
+        for (i, (valid, record)) in records(env!("CARGO_MANIFEST_DIR"), name, 3).iter().enumerate() {
+            let (key, msg, tag) = (&record[0], &record[1], &record[2]);
+            let mut hmac = Hmac::new_with(key, hasher);
+            hmac.update(msg);
+            assert_eq!(hmac.verify(tag), *valid, "{} record {}", name, i);
+        }
+    }
+
+    #[test]
+    fn wycheproof() {
+        check_wycheproof("hmac_sha256_test.bin", Sha256::default);
+        check_wycheproof("hmac_sha512_test.bin", soft512);
+    }
+}
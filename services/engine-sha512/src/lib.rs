@@ -4,9 +4,15 @@ mod api;
 pub use api::*;
 mod consts;
 
+mod context;
 mod sha256;
 mod sha512;
+use sha512::soft as soft512;
+pub mod hkdf;
+pub mod hmac;
 
 pub use digest::{self, Digest};
+pub use hkdf::{HkdfSha256, HkdfSha512};
+pub use hmac::{HmacSha256, HmacSha512};
 pub use sha256::{Sha224, Sha256};
 pub use sha512::{Sha384, Sha512, Sha512Trunc224, Sha512Trunc256};
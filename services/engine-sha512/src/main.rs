@@ -5,6 +5,14 @@
 
 mod api;
 use api::*;
+#[allow(dead_code)] // the server only needs the SHA-512 initial values
+mod consts;
+#[allow(dead_code)] // shared with the library, which uses more of it
+mod context;
+mod context_table;
+use context_table::{ContextTable, Grant};
+#[path = "sha512/soft.rs"]
+mod soft512;
 
 use num_traits::{FromPrimitive, ToPrimitive};
 use xous::msg_blocking_scalar_unpack;
@@ -263,11 +271,14 @@ mod implementation {
             ([0; 64], 0)
         }
         pub(crate) fn is_idle(&mut self) -> bool {
-            false
+            true
         }
     }
 }
 
+/// Hosted mode has no engine, so every stream is parked in software, and gets a real hash back
+const HARDWARE_PRESENT: bool = cfg!(any(target_os = "none", target_os = "xous"));
+
 static HASH_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
 static SUSPEND_FAILURE: AtomicBool = AtomicBool::new(false);
 static SUSPEND_PENDING: AtomicBool = AtomicBool::new(false);
@@ -315,6 +326,20 @@ fn susres_thread(sid0: usize, sid1: usize, sid2: usize, sid3: usize) {
     xous::destroy_server(susres_sid).unwrap();
 }
 
+fn fill_result(finalized: &mut Sha2Finalize, config: Sha2Config, hash: &[u8; 64], length_in_bits: u64) {
+    match config {
+        Sha2Config::Sha512 => {
+            finalized.result = Sha2Result::Sha512Result(*hash);
+        }
+        Sha2Config::Sha512Trunc256 => {
+            let mut trunc: [u8; 32] = [0; 32];
+            trunc.clone_from_slice(&hash[..32]);
+            finalized.result = Sha2Result::Sha512Trunc256Result(trunc);
+        }
+    }
+    finalized.length_in_bits = Some(length_in_bits);
+}
+
 #[xous::xous_main]
 fn xmain() -> ! {
     use crate::implementation::Engine512;
@@ -336,6 +361,7 @@ fn xmain() -> ! {
 
     // handle suspend/resume with a separate thread, which monitors our in-progress state
     // we can't save hardware state of a hash, so the hash MUST finish before we can suspend.
+    // (hashes parked in software contexts are unaffected)
     let susres_mgr_sid = xous::create_server().unwrap();
     let (sid0, sid1, sid2, sid3) = susres_mgr_sid.to_u32();
     xous::create_thread_4(
@@ -347,57 +373,64 @@ fn xmain() -> ! {
     )
     .expect("couldn't start susres handler thread");
 
-    let mut client_id: Option<[u32; 3]> = None;
-    let mut mode: Option<Sha2Config> = None;
+    let mut contexts = ContextTable::new();
     let mut job_count = 0;
     loop {
         let mut msg = xous::receive_message(engine512_sid).unwrap();
         match FromPrimitive::from_usize(msg.body.id()) {
             Some(Opcode::AcquireExclusive) => {
                 msg_blocking_scalar_unpack!(msg, id0, id1, id2, flags, {
-                    if client_id.is_none() && !SUSPEND_PENDING.load(Ordering::Relaxed) {
-                        client_id = Some([id0 as u32, id1 as u32, id2 as u32]);
-                        //log::trace!("giving {:x?} an exclusive lock", client_id);
-                        mode = Some(FromPrimitive::from_usize(flags).unwrap());
-                        SUSPEND_FAILURE.store(false, Ordering::Relaxed);
-                        HASH_IN_PROGRESS.store(true, Ordering::Relaxed);
-                        engine512.setup(mode.unwrap());
-                        xous::return_scalar(msg.sender, 1).unwrap();
-                    } else {
-                        xous::return_scalar(msg.sender, 0).unwrap();
+                    let id = [id0 as u32, id1 as u32, id2 as u32];
+                    let config: Sha2Config = match FromPrimitive::from_usize(flags & ACQUIRE_CONFIG_MASK) {
+                        Some(config) => config,
+                        None => {
+                            log::error!("unknown hash config in flags {:x}", flags);
+                            xous::return_scalar(msg.sender, ACQUIRED_NONE).unwrap();
+                            continue;
+                        }
+                    };
+                    let may_park = (flags & ACQUIRE_FLAG_PARK) != 0 || !HARDWARE_PRESENT;
+                    let hardware_ok = HARDWARE_PRESENT && !SUSPEND_PENDING.load(Ordering::Relaxed);
+                    let already_running = contexts.grant(&id).is_some();
+                    match contexts.acquire(id, config, may_park, hardware_ok) {
+                        Some(Grant::Hardware) => {
+                            if !already_running {
+                                //log::trace!("giving {:x?} an exclusive lock", id);
+                                SUSPEND_FAILURE.store(false, Ordering::Relaxed);
+                                HASH_IN_PROGRESS.store(true, Ordering::Relaxed);
+                                engine512.setup(config);
+                            }
+                            xous::return_scalar(msg.sender, ACQUIRED_HARDWARE).unwrap();
+                        }
+                        Some(Grant::Parked) => {
+                            log::debug!("hardware busy, parked {:x?} ({} parked)", id, contexts.parked_count());
+                            xous::return_scalar(msg.sender, ACQUIRED_PARKED).unwrap();
+                        }
+                        None => xous::return_scalar(msg.sender, ACQUIRED_NONE).unwrap(),
                     }
                 })
             }
             Some(Opcode::Reset) => msg_blocking_scalar_unpack!(msg, r_id0, r_id1, r_id2, _, {
-                match client_id {
-                    Some([id0, id1, id2]) => {
-                        if id0 == r_id0 as u32 && id1 == r_id1 as u32 && id2 == r_id2 as u32 {
-                            SUSPEND_FAILURE.store(false, Ordering::Relaxed);
-                            HASH_IN_PROGRESS.store(false, Ordering::Relaxed);
-                            client_id = None;
-                            mode = None;
-                            engine512.reset();
-                            xous::return_scalar(msg.sender, 1).unwrap();
-                        } else {
-                            xous::return_scalar(msg.sender, 0).unwrap();
-                        }
-                    }
-                    _ => {
-                        xous::return_scalar(msg.sender, 0).unwrap();
+                match contexts.release(&[r_id0 as u32, r_id1 as u32, r_id2 as u32]) {
+                    Some(Grant::Hardware) => {
+                        SUSPEND_FAILURE.store(false, Ordering::Relaxed);
+                        HASH_IN_PROGRESS.store(false, Ordering::Relaxed);
+                        engine512.reset();
+                        xous::return_scalar(msg.sender, 1).unwrap();
                     }
+                    Some(Grant::Parked) => xous::return_scalar(msg.sender, 1).unwrap(),
+                    None => xous::return_scalar(msg.sender, 0).unwrap(),
                 }
             }),
             Some(Opcode::Update) => {
                 let buffer =
                     unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
                 let update = buffer.as_flat::<Sha2Update, _>().unwrap();
-                match client_id {
-                    Some(id) => {
-                        if id == update.id {
-                            engine512.update(&update.buffer[..update.len as usize]);
-                        }
-                    }
-                    _ => {
+                let data = &update.buffer[..update.len as usize];
+                match contexts.update(&update.id, data) {
+                    Some(Grant::Hardware) => engine512.update(data),
+                    Some(Grant::Parked) => (),
+                    None => {
                         log::error!("Received a SHA-2 block, but the client ID did not match! Ignoring block.");
                     }
                 }
@@ -411,45 +444,70 @@ fn xmain() -> ! {
                     Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
                 };
                 let mut finalized = buffer.to_original::<Sha2Finalize, _>().unwrap();
-                match client_id {
-                    Some(id) => {
-                        if id == finalized.id {
-                            if SUSPEND_FAILURE.load(Ordering::Relaxed) {
-                                finalized.result = Sha2Result::SuspendError;
-                                finalized.length_in_bits = None;
-                            } else {
-                                let (hash, length_in_bits) = engine512.finalize();
-                                match mode {
-                                    Some(Sha2Config::Sha512) => {
-                                        finalized.result = Sha2Result::Sha512Result(hash);
-                                        finalized.length_in_bits = Some(length_in_bits);
-                                    }
-                                    Some(Sha2Config::Sha512Trunc256) => {
-                                        let mut trunc: [u8; 32] = [0; 32];
-                                        trunc.clone_from_slice(&hash[..32]);
-                                        finalized.result = Sha2Result::Sha512Trunc256Result(trunc);
-                                        finalized.length_in_bits = Some(length_in_bits);
-                                    }
-                                    None => {
-                                        finalized.result = Sha2Result::Uninitialized;
-                                    }
-                                }
-                            }
-                        } else {
-                            finalized.result = Sha2Result::IdMismatch;
+                match contexts.grant(&finalized.id) {
+                    Some(Grant::Hardware) => {
+                        if SUSPEND_FAILURE.load(Ordering::Relaxed) {
+                            finalized.result = Sha2Result::SuspendError;
                             finalized.length_in_bits = None;
+                        } else {
+                            let (hash, length_in_bits) = engine512.finalize();
+                            fill_result(&mut finalized, contexts.hardware_config().unwrap(), &hash, length_in_bits);
                         }
                     }
-                    _ => {
+                    Some(Grant::Parked) => {
+                        let (config, hash, length_in_bits) = contexts.finalize_parked(&finalized.id).unwrap();
+                        fill_result(&mut finalized, config, &hash, length_in_bits);
+                    }
+                    None => {
                         log::error!(
-                            "Received a SHA-2 finalize call, but we aren't doing a hash. Ignoring."
+                            "Received a SHA-2 finalize call, but we aren't doing a hash for that ID. Ignoring."
                         );
+                        finalized.result = Sha2Result::IdMismatch;
+                        finalized.length_in_bits = None;
                     }
                 }
                 buffer
                     .replace(finalized)
                     .expect("couldn't return hash result");
             }
+            Some(Opcode::SaveContext) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let mut park = buffer.to_original::<Sha2Park, _>().unwrap();
+                park.context = None;
+                park.error = None;
+                match contexts.save(&park.id) {
+                    Some(Ok((context, from))) => {
+                        if from == Grant::Hardware {
+                            SUSPEND_FAILURE.store(false, Ordering::Relaxed);
+                            HASH_IN_PROGRESS.store(false, Ordering::Relaxed);
+                            engine512.reset();
+                        }
+                        park.context = Some(context);
+                    }
+                    Some(Err(e)) => park.error = Some(e),
+                    None => (),
+                }
+                buffer.replace(park).expect("couldn't return hash context");
+            }
+            Some(Opcode::RestoreContext) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let mut park = buffer.to_original::<Sha2Park, _>().unwrap();
+                park.error = None;
+                match contexts.restore(park.id, &mut park.context) {
+                    Ok(()) if park.context.is_some() => log::debug!("no room to restore a hash context, handing it back"),
+                    Ok(()) => (),
+                    Err(e) => {
+                        log::warn!("refused a hash context: {:?}", e);
+                        park.context = None;
+                        park.error = Some(e);
+                    }
+                }
+                buffer.replace(park).expect("couldn't return hash context");
+            }
             Some(Opcode::IsIdle) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
                 if engine512.is_idle() {
                     xous::return_scalar(msg.sender, 1).expect("couldn't return IsIdle query");
@@ -458,7 +516,8 @@ fn xmain() -> ! {
                 }
             }),
             Some(Opcode::AcquireSuspendLock) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
-                if client_id.is_none() {
+                // parked contexts are just memory, so only the hardware can hold up a suspend
+                if !contexts.hardware_busy() {
                     SUSPEND_PENDING.store(true, Ordering::Relaxed);
                     xous::return_scalar(msg.sender, 1).expect("couldn't ack AcquireSuspendLock");
                 } else {
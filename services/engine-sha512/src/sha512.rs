@@ -1,5 +1,5 @@
 //! SHA-512
-mod soft;
+pub(crate) mod soft;
 use crate::consts::*;
 use soft::compress;
 
@@ -17,6 +17,9 @@ use core::sync::atomic::{AtomicU32, Ordering};
 static HW_CONN: AtomicU32 = AtomicU32::new(0);
 /// a unique-enough random ID number to prove we own our connection to the hashing engine hardware
 static TOKEN: [AtomicU32; 3] = [AtomicU32::new(0), AtomicU32::new(0), AtomicU32::new(0)];
+/// each hasher instance mixes a count into the token, so the server can tell apart several
+/// streams coming from the same process
+static INSTANCE: AtomicU32 = AtomicU32::new(0);
 
 use block_buffer::BlockBuffer;
 use core::slice::from_ref;
@@ -30,7 +33,9 @@ type BlockSize = U128;
   License is Apache 2.0
 */
 /// Structure that keeps state of the software-emulated Sha-512 operation and
-/// contains the logic necessary to perform the final calculations.
+/// contains the logic necessary to perform the final calculations. Used by the
+/// software-only modes; `Sha512` and `Sha512Trunc256` keep their software state
+/// in a `Sha2Context` instead, so it can be saved and restored.
 #[derive(Clone)]
 struct Engine512 {
     len: u128,
@@ -140,23 +145,39 @@ macro_rules! sha512_comms {
             )
             .map(|_| ())
         }
+        pub(crate) fn new_id(&self) -> [u32; 3] {
+            self.ensure_conn(); // also ensures the token
+            [
+                TOKEN[0].load(Ordering::Relaxed),
+                TOKEN[1].load(Ordering::Relaxed),
+                TOKEN[2].load(Ordering::Relaxed) ^ INSTANCE.fetch_add(1, Ordering::Relaxed),
+            ]
+        }
         pub(crate) fn try_acquire_hw(&mut self, config: Sha2Config) {
             if !self.in_progress && (self.strategy != FallbackStrategy::SoftwareOnly) {
+                self.id = self.new_id();
+                // if the hardware is busy, a server-side software context beats blocking, but a
+                // caller that asked for the hardware gets the hardware
+                let flags = if self.strategy == FallbackStrategy::HardwareThenSoftware {
+                    config.to_usize().unwrap() | ACQUIRE_FLAG_PARK
+                } else {
+                    config.to_usize().unwrap()
+                };
                 loop {
-                    let conn = self.ensure_conn(); // also ensures the ID
                     let response = send_message(
-                        conn,
+                        self.ensure_conn(),
                         Message::new_blocking_scalar(
                             Opcode::AcquireExclusive.to_usize().unwrap(),
-                            TOKEN[0].load(Ordering::Relaxed) as usize,
-                            TOKEN[1].load(Ordering::Relaxed) as usize,
-                            TOKEN[2].load(Ordering::Relaxed) as usize,
-                            config.to_usize().unwrap(),
+                            self.id[0] as usize,
+                            self.id[1] as usize,
+                            self.id[2] as usize,
+                            flags,
                         ),
                     )
                     .expect("couldn't send AcquireExclusive message to Sha2 hardware!");
                     if let xous::Result::Scalar1(result) = response {
-                        if result != 0 {
+                        // hardware or a parked context: either way, the server does the hashing
+                        if result != ACQUIRED_NONE {
                             self.use_soft = false;
                             self.in_progress = true;
                             break;
@@ -185,9 +206,9 @@ macro_rules! sha512_comms {
                 self.ensure_conn(),
                 Message::new_blocking_scalar(
                     Opcode::Reset.to_usize().unwrap(),
-                    TOKEN[0].load(Ordering::Relaxed) as usize,
-                    TOKEN[1].load(Ordering::Relaxed) as usize,
-                    TOKEN[2].load(Ordering::Relaxed) as usize,
+                    self.id[0] as usize,
+                    self.id[1] as usize,
+                    self.id[2] as usize,
                     0,
                 ),
             )
@@ -196,6 +217,83 @@ macro_rules! sha512_comms {
             self.length = 0;
             self.in_progress = false;
             self.use_soft = true;
+            self.engine.reset();
+        }
+        pub(crate) fn send_update(&mut self, input: &[u8]) {
+            for chunk in input.chunks(3968) {
+                // one SHA512 block (128 bytes) short of 4096 to give space for struct overhead in page remap handling
+                let mut update = Sha2Update {
+                    id: self.id,
+                    buffer: [0; 3968],
+                    len: 0,
+                };
+                self.length += (chunk.len() as u64) * 8; // we need to keep track of length in bits
+                for (&src, dest) in chunk.iter().zip(&mut update.buffer) {
+                    *dest = src;
+                }
+                update.len = chunk.len() as u16;
+                let buf = Buffer::into_buf(update).expect("couldn't map chunk into IPC buffer");
+                buf.lend(self.ensure_conn(), Opcode::Update.to_u32().unwrap())
+                    .expect("hardware rejected our hash chunk!");
+            }
+        }
+        /// Returns the hash so far as plain data, which can be handed to `restore()` in this or
+        /// another process. A hash held by the server moves into this hasher, which carries on in
+        /// software. A hash running on the hardware can only be saved before it's been fed any
+        /// data: the engine can neither hand out nor take in a partial hash, so after that it has
+        /// to be finished where it is, and this fails with `ContextError::InHardware`.
+        pub fn save(&mut self) -> Result<Sha2Context, ContextError> {
+            if self.use_soft || !self.in_progress {
+                return Ok(self.engine.clone());
+            }
+            let park = Sha2Park { id: self.id, context: None, error: None };
+            let mut buf = Buffer::into_buf(park).expect("couldn't map memory for the context");
+            buf.lend_mut(self.ensure_conn(), Opcode::SaveContext.to_u32().unwrap())
+                .expect("couldn't save hash context");
+            let returned: Sha2Park = buf.to_original().expect("couldn't decode the context");
+            if let Some(e) = returned.error {
+                return Err(e);
+            }
+            // the server has no record of this hash, so it's on the hardware in a state we can't see
+            let context = returned.context.ok_or(ContextError::InHardware)?;
+            self.engine = context.clone();
+            self.use_soft = true;
+            self.length = 0;
+            Ok(context)
+        }
+        /// Picks up a hash from `save()`. It always carries on in software, since the engine can't
+        /// be loaded with a partial hash: in one of the server's contexts if it has room, otherwise
+        /// here. Fails if `context` was saved from a different kind of hash, or is damaged.
+        pub fn restore(context: Sha2Context) -> Result<Self, ContextError> {
+            let mut hasher = Self::new();
+            if context.config != hasher.engine.config {
+                return Err(ContextError::WrongKind);
+            }
+            if !context.is_valid() {
+                return Err(ContextError::Invalid);
+            }
+            hasher.in_progress = true;
+            hasher.id = hasher.new_id();
+            let length = context.length_in_bits;
+            let park = Sha2Park { id: hasher.id, context: Some(context), error: None };
+            let mut buf = Buffer::into_buf(park).expect("couldn't map memory for the context");
+            buf.lend_mut(hasher.ensure_conn(), Opcode::RestoreContext.to_u32().unwrap())
+                .expect("couldn't restore hash context");
+            let returned: Sha2Park = buf.to_original().expect("couldn't decode the context");
+            if let Some(e) = returned.error {
+                return Err(e);
+            }
+            match returned.context {
+                None => {
+                    hasher.use_soft = false;
+                    hasher.length = length;
+                }
+                Some(context) => {
+                    hasher.use_soft = true;
+                    hasher.engine = context;
+                }
+            }
+            Ok(hasher)
         }
     };
 }
@@ -204,7 +302,9 @@ macro_rules! sha512_comms {
 #[derive(Clone)]
 pub struct Sha512 {
     /// software fallback engine
-    engine: Engine512,
+    engine: Sha2Context,
+    /// identifies this hash to the server
+    id: [u32; 3],
     /// whether or not this current hasher instance will use software or hardware acceleration
     use_soft: bool,
     /// specifies the strategy for fallback in case multiple hashes are initiated simultaneously
@@ -220,7 +320,8 @@ impl Sha512 {
         Sha512 {
             use_soft: false,
             strategy: FallbackStrategy::HardwareThenSoftware,
-            engine: Engine512::new(&H512),
+            engine: Sha2Context::new(Sha2Config::Sha512),
+            id: [0; 3],
             in_progress: false,
             length: 0,
         }
@@ -229,7 +330,8 @@ impl Sha512 {
         Sha512 {
             use_soft: false,
             strategy: strat,
-            engine: Engine512::new(&H512),
+            engine: Sha2Context::new(Sha2Config::Sha512),
+            id: [0; 3],
             in_progress: false,
             length: 0,
         }
@@ -250,7 +352,7 @@ impl Drop for Sha512 {
         // normally, we would de-allocate a connection but because the Digest API assumes that
         // all instances are fungible we can't do that, as the connection needs to be persistent
         // between invocations of the object.
-        if !self.use_soft {
+        if !self.use_soft && self.in_progress {
             self.reset_hw();
         }
     }
@@ -263,30 +365,10 @@ impl BlockInput for Sha512 {
 impl Update for Sha512 {
     fn update(&mut self, input: impl AsRef<[u8]>) {
         self.try_acquire_hw(Sha2Config::Sha512);
-        // split the incoming blocks to page size and send to the engine
         if self.use_soft {
             self.engine.update(input.as_ref());
         } else {
-            for chunk in input.as_ref().chunks(3968) {
-                // one SHA512 block (128 bytes) short of 4096 to give space for struct overhead in page remap handling
-                let mut update = Sha2Update {
-                    id: [
-                        TOKEN[0].load(Ordering::Relaxed),
-                        TOKEN[1].load(Ordering::Relaxed),
-                        TOKEN[2].load(Ordering::Relaxed),
-                    ],
-                    buffer: [0; 3968],
-                    len: 0,
-                };
-                self.length += (chunk.len() as u64) * 8; // we need to keep track of length in bits
-                for (&src, dest) in chunk.iter().zip(&mut update.buffer) {
-                    *dest = src;
-                }
-                update.len = chunk.len() as u16;
-                let buf = Buffer::into_buf(update).expect("couldn't map chunk into IPC buffer");
-                buf.lend(self.ensure_conn(), Opcode::Update.to_u32().unwrap())
-                    .expect("hardware rejected our hash chunk!");
-            }
+            self.send_update(input.as_ref());
         }
     }
 }
@@ -296,18 +378,10 @@ impl FixedOutputDirty for Sha512 {
 
     fn finalize_into_dirty(&mut self, out: &mut digest::Output<Self>) {
         if self.use_soft {
-            self.engine.finish();
-            let s = self.engine.state;
-            for (chunk, v) in out.chunks_exact_mut(8).zip(s.iter()) {
-                chunk.copy_from_slice(&v.to_be_bytes());
-            }
+            out.copy_from_slice(&self.engine.finalize());
         } else {
             let result = Sha2Finalize {
-                id: [
-                    TOKEN[0].load(Ordering::Relaxed),
-                    TOKEN[1].load(Ordering::Relaxed),
-                    TOKEN[2].load(Ordering::Relaxed),
-                ],
+                id: self.id,
                 result: Sha2Result::Uninitialized,
                 length_in_bits: None,
             };
@@ -351,7 +425,7 @@ impl FixedOutputDirty for Sha512 {
 impl Reset for Sha512 {
     fn reset(&mut self) {
         if self.use_soft {
-            self.engine.reset(&H512);
+            self.engine.reset();
         } else {
             self.reset_hw();
         }
@@ -366,7 +440,10 @@ impl Reset for Sha512 {
 /// that the core hardware API even works...
 #[derive(Clone)]
 pub struct Sha512Trunc256 {
-    engine: Engine512,
+    /// software fallback engine
+    engine: Sha2Context,
+    /// identifies this hash to the server
+    id: [u32; 3],
     /// whether or not this current hasher instance will use software or hardware acceleration
     use_soft: bool,
     /// specifies the strategy for fallback in case multiple hashes are initiated simultaneously
@@ -382,7 +459,8 @@ impl Sha512Trunc256 {
         Sha512Trunc256 {
             use_soft: false,
             strategy: FallbackStrategy::HardwareThenSoftware,
-            engine: Engine512::new(&H512_TRUNC_256),
+            engine: Sha2Context::new(Sha2Config::Sha512Trunc256),
+            id: [0; 3],
             in_progress: false,
             length: 0,
         }
@@ -391,7 +469,8 @@ impl Sha512Trunc256 {
         Sha512Trunc256 {
             use_soft: false,
             strategy: strat,
-            engine: Engine512::new(&H512_TRUNC_256),
+            engine: Sha2Context::new(Sha2Config::Sha512Trunc256),
+            id: [0; 3],
             in_progress: false,
             length: 0,
         }
@@ -402,7 +481,7 @@ impl Sha512Trunc256 {
 
 impl Drop for Sha512Trunc256 {
     fn drop(&mut self) {
-        if !self.use_soft {
+        if !self.use_soft && self.in_progress {
             self.reset_hw();
         }
     }
@@ -424,26 +503,7 @@ impl Update for Sha512Trunc256 {
         if self.use_soft {
             self.engine.update(input.as_ref());
         } else {
-            for chunk in input.as_ref().chunks(3968) {
-                // one SHA512 block (128 bytes) short of 4096 to give space for struct overhead in page remap handling
-                let mut update = Sha2Update {
-                    id: [
-                        TOKEN[0].load(Ordering::Relaxed),
-                        TOKEN[1].load(Ordering::Relaxed),
-                        TOKEN[2].load(Ordering::Relaxed),
-                    ],
-                    buffer: [0; 3968],
-                    len: 0,
-                };
-                self.length += (chunk.len() as u64) * 8; // we need to keep track of length in bits
-                for (&src, dest) in chunk.iter().zip(&mut update.buffer) {
-                    *dest = src;
-                }
-                update.len = chunk.len() as u16;
-                let buf = Buffer::into_buf(update).expect("couldn't map chunk into IPC buffer");
-                buf.lend(self.ensure_conn(), Opcode::Update.to_u32().unwrap())
-                    .expect("hardware rejected our hash chunk!");
-            }
+            self.send_update(input.as_ref());
         }
     }
 }
@@ -453,18 +513,10 @@ impl FixedOutputDirty for Sha512Trunc256 {
 
     fn finalize_into_dirty(&mut self, out: &mut digest::Output<Self>) {
         if self.use_soft {
-            self.engine.finish();
-            let s = &self.engine.state[..4];
-            for (chunk, v) in out.chunks_exact_mut(8).zip(s.iter()) {
-                chunk.copy_from_slice(&v.to_be_bytes());
-            }
+            out.copy_from_slice(&self.engine.finalize()[..32]);
         } else {
             let result = Sha2Finalize {
-                id: [
-                    TOKEN[0].load(Ordering::Relaxed),
-                    TOKEN[1].load(Ordering::Relaxed),
-                    TOKEN[2].load(Ordering::Relaxed),
-                ],
+                id: self.id,
                 result: Sha2Result::Uninitialized,
                 length_in_bits: None,
             };
@@ -507,7 +559,7 @@ impl FixedOutputDirty for Sha512Trunc256 {
 impl Reset for Sha512Trunc256 {
     fn reset(&mut self) {
         if self.use_soft {
-            self.engine.reset(&H512_TRUNC_256);
+            self.engine.reset();
         } else {
             self.reset_hw();
         }
@@ -98,6 +98,26 @@ pub struct UxRegistration {
     pub rawkeys_id: Option<u32>,
     /// optional opcode ID code for focus change notifications. Most applications will want to provide this to stop hogging resources when backgrounded
     pub focuschange_id: Option<u32>,
+    /// optional opcode ID for lifecycle events (see `LifecycleEvent`). Apps that keep state should provide this so they can save it when backgrounded or terminated
+    pub lifecycle_id: Option<u32>,
+}
+
+/// Maximum size of the state blob an app can park with the GAM.
+pub const MAX_APP_STATE_LEN: usize = 2048;
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub struct AppStateBlob {
+    pub token: [u32; 4],
+    pub data: [u8; MAX_APP_STATE_LEN],
+    /// length of valid data; on a restore, `None` indicates that no state was saved
+    pub len: Option<usize>,
+}
+
+/// Used to look up whose token a token is
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub struct TokenOwner {
+    pub token: [u32; 4],
+    /// filled in by the GAM; `None` if no one holds the token
+    pub name: Option<String::<128>>,
 }
 
 
@@ -179,6 +199,18 @@ pub(crate) enum Opcode {
     /// Show a test pattern. Can only call this once (to prevent abuse)
     TestPattern,
 
+    /// park a small blob of app state with the GAM, keyed to the app's context name
+    SaveAppState,
+    /// retrieve a previously parked state blob
+    RestoreAppState,
+
+    /// broadcast a low memory lifecycle event to all backgrounded contexts
+    LowMemory,
+    /// deliver a terminate lifecycle event to an app, and retire its context so it may re-register
+    TerminateApp,
+    /// the name of the app or context that holds a token
+    TokenOwner,
+
     Quit,
 }
 
@@ -14,7 +14,7 @@ use enum_dispatch::enum_dispatch;
 
 pub (crate) const MISC_CONTEXT_DEFAULT_TRUST: u8 = 127;
 
-#[derive(PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub(crate) enum LayoutBehavior {
     /// a layout that can render over others, takes focus, and only dismissed if explicitly dismissed
     Alert,
@@ -22,6 +22,40 @@ pub(crate) enum LayoutBehavior {
     App,
 }
 
+/// Where a key hit goes
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum KeyRoute {
+    /// the menu key over an app raises the main menu
+    MainMenu,
+    /// shift + menu over an app raises the recent apps switcher
+    AppSwitcher,
+    /// nothing is left to deliver
+    Discard,
+    /// deliver these keys to the focused context
+    Focused([char; 4]),
+}
+
+/// Decides where a key hit goes, given the behavior of the focused context. Only the primary key
+/// hit can raise the menu or the switcher; multi-key hits are rejected. The switcher key is
+/// meaningless outside of an app, so it is never delivered to modals and menus.
+pub(crate) fn route_keys(mut keys: [char; 4], focus: Option<LayoutBehavior>) -> KeyRoute {
+    if focus == Some(LayoutBehavior::App) {
+        return match keys[0] {
+            '∴' => KeyRoute::MainMenu,
+            '⇄' => KeyRoute::AppSwitcher,
+            _ => KeyRoute::Focused(keys),
+        }
+    }
+    for key in keys.iter_mut().filter(|key| **key == '⇄') {
+        *key = '\u{0000}';
+    }
+    if keys.iter().all(|&key| key == '\u{0000}') {
+        KeyRoute::Discard
+    } else {
+        KeyRoute::Focused(keys)
+    }
+}
+
 #[enum_dispatch]
 pub(crate) trait LayoutApi {
     fn clear(&self, gfx: &graphics_server::Gfx, canvases: &mut HashMap<Gid, Canvas>) -> Result<(), xous::Error>;
@@ -73,6 +107,8 @@ pub(crate) struct UxContext {
     pub audioframe_id: Option<u32>,
     /// opcode ID for focus change
     pub focuschange_id: Option<u32>,
+    /// opcode ID for lifecycle events
+    pub lifecycle_id: Option<u32>,
 }
 pub(crate) const BOOT_CONTEXT_TRUSTLEVEL: u8 = 254;
 
@@ -95,6 +131,12 @@ pub(crate) struct ContextManager {
     /// for internal generation of deface states
     pub trng: trng::Trng,
     tt: ticktimer_server::Ticktimer,
+    /// app_tokens of App-type contexts, in most-recently-focused order
+    recents: Vec<[u32; 4]>,
+    /// state blobs parked by apps, keyed by context name so they survive a terminate/relaunch cycle
+    app_states: HashMap<std::string::String, Vec<u8>>,
+    /// connection to the recent apps switcher thread
+    switcher_conn: Option<xous::CID>,
 }
 impl ContextManager {
     pub fn new(xns: &xous_names::XousNames) -> Self {
@@ -116,8 +158,14 @@ impl ContextManager {
             main_menu_app_token: None,
             trng: trng::Trng::new(&xns).expect("couldn't connect to trng"),
             tt: ticktimer_server::Ticktimer::new().unwrap(),
+            recents: Vec::new(),
+            app_states: HashMap::new(),
+            switcher_conn: None,
         }
     }
+    pub(crate) fn set_switcher_conn(&mut self, conn: xous::CID) {
+        self.switcher_conn = Some(conn);
+    }
     pub(crate) fn claim_token(&mut self, name: &str) -> Option<[u32; 4]> {
         self.tm.claim_token(name)
     }
@@ -152,6 +200,7 @@ impl ContextManager {
                         gotinput_id: registration.gotinput_id,
                         audioframe_id: registration.audioframe_id,
                         focuschange_id: registration.focuschange_id,
+                        lifecycle_id: registration.lifecycle_id,
                         rawkeys_id: None,
                         vibe: false,
                     };
@@ -173,6 +222,7 @@ impl ContextManager {
                         gotinput_id: None,
                         audioframe_id: None,
                         focuschange_id: registration.focuschange_id,
+                        lifecycle_id: registration.lifecycle_id,
                         rawkeys_id: registration.rawkeys_id,
                         vibe: false,
                     };
@@ -200,6 +250,7 @@ impl ContextManager {
                         gotinput_id: None,
                         audioframe_id: None,
                         focuschange_id: registration.focuschange_id,
+                        lifecycle_id: registration.lifecycle_id,
                         rawkeys_id: registration.rawkeys_id,
                         vibe: false,
                     };
@@ -227,6 +278,7 @@ impl ContextManager {
                         gotinput_id: None,
                         audioframe_id: None,
                         focuschange_id: registration.focuschange_id,
+                        lifecycle_id: registration.lifecycle_id,
                         rawkeys_id: registration.rawkeys_id,
                         vibe: false,
                     };
@@ -368,6 +420,7 @@ impl ContextManager {
                 // immutable borrow here can't be combined with mutable borrow below
                 if let Some(old_context) = self.get_context_by_token(self.focused_context.unwrap()) {
                     self.notify_focus_change_to(gam::FocusState::Background, old_context).unwrap();
+                    self.notify_lifecycle(gam::LifecycleEvent::Background, old_context).unwrap();
                     log::trace!("lowered focus to: {:?}", old_context);
                 }
                 if let Some(old_context) = self.get_context_by_token_mut(self.focused_context.unwrap()) {
@@ -431,8 +484,14 @@ impl ContextManager {
                 self.kbd.set_vibe(context.vibe).expect("couldn't restore keyboard vibe");
 
                 self.notify_focus_change_to(gam::FocusState::Foreground, context).unwrap();
+                self.notify_lifecycle(gam::LifecycleEvent::Foreground, context).unwrap();
                 log::trace!("raised focus to: {:?}", context);
                 let last_token = context.app_token;
+                if context.layout.behavior() == LayoutBehavior::App {
+                    self.recents.retain(|&t| t != last_token);
+                    self.recents.insert(0, last_token);
+                    self.recents.truncate(MAX_RECENT_APPS);
+                }
                 self.last_context = self.focused_context;
                 self.focused_context = Some(last_token);
             }
@@ -464,6 +523,104 @@ impl ContextManager {
         }
         Ok(())
     }
+    fn notify_lifecycle(&self, event: gam::LifecycleEvent, context: &UxContext) -> Result<(), xous::Error> {
+        if let Some(lifecycle_id) = context.lifecycle_id {
+            log::trace!("lifecycle {:?} msg to {}, id {}", event, context.listener, lifecycle_id);
+            return xous::send_message(context.listener,
+                xous::Message::new_scalar(lifecycle_id as usize, event as usize, 0, 0, 0)
+            ).map(|_| ())
+        }
+        Ok(())
+    }
+    /// sends a LowMemory event to every context that does not currently have focus
+    pub(crate) fn low_memory(&self) {
+        for (token, context) in self.contexts.iter() {
+            if Some(*token) != self.focused_context {
+                self.notify_lifecycle(gam::LifecycleEvent::LowMemory, context).ok();
+            }
+        }
+    }
+    /// Delivers a Terminate event to an app, then retires its context: the canvases are released,
+    /// the app is dropped from the recents list, and its name may be registered again by a relaunched app.
+    /// Its token is released once it saves its state, or after `TERMINATE_GRACE_MS` if it doesn't.
+    /// Boot contexts (shellchat, menus, modals, etc.) can't be terminated.
+    pub(crate) fn terminate(&mut self,
+        name: &str,
+        gfx: &graphics_server::Gfx,
+        canvases: &mut HashMap<Gid, Canvas>,
+    ) -> Result<(), xous::Error> {
+        if gam::EXPECTED_APP_CONTEXTS.iter().find(|&&context| context == name).is_none() {
+            log::warn!("Refusing to terminate {}: not an app context", name);
+            return Err(xous::Error::AccessDenied)
+        }
+        let token = self.find_app_token_by_name(name).ok_or(xous::Error::ProcessNotFound)?;
+        if self.focused_context == Some(token) {
+            // hand focus back to the last app before tearing this one down
+            let next = self.recents.iter().find(|&&t| t != token).copied();
+            match next.or(self.find_app_token_by_name(gam::APP_NAME_SHELLCHAT)) {
+                Some(next_token) => self.activate(gfx, canvases, next_token, true)?,
+                None => return Err(xous::Error::UseBeforeInit),
+            }
+        }
+        if self.last_context == Some(token) {
+            self.last_context = None;
+        }
+        self.recents.retain(|&t| t != token);
+        if let Some(context) = self.contexts.remove(&token) {
+            self.notify_lifecycle(gam::LifecycleEvent::Terminate, &context).ok();
+            for gr in context.layout.get_gids().iter() {
+                canvases.remove(&gr.gid);
+            }
+            recompute_canvases(canvases);
+            unsafe{xous::disconnect(context.listener).ok();}
+        }
+        // the token stays good until the app saves its state, or its time is up
+        self.tm.retire_token(name, self.tt.elapsed_ms());
+        log::info!("terminated app context {}", name);
+        Ok(())
+    }
+    /// Releases the tokens of terminated apps that haven't saved their state in time
+    pub(crate) fn expire_terminated(&mut self) {
+        self.tm.expire_tokens(self.tt.elapsed_ms());
+    }
+    pub(crate) fn save_app_state(&mut self, token: [u32; 4], state: &[u8]) -> bool {
+        if let Some(name) = self.tm.find_name(token) {
+            let name = std::string::String::from(name);
+            self.app_states.insert(name.clone(), state.to_vec());
+            if self.tm.is_retiring(&name) {
+                // that was the terminated app's last word
+                self.tm.release_token(&name);
+            }
+            true
+        } else {
+            false
+        }
+    }
+    pub(crate) fn restore_app_state(&self, token: [u32; 4]) -> Option<&Vec<u8>> {
+        if let Some(name) = self.tm.find_name(token) {
+            self.app_states.get(name)
+        } else {
+            None
+        }
+    }
+    /// sends the recent apps list to the switcher thread, which rebuilds and raises the switcher menu
+    fn raise_app_switcher(&self) {
+        if let Some(conn) = self.switcher_conn {
+            let mut recent = RecentApps::default();
+            for (token, entry) in self.recents.iter()
+                .filter(|&&t| Some(t) != self.focused_context)
+                .zip(recent.names.iter_mut())
+            {
+                if let Some(name) = self.tm.find_name(*token) {
+                    *entry = Some(String::<128>::from_str(name));
+                }
+            }
+            let buf = Buffer::into_buf(recent).or(Err(xous::Error::InternalError)).unwrap();
+            buf.send(conn, SwitcherOp::Show.to_u32().unwrap()).expect("couldn't raise app switcher");
+        } else {
+            log::warn!("app switcher requested before it was initialized");
+        }
+    }
     pub(crate) fn redraw(&self) -> Result<(), xous::Error> { // redraws the currently focused context
         if let Some(token) = self.focused_app() {
             if let Some(context) = self.contexts.get(&token) {
@@ -494,6 +651,9 @@ impl ContextManager {
     pub(crate) fn find_app_token_by_name(&self, name: &str) -> Option<[u32; 4]> {
         self.tm.find_token(name)
     }
+    pub(crate) fn find_name_by_token(&self, token: [u32; 4]) -> Option<&str> {
+        self.tm.find_name(token)
+    }
     pub(crate) fn focused_app(&self) -> Option<[u32; 4]> {
         self.focused_context
     }
@@ -514,23 +674,28 @@ impl ContextManager {
         gfx: &graphics_server::Gfx,
         canvases: &mut HashMap<Gid, Canvas>,
     ) {
-        // only pop up the menu if the primary key hit is the menu key (search just the first entry of keys); reject multi-key hits
-        // only pop up the menu if it isn't already popped up
-        if keys[0] == '∴' {
-            if let Some(context) = self.get_context_by_token(self.focused_context.unwrap()) {
-                if context.layout.behavior() == LayoutBehavior::App {
-                    if let Some(menu_token) = self.find_app_token_by_name(MAIN_MENU_NAME) {
-                        // set the menu to the active context
-                        match self.activate(gfx, canvases, menu_token, false) {
-                            Ok(_) => (),
-                            Err(_) => log::warn!("Couldn't raise menu, user will have to try again."),
-                        }
-                        // don't pass the initial key hit back to the menu app, just eat it and return
-                        return;
+        let behavior = self.focused_context().map(|context| context.layout.behavior());
+        let keys = match route_keys(keys, behavior) {
+            KeyRoute::MainMenu => {
+                // the menu is only raised over an app, so it never pops up over itself
+                if let Some(menu_token) = self.find_app_token_by_name(MAIN_MENU_NAME) {
+                    // set the menu to the active context
+                    match self.activate(gfx, canvases, menu_token, false) {
+                        Ok(_) => (),
+                        Err(_) => log::warn!("Couldn't raise menu, user will have to try again."),
                     }
+                    // don't pass the initial key hit back to the menu app, just eat it and return
+                    return;
                 }
+                keys
             }
-        }
+            KeyRoute::AppSwitcher => {
+                self.raise_app_switcher();
+                return;
+            }
+            KeyRoute::Discard => return,
+            KeyRoute::Focused(keys) => keys,
+        };
 
         if self.imef_active {
             // use the IMEF
@@ -600,3 +765,30 @@ impl ContextManager {
         Err(xous::Error::ProcessNotFound)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NONE: char = '\u{0000}';
+
+    #[test]
+    fn menu_and_switcher_over_apps() {
+        let app = Some(LayoutBehavior::App);
+        assert_eq!(route_keys(['∴', NONE, NONE, NONE], app), KeyRoute::MainMenu);
+        assert_eq!(route_keys(['⇄', NONE, NONE, NONE], app), KeyRoute::AppSwitcher);
+        assert_eq!(route_keys(['a', NONE, NONE, NONE], app), KeyRoute::Focused(['a', NONE, NONE, NONE]));
+        // only the primary key hit counts
+        assert_eq!(route_keys(['a', '∴', NONE, NONE], app), KeyRoute::Focused(['a', '∴', NONE, NONE]));
+    }
+
+    #[test]
+    fn switcher_key_stays_out_of_alerts() {
+        for focus in [Some(LayoutBehavior::Alert), None].iter() {
+            assert_eq!(route_keys(['⇄', NONE, NONE, NONE], *focus), KeyRoute::Discard);
+            assert_eq!(route_keys(['a', '⇄', 'b', NONE], *focus), KeyRoute::Focused(['a', NONE, 'b', NONE]));
+            // the menu key is an ordinary key to a menu or modal
+            assert_eq!(route_keys(['∴', NONE, NONE, NONE], *focus), KeyRoute::Focused(['∴', NONE, NONE, NONE]));
+        }
+    }
+}
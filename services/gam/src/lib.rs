@@ -35,6 +35,7 @@ pub const STATUS_BAR_NAME: &'static str = "status";
 pub const APP_NAME_SHELLCHAT: &'static str = "shellchat";
 pub const APP_MENU_NAME: &'static str = "app menu";
 pub const KBD_MENU_NAME: &'static str = "keyboard menu";
+pub const RECENT_APPS_MENU_NAME: &'static str = "recent apps menu";
 
 /// UX context registry. Names here are authorized by the GAM to have Canvases.
 pub const EXPECTED_BOOT_CONTEXTS: &[&'static str] = &[
@@ -48,6 +49,7 @@ pub const EXPECTED_BOOT_CONTEXTS: &[&'static str] = &[
     PDDB_MENU_NAME,
     APP_MENU_NAME,
     KBD_MENU_NAME,
+    RECENT_APPS_MENU_NAME,
 ];
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -66,6 +68,27 @@ impl FocusState {
     }
 }
 
+/// Lifecycle events are sent to the `lifecycle_id` opcode of a context, if it registered one.
+/// The event code is in the first scalar argument of the message.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, num_derive::FromPrimitive, num_derive::ToPrimitive)]
+#[repr(u32)]
+pub enum LifecycleEvent {
+    /// the app has been raised and has I/O focus
+    Foreground = 0,
+    /// the app has lost focus. This is the time to save state with `Gam::save_app_state()`.
+    Background = 1,
+    /// the system is running short on memory; backgrounded apps should drop caches
+    LowMemory = 2,
+    /// the app has been retired and should exit. Its token stays good until it saves its state with
+    /// `Gam::save_app_state()`, or for a couple of seconds if it doesn't. Its context may be re-registered later.
+    Terminate = 3,
+}
+impl LifecycleEvent {
+    pub fn from_code(code: usize) -> Option<LifecycleEvent> {
+        FromPrimitive::from_usize(code)
+    }
+}
+
 
 #[derive(Debug)]
 pub struct Gam {
@@ -368,6 +391,76 @@ impl Gam {
     pub fn raise_modal(&self, modal_name: &str) -> Result<(), xous::Error> {
         self.raise_menu(modal_name)
     }
+    /// Parks a small blob of state with the GAM, keyed to the context name of the app that owns `token`.
+    /// The blob can be retrieved with `restore_app_state()` after the app is terminated and re-registers.
+    /// The GAM keeps this in RAM (it can't depend on the PDDB, as the PDDB depends on it), so the state
+    /// does not survive a reboot; apps that need durable state should store it in the PDDB.
+    pub fn save_app_state(&self, token: [u32; 4], state: &[u8]) -> Result<(), xous::Error> {
+        if state.len() > MAX_APP_STATE_LEN {
+            return Err(xous::Error::OutOfMemory);
+        }
+        let mut blob = AppStateBlob {
+            token,
+            data: [0u8; MAX_APP_STATE_LEN],
+            len: Some(state.len()),
+        };
+        blob.data[..state.len()].copy_from_slice(state);
+        let mut buf = Buffer::into_buf(blob).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::SaveAppState.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        let ret = buf.to_original::<AppStateBlob, _>().unwrap();
+        if ret.len.is_some() {
+            Ok(())
+        } else {
+            Err(xous::Error::AccessDenied)
+        }
+    }
+    /// Retrieves the state blob previously saved by the app that owns `token`, if any.
+    pub fn restore_app_state(&self, token: [u32; 4]) -> Result<Option<Vec<u8>>, xous::Error> {
+        let blob = AppStateBlob {
+            token,
+            data: [0u8; MAX_APP_STATE_LEN],
+            len: None,
+        };
+        let mut buf = Buffer::into_buf(blob).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::RestoreAppState.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        let ret = buf.to_original::<AppStateBlob, _>().unwrap();
+        if let Some(len) = ret.len {
+            Ok(Some(ret.data[..len].to_vec()))
+        } else {
+            Ok(None)
+        }
+    }
+    /// The name of the app or context that holds `token`, or `None` if no one does. Servers that keep
+    /// things on behalf of apps can use this to tell them apart: an app proves who it is by handing over
+    /// its token, which no other process can know.
+    pub fn token_owner(&self, token: [u32; 4]) -> Result<Option<std::string::String>, xous::Error> {
+        let owner = TokenOwner {
+            token,
+            name: None,
+        };
+        let mut buf = Buffer::into_buf(owner).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::TokenOwner.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        let ret = buf.to_original::<TokenOwner, _>().unwrap();
+        Ok(ret.name.and_then(|name| name.as_str().ok().map(std::string::String::from)))
+    }
+    /// Asks the GAM to send a `LowMemory` lifecycle event to all backgrounded contexts. Like
+    /// `terminate_app()`, only certain sources may do this. Failure is silent.
+    pub fn low_memory_notify(&self, token: [u32; 4]) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::LowMemory.to_usize().unwrap(),
+                token[0] as usize, token[1] as usize, token[2] as usize, token[3] as usize)
+        ).map(|_| ())
+    }
+    /// Requests that an app be terminated. Like `switch_to_app()`, only certain sources may do this,
+    /// and only app contexts (not boot contexts) can be terminated. Failure is silent.
+    pub fn terminate_app(&self, app_name: &str, token: [u32; 4]) -> Result<(), xous::Error> {
+        let terminate = SwitchToApp {
+            token,
+            app_name: String::<128>::from_str(app_name),
+        };
+        let buf = Buffer::into_buf(terminate).or(Err(xous::Error::InternalError))?;
+        buf.send(self.conn, Opcode::TerminateApp.to_u32().unwrap()).or(Err(xous::Error::InternalError)).map(|_|())
+    }
     /// this is a one-way door, once you've set it, you can't unset it.
     pub fn set_devboot(&self, enable: bool) -> Result<(), xous::Error> {
         let ena =
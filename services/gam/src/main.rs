@@ -11,6 +11,8 @@ mod layouts;
 use layouts::*;
 mod contexts;
 use contexts::*;
+mod recents;
+use recents::*;
 
 use graphics_server::*;
 use xous_ipc::{Buffer, String};
@@ -88,6 +90,9 @@ fn xmain() -> ! {
     // a random number we can use to identify ourselves between API calls
     let gam_token = [trng.get_u32().unwrap(), trng.get_u32().unwrap(), trng.get_u32().unwrap(), trng.get_u32().unwrap()];
 
+    // the recent apps switcher registers a menu with us, so it only completes its setup once the main loop is running
+    context_mgr.set_switcher_conn(start_switcher(gam_token));
+
     let mut powerdown_requested = false;
     let mut last_time: u64 = ticktimer.elapsed_ms();
     let mut did_test = false; // allow one go at the test pattern
@@ -98,7 +103,9 @@ fn xmain() -> ! {
         log::info!("********************************************************************************");
         log::info!("USAGE:");
         log::info!("   `Home` key to bring up menu; arrow keys to go up/down; `Home` to select again");
+        log::info!("   `Shift`+`Home` to bring up the recent apps switcher");
         log::info!("   `F1`-`F4` to pick predictions; `F5` and `F6` generate test unicode characters");
+        log::info!("   `F7` is the compose key, e.g. `F7` `o` `/` types ø");
         log::info!("   Otherwise type in the GUI window; `help` for the current command list");
         log::info!("   ^C in the console window (this window) to quit");
         log::info!("********************************************************************************");
@@ -106,6 +113,7 @@ fn xmain() -> ! {
     loop {
         let mut msg = xous::receive_message(gam_sid).unwrap();
         log::trace!("Message: {:?}", msg);
+        context_mgr.expire_terminated();
         match FromPrimitive::from_usize(msg.body.id()) {
             Some(Opcode::ClearCanvas) => {
                 msg_scalar_unpack!(msg, g0, g1, g2, g3, {
@@ -555,6 +563,59 @@ fn xmain() -> ! {
                 }
                 xous::return_scalar(msg.sender, 1).expect("couldn't ack self test");
             }),
+            Some(Opcode::SaveAppState) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut blob = buffer.to_original::<AppStateBlob, _>().unwrap();
+                let saved = match blob.len {
+                    Some(len) if len <= blob.data.len() => context_mgr.save_app_state(blob.token, &blob.data[..len]),
+                    _ => false,
+                };
+                if !saved {
+                    log::warn!("couldn't save app state: invalid token or length");
+                    blob.len = None;
+                }
+                blob.data = [0u8; MAX_APP_STATE_LEN]; // don't echo the state back
+                buffer.replace(blob).unwrap();
+            },
+            Some(Opcode::RestoreAppState) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut blob = buffer.to_original::<AppStateBlob, _>().unwrap();
+                if let Some(state) = context_mgr.restore_app_state(blob.token) {
+                    blob.data[..state.len()].copy_from_slice(state);
+                    blob.len = Some(state.len());
+                } else {
+                    blob.len = None;
+                }
+                buffer.replace(blob).unwrap();
+            },
+            Some(Opcode::LowMemory) => msg_scalar_unpack!(msg, t0, t1, t2, t3, {
+                let token = [t0 as u32, t1 as u32, t2 as u32, t3 as u32];
+                // same policy as TerminateApp
+                if may_manage_apps(&context_mgr, gam_token, token) {
+                    context_mgr.low_memory();
+                } else {
+                    log::warn!("unauthorized low memory notification");
+                }
+            }),
+            Some(Opcode::TerminateApp) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let terminate = buffer.to_original::<SwitchToApp, _>().unwrap();
+                let name = terminate.app_name.as_str().unwrap_or("UTF-8 error");
+                if may_manage_apps(&context_mgr, gam_token, terminate.token) {
+                    match context_mgr.terminate(name, &gfx, &mut canvases) {
+                        Ok(_) => (),
+                        Err(e) => log::warn!("failed to terminate {}: {:?}, silent error!", name, e),
+                    }
+                } else {
+                    log::warn!("unauthorized request to terminate {}", name);
+                }
+            },
+            Some(Opcode::TokenOwner) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut owner = buffer.to_original::<TokenOwner, _>().unwrap();
+                owner.name = context_mgr.find_name_by_token(owner.token).map(String::<128>::from_str);
+                buffer.replace(owner).unwrap();
+            },
             Some(Opcode::Quit) => break,
             None => {log::error!("unhandled message {:?}", msg);}
         }
@@ -570,3 +631,11 @@ fn xmain() -> ! {
     log::trace!("quitting");
     xous::terminate_process(0)
 }
+
+/// Same policy as SwitchToApp: only the main menu, the status bar, or ourselves may retire apps, or
+/// tell them to give memory back
+fn may_manage_apps(context_mgr: &ContextManager, gam_token: [u32; 4], token: [u32; 4]) -> bool {
+    context_mgr.find_app_token_by_name(MAIN_MENU_NAME) == Some(token)
+        || context_mgr.find_app_token_by_name(gam::STATUS_BAR_NAME) == Some(token)
+        || gam_token == token
+}
@@ -54,6 +54,7 @@ impl<'a> Menu<'a> {
                 gotinput_id: None,
                 audioframe_id: None,
                 focuschange_id: None,
+                lifecycle_id: None,
                 rawkeys_id: Some(MenuOpcode::Rawkeys.to_u32().unwrap()),
             }
         ).expect("couldn't register my Ux element with GAM");
@@ -280,6 +280,7 @@ impl<'a> Modal<'a> {
                 gotinput_id: None,
                 audioframe_id: None,
                 focuschange_id: None,
+                lifecycle_id: None,
                 rawkeys_id: Some(ModalOpcode::Rawkeys.to_u32().unwrap()),
             }
         ).expect("couldn't register my Ux element with GAM");
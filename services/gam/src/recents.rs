@@ -0,0 +1,100 @@
+use gam::{MenuItem, MenuPayload};
+use xous_ipc::{Buffer, String};
+use num_traits::*;
+use locales::t;
+
+/// Maximum number of entries tracked in the recent apps switcher
+pub(crate) const MAX_RECENT_APPS: usize = 8;
+
+#[derive(Debug, Default, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub(crate) struct RecentApps {
+    /// app names in most-recently-used order; the list is compacted, with no holes
+    pub(crate) names: [Option<String::<128>>; MAX_RECENT_APPS],
+}
+
+#[derive(Debug, num_derive::FromPrimitive, num_derive::ToPrimitive)]
+pub(crate) enum SwitcherOp {
+    /// rebuild the switcher menu from a `RecentApps` list, and raise it
+    Show,
+    /// a menu item was picked; the argument is an index into the last list shown
+    Select,
+    Quit,
+}
+
+/*
+  The switcher is a regular Menu, and Menus talk to the GAM using blocking calls. So, the
+  GAM main loop can't manage the menu directly without deadlocking itself; instead, it
+  hands the list of recent apps to this thread, which rebuilds the menu and raises it.
+  Selections are turned into a SwitchToApp request authenticated with the GAM's own token.
+*/
+pub(crate) fn start_switcher(gam_token: [u32; 4]) -> xous::CID {
+    let sid = xous::create_server().expect("couldn't create app switcher server");
+    let conn = xous::connect(sid).expect("couldn't connect to app switcher server");
+    std::thread::spawn({
+        move || {
+            switcher_thread(sid, conn, gam_token);
+        }
+    });
+    conn
+}
+
+fn switcher_thread(sid: xous::SID, self_conn: xous::CID, gam_token: [u32; 4]) {
+    let xns = xous_names::XousNames::new().unwrap();
+    let gam = gam::Gam::new(&xns).expect("can't connect to GAM");
+    let mgr_sid = xous::create_server().expect("couldn't create app switcher menu manager");
+    let menu = gam::menu_matic(Vec::new(), gam::RECENT_APPS_MENU_NAME, Some(mgr_sid)).expect("couldn't create app switcher menu");
+
+    // names of the menu items currently installed, so they can be removed on the next rebuild
+    let mut shown: Vec::<std::string::String> = Vec::new();
+    // the app names corresponding to each selectable index
+    let mut snapshot: Vec::<std::string::String> = Vec::new();
+    loop {
+        let msg = xous::receive_message(sid).unwrap();
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(SwitcherOp::Show) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let recent = buffer.to_original::<RecentApps, _>().unwrap();
+                for name in shown.drain(..) {
+                    menu.delete_item(&name);
+                }
+                snapshot.clear();
+                for name in recent.names.iter() {
+                    if let Some(name) = name {
+                        let item_name = std::string::String::from(name.as_str().unwrap_or("UTF-8 error"));
+                        menu.add_item(MenuItem {
+                            name: String::<64>::from_str(&item_name),
+                            action_conn: Some(self_conn),
+                            action_opcode: SwitcherOp::Select.to_u32().unwrap(),
+                            action_payload: MenuPayload::Scalar([snapshot.len() as u32, 0, 0, 0]),
+                            close_on_select: true,
+                        });
+                        shown.push(item_name.clone());
+                        snapshot.push(item_name);
+                    } else {
+                        break;
+                    }
+                }
+                let close = std::string::String::from(t!("mainmenu.closemenu", xous::LANG));
+                menu.add_item(MenuItem {
+                    name: String::<64>::from_str(&close),
+                    action_conn: None,
+                    action_opcode: 0,
+                    action_payload: MenuPayload::Scalar([0, 0, 0, 0]),
+                    close_on_select: true,
+                });
+                shown.push(close);
+                menu.set_index(0);
+                gam.raise_menu(gam::RECENT_APPS_MENU_NAME).ok();
+            }
+            Some(SwitcherOp::Select) => xous::msg_scalar_unpack!(msg, index, _, _, _, {
+                if let Some(name) = snapshot.get(index) {
+                    gam.switch_to_app(name, gam_token).expect("couldn't request app switch");
+                }
+            }),
+            Some(SwitcherOp::Quit) => break,
+            None => log::error!("unknown opcode {:?}", msg.body.id()),
+        }
+    }
+    menu.quit();
+    xous::destroy_server(sid).unwrap();
+}
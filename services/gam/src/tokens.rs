@@ -16,27 +16,108 @@ pub(crate) struct NamedToken {
     token: [u32; 4],
     name: String,
 }
+
+/// How long a terminated app's token stays good for, so it can save its state on the way out
+pub(crate) const TERMINATE_GRACE_MS: u64 = 2000;
+
+/// The registered contexts. Once an app context is released, its slot is emptied, so its token no
+/// longer resolves, and its name may be claimed again.
+#[derive(Default)]
+struct TokenTable {
+    tokens: Vec::<Option<NamedToken>>,
+    released: Vec::<String>,
+    /// apps that have been told to terminate, and when their tokens are released if they haven't
+    /// saved their state by then
+    retiring: Vec::<(String, u64)>,
+}
+impl TokenTable {
+    fn occupied(&self) -> usize {
+        self.tokens.len()
+    }
+    fn names(&self) -> impl Iterator<Item = &str> {
+        self.tokens.iter().map(|t| t.as_ref().map_or("(released)", |t| t.name.as_str()))
+    }
+    fn claim(&mut self, name: &str, token: [u32; 4]) -> Option<[u32; 4]> {
+        if self.is_retiring(name) {
+            // relaunched before the old instance was done: it's had its chance to save
+            self.release(name);
+        }
+        if self.tokens.iter().flatten().any(|namedtoken| namedtoken.name == name) {
+            log::error!("Attempt to re-register a UX context: {}", name);
+            return None
+        }
+        let named = NamedToken { token, name: String::from(name) };
+        if let Some(i) = self.released.iter().position(|released| released == name) {
+            // a terminated app is being relaunched: re-issue a fresh token in the slot it vacated
+            log::info!("re-registering released UX context {}", name);
+            self.released.remove(i);
+            let slot = self.tokens.iter_mut().find(|t| t.is_none()).expect("released context has no free slot");
+            *slot = Some(named);
+            return Some(token)
+        }
+        log::trace!("registering {} to {:x?}", name, token);
+        self.tokens.push(Some(named));
+        Some(token)
+    }
+    fn find_token(&self, name: &str) -> Option<[u32; 4]> {
+        self.tokens.iter().flatten().find(|namedtoken| namedtoken.name == name).map(|namedtoken| namedtoken.token)
+    }
+    fn find_name(&self, token: [u32; 4]) -> Option<&str> {
+        self.tokens.iter().flatten().find(|namedtoken| namedtoken.token == token).map(|namedtoken| namedtoken.name.as_str())
+    }
+    fn retire(&mut self, name: &str, deadline_ms: u64) -> bool {
+        if self.find_token(name).is_none() || self.is_retiring(name) {
+            return false
+        }
+        self.retiring.push((String::from(name), deadline_ms));
+        true
+    }
+    fn is_retiring(&self, name: &str) -> bool {
+        self.retiring.iter().any(|(retiring, _)| retiring == name)
+    }
+    fn expire(&mut self, now_ms: u64) {
+        let due: Vec<String> = self.retiring.iter()
+            .filter(|(_, deadline)| *deadline <= now_ms)
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in due {
+            log::info!("{} didn't save its state in time, releasing its token", name);
+            self.release(&name);
+        }
+    }
+    fn release(&mut self, name: &str) -> bool {
+        self.retiring.retain(|(retiring, _)| retiring != name);
+        if let Some(slot) = self.tokens.iter_mut().find(|t| matches!(t, Some(t) if t.name == name)) {
+            *slot = None;
+            self.released.push(String::from(name));
+            true
+        } else {
+            false
+        }
+    }
+}
+
 pub(crate) struct TokenManager {
-    tokens: Vec::<NamedToken>,
+    tokens: TokenTable,
     trng: trng::Trng,
 }
 impl<'a> TokenManager {
     pub(crate) fn new(xns: &xous_names::XousNames) -> TokenManager {
         TokenManager {
-            tokens: Vec::new(),
+            tokens: TokenTable::default(),
             trng: trng::Trng::new(&xns).unwrap(),
         }
     }
     /// checks to see if all the slots have been occupied. We can't allow untrusted code to run until all slots have checked in
     pub(crate) fn allow_untrusted_code(&self) -> bool {
-        if self.tokens.len() == (EXPECTED_BOOT_CONTEXTS.len() + EXPECTED_APP_CONTEXTS.len()) {
+        if self.tokens.occupied() == (EXPECTED_BOOT_CONTEXTS.len() + EXPECTED_APP_CONTEXTS.len()) {
             true
         } else {
             // throw a bone to the dev who has to debug this error. This typically only triggers after a major
             // refactor and some UX element was removed and we forgot to update it in this table here.
             log::info!("Occupied token slots:");
-            for t in self.tokens.iter() {
-                log::info!("{}", t.name);
+            for name in self.tokens.names() {
+                log::info!("{}", name);
             }
             false
         }
@@ -55,31 +136,108 @@ impl<'a> TokenManager {
             log::error!("Server {} is not pre-registered in gam/lib.rs/EXPECTED_BOOT_CONTEXTS or apps.rs/EXPECTED_APP_CONTEXTS. Did you forget to register it?", name);
             return None
         }
-        // now check if it hasn't already been registered
-        if self.tokens.iter().find(|&namedtoken| namedtoken.name == name).is_some() {
-            log::error!("Attempt to re-register a UX context: {}", name);
-            return None
-        }
-        // now do the registration
+        // now check if it hasn't already been registered, and do the registration
         let token = [self.trng.get_u32().unwrap(), self.trng.get_u32().unwrap(), self.trng.get_u32().unwrap(), self.trng.get_u32().unwrap(),];
-        log::trace!("registering {} to {:x?}", name, token);
-        self.tokens.push(
-            NamedToken {
-                token,
-                name: String::from(name),
-            }
-        );
-        return Some(token)
+        self.tokens.claim(name, token)
     }
     pub(crate) fn is_token_valid(&self, token: [u32; 4]) -> bool {
-        self.tokens.iter().find(|&namedtoken| namedtoken.token == token).is_some()
+        self.tokens.find_name(token).is_some()
     }
     pub(crate) fn find_token(&self, name: &str) -> Option<[u32; 4]> {
-        if let Some(i) = self.tokens.iter().position(|namedtoken| namedtoken.name == name) {
-            log::debug!("found {}:{:?}", name, self.tokens[i].token);
-            Some(self.tokens[i].token)
-        } else {
-            None
+        let token = self.tokens.find_token(name);
+        if let Some(token) = token {
+            log::debug!("found {}:{:?}", name, token);
         }
+        token
+    }
+    pub(crate) fn find_name(&self, token: [u32; 4]) -> Option<&str> {
+        self.tokens.find_name(token)
+    }
+    /// Invalidates the token associated with `name`, and allows the name to be claimed again. The slot
+    /// is kept, so the boot-time accounting in `allow_untrusted_code()` is not disturbed.
+    pub(crate) fn release_token(&mut self, name: &str) -> bool {
+        self.tokens.release(name)
+    }
+    /// Schedules the release of the token associated with `name`, `TERMINATE_GRACE_MS` after `now_ms`.
+    /// Until then, the app can still use it to save its state.
+    pub(crate) fn retire_token(&mut self, name: &str, now_ms: u64) -> bool {
+        self.tokens.retire(name, now_ms + TERMINATE_GRACE_MS)
+    }
+    pub(crate) fn is_retiring(&self, name: &str) -> bool {
+        self.tokens.is_retiring(name)
+    }
+    /// Releases the tokens of retiring apps whose grace period is over as of `now_ms`
+    pub(crate) fn expire_tokens(&mut self, now_ms: u64) {
+        self.tokens.expire(now_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn released_tokens_do_not_resolve() {
+        let mut table = TokenTable::default();
+        let old = table.claim("repl", [1, 2, 3, 4]).unwrap();
+        assert!(table.claim("shellchat", [5, 6, 7, 8]).is_some());
+        assert_eq!(table.find_name(old), Some("repl"));
+        assert!(table.release("repl"));
+        assert_eq!(table.find_name(old), None);
+        assert_eq!(table.find_token("repl"), None);
+        assert!(!table.release("repl"));
+        // the slot is still counted, so untrusted code stays locked out until the boot set is in
+        assert_eq!(table.occupied(), 2);
+        // a relaunch gets a fresh token in the same slot, and the old one stays dead
+        let new = table.claim("repl", [9, 10, 11, 12]).unwrap();
+        assert_eq!(table.occupied(), 2);
+        assert_eq!(table.find_name(new), Some("repl"));
+        assert_eq!(table.find_name(old), None);
+        assert_eq!(table.find_token("repl"), Some(new));
+    }
+
+    #[test]
+    fn names_register_once() {
+        let mut table = TokenTable::default();
+        assert!(table.claim("repl", [1, 2, 3, 4]).is_some());
+        assert!(table.claim("repl", [5, 6, 7, 8]).is_none());
+        assert_eq!(table.find_token("repl"), Some([1, 2, 3, 4]));
+        // only released names can be re-registered
+        assert!(!table.release("ball"));
+        assert!(table.claim("ball", [5, 6, 7, 8]).is_some());
+        assert_eq!(table.occupied(), 2);
+    }
+
+    #[test]
+    fn retiring_tokens_last_until_released_or_expired() {
+        let mut table = TokenTable::default();
+        let old = table.claim("repl", [1, 2, 3, 4]).unwrap();
+        assert!(table.claim("ball", [5, 6, 7, 8]).is_some());
+        assert!(table.retire("repl", 100));
+        assert!(!table.retire("repl", 200));
+        assert!(!table.retire("nobody", 100));
+        // still good for saving state until the deadline
+        table.expire(99);
+        assert_eq!(table.find_name(old), Some("repl"));
+        table.expire(100);
+        assert_eq!(table.find_name(old), None);
+        assert!(!table.is_retiring("repl"));
+
+        // saving state releases it straight away, and the deadline is forgotten
+        let old = table.claim("repl", [9, 10, 11, 12]).unwrap();
+        assert!(table.retire("repl", 300));
+        assert!(table.release("repl"));
+        assert_eq!(table.find_name(old), None);
+        let new = table.claim("repl", [13, 14, 15, 16]).unwrap();
+        table.expire(400);
+        assert_eq!(table.find_name(new), Some("repl"));
+
+        // relaunching cuts the grace period short
+        assert!(table.retire("ball", 500));
+        let ball = table.claim("ball", [17, 18, 19, 20]).unwrap();
+        assert_eq!(table.find_name([5, 6, 7, 8]), None);
+        assert_eq!(table.find_name(ball), Some("ball"));
+        assert!(!table.is_retiring("ball"));
+        assert_eq!(table.occupied(), 2);
     }
 }
@@ -202,6 +202,7 @@ impl XousKeyboardHandler {
                 Key::F4 => 0x14_u8.into(),
                 Key::F5 => '😊',
                 Key::F6 => '福',
+                Key::F7 => '⎄', // compose key
                 _ => '\u{0000}',
             }
         } else {
@@ -246,7 +247,7 @@ impl XousKeyboardHandler {
                 Key::Right => '→',
                 Key::Up => '↑',
                 Key::Down => '↓',
-                Key::Home => '∴',
+                Key::Home => '⇄', // shift + menu raises the app switcher
                 Key::Backspace => '\u{0008}',
                 Key::Delete => '\u{0008}',
                 //Key::Space => ' ',
@@ -3,6 +3,7 @@
 
 mod emoji;
 use emoji::*;
+mod splice;
 
 use gam::api::SetCanvasBoundsRequest;
 use ime_plugin_api::{ImefCallback, ImefDescriptor, ImefOpcode};
@@ -175,55 +176,19 @@ impl InputTracker {
         let debug1 = false;
         if debug1{info!("IMEF|insert_prediction index {}", index);}
         let pred_str = match &self.pred_options[index] {
-            Some(s) => s,
+            Some(s) => s.clone(),
             _ => return // if the index doesn't exist for some reason, do nothing without throwing an error
         };
         if debug1{info!("IMEF|insert_prediction string {}, last_trigger {:?}", pred_str, self.last_trigger_char);}
         if let Some(offset) = self.last_trigger_char {
             if offset < self.characters {
-                // copy the bytes in the original string, up to the offset; and then copy the bytes in the selected predictor
-                let tempstr = self.line.to_string();
-                self.line.clear();
-                let mut chars = 0;
-                let mut c_iter = tempstr.chars();
-                loop {
-                    if chars == offset + 1 { // +1 to include the original trigger (don't overwrite it)
-                        break;
-                    }
-                    if let Some(c) = c_iter.next() {
-                        self.line.push(c);
-                        chars += 1;
-                    } else {
-                        break;
-                    }
-                }
-                // now push the characters in the predicted options onto the line
-                for c in pred_str.as_str().chars() {
-                    self.line.push(c);
-                    chars += 1;
-                }
-                // forward until we find the next prediction trigger in the original string
-                while let Some(c) = c_iter.next() {
-                    if let Some(trigger) = self.pred_triggers {
-                        if trigger.whitespace && c.is_ascii_whitespace() ||
-                           trigger.punctuation && c.is_ascii_punctuation() {
-                               // include the trigger that was found
-                               self.line.push(c);
-                               chars += 1;
-                               break;
-                        }
-                    } else {
-                        // skip the replaced characters
-                    }
-                }
-                self.insertion = chars;
-                self.last_trigger_char = Some(chars);
-                // copy the remainder of the line, if any
-                while let Some(c) = c_iter.next() {
-                    self.line.push(c);
-                    chars += 1;
-                }
-                self.characters = chars;
+                let spliced = splice::splice(&self.line, offset, &pred_str, self.pred_triggers);
+                self.line = spliced.line;
+                self.characters = spliced.characters;
+                self.insertion = spliced.insertion;
+                if spliced.found_trigger {
+                    self.last_trigger_char = Some(spliced.insertion);
+                } // otherwise the picked text is still being worked on, and another pick replaces it again
             } else {
                 // just append the prediction to the line
                 for c in pred_str.as_str().chars() {
@@ -233,6 +198,9 @@ impl InputTracker {
                 self.last_trigger_char = Some(self.insertion);
                 self.insertion = self.characters;
             }
+            // the picked word replaces what was typed of it, so that's what the predictor learns on the next trigger
+            self.pred_phrase.clear();
+            self.pred_phrase.push_str(&pred_str);
         }
     }
 
@@ -294,18 +262,22 @@ impl InputTracker {
                     '\u{0011}' => { // F1
                         self.insert_prediction(0);
                         do_redraw = true;
+                        update_predictor = true;
                     }
                     '\u{0012}' => { // F2
                         self.insert_prediction(1);
                         do_redraw = true;
+                        update_predictor = true;
                     }
                     '\u{0013}' => { // F3
                         self.insert_prediction(2);
                         do_redraw = true;
+                        update_predictor = true;
                     }
                     '\u{0014}' => { // F4
                         self.insert_prediction(3);
                         do_redraw = true;
+                        update_predictor = true;
                     }
                     '\u{0008}' => { // backspace
                         #[cfg(feature="tts")]
@@ -413,7 +385,8 @@ impl InputTracker {
                                     self.can_unpick = true;
                                     update_predictor = true;
                                 }
-                                self.last_trigger_char = Some(self.insertion);
+                                // the trigger is about to go in at the insertion point; predictions start after it
+                                self.last_trigger_char = Some(self.insertion + 1);
                             } else if trigger.punctuation && k.is_ascii_punctuation() {
                                 if self.pred_phrase.len() > 0 {
                                     self.predictor.unwrap().feedback_picked(
@@ -422,7 +395,7 @@ impl InputTracker {
                                     self.can_unpick = true;
                                     update_predictor = true;
                                 }
-                                self.last_trigger_char = Some(self.insertion);
+                                self.last_trigger_char = Some(self.insertion + 1);
                             }
                         }
                         if self.insertion == self.characters {
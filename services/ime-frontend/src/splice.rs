@@ -0,0 +1,104 @@
+//! Putting a picked prediction into the middle of the input line.
+//!
+//! A prediction replaces what was typed since the last prediction trigger, up to the next
+//! trigger or the end of the line. The position of the last trigger is kept as the character
+//! just after it, so the trigger itself stays on the line, and at the start of a line (where
+//! there was no trigger) nothing is kept.
+
+use ime_plugin_api::PredictionTriggers;
+
+/// The input line once a prediction has gone in
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Spliced {
+    pub line: String,
+    /// length of the line in characters
+    pub characters: usize,
+    /// where typing carries on: just after the prediction, and the trigger after it if any
+    pub insertion: usize,
+    /// whether the replaced text ended at a trigger. If it didn't, the prediction is still the
+    /// word being worked on, and picking another prediction replaces it again.
+    pub found_trigger: bool,
+}
+
+/// Replaces the characters of `line` from `offset` up to the next trigger with `prediction`
+pub(crate) fn splice(line: &str, offset: usize, prediction: &str, triggers: Option<PredictionTriggers>) -> Spliced {
+    let mut spliced = String::new();
+    let mut chars = 0;
+    let mut c_iter = line.chars();
+    // keep everything up to the offset, which includes the trigger, if any
+    for c in c_iter.by_ref().take(offset) {
+        spliced.push(c);
+        chars += 1;
+    }
+    for c in prediction.chars() {
+        spliced.push(c);
+        chars += 1;
+    }
+    // skip the replaced characters, up to and including the next trigger
+    let mut found_trigger = false;
+    for c in c_iter.by_ref() {
+        if let Some(trigger) = triggers {
+            if trigger.whitespace && c.is_ascii_whitespace() || trigger.punctuation && c.is_ascii_punctuation() {
+                spliced.push(c);
+                chars += 1;
+                found_trigger = true;
+                break;
+            }
+        }
+    }
+    let insertion = chars;
+    // copy the remainder of the line, if any
+    for c in c_iter {
+        spliced.push(c);
+        chars += 1;
+    }
+    Spliced { line: spliced, characters: chars, insertion, found_trigger }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORDS: Option<PredictionTriggers> = Some(PredictionTriggers { newline: false, punctuation: true, whitespace: true });
+
+    #[test]
+    fn first_word_is_replaced_whole() {
+        // there's no trigger before the first word, so nothing of it is kept
+        let s = splice("hel", 0, "hello", WORDS);
+        assert_eq!(s.line, "hello");
+        assert_eq!((s.characters, s.insertion, s.found_trigger), (5, 5, false));
+    }
+
+    #[test]
+    fn trigger_before_the_word_is_kept() {
+        // "say " ends in a trigger at position 3, so the word starts at 4
+        let s = splice("say hel", 4, "hello", WORDS);
+        assert_eq!(s.line, "say hello");
+        assert_eq!((s.characters, s.insertion, s.found_trigger), (9, 9, false));
+    }
+
+    #[test]
+    fn words_after_the_next_trigger_stay() {
+        let s = splice("say hel there", 4, "hello", WORDS);
+        assert_eq!(s.line, "say hello there");
+        // typing carries on after the trigger that ended the replaced word
+        assert_eq!((s.characters, s.insertion, s.found_trigger), (15, 10, true));
+    }
+
+    #[test]
+    fn picking_again_replaces_the_pick() {
+        // with no trigger after it, the picked word is still the one being worked on
+        let first = splice("nihao", 0, "你好", WORDS);
+        assert!(!first.found_trigger);
+        let second = splice(&first.line, 0, "拟好", WORDS);
+        assert_eq!(second.line, "拟好");
+        assert_eq!(second.characters, 2);
+    }
+
+    #[test]
+    fn without_triggers_the_rest_of_the_line_goes() {
+        let s = splice("ab cd", 1, "xyz", None);
+        assert_eq!(s.line, "axyz");
+        assert_eq!((s.characters, s.insertion, s.found_trigger), (4, 4, false));
+    }
+}
@@ -0,0 +1,27 @@
+// Compresses the dictionaries in `dict/` so they take up less room in the image.
+use std::env;
+use std::fs;
+use std::path::Path;
+
+const DICTIONARIES: [&str; 2] = ["pinyin", "kana"];
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    for name in DICTIONARIES.iter() {
+        let source = format!("dict/{}.txt", name);
+        println!("cargo:rerun-if-changed={}", source);
+        let text = fs::read_to_string(&source).expect("couldn't read dictionary");
+        // comments are only for people editing the source
+        let mut stripped = String::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                stripped.push_str(line);
+                stripped.push('\n');
+            }
+        }
+        let compressed = miniz_oxide::deflate::compress_to_vec(stripped.as_bytes(), 10);
+        fs::write(Path::new(&out_dir).join(format!("{}.dict.z", name)), compressed).expect("couldn't write dictionary");
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
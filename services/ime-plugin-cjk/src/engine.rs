@@ -0,0 +1,160 @@
+//! The state shared by both conversion schemes: the lexicon, the candidates for the current
+//! input, and learning from what the user picks.
+
+use crate::lexicon::{Learned, Lexicon};
+use crate::{kana, pinyin};
+
+/// A conversion offered to the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// the whole input line, as it reads once this candidate is picked
+    pub text: String,
+    /// the part of the input that was converted
+    pub reading: String,
+    /// what `reading` was converted into
+    pub converted: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Scheme {
+    Pinyin,
+    Kana,
+}
+
+#[derive(Debug)]
+pub struct Engine {
+    scheme: Scheme,
+    lexicon: Lexicon,
+    candidates: Vec<Candidate>,
+    /// what the last pick taught the lexicon, until it's unpicked
+    last_learned: Option<Learned>,
+    /// the learned candidates changed since they were last saved
+    dirty: bool,
+}
+
+impl Engine {
+    pub fn new(scheme: Scheme, lexicon: Lexicon) -> Self {
+        Engine { scheme, lexicon, candidates: Vec::new(), last_learned: None, dirty: false }
+    }
+    /// Takes in the input line as it stands. The frontend doesn't say when a prediction gets
+    /// picked, but it sends the new input right after, so a pick is recognised by the input
+    /// turning into one of the candidates.
+    pub fn set_input(&mut self, input: &str) {
+        if let Some(c) = self.candidates.iter().find(|c| c.text == input) {
+            if !c.reading.is_empty() && c.converted != c.reading {
+                self.last_learned = Some(self.lexicon.learn(&c.reading, &c.converted));
+                self.dirty = true;
+            }
+        }
+        self.candidates = match self.scheme {
+            Scheme::Pinyin => pinyin::candidates(&self.lexicon, input),
+            Scheme::Kana => kana::candidates(&self.lexicon, input),
+        };
+    }
+    /// The line was committed; start over.
+    pub fn picked(&mut self) {
+        self.candidates.clear();
+    }
+    /// The user backed out of the last pick, so it isn't offered first from now on after all.
+    /// Only the last pick can be taken back.
+    pub fn unpick(&mut self) {
+        if let Some(learned) = self.last_learned.take() {
+            self.lexicon.forget(learned);
+            self.dirty = true;
+        }
+    }
+    pub fn prediction(&self, index: usize) -> Option<&str> {
+        self.candidates.get(index).map(|c| c.text.as_str())
+    }
+
+    pub fn lexicon_mut(&mut self) -> &mut Lexicon {
+        // whatever changes the lexicon next, the last pick can't be taken back on top of it
+        self.last_learned = None;
+        &mut self.lexicon
+    }
+    pub fn learned_to_text(&self) -> String {
+        self.lexicon.learned_to_text()
+    }
+    /// Returns whether anything was learned since the last call.
+    pub fn take_dirty(&mut self) -> bool {
+        core::mem::replace(&mut self.dirty, false)
+    }
+    pub fn set_dirty(&mut self) {
+        self.dirty = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn learns_picks() {
+        let mut lex = Lexicon::default();
+        lex.extend("shi 是 时 事\nshijian 时间 事件\n");
+        let mut engine = Engine::new(Scheme::Pinyin, lex);
+        engine.set_input("shijian");
+        assert_eq!(engine.prediction(0), Some("时间"));
+        assert_eq!(engine.prediction(1), Some("事件"));
+        // the frontend sends the picked candidate back as the new input
+        engine.set_input("事件");
+        assert!(engine.take_dirty());
+        assert!(!engine.take_dirty());
+        assert_eq!(engine.prediction(0), None);
+        engine.picked();
+        engine.set_input("shijian");
+        assert_eq!(engine.prediction(0), Some("事件"));
+        assert_eq!(engine.prediction(1), Some("时间"));
+        assert_eq!(engine.learned_to_text(), "shijian 事件\n");
+
+        // picking the hiragana spelling isn't worth remembering
+        let mut engine = Engine::new(Scheme::Kana, Lexicon::default());
+        engine.set_input("kana");
+        assert_eq!(engine.prediction(0), Some("かな"));
+        engine.set_input("かな");
+        assert!(!engine.take_dirty());
+    }
+
+    #[test]
+    fn unpicking() {
+        let mut lex = Lexicon::default();
+        lex.extend("shijian 时间 事件\n");
+        let mut engine = Engine::new(Scheme::Pinyin, lex);
+        engine.set_input("shijian");
+        engine.set_input("事件");
+        engine.picked();
+        assert!(engine.take_dirty());
+        engine.unpick();
+        // the saved picks need rewriting without it
+        assert!(engine.take_dirty());
+        engine.set_input("shijian");
+        assert_eq!(engine.prediction(0), Some("时间"));
+        assert_eq!(engine.learned_to_text(), "");
+        // there's only one pick to take back
+        engine.unpick();
+        assert!(!engine.take_dirty());
+    }
+
+    #[test]
+    fn builtin_dictionaries() {
+        let mut lex = Lexicon::default();
+        assert_eq!(lex.extend(include_str!("../dict/pinyin.txt")), 0);
+        let mut engine = Engine::new(Scheme::Pinyin, lex);
+        engine.set_input("nihaoshijie");
+        assert_eq!(engine.prediction(0), Some("你好世界"));
+        assert_eq!(engine.prediction(1), Some("你好shijie"));
+        engine.set_input("womenzhongguo");
+        assert_eq!(engine.prediction(0), Some("我们中国"));
+
+        let mut lex = Lexicon::default();
+        assert_eq!(lex.extend(include_str!("../dict/kana.txt")), 0);
+        let mut engine = Engine::new(Scheme::Kana, lex);
+        engine.set_input("nihongo");
+        assert_eq!(engine.prediction(0), Some("にほんご"));
+        assert_eq!(engine.prediction(1), Some("日本語"));
+        assert_eq!(engine.prediction(2), Some("ニホンゴ"));
+        assert_eq!(engine.prediction(3), Some("日本ご"));
+        engine.set_input("watashiha");
+        assert_eq!(engine.prediction(0), Some("わたしは"));
+    }
+}
@@ -0,0 +1,213 @@
+//! Romaji to kana and kanji conversion.
+//!
+//! The run of romaji and hiragana at the end of the input is converted: first the romaji is
+//! spelled out in hiragana (Hepburn and kunrei-shiki both work, `x` or `l` in front of a vowel
+//! or `tsu` gives the small form), then the reading is looked up in the lexicon. The hiragana
+//! and katakana spellings are always offered too, so words that aren't in the dictionary can
+//! still be typed.
+
+use crate::engine::Candidate;
+use crate::lexicon::Lexicon;
+
+/// Number of candidates offered for an input
+pub const MAX_CANDIDATES: usize = 8;
+
+/// Romaji spellings and their hiragana, looked up longest first
+const ROMAJI: &[(&str, &str)] = &[
+    ("a", "あ"), ("i", "い"), ("u", "う"), ("e", "え"), ("o", "お"),
+    ("ka", "か"), ("ki", "き"), ("ku", "く"), ("ke", "け"), ("ko", "こ"),
+    ("ga", "が"), ("gi", "ぎ"), ("gu", "ぐ"), ("ge", "げ"), ("go", "ご"),
+    ("sa", "さ"), ("si", "し"), ("shi", "し"), ("su", "す"), ("se", "せ"), ("so", "そ"),
+    ("za", "ざ"), ("zi", "じ"), ("ji", "じ"), ("zu", "ず"), ("ze", "ぜ"), ("zo", "ぞ"),
+    ("ta", "た"), ("ti", "ち"), ("chi", "ち"), ("tu", "つ"), ("tsu", "つ"), ("te", "て"), ("to", "と"),
+    ("da", "だ"), ("di", "ぢ"), ("du", "づ"), ("de", "で"), ("do", "ど"),
+    ("na", "な"), ("ni", "に"), ("nu", "ぬ"), ("ne", "ね"), ("no", "の"),
+    ("ha", "は"), ("hi", "ひ"), ("hu", "ふ"), ("fu", "ふ"), ("he", "へ"), ("ho", "ほ"),
+    ("ba", "ば"), ("bi", "び"), ("bu", "ぶ"), ("be", "べ"), ("bo", "ぼ"),
+    ("pa", "ぱ"), ("pi", "ぴ"), ("pu", "ぷ"), ("pe", "ぺ"), ("po", "ぽ"),
+    ("ma", "ま"), ("mi", "み"), ("mu", "む"), ("me", "め"), ("mo", "も"),
+    ("ya", "や"), ("yu", "ゆ"), ("yo", "よ"),
+    ("ra", "ら"), ("ri", "り"), ("ru", "る"), ("re", "れ"), ("ro", "ろ"),
+    ("wa", "わ"), ("wo", "を"), ("n'", "ん"),
+    ("kya", "きゃ"), ("kyu", "きゅ"), ("kyo", "きょ"),
+    ("gya", "ぎゃ"), ("gyu", "ぎゅ"), ("gyo", "ぎょ"),
+    ("sya", "しゃ"), ("syu", "しゅ"), ("syo", "しょ"), ("sha", "しゃ"), ("shu", "しゅ"), ("she", "しぇ"), ("sho", "しょ"),
+    ("zya", "じゃ"), ("zyu", "じゅ"), ("zyo", "じょ"), ("ja", "じゃ"), ("ju", "じゅ"), ("je", "じぇ"), ("jo", "じょ"),
+    ("jya", "じゃ"), ("jyu", "じゅ"), ("jyo", "じょ"),
+    ("tya", "ちゃ"), ("tyu", "ちゅ"), ("tyo", "ちょ"), ("cha", "ちゃ"), ("chu", "ちゅ"), ("che", "ちぇ"), ("cho", "ちょ"),
+    ("nya", "にゃ"), ("nyu", "にゅ"), ("nyo", "にょ"),
+    ("hya", "ひゃ"), ("hyu", "ひゅ"), ("hyo", "ひょ"),
+    ("bya", "びゃ"), ("byu", "びゅ"), ("byo", "びょ"),
+    ("pya", "ぴゃ"), ("pyu", "ぴゅ"), ("pyo", "ぴょ"),
+    ("mya", "みゃ"), ("myu", "みゅ"), ("myo", "みょ"),
+    ("rya", "りゃ"), ("ryu", "りゅ"), ("ryo", "りょ"),
+    ("fa", "ふぁ"), ("fi", "ふぃ"), ("fe", "ふぇ"), ("fo", "ふぉ"),
+    ("vu", "ゔ"), ("-", "ー"),
+    ("xa", "ぁ"), ("xi", "ぃ"), ("xu", "ぅ"), ("xe", "ぇ"), ("xo", "ぉ"),
+    ("la", "ぁ"), ("li", "ぃ"), ("lu", "ぅ"), ("le", "ぇ"), ("lo", "ぉ"),
+    ("xya", "ゃ"), ("xyu", "ゅ"), ("xyo", "ょ"), ("lya", "ゃ"), ("lyu", "ゅ"), ("lyo", "ょ"),
+    ("xtu", "っ"), ("xtsu", "っ"), ("ltu", "っ"), ("ltsu", "っ"),
+];
+/// Length of the longest romaji spelling
+const LONGEST_ROMAJI: usize = 4;
+
+fn is_vowel(c: u8) -> bool {
+    matches!(c, b'a' | b'i' | b'u' | b'e' | b'o')
+}
+fn is_romaji(c: char) -> bool {
+    c.is_ascii_lowercase() || c == '\'' || c == '-'
+}
+fn is_hiragana(c: char) -> bool {
+    ('\u{3041}'..='\u{3096}').contains(&c) || c == 'ー'
+}
+
+/// Spells out the romaji in `s` in hiragana. Romaji at the end that doesn't spell anything yet
+/// is returned separately; when `finish` is set, a trailing `n` is taken to be ん.
+pub fn to_hiragana(s: &str, finish: bool) -> (String, String) {
+    let mut kana = String::new();
+    let mut i = 0;
+    'outer: while i < s.len() {
+        let rest = &s[i..];
+        let c = rest.chars().next().unwrap();
+        if !c.is_ascii() {
+            kana.push(c);
+            i += c.len_utf8();
+            continue;
+        }
+        let b = rest.as_bytes();
+        // a doubled consonant is a small tsu, as is the t of "tch"
+        if b.len() >= 2 && b[0].is_ascii_lowercase() && !is_vowel(b[0]) && b[0] != b'n'
+            && (b[1] == b[0] || (b[0] == b't' && b[1] == b'c')) {
+            kana.push('っ');
+            i += 1;
+            continue;
+        }
+        // n before a consonant is ん; "nn" is too, unless the second n starts the next syllable
+        if b[0] == b'n' && b.len() >= 2 && b[1].is_ascii_lowercase() && !is_vowel(b[1]) && b[1] != b'y' {
+            kana.push('ん');
+            let next_syllable = b[1] == b'n' && b.len() >= 3 && (is_vowel(b[2]) || b[2] == b'y');
+            i += if b[1] == b'n' && !next_syllable { 2 } else { 1 };
+            continue;
+        }
+        for len in (1..=LONGEST_ROMAJI.min(rest.len())).rev() {
+            if !rest.is_char_boundary(len) {
+                continue;
+            }
+            if let Some((_, k)) = ROMAJI.iter().find(|(r, _)| *r == &rest[..len]) {
+                kana.push_str(k);
+                i += len;
+                continue 'outer;
+            }
+        }
+        break;
+    }
+    let mut rest = String::from(&s[i..]);
+    if finish && rest == "n" {
+        kana.push('ん');
+        rest.clear();
+    }
+    (kana, rest)
+}
+
+/// The katakana spelling of a hiragana string.
+pub fn to_katakana(hiragana: &str) -> String {
+    hiragana.chars().map(|c| {
+        if ('\u{3041}'..='\u{3096}').contains(&c) {
+            core::char::from_u32(c as u32 + 0x60).unwrap()
+        } else {
+            c
+        }
+    }).collect()
+}
+
+/// Splits `input` into the text to keep and the trailing romaji and hiragana to convert.
+fn split_tail(input: &str) -> (&str, &str) {
+    let start = input.char_indices().rev()
+        .take_while(|&(_, c)| is_romaji(c) || is_hiragana(c))
+        .last()
+        .map_or(input.len(), |(i, _)| i);
+    input.split_at(start)
+}
+
+/// The conversions offered for `input`, best first.
+pub fn candidates(lex: &Lexicon, input: &str) -> Vec<Candidate> {
+    let (keep, tail) = split_tail(input);
+    let mut found: Vec<Candidate> = Vec::new();
+    if tail.is_empty() {
+        return found;
+    }
+    let (reading, rest) = to_hiragana(tail, true);
+    if reading.is_empty() {
+        return found;
+    }
+    let push = |found: &mut Vec<Candidate>, converted: String, reading: &str, rest: &str| {
+        let text = format!("{}{}{}", keep, converted, rest);
+        if text != input && !found.iter().any(|c| c.text == text) {
+            found.push(Candidate { text, reading: String::from(reading), converted });
+        }
+    };
+
+    // hiragana is what is being typed, so it comes first; the user picks it to keep going
+    push(&mut found, reading.clone(), &reading, &rest);
+    for c in lex.candidates(&reading) {
+        push(&mut found, String::from(c), &reading, &rest);
+    }
+    push(&mut found, to_katakana(&reading), &reading, &rest);
+    // then the readings that start the run, longest first
+    let chars: Vec<(usize, char)> = reading.char_indices().collect();
+    for len in (1..chars.len().min(lex.longest() + 1)).rev() {
+        let split = chars[len].0;
+        let start = &reading[..split];
+        let tail = format!("{}{}", &reading[split..], rest);
+        for c in lex.candidates(start) {
+            push(&mut found, String::from(c), start, &tail);
+        }
+        if found.len() >= MAX_CANDIDATES {
+            break;
+        }
+    }
+    found.truncate(MAX_CANDIDATES);
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn romaji() {
+        let h = |s| to_hiragana(s, true);
+        assert_eq!(h("konnichiha"), (String::from("こんにちは"), String::new()));
+        assert_eq!(h("kyouha"), (String::from("きょうは"), String::new()));
+        assert_eq!(h("kitte"), (String::from("きって"), String::new()));
+        assert_eq!(h("matcha"), (String::from("まっちゃ"), String::new()));
+        assert_eq!(h("shinbun"), (String::from("しんぶん"), String::new()));
+        assert_eq!(h("kanji"), (String::from("かんじ"), String::new()));
+        assert_eq!(h("kan'i"), (String::from("かんい"), String::new()));
+        assert_eq!(h("honn"), (String::from("ほん"), String::new()));
+        assert_eq!(h("hon"), (String::from("ほん"), String::new()));
+        assert_eq!(h("onnna"), (String::from("おんな"), String::new()));
+        assert_eq!(to_hiragana("hon", false), (String::from("ほ"), String::from("n")));
+        assert_eq!(h("tenk"), (String::from("てん"), String::from("k")));
+        assert_eq!(h("ra-men"), (String::from("らーめん"), String::new()));
+        assert_eq!(h("xtsu"), (String::from("っ"), String::new()));
+        assert_eq!(to_katakana("らーめん"), "ラーメン");
+    }
+
+    #[test]
+    fn candidate_order() {
+        let mut lex = Lexicon::default();
+        lex.extend("きょう 今日 京\nは 葉 歯\nにほん 日本\nにほんご 日本語\n");
+        let texts = |input| candidates(&lex, input).into_iter().map(|c| c.text).collect::<Vec<String>>();
+        assert_eq!(texts("kyou"), ["きょう", "今日", "京", "キョウ"]);
+        assert_eq!(texts("kyouha"), ["きょうは", "キョウハ", "今日は", "京は"]);
+        assert_eq!(texts("nihongo"), ["にほんご", "日本語", "ニホンゴ", "日本ご"]);
+        // incomplete romaji stays latin, and what was converted before is kept
+        assert_eq!(texts("今日、nihonk"), ["今日、にほんk", "今日、日本k", "今日、ニホンk"]);
+        // hiragana picked earlier can still be converted
+        let found = candidates(&lex, "きょう");
+        assert_eq!(found[0].text, "今日");
+        assert_eq!(found[0].reading, "きょう");
+        assert!(candidates(&lex, "今日").is_empty());
+    }
+}
@@ -0,0 +1,270 @@
+//! Readings and the text they convert to.
+//!
+//! A dictionary is plain text, one reading per line, followed by its candidates, most common
+//! first:
+//!
+//! ```text
+//! nihao 你好
+//! きょう 今日 京
+//! ```
+//!
+//! The built-in dictionaries live in `dict/`, and are deflated into the image by `build.rs`.
+//! A user dictionary in the same format can be kept in the PDDB; its entries come before the
+//! built-in ones. On top of both, the lexicon remembers which candidate the user picked for a
+//! reading, and offers that first the next time around.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+
+/// Number of candidates remembered for each reading
+const MAX_LEARNED_PER_READING: usize = 4;
+/// Number of readings remembered; the least recently used is forgotten first
+const MAX_LEARNED_READINGS: usize = 1024;
+
+/// What `learn()` changed, so that `forget()` can put it back
+#[derive(Debug)]
+pub struct Learned {
+    reading: String,
+    /// the candidates learned for the reading before
+    previous: Option<Vec<String>>,
+    /// where the reading was in `recency` before
+    position: Option<usize>,
+    /// the reading that was forgotten to make room, if any
+    evicted: Option<(String, Vec<String>)>,
+    longest: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Lexicon {
+    /// built-in and user dictionary entries
+    entries: BTreeMap<String, Vec<String>>,
+    /// candidates picked by the user, most recent first
+    learned: BTreeMap<String, Vec<String>>,
+    /// learned readings, most recently used first
+    recency: VecDeque<String>,
+    /// length in characters of the longest reading
+    longest: usize,
+}
+
+impl Lexicon {
+    /// Unpacks a built-in dictionary.
+    pub fn from_compressed(data: &[u8]) -> Self {
+        let text = miniz_oxide::inflate::decompress_to_vec(data).expect("built-in dictionary is corrupt");
+        let mut lexicon = Lexicon::default();
+        let rejected = lexicon.extend(core::str::from_utf8(&text).expect("built-in dictionary is not UTF-8"));
+        assert!(rejected == 0, "built-in dictionary is malformed");
+        lexicon
+    }
+    /// Adds the entries of a dictionary, whose candidates go ahead of the ones already known.
+    /// Returns the number of lines that could not be parsed.
+    pub fn extend(&mut self, text: &str) -> usize {
+        let mut rejected = 0;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let reading = fields.next().unwrap();
+            let candidates: Vec<String> = fields.map(String::from).collect();
+            if candidates.is_empty() {
+                rejected += 1;
+                continue;
+            }
+            let entry = self.entries.entry(String::from(reading)).or_insert_with(Vec::new);
+            entry.retain(|c| !candidates.contains(c));
+            entry.splice(0..0, candidates);
+            self.longest = self.longest.max(reading.chars().count());
+        }
+        rejected
+    }
+
+    pub fn contains(&self, reading: &str) -> bool {
+        self.entries.contains_key(reading) || self.learned.contains_key(reading)
+    }
+    /// The length in characters of the longest reading the lexicon knows.
+    pub fn longest(&self) -> usize {
+        self.longest
+    }
+    /// The candidates for `reading`, best first.
+    pub fn candidates(&self, reading: &str) -> Vec<&str> {
+        let mut found: Vec<&str> = Vec::new();
+        for list in [self.learned.get(reading), self.entries.get(reading)].iter().flatten() {
+            for c in list.iter() {
+                if !found.contains(&c.as_str()) {
+                    found.push(c.as_str());
+                }
+            }
+        }
+        found
+    }
+    pub fn top(&self, reading: &str) -> Option<&str> {
+        self.learned.get(reading).or_else(|| self.entries.get(reading))
+            .and_then(|list| list.first()).map(|c| c.as_str())
+    }
+
+    /// Remembers that the user converted `reading` into `candidate`.
+    pub fn learn(&mut self, reading: &str, candidate: &str) -> Learned {
+        let previous = self.learned.get(reading).cloned();
+        let position = self.recency.iter().position(|r| r == reading);
+        let longest = self.longest;
+        let list = self.learned.entry(String::from(reading)).or_insert_with(Vec::new);
+        list.retain(|c| c != candidate);
+        list.insert(0, String::from(candidate));
+        list.truncate(MAX_LEARNED_PER_READING);
+        if let Some(p) = position {
+            self.recency.remove(p);
+        }
+        self.recency.push_front(String::from(reading));
+        let mut evicted = None;
+        if let Some(oldest) = if self.recency.len() > MAX_LEARNED_READINGS { self.recency.pop_back() } else { None } {
+            let list = self.learned.remove(&oldest).unwrap_or_default();
+            evicted = Some((oldest, list));
+        }
+        self.longest = self.longest.max(reading.chars().count());
+        Learned { reading: String::from(reading), previous, position, evicted, longest }
+    }
+    /// Takes back the last thing learned, which `learned` describes.
+    pub fn forget(&mut self, learned: Learned) {
+        let Learned { reading, previous, position, evicted, longest } = learned;
+        match previous {
+            Some(list) => {
+                self.learned.insert(reading.clone(), list);
+            }
+            None => {
+                self.learned.remove(&reading);
+            }
+        }
+        self.recency.retain(|r| *r != reading);
+        if let Some((oldest, list)) = evicted {
+            self.recency.push_back(oldest.clone());
+            self.learned.insert(oldest, list);
+        }
+        if let Some(p) = position {
+            self.recency.insert(p, reading);
+        }
+        self.longest = longest;
+    }
+    /// The learned candidates, in the dictionary format, most recently used reading first.
+    pub fn learned_to_text(&self) -> String {
+        let mut text = String::new();
+        for reading in self.recency.iter() {
+            if let Some(list) = self.learned.get(reading) {
+                write!(text, "{}", reading).unwrap();
+                for c in list.iter() {
+                    write!(text, " {}", c).unwrap();
+                }
+                writeln!(text).unwrap();
+            }
+        }
+        text
+    }
+    /// Restores candidates saved with `learned_to_text()`. Anything learned since takes precedence.
+    /// Returns the number of lines that could not be parsed.
+    pub fn merge_learned(&mut self, text: &str) -> usize {
+        let newer = core::mem::take(&mut self.learned);
+        let newer_order = core::mem::take(&mut self.recency);
+        let mut rejected = 0;
+        for line in text.lines().rev() {
+            let mut fields = line.split_whitespace();
+            let reading = match fields.next() {
+                Some(r) => r,
+                None => continue,
+            };
+            let saved: Vec<&str> = fields.collect();
+            if saved.is_empty() {
+                rejected += 1;
+                continue;
+            }
+            for c in saved.iter().rev() {
+                self.learn(reading, c);
+            }
+        }
+        for reading in newer_order.iter().rev() {
+            for c in newer[reading].iter().rev() {
+                self.learn(reading, c);
+            }
+        }
+        rejected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_and_learning() {
+        let mut lex = Lexicon::default();
+        assert_eq!(lex.extend("# comment\nshi 是 时 事\nshijian 时间 事件\n\nbad\n"), 1);
+        assert_eq!(lex.longest(), 7);
+        assert_eq!(lex.candidates("shi"), ["是", "时", "事"]);
+        // user dictionary entries go first
+        lex.extend("shi 事 十");
+        assert_eq!(lex.candidates("shi"), ["事", "十", "是", "时"]);
+        // then what the user picked, most recent first
+        lex.learn("shi", "时");
+        lex.learn("shijian", "事件");
+        assert_eq!(lex.candidates("shi"), ["时", "事", "十", "是"]);
+        assert_eq!(lex.top("shijian"), Some("事件"));
+        lex.learn("wo", "我");
+        assert!(lex.contains("wo"));
+        assert_eq!(lex.candidates("wo"), ["我"]);
+
+        let saved = lex.learned_to_text();
+        assert_eq!(saved, "wo 我\nshijian 事件\nshi 时\n");
+        let mut restored = Lexicon::default();
+        restored.learn("shi", "是");
+        assert_eq!(restored.merge_learned(&saved), 0);
+        assert_eq!(restored.candidates("shi"), ["是", "时"]);
+        assert_eq!(restored.learned_to_text(), "shi 是 时\nwo 我\nshijian 事件\n");
+    }
+
+    #[test]
+    fn learned_limits() {
+        let mut lex = Lexicon::default();
+        for c in ["一", "二", "三", "四", "五"].iter() {
+            lex.learn("a", c);
+        }
+        assert_eq!(lex.candidates("a"), ["五", "四", "三", "二"]);
+        for i in 0..MAX_LEARNED_READINGS {
+            lex.learn(&format!("r{}", i), "x");
+        }
+        // "a" was the least recently used
+        assert!(!lex.contains("a"));
+        assert!(lex.contains("r0"));
+    }
+
+    #[test]
+    fn forgetting() {
+        let mut lex = Lexicon::default();
+        lex.extend("shi 是 时 事
+");
+        lex.learn("shi", "时");
+        lex.learn("wo", "我");
+        let before = lex.learned_to_text();
+        let learned = lex.learn("shi", "事");
+        assert_eq!(lex.candidates("shi"), ["事", "时", "是"]);
+        lex.forget(learned);
+        assert_eq!(lex.candidates("shi"), ["时", "是", "事"]);
+        assert_eq!(lex.learned_to_text(), before);
+        // a reading learned for the first time goes again
+        let learned = lex.learn("zhongwen", "中文");
+        lex.forget(learned);
+        assert!(!lex.contains("zhongwen"));
+        assert_eq!(lex.longest(), 3);
+        assert_eq!(lex.learned_to_text(), before);
+
+        // and one that was forgotten to make room comes back
+        let mut lex = Lexicon::default();
+        for i in 0..MAX_LEARNED_READINGS {
+            lex.learn(&format!("r{}", i), "x");
+        }
+        let before = lex.learned_to_text();
+        let learned = lex.learn("new", "y");
+        assert!(!lex.contains("r0"));
+        lex.forget(learned);
+        assert!(lex.contains("r0"));
+        assert_eq!(lex.learned_to_text(), before);
+    }
+}
@@ -0,0 +1,9 @@
+#![cfg_attr(target_os = "none", no_std)]
+
+/// Pinyin to hanzi conversion
+pub const SERVER_NAME_IME_PLUGIN_PINYIN: &str = "_IME pinyin plugin_";
+/// Romaji to kana and kanji conversion
+pub const SERVER_NAME_IME_PLUGIN_KANA: &str = "_IME kana plugin_";
+
+// just inherit all the default from the ime_plugin_api
+pub use ime_plugin_api::*;
@@ -0,0 +1,184 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+mod engine;
+mod kana;
+mod lexicon;
+mod pinyin;
+use engine::{Engine, Scheme};
+use lexicon::Lexicon;
+
+use ime_plugin_api::*;
+
+use log::{error, info};
+
+use xous_ipc::{String, Buffer};
+use num_traits::FromPrimitive;
+
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// PDDB key holding a user dictionary, in the same format as the built-in ones
+const USER_DICT_KEY: &str = "dict";
+/// PDDB key holding the candidates the user picked
+const LEARNED_KEY: &str = "learned";
+/// How often the learned candidates are saved, if they have changed
+const SAVE_INTERVAL_MS: usize = 60_000;
+
+static PINYIN_DICT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/pinyin.dict.z"));
+static KANA_DICT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/kana.dict.z"));
+
+#[xous::xous_main]
+fn xmain() -> ! {
+    log_server::init_wait().unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+    info!("my PID is {}", xous::process::id());
+
+    // both schemes live in one process, so they share the code, but each is a server of its own
+    let pinyin = thread::spawn(move || {
+        conversion_server(ime_plugin_cjk::SERVER_NAME_IME_PLUGIN_PINYIN, Scheme::Pinyin, PINYIN_DICT, "ime.pinyin");
+    });
+    let kana = thread::spawn(move || {
+        conversion_server(ime_plugin_cjk::SERVER_NAME_IME_PLUGIN_KANA, Scheme::Kana, KANA_DICT, "ime.kana");
+    });
+    pinyin.join().unwrap();
+    kana.join().unwrap();
+
+    log::trace!("quitting");
+    xous::terminate_process(0)
+}
+
+fn conversion_server(name: &str, scheme: Scheme, compressed: &'static [u8], pddb_dict: &'static str) {
+    let xns = xous_names::XousNames::new().unwrap();
+    // one connection only, should be the IME front end
+    let sid = xns.register_name(name, Some(1)).expect("can't register server");
+    log::trace!("registered {} with NS -- {:?}", name, sid);
+
+    let engine = Arc::new(Mutex::new(Engine::new(scheme, Lexicon::from_compressed(compressed))));
+    // the PDDB mounts long after we start, so the user's entries are loaded in the background
+    thread::spawn({
+        let engine = Arc::clone(&engine);
+        move || {
+            storage_thread(engine, pddb_dict);
+        }
+    });
+
+    // conversion runs over the whole line, so that picked text and the romaji after it can be
+    // told apart; only punctuation starts over
+    let mytriggers = PredictionTriggers {
+        newline: false,
+        punctuation: true,
+        whitespace: false,
+    };
+
+    info!("{} ready to accept requests", name);
+    loop {
+        let mut msg = xous::receive_message(sid).unwrap();
+        log::trace!("received message {:?}", msg);
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(Opcode::Input) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let s = buffer.as_flat::<String::<4000>, _>().unwrap();
+                engine.lock().unwrap().set_input(s.as_str());
+            }
+            Some(Opcode::Picked) => {
+                engine.lock().unwrap().picked();
+            }
+            Some(Opcode::Prediction) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut prediction: Prediction = buffer.to_original::<Prediction, _>().unwrap();
+                log::trace!("querying prediction index {}", prediction.index);
+                prediction.string.clear();
+                prediction.valid = false;
+                if let Some(text) = engine.lock().unwrap().prediction(prediction.index as usize) {
+                    for ch in text.chars() {
+                        if prediction.string.push(ch).is_err() {
+                            break;
+                        }
+                    }
+                    prediction.valid = true;
+                }
+                log::trace!("returning index {} string {:?}", prediction.index, prediction.string);
+                buffer.replace(Return::Prediction(prediction)).expect("couldn't return Prediction");
+            }
+            Some(Opcode::Unpick) => {
+                engine.lock().unwrap().unpick();
+            }
+            Some(Opcode::GetPredictionTriggers) => {
+                xous::return_scalar(msg.sender, mytriggers.into()).expect("couldn't return GetPredictionTriggers");
+            }
+            Some(Opcode::Quit) => {
+                error!("received quit, goodbye!"); break;
+            }
+            None => {error!("unknown Opcode");}
+        }
+    }
+    log::trace!("{} main loop exit, destroying server", name);
+    xns.unregister_server(sid).unwrap();
+    xous::destroy_server(sid).unwrap();
+}
+
+fn read_key(pddb: &pddb::Pddb, dict: &str, key: &str) -> Option<std::string::String> {
+    match pddb.get(dict, key, None, false, false, None, None::<fn()>) {
+        Ok(mut k) => {
+            let mut text = std::string::String::new();
+            match k.read_to_string(&mut text) {
+                Ok(_) => Some(text),
+                Err(e) => {
+                    log::error!("couldn't read {}:{}: {:?}", dict, key, e);
+                    None
+                }
+            }
+        }
+        Err(_) => None,
+    }
+}
+
+/// Waits for the PDDB to mount, loads the user dictionary and the saved picks, and from then on
+/// saves the picks periodically whenever they change.
+fn storage_thread(engine: Arc<Mutex<Engine>>, dict: &str) {
+    let tt = ticktimer_server::Ticktimer::new().unwrap();
+    let mut pddb = pddb::Pddb::new();
+    pddb.is_mounted_blocking(None);
+    if let Some(text) = read_key(&pddb, dict, USER_DICT_KEY) {
+        let rejected = engine.lock().unwrap().lexicon_mut().extend(&text);
+        if rejected > 0 {
+            log::warn!("ignored {} malformed lines in {}:{}", rejected, dict, USER_DICT_KEY);
+        }
+    }
+    if let Some(text) = read_key(&pddb, dict, LEARNED_KEY) {
+        let rejected = engine.lock().unwrap().lexicon_mut().merge_learned(&text);
+        if rejected > 0 {
+            log::warn!("ignored {} malformed lines in {}:{}", rejected, dict, LEARNED_KEY);
+        }
+    }
+
+    loop {
+        tt.sleep_ms(SAVE_INTERVAL_MS).unwrap();
+        let text = {
+            let mut e = engine.lock().unwrap();
+            if !e.take_dirty() {
+                continue;
+            }
+            e.learned_to_text()
+        };
+        // the picks only ever get rewritten whole, so start from an empty key
+        pddb.delete_key(dict, LEARNED_KEY, None).ok();
+        let result = match pddb.get(dict, LEARNED_KEY, None, true, true, Some(text.len()), None::<fn()>) {
+            Ok(mut key) => key.write_all(text.as_bytes()),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(_) => {
+                pddb.sync().ok();
+                log::debug!("saved {}:{}, {} bytes", dict, LEARNED_KEY, text.len());
+            }
+            Err(e) => {
+                // e.g. the basis holding the picks was locked; try again next time around
+                log::warn!("couldn't save {}:{}: {:?}", dict, LEARNED_KEY, e);
+                engine.lock().unwrap().set_dirty();
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Pinyin to hanzi conversion.
+//!
+//! Only the run of latin letters at the end of the input is converted; anything in front of it
+//! (hanzi picked earlier, punctuation) is kept as it is. The letters are split into readings the
+//! lexicon knows, preferring as few readings as possible, so that `nihao` becomes 你好 rather
+//! than 你 followed by 好. Letters at the end that don't make up a reading yet, typically a
+//! syllable still being typed, stay latin.
+
+use crate::engine::Candidate;
+use crate::lexicon::Lexicon;
+
+/// Number of candidates offered for an input
+pub const MAX_CANDIDATES: usize = 8;
+
+/// Splits `input` into the text to keep and the trailing latin letters to convert.
+fn split_tail(input: &str) -> (&str, &str) {
+    let start = input.char_indices().rev()
+        .take_while(|(_, c)| c.is_ascii_lowercase())
+        .last()
+        .map_or(input.len(), |(i, _)| i);
+    input.split_at(start)
+}
+
+/// Splits the longest convertible start of `tail` into as few readings as possible. Returns
+/// the byte offsets where each reading ends; empty if no start of `tail` is a known reading.
+fn segment(lex: &Lexicon, tail: &str) -> Vec<usize> {
+    let n = tail.len();
+    // best[i]: fewest readings covering tail[..i], and where the last one starts
+    let mut best: Vec<Option<(usize, usize)>> = vec![None; n + 1];
+    best[0] = Some((0, 0));
+    for end in 1..=n {
+        for start in end.saturating_sub(lex.longest())..end {
+            if let Some((count, _)) = best[start] {
+                if lex.contains(&tail[start..end]) {
+                    // on a tie, the later start wins, so the earlier readings are the longer ones
+                    if best[end].map_or(true, |(c, _)| count + 1 <= c) {
+                        best[end] = Some((count + 1, start));
+                    }
+                }
+            }
+        }
+    }
+    let mut end = match (1..=n).rev().find(|&i| best[i].is_some()) {
+        Some(end) => end,
+        None => return Vec::new(),
+    };
+    let mut ends = Vec::new();
+    while end > 0 {
+        ends.push(end);
+        end = best[end].unwrap().1;
+    }
+    ends.reverse();
+    ends
+}
+
+/// The conversions offered for `input`, best first.
+pub fn candidates(lex: &Lexicon, input: &str) -> Vec<Candidate> {
+    let (keep, tail) = split_tail(input);
+    let mut found: Vec<Candidate> = Vec::new();
+    if tail.is_empty() {
+        return found;
+    }
+    let push = |found: &mut Vec<Candidate>, converted: String, reading: &str, rest: &str| {
+        let text = format!("{}{}{}", keep, converted, rest);
+        if text != input && !found.iter().any(|c| c.text == text) {
+            found.push(Candidate { text, reading: String::from(reading), converted });
+        }
+    };
+
+    // the whole run as a sentence of the best reading of each part
+    let ends = segment(lex, tail);
+    if ends.len() > 1 {
+        let mut converted = String::new();
+        let mut start = 0;
+        for &end in ends.iter() {
+            converted.push_str(lex.top(&tail[start..end]).unwrap());
+            start = end;
+        }
+        let reading = &tail[..start];
+        push(&mut found, converted, reading, &tail[start..]);
+    }
+    // then every reading of the run, longest first
+    for len in (1..=tail.len().min(lex.longest())).rev() {
+        let reading = &tail[..len];
+        for c in lex.candidates(reading) {
+            push(&mut found, String::from(c), reading, &tail[len..]);
+        }
+        if found.len() >= MAX_CANDIDATES {
+            break;
+        }
+    }
+    found.truncate(MAX_CANDIDATES);
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lexicon() -> Lexicon {
+        let mut lex = Lexicon::default();
+        lex.extend("ni 你 泥\nhao 好 号\nnihao 你好\nshi 是 时 事\nshijian 时间 事件\njian 见 间\nmen 们 门\nwo 我\nwomen 我们\n");
+        lex
+    }
+    fn texts(found: Vec<Candidate>) -> Vec<String> {
+        found.into_iter().map(|c| c.text).collect()
+    }
+
+    #[test]
+    fn whole_readings_first() {
+        let lex = lexicon();
+        assert_eq!(texts(candidates(&lex, "nihao")), ["你好", "你hao", "泥hao"]);
+        assert_eq!(texts(candidates(&lex, "shijian")), ["时间", "事件", "是jian", "时jian", "事jian"]);
+        assert!(candidates(&lex, "").is_empty());
+        assert!(candidates(&lex, "你好").is_empty());
+    }
+
+    #[test]
+    fn sentences_and_partial_input() {
+        let lex = lexicon();
+        // a syllable still being typed stays latin
+        assert_eq!(texts(candidates(&lex, "nihaosh"))[0], "你好sh");
+        // fewest readings wins: 我们 rather than 我 + 们
+        let found = candidates(&lex, "womenshi");
+        assert_eq!(found[0].text, "我们是");
+        assert_eq!(found[0].reading, "womenshi");
+        assert_eq!(found[1].text, "我们shi");
+        // what was converted before is kept
+        assert_eq!(texts(candidates(&lex, "你好shi"))[..3], ["你好是", "你好时", "你好事"]);
+        assert_eq!(texts(candidates(&lex, "xyz")), Vec::<String>::new());
+    }
+}
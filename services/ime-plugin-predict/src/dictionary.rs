@@ -0,0 +1,437 @@
+//! The word-frequency dictionary behind the predictor.
+//!
+//! Every word the user finishes typing is counted, along with the word that came before it.
+//! While a word is being typed, the predictor offers completions of it, ranked by how often each
+//! candidate was used, and more so if it was used after the previous word. Once a word is
+//! finished, the words that most often followed it are offered instead. Words from the locale's
+//! seed list fill in when the user's own dictionary has nothing more to offer.
+//!
+//! The dictionary is saved to the PDDB as text, one entry per line:
+//!
+//! ```text
+//! w <count> <word>
+//! b <count> <previous word> <word>
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Number of candidates kept for each prediction request
+pub const MAX_CANDIDATES: usize = 8;
+/// Words longer than this are not learned
+const MAX_WORD_CHARS: usize = 32;
+/// When the dictionary outgrows these limits, all counts are halved, and entries that drop to zero are forgotten
+const MAX_WORDS: usize = 2048;
+const MAX_BIGRAMS: usize = 4096;
+/// Following the previous word is worth this many uses of a word on its own
+const BIGRAM_WEIGHT: u32 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    /// the word as it is offered, e.g. keeping the capitals of a proper noun
+    word: String,
+    count: u32,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Dictionary {
+    /// keyed by the lower-case form of the word
+    words: BTreeMap<String, Entry>,
+    /// (previous, next) -> count, keyed by lower-case forms
+    bigrams: BTreeMap<(String, String), u32>,
+}
+
+impl Dictionary {
+    /// Count a use of `word`, after the word with key `prev`.
+    pub fn add(&mut self, word: &str, prev: Option<&str>) {
+        let key = word.to_lowercase();
+        let entry = self.words.entry(key.clone()).or_insert_with(|| Entry { word: String::from(word), count: 0 });
+        entry.count = entry.count.saturating_add(1);
+        if word == key {
+            // a capitalised form was probably just the start of a sentence
+            entry.word = key.clone();
+        }
+        if let Some(prev) = prev {
+            let count = self.bigrams.entry((String::from(prev), key)).or_insert(0);
+            *count = count.saturating_add(1);
+        }
+        if self.words.len() > MAX_WORDS || self.bigrams.len() > MAX_BIGRAMS {
+            self.age();
+        }
+    }
+    /// Take back a use of `word` that was counted with `add()`.
+    pub fn remove(&mut self, word: &str, prev: Option<&str>) {
+        let key = word.to_lowercase();
+        if let Some(entry) = self.words.get_mut(&key) {
+            entry.count -= 1;
+            if entry.count == 0 {
+                self.words.remove(&key);
+            }
+        }
+        if let Some(prev) = prev {
+            let bigram = (String::from(prev), key);
+            if let Some(count) = self.bigrams.get_mut(&bigram) {
+                *count -= 1;
+                if *count == 0 {
+                    self.bigrams.remove(&bigram);
+                }
+            }
+        }
+    }
+    pub fn contains(&self, key: &str) -> bool {
+        self.words.contains_key(key)
+    }
+    fn follows(&self, prev: Option<&str>, key: &str) -> u32 {
+        prev.and_then(|p| self.bigrams.get(&(String::from(p), String::from(key)))).copied().unwrap_or(0)
+    }
+    fn display<'a>(&'a self, key: &'a str) -> &'a str {
+        self.words.get(key).map_or(key, |e| e.word.as_str())
+    }
+
+    /// Known words that start with `prefix` (in lower case), best first. The prefix itself is not a completion.
+    pub fn completions(&self, prefix: &str, prev: Option<&str>) -> Vec<&str> {
+        let mut found: Vec<(u32, &str, &str)> = self.words.range(String::from(prefix)..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter(|(key, _)| key.as_str() != prefix)
+            .map(|(key, e)| (e.count.saturating_add(BIGRAM_WEIGHT.saturating_mul(self.follows(prev, key))), key.as_str(), e.word.as_str()))
+            .collect();
+        found.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+        found.into_iter().take(MAX_CANDIDATES).map(|(_, _, word)| word).collect()
+    }
+    /// The words that most often followed `prev`, best first.
+    pub fn next_words(&self, prev: &str) -> Vec<&str> {
+        let start = (String::from(prev), String::new());
+        let mut found: Vec<(u32, &str)> = self.bigrams.range(start..)
+            .take_while(|((p, _), _)| p == prev)
+            .map(|((_, next), &count)| (count, next.as_str()))
+            .collect();
+        found.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+        found.into_iter().take(MAX_CANDIDATES).map(|(_, key)| self.display(key)).collect()
+    }
+
+    /// Halve every count, forgetting the entries that were only used once, until the dictionary fits again.
+    fn age(&mut self) {
+        while self.words.len() > MAX_WORDS || self.bigrams.len() > MAX_BIGRAMS {
+            self.words.retain(|_, e| { e.count /= 2; e.count > 0 });
+            self.bigrams.retain(|_, count| { *count /= 2; *count > 0 });
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for e in self.words.values() {
+            writeln!(text, "w {} {}", e.count, e.word).unwrap();
+        }
+        for ((prev, next), count) in self.bigrams.iter() {
+            writeln!(text, "b {} {} {}", count, prev, next).unwrap();
+        }
+        text
+    }
+    /// Adds the counts in `text` to this dictionary. Returns the number of lines that could not be parsed.
+    pub fn merge_text(&mut self, text: &str) -> usize {
+        let mut rejected = 0;
+        for line in text.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if self.merge_line(line).is_none() {
+                rejected += 1;
+            }
+        }
+        if self.words.len() > MAX_WORDS || self.bigrams.len() > MAX_BIGRAMS {
+            self.age();
+        }
+        rejected
+    }
+    fn merge_line(&mut self, line: &str) -> Option<()> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let count = fields.get(1)?.parse::<u32>().ok().filter(|&c| c > 0)?;
+        match fields[..] {
+            ["w", _, word] => {
+                let e = self.words.entry(word.to_lowercase()).or_insert_with(|| Entry { word: String::from(word), count: 0 });
+                e.count = e.count.saturating_add(count);
+            }
+            ["b", _, prev, next] => {
+                let c = self.bigrams.entry((prev.to_lowercase(), next.to_lowercase())).or_insert(0);
+                *c = c.saturating_add(count);
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+/// The words counted by the last pick, so it can be taken back
+#[derive(Debug)]
+struct Pick {
+    /// each word, with the key of the word before it
+    words: Vec<(String, Option<String>)>,
+    /// the context before the pick
+    context: Option<String>,
+}
+
+/// Tracks the word being typed and the one before it, and turns them into predictions.
+#[derive(Debug)]
+pub struct Predictor {
+    dict: Dictionary,
+    /// seed words in lower case, most common first
+    seed: Vec<&'static str>,
+    /// the partial word being typed
+    input: String,
+    /// key of the previous word, if it is still relevant
+    context: Option<String>,
+    last_pick: Option<Pick>,
+    candidates: Vec<String>,
+    /// the dictionary has changed since it was last saved
+    dirty: bool,
+}
+
+impl Predictor {
+    pub fn new(seed: Option<&'static str>) -> Self {
+        let seed = seed.map_or(Vec::new(), |text|
+            text.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#')).collect()
+        );
+        Predictor {
+            dict: Dictionary::default(),
+            seed,
+            input: String::new(),
+            context: None,
+            last_pick: None,
+            candidates: Vec::new(),
+            dirty: false,
+        }
+    }
+
+    pub fn set_input(&mut self, s: &str) {
+        self.input.clear();
+        self.input.push_str(s.trim());
+        self.refresh();
+    }
+    /// Learns the words of `s`. An empty pick ends the sentence, so the next word has no context.
+    pub fn picked(&mut self, s: &str) {
+        let mut pick = Pick { words: Vec::new(), context: self.context.clone() };
+        if s.trim().is_empty() {
+            self.context = None;
+        }
+        for token in s.split(|c: char| !(c.is_alphanumeric() || c == '\'')) {
+            let token = token.trim_matches('\'');
+            if token.is_empty() {
+                continue;
+            }
+            match self.normalize(token) {
+                Some(word) => {
+                    let key = word.to_lowercase();
+                    // completing a single letter saves nothing, so those only serve as context
+                    if word.chars().count() > 1 {
+                        self.dict.add(&word, self.context.as_deref());
+                        pick.words.push((word, self.context.clone()));
+                        self.dirty = true;
+                    }
+                    self.context = Some(key);
+                }
+                None => self.context = None,
+            }
+        }
+        self.last_pick = Some(pick);
+        self.input.clear();
+        self.refresh();
+    }
+    /// Takes back the last pick. Repeated calls do nothing.
+    pub fn unpick(&mut self) {
+        if let Some(pick) = self.last_pick.take() {
+            for (word, prev) in pick.words.iter() {
+                self.dict.remove(word, prev.as_deref());
+                self.dirty = true;
+            }
+            self.context = pick.context;
+        }
+        self.input.clear();
+        self.refresh();
+    }
+    pub fn prediction(&self, index: usize) -> Option<&str> {
+        self.candidates.get(index).map(|s| s.as_str())
+    }
+
+    /// The form in which a typed word is learned, or `None` if it isn't a word.
+    fn normalize(&self, token: &str) -> Option<String> {
+        if token.chars().count() > MAX_WORD_CHARS || !token.chars().any(|c| c.is_alphabetic()) {
+            return None;
+        }
+        let lower = token.to_lowercase();
+        let mut rest = token.chars().skip(1);
+        if rest.all(|c| !c.is_uppercase()) && (self.dict.contains(&lower) || self.seed.contains(&lower.as_str())) {
+            // a known word, capitalised because it started a sentence
+            Some(lower)
+        } else {
+            Some(String::from(token))
+        }
+    }
+
+    fn refresh(&mut self) {
+        self.candidates.clear();
+        let prefix = self.input.to_lowercase();
+        if prefix.is_empty() {
+            if let Some(prev) = self.context.as_deref() {
+                self.candidates.extend(self.dict.next_words(prev).into_iter().map(String::from));
+            }
+            return;
+        }
+        let mut found: Vec<String> = self.dict.completions(&prefix, self.context.as_deref())
+            .into_iter().map(String::from).collect();
+        for &word in self.seed.iter() {
+            if found.len() >= MAX_CANDIDATES {
+                break;
+            }
+            if word.starts_with(prefix.as_str()) && word != prefix && !found.iter().any(|f| f.to_lowercase() == word) {
+                found.push(String::from(word));
+            }
+        }
+        // follow the capitalisation of what has been typed so far
+        let first_upper = self.input.chars().next().map_or(false, |c| c.is_uppercase());
+        let all_upper = self.input.chars().count() > 1 && self.input.chars().all(|c| !c.is_lowercase());
+        for word in found.into_iter() {
+            self.candidates.push(if all_upper {
+                word.to_uppercase()
+            } else if first_upper {
+                let mut chars = word.chars();
+                chars.next().map_or(String::new(), |c| c.to_uppercase().chain(chars).collect())
+            } else {
+                word
+            });
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        self.dict.to_text()
+    }
+    /// Merges a saved dictionary into the one in memory, which may already have learned some words.
+    pub fn merge_text(&mut self, text: &str) -> usize {
+        let rejected = self.dict.merge_text(text);
+        self.refresh();
+        rejected
+    }
+    /// Returns true if the dictionary needs saving, and assumes that it will be.
+    pub fn take_dirty(&mut self) -> bool {
+        core::mem::replace(&mut self.dirty, false)
+    }
+    pub fn set_dirty(&mut self) {
+        self.dirty = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: &str = "# test seed\nthe\nof\nthere\nthey\ni\n";
+
+    fn predictions(p: &Predictor) -> Vec<&str> {
+        (0..MAX_CANDIDATES).filter_map(|i| p.prediction(i)).collect()
+    }
+    fn type_words(p: &mut Predictor, text: &str) {
+        for word in text.split(' ') {
+            p.picked(word);
+        }
+    }
+
+    #[test]
+    fn completions() {
+        let mut p = Predictor::new(Some(SEED));
+        p.set_input("th");
+        // nothing learned yet: the seed words, in order
+        assert_eq!(predictions(&p), ["the", "there", "they"]);
+        type_words(&mut p, "they think they thought they");
+        p.picked("");
+        p.set_input("th");
+        assert_eq!(predictions(&p), ["they", "think", "thought", "the", "there"]);
+        // capitalisation follows the input
+        p.set_input("Th");
+        assert_eq!(predictions(&p)[0], "They");
+        p.set_input("THO");
+        assert_eq!(predictions(&p), ["THOUGHT"]);
+        // the input itself is not offered
+        p.set_input("they");
+        assert!(predictions(&p).is_empty());
+    }
+
+    #[test]
+    fn next_words() {
+        let mut p = Predictor::new(None);
+        type_words(&mut p, "good morning good night good morning");
+        p.picked("");
+        assert!(predictions(&p).is_empty());
+        p.picked("Good");
+        assert_eq!(predictions(&p), ["morning", "night"]);
+        // context ranks completions too
+        type_words(&mut p, "nice night nice night");
+        p.picked("");
+        p.picked("good");
+        p.set_input("n");
+        assert_eq!(predictions(&p), ["night", "nice"]);
+        // punctuation and numbers break the context
+        p.picked("good, 42");
+        assert!(predictions(&p).is_empty());
+    }
+
+    #[test]
+    fn unpick() {
+        let mut p = Predictor::new(None);
+        type_words(&mut p, "hello world");
+        let before = p.to_text();
+        p.picked("again");
+        p.unpick();
+        assert_eq!(p.to_text(), before);
+        // the context is restored, and "again" no longer follows "world"
+        assert!(predictions(&p).is_empty());
+        p.picked("");
+        p.picked("hello");
+        assert_eq!(predictions(&p), ["world"]);
+        p.unpick();
+        p.unpick();
+        assert!(predictions(&p).is_empty());
+    }
+
+    #[test]
+    fn capitals() {
+        let mut p = Predictor::new(Some(SEED));
+        // known words are learned in lower case at the start of a sentence; names keep their capitals
+        type_words(&mut p, "They met Xous");
+        assert!(p.to_text().contains("w 1 they\n"));
+        assert!(p.to_text().contains("w 1 Xous\n"));
+        p.set_input("x");
+        assert_eq!(predictions(&p), ["Xous"]);
+        // single letters are only context, and apostrophes within a word are kept
+        type_words(&mut p, "i don't");
+        assert!(!p.to_text().contains("w 1 i\n"));
+        assert!(p.to_text().contains("b 1 i don't\n"));
+    }
+
+    #[test]
+    fn persistence() {
+        let mut p = Predictor::new(None);
+        type_words(&mut p, "the quick brown fox jumps over the lazy dog");
+        assert!(p.take_dirty());
+        assert!(!p.take_dirty());
+        let text = p.to_text();
+        let mut restored = Predictor::new(None);
+        assert_eq!(restored.merge_text(&text), 0);
+        assert_eq!(restored.to_text(), text);
+        // merging adds to what was learned before the PDDB was mounted
+        restored.merge_text(&text);
+        assert!(restored.to_text().contains("w 4 the\n"));
+        assert_eq!(restored.merge_text("w 3\nx 1 y\nw 0 zero\nb 1 a\nw 1 two words\n\n"), 5);
+    }
+
+    #[test]
+    fn aging() {
+        let mut d = Dictionary::default();
+        for i in 0..MAX_WORDS {
+            d.add(&format!("w{}", i), None);
+        }
+        d.add("w0", None);
+        assert_eq!(d.words.len(), MAX_WORDS);
+        d.add("overflow", None);
+        // only the word that was used twice survives
+        assert_eq!(d.to_text(), "w 1 w0\n");
+    }
+}
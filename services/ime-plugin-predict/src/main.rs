@@ -0,0 +1,156 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+mod dictionary;
+use dictionary::Predictor;
+
+use ime_plugin_api::*;
+
+use log::{error, info};
+
+use xous_ipc::{String, Buffer};
+use num_traits::FromPrimitive;
+
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// PDDB dictionary and key that hold the learned words
+const PREDICT_DICT: &str = "ime.predict";
+const PREDICT_KEY: &str = "words";
+/// How often the dictionary is saved, if it has changed
+const SAVE_INTERVAL_MS: usize = 60_000;
+
+#[xous::xous_main]
+fn xmain() -> ! {
+    log_server::init_wait().unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+    info!("my PID is {}", xous::process::id());
+
+    let xns = xous_names::XousNames::new().unwrap();
+    // one connection only, should be the IME front end
+    let ime_pr_sid = xns.register_name(ime_plugin_predict::SERVER_NAME_IME_PLUGIN_PREDICT, Some(1)).expect("can't register server");
+    log::trace!("registered with NS -- {:?}", ime_pr_sid);
+
+    #[cfg(feature = "seed-words")]
+    let seed = locales::wordlists::seed_words(xous::LANG);
+    #[cfg(not(feature = "seed-words"))]
+    let seed = None;
+    let predictor = Arc::new(Mutex::new(Predictor::new(seed)));
+
+    // the PDDB mounts long after we start, so loading and saving the dictionary happens in the background
+    thread::spawn({
+        let predictor = Arc::clone(&predictor);
+        move || {
+            storage_thread(predictor);
+        }
+    });
+
+    let mytriggers = PredictionTriggers {
+        newline: false,
+        punctuation: true,
+        whitespace: true,
+    };
+
+    info!("ready to accept requests");
+    loop {
+        let mut msg = xous::receive_message(ime_pr_sid).unwrap();
+        log::trace!("received message {:?}", msg);
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(Opcode::Input) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let s = buffer.as_flat::<String::<4000>, _>().unwrap();
+                predictor.lock().unwrap().set_input(s.as_str());
+            }
+            Some(Opcode::Picked) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let s = buffer.as_flat::<String::<4000>, _>().unwrap();
+                log::trace!("learning | {}", s.as_str());
+                predictor.lock().unwrap().picked(s.as_str());
+            }
+            Some(Opcode::Prediction) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut prediction: Prediction = buffer.to_original::<Prediction, _>().unwrap();
+                log::trace!("querying prediction index {}", prediction.index);
+                prediction.string.clear();
+                prediction.valid = false;
+                if let Some(word) = predictor.lock().unwrap().prediction(prediction.index as usize) {
+                    for ch in word.chars() {
+                        if prediction.string.push(ch).is_err() {
+                            break;
+                        }
+                    }
+                    prediction.valid = true;
+                }
+                log::trace!("returning index {} string {:?}", prediction.index, prediction.string);
+                buffer.replace(Return::Prediction(prediction)).expect("couldn't return Prediction");
+            }
+            Some(Opcode::Unpick) => {
+                predictor.lock().unwrap().unpick();
+            }
+            Some(Opcode::GetPredictionTriggers) => {
+                xous::return_scalar(msg.sender, mytriggers.into()).expect("couldn't return GetPredictionTriggers");
+            }
+            Some(Opcode::Quit) => {
+                error!("received quit, goodbye!"); break;
+            }
+            None => {error!("unknown Opcode");}
+        }
+    }
+    log::trace!("main loop exit, destroying servers");
+    xns.unregister_server(ime_pr_sid).unwrap();
+    xous::destroy_server(ime_pr_sid).unwrap();
+    log::trace!("quitting");
+    xous::terminate_process(0)
+}
+
+/// Waits for the PDDB to mount, merges the saved dictionary into whatever has been learned so far,
+/// and from then on saves the dictionary periodically whenever it changes.
+fn storage_thread(predictor: Arc<Mutex<Predictor>>) {
+    let tt = ticktimer_server::Ticktimer::new().unwrap();
+    let mut pddb = pddb::Pddb::new();
+    pddb.is_mounted_blocking(None);
+    match pddb.get(PREDICT_DICT, PREDICT_KEY, None, false, false, None, None::<fn()>) {
+        Ok(mut key) => {
+            let mut text = std::string::String::new();
+            match key.read_to_string(&mut text) {
+                Ok(_) => {
+                    let rejected = predictor.lock().unwrap().merge_text(&text);
+                    if rejected > 0 {
+                        log::warn!("ignored {} malformed lines in the saved dictionary", rejected);
+                    }
+                }
+                Err(e) => log::error!("couldn't read the saved dictionary: {:?}", e),
+            }
+        }
+        Err(_) => log::info!("no saved dictionary, starting a new one"),
+    }
+
+    loop {
+        tt.sleep_ms(SAVE_INTERVAL_MS).unwrap();
+        let text = {
+            let mut p = predictor.lock().unwrap();
+            if !p.take_dirty() {
+                continue;
+            }
+            p.to_text()
+        };
+        // the dictionary only ever gets rewritten whole, so start from an empty key
+        pddb.delete_key(PREDICT_DICT, PREDICT_KEY, None).ok();
+        let result = match pddb.get(PREDICT_DICT, PREDICT_KEY, None, true, true, Some(text.len()), None::<fn()>) {
+            Ok(mut key) => key.write_all(text.as_bytes()),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(_) => {
+                pddb.sync().ok();
+                log::debug!("saved dictionary, {} bytes", text.len());
+            }
+            Err(e) => {
+                // e.g. the basis holding the dictionary was locked; try again next time around
+                log::warn!("couldn't save the dictionary: {:?}", e);
+                predictor.lock().unwrap().set_dirty();
+            }
+        }
+    }
+}
@@ -1,6 +1,9 @@
 pub const SERVER_NAME_KBD: &str      = "_Matrix keyboard driver_";
 
-#[derive(Debug, Default, Copy, Clone)]
+/// maximum length of the text of a custom keymap, after comments are stripped
+pub const MAX_KEYMAP_LEN: usize = 4000;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
 pub struct ScanCode {
     /// base key value
     pub key: Option<char>,
@@ -12,13 +15,15 @@ pub struct ScanCode {
     pub alt: Option<char>,
 }
 
-#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub enum KeyMap {
     Qwerty,
     Azerty,
     Qwertz,
     Dvorak,
     Braille,
+    /// a user-supplied map, see `keymap.rs` for the format
+    Custom,
     Undefined,
 }
 impl From<usize> for KeyMap {
@@ -29,6 +34,7 @@ impl From<usize> for KeyMap {
             2 => KeyMap::Qwertz,
             3 => KeyMap::Dvorak,
             4 => KeyMap::Braille,
+            5 => KeyMap::Custom,
             _ => KeyMap::Qwerty,
         }
     }
@@ -36,12 +42,13 @@ impl From<usize> for KeyMap {
 impl Into<usize> for KeyMap {
     fn into(self) -> usize {
         match self {
-            // note: these indicese correspond to the position on the keyboard menu
+            // note: these indicese correspond to the position on the keyboard menu, and are also the code stored in the early boot setting
             KeyMap::Qwerty => 0,
             KeyMap::Azerty => 1,
             KeyMap::Qwertz => 2,
             KeyMap::Dvorak => 3,
             KeyMap::Braille => 4,
+            KeyMap::Custom => 5,
             KeyMap::Undefined => 255,
         }
     }
@@ -52,6 +59,12 @@ pub(crate) enum Opcode {
     /// set which keyboard mapping is present
     SelectKeyMap, //(KeyMap),
     GetKeyMap,
+    /// load the text of a custom keymap; it becomes active when `KeyMap::Custom` is selected
+    LoadCustomKeyMap, //(xous_ipc::String::<MAX_KEYMAP_LEN>)
+    /// load the user's compose sequences, in the format described in `compose.rs`
+    LoadComposeTable, //(xous_ipc::String::<MAX_KEYMAP_LEN>)
+    /// returns 1 if a dead key or compose sequence is in progress
+    GetComposeState,
 
     /// request for ScanCodes
     RegisterListener,
@@ -87,7 +100,7 @@ pub(crate) struct KeyboardRegistration {
     pub listener_op_id: usize,
 }
 
-#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct RowCol {
     pub r: u8,
     pub c: u8,
@@ -0,0 +1,310 @@
+//! Dead keys and compose sequences.
+//!
+//! A dead key is a character that produces nothing by itself, but modifies the character typed
+//! after it: `´` then `e` gives `é`. The compose key (`COMPOSE_KEY`) starts a sequence of any
+//! length: compose, `o`, `/` gives `ø`. Both are looked up in a `ComposeTable`, which is
+//! written with the `dead` and `compose` directives of the keymap format:
+//!
+//! ```text
+//! dead <accent>                         <accent> is a dead key, combined using the compose sequences
+//! dead <accent> <base> <result>         as above, and adds the sequence <accent> <base> -> <result>
+//! compose <c1> <c2> ... <result>        compose, then <c1> <c2> ... yields <result>
+//! ```
+//!
+//! The table in use is built from the defaults in `keymaps/compose.txt`, the user's table, and
+//! then the sequences of the current keymap, with later entries overriding earlier ones.
+//!
+//! The built-in maps put the compose key on the hold layer of the full stop; in hosted mode it is `F7`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::keymap::{format_char, parse_char, KeyMapError};
+
+/// The character a keymap emits for the compose key (U+2384 COMPOSITION SYMBOL)
+pub const COMPOSE_KEY: char = '⎄';
+
+const DEFAULT_COMPOSE: &str = include_str!("keymaps/compose.txt");
+
+const BACKSPACE: char = '\u{0008}';
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ComposeTable {
+    /// characters that act as dead keys
+    dead: BTreeSet<char>,
+    /// the characters typed after the compose key, and what they produce
+    sequences: BTreeMap<Vec<char>, char>,
+}
+
+impl ComposeTable {
+    /// Parse a table that consists only of `dead` and `compose` directives.
+    pub fn parse(text: &str) -> Result<Self, KeyMapError> {
+        let mut table = ComposeTable::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let directive = fields.next().unwrap();
+            match directive {
+                "dead" | "compose" => table.parse_directive(directive, fields),
+                _ => Err("unknown directive"),
+            }.map_err(|reason| KeyMapError { line: index + 1, reason })?;
+        }
+        Ok(table)
+    }
+
+    /// The built-in sequences
+    pub fn default_sequences() -> Self {
+        ComposeTable::parse(DEFAULT_COMPOSE).expect("built-in compose table is malformed")
+    }
+
+    /// Parses the arguments of a `dead` or `compose` directive into the table.
+    pub(crate) fn parse_directive<'a>(&mut self, directive: &str, fields: impl Iterator<Item = &'a str>) -> Result<(), &'static str> {
+        let mut chars = Vec::new();
+        for field in fields {
+            if field.starts_with('#') && !chars.is_empty() {
+                break; // trailing comment
+            }
+            chars.push(parse_char(field).ok_or("invalid character")?.ok_or("sequences can't be empty")?);
+        }
+        if directive == "dead" {
+            match chars.len() {
+                1 => {
+                    self.dead.insert(chars[0]);
+                    return Ok(());
+                }
+                3 => {
+                    self.dead.insert(chars[0]);
+                }
+                _ => return Err("dead keys take an accent, or an accent, base and result"),
+            }
+        } else if chars.len() < 2 {
+            return Err("compose sequences need at least one character and a result");
+        }
+        let result = chars.pop().unwrap();
+        if self.sequences.insert(chars, result).is_some() {
+            return Err("sequence declared twice");
+        }
+        Ok(())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for &c in self.dead.iter() {
+            writeln!(text, "dead {}", format_char(Some(c))).unwrap();
+        }
+        for (sequence, &result) in self.sequences.iter() {
+            write!(text, "compose").unwrap();
+            for &c in sequence.iter() {
+                write!(text, " {}", format_char(Some(c))).unwrap();
+            }
+            writeln!(text, " {}", format_char(Some(result))).unwrap();
+        }
+        text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dead.is_empty() && self.sequences.is_empty()
+    }
+    pub fn is_dead(&self, c: char) -> bool {
+        self.dead.contains(&c)
+    }
+    pub fn lookup(&self, sequence: &[char]) -> Option<char> {
+        self.sequences.get(sequence).copied()
+    }
+    /// What dead key `accent` followed by `base` yields
+    pub fn combine(&self, accent: char, base: char) -> Option<char> {
+        self.lookup(&[accent, base])
+    }
+    /// Returns true if `sequence` is the start of some longer sequence in the table
+    pub fn is_prefix(&self, sequence: &[char]) -> bool {
+        self.sequences.range(sequence.to_vec()..)
+            .next()
+            .map_or(false, |(s, _)| s.len() > sequence.len() && s.starts_with(sequence))
+    }
+    /// Adds the entries of `other`, which take precedence over this table's.
+    pub fn extend(&mut self, other: &ComposeTable) {
+        self.dead.extend(other.dead.iter());
+        for (sequence, &result) in other.sequences.iter() {
+            self.sequences.insert(sequence.clone(), result);
+        }
+    }
+    /// The table that is actually used: the defaults, then the user's table, then the keymap's.
+    pub fn effective(user: Option<&ComposeTable>, map: Option<&ComposeTable>) -> Self {
+        let mut table = ComposeTable::default_sequences();
+        for t in [user, map].iter() {
+            if let Some(t) = t {
+                table.extend(t);
+            }
+        }
+        table
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Idle,
+    /// a dead key was typed, waiting for the character to apply it to
+    Dead(char),
+    /// the compose key was typed, collecting a sequence
+    Compose,
+}
+
+/// The dead key and compose state machine. It sits between key interpretation and the
+/// listener, so every source of characters (key matrix, chording, injected keys) composes
+/// the same way.
+#[derive(Debug)]
+pub struct Composer {
+    state: State,
+    pending: Vec<char>,
+}
+impl Default for Composer {
+    fn default() -> Self {
+        Composer { state: State::Idle, pending: Vec::new() }
+    }
+}
+
+impl Composer {
+    /// Drops any partial sequence, e.g. when the map or the listener changes.
+    pub fn reset(&mut self) {
+        self.state = State::Idle;
+        self.pending.clear();
+    }
+    pub fn is_composing(&self) -> bool {
+        self.state != State::Idle
+    }
+
+    /// Run a batch of typed characters through the state machine, returning the characters that
+    /// should be delivered. Partial sequences are carried over to the next call.
+    pub fn process(&mut self, table: &ComposeTable, keys: Vec<char>) -> Vec<char> {
+        if table.is_empty() && !self.is_composing() && !keys.contains(&COMPOSE_KEY) {
+            return keys;
+        }
+        let mut out = Vec::with_capacity(keys.len());
+        for c in keys {
+            self.feed(table, c, &mut out);
+        }
+        out
+    }
+
+    fn feed(&mut self, table: &ComposeTable, c: char, out: &mut Vec<char>) {
+        match self.state {
+            State::Idle => {
+                if c == COMPOSE_KEY {
+                    self.state = State::Compose;
+                    self.pending.clear();
+                } else if table.is_dead(c) {
+                    self.state = State::Dead(c);
+                } else {
+                    out.push(c);
+                }
+            }
+            State::Dead(accent) => {
+                self.state = State::Idle;
+                if let Some(result) = table.combine(accent, c) {
+                    out.push(result);
+                } else if c == ' ' || c == accent {
+                    // accent followed by space, or typed twice, produces the accent itself
+                    out.push(accent);
+                } else if c == BACKSPACE {
+                    // backspace just cancels the pending accent
+                } else {
+                    // no combination: emit the accent as typed, and interpret the key afresh
+                    out.push(accent);
+                    self.feed(table, c, out);
+                }
+            }
+            State::Compose => {
+                if c == COMPOSE_KEY {
+                    // compose twice cancels the sequence
+                    self.reset();
+                } else if c == BACKSPACE {
+                    if self.pending.pop().is_none() {
+                        self.state = State::Idle;
+                    }
+                } else if is_action_key(c) {
+                    // arrows, enter, the menu key etc. abandon the sequence, keeping what was typed
+                    out.extend(self.pending.drain(..));
+                    out.push(c);
+                    self.state = State::Idle;
+                } else {
+                    self.pending.push(c);
+                    if let Some(result) = table.lookup(&self.pending) {
+                        out.push(result);
+                        self.reset();
+                    } else if !table.is_prefix(&self.pending) {
+                        // not a known sequence: deliver the characters as typed
+                        out.extend(self.pending.drain(..));
+                        self.state = State::Idle;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn is_action_key(c: char) -> bool {
+    c.is_control() || ['←', '→', '↑', '↓', '∴', '⇄'].contains(&c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_str(composer: &mut Composer, table: &ComposeTable, s: &str) -> String {
+        composer.process(table, s.chars().collect()).into_iter().collect()
+    }
+
+    #[test]
+    fn compose_sequences() {
+        let table = ComposeTable::default_sequences();
+        let mut composer = Composer::default();
+        assert_eq!(type_str(&mut composer, &table, "⎄o/"), "ø");
+        assert_eq!(type_str(&mut composer, &table, "caf⎄'e!"), "café!");
+        // sequences can span several batches of keys
+        assert_eq!(type_str(&mut composer, &table, "⎄\""), "");
+        assert!(composer.is_composing());
+        assert_eq!(type_str(&mut composer, &table, "u"), "ü");
+        assert!(!composer.is_composing());
+        // unknown sequences are delivered as typed
+        assert_eq!(type_str(&mut composer, &table, "⎄qz"), "qz");
+        // compose twice cancels, backspace edits the sequence
+        assert_eq!(type_str(&mut composer, &table, "⎄⎄a"), "a");
+        assert_eq!(type_str(&mut composer, &table, "⎄'\u{8}\"o"), "ö");
+        assert_eq!(type_str(&mut composer, &table, "⎄'→"), "'→");
+    }
+
+    #[test]
+    fn dead_keys() {
+        let mut table = ComposeTable::default_sequences();
+        table.extend(&ComposeTable::parse("dead ´\ndead ` a à\ndead ¨ x ẍ  # a comment").unwrap());
+        let mut composer = Composer::default();
+        assert_eq!(type_str(&mut composer, &table, "´e"), "é");
+        assert_eq!(type_str(&mut composer, &table, "`a"), "à");
+        assert_eq!(type_str(&mut composer, &table, "¨x"), "ẍ");
+        assert_eq!(type_str(&mut composer, &table, "´ ´´"), "´´");
+        assert_eq!(type_str(&mut composer, &table, "´\u{8}e"), "e");
+        // a dead key that doesn't combine is kept, and the next key is interpreted normally
+        assert_eq!(type_str(&mut composer, &table, "´q´`a"), "´q´à");
+        assert_eq!(type_str(&mut composer, &table, "´⎄o/"), "´ø");
+    }
+
+    #[test]
+    fn tables() {
+        let table = ComposeTable::default_sequences();
+        assert_eq!(table, ComposeTable::parse(&table.to_text()).unwrap());
+        assert!(table.is_prefix(&['o']));
+        assert!(!table.is_prefix(&['o', '/']));
+        assert_eq!(ComposeTable::parse("compose a").unwrap_err().line, 1);
+        assert_eq!(ComposeTable::parse("\ndead a b").unwrap_err().line, 2);
+        assert!(ComposeTable::parse("compose a b c\ncompose a b d").is_err());
+        // later tables override earlier ones
+        let user = ComposeTable::parse("compose o / 0").unwrap();
+        let map = ComposeTable::parse("compose a e ä").unwrap();
+        let effective = ComposeTable::effective(Some(&user), Some(&map));
+        assert_eq!(effective.lookup(&['o', '/']), Some('0'));
+        assert_eq!(effective.lookup(&['a', 'e']), Some('ä'));
+    }
+}
@@ -0,0 +1,330 @@
+//! Data-driven keymaps.
+//!
+//! A keymap is described by a plain text file, one directive per line. Blank lines and
+//! lines starting with `#` are ignored; any text following the last field of a directive
+//! is also ignored, provided it starts with `#`.
+//!
+//! ```text
+//! name <name>                           the name of the map (may contain spaces)
+//! shift <row> <col>                     a key that selects the shift layer
+//! alt <row> <col>                       a key that selects the alt layer
+//! key <row> <col> <base> <shift> <hold> <alt>
+//! dead <accent>                         <accent> is a dead key, combined using the compose sequences
+//! dead <accent> <base> <result>         typing <accent> then <base> yields <result>
+//! compose <c1> <c2> ... <result>        compose sequences, see `compose.rs`
+//! ```
+//!
+//! Characters are either written literally, or as `U+XXXX` for control characters, spaces and
+//! anything else that is awkward to type. A `-` means "no mapping"; a literal dash is `U+002D`,
+//! and a `#` that could be mistaken for a comment is `U+0023`. A layer can emit `COMPOSE_KEY`
+//! to start a compose sequence.
+//!
+//! If a map declares no `alt` keys, the alt layer is never selected. A key with no `hold`
+//! mapping repeats when held down.
+//!
+//! The built-in maps in `keymaps/` are written in this format, and make good starting points.
+//! Custom maps are kept by the status bar in the `kbd.keymaps` PDDB dictionary, one map per key,
+//! and are selected with `KeyMap::Custom` once loaded.
+
+use crate::api::*;
+use crate::compose::ComposeTable;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Text of the built-in maps. These are compiled in, so that the keyboard works before the PDDB is mounted.
+const QWERTY: &str = include_str!("keymaps/qwerty.kmap");
+const AZERTY: &str = include_str!("keymaps/azerty.kmap");
+const QWERTZ: &str = include_str!("keymaps/qwertz.kmap");
+const DVORAK: &str = include_str!("keymaps/dvorak.kmap");
+
+/// Returns the source text of a built-in map. Braille is a chorded layout and has no keymap.
+pub fn builtin_text(map: KeyMap) -> Option<&'static str> {
+    match map {
+        KeyMap::Qwerty => Some(QWERTY),
+        KeyMap::Azerty => Some(AZERTY),
+        KeyMap::Qwertz => Some(QWERTZ),
+        KeyMap::Dvorak => Some(DVORAK),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeyMapError {
+    /// line number of the offending directive, starting from 1
+    pub line: usize,
+    pub reason: &'static str,
+}
+impl std::fmt::Display for KeyMapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct KeyMapData {
+    pub name: String,
+    keys: BTreeMap<RowCol, ScanCode>,
+    shift_keys: Vec<RowCol>,
+    alt_keys: Vec<RowCol>,
+    /// dead keys and compose sequences specific to this map
+    compose: ComposeTable,
+}
+
+impl KeyMapData {
+    /// Parse and validate a keymap from its text representation.
+    pub fn parse(text: &str) -> Result<Self, KeyMapError> {
+        let mut map = KeyMapData::default();
+        for (index, line) in text.lines().enumerate() {
+            let err = |reason| KeyMapError { line: index + 1, reason };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let directive = fields.next().unwrap();
+            match directive {
+                "name" => {
+                    let name = line["name".len()..].trim();
+                    if name.is_empty() {
+                        return Err(err("missing name"));
+                    }
+                    map.name = String::from(name);
+                    continue;
+                }
+                "shift" | "alt" => {
+                    let rc = parse_rowcol(&mut fields).ok_or(err("invalid row/col"))?;
+                    if map.shift_keys.contains(&rc) || map.alt_keys.contains(&rc) {
+                        return Err(err("modifier key declared twice"));
+                    }
+                    if directive == "shift" {
+                        map.shift_keys.push(rc);
+                    } else {
+                        map.alt_keys.push(rc);
+                    }
+                }
+                "key" => {
+                    let rc = parse_rowcol(&mut fields).ok_or(err("invalid row/col"))?;
+                    let mut layers = [None; 4];
+                    for layer in layers.iter_mut() {
+                        *layer = parse_char(fields.next().ok_or(err("missing layer"))?).ok_or(err("invalid character"))?;
+                    }
+                    let code = ScanCode { key: layers[0], shift: layers[1], hold: layers[2], alt: layers[3] };
+                    if map.keys.insert(rc, code).is_some() {
+                        return Err(err("key declared twice"));
+                    }
+                }
+                "dead" | "compose" => {
+                    // these take a variable number of fields, and handle their own trailing comments
+                    map.compose.parse_directive(directive, &mut fields).map_err(err)?;
+                    continue;
+                }
+                _ => return Err(err("unknown directive")),
+            }
+            if let Some(extra) = fields.next() {
+                if !extra.starts_with('#') {
+                    return Err(err("too many fields"));
+                }
+            }
+        }
+        if map.name.is_empty() {
+            return Err(KeyMapError { line: 0, reason: "map has no name" });
+        }
+        if map.keys.is_empty() {
+            return Err(KeyMapError { line: 0, reason: "map has no keys" });
+        }
+        Ok(map)
+    }
+
+    /// Returns the parsed form of a built-in map.
+    pub fn builtin(map: KeyMap) -> Option<Self> {
+        builtin_text(map).map(|text| KeyMapData::parse(text).expect("built-in keymap is malformed"))
+    }
+
+    /// Render the map back into its text representation. Comments are not preserved.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        writeln!(text, "name {}", self.name).unwrap();
+        for rc in self.shift_keys.iter() {
+            writeln!(text, "shift {} {}", rc.r, rc.c).unwrap();
+        }
+        for rc in self.alt_keys.iter() {
+            writeln!(text, "alt {} {}", rc.r, rc.c).unwrap();
+        }
+        for (rc, code) in self.keys.iter() {
+            writeln!(text, "key {} {} {} {} {} {}", rc.r, rc.c,
+                format_char(code.key), format_char(code.shift), format_char(code.hold), format_char(code.alt)
+            ).unwrap();
+        }
+        text.push_str(&self.compose.to_text());
+        text
+    }
+
+    /// Compute the key mapping of row/col to key tuples
+    pub fn lookup(&self, rc: RowCol) -> ScanCode {
+        self.keys.get(&rc).copied().unwrap_or_default()
+    }
+    pub fn is_shift(&self, rc: RowCol) -> bool {
+        self.shift_keys.contains(&rc)
+    }
+    pub fn is_alt(&self, rc: RowCol) -> bool {
+        self.alt_keys.contains(&rc)
+    }
+    /// Returns true if the key repeats when held down: it has no meaning of its own when held,
+    /// and it isn't the menu key
+    pub fn repeats(&self, rc: RowCol) -> bool {
+        self.lookup(rc).hold.is_none() && rc != RowCol::new(5, 2)
+    }
+    /// Returns true if `c` starts a dead key sequence in this map
+    pub fn is_dead(&self, c: char) -> bool {
+        self.compose.is_dead(c)
+    }
+    /// What `accent` followed by `base` yields, from this map's own sequences only. The keyboard
+    /// service also falls back on the default and user sequences, see `ComposeTable::effective`.
+    pub fn combine(&self, accent: char, base: char) -> Option<char> {
+        self.compose.combine(accent, base)
+    }
+    pub fn compose_table(&self) -> &ComposeTable {
+        &self.compose
+    }
+}
+
+fn parse_rowcol<'a>(fields: &mut impl Iterator<Item = &'a str>) -> Option<RowCol> {
+    let r = fields.next()?.parse::<u8>().ok()?;
+    let c = fields.next()?.parse::<u8>().ok()?;
+    Some(RowCol { r, c })
+}
+
+/// `Some(None)` is the "no mapping" placeholder; `None` is a malformed character.
+pub(crate) fn parse_char(field: &str) -> Option<Option<char>> {
+    if field == "-" {
+        return Some(None);
+    }
+    if field.len() > 2 && field.starts_with("U+") {
+        let code = u32::from_str_radix(&field[2..], 16).ok()?;
+        return core::char::from_u32(code).map(|c| Some(c));
+    }
+    let mut chars = field.chars();
+    let c = chars.next()?;
+    if chars.next().is_some() {
+        None
+    } else {
+        Some(Some(c))
+    }
+}
+
+pub(crate) fn format_char(c: Option<char>) -> String {
+    match c {
+        None => String::from("-"),
+        Some(c) if c.is_whitespace() || c.is_control() || c == '-' || c == '#' => format!("U+{:04X}", c as u32),
+        Some(c) => c.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUILTINS: [KeyMap; 4] = [KeyMap::Qwerty, KeyMap::Azerty, KeyMap::Qwertz, KeyMap::Dvorak];
+
+    #[test]
+    fn builtins_round_trip() {
+        for &map in BUILTINS.iter() {
+            let parsed = KeyMapData::parse(builtin_text(map).unwrap()).unwrap();
+            let reparsed = KeyMapData::parse(&parsed.to_text()).unwrap();
+            assert_eq!(parsed, reparsed, "{:?} did not survive a round trip", map);
+            assert_eq!(reparsed.to_text(), parsed.to_text());
+        }
+    }
+
+    #[test]
+    fn builtins_match_hardware() {
+        for &map in BUILTINS.iter() {
+            let data = KeyMapData::builtin(map).unwrap();
+            // every map covers the full set of 54 keys, and uses both physical shift keys as modifiers
+            assert_eq!(data.keys.len(), 54);
+            assert!(data.is_shift(RowCol::new(8, 9)));
+            match map {
+                KeyMap::Azerty => assert!(data.is_alt(RowCol::new(8, 5))),
+                _ => assert!(data.is_shift(RowCol::new(8, 5))),
+            }
+            assert_eq!(data.lookup(RowCol::new(5, 2)).key, Some('∴'));
+            assert_eq!(data.lookup(RowCol::new(7, 9)).key, Some('\r'));
+            // space repeats, the menu key and the compose key don't
+            assert!(data.repeats(RowCol::new(8, 7)));
+            assert!(!data.repeats(RowCol::new(5, 2)));
+            assert!(!data.repeats(RowCol::new(8, 8)));
+        }
+        let qwerty = KeyMapData::builtin(KeyMap::Qwerty).unwrap();
+        assert_eq!(qwerty.lookup(RowCol::new(1, 0)),
+            ScanCode { key: Some('q'), shift: Some('Q'), hold: Some('%'), alt: None });
+        assert_eq!(qwerty.lookup(RowCol::new(8, 8)),
+            ScanCode { key: Some('.'), shift: Some('😊'), hold: Some(crate::compose::COMPOSE_KEY), alt: None });
+        let azerty = KeyMapData::builtin(KeyMap::Azerty).unwrap();
+        assert_eq!(azerty.lookup(RowCol::new(6, 5)).hold, Some('-'));
+        assert_eq!(azerty.lookup(RowCol::new(6, 9)).hold, Some('#'));
+        assert_eq!(azerty.lookup(RowCol::new(0, 0)).alt, Some('§'));
+        // keys that aren't in the map have no meaning
+        assert_eq!(qwerty.lookup(RowCol::new(15, 15)), ScanCode::default());
+        assert!(KeyMapData::builtin(KeyMap::Braille).is_none());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(KeyMapData::parse("key 1 0 q Q - -").unwrap_err().reason, "map has no name");
+        assert_eq!(KeyMapData::parse("name x\nkey 1 0 q Q -").unwrap_err(),
+            KeyMapError { line: 2, reason: "missing layer" });
+        assert_eq!(KeyMapData::parse("name x\nkey 1 0 qq Q - -").unwrap_err().reason, "invalid character");
+        assert_eq!(KeyMapData::parse("name x\nkey 1 0 q Q - - z").unwrap_err().reason, "too many fields");
+        assert_eq!(KeyMapData::parse("name x\nkey 1 0 q Q - -\nkey 1 0 a A - -").unwrap_err().reason, "key declared twice");
+        assert_eq!(KeyMapData::parse("name x\nshift 8 5\nalt 8 5\nkey 1 0 q Q - -").unwrap_err().reason,
+            "modifier key declared twice");
+        assert_eq!(KeyMapData::parse("name x\nfoo\nkey 1 0 q Q - -").unwrap_err().reason, "unknown directive");
+        assert!(KeyMapData::parse("name my map\nkey 1 0 q Q # -  # a trailing comment").is_ok());
+    }
+
+    #[test]
+    fn dead_keys() {
+        use crate::compose::Composer;
+        let map = KeyMapData::parse("name dead\nkey 1 0 ´ ` - -\ndead ´ e é\ndead ` a à\n").unwrap();
+        assert_eq!(map, KeyMapData::parse(&map.to_text()).unwrap());
+        assert!(map.is_dead('´') && map.is_dead('`') && !map.is_dead('e'));
+        assert_eq!(map.combine('´', 'e'), Some('é'));
+        assert_eq!(map.combine('´', 'a'), None);
+        // the map's own sequences are enough to drive the composer
+        let mut composer = Composer::default();
+        let mut resolve = |keys: Vec<char>| composer.process(map.compose_table(), keys);
+        assert_eq!(resolve(vec!['´']), vec![]);
+        assert_eq!(resolve(vec!['e', '`', 'a']), vec!['é', 'à']);
+        assert_eq!(resolve(vec!['´', 'x', '´', ' ']), vec!['´', 'x', '´']);
+        assert_eq!(resolve(vec!['´', '\u{0008}', 'e']), vec!['e']);
+    }
+
+    #[test]
+    fn builtin_dead_keys() {
+        use crate::compose::Composer;
+        // AZERTY has the circumflex and diaeresis dead keys of a French keyboard, on v
+        let azerty = KeyMapData::builtin(KeyMap::Azerty).unwrap();
+        let v = azerty.lookup(RowCol::new(3, 4));
+        assert_eq!((v.hold, v.alt), (Some('^'), Some('¨')));
+        assert!(azerty.is_dead('^') && azerty.is_dead('¨'));
+        let table = ComposeTable::effective(None, Some(azerty.compose_table()));
+        let mut composer = Composer::default();
+        let typed: String = composer.process(&table, "f^ete, l¨y ^ ".chars().collect()).into_iter().collect();
+        assert_eq!(typed, "fête, lÿ ^");
+        // the other built-in maps have none
+        for &map in BUILTINS.iter().filter(|&&m| m != KeyMap::Azerty) {
+            assert!(KeyMapData::builtin(map).unwrap().compose_table().is_empty(), "{:?}", map);
+        }
+    }
+
+    #[test]
+    fn map_sequences() {
+        let map = KeyMapData::parse("name dead\nkey 1 0 ´ ` - ⎄\ndead ´\ndead ` a à\ncompose a a å\n").unwrap();
+        assert_eq!(map, KeyMapData::parse(&map.to_text()).unwrap());
+        assert!(map.compose_table().is_dead('´'));
+        assert_eq!(map.compose_table().lookup(&['`', 'a']), Some('à'));
+        assert_eq!(map.compose_table().lookup(&['a', 'a']), Some('å'));
+        assert_eq!(KeyMapData::parse("name x\nkey 1 0 q Q - -\ndead a b").unwrap_err(),
+            KeyMapError { line: 3, reason: "dead keys take an accent, or an accent, base and result" });
+    }
+}
@@ -3,8 +3,12 @@
 use num_traits::*;
 
 pub mod api;
+pub mod keymap;
+pub mod compose;
 
 pub use api::*;
+pub use keymap::{KeyMapData, KeyMapError};
+pub use compose::{ComposeTable, COMPOSE_KEY};
 use xous::{send_message, Message};
 use xous_ipc::{Buffer, String};
 
@@ -70,6 +74,48 @@ impl Keyboard {
         }
     }
 
+    /// Validates and loads the text of a custom keymap. The map is only used once `KeyMap::Custom`
+    /// is selected with `set_keymap()`; the custom map does not persist across reboots.
+    pub fn load_custom_keymap(&self, text: &str) -> Result<KeyMapData, KeyMapError> {
+        let data = KeyMapData::parse(text)?;
+        // send the normalized form, which drops comments and is therefore much more compact
+        let normalized = data.to_text();
+        if normalized.len() > MAX_KEYMAP_LEN {
+            return Err(KeyMapError { line: 0, reason: "map is too large" });
+        }
+        let buf = Buffer::into_buf(String::<MAX_KEYMAP_LEN>::from_str(&normalized))
+            .or(Err(KeyMapError { line: 0, reason: "internal error" }))?;
+        buf.lend(self.conn, Opcode::LoadCustomKeyMap.to_u32().unwrap())
+            .or(Err(KeyMapError { line: 0, reason: "internal error" }))?;
+        Ok(data)
+    }
+
+    /// Validates and loads the user's compose sequences. These add to the built-in sequences, and
+    /// are in turn overridden by any sequences defined by the current keymap.
+    pub fn load_compose_table(&self, text: &str) -> Result<ComposeTable, KeyMapError> {
+        let table = ComposeTable::parse(text)?;
+        let normalized = table.to_text();
+        if normalized.len() > MAX_KEYMAP_LEN {
+            return Err(KeyMapError { line: 0, reason: "table is too large" });
+        }
+        let buf = Buffer::into_buf(String::<MAX_KEYMAP_LEN>::from_str(&normalized))
+            .or(Err(KeyMapError { line: 0, reason: "internal error" }))?;
+        buf.lend(self.conn, Opcode::LoadComposeTable.to_u32().unwrap())
+            .or(Err(KeyMapError { line: 0, reason: "internal error" }))?;
+        Ok(table)
+    }
+    /// Returns true if a dead key or compose sequence has been started but not finished. Raw listeners
+    /// can use this to tell that the keys they are seeing haven't produced characters yet.
+    pub fn is_composing(&self) -> Result<bool, xous::Error> {
+        match send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::GetComposeState.to_usize().unwrap(),
+            0, 0, 0, 0)
+        ) {
+            Ok(xous::Result::Scalar1(state)) => Ok(state != 0),
+            _ => Err(xous::Error::InternalError)
+        }
+    }
+
     #[cfg(not(any(target_os = "none", target_os = "xous")))]
     pub fn hostmode_inject_key(&self, c: char) {
         send_message(self.conn,
@@ -3,7 +3,11 @@
 
 mod api;
 use api::*;
-mod mappings;
+#[allow(dead_code)] // the server doesn't use the map rendering helpers
+mod keymap;
+#[allow(dead_code)]
+mod compose;
+use compose::{ComposeTable, Composer};
 
 use log::info;
 
@@ -14,7 +18,8 @@ use xous::{CID, msg_scalar_unpack, msg_blocking_scalar_unpack};
 mod implementation {
     use utralib::generated::*;
     use crate::{RowCol, KeyRawStates, api::*};
-    use crate::mappings::*;
+    use crate::keymap::KeyMapData;
+    use crate::compose::ComposeTable;
     use ticktimer_server::Ticktimer;
     use xous::CID;
     use num_traits::ToPrimitive;
@@ -37,6 +42,14 @@ mod implementation {
         ticktimer: Ticktimer,
         /// mapping for ScanCode translation
         map: KeyMap,
+        /// the key data for the current map
+        layout: KeyMapData,
+        /// a custom map, if one has been loaded
+        custom: Option<KeyMapData>,
+        /// compose sequences loaded by the user
+        user_compose: Option<ComposeTable>,
+        /// the compose table in effect: defaults, user sequences and the current map's sequences
+        compose: ComposeTable,
         /// delay in ms before a key is considered to be repeating
         delay: u32,
         /// rate in ms for repeating a key
@@ -167,6 +180,10 @@ mod implementation {
                 last_state: HashSet::with_capacity(16),
                 ticktimer,
                 map: default_map,
+                layout: KeyMapData::default(), // filled in by apply_map() below
+                custom: None,
+                user_compose: None,
+                compose: ComposeTable::default(),
                 delay: 500,
                 rate: 20,
                 shift_down: false,
@@ -185,6 +202,7 @@ mod implementation {
                 settings: setting_page,
                 spinor: spinor::Spinor::new(&xns).unwrap(),
             };
+            kbd.apply_map(default_map);
 
             xous::claim_interrupt(
                 utra::keyboard::KEYBOARD_IRQ,
@@ -240,15 +258,42 @@ mod implementation {
             self.spinor.patch(settings, xous::EARLY_SETTINGS,
                 &code, 0
             ).expect("couldn't patch our keyboard code");
-            self.map = map;
+            self.apply_map(map);
         }
         pub(crate) fn get_map(&mut self) -> KeyMap {
             // refresh the map from the setting in the FLASH
             let settings: &[u8] = self.settings.as_slice();
             let code = u32::from_le_bytes(settings[..4].try_into().unwrap());
-            self.map = KeyMap::from(code as usize);
+            let map = KeyMap::from(code as usize);
+            if map != self.map {
+                self.apply_map(map);
+            }
             self.map
         }
+        pub(crate) fn set_custom_map(&mut self, data: KeyMapData) {
+            log::info!("loaded custom keymap '{}'", data.name);
+            self.custom = Some(data);
+            if self.map == KeyMap::Custom {
+                self.apply_map(KeyMap::Custom);
+            }
+        }
+        pub(crate) fn set_compose_table(&mut self, table: ComposeTable) {
+            self.user_compose = Some(table);
+            self.compose = ComposeTable::effective(self.user_compose.as_ref(), Some(self.layout.compose_table()));
+        }
+        pub(crate) fn compose_table(&self) -> &ComposeTable {
+            &self.compose
+        }
+        fn apply_map(&mut self, map: KeyMap) {
+            self.map = map;
+            self.layout = match map {
+                // the custom map is loaded out of the PDDB, which isn't available in early boot.
+                // Use QWERTY until it shows up, so the PDDB password can still be typed.
+                KeyMap::Custom => self.custom.clone(),
+                _ => KeyMapData::builtin(map),
+            }.unwrap_or(KeyMapData::builtin(KeyMap::Qwerty).unwrap());
+            self.compose = ComposeTable::effective(self.user_compose.as_ref(), Some(self.layout.compose_table()));
+        }
         pub(crate) fn set_repeat(&mut self, rate: u32, delay: u32) {
             self.rate = rate;
             self.delay = delay;
@@ -465,70 +510,39 @@ mod implementation {
              */
             let mut ks: Vec<char> = Vec::new();
 
-            // first check for shift and alt keys
-            for rc in krs.keydowns.iter() {
-                match self.map {
-                    KeyMap::Azerty => {
-                        if (rc.r == 8) && (rc.c == 5) { // left shift (orange)
-                            if self.alt_up == false {
-                                self.alt_down = true;
-                            } else {
-                                self.alt_up = false;
-                            }
-                        } else if (rc.r == 8) && (rc.c == 9) { // right shift (yellow)
-                            if self.shift_up == false {
-                                self.shift_down = true;
-                            } else {
-                                self.shift_up = false;
-                            }
-                        }
-                    },
-                    _ => { // the rest just have one color of shift
-                        if ((rc.r == 8) && (rc.c == 5)) || ((rc.r == 8) && (rc.c == 9)) {
-                            // if the shift key was tapped twice, remove the shift modifier
-                            if self.shift_up == false {
-                                //info!("shift down true");
-                                self.shift_down = true;
-                            } else {
-                                //info!("shift up false");
-                                self.shift_up = false;
-                            }
-                        }
+            // first check for shift and alt keys. Which physical keys act as modifiers is defined by the map;
+            // maps that don't declare any alt keys (i.e. everything but AZERTY) just have one color of shift.
+            for &rc in krs.keydowns.iter() {
+                if self.layout.is_alt(rc) {
+                    if self.alt_up == false {
+                        self.alt_down = true;
+                    } else {
+                        self.alt_up = false;
+                    }
+                } else if self.layout.is_shift(rc) {
+                    // if the shift key was tapped twice, remove the shift modifier
+                    if self.shift_up == false {
+                        self.shift_down = true;
+                    } else {
+                        self.shift_up = false;
                     }
                 }
             }
             let mut keyups_noshift: Vec::<RowCol> = Vec::new();
             for &rc in krs.keyups.iter() {
-                match self.map {
-                    KeyMap::Azerty => {
-                        if (rc.r == 8) && (rc.c == 5) { // left shift (orange)
-                            if self.alt_down {
-                                self.alt_up = true;
-                            }
-                            self.alt_down = false;
-                        } else if (rc.r == 8) && (rc.c == 9) { // right shift (yellow)
-                            if self.shift_down {
-                                self.shift_up = true;
-                            }
-                            self.shift_down = false;
-                        } else {
-                            keyups_noshift.push(RowCol{r: rc.r as _, c: rc.c as _});
-                        }
-                    },
-                    _ => { // the rest just have one color of shift
-                        if ((rc.r == 8) && (rc.c == 5)) || ((rc.r == 8) && (rc.c == 9)) {
-                            // only set the shift-up if we didn't previously clear it with a double-tap of shift
-                            if self.shift_down {
-                                //info!("shift up true");
-                                self.shift_up = true;
-                            }
-                            //info!("shift down false");
-                            self.shift_down = false;
-                        } else {
-                            //info!("adding non-shift entry {:?}", rc);
-                            keyups_noshift.push(RowCol{r: rc.r as _, c: rc.c as _});
-                        }
+                if self.layout.is_alt(rc) {
+                    if self.alt_down {
+                        self.alt_up = true;
                     }
+                    self.alt_down = false;
+                } else if self.layout.is_shift(rc) {
+                    // only set the shift-up if we didn't previously clear it with a double-tap of shift
+                    if self.shift_down {
+                        self.shift_up = true;
+                    }
+                    self.shift_down = false;
+                } else {
+                    keyups_noshift.push(RowCol{r: rc.r as _, c: rc.c as _});
                 }
             }
 
@@ -537,16 +551,8 @@ mod implementation {
                 self.chord_timestamp = self.ticktimer.elapsed_ms();
             }
             for &rc in krs.keydowns.iter() {
-                let code = match self.map {
-                    KeyMap::Qwerty => map_qwerty(rc),
-                    KeyMap::Dvorak => map_dvorak(rc),
-                    KeyMap::Azerty => map_azerty(rc),
-                    KeyMap::Qwertz => map_qwertz(rc),
-                    _ => ScanCode {key: None, shift: None, hold: None, alt: None},
-                };
-                if code.hold == None
-                && !((rc.r == 5) && (rc.c == 2)) // scan code for the menu key
-                 { // if there isn't a pre-defined meaning if the key is held *and* it's not the menu key: it's a repeating key
+                let code = self.layout.lookup(rc);
+                if self.layout.repeats(rc) {
                     if let Some(key) = code.key {
                         self.repeating_key = Some(key);
                     }
@@ -566,13 +572,7 @@ mod implementation {
 
             for &rc in keyups_noshift.iter() {
                 // info!("interpreting keyups_noshift entry {:?}", rc);
-                let code = match self.map {
-                    KeyMap::Qwerty => map_qwerty(rc),
-                    KeyMap::Dvorak => map_dvorak(rc),
-                    KeyMap::Azerty => map_azerty(rc),
-                    KeyMap::Qwertz => map_qwertz(rc),
-                    _ => ScanCode {key: None, shift: None, hold: None, alt: None},
-                };
+                let code = self.layout.lookup(rc);
                 // delete the key repeat if there is one
                 if code.hold == None {
                     if let Some(key) = code.key {
@@ -584,57 +584,31 @@ mod implementation {
                     }
                 }
 
-                match self.map {
-                    KeyMap::Azerty => {
-                        if self.shift_down || self.shift_up {
-                            if let Some(shiftcode) = code.shift {
-                                ks.push(shiftcode);
-                            } else if let Some(keycode) = code.key {
-                                ks.push(keycode);
-                            }
-                            self.shift_down = false;
-                            self.shift_up = false;
-                        } else if self.alt_down || self.alt_up {
-                            if let Some(altcode) = code.alt {
-                                ks.push(altcode);
-                            } else if let Some(shiftcode) = code.shift {
-                                ks.push(shiftcode);
-                            } else if let Some(keycode) = code.key {
-                                ks.push(keycode);
-                            }
-                            self.alt_down = false;
-                            self.alt_up = false;
-                        } else if hold {
-                            if let Some(holdcode) = code.hold {
-                                ks.push(holdcode);
-                            }
-                        } else {
-                            if let Some(keycode) = code.key {
-                                ks.push(keycode);
-                            }
-                        }
-                    },
-                    _ => {
-                        if self.shift_down || self.alt_down || self.shift_up || self.alt_up {
-                            if let Some(shiftcode) = code.shift {
-                                ks.push(shiftcode);
-                            } else if let Some(keycode) = code.key {
-                                ks.push(keycode);
-                            }
-                            self.shift_down = false;
-                            self.alt_down = false;
-                            self.shift_up = false;
-                            self.alt_up = false;
-                        } else if hold {
-                            if let Some(holdcode) = code.hold {
-                                ks.push(holdcode);
-                            }
-                        } else {
-                            if let Some(keycode) = code.key {
-                                // info!("appeding normal key '{}'", keycode);
-                                ks.push(keycode);
-                            }
-                        }
+                if self.shift_down || self.shift_up {
+                    if let Some(shiftcode) = code.shift {
+                        ks.push(shiftcode);
+                    } else if let Some(keycode) = code.key {
+                        ks.push(keycode);
+                    }
+                    self.shift_down = false;
+                    self.shift_up = false;
+                } else if self.alt_down || self.alt_up {
+                    if let Some(altcode) = code.alt {
+                        ks.push(altcode);
+                    } else if let Some(shiftcode) = code.shift {
+                        ks.push(shiftcode);
+                    } else if let Some(keycode) = code.key {
+                        ks.push(keycode);
+                    }
+                    self.alt_down = false;
+                    self.alt_up = false;
+                } else if hold {
+                    if let Some(holdcode) = code.hold {
+                        ks.push(holdcode);
+                    }
+                } else {
+                    if let Some(keycode) = code.key {
+                        ks.push(keycode);
                     }
                 }
             }
@@ -664,6 +638,9 @@ mod implementation {
     pub(crate) struct Keyboard {
         cid: xous::CID,
         map: KeyMap,
+        custom: Option<crate::keymap::KeyMapData>,
+        user_compose: Option<ComposeTable>,
+        compose: ComposeTable,
         rate: u32,
         delay: u32,
         chord_interval: u32,
@@ -675,6 +652,9 @@ mod implementation {
             Keyboard {
                 cid: xous::connect(sid).unwrap(),
                 map: KeyMap::Qwerty,
+                custom: None,
+                user_compose: None,
+                compose: ComposeTable::default_sequences(),
                 rate: 20,
                 delay: 200,
                 chord_interval: 50,
@@ -688,8 +668,30 @@ mod implementation {
 
         pub fn set_map(&mut self, map: KeyMap) {
             self.map = map;
+            self.update_compose();
         }
         pub fn get_map(&self) -> KeyMap {self.map}
+        pub fn set_custom_map(&mut self, data: crate::keymap::KeyMapData) {
+            log::info!("loaded custom keymap '{}'", data.name);
+            self.custom = Some(data);
+            self.update_compose();
+        }
+        pub fn set_compose_table(&mut self, table: ComposeTable) {
+            self.user_compose = Some(table);
+            self.update_compose();
+        }
+        pub fn compose_table(&self) -> &ComposeTable {
+            &self.compose
+        }
+        /// Keys are typed on the host, so the only part of the map that matters are its compose sequences
+        fn update_compose(&mut self) {
+            let builtin = crate::keymap::KeyMapData::builtin(self.map);
+            let map = match self.map {
+                KeyMap::Custom => self.custom.as_ref(),
+                _ => builtin.as_ref(),
+            };
+            self.compose = ComposeTable::effective(self.user_compose.as_ref(), map.map(|m| m.compose_table()));
+        }
 
         pub fn update(&self) -> KeyRawStates {
             KeyRawStates::new()
@@ -764,6 +766,8 @@ fn xmain() -> ! {
     }*/
     let mut esc_index: Option<usize> = None;
     let mut esc_chars = [0u8; 16];
+    // dead key and compose state. This is applied to the characters from every source, just before they go to the listener.
+    let mut composer = Composer::default();
 
     log::trace!("starting main loop");
     loop {
@@ -774,6 +778,7 @@ fn xmain() -> ! {
                 kbd.suspend();
                 susres.suspend_until_resume(token).expect("couldn't execute suspend/resume");
                 kbd.resume();
+                composer.reset();
             }),
             Some(Opcode::Vibe) => msg_scalar_unpack!(msg, ena, _,  _,  _, {
                 if ena != 0 { vibe = true }
@@ -786,6 +791,8 @@ fn xmain() -> ! {
                     Ok(cid) => {
                         listener_conn = Some(cid);
                         listener_op = Some(kr.listener_op_id as usize);
+                        // don't let a half-typed sequence leak into a new listener
+                        composer.reset();
                     }
                     Err(e) => {
                         log::error!("couldn't connect to listener: {:?}", e);
@@ -801,6 +808,8 @@ fn xmain() -> ! {
                     Ok(cid) => {
                         raw_listener_conn = Some(cid);
                         raw_listener_op = Some(kr.listener_op_id as u32);
+                        // raw listeners see physical keys only, so a pending sequence would be invisible to them
+                        composer.reset();
                     }
                     Err(e) => {
                         log::error!("couldn't connect to listener: {:?}", e);
@@ -810,13 +819,36 @@ fn xmain() -> ! {
                 }
             },
             Some(Opcode::SelectKeyMap) => msg_scalar_unpack!(msg, km, _, _, _, {
-                kbd.set_map(KeyMap::from(km))
+                kbd.set_map(KeyMap::from(km));
+                composer.reset();
             }),
             Some(Opcode::GetKeyMap) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
                 xous::return_scalar(msg.sender,
                     kbd.get_map().into()
                 ).expect("can't retrieve keymap");
             }),
+            Some(Opcode::LoadCustomKeyMap) => {
+                let buffer = unsafe{Buffer::from_memory_message(msg.body.memory_message().unwrap())};
+                let text = buffer.to_original::<xous_ipc::String::<MAX_KEYMAP_LEN>, _>().unwrap();
+                match keymap::KeyMapData::parse(text.as_str().unwrap_or("")) {
+                    Ok(data) => kbd.set_custom_map(data),
+                    Err(e) => log::error!("custom keymap rejected: {}", e),
+                }
+            },
+            Some(Opcode::LoadComposeTable) => {
+                let buffer = unsafe{Buffer::from_memory_message(msg.body.memory_message().unwrap())};
+                let text = buffer.to_original::<xous_ipc::String::<MAX_KEYMAP_LEN>, _>().unwrap();
+                match ComposeTable::parse(text.as_str().unwrap_or("")) {
+                    Ok(table) => {
+                        kbd.set_compose_table(table);
+                        composer.reset();
+                    }
+                    Err(e) => log::error!("compose table rejected: {}", e),
+                }
+            },
+            Some(Opcode::GetComposeState) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                xous::return_scalar(msg.sender, if composer.is_composing() { 1 } else { 0 }).expect("can't return compose state");
+            }),
             Some(Opcode::SetRepeat) => msg_scalar_unpack!(msg, rate, delay, _, _, {
                 kbd.set_repeat(rate as u32, delay as u32);
             }),
@@ -883,14 +915,16 @@ fn xmain() -> ! {
                 if let Some(conn) = listener_conn {
                     if key != '\u{0000}' {
                         log::info!("injecting key '{}'({:x})", key, key as u32); // always be noisy about this, it's an exploit path
-                        xous::send_message(conn,
-                            xous::Message::new_scalar(listener_op.unwrap(),
-                                key as u32 as usize,
-                                '\u{0000}' as u32 as usize,
-                                '\u{0000}' as u32 as usize,
-                                '\u{0000}' as u32 as usize,
-                        )
-                        ).unwrap();
+                        for composed in composer.process(kbd.compose_table(), vec![key]) {
+                            xous::send_message(conn,
+                                xous::Message::new_scalar(listener_op.unwrap(),
+                                    composed as u32 as usize,
+                                    '\u{0000}' as u32 as usize,
+                                    '\u{0000}' as u32 as usize,
+                                    '\u{0000}' as u32 as usize,
+                            )
+                            ).unwrap();
+                        }
                     }
                 }
             }),
@@ -925,6 +959,7 @@ fn xmain() -> ! {
                         kbd.track_keys(&rawstates)
                     },
                 };
+                let kc = composer.process(kbd.compose_table(), kc);
 
                 // send keys, if any
                 if kc.len() > 0 && listener_conn.is_some() && listener_op.is_some() {
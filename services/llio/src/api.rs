@@ -125,8 +125,10 @@ pub(crate) enum Opcode {
     SuspendResume,
 
     /// sets a wake-up alarm. This forces the SoC into power-on state, if it happens to be off.
-    /// primarily used to trigger cold reboots, but could have other reasons
-    SetWakeupAlarm, //(u8, TimeUnits),
+    /// primarily used to trigger cold reboots, but could have other reasons.
+    /// arg1 is the count, arg2 the `TimeUnits`, and if arg3 is nonzero, an alarm that's already
+    /// set to go off sooner is left alone. Returns 0 if the alarm was set, 2 if it was left alone.
+    SetWakeupAlarm, //(u8, TimeUnits, bool),
     /// clear any wakeup alarms that have been set
     ClearWakeupAlarm,
     /// sets an RTC alarm. This just triggers a regular interrupt, no other side-effect
@@ -15,12 +15,39 @@ impl Default for Weekday {
     fn default() -> Self { Weekday::Sunday }
 }
 
-#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone, PartialEq, Eq)]
 pub enum TimeUnits {
     Seconds,
     Minutes,
     Hours,
 }
+impl TimeUnits {
+    pub fn seconds(&self) -> u64 {
+        match self {
+            TimeUnits::Seconds => 1,
+            TimeUnits::Minutes => 60,
+            TimeUnits::Hours => 3600,
+        }
+    }
+}
+impl From<usize> for TimeUnits {
+    fn from(code: usize) -> Self {
+        match code {
+            1 => TimeUnits::Minutes,
+            2 => TimeUnits::Hours,
+            _ => TimeUnits::Seconds,
+        }
+    }
+}
+impl From<TimeUnits> for usize {
+    fn from(units: TimeUnits) -> usize {
+        match units {
+            TimeUnits::Seconds => 0,
+            TimeUnits::Minutes => 1,
+            TimeUnits::Hours => 2,
+        }
+    }
+}
 
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone, Default)]
 pub struct DateTime {
@@ -388,6 +388,20 @@ impl Llio {
             Message::new_blocking_scalar(Opcode::SetWakeupAlarm.to_usize().unwrap(), seconds_from_now as _, 0, 0, 0)
         ).map(|_|())
     }
+    /// Like `set_wakeup_alarm`, but counts in coarser units so it can reach up to 255 hours out, and
+    /// leaves alone a wakeup alarm that's already due sooner. Returns `true` if the alarm was set.
+    ///
+    /// The RTC counts down from whenever its clock next ticks over, so the first minute or hour
+    /// can be short: ask for one unit less than the time you have, and finish with seconds.
+    pub fn set_wakeup_alarm_units(&self, count: u8, units: TimeUnits) -> Result<bool, xous::Error> {
+        match send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::SetWakeupAlarm.to_usize().unwrap(), count as _, units.into(), 1, 0)
+        )? {
+            xous::Result::Scalar1(0) => Ok(true),
+            xous::Result::Scalar1(2) => Ok(false),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
     pub fn clear_wakeup_alarm(&self) -> Result<(), xous::Error> {
         send_message(self.conn,
             Message::new_blocking_scalar(Opcode::ClearWakeupAlarm.to_usize().unwrap(), 0, 0, 0, 0)
@@ -161,6 +161,9 @@ fn xmain() -> ! {
     let mut i2c = llio::I2c::new(&xns);
     let mut rtc_alarm_enabled = false;
     let mut wakeup_alarm_enabled = false;
+    // when the wakeup alarm goes off, in ticktimer ms; forgotten on resume, because the ticktimer
+    // stands still while we're suspended, and the alarm has probably gone off anyway
+    let mut wakeup_alarm_due: Option<u64> = None;
     let tt = ticktimer_server::Ticktimer::new().unwrap();
 
     log::trace!("starting main loop");
@@ -174,6 +177,7 @@ fn xmain() -> ! {
                 llio.tts_sleep_indicate(); // this happens after the suspend call because we don't want the sleep indicator to be restored on resume
                 susres.suspend_until_resume(token).expect("couldn't execute suspend/resume");
                 llio.resume();
+                wakeup_alarm_due = None;
                 #[cfg(feature="tts")]
                 llio.vibe(VibePattern::Double);
                 lockstatus_force_update = true; // notify the status bar that yes, it does need to redraw the lock status, even if the value hasn't changed since the last read
@@ -423,20 +427,32 @@ fn xmain() -> ! {
                 xous::return_scalar2(msg.sender, is_locked, force_update).expect("couldn't return status");
                 lockstatus_force_update = false;
             }),
-            Some(Opcode::SetWakeupAlarm) => msg_blocking_scalar_unpack!(msg, delay, _, _, _, {
+            Some(Opcode::SetWakeupAlarm) => msg_blocking_scalar_unpack!(msg, delay, units, keep_sooner, _, {
                 if delay > u8::MAX as usize {
-                    log::error!("Wakeup must be no longer than {} secs in the future", u8::MAX);
+                    log::error!("Wakeup must be no longer than {} units in the future", u8::MAX);
                     xous::return_scalar(msg.sender, 1).expect("couldn't return to caller");
                     continue;
                 }
-                let seconds = delay as u8;
+                let count = delay as u8;
+                let units = TimeUnits::from(units);
+                let due = tt.elapsed_ms() + count as u64 * units.seconds() * 1000;
+                if keep_sooner != 0 && wakeup_alarm_due.map_or(false, |d| d <= due) {
+                    xous::return_scalar(msg.sender, 2).expect("couldn't return to caller");
+                    continue;
+                }
+                wakeup_alarm_due = Some(due);
                 wakeup_alarm_enabled = true;
                 // make sure battery switchover is enabled, otherwise we won't keep time when power goes off
                 i2c.i2c_write(ABRTCMC_I2C_ADR, ABRTCMC_CONTROL3, &[(Control3::BATT_STD_BL_EN).bits()]).expect("RTC access error");
-                // set clock units to 1 second, output pulse length to ~218ms
-                i2c.i2c_write(ABRTCMC_I2C_ADR, ABRTCMC_TIMERB_CLK, &[(TimerClk::CLK_1_S | TimerClk::PULSE_218_MS).bits()]).expect("RTC access error");
+                // set clock units, output pulse length to ~218ms
+                let clk = match units {
+                    TimeUnits::Seconds => TimerClk::CLK_1_S,
+                    TimeUnits::Minutes => TimerClk::CLK_60_S,
+                    TimeUnits::Hours => TimerClk::CLK_3600_S,
+                };
+                i2c.i2c_write(ABRTCMC_I2C_ADR, ABRTCMC_TIMERB_CLK, &[(clk | TimerClk::PULSE_218_MS).bits()]).expect("RTC access error");
                 // program elapsed time
-                i2c.i2c_write(ABRTCMC_I2C_ADR, ABRTCMC_TIMERB, &[seconds]).expect("RTC access error");
+                i2c.i2c_write(ABRTCMC_I2C_ADR, ABRTCMC_TIMERB, &[count]).expect("RTC access error");
                 // enable timerb countdown interrupt, also clears any prior interrupt flag
                 let mut control2 = (Control2::COUNTDOWN_B_INT).bits();
                 if rtc_alarm_enabled {
@@ -453,6 +469,7 @@ fn xmain() -> ! {
             }),
             Some(Opcode::ClearWakeupAlarm) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
                 wakeup_alarm_enabled = false;
+                wakeup_alarm_due = None;
                 // make sure battery switchover is enabled, otherwise we won't keep time when power goes off
                 i2c.i2c_write(ABRTCMC_I2C_ADR, ABRTCMC_CONTROL3, &[(Control3::BATT_STD_BL_EN).bits()]).expect("RTC access error");
                 let mut config = Config::CLKOUT_DISABLE.bits();
@@ -561,7 +578,11 @@ fn xmain() -> ! {
             Some(Opcode::GetRtcValue) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
                 use chrono::prelude::*;
                 let now = Local::now();
-                let total_secs = now.timestamp_millis() / 1000 - 148409348; // sets the offset to something like 1974, which is roughly where an RTC value ends up in reality
+                let mut total_secs = now.timestamp_millis() / 1000 - 148409348; // sets the offset to something like 1974, which is roughly where an RTC value ends up in reality
+                if susres::simulation_enabled() {
+                    // the RTC keeps counting through a simulated suspend
+                    total_secs += (susres.simulated_sleep_ms().unwrap_or(0) / 1000) as i64;
+                }
                 xous::return_scalar2(msg.sender,
                     ((total_secs >> 32) & 0xFFFF_FFFF) as usize,
                     (total_secs & 0xFFFF_FFFF) as usize,
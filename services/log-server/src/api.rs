@@ -9,8 +9,49 @@ pub struct LogRecord {
     pub level: u32,
     pub args_length: u32,
     pub args: [u8; 3000],
+    /// key/value fields, each as `key KV_SEP value KV_END`
+    pub kv_length: u32,
+    pub kv: [u8; 512],
 }
 
+/// Between a field's key and its value, in `LogRecord::kv`
+pub const KV_SEP: u8 = 0x1f;
+/// After each field's value, in `LogRecord::kv`
+pub const KV_END: u8 = 0x1e;
+
+/// The name a process goes by in the log
+#[repr(C)]
+pub struct ProgramName {
+    pub name_length: u32,
+    pub name: [u8; 64],
+}
+
+/// How log records are written out, set with `Opcode::SetOutputFormat`
+pub const FORMAT_TEXT: usize = 0;
+/// One JSON object per line. Panics, stdout and the log server's own notes stay plain text, so
+/// tools should skip lines that don't start with `{`.
+pub const FORMAT_JSON_LINES: usize = 1;
+
+/// Sets the most verbose level logged for a module and everything under it
+#[repr(C)]
+pub struct ModuleLevel {
+    pub module_length: u32,
+    pub module: [u8; 128],
+    /// a `log::Level` discriminant, 0 for off, or `LEVEL_DEFAULT` to remove the module's filter
+    pub level: u32,
+    /// set to 1 by the log server if the filter was set; see `Opcode::ClaimControl`
+    pub accepted: u32,
+}
+pub const LEVEL_DEFAULT: u32 = u32::MAX;
+
+/// Message IDs a registered sink receives. Each is a memory message moved to the sink, holding
+/// `valid` bytes of log lines as UTF-8 text.
+pub const SINK_BATCH: usize = 0;
+/// As `SINK_BATCH`, but holding a panic: worth saving right away
+pub const SINK_PANIC: usize = 1;
+/// What `Opcode::SinkRegister` returns to a process without control
+pub const SINK_REFUSED: usize = 2;
+
 #[derive(Debug, PartialEq, num_derive::FromPrimitive, num_derive::ToPrimitive)]
 pub enum Opcode {
     /// A `LogRecord` message, delivering structured log output
@@ -22,9 +63,37 @@ pub enum Opcode {
     /// A `&[u8]` destined for stderr
     StandardError = 2,
 
-    /// A `xous::StringBuffer` containing this program's name
+    /// A `ProgramName`, naming the sender in the records it logs
     ProgramName = 3,
 
+    /// A `ModuleLevel`, lent mutably, filtering what gets logged. Only for processes that have
+    /// control; see `ClaimControl`.
+    SetModuleLevel = 4,
+
+    /// Removes all the module level filters. Returns 1 if they were, 0 if the caller doesn't have
+    /// control.
+    ClearModuleLevels = 5,
+
+    /// Registers a sink for log lines. The four arguments are the sink's SID. There can only be
+    /// one sink; the call returns 1 if it was registered, 0 if there already is one, and
+    /// `SINK_REFUSED` if the caller doesn't have control.
+    SinkRegister = 6,
+
+    /// Unregisters the sink. Only the process that registered it can do this.
+    SinkUnregister = 7,
+
+    /// Sends whatever lines are staged to the sink now, returning once they are on its queue
+    SinkFlush = 8,
+
+    /// Selects how records are written out: `FORMAT_TEXT` or `FORMAT_JSON_LINES` in arg1.
+    /// Returns 1 if it was set, 0 if the caller doesn't have control.
+    SetOutputFormat = 9,
+
+    /// Claims control of logging: the module levels, the output format and the sink. Only the
+    /// processes that claim it before xous-names' trusted init is done get it. Returns 1 if the
+    /// caller has control, 0 if not.
+    ClaimControl = 10,
+
     /// A panic occurred, and a panic log is forthcoming
     PanicStarted = 1000,
 
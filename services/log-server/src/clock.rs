@@ -0,0 +1,151 @@
+//! The time records are stamped with.
+//!
+//! It comes from the ticktimer, but the log server can never wait on the ticktimer: the ticktimer
+//! logs through us, so it may be stuck waiting on us, and it's parked for the last part of a
+//! suspend while susres and others are still logging. So a thread of our own asks it the time,
+//! and records are stamped with the latest answer. The thread is asked as each record comes in,
+//! and given a few slices to answer, so the time is normally fresh; if the ticktimer's busy, the
+//! record gets the last time heard instead.
+
+use core::sync::atomic::{fence, AtomicBool, AtomicU32, Ordering};
+
+/// How many slices a record waits for a fresh time, before making do with the last one
+pub(crate) const FRESH_TIME_YIELDS: usize = 4;
+
+pub(crate) struct Clock {
+    /// odd while a time is being written; goes up by two for each one
+    seq: AtomicU32,
+    lo: AtomicU32,
+    hi: AtomicU32,
+    /// the time has been asked for, and not yet heard
+    pending: AtomicBool,
+}
+
+impl Clock {
+    pub const fn new() -> Clock {
+        Clock {
+            seq: AtomicU32::new(0),
+            lo: AtomicU32::new(0),
+            hi: AtomicU32::new(0),
+            pending: AtomicBool::new(false),
+        }
+    }
+
+    /// Called by the thread that asks the ticktimer, with its answer. There's only ever the one
+    /// thread doing this.
+    pub fn publish(&self, ms: u64) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.lo.store(ms as u32, Ordering::Relaxed);
+        self.hi.store((ms >> 32) as u32, Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+        self.pending.store(false, Ordering::Release);
+    }
+
+    /// Called by the clock thread when the ticktimer couldn't be asked
+    pub fn give_up(&self) {
+        self.pending.store(false, Ordering::Release);
+    }
+
+    /// The last time heard, if there's been one
+    pub fn latest(&self, yield_now: &mut dyn FnMut()) -> Option<u64> {
+        loop {
+            let before = self.seq.load(Ordering::Acquire);
+            if before == 0 {
+                return None;
+            }
+            if before & 1 == 0 {
+                let ms = self.lo.load(Ordering::Relaxed) as u64 | (self.hi.load(Ordering::Relaxed) as u64) << 32;
+                fence(Ordering::Acquire);
+                if self.seq.load(Ordering::Relaxed) == before {
+                    return Some(ms);
+                }
+            }
+            // the clock thread is partway through writing it
+            yield_now();
+        }
+    }
+
+    /// The time for a record that just came in. `ask` wakes the clock thread, returning false if
+    /// it couldn't be reached; it's only called when the thread isn't already busy asking.
+    pub fn stamp(&self, ask: &mut dyn FnMut() -> bool, yield_now: &mut dyn FnMut()) -> Option<u64> {
+        let seen = self.seq.load(Ordering::Acquire) & !1;
+        if !self.pending.swap(true, Ordering::AcqRel) && !ask() {
+            self.pending.store(false, Ordering::Release);
+        }
+        for _ in 0..FRESH_TIME_YIELDS {
+            if self.seq.load(Ordering::Acquire) != seen {
+                break;
+            }
+            yield_now();
+        }
+        self.latest(yield_now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+
+    #[test]
+    fn fresh_when_answered() {
+        let clock = Clock::new();
+        assert_eq!(clock.latest(&mut || ()), None);
+        // the "ticktimer" answers within the first yield
+        let mut now = 0x1_0000_0005u64;
+        let mut asked = 0;
+        let stamp = clock.stamp(&mut || { asked += 1; true }, &mut || clock.publish(now));
+        assert_eq!((stamp, asked), (Some(0x1_0000_0005), 1));
+        now += 10;
+        assert_eq!(clock.stamp(&mut || true, &mut || clock.publish(now)), Some(0x1_0000_000f));
+    }
+
+    #[test]
+    fn logging_goes_on_while_the_ticktimer_is_blocked() {
+        let clock = Arc::new(Clock::new());
+        clock.publish(1000);
+        // the clock thread: each request is answered only once the "ticktimer" gets unblocked
+        let (request_tx, request_rx) = channel::<()>();
+        let (unblock_tx, unblock_rx) = channel::<u64>();
+        let thread_clock = clock.clone();
+        let thread = std::thread::spawn(move || {
+            while request_rx.recv().is_ok() {
+                match unblock_rx.recv() {
+                    Ok(ms) => thread_clock.publish(ms),
+                    Err(_) => break,
+                }
+            }
+        });
+        let mut asked = 0;
+        let mut yields = 0;
+        for _ in 0..100 {
+            let stamp = clock.stamp(&mut || { asked += 1; request_tx.send(()).is_ok() }, &mut || yields += 1);
+            assert_eq!(stamp, Some(1000));
+        }
+        // only one request is outstanding at a time, and each record waits a bounded while
+        assert_eq!(asked, 1);
+        assert_eq!(yields, 100 * FRESH_TIME_YIELDS);
+
+        unblock_tx.send(5000).unwrap();
+        while clock.pending.load(Ordering::Acquire) {
+            std::thread::yield_now();
+        }
+        assert_eq!(clock.latest(&mut || ()), Some(5000));
+        drop(request_tx);
+        drop(unblock_tx);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn unreachable_clock_thread() {
+        let clock = Clock::new();
+        assert_eq!(clock.stamp(&mut || false, &mut || ()), None);
+        // not left looking busy, so the next record asks again
+        let mut asked = false;
+        clock.stamp(&mut || { asked = true; true }, &mut || ());
+        assert!(asked);
+    }
+}
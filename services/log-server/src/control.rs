@@ -0,0 +1,82 @@
+//! Who may change how the log server logs: its module levels, its output format and its sink.
+//!
+//! The log server's address is well known, so anything can send it these. The processes that
+//! may are the ones that claimed control while the boot set was starting, the same processes
+//! xous-names trusts. Once xous-names says trusted init is done, no other process can claim it.
+//! The log server can't wait on xous-names, which logs through us, so a thread of our own asks
+//! it and `CLOSED` is set when the answer is yes.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by the thread that asks xous-names, once trusted init is done
+pub(crate) static CLOSED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Default)]
+pub(crate) struct Control {
+    /// PIDs that claimed control before it closed
+    trusted: Vec<u8>,
+    closed: bool,
+}
+
+impl Control {
+    pub fn new() -> Self {
+        Control { trusted: Vec::new(), closed: false }
+    }
+
+    /// Stops any process that hasn't claimed control yet from doing so
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    /// Whether `pid` may control logging. Until control closes, any process may, and is
+    /// remembered as one that can.
+    pub fn allows(&mut self, pid: Option<u8>) -> bool {
+        let pid = match pid {
+            Some(pid) => pid,
+            None => return false,
+        };
+        if self.trusted.contains(&pid) {
+            true
+        } else if self.closed {
+            false
+        } else {
+            self.trusted.push(pid);
+            true
+        }
+    }
+
+    /// As `allows()`, first closing control if xous-names has said trusted init is done
+    pub fn check(&mut self, pid: Option<u8>) -> bool {
+        if CLOSED.load(Ordering::Acquire) {
+            self.close();
+        }
+        self.allows(pid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_set_keeps_control() {
+        let mut control = Control::new();
+        assert!(control.allows(Some(5)));
+        assert!(control.allows(Some(7)));
+        control.close();
+        assert!(control.allows(Some(5)));
+        assert!(control.allows(Some(7)));
+    }
+
+    #[test]
+    fn late_claims_are_refused() {
+        let mut control = Control::new();
+        assert!(control.allows(Some(5)));
+        control.close();
+        assert!(!control.allows(Some(9)));
+        // asking again doesn't get it in either
+        assert!(!control.allows(Some(9)));
+        assert!(!control.allows(None));
+        assert!(control.allows(Some(5)));
+    }
+}
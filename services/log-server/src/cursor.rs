@@ -15,6 +15,11 @@ impl<'a> BufferWrapper<'a> {
     pub fn len(&self) -> usize {
         self.offset
     }
+
+    /// Drops everything written after the first `len` bytes
+    pub fn truncate(&mut self, len: usize) {
+        self.offset = self.offset.min(len);
+    }
 }
 
 impl<'a> core::fmt::Write for BufferWrapper<'a> {
@@ -0,0 +1,91 @@
+//! Per-module level filters. Levels are the `log::Level` discriminants carried in a `LogRecord`
+//! (1 = error through 5 = trace), with 0 meaning off.
+//!
+//! These can only quiet things down: a record the sending process filtered out never gets here.
+
+/// Stand-in for a module's level when no filter applies
+pub const LEVEL_ALL: u32 = 5;
+
+#[derive(Debug, Default)]
+pub struct ModuleFilters {
+    /// (module path, most verbose level let through), most specific first
+    rules: Vec<(String, u32)>,
+}
+
+impl ModuleFilters {
+    pub fn new() -> Self {
+        ModuleFilters { rules: Vec::new() }
+    }
+
+    /// Sets the level for `module` and everything under it, or removes its rule if `level` is
+    /// `None`. An empty module, or `*`, sets the level for everything without a rule of its own.
+    pub fn set(&mut self, module: &str, level: Option<u32>) {
+        let module = if module == "*" { "" } else { module.trim_end_matches("::") };
+        self.rules.retain(|(m, _)| m != module);
+        if let Some(level) = level {
+            self.rules.push((module.to_string(), level.min(LEVEL_ALL)));
+            // longest path first, so the first match is the most specific
+            self.rules.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.rules.clear();
+    }
+
+    /// The most verbose level let through for `module`
+    pub fn level_for(&self, module: &str) -> u32 {
+        self.rules.iter()
+            .find(|(m, _)| m.is_empty() || module == m || (module.starts_with(m.as_str()) && module[m.len()..].starts_with("::")))
+            .map(|&(_, level)| level)
+            .unwrap_or(LEVEL_ALL)
+    }
+
+    pub fn allows(&self, module: &str, level: u32) -> bool {
+        level != 0 && level <= self.level_for(module)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_specific_rule_wins() {
+        let mut f = ModuleFilters::new();
+        assert!(f.allows("net::wifi", 5));
+        f.set("*", Some(3));
+        f.set("net", Some(1));
+        f.set("net::wifi::", Some(4));
+        assert_eq!(f.level_for("shellchat"), 3);
+        assert_eq!(f.level_for("net"), 1);
+        assert_eq!(f.level_for("net::dhcp"), 1);
+        assert_eq!(f.level_for("net::wifi"), 4);
+        assert_eq!(f.level_for("net::wifi::scan"), 4);
+        // a prefix has to end on a path separator
+        assert_eq!(f.level_for("netmgr"), 3);
+        assert!(f.allows("net::wifi", 4));
+        assert!(!f.allows("net::dhcp", 2));
+        assert!(!f.allows("net", 0));
+    }
+
+    #[test]
+    fn rules_replace_and_clear() {
+        let mut f = ModuleFilters::new();
+        f.set("pddb", Some(0));
+        assert!(!f.allows("pddb::backend", 1));
+        f.set("pddb", Some(2));
+        assert!(f.allows("pddb::backend", 2));
+        f.set("pddb", None);
+        assert!(f.is_empty());
+        assert!(f.allows("pddb", 5));
+        f.set("", Some(9));
+        assert_eq!(f.level_for("any"), LEVEL_ALL);
+        f.clear();
+        assert!(f.is_empty());
+    }
+}
@@ -48,7 +48,7 @@ impl Default for XousLoggerBacking<'_> {
 }
 
 impl XousLoggerBacking<'_> {
-    fn log_impl(&mut self, record: &log::Record) {
+    fn log_impl(&mut self, record: &log::Record, kv: &[(&str, &dyn core::fmt::Display)]) {
         {
             assert!(core::mem::size_of::<api::LogRecord>() < BUFFER_SIZE);
             let log_record = unsafe { &mut *(self.buffer.as_mut_ptr() as *mut api::LogRecord) };
@@ -71,6 +71,17 @@ impl XousLoggerBacking<'_> {
             let mut wrapper = cursor::BufferWrapper::new(&mut log_record.args);
             write!(wrapper, "{}", record.args()).ok(); // truncate if error
             log_record.args_length = wrapper.len() as u32;
+
+            let mut wrapper = cursor::BufferWrapper::new(&mut log_record.kv);
+            for (key, value) in kv.iter() {
+                let start = wrapper.len();
+                // fields that don't fit are left out whole
+                if write!(wrapper, "{}{}{}{}", key, api::KV_SEP as char, value, api::KV_END as char).is_err() {
+                    wrapper.truncate(start);
+                    break;
+                }
+            }
+            log_record.kv_length = wrapper.len() as u32;
         }
 
         self.buffer
@@ -103,7 +114,7 @@ impl log::Log for XousLogger {
         if unsafe { XOUS_LOGGER_BACKING.is_none() } {
             unsafe { XOUS_LOGGER_BACKING = Some(XousLoggerBacking::default()) };
         }
-        unsafe { XOUS_LOGGER_BACKING.as_mut().unwrap().log_impl(record) };
+        unsafe { XOUS_LOGGER_BACKING.as_mut().unwrap().log_impl(record, &[]) };
         self.locked
             .compare_exchange(true, false, Ordering::SeqCst, Ordering::Acquire)
             .expect("LOG: logger became unlocked somehow");
@@ -111,8 +122,95 @@ impl log::Log for XousLogger {
     fn flush(&self) {}
 }
 
+impl XousLogger {
+    fn log_kv(&self, record: &log::Record, kv: &[(&str, &dyn core::fmt::Display)]) {
+        while self
+            .locked
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::Acquire)
+            .is_err()
+        {
+            xous::yield_slice();
+        }
+
+        if unsafe { XOUS_LOGGER_BACKING.is_none() } {
+            unsafe { XOUS_LOGGER_BACKING = Some(XousLoggerBacking::default()) };
+        }
+        unsafe { XOUS_LOGGER_BACKING.as_mut().unwrap().log_impl(record, kv) };
+        self.locked
+            .compare_exchange(true, false, Ordering::SeqCst, Ordering::Acquire)
+            .expect("LOG: logger became unlocked somehow");
+    }
+}
+
+/// Logs a record with key/value fields, which go out as `key=value` in text, or as a `kv`
+/// object in JSON lines. Use it through `log_kv!`.
+pub fn log_kv(level: log::Level, module: &'static str, file: &'static str, line: u32,
+    args: core::fmt::Arguments, kv: &[(&str, &dyn core::fmt::Display)]) {
+    if level > log::max_level() {
+        return;
+    }
+    XOUS_LOGGER.log_kv(&log::Record::builder()
+        .level(level)
+        .target(module)
+        .module_path_static(Some(module))
+        .file_static(Some(file))
+        .line(Some(line))
+        .args(args)
+        .build(),
+        kv);
+}
+
+/// `log_kv!(log::Level::Info, "mounted {}", basis; "keys" => count, "dict" => name)`: a log
+/// line carrying key/value fields after the `;`
+#[macro_export]
+macro_rules! log_kv {
+    ($level:expr, $($arg:expr),+ ; $($key:expr => $value:expr),* $(,)?) => {
+        $crate::log_kv($level, module_path!(), file!(), line!(), format_args!($($arg),+),
+            &[$(($key, &$value as &dyn core::fmt::Display)),*])
+    };
+}
+
+/// The name of the program whose `main.rs` is at `path`, e.g. `services/pddb/src/main.rs` is
+/// `pddb`: the directory holding `src`, or failing that, the file's own name
+fn program_name_from_path(path: &str) -> &str {
+    let mut parts = path.rsplit(|c| c == '/' || c == '\\').filter(|p| !p.is_empty());
+    let file = parts.next().unwrap_or("");
+    let mut prev = file;
+    for part in parts {
+        if prev == "src" {
+            return part;
+        }
+        prev = part;
+    }
+    file.trim_end_matches(".rs")
+}
+
+/// Tells the log server what this process is called, so its records can be told apart.
+/// Hosted processes are named by the loader; otherwise the name comes from where `init()` was
+/// called from.
+fn send_program_name(conn: xous::CID, caller: &core::panic::Location) {
+    #[cfg(not(any(target_os = "none", target_os = "xous")))]
+    let hosted_name = std::env::var("XOUS_PROCESS_NAME").ok();
+    #[cfg(any(target_os = "none", target_os = "xous"))]
+    let hosted_name: Option<&str> = None;
+    let name = match hosted_name.as_ref() {
+        Some(name) => name.as_ref(),
+        None => program_name_from_path(caller.file()),
+    };
+    let mut buffer = Buffer::new(core::mem::size_of::<api::ProgramName>());
+    {
+        let pn = unsafe { &mut *(buffer.as_mut_ptr() as *mut api::ProgramName) };
+        let len = name.len().min(pn.name.len());
+        pn.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        pn.name_length = len as u32;
+    }
+    buffer.lend(conn, api::Opcode::ProgramName.to_u32().unwrap()).ok();
+}
+
+#[track_caller]
 pub fn init() -> Result<(), LogError> {
     if let Ok(backing) = XousLoggerBacking::new() {
+        send_program_name(backing.conn, core::panic::Location::caller());
         unsafe {
             XOUS_LOGGER_BACKING = Some(backing);
         }
@@ -124,9 +222,12 @@ pub fn init() -> Result<(), LogError> {
     }
 }
 
+#[track_caller]
 pub fn init_wait() -> Result<(), log::SetLoggerError> {
+    let caller = core::panic::Location::caller();
     loop {
         if let Ok(backing) = XousLoggerBacking::new() {
+            send_program_name(backing.conn, caller);
             unsafe {
                 XOUS_LOGGER_BACKING = Some(backing);
                 break;
@@ -142,3 +243,98 @@ pub fn init_wait() -> Result<(), log::SetLoggerError> {
 pub fn resume() {
     unsafe { XOUS_LOGGER_BACKING.as_mut().unwrap().resume() };
 }
+
+fn server_conn() -> Result<xous::CID, xous::Error> {
+    xous::connect(xous::SID::from_bytes(b"xous-log-server ").unwrap())
+}
+
+/// Claims control of logging, so this process can later call `set_module_level()`,
+/// `clear_module_levels()`, `set_output_format()` and `register_sink()`. Only processes that
+/// claim it while the boot set is starting, before xous-names' trusted init is done, get it;
+/// returns `false` if this one is too late.
+pub fn claim_control() -> Result<bool, xous::Error> {
+    match xous::send_message(server_conn()?,
+        xous::Message::new_blocking_scalar(api::Opcode::ClaimControl.to_usize().unwrap(), 0, 0, 0, 0)
+    )? {
+        xous::Result::Scalar1(granted) => Ok(granted != 0),
+        _ => Err(xous::Error::InternalError),
+    }
+}
+
+/// Limits what gets logged from `module` and everything under it, e.g. `net` or `pddb::backend`,
+/// to `level`. `None` removes the limit. This can only quiet a module down: a record that its
+/// process' own `log::max_level()` filters out never reaches the log server.
+///
+/// Fails with `AccessDenied` if this process doesn't have control; see `claim_control()`.
+pub fn set_module_level(module: &str, level: Option<log::LevelFilter>) -> Result<(), xous::Error> {
+    let conn = server_conn()?;
+    let mut buffer = Buffer::new(core::mem::size_of::<api::ModuleLevel>());
+    {
+        let ml = unsafe { &mut *(buffer.as_mut_ptr() as *mut api::ModuleLevel) };
+        let module = module.as_bytes();
+        ml.module_length = module.len().min(ml.module.len()) as u32;
+        for (dest, src) in ml.module.iter_mut().zip(module) {
+            *dest = *src;
+        }
+        ml.level = level.map(|l| l as u32).unwrap_or(api::LEVEL_DEFAULT);
+        ml.accepted = 0;
+    }
+    buffer.lend_mut(conn, api::Opcode::SetModuleLevel.to_u32().unwrap())?;
+    let ml = unsafe { &*(buffer.as_ptr() as *const api::ModuleLevel) };
+    if ml.accepted == 1 {
+        Ok(())
+    } else {
+        Err(xous::Error::AccessDenied)
+    }
+}
+
+/// Sends a control request that returns 1 if it was carried out
+fn control_scalar(opcode: api::Opcode, arg: usize) -> Result<(), xous::Error> {
+    match xous::send_message(server_conn()?,
+        xous::Message::new_blocking_scalar(opcode.to_usize().unwrap(), arg, 0, 0, 0)
+    )? {
+        xous::Result::Scalar1(1) => Ok(()),
+        xous::Result::Scalar1(_) => Err(xous::Error::AccessDenied),
+        _ => Err(xous::Error::InternalError),
+    }
+}
+
+/// Removes all the limits set by `set_module_level()`. Fails with `AccessDenied` if this
+/// process doesn't have control.
+pub fn clear_module_levels() -> Result<(), xous::Error> {
+    control_scalar(api::Opcode::ClearModuleLevels, 0)
+}
+
+/// Asks for every log line and panic to be copied to `sid` as well, in messages with the IDs
+/// `api::SINK_BATCH` and `api::SINK_PANIC`. Returns `false` if another process already has,
+/// and fails with `AccessDenied` if this process doesn't have control.
+pub fn register_sink(sid: xous::SID) -> Result<bool, xous::Error> {
+    let s = sid.to_u32();
+    match xous::send_message(server_conn()?,
+        xous::Message::new_blocking_scalar(api::Opcode::SinkRegister.to_usize().unwrap(),
+            s.0 as usize, s.1 as usize, s.2 as usize, s.3 as usize)
+    )? {
+        xous::Result::Scalar1(api::SINK_REFUSED) => Err(xous::Error::AccessDenied),
+        xous::Result::Scalar1(registered) => Ok(registered != 0),
+        _ => Err(xous::Error::InternalError),
+    }
+}
+
+pub fn unregister_sink() -> Result<(), xous::Error> {
+    xous::send_message(server_conn()?,
+        xous::Message::new_blocking_scalar(api::Opcode::SinkUnregister.to_usize().unwrap(), 0, 0, 0, 0)
+    ).map(|_| ())
+}
+
+/// Sends the sink whatever lines are waiting for a full batch
+pub fn flush_sink() -> Result<(), xous::Error> {
+    xous::send_message(server_conn()?,
+        xous::Message::new_blocking_scalar(api::Opcode::SinkFlush.to_usize().unwrap(), 0, 0, 0, 0)
+    ).map(|_| ())
+}
+
+/// Writes records out as text or as JSON lines, e.g. `api::FORMAT_JSON_LINES` so host tools
+/// can pick the log apart. Fails with `AccessDenied` if this process doesn't have control.
+pub fn set_output_format(format: usize) -> Result<(), xous::Error> {
+    control_scalar(api::Opcode::SetOutputFormat, format)
+}
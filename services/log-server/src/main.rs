@@ -3,6 +3,10 @@
 
 mod api;
 use api::*;
+mod clock;
+mod control;
+mod filter;
+mod record;
 
 #[cfg(any(target_os = "none", target_os = "xous"))]
 #[macro_use]
@@ -247,14 +251,130 @@ mod implementation {
     }
 }
 
+/// Where log lines go besides the console, e.g. to be kept in the PDDB
+struct Sink {
+    cid: xous::CID,
+    pid: xous::PID,
+    staged: Vec<u8>,
+    /// batches lost because the sink was backed up
+    dropped: u32,
+}
+
+/// A batch is one page, moved to the sink
+const SINK_BATCH_SIZE: usize = 4096;
+/// How much gets staged before it's sent, leaving room for a note about lost batches
+const SINK_STAGE_LIMIT: usize = SINK_BATCH_SIZE - 256;
+/// The most of one panic that gets kept for the sink
+const PANIC_TEXT_LIMIT: usize = 3072;
+
+impl Sink {
+    fn stage(&mut self, line: &[u8]) {
+        if self.staged.len() + line.len() > SINK_STAGE_LIMIT {
+            self.flush(SINK_BATCH);
+        }
+        self.staged.extend_from_slice(&line[..line.len().min(SINK_STAGE_LIMIT)]);
+    }
+
+    /// Moves the staged lines to the sink. This never blocks: the sink logs through us too, so
+    /// waiting on it could deadlock. If it's backed up, the lines are lost, and it's told so later.
+    fn flush(&mut self, id: usize) {
+        if self.staged.is_empty() {
+            return;
+        }
+        let mut mem = match xous::map_memory(None, None, SINK_BATCH_SIZE, xous::MemoryFlags::R | xous::MemoryFlags::W) {
+            Ok(mem) => mem,
+            Err(_) => {
+                self.staged.clear();
+                self.dropped += 1;
+                return;
+            }
+        };
+        let mut len = 0;
+        {
+            let page = mem.as_slice_mut::<u8>();
+            if self.dropped > 0 {
+                let mut note = String::new();
+                write!(note, "LOG: {} batches of log lines were lost\n", self.dropped).ok();
+                page[..note.len()].copy_from_slice(note.as_bytes());
+                len = note.len();
+            }
+            let n = self.staged.len().min(page.len() - len);
+            page[len..len + n].copy_from_slice(&self.staged[..n]);
+            len += n;
+        }
+        self.staged.clear();
+        let msg = xous::MemoryMessage {
+            id,
+            buf: mem,
+            offset: None,
+            valid: xous::MemorySize::new(len),
+        };
+        match xous::try_send_message(self.cid, xous::Message::Move(msg)) {
+            Ok(_) => self.dropped = 0,
+            Err(_) => {
+                xous::unmap_memory(mem).ok();
+                self.dropped += 1;
+            }
+        }
+    }
+}
+
+/// What the log server keeps between messages
+struct State {
+    filters: filter::ModuleFilters,
+    sink: Option<Sink>,
+    /// panics being reported, by process
+    panics: Vec<(xous::PID, Vec<u8>)>,
+    /// what each process is called, once it has said
+    names: Vec<(xous::PID, String)>,
+    format: record::Format,
+    /// who may change the filters, the format and the sink
+    control: control::Control,
+    /// wakes the clock thread
+    clock_conn: xous::CID,
+}
+
+impl State {
+    /// Whether `pid` may control logging, noting it on the console if not
+    fn may_control(&mut self, output: &mut implementation::OutputWriter, pid: Option<xous::PID>, what: &str) -> bool {
+        let allowed = self.control.check(pid.map(|p| p.get()));
+        if !allowed {
+            writeln!(output, "LOG: PID {} may not {}", pid.map(|p| p.get()).unwrap_or_default(), what).unwrap();
+        }
+        allowed
+    }
+
+    fn panic_text(&mut self, pid: xous::PID) -> &mut Vec<u8> {
+        let pos = match self.panics.iter().position(|(p, _)| *p == pid) {
+            Some(pos) => pos,
+            None => {
+                self.panics.push((pid, Vec::new()));
+                self.panics.len() - 1
+            }
+        };
+        &mut self.panics[pos].1
+    }
+}
+
 fn handle_scalar(
     output: &mut implementation::OutputWriter,
+    state: &mut State,
     sender: xous::MessageSender,
     msg: &xous::ScalarMessage,
     sender_pid: xous::PID,
 ) {
     match msg.id {
-        1000 => writeln!(output, "PANIC in PID {}:", sender_pid).unwrap(),
+        1000 => {
+            let header = match state.names.iter().find(|(p, _)| *p == sender_pid) {
+                Some((_, name)) => format!("PANIC in PID {} ({}):", sender_pid, name),
+                None => format!("PANIC in PID {}:", sender_pid),
+            };
+            writeln!(output, "{}", header).unwrap();
+            let text = state.panic_text(sender_pid);
+            text.clear();
+            text.extend_from_slice(header.as_bytes());
+            text.push(b'\n');
+        }
         1100 => (),
         1101..=1132 => {
             let mut output_bfr = [0u8; core::mem::size_of::<usize>() * 4];
@@ -275,20 +395,90 @@ fn handle_scalar(
             for (dest, src) in output_iter.zip(input_iter) {
                 *dest = *src;
             }
-            let total_chars = msg.id - 1100;
-            for (idx, c) in output_bfr.iter().enumerate() {
-                if idx >= total_chars {
-                    break;
-                }
+            let total_chars = (msg.id - 1100).min(output_bfr.len());
+            for c in output_bfr[..total_chars].iter() {
                 output.putc(*c);
             }
+            let text = state.panic_text(sender_pid);
+            if text.len() + total_chars <= PANIC_TEXT_LIMIT {
+                text.extend_from_slice(&output_bfr[..total_chars]);
+            }
+        }
+        1200 => {
+            writeln!(output, "Terminating process").unwrap();
+            if let Some(pos) = state.panics.iter().position(|(p, _)| *p == sender_pid) {
+                let (_, mut text) = state.panics.swap_remove(pos);
+                if let Some(sink) = state.sink.as_mut() {
+                    text.extend_from_slice(b"\nTerminating process\n");
+                    // whatever was logged before the panic goes first
+                    sink.flush(SINK_BATCH);
+                    sink.stage(&text);
+                    sink.flush(SINK_PANIC);
+                }
+            }
         }
-        1200 => writeln!(output, "Terminating process").unwrap(),
         2000 => {
             #[cfg(any(target_os = "none", target_os = "xous"))]
             crate::debug::DEFAULT.enable_rx();
             writeln!(output, "Resuming logger").unwrap();
         }
+        5 => {
+            let allowed = state.may_control(output, Some(sender_pid), "clear module levels");
+            if allowed {
+                state.filters.clear();
+                writeln!(output, "LOG: module level filters cleared").unwrap();
+            }
+            xous::return_scalar(sender, allowed as usize).ok();
+        }
+        6 => {
+            let registered = if !state.may_control(output, Some(sender_pid), "register a log sink") {
+                SINK_REFUSED
+            } else if state.sink.is_none() {
+                let sid = xous::SID::from_u32(msg.arg1 as u32, msg.arg2 as u32, msg.arg3 as u32, msg.arg4 as u32);
+                match xous::connect(sid) {
+                    Ok(cid) => {
+                        writeln!(output, "LOG: PID {} registered a log sink", sender_pid).unwrap();
+                        state.sink = Some(Sink { cid, pid: sender_pid, staged: Vec::new(), dropped: 0 });
+                        1
+                    }
+                    Err(e) => {
+                        writeln!(output, "LOG: couldn't connect to the log sink: {:?}", e).unwrap();
+                        0
+                    }
+                }
+            } else {
+                0
+            };
+            xous::return_scalar(sender, registered).ok();
+        }
+        7 => {
+            if state.sink.as_ref().map(|s| s.pid) == Some(sender_pid) {
+                let mut sink = state.sink.take().unwrap();
+                sink.flush(SINK_BATCH);
+                unsafe { xous::disconnect(sink.cid).ok() };
+                writeln!(output, "LOG: log sink unregistered").unwrap();
+            }
+            xous::return_scalar(sender, 0).ok();
+        }
+        8 => {
+            if let Some(sink) = state.sink.as_mut() {
+                sink.flush(SINK_BATCH);
+            }
+            // the batch is queued at the sink before the caller carries on
+            xous::return_scalar(sender, 0).ok();
+        }
+        9 => {
+            let allowed = state.may_control(output, Some(sender_pid), "set the output format");
+            if allowed {
+                state.format = if msg.arg1 == FORMAT_JSON_LINES { record::Format::JsonLines } else { record::Format::Text };
+                writeln!(output, "LOG: writing records as {:?}", state.format).unwrap();
+            }
+            xous::return_scalar(sender, allowed as usize).ok();
+        }
+        10 => {
+            let allowed = state.may_control(output, Some(sender_pid), "control logging");
+            xous::return_scalar(sender, allowed as usize).ok();
+        }
         _ => writeln!(
             output,
             "Unrecognized scalar message from {}: {:#?}",
@@ -298,30 +488,66 @@ fn handle_scalar(
     }
 }
 
+fn level_name(level: u32) -> &'static str {
+    if log::Level::Error as u32 == level {
+        "ERR "
+    } else if log::Level::Warn as u32 == level {
+        "WARN"
+    } else if log::Level::Info as u32 == level {
+        "INFO"
+    } else if log::Level::Debug as u32 == level {
+        "DBG "
+    } else if log::Level::Trace as u32 == level {
+        "TRCE"
+    } else {
+        "UNKNOWN"
+    }
+}
+
+/// A `ModuleLevel`, marked as accepted if the sender may set it
+fn set_module_level(
+    output: &mut implementation::OutputWriter,
+    state: &mut State,
+    sender: xous::MessageSender,
+    mem: &mut xous::MemoryMessage,
+) {
+    if !state.may_control(output, sender.pid(), "set module levels") {
+        return;
+    }
+    let mut buffer = unsafe { xous_ipc::Buffer::from_memory_message_mut(mem) };
+    let ml = unsafe { &mut *(buffer.as_mut_ptr() as *mut ModuleLevel) };
+    if ml.module_length as usize > ml.module.len() {
+        return;
+    }
+    ml.accepted = 1;
+    let module = String::from_utf8_lossy(&ml.module[..ml.module_length as usize]);
+    if ml.level == LEVEL_DEFAULT {
+        state.filters.set(&module, None);
+        writeln!(output, "LOG: {} back to the default level", module).unwrap();
+    } else {
+        state.filters.set(&module, Some(ml.level));
+        writeln!(output, "LOG: {} logging up to {}", module, level_name(ml.level)).unwrap();
+    }
+}
+
 fn handle_opcode(
     output: &mut implementation::OutputWriter,
+    state: &mut State,
     sender: xous::MessageSender,
     opcode: api::Opcode,
-    message: &xous::Message,
+    message: &mut xous::Message,
 ) {
+    if opcode == api::Opcode::SetModuleLevel {
+        if let Some(mem) = message.memory_message_mut() {
+            set_module_level(output, state, sender, mem);
+        }
+        return;
+    }
     if let Some(mem) = message.memory_message() {
         match opcode {
             api::Opcode::LogRecord => {
                 let buffer = unsafe { xous_ipc::Buffer::from_memory_message(mem) };
                 let lr = unsafe { &*(buffer.as_ptr() as *const LogRecord) };
-                let level = if log::Level::Error as u32 == lr.level {
-                    "ERR "
-                } else if log::Level::Warn as u32 == lr.level {
-                    "WARN"
-                } else if log::Level::Info as u32 == lr.level {
-                    "INFO"
-                } else if log::Level::Debug as u32 == lr.level {
-                    "DBG "
-                } else if log::Level::Trace as u32 == lr.level {
-                    "TRCE"
-                } else {
-                    "UNKNOWN"
-                };
                 if lr.file_length as usize >= lr.file.len() {
                     return;
                 }
@@ -332,29 +558,51 @@ fn handle_opcode(
                     return;
                 }
 
-                let file_slice = &lr.file[0..lr.file_length as usize];
-
-                let args_slice = &lr.args[0..lr.args_length as usize];
-
-                let module_slice = &lr.module[0..lr.module_length as usize];
-
-                write!(output, "{}:", level).ok();
-                for c in module_slice {
-                    output.putc(*c);
+                if lr.kv_length as usize > lr.kv.len() {
+                    return;
                 }
-                write!(output, ": ").ok();
-                for c in args_slice {
-                    output.putc(*c);
+
+                let file = String::from_utf8_lossy(&lr.file[0..lr.file_length as usize]);
+                let args = String::from_utf8_lossy(&lr.args[0..lr.args_length as usize]);
+                let module = String::from_utf8_lossy(&lr.module[0..lr.module_length as usize]);
+                let kv = String::from_utf8_lossy(&lr.kv[0..lr.kv_length as usize]);
+                if !state.filters.allows(&module, lr.level) {
+                    return;
                 }
 
-                write!(output, " (").ok();
-                for c in file_slice {
-                    output.putc(*c);
+                let pid = sender.pid();
+                let clock_conn = state.clock_conn;
+                let timestamp_ms = CLOCK.stamp(
+                    &mut || xous::try_send_message(clock_conn, xous::Message::new_scalar(0, 0, 0, 0, 0)).is_ok(),
+                    &mut || xous::yield_slice(),
+                );
+                let line = record::Record {
+                    level: level_name(lr.level),
+                    pid: pid.map(|p| p.get()),
+                    process: state.names.iter().find(|(p, _)| Some(*p) == pid).map(|(_, name)| name.as_str()),
+                    timestamp_ms,
+                    module: &module,
+                    file: &file,
+                    line: lr.line,
+                    message: &args,
+                    kv: record::decode_kv(&kv),
+                }.format(state.format);
+                write!(output, "{}", line).ok();
+                if let Some(sink) = state.sink.as_mut() {
+                    sink.stage(line.as_bytes());
+                }
+            }
+            api::Opcode::ProgramName => {
+                let buffer = unsafe { xous_ipc::Buffer::from_memory_message(mem) };
+                let pn = unsafe { &*(buffer.as_ptr() as *const ProgramName) };
+                if pn.name_length as usize > pn.name.len() {
+                    return;
                 }
-                if let Some(line) = lr.line {
-                    write!(output, ":{}", line).ok();
+                if let Some(pid) = sender.pid() {
+                    let name = String::from_utf8_lossy(&pn.name[..pn.name_length as usize]).into_owned();
+                    state.names.retain(|(p, _)| *p != pid);
+                    state.names.push((pid, name));
                 }
-                writeln!(output, ")").ok();
             }
             api::Opcode::StandardOutput | api::Opcode::StandardError => {
                 // let mut buffer_start_offset = mem.offset.map(|o| o.get()).unwrap_or(0);
@@ -387,7 +635,62 @@ fn handle_opcode(
         }
     } else if let Some(scalar) = message.scalar_message() {
         // Scalar message
-        handle_scalar(output, sender, scalar, sender.pid().unwrap());
+        handle_scalar(output, state, sender, scalar, sender.pid().unwrap());
+    }
+}
+
+/// The time records are stamped with; see `clock`
+static CLOCK: clock::Clock = clock::Clock::new();
+/// `ElapsedMs` in the ticktimer's API. Its discriminants are fixed, as libstd uses them too.
+const TICKTIMER_ELAPSED_MS: usize = 0;
+
+/// Asks the ticktimer the time, whenever the reader thread pokes it. This is the only place the
+/// log server waits on the ticktimer.
+fn clock_thread(sid0: usize, sid1: usize, sid2: usize, sid3: usize) {
+    let sid = xous::SID::from_u32(sid0 as u32, sid1 as u32, sid2 as u32, sid3 as u32);
+    // connected on first use, as the ticktimer starts after we do
+    let mut ticktimer: Option<xous::CID> = None;
+    loop {
+        xous::receive_message(sid).expect("couldn't receive clock request");
+        if ticktimer.is_none() {
+            ticktimer = xous::try_connect(xous::SID::from_bytes(b"ticktimer-server").unwrap()).ok();
+        }
+        let ms = ticktimer.and_then(|tt| {
+            match xous::send_message(tt, xous::Message::new_blocking_scalar(TICKTIMER_ELAPSED_MS, 0, 0, 0, 0)) {
+                Ok(xous::Result::Scalar2(lo, hi)) => Some(lo as u64 | (hi as u64) << 32),
+                _ => None,
+            }
+        });
+        match ms {
+            Some(ms) => CLOCK.publish(ms),
+            None => CLOCK.give_up(),
+        }
+    }
+}
+
+/// `TrustedInitDone` in xous-names' API
+const XNS_TRUSTED_INIT_DONE: usize = 5;
+/// How often to ask xous-names whether the boot set is done starting
+const CONTROL_POLL_MS: u64 = 500;
+
+/// Asks xous-names until trusted init is done, then closes control of logging to anyone who
+/// hasn't claimed it yet. See `control`.
+fn control_thread() {
+    // connected on first use, as xous-names starts after we do
+    let mut xns: Option<xous::CID> = None;
+    loop {
+        if xns.is_none() {
+            xns = xous::try_connect(xous::SID::from_bytes(b"xous-name-server").unwrap()).ok();
+        }
+        if let Some(conn) = xns {
+            if let Ok(xous::Result::Scalar1(1)) =
+                xous::send_message(conn, xous::Message::new_blocking_scalar(XNS_TRUSTED_INIT_DONE, 0, 0, 0, 0))
+            {
+                control::CLOSED.store(true, core::sync::atomic::Ordering::Release);
+                return;
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(CONTROL_POLL_MS));
     }
 }
 
@@ -398,6 +701,26 @@ fn reader_thread(arg: usize) {
     writeln!(output, "LOG: Server listening on address {:?}", server_addr).unwrap();
 
     println!("LOG: my PID is {}", xous::process::id());
+    let clock_sid = xous::create_server().expect("couldn't create clock server");
+    let clock_conn = xous::connect(clock_sid).expect("couldn't connect to clock server");
+    let s = clock_sid.to_u32();
+    xous::create_thread_4(clock_thread, s.0 as usize, s.1 as usize, s.2 as usize, s.3 as usize)
+        .expect("couldn't start clock thread");
+    xous::create_thread_0(control_thread).expect("couldn't start control thread");
+    let mut state = State {
+        filters: filter::ModuleFilters::new(),
+        sink: None,
+        panics: Vec::new(),
+        names: Vec::new(),
+        format: record::Format::Text,
+        control: control::Control::new(),
+        clock_conn,
+    };
+    // hosted runs can ask for JSON lines from the start, for tools reading the console
+    #[cfg(not(any(target_os = "none", target_os = "xous")))]
+    if std::env::var("XOUS_LOG_FORMAT").map(|f| f == "json").unwrap_or(false) {
+        state.format = record::Format::JsonLines;
+    }
     let mut counter: usize = 0;
     loop {
         if counter.trailing_zeros() >= 12 {
@@ -405,10 +728,10 @@ fn reader_thread(arg: usize) {
         }
         counter += 1;
         // writeln!(output, "LOG: Waiting for an event...").unwrap();
-        let envelope = xous::syscall::receive_message(server_addr).expect("couldn't get address");
+        let mut envelope = xous::syscall::receive_message(server_addr).expect("couldn't get address");
         let sender = envelope.sender;
         if let Some(opcode) = FromPrimitive::from_usize(envelope.body.id()) {
-            handle_opcode(output, sender, opcode, &envelope.body);
+            handle_opcode(output, &mut state, sender, opcode, &mut envelope.body);
         } else {
             writeln!(
                 output,
@@ -0,0 +1,162 @@
+//! Formatting a log record for output, as text or as one JSON object per line.
+
+use crate::api::{KV_END, KV_SEP};
+
+/// The fields encoded in a `LogRecord`, in order. Anything malformed ends the list.
+pub fn decode_kv(kv: &str) -> Vec<(&str, &str)> {
+    kv.split(KV_END as char)
+        .filter(|field| !field.is_empty())
+        .map(|field| field.split_once(KV_SEP as char))
+        .take_while(|pair| pair.is_some())
+        .flatten()
+        .collect()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    JsonLines,
+}
+
+#[derive(Debug)]
+pub struct Record<'a> {
+    pub level: &'a str,
+    pub pid: Option<u8>,
+    pub process: Option<&'a str>,
+    pub timestamp_ms: Option<u64>,
+    pub module: &'a str,
+    pub file: &'a str,
+    pub line: Option<u32>,
+    pub message: &'a str,
+    pub kv: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Record<'a> {
+    /// The record as one line, newline included
+    pub fn format(&self, format: Format) -> String {
+        match format {
+            Format::Text => self.to_text(),
+            Format::JsonLines => self.to_json(),
+        }
+    }
+
+    /// `1.234 pddb[5] INFO:pddb::backend: message key=value (file.rs:12)`
+    pub fn to_text(&self) -> String {
+        let mut s = String::new();
+        if let Some(ms) = self.timestamp_ms {
+            s.push_str(&format!("{}.{:03} ", ms / 1000, ms % 1000));
+        }
+        match (self.process, self.pid) {
+            (Some(name), Some(pid)) => s.push_str(&format!("{}[{}] ", name, pid)),
+            (None, Some(pid)) => s.push_str(&format!("[{}] ", pid)),
+            _ => {}
+        }
+        s.push_str(&format!("{}:{}: {}", self.level.trim_end(), self.module, self.message));
+        for (k, v) in self.kv.iter() {
+            if v.is_empty() || v.contains(|c: char| c.is_whitespace() || c == '"') {
+                s.push_str(&format!(" {}=\"{}\"", k, v.replace('"', "\\\"")));
+            } else {
+                s.push_str(&format!(" {}={}", k, v));
+            }
+        }
+        s.push_str(&format!(" ({}", self.file));
+        if let Some(line) = self.line {
+            s.push_str(&format!(":{}", line));
+        }
+        s.push_str(")\n");
+        s
+    }
+
+    /// `{"ts":1234,"pid":5,"process":"pddb","level":"INFO",...,"kv":{"key":"value"}}`; fields
+    /// that aren't known are left out
+    pub fn to_json(&self) -> String {
+        let mut s = String::from("{");
+        if let Some(ms) = self.timestamp_ms {
+            s.push_str(&format!("\"ts\":{},", ms));
+        }
+        if let Some(pid) = self.pid {
+            s.push_str(&format!("\"pid\":{},", pid));
+        }
+        if let Some(name) = self.process {
+            s.push_str(&format!("\"process\":{},", json_string(name)));
+        }
+        s.push_str(&format!("\"level\":{},\"module\":{},\"file\":{},",
+            json_string(self.level.trim_end()), json_string(self.module), json_string(self.file)));
+        if let Some(line) = self.line {
+            s.push_str(&format!("\"line\":{},", line));
+        }
+        s.push_str(&format!("\"msg\":{}", json_string(self.message)));
+        if !self.kv.is_empty() {
+            s.push_str(",\"kv\":{");
+            for (i, (k, v)) in self.kv.iter().enumerate() {
+                if i > 0 {
+                    s.push(',');
+                }
+                s.push_str(&format!("{}:{}", json_string(k), json_string(v)));
+            }
+            s.push('}');
+        }
+        s.push_str("}\n");
+        s
+    }
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record<'a>(kv: Vec<(&'a str, &'a str)>) -> Record<'a> {
+        Record {
+            level: "INFO",
+            pid: Some(5),
+            process: Some("pddb"),
+            timestamp_ms: Some(12_045),
+            module: "pddb::backend",
+            file: "services/pddb/src/backend.rs",
+            line: Some(42),
+            message: "mounted \"sys\"",
+            kv,
+        }
+    }
+
+    #[test]
+    fn kv_fields() {
+        let encoded = format!("dict{}log.persist{}keys{}8{}", KV_SEP as char, KV_END as char, KV_SEP as char, KV_END as char);
+        assert_eq!(decode_kv(&encoded), vec![("dict", "log.persist"), ("keys", "8")]);
+        assert_eq!(decode_kv(""), vec![]);
+        // a field without a separator ends the list
+        assert_eq!(decode_kv(&format!("a{}1{}b{}", KV_SEP as char, KV_END as char, KV_END as char)), vec![("a", "1")]);
+    }
+
+    #[test]
+    fn text_and_json() {
+        let r = record(vec![("keys", "8"), ("basis", "my basis")]);
+        assert_eq!(r.format(Format::Text),
+            "12.045 pddb[5] INFO:pddb::backend: mounted \"sys\" keys=8 basis=\"my basis\" (services/pddb/src/backend.rs:42)\n");
+        assert_eq!(r.format(Format::JsonLines),
+            "{\"ts\":12045,\"pid\":5,\"process\":\"pddb\",\"level\":\"INFO\",\"module\":\"pddb::backend\",\
+             \"file\":\"services/pddb/src/backend.rs\",\"line\":42,\"msg\":\"mounted \\\"sys\\\"\",\
+             \"kv\":{\"keys\":\"8\",\"basis\":\"my basis\"}}\n");
+        let bare = Record { pid: None, process: None, timestamp_ms: None, line: None, level: "DBG ", ..record(vec![]) };
+        assert_eq!(bare.to_text(), "DBG:pddb::backend: mounted \"sys\" (services/pddb/src/backend.rs)\n");
+        assert_eq!(json_string("a\u{1}\tb"), "\"a\\u0001\\tb\"");
+    }
+}
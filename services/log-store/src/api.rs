@@ -0,0 +1,40 @@
+pub(crate) const SERVER_NAME_LOG_STORE: &str     = "_Persistent log store_";
+
+/// The dict the log is kept in: one key per segment, see `ring::segment_key()`
+pub const LOG_STORE_DICT: &str = "log.persist";
+/// Holds `enabled=1` or `enabled=0`
+pub const LOG_STORE_CONFIG_KEY: &str = "config";
+
+#[allow(dead_code)]
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+pub(crate) enum Opcode {
+    /// lines from the log server; the ID is fixed by `log_server::api::SINK_BATCH`
+    Batch = 0,
+
+    /// a panic from the log server; the ID is fixed by `log_server::api::SINK_PANIC`
+    Panic = 1,
+
+    /// starts or stops keeping the log. The setting is saved.
+    SetEnabled,
+
+    /// deletes the stored log
+    Clear,
+
+    /// if the log is being kept, and how many bytes of it there are
+    Status,
+
+    /// writes out everything logged so far, returning once it's in the PDDB
+    Sync,
+
+    /// internal: the PDDB is mounted
+    Mounted,
+
+    /// internal: time to write out what has been received
+    Pump,
+
+    /// internal: the log server has sent what it had staged; write it all out
+    WriteOut,
+
+    /// exits the server
+    Quit,
+}
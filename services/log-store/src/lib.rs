@@ -0,0 +1,62 @@
+#![cfg_attr(target_os = "none", no_std)]
+
+pub mod api;
+pub use api::*;
+pub mod ring;
+use xous::{CID, send_message, Message};
+use num_traits::ToPrimitive;
+
+/// Controls the persistent log. It's kept in the `LOG_STORE_DICT` dict, and can be read back
+/// from there in order with `ring::parse_segment_key()`.
+#[derive(Debug)]
+pub struct LogStore {
+    conn: CID,
+}
+impl LogStore {
+    pub fn new(xns: &xous_names::XousNames) -> Result<Self, xous::Error> {
+        REFCOUNT.fetch_add(1, Ordering::Relaxed);
+        let conn = xns.request_connection_blocking(api::SERVER_NAME_LOG_STORE).expect("Can't connect to LogStore server");
+        Ok(LogStore {
+            conn
+        })
+    }
+    pub fn set_enabled(&self, enabled: bool) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::SetEnabled.to_usize().unwrap(), enabled as usize, 0, 0, 0)
+        ).map(|_| ())
+    }
+    pub fn clear(&self) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::Clear.to_usize().unwrap(), 0, 0, 0, 0)
+        ).map(|_| ())
+    }
+    /// If the log is being kept, and how many bytes of it there are, stored or about to be
+    pub fn status(&self) -> Result<(bool, usize), xous::Error> {
+        let response = send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::Status.to_usize().unwrap(), 0, 0, 0, 0))?;
+        if let xous::Result::Scalar2(enabled, bytes) = response {
+            Ok((enabled != 0, bytes))
+        } else {
+            log::error!("unexpected return value: {:#?}", response);
+            Err(xous::Error::InternalError)
+        }
+    }
+    /// Returns once everything logged so far is in the PDDB, e.g. before reading it back
+    pub fn sync(&self) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::Sync.to_usize().unwrap(), 0, 0, 0, 0)
+        ).map(|_| ())
+    }
+}
+
+use core::sync::atomic::{AtomicU32, Ordering};
+static REFCOUNT: AtomicU32 = AtomicU32::new(0);
+impl Drop for LogStore {
+    fn drop(&mut self) {
+        // the connection to the server side must be reference counted, so that multiple instances of this object within
+        // a single process do not end up de-allocating the CID on other threads before they go out of scope.
+        if REFCOUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
+            unsafe{xous::disconnect(self.conn).unwrap();}
+        }
+    }
+}
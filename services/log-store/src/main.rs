@@ -0,0 +1,285 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+mod api;
+use api::*;
+mod ring;
+use ring::{Action, LogRing};
+
+use num_traits::{ToPrimitive, FromPrimitive};
+use xous::{msg_blocking_scalar_unpack, msg_scalar_unpack};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use log::info;
+
+/// Segments are filled to this size before the next is started
+const SEGMENT_SIZE: usize = 16 * 1024;
+/// How many segments are kept; beyond this, the oldest is deleted
+const MAX_SEGMENTS: u32 = 8;
+/// Most held in RAM: before the PDDB is mounted, and between writes. Past this the oldest lines go.
+const MAX_PENDING: usize = 32 * 1024;
+/// Written out once this much is waiting...
+const WRITE_THRESHOLD: usize = 8 * 1024;
+/// ...or this often, whichever comes first
+const WRITE_INTERVAL_MS: usize = 30_000;
+
+struct Store {
+    pddb: pddb::Pddb,
+    ring: LogRing,
+    /// the PDDB has been mounted, and `ring` read from it
+    mounted: bool,
+    /// `None` until the setting has been read
+    enabled: Option<bool>,
+    pending: Vec<u8>,
+    /// lines dropped from `pending` since the last write
+    lost: usize,
+}
+impl Store {
+    fn receive(&mut self, data: &[u8]) {
+        if self.enabled == Some(false) {
+            return;
+        }
+        self.pending.extend_from_slice(data);
+        if self.pending.len() > MAX_PENDING {
+            let excess = self.pending.len() - MAX_PENDING;
+            // drop whole lines, so what's kept starts at the beginning of one
+            let cut = self.pending[excess..].iter().position(|&b| b == b'\n').map(|p| excess + p + 1).unwrap_or(self.pending.len());
+            self.lost += self.pending[..cut].iter().filter(|&&b| b == b'\n').count();
+            self.pending.drain(..cut);
+        }
+    }
+
+    /// Reads where the log left off, and whether it's to be kept at all
+    fn mount(&mut self) {
+        self.enabled = Some(match self.pddb.get(LOG_STORE_DICT, LOG_STORE_CONFIG_KEY, None, false, false, None, None::<fn()>) {
+            Ok(mut key) => {
+                let mut text = String::new();
+                key.read_to_string(&mut text).ok();
+                text.lines().any(|l| l.trim() == "enabled=1")
+            }
+            // off until asked for
+            Err(_) => false,
+        });
+        let mut segments: Vec<u32> = self.pddb.list_keys(LOG_STORE_DICT, None)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|k| ring::parse_segment_key(k))
+            .collect();
+        segments.sort();
+        let last_len = match segments.last() {
+            Some(&last) => match self.pddb.get(LOG_STORE_DICT, &ring::segment_key(last), None, false, false, None, None::<fn()>) {
+                Ok(mut key) => key.seek(SeekFrom::End(0)).unwrap_or(0) as usize,
+                Err(_) => 0,
+            },
+            None => 0,
+        };
+        self.ring = LogRing::resume(SEGMENT_SIZE, MAX_SEGMENTS, &segments, last_len);
+        self.mounted = true;
+        log_server::log_kv!(log::Level::Debug, "resumed the stored log"; "segments" => segments.len(), "bytes" => self.ring.bytes_stored());
+        if self.enabled != Some(true) {
+            self.set_enabled(false);
+        } else {
+            self.write_out();
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = Some(enabled);
+        if !enabled {
+            self.pending.clear();
+            self.lost = 0;
+        }
+    }
+
+    fn save_enabled(&mut self, enabled: bool) {
+        // the old setting goes, rather than being overwritten in place, so nothing is left over
+        self.pddb.delete_key(LOG_STORE_DICT, LOG_STORE_CONFIG_KEY, None).ok();
+        match self.pddb.get(LOG_STORE_DICT, LOG_STORE_CONFIG_KEY, None, true, true, Some(64), None::<fn()>) {
+            Ok(mut key) => {
+                key.write_all(format!("enabled={}\n", enabled as u8).as_bytes()).ok();
+                self.pddb.sync().ok();
+            }
+            Err(e) => log::warn!("couldn't save the log setting: {:?}", e),
+        }
+    }
+
+    fn write_out(&mut self) {
+        if !self.mounted || self.enabled != Some(true) || (self.pending.is_empty() && self.lost == 0) {
+            return;
+        }
+        let mut data = if self.lost != 0 {
+            format!("LOG-STORE: {} lines were lost before they could be stored\n", self.lost).into_bytes()
+        } else {
+            Vec::new()
+        };
+        data.append(&mut self.pending);
+        self.lost = 0;
+        for action in self.ring.plan(&data) {
+            match action {
+                Action::Append { segment, start, end } => {
+                    match self.pddb.get(LOG_STORE_DICT, &ring::segment_key(segment), None, true, true, Some(SEGMENT_SIZE), None::<fn()>) {
+                        Ok(mut key) => {
+                            if key.seek(SeekFrom::End(0)).and_then(|_| key.write_all(&data[start..end])).is_err() {
+                                log::debug!("couldn't append to log segment {}", segment);
+                            }
+                        }
+                        Err(e) => log::debug!("couldn't open log segment {}: {:?}", segment, e),
+                    }
+                }
+                Action::Delete(segment) => {
+                    self.pddb.delete_key(LOG_STORE_DICT, &ring::segment_key(segment), None).ok();
+                }
+            }
+        }
+        self.pddb.sync().ok();
+    }
+
+    fn clear(&mut self) {
+        for segment in self.ring.clear() {
+            self.pddb.delete_key(LOG_STORE_DICT, &ring::segment_key(segment), None).ok();
+        }
+        self.pending.clear();
+        self.lost = 0;
+        self.pddb.sync().ok();
+    }
+}
+
+#[xous::xous_main]
+fn xmain() -> ! {
+    log_server::init_wait().unwrap();
+    // anything this logs comes straight back to it, so it keeps quiet
+    log::set_max_level(log::LevelFilter::Info);
+    info!("my PID is {}", xous::process::id());
+
+    let xns = xous_names::XousNames::new().unwrap();
+    let store_sid = xns.register_name(api::SERVER_NAME_LOG_STORE, None).expect("can't register server");
+    let self_cid = xous::connect(store_sid).unwrap();
+
+    // the sink may be registered again long after boot, when persistence is turned back on
+    if !log_server::claim_control().unwrap_or(false) {
+        log::warn!("couldn't claim control of logging; the log won't be kept");
+    }
+    // start collecting right away, so what happens before the PDDB is mounted isn't lost
+    let mut registered = log_server::register_sink(store_sid).unwrap_or(false);
+    if !registered {
+        log::warn!("another process is taking the log; the log won't be kept");
+    }
+
+    std::thread::spawn(move || {
+        let pddb = pddb::Pddb::new();
+        pddb.is_mounted_blocking(None);
+        xous::send_message(self_cid,
+            xous::Message::new_scalar(Opcode::Mounted.to_usize().unwrap(), 0, 0, 0, 0)
+        ).expect("couldn't report the PDDB mount");
+    });
+    std::thread::spawn(move || {
+        let tt = ticktimer_server::Ticktimer::new().unwrap();
+        loop {
+            tt.sleep_ms(WRITE_INTERVAL_MS).unwrap();
+            xous::send_message(self_cid,
+                xous::Message::new_scalar(Opcode::Pump.to_usize().unwrap(), 0, 0, 0, 0)
+            ).expect("couldn't pump the log store");
+        }
+    });
+
+    let mut store = Store {
+        pddb: pddb::Pddb::new(),
+        ring: LogRing::new(SEGMENT_SIZE, MAX_SEGMENTS),
+        mounted: false,
+        enabled: None,
+        pending: Vec::new(),
+        lost: 0,
+    };
+    // callers waiting for everything logged so far to be written out
+    let mut syncs: Vec<xous::MessageSender> = Vec::new();
+    loop {
+        let msg = xous::receive_message(store_sid).unwrap();
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(Opcode::Batch) | Some(Opcode::Panic) => {
+                let panic = msg.body.id() == Opcode::Panic.to_usize().unwrap();
+                if let Some(mem) = msg.body.memory_message() {
+                    let len = mem.valid.map(|v| v.get()).unwrap_or(0).min(mem.buf.len());
+                    let data = unsafe { &mem.buf.as_slice::<u8>()[..len] };
+                    store.receive(data);
+                }
+                if panic || store.pending.len() >= WRITE_THRESHOLD {
+                    store.write_out();
+                }
+            }
+            Some(Opcode::SetEnabled) => msg_blocking_scalar_unpack!(msg, enabled, _, _, _, {
+                let enabled = enabled != 0;
+                if store.mounted {
+                    store.save_enabled(enabled);
+                }
+                if enabled && !registered {
+                    registered = log_server::register_sink(store_sid).unwrap_or(false);
+                } else if !enabled && registered {
+                    log_server::unregister_sink().ok();
+                    registered = false;
+                }
+                store.set_enabled(enabled && registered);
+                xous::return_scalar(msg.sender, registered as usize).expect("couldn't return SetEnabled");
+            }),
+            Some(Opcode::Clear) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                if store.mounted {
+                    store.clear();
+                }
+                xous::return_scalar(msg.sender, 0).expect("couldn't return Clear");
+            }),
+            Some(Opcode::Status) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                xous::return_scalar2(msg.sender,
+                    (store.enabled == Some(true)) as usize,
+                    store.ring.bytes_stored() + store.pending.len(),
+                ).expect("couldn't return Status");
+            }),
+            Some(Opcode::Sync) => {
+                if !msg.body.is_blocking() {
+                    continue;
+                }
+                if registered {
+                    // the log server's staged lines land on the queue ahead of the `WriteOut`
+                    log_server::flush_sink().ok();
+                }
+                syncs.push(msg.sender);
+                xous::send_message(self_cid,
+                    xous::Message::new_scalar(Opcode::WriteOut.to_usize().unwrap(), 0, 0, 0, 0)
+                ).expect("couldn't queue a write");
+            }
+            Some(Opcode::Mounted) => msg_scalar_unpack!(msg, _, _, _, _, {
+                store.mount();
+                if store.enabled != Some(true) && registered {
+                    log_server::unregister_sink().ok();
+                    registered = false;
+                }
+            }),
+            Some(Opcode::Pump) => msg_scalar_unpack!(msg, _, _, _, _, {
+                if registered && store.mounted {
+                    log_server::flush_sink().ok();
+                    xous::send_message(self_cid,
+                        xous::Message::new_scalar(Opcode::WriteOut.to_usize().unwrap(), 0, 0, 0, 0)
+                    ).expect("couldn't queue a write");
+                }
+            }),
+            Some(Opcode::WriteOut) => msg_scalar_unpack!(msg, _, _, _, _, {
+                store.write_out();
+                for sender in syncs.drain(..) {
+                    xous::return_scalar(sender, 0).expect("couldn't return Sync");
+                }
+            }),
+            Some(Opcode::Quit) => {
+                log::warn!("Quit received, goodbye world!");
+                break;
+            },
+            None => {
+                log::error!("couldn't convert opcode: {:?}", msg);
+            }
+        }
+    }
+    // clean up our program
+    if registered {
+        log_server::unregister_sink().ok();
+    }
+    xns.unregister_server(store_sid).unwrap();
+    xous::destroy_server(store_sid).unwrap();
+    xous::terminate_process(0)
+}
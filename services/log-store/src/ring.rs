@@ -0,0 +1,172 @@
+//! The log is kept as numbered segment keys, each filled up to a size before the next is started.
+//! Once there are too many, the oldest is deleted, so the log never takes more than about
+//! `max_segments * segment_size` bytes.
+
+/// The PDDB key for segment `n`. Zero padded, so a listing sorts oldest first.
+pub fn segment_key(n: u32) -> String {
+    format!("seg.{:08x}", n)
+}
+
+pub fn parse_segment_key(key: &str) -> Option<u32> {
+    let hex = key.strip_prefix("seg.")?;
+    if hex.len() != 8 {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    /// append `data[start..end]` to segment `n`, creating it if need be
+    Append { segment: u32, start: usize, end: usize },
+    Delete(u32),
+}
+
+#[derive(Debug)]
+pub struct LogRing {
+    segment_size: usize,
+    max_segments: u32,
+    /// the oldest segment, and the one being filled
+    first: u32,
+    last: u32,
+    /// bytes in the last segment
+    fill: usize,
+    /// there are no segments at all
+    empty: bool,
+}
+
+impl LogRing {
+    pub fn new(segment_size: usize, max_segments: u32) -> Self {
+        LogRing { segment_size, max_segments: max_segments.max(1), first: 0, last: 0, fill: 0, empty: true }
+    }
+
+    /// Picks up where a previous boot left off, from the segments found and the size of the newest
+    pub fn resume(segment_size: usize, max_segments: u32, segments: &[u32], last_len: usize) -> Self {
+        let mut ring = LogRing::new(segment_size, max_segments);
+        if let (Some(&first), Some(&last)) = (segments.iter().min(), segments.iter().max()) {
+            ring.first = first;
+            ring.last = last;
+            ring.fill = last_len;
+            ring.empty = false;
+        }
+        ring
+    }
+
+    /// The segments holding the log, oldest first
+    pub fn segments(&self) -> impl Iterator<Item = u32> {
+        let (first, last, empty) = (self.first, self.last, self.empty);
+        (0..=last.wrapping_sub(first)).filter(move |_| !empty).map(move |i| first.wrapping_add(i))
+    }
+
+    pub fn bytes_stored(&self) -> usize {
+        if self.empty {
+            0
+        } else {
+            self.last.wrapping_sub(self.first) as usize * self.segment_size + self.fill
+        }
+    }
+
+    /// What to do to store `data`. Segments end on a line boundary where there is one, so a
+    /// segment read on its own starts with a whole line.
+    pub fn plan(&mut self, data: &[u8]) -> Vec<Action> {
+        let mut actions = Vec::new();
+        let mut start = 0;
+        if self.empty && !data.is_empty() {
+            self.empty = false;
+            self.fill = 0;
+        }
+        while start < data.len() {
+            let room = self.segment_size.saturating_sub(self.fill);
+            let rest = &data[start..];
+            let take = if rest.len() <= room {
+                rest.len()
+            } else {
+                match rest[..room].iter().rposition(|&b| b == b'\n') {
+                    Some(nl) => nl + 1,
+                    // a fresh segment takes as much as fits, even mid-line
+                    None if self.fill == 0 => room,
+                    None => 0,
+                }
+            };
+            if take > 0 {
+                actions.push(Action::Append { segment: self.last, start, end: start + take });
+                self.fill += take;
+                start += take;
+            }
+            if start < data.len() {
+                self.last = self.last.wrapping_add(1);
+                self.fill = 0;
+                while self.last.wrapping_sub(self.first) >= self.max_segments {
+                    actions.push(Action::Delete(self.first));
+                    self.first = self.first.wrapping_add(1);
+                }
+            }
+        }
+        actions
+    }
+
+    /// Forgets everything, returning the segments to delete
+    pub fn clear(&mut self) -> Vec<u32> {
+        let segments = self.segments().collect();
+        self.first = self.last.wrapping_add(1);
+        self.last = self.first;
+        self.fill = 0;
+        self.empty = true;
+        segments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        assert_eq!(segment_key(0x1f), "seg.0000001f");
+        assert_eq!(parse_segment_key("seg.0000001f"), Some(0x1f));
+        assert_eq!(parse_segment_key("seg.1f"), None);
+        assert_eq!(parse_segment_key("config"), None);
+    }
+
+    #[test]
+    fn fills_on_line_boundaries_and_wraps() {
+        let mut ring = LogRing::new(16, 3);
+        assert_eq!(ring.segments().count(), 0);
+        assert_eq!(ring.plan(b"0123456789\n"), vec![Action::Append { segment: 0, start: 0, end: 11 }]);
+        // the next line doesn't fit, so it starts a new segment
+        assert_eq!(ring.plan(b"abcdefgh\n"), vec![Action::Append { segment: 1, start: 0, end: 9 }]);
+        assert_eq!(ring.bytes_stored(), 16 + 9);
+        // two lines: the first fits, the second goes on
+        assert_eq!(ring.plan(b"xyz\nABCDEFGHIJ\n"), vec![
+            Action::Append { segment: 1, start: 0, end: 4 },
+            Action::Append { segment: 2, start: 4, end: 15 },
+        ]);
+        // a fourth segment pushes out the first
+        assert_eq!(ring.plan(b"0123456789\n"), vec![
+            Action::Delete(0),
+            Action::Append { segment: 3, start: 0, end: 11 },
+        ]);
+        assert_eq!(ring.segments().collect::<Vec<_>>(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn long_lines_and_resume() {
+        let mut ring = LogRing::new(8, 4);
+        let line = b"a line much longer than a segment\n";
+        let actions = ring.plan(line);
+        let appended: usize = actions.iter().map(|a| match a {
+            Action::Append { start, end, .. } => end - start,
+            Action::Delete(_) => 0,
+        }).sum();
+        assert_eq!(appended, line.len());
+        assert_eq!(ring.segments().count(), 4);
+        assert_eq!(ring.bytes_stored(), 3 * 8 + 2);
+
+        let mut again = LogRing::resume(8, 4, &[5, 3, 4], 6);
+        assert_eq!(again.segments().collect::<Vec<_>>(), vec![3, 4, 5]);
+        assert_eq!(again.plan(b"x\n"), vec![Action::Append { segment: 5, start: 0, end: 2 }]);
+        assert_eq!(again.clear(), vec![3, 4, 5]);
+        assert_eq!(again.bytes_stored(), 0);
+        assert_eq!(again.plan(b"y\n"), vec![Action::Append { segment: 6, start: 0, end: 2 }]);
+    }
+}
@@ -0,0 +1,95 @@
+pub(crate) const SERVER_NAME_MIXER: &str     = "_Audio Mixer_";
+
+#[allow(dead_code)]
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+pub(crate) enum Opcode {
+    /// opens a stream; returns its handle and the format it was granted
+    OpenStream,
+
+    /// closes a stream, dropping anything still queued on it
+    CloseStream,
+
+    /// queues as many frames as fit on a stream; the rest are handed back
+    WriteFrames,
+
+    /// register a callback for when a stream has room for more frames
+    StreamSubscribe,
+
+    /// sets a stream's volume, in percent
+    SetVolume,
+
+    /// stops mixing in a stream, keeping what is queued on it
+    PauseStream,
+
+    /// starts mixing in a stream again, and asks its client for frames
+    ResumeStream,
+
+    /// drops everything queued on a stream
+    FlushStream,
+
+    /// how many milliseconds of audio are queued on a stream, and if it is playing
+    StreamStatus,
+
+    /// starts or stops keeping what the microphone picks up for a stream
+    CaptureStream,
+
+    /// hands back the microphone frames kept for a stream
+    ReadFrames,
+
+    /// the CODEC wants more frames
+    CodecFrame,
+
+    /// exit the server
+    Quit,
+}
+
+#[derive(Debug, num_derive::FromPrimitive, num_derive::ToPrimitive)]
+pub(crate) enum EventCallback {
+    Event,
+    Drop,
+}
+
+/// How a stream ranks against the others. While a stream that ducks is playing, every stream of
+/// a lower priority is turned down.
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// music and other long-running audio; ducked by anything that ducks
+    Background,
+    /// UI sounds and the like; mixed in as-is, and doesn't duck anything
+    Normal,
+    /// text to speech and voice prompts; ducks lower priorities
+    Speech,
+    /// alarms and other things that must be heard; ducks everything else
+    Alert,
+}
+impl Priority {
+    pub fn ducks_others(&self) -> bool {
+        *self >= Priority::Speech
+    }
+}
+
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub(crate) struct OpenRequest {
+    pub requested: codec::StreamFormat,
+    pub priority: Priority,
+    /// in percent
+    pub volume: u8,
+    /// filled in by the server
+    pub granted: Option<codec::StreamFormat>,
+    pub handle: u32,
+}
+
+/// Frames being written to a stream. Frames that didn't fit are left in `frames`.
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub(crate) struct StreamFrames {
+    pub handle: u32,
+    pub frames: codec::FrameRing,
+}
+
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub(crate) struct ScalarHook {
+    pub sid: (u32, u32, u32, u32),
+    pub id: u32,  // ID of the scalar message to send through (e.g. the discriminant of the Enum on the caller's side API)
+    pub cid: xous::CID,   // caller-side connection ID for the scalar message to route to. Created by the caller before hooking.
+    pub handle: u32,
+}
@@ -0,0 +1,198 @@
+#![cfg_attr(target_os = "none", no_std)]
+
+pub mod api;
+use xous::{CID, send_message, Message};
+use num_traits::{ToPrimitive, FromPrimitive};
+use xous_ipc::Buffer;
+pub use api::*;
+pub use codec::{Channels, FrameRing, SampleFormat, StreamFormat, FIFO_DEPTH};
+
+/// This is a keyword reserved for the "arg4" slot of a scalar callback, where args are numbered 1-4.
+pub const MIXER_CB_ROUTING_ID: usize = 1;
+
+/// Plays audio alongside everything else that is playing. Open a `MixerStream` for each sound
+/// source; the mixer takes care of the CODEC.
+#[derive(Debug)]
+pub struct Mixer {
+    conn: CID,
+}
+impl Mixer {
+    pub fn new(xns: &xous_names::XousNames) -> Result<Self, xous::Error> {
+        REFCOUNT.fetch_add(1, Ordering::Relaxed);
+        let conn = xns.request_connection_blocking(api::SERVER_NAME_MIXER).expect("Can't connect to Mixer server");
+        Ok(Mixer {
+            conn,
+        })
+    }
+    /// Opens a stream in `format`, or the closest format the mixer can take, which is available
+    /// from `MixerStream::format()`. The stream starts out paused: frames written to it are
+    /// queued, and play once it is resumed.
+    pub fn open_stream(&self, format: StreamFormat, priority: Priority, volume: u8) -> Result<MixerStream, xous::Error> {
+        let req = OpenRequest {
+            requested: format,
+            priority,
+            volume,
+            granted: None,
+            handle: 0,
+        };
+        let mut buf = Buffer::into_buf(req).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::OpenStream.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        let req = buf.to_original::<OpenRequest, _>().unwrap();
+        let granted = req.granted.ok_or(xous::Error::OutOfMemory)?; // all the streams are in use
+        REFCOUNT.fetch_add(1, Ordering::Relaxed);
+        Ok(MixerStream {
+            conn: self.conn,
+            handle: req.handle,
+            format: granted,
+            frame_sid: None,
+        })
+    }
+}
+
+/// One source of audio. Closed when dropped.
+#[derive(Debug)]
+pub struct MixerStream {
+    conn: CID,
+    handle: u32,
+    format: StreamFormat,
+    frame_sid: Option<xous::SID>,
+}
+impl MixerStream {
+    /// The format frames have to be written in
+    pub fn format(&self) -> StreamFormat {
+        self.format
+    }
+    /// A `FrameRing` for writing to this stream
+    pub fn frame_ring(&self) -> FrameRing {
+        FrameRing::with_format(self.format)
+    }
+    /// Asks for a scalar message with `id` to be sent to `cid` whenever the stream is playing and
+    /// has room for more frames. arg1 is the number of frames it has room for.
+    pub fn hook_frame_callback(&mut self, id: u32, cid: CID) -> Result<(), xous::Error> {
+        if self.frame_sid.is_none() {
+            let sid = xous::create_server().unwrap();
+            self.frame_sid = Some(sid);
+            let sid_tuple = sid.to_u32();
+            xous::create_thread_4(frame_cb_server, sid_tuple.0 as usize, sid_tuple.1 as usize, sid_tuple.2 as usize, sid_tuple.3 as usize).unwrap();
+            let hookdata = ScalarHook {
+                sid: sid_tuple,
+                id,
+                cid,
+                handle: self.handle,
+            };
+            let buf = Buffer::into_buf(hookdata).or(Err(xous::Error::InternalError))?;
+            buf.lend(self.conn, Opcode::StreamSubscribe.to_u32().unwrap()).map(|_|())
+        } else {
+            Err(xous::Error::MemoryInUse) // can't hook it twice
+        }
+    }
+    /// Queues as many of `frames` as the stream has room for. Whatever doesn't fit is left in
+    /// `frames`, to be written again on the next callback.
+    pub fn write_frames(&mut self, frames: &mut FrameRing) -> Result<(), xous::Error> {
+        let write = StreamFrames {
+            handle: self.handle,
+            frames: *frames,
+        };
+        let mut buf = Buffer::into_buf(write).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::WriteFrames.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        *frames = buf.to_original::<StreamFrames, _>().unwrap().frames;
+        Ok(())
+    }
+    /// `percent` goes from 0 (silent) to 100 (as written)
+    pub fn set_volume(&self, percent: u8) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::SetVolume.to_usize().unwrap(), self.handle as usize, percent as usize, 0, 0)
+        ).map(|_| ())
+    }
+    /// Stops playing, keeping what is queued
+    pub fn pause(&self) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::PauseStream.to_usize().unwrap(), self.handle as usize, 0, 0, 0)
+        ).map(|_| ())
+    }
+    /// Starts playing. If a callback is hooked, it is called right away for the first frames.
+    pub fn resume(&self) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::ResumeStream.to_usize().unwrap(), self.handle as usize, 0, 0, 0)
+        ).map(|_| ())
+    }
+    /// Drops everything queued
+    pub fn flush(&self) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::FlushStream.to_usize().unwrap(), self.handle as usize, 0, 0, 0)
+        ).map(|_| ())
+    }
+    /// Starts or stops keeping what the microphone picks up while the stream plays, to be read
+    /// with `read_frames`. Up to 500ms is kept; past that, the oldest frames are dropped.
+    pub fn set_capture(&self, on: bool) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::CaptureStream.to_usize().unwrap(), self.handle as usize, if on { 1 } else { 0 }, 0, 0)
+        ).map(|_| ())
+    }
+    /// Replaces `frames` with as many microphone frames as have been kept for the stream. They
+    /// are stereo, in the format the CODEC is running in, which is in `frames.format()`.
+    pub fn read_frames(&mut self, frames: &mut FrameRing) -> Result<(), xous::Error> {
+        let read = StreamFrames {
+            handle: self.handle,
+            frames: *frames,
+        };
+        let mut buf = Buffer::into_buf(read).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::ReadFrames.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        *frames = buf.to_original::<StreamFrames, _>().unwrap().frames;
+        Ok(())
+    }
+    /// Milliseconds of audio queued, and if the stream is playing
+    pub fn status(&self) -> Result<(u32, bool), xous::Error> {
+        let response = send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::StreamStatus.to_usize().unwrap(), self.handle as usize, 0, 0, 0))?;
+        if let xous::Result::Scalar2(queued_ms, playing) = response {
+            Ok((queued_ms as u32, playing != 0))
+        } else {
+            log::error!("unexpected return value: {:#?}", response);
+            Err(xous::Error::InternalError)
+        }
+    }
+}
+impl Drop for MixerStream {
+    fn drop(&mut self) {
+        // the server tears down the callback server, if there is one
+        send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::CloseStream.to_usize().unwrap(), self.handle as usize, 0, 0, 0)
+        ).ok();
+        if REFCOUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
+            unsafe{xous::disconnect(self.conn).unwrap();}
+        }
+    }
+}
+
+use core::sync::atomic::{AtomicU32, Ordering};
+static REFCOUNT: AtomicU32 = AtomicU32::new(0);
+impl Drop for Mixer {
+    fn drop(&mut self) {
+        // de-allocate myself. It's unsafe because we are responsible to make sure nobody else is using the connection.
+        if REFCOUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
+            unsafe{xous::disconnect(self.conn).unwrap();}
+        }
+    }
+}
+
+/// handles callback messages that indicate a stream has room for more frames, in the library user's process space.
+fn frame_cb_server(sid0: usize, sid1: usize, sid2: usize, sid3: usize) {
+    let sid = xous::SID::from_u32(sid0 as u32, sid1 as u32, sid2 as u32, sid3 as u32);
+    loop {
+        let msg = xous::receive_message(sid).unwrap();
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(EventCallback::Event) => xous::msg_scalar_unpack!(msg, cid, id, free_frames, _, {
+                // directly pass the scalar message onto the CID with the ID memorized in the original hook
+                send_message(cid as u32,
+                    Message::new_scalar(id, free_frames, 0, 0, MIXER_CB_ROUTING_ID)
+                ).unwrap();
+            }),
+            Some(EventCallback::Drop) => {
+                break; // this exits the loop and kills the thread
+            }
+            None => (),
+        }
+    }
+    xous::destroy_server(sid).unwrap();
+}
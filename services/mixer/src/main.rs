@@ -0,0 +1,274 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+mod api;
+use api::*;
+mod mix;
+use mix::{Mixer, MAX_STREAMS};
+
+use num_traits::{ToPrimitive, FromPrimitive};
+use xous_ipc::Buffer;
+use xous::{CID, msg_scalar_unpack, msg_blocking_scalar_unpack};
+use codec::{Channels, SampleFormat, StreamFormat};
+
+use log::info;
+
+/// Once nothing has been playing for this many frames, the CODEC is paused
+const IDLE_FRAMES: usize = 8;
+
+#[derive(Copy, Clone, Debug)]
+struct ScalarCallback {
+    server_to_cb_cid: CID,
+    cb_to_client_cid: CID,
+    cb_to_client_id: u32,
+}
+
+/// The CODEC, and the format it was last set up to stream in
+struct Output {
+    codec: codec::Codec,
+    format: Option<StreamFormat>,
+    live: bool,
+    idle_frames: usize,
+}
+impl Output {
+    /// Sets up the CODEC for whatever is open on the mixer, and starts it playing. If the streams
+    /// now call for a different rate, the CODEC is set up again, even if it is already playing.
+    fn start(&mut self, mixer: &mut Mixer) {
+        let rate = mixer.preferred_rate().unwrap_or(mixer.out_rate());
+        if self.format.map(|f| f.rate) != Some(rate) {
+            if self.live {
+                log::info!("streams changed, moving from {}Hz to {}Hz", mixer.out_rate(), rate);
+            }
+            // setting up a stream stops the CODEC if it is playing
+            match self.codec.setup_stream(StreamFormat::new(rate, Channels::Stereo, SampleFormat::I16)) {
+                Ok(granted) => {
+                    log::info!("mixing at {}Hz", granted.rate);
+                    mixer.set_out_rate(granted.rate);
+                    self.format = Some(granted);
+                }
+                Err(e) => {
+                    log::error!("couldn't set up the CODEC for {}Hz: {:?}", rate, e);
+                    self.live = false;
+                    return;
+                }
+            }
+        } else if self.live {
+            return;
+        }
+        self.idle_frames = 0;
+        self.codec.resume().expect("couldn't start the CODEC");
+        self.live = true;
+    }
+    fn stop(&mut self) {
+        if self.live {
+            self.codec.pause().expect("couldn't pause the CODEC");
+            self.live = false;
+        }
+    }
+}
+
+#[xous::xous_main]
+fn xmain() -> ! {
+    log_server::init_wait().unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+    info!("my PID is {}", xous::process::id());
+
+    let xns = xous_names::XousNames::new().unwrap();
+    // unlimited connections allowed: anything can play a sound
+    let mixer_sid = xns.register_name(api::SERVER_NAME_MIXER, None).expect("can't register server");
+    log::trace!("registered with NS -- {:?}", mixer_sid);
+
+    let mut output = Output {
+        codec: codec::Codec::new(&xns).expect("couldn't connect to the CODEC"),
+        format: None,
+        live: false,
+        idle_frames: 0,
+    };
+    let caps = output.codec.capabilities().expect("couldn't get the CODEC's capabilities");
+    let self_cid = xous::connect(mixer_sid).unwrap();
+    output.codec.hook_frame_callback(Opcode::CodecFrame.to_u32().unwrap(), self_cid).expect("couldn't hook the CODEC's frame callback");
+
+    let mut mixer = Mixer::new(8000);
+    // indexed by the stream's slot
+    let mut callbacks: [Option<ScalarCallback>; MAX_STREAMS] = [None; MAX_STREAMS];
+    log::trace!("ready to accept requests");
+
+    loop {
+        let mut msg = xous::receive_message(mixer_sid).unwrap();
+        let sender = msg.sender.pid().map(|p| p.get());
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(Opcode::OpenStream) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<OpenRequest, _>().unwrap();
+                let format = StreamFormat::new(
+                    req.requested.rate.max(caps.min_rate).min(caps.max_rate),
+                    req.requested.channels,
+                    SampleFormat::I16,
+                );
+                match mixer.open(sender, format, req.priority, req.volume) {
+                    Some(handle) => {
+                        log::debug!("opened stream {:x}: {:?} at {:?}", handle, format, req.priority);
+                        req.granted = Some(format);
+                        req.handle = handle;
+                    }
+                    None => log::warn!("no streams left to open"),
+                }
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::CloseStream) => msg_blocking_scalar_unpack!(msg, handle, _, _, _, {
+                if mixer.owns(sender, handle as u32) && mixer.close(handle as u32) {
+                    unhook(&mut callbacks[handle & 0xFF]);
+                }
+                xous::return_scalar(msg.sender, 1).expect("couldn't ack CloseStream");
+            }),
+            Some(Opcode::StreamSubscribe) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let hookdata = buffer.to_original::<ScalarHook, _>().unwrap();
+                if mixer.owns(sender, hookdata.handle) {
+                    let slot = &mut callbacks[(hookdata.handle & 0xFF) as usize];
+                    unhook(slot);
+                    let (s0, s1, s2, s3) = hookdata.sid;
+                    *slot = Some(ScalarCallback {
+                        server_to_cb_cid: xous::connect(xous::SID::from_u32(s0, s1, s2, s3)).unwrap(),
+                        cb_to_client_cid: hookdata.cid,
+                        cb_to_client_id: hookdata.id,
+                    });
+                } else {
+                    log::warn!("hook for unknown stream {:x}", hookdata.handle);
+                }
+            }
+            Some(Opcode::WriteFrames) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut write = buffer.to_original::<StreamFrames, _>().unwrap();
+                match mixer.format(write.handle).filter(|_| mixer.owns(sender, write.handle)) {
+                    Some(format) if format == write.frames.format() => {
+                        for _ in 0..mixer.free_frames(write.handle) {
+                            match write.frames.dq_frame() {
+                                Some(frame) => { mixer.queue_frame(write.handle, &frame); }
+                                None => break,
+                            }
+                        }
+                    }
+                    Some(format) => log::warn!("got frames in {:?} for a stream of {:?}, ignoring", write.frames.format(), format),
+                    None => log::warn!("frames for unknown stream {:x}", write.handle),
+                }
+                buffer.replace(write).unwrap();
+                if mixer.is_active() {
+                    output.start(&mut mixer);
+                }
+            }
+            Some(Opcode::SetVolume) => msg_scalar_unpack!(msg, handle, percent, _, _, {
+                if mixer.owns(sender, handle as u32) {
+                    mixer.set_volume(handle as u32, percent.min(100) as u8);
+                }
+            }),
+            Some(Opcode::PauseStream) => msg_scalar_unpack!(msg, handle, _, _, _, {
+                if mixer.owns(sender, handle as u32) {
+                    mixer.set_paused(handle as u32, true);
+                }
+            }),
+            Some(Opcode::ResumeStream) => msg_scalar_unpack!(msg, handle, _, _, _, {
+                if mixer.owns(sender, handle as u32) && mixer.set_paused(handle as u32, false) {
+                    if mixer.is_active() {
+                        output.start(&mut mixer);
+                    }
+                    // prime the stream; after this, requests for more come as the CODEC plays
+                    if let Some(cb) = &callbacks[handle & 0xFF] {
+                        send_event(cb, mixer.free_frames(handle as u32));
+                    }
+                }
+            }),
+            Some(Opcode::FlushStream) => msg_scalar_unpack!(msg, handle, _, _, _, {
+                if mixer.owns(sender, handle as u32) {
+                    mixer.flush(handle as u32);
+                }
+            }),
+            Some(Opcode::StreamStatus) => msg_blocking_scalar_unpack!(msg, handle, _, _, _, {
+                let owned = mixer.owns(sender, handle as u32);
+                let queued_ms = mixer.queued_ms(handle as u32).filter(|_| owned).unwrap_or(0);
+                let playing = owned && mixer.is_paused(handle as u32) == Some(false);
+                xous::return_scalar2(msg.sender, queued_ms as usize, if playing { 1 } else { 0 }).expect("couldn't return StreamStatus");
+            }),
+            Some(Opcode::CaptureStream) => msg_scalar_unpack!(msg, handle, on, _, _, {
+                if mixer.owns(sender, handle as u32) {
+                    mixer.set_capture(handle as u32, on != 0);
+                }
+            }),
+            Some(Opcode::ReadFrames) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut read = buffer.to_original::<StreamFrames, _>().unwrap();
+                // what the microphone picks up comes in the format the CODEC is running in
+                read.frames = codec::FrameRing::with_format(output.format.unwrap_or(StreamFormat::new(mixer.out_rate(), Channels::Stereo, SampleFormat::I16)));
+                if mixer.owns(sender, read.handle) {
+                    while read.frames.writeable_count() > 0 {
+                        match mixer.read_captured(read.handle) {
+                            Some(frame) => read.frames.nq_frame(frame).unwrap(),
+                            None => break,
+                        }
+                    }
+                }
+                buffer.replace(read).unwrap();
+            }
+            Some(Opcode::CodecFrame) => msg_scalar_unpack!(msg, free_play, _avail_rec, _, _, {
+                // callbacks already in flight when we paused are ignored, as are any that don't come
+                // from the CODEC's callback thread, which runs in our own process
+                if output.live && sender == Some(xous::process::id() as u8) {
+                    let mut frames = codec::FrameRing::with_format(output.format.unwrap());
+                    for _ in 0..free_play.min(frames.writeable_count()) {
+                        if mixer.is_active() {
+                            output.idle_frames = 0;
+                        } else {
+                            output.idle_frames += 1;
+                        }
+                        frames.nq_frame(mixer.mix_frame()).unwrap();
+                    }
+                    output.codec.swap_frames(&mut frames).expect("couldn't hand frames to the CODEC");
+                    // the CODEC hands back what the microphone picked up in their place
+                    while let Some(frame) = frames.dq_frame() {
+                        mixer.capture_frame(&frame);
+                    }
+                    if output.idle_frames >= IDLE_FRAMES {
+                        log::debug!("nothing to play, pausing");
+                        output.stop();
+                    }
+                    for (handle, free) in mixer.wanting_frames() {
+                        if let Some(cb) = &callbacks[(handle & 0xFF) as usize] {
+                            send_event(cb, free);
+                        }
+                    }
+                }
+            }),
+            Some(Opcode::Quit) => {
+                log::warn!("Quit received, goodbye!");
+                break;
+            }
+            None => {
+                log::error!("couldn't convert opcode: {:?}", msg);
+            }
+        }
+    }
+    for slot in callbacks.iter_mut() {
+        unhook(slot);
+    }
+    output.codec.abort().ok();
+    xns.unregister_server(mixer_sid).unwrap();
+    xous::destroy_server(mixer_sid).unwrap();
+    log::trace!("quitting");
+    xous::terminate_process(0)
+}
+
+fn unhook(slot: &mut Option<ScalarCallback>) {
+    if let Some(scb) = slot.take() {
+        xous::send_message(scb.server_to_cb_cid,
+            xous::Message::new_blocking_scalar(EventCallback::Drop.to_usize().unwrap(), 0, 0, 0, 0)
+        ).unwrap();
+        unsafe{xous::disconnect(scb.server_to_cb_cid).unwrap();}
+    }
+}
+
+fn send_event(scb: &ScalarCallback, free_frames: usize) {
+    xous::send_message(scb.server_to_cb_cid,
+        xous::Message::new_scalar(EventCallback::Event.to_usize().unwrap(),
+            scb.cb_to_client_cid as usize, scb.cb_to_client_id as usize, free_frames, 0)
+    ).unwrap();
+}
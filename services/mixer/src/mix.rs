@@ -0,0 +1,449 @@
+//! Mixes any number of client streams down to the one stream the CODEC plays.
+//!
+//! Each stream is converted to stereo at the output rate as it is written, so mixing itself is
+//! just scaling and summing. A stream's gain is its volume times its ducking gain; the ducking
+//! gain ramps over a frame whenever it changes, so ducking doesn't click.
+
+use std::collections::VecDeque;
+
+use codec::resample::Resampler;
+use codec::{Channels, StreamFormat, FIFO_DEPTH};
+
+use crate::api::Priority;
+
+pub(crate) const MAX_STREAMS: usize = 16;
+/// How much audio a stream can have queued, in milliseconds. More than this and a writer is told
+/// to hold on to its frames.
+const MAX_QUEUED_MS: usize = 500;
+/// Gains are fixed point, with 12 fractional bits
+const UNITY: i32 = 1 << 12;
+/// The gain of a ducked stream, about -12dB
+const DUCKED: i32 = UNITY / 4;
+
+struct Channel {
+    /// the PID of the process that opened the stream, which is the only one that may use it
+    owner: Option<u8>,
+    format: StreamFormat,
+    priority: Priority,
+    /// in percent
+    volume: u8,
+    paused: bool,
+    resampler: Resampler,
+    /// left/right pairs at the output rate
+    queue: VecDeque<(i16, i16)>,
+    /// the ducking gain at the end of the last frame mixed
+    duck: i32,
+    /// microphone frames waiting to be read, if the stream is capturing
+    captured: Option<VecDeque<[u32; FIFO_DEPTH]>>,
+}
+
+impl Channel {
+    fn is_sounding(&self) -> bool {
+        !self.paused && !self.queue.is_empty()
+    }
+}
+
+pub(crate) struct Mixer {
+    out_rate: u32,
+    slots: Vec<Option<Channel>>,
+    /// bumped every time a slot is reused, so a closed stream's handle doesn't reach its successor
+    generations: [u8; MAX_STREAMS],
+}
+
+/// Percent to gain, on a square law so the steps sound about even
+fn volume_gain(percent: u8) -> i32 {
+    let p = percent.min(100) as i32;
+    p * p * UNITY / 10_000
+}
+
+impl Mixer {
+    pub(crate) fn new(out_rate: u32) -> Mixer {
+        Mixer {
+            out_rate,
+            slots: (0..MAX_STREAMS).map(|_| None).collect(),
+            generations: [0; MAX_STREAMS],
+        }
+    }
+    pub(crate) fn out_rate(&self) -> u32 {
+        self.out_rate
+    }
+    fn capacity(&self) -> usize {
+        self.out_rate as usize * MAX_QUEUED_MS / 1000
+    }
+    fn channel(&self, handle: u32) -> Option<&Channel> {
+        let slot = (handle & 0xFF) as usize;
+        if slot >= MAX_STREAMS || (handle >> 8) as u8 != self.generations[slot] {
+            return None;
+        }
+        self.slots[slot].as_ref()
+    }
+    fn channel_mut(&mut self, handle: u32) -> Option<&mut Channel> {
+        let slot = (handle & 0xFF) as usize;
+        if slot >= MAX_STREAMS || (handle >> 8) as u8 != self.generations[slot] {
+            return None;
+        }
+        self.slots[slot].as_mut()
+    }
+
+    /// Opens a stream for `owner`, paused. Returns its handle, or `None` if all the slots are taken.
+    pub(crate) fn open(&mut self, owner: Option<u8>, format: StreamFormat, priority: Priority, volume: u8) -> Option<u32> {
+        let slot = self.slots.iter().position(|s| s.is_none())?;
+        self.generations[slot] = self.generations[slot].wrapping_add(1);
+        self.slots[slot] = Some(Channel {
+            owner,
+            format,
+            priority,
+            volume: volume.min(100),
+            paused: true,
+            resampler: Resampler::new(format.rate, self.out_rate, format.channels.count()),
+            queue: VecDeque::new(),
+            duck: UNITY,
+            captured: None,
+        });
+        Some(slot as u32 | (self.generations[slot] as u32) << 8)
+    }
+    /// Whether `handle` is an open stream that `pid` opened. Handles are easy to guess, so the
+    /// server checks this before doing anything with one.
+    pub(crate) fn owns(&self, pid: Option<u8>, handle: u32) -> bool {
+        matches!(self.channel(handle), Some(c) if c.owner == pid)
+    }
+    pub(crate) fn close(&mut self, handle: u32) -> bool {
+        if self.channel(handle).is_some() {
+            self.slots[(handle & 0xFF) as usize] = None;
+            true
+        } else {
+            false
+        }
+    }
+    pub(crate) fn format(&self, handle: u32) -> Option<StreamFormat> {
+        self.channel(handle).map(|c| c.format)
+    }
+    pub(crate) fn set_volume(&mut self, handle: u32, percent: u8) -> bool {
+        self.channel_mut(handle).map(|c| c.volume = percent.min(100)).is_some()
+    }
+    pub(crate) fn set_paused(&mut self, handle: u32, paused: bool) -> bool {
+        self.channel_mut(handle).map(|c| c.paused = paused).is_some()
+    }
+    pub(crate) fn is_paused(&self, handle: u32) -> Option<bool> {
+        self.channel(handle).map(|c| c.paused)
+    }
+    pub(crate) fn flush(&mut self, handle: u32) -> bool {
+        self.channel_mut(handle).map(|c| {
+            c.queue.clear();
+            c.resampler.reset();
+            if let Some(captured) = c.captured.as_mut() {
+                captured.clear();
+            }
+        }).is_some()
+    }
+    /// Starts or stops keeping microphone frames for the stream. Stopping drops any not yet read.
+    pub(crate) fn set_capture(&mut self, handle: u32, on: bool) -> bool {
+        self.channel_mut(handle).map(|c| {
+            if !on {
+                c.captured = None;
+            } else if c.captured.is_none() {
+                c.captured = Some(VecDeque::new());
+            }
+        }).is_some()
+    }
+    /// Hands a frame the microphone picked up, as stereo words at the output rate, to every
+    /// stream that is playing and capturing. Each keeps up to `MAX_QUEUED_MS` of them; past that,
+    /// the oldest are dropped.
+    pub(crate) fn capture_frame(&mut self, frame: &[u32; FIFO_DEPTH]) {
+        let max_frames = (self.capacity() / FIFO_DEPTH).max(1);
+        for c in self.slots.iter_mut().flatten().filter(|c| !c.paused) {
+            if let Some(captured) = c.captured.as_mut() {
+                if captured.len() >= max_frames {
+                    captured.pop_front();
+                }
+                captured.push_back(*frame);
+            }
+        }
+    }
+    /// The oldest microphone frame the stream hasn't read yet
+    pub(crate) fn read_captured(&mut self, handle: u32) -> Option<[u32; FIFO_DEPTH]> {
+        self.channel_mut(handle)?.captured.as_mut()?.pop_front()
+    }
+
+    /// Converts `frame` onto the stream's queue. Fails if the stream doesn't have room for it.
+    pub(crate) fn queue_frame(&mut self, handle: u32, frame: &[u32; FIFO_DEPTH]) -> bool {
+        let capacity = self.capacity();
+        let channel = match self.channel_mut(handle) {
+            Some(c) => c,
+            None => return false,
+        };
+        let per_frame = channel.resampler.max_output(FIFO_DEPTH * channel.format.frames_per_word());
+        if channel.queue.len() + per_frame > capacity {
+            return false;
+        }
+        let mut samples = Vec::with_capacity(FIFO_DEPTH * 2);
+        channel.format.unpack(frame, &mut samples);
+        let mut resampled = Vec::with_capacity(per_frame * 2);
+        channel.resampler.process(&samples, &mut resampled);
+        match channel.format.channels {
+            Channels::Stereo => channel.queue.extend(resampled.chunks_exact(2).map(|p| (p[0], p[1]))),
+            Channels::Mono => channel.queue.extend(resampled.iter().map(|&s| (s, s))),
+        }
+        true
+    }
+    /// How many more frames the stream can take right now
+    pub(crate) fn free_frames(&self, handle: u32) -> usize {
+        match self.channel(handle) {
+            Some(c) => {
+                let per_frame = c.resampler.max_output(FIFO_DEPTH * c.format.frames_per_word());
+                self.capacity().saturating_sub(c.queue.len()) / per_frame
+            }
+            None => 0,
+        }
+    }
+    pub(crate) fn queued_ms(&self, handle: u32) -> Option<u32> {
+        self.channel(handle).map(|c| (c.queue.len() as u64 * 1000 / self.out_rate as u64) as u32)
+    }
+    /// Handles of the streams that are playing and have room for at least one more frame
+    pub(crate) fn wanting_frames(&self) -> Vec<(u32, usize)> {
+        let mut wanting = Vec::new();
+        for (slot, channel) in self.slots.iter().enumerate() {
+            if let Some(c) = channel {
+                if !c.paused {
+                    let handle = slot as u32 | (self.generations[slot] as u32) << 8;
+                    let free = self.free_frames(handle);
+                    if free > 0 {
+                        wanting.push((handle, free));
+                    }
+                }
+            }
+        }
+        wanting
+    }
+
+    /// If any stream has something to play
+    pub(crate) fn is_active(&self) -> bool {
+        self.slots.iter().flatten().any(|c| c.is_sounding())
+    }
+    /// The rate to run the output at so that no open stream loses anything
+    pub(crate) fn preferred_rate(&self) -> Option<u32> {
+        self.slots.iter().flatten().map(|c| c.format.rate).max()
+    }
+    /// Changes the output rate. Anything already queued is converted to the new rate, so this is
+    /// best done while nothing is playing.
+    pub(crate) fn set_out_rate(&mut self, rate: u32) {
+        let old_rate = self.out_rate;
+        if rate == old_rate {
+            return;
+        }
+        self.out_rate = rate;
+        for c in self.slots.iter_mut().flatten() {
+            c.resampler = Resampler::new(c.format.rate, rate, c.format.channels.count());
+            if !c.queue.is_empty() {
+                let mut queued = Vec::with_capacity(c.queue.len() * 2);
+                for (l, r) in c.queue.drain(..) {
+                    queued.push(l);
+                    queued.push(r);
+                }
+                let mut converted = Vec::with_capacity(queued.len() * rate as usize / old_rate as usize + 4);
+                Resampler::new(old_rate, rate, 2).process(&queued, &mut converted);
+                c.queue.extend(converted.chunks_exact(2).map(|p| (p[0], p[1])));
+            }
+        }
+    }
+
+    /// Mixes the next frame of output, as stereo words `|31 right 16|15 left 0|`. Streams that
+    /// run out part way through are padded with silence.
+    pub(crate) fn mix_frame(&mut self) -> [u32; FIFO_DEPTH] {
+        let ducking = self.slots.iter().flatten()
+            .filter(|c| c.is_sounding() && c.priority.ducks_others())
+            .map(|c| c.priority)
+            .max();
+        let mut acc = [(0i32, 0i32); FIFO_DEPTH];
+        for c in self.slots.iter_mut().flatten() {
+            let target = match ducking {
+                Some(p) if p > c.priority => DUCKED,
+                _ => UNITY,
+            };
+            if !c.is_sounding() {
+                // nothing to ramp; come back in at the right level
+                c.duck = target;
+                continue;
+            }
+            let start = c.duck;
+            let volume = volume_gain(c.volume);
+            for (i, (l, r)) in acc.iter_mut().enumerate() {
+                let (sl, sr) = match c.queue.pop_front() {
+                    Some(pair) => pair,
+                    None => break,
+                };
+                let duck = start + (target - start) * i as i32 / FIFO_DEPTH as i32;
+                let gain = (volume as i64 * duck as i64) >> 12;
+                *l += ((sl as i64 * gain) >> 12) as i32;
+                *r += ((sr as i64 * gain) >> 12) as i32;
+            }
+            c.duck = target;
+        }
+        let mut frame = [0u32; FIFO_DEPTH];
+        for (word, &(l, r)) in frame.iter_mut().zip(acc.iter()) {
+            let l = l.max(i16::MIN as i32).min(i16::MAX as i32) as i16;
+            let r = r.max(i16::MIN as i32).min(i16::MAX as i32) as i16;
+            *word = l as u16 as u32 | (r as u16 as u32) << 16;
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec::SampleFormat;
+
+    fn stereo(rate: u32) -> StreamFormat {
+        StreamFormat::new(rate, Channels::Stereo, SampleFormat::I16)
+    }
+    fn constant(left: i16, right: i16) -> [u32; FIFO_DEPTH] {
+        [left as u16 as u32 | (right as u16 as u32) << 16; FIFO_DEPTH]
+    }
+    fn left(word: u32) -> i16 {
+        (word & 0xFFFF) as u16 as i16
+    }
+
+    #[test]
+    fn sums_and_saturates() {
+        let mut m = Mixer::new(8000);
+        let a = m.open(Some(5), stereo(8000), Priority::Normal, 100).unwrap();
+        let b = m.open(Some(5), stereo(8000), Priority::Normal, 50).unwrap();
+        // nothing plays until the streams are resumed
+        assert!(m.queue_frame(a, &constant(1000, 30000)));
+        assert!(m.queue_frame(b, &constant(4000, 30000)));
+        assert!(!m.is_active());
+        m.set_paused(a, false);
+        m.set_paused(b, false);
+        assert!(m.is_active());
+
+        let frame = m.mix_frame();
+        // 50% volume is a quarter of the amplitude
+        assert_eq!(left(frame[0]), 2000);
+        assert_eq!((frame[0] >> 16) as u16 as i16, i16::MAX);
+        assert!(!m.is_active());
+        // and silence once they run out
+        assert_eq!(m.mix_frame(), [0; FIFO_DEPTH]);
+    }
+
+    #[test]
+    fn speech_ducks_music() {
+        let mut m = Mixer::new(8000);
+        let music = m.open(Some(5), stereo(8000), Priority::Background, 100).unwrap();
+        let speech = m.open(Some(5), stereo(8000), Priority::Speech, 100).unwrap();
+        let click = m.open(Some(5), stereo(8000), Priority::Normal, 100).unwrap();
+        m.set_paused(music, false);
+        m.set_paused(speech, false);
+        m.set_paused(click, false);
+        for _ in 0..4 {
+            assert!(m.queue_frame(music, &constant(4000, 4000)));
+        }
+        // a UI sound doesn't duck the music
+        assert!(m.queue_frame(click, &constant(0, 0)));
+        assert_eq!(left(m.mix_frame()[FIFO_DEPTH - 1]), 4000);
+
+        // speech does, ramping down over a frame, and back up once it's done
+        assert!(m.queue_frame(speech, &constant(0, 0)));
+        let frame = m.mix_frame();
+        assert_eq!(left(frame[0]), 4000);
+        assert!(left(frame[FIFO_DEPTH / 2]) < 4000 && left(frame[FIFO_DEPTH / 2]) > 1000);
+        assert!((left(frame[FIFO_DEPTH - 1]) - 1000).abs() < 20);
+        let frame = m.mix_frame();
+        assert!(frame.windows(2).all(|w| left(w[0]) <= left(w[1])));
+        assert!((left(frame[FIFO_DEPTH - 1]) - 4000).abs() < 20);
+        assert_eq!(left(m.mix_frame()[0]), 4000);
+    }
+
+    #[test]
+    fn resamples_and_bounds_the_queue() {
+        let mut m = Mixer::new(16000);
+        let mono8k = StreamFormat::new(8000, Channels::Mono, SampleFormat::I16);
+        let h = m.open(Some(5), mono8k, Priority::Normal, 100).unwrap();
+        let frame = [100u32 | 100 << 16; FIFO_DEPTH];
+        let free = m.free_frames(h);
+        assert!(free > 0);
+        for _ in 0..free {
+            assert!(m.queue_frame(h, &frame));
+        }
+        assert!(!m.queue_frame(h, &frame));
+        assert_eq!(m.free_frames(h), 0);
+        // each 64ms frame of 8k mono is 2048 pairs at 16k
+        let queued = m.queued_ms(h).unwrap();
+        assert!(queued as usize >= free * 64 - 1 && queued <= MAX_QUEUED_MS as u32, "{}", queued);
+        m.set_paused(h, false);
+        assert!(m.wanting_frames().is_empty());
+        let out = m.mix_frame();
+        // duplicated into both channels
+        assert_eq!(out[FIFO_DEPTH - 1], 100 | 100 << 16);
+        assert_eq!(m.preferred_rate(), Some(8000));
+
+        // what's queued is carried over a change of rate
+        let before = m.queued_ms(h).unwrap();
+        m.set_out_rate(8000);
+        assert!((m.queued_ms(h).unwrap() as i32 - before as i32).abs() <= 1);
+        assert_eq!(m.mix_frame()[FIFO_DEPTH - 1], 100 | 100 << 16);
+    }
+
+    #[test]
+    fn stale_handles() {
+        let mut m = Mixer::new(8000);
+        let a = m.open(Some(5), stereo(8000), Priority::Normal, 100).unwrap();
+        assert!(m.close(a));
+        let b = m.open(Some(5), stereo(8000), Priority::Normal, 100).unwrap();
+        // same slot, new handle
+        assert_eq!(a & 0xFF, b & 0xFF);
+        assert!(!m.set_volume(a, 10));
+        assert!(!m.queue_frame(a, &constant(1, 1)));
+        assert!(m.queue_frame(b, &constant(1, 1)));
+        assert!(!m.close(a));
+        assert!(m.format(b).is_some());
+        for _ in 1..MAX_STREAMS {
+            assert!(m.open(Some(5), stereo(8000), Priority::Normal, 100).is_some());
+        }
+        assert!(m.open(Some(5), stereo(8000), Priority::Normal, 100).is_none());
+    }
+
+    #[test]
+    fn streams_belong_to_their_openers() {
+        let mut m = Mixer::new(8000);
+        let a = m.open(Some(5), stereo(8000), Priority::Normal, 100).unwrap();
+        assert!(m.owns(Some(5), a));
+        assert!(!m.owns(Some(6), a));
+        assert!(!m.owns(None, a));
+        // a stale handle isn't anybody's
+        assert!(m.close(a));
+        assert!(!m.owns(Some(5), a));
+        let b = m.open(Some(6), stereo(8000), Priority::Normal, 100).unwrap();
+        assert!(!m.owns(Some(5), b));
+        assert!(m.owns(Some(6), b));
+    }
+
+    #[test]
+    fn captures_while_playing() {
+        let mut m = Mixer::new(8000);
+        let rec = m.open(Some(5), stereo(8000), Priority::Normal, 100).unwrap();
+        let other = m.open(Some(5), stereo(8000), Priority::Normal, 100).unwrap();
+        // only streams that asked for it, and only once they're playing
+        assert!(m.set_capture(rec, true));
+        m.capture_frame(&constant(1, 1));
+        assert_eq!(m.read_captured(rec), None);
+        m.set_paused(rec, false);
+        m.set_paused(other, false);
+        m.capture_frame(&constant(2, 2));
+        m.capture_frame(&constant(3, 3));
+        assert_eq!(m.read_captured(other), None);
+        assert_eq!(m.read_captured(rec), Some(constant(2, 2)));
+
+        // a reader that falls behind loses the oldest frames
+        let max_frames = 8000 * MAX_QUEUED_MS / 1000 / FIFO_DEPTH;
+        for i in 0..max_frames as i16 {
+            m.capture_frame(&constant(10 + i, 0));
+        }
+        assert_eq!(m.read_captured(rec), Some(constant(10, 0)));
+        assert!(m.flush(rec));
+        assert_eq!(m.read_captured(rec), None);
+        m.capture_frame(&constant(4, 4));
+        assert!(m.set_capture(rec, false));
+        assert_eq!(m.read_captured(rec), None);
+    }
+}
@@ -0,0 +1,579 @@
+#![cfg_attr(target_os = "none", no_std)]
+
+//! Detailed docs are parked under Structs/Shell down below
+
+mod parse;
+use parse::{Command, Cond, Piece, Statement, Word};
+mod store;
+use store::{Kind, Store};
+
+use std::collections::HashMap;
+
+/// The shell a `Shell` runs its commands on
+pub trait Host {
+    /// Runs one of the shell's own commands, given as its verb and arguments separated by spaces
+    fn run(&mut self, line: &str) -> Result<Option<String>, xous::Error>;
+    fn sleep_ms(&mut self, ms: usize);
+}
+
+/// how deeply blocks, aliases and scripts may nest
+const MAX_DEPTH: usize = 16;
+/// most commands one line may run, counting every pass through a loop
+const MAX_STEPS: usize = 10_000;
+/// longest one line may sleep in all, since the shell can't take input until the line is done
+const MAX_SLEEP_MS: usize = 60_000;
+/// most output one line may produce
+const MAX_OUTPUT: usize = 4096;
+/// the variables a script's arguments are passed in
+const POSITIONAL: [&str; 10] = ["1", "2", "3", "4", "5", "6", "7", "8", "9", "*"];
+
+/// A word of a command, once its variables have been filled in
+#[derive(Debug, Clone)]
+enum Arg {
+    Text(String),
+    Block(String),
+}
+
+impl Arg {
+    fn text(&self) -> String {
+        match self {
+            Arg::Text(t) => t.clone(),
+            Arg::Block(b) => format!("{{ {} }}", b),
+        }
+    }
+    fn word(&self) -> Word {
+        match self {
+            Arg::Text(t) => Word::literal(t),
+            Arg::Block(b) => Word::Block(b.clone()),
+        }
+    }
+}
+
+fn join(args: &[Arg]) -> String {
+    args.iter().map(|a| a.text()).collect::<Vec<String>>().join(" ")
+}
+
+fn var_name_ok(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Whether it worked, and what it printed
+type Outcome = (bool, Vec<String>);
+
+fn fail(message: String) -> Outcome {
+    (false, vec![message])
+}
+
+/// The state of one line's run
+struct Run<'a> {
+    host: &'a mut dyn Host,
+    steps: usize,
+    slept_ms: usize,
+    /// aliases being expanded; inside its own body, an alias's name means the command it hides
+    aliases: Vec<String>,
+}
+
+#[doc = include_str!("../README.md")]
+pub struct Shell {
+    vars: HashMap<String, String>,
+    store: Store,
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shell {
+    pub fn new() -> Shell {
+        Shell {
+            vars: HashMap::new(),
+            store: Store::new(),
+        }
+    }
+
+    /// The script in a line, if it's one for us: the rest of a line that starts with `sh`, or a
+    /// whole line that starts with the name of an alias or a saved script. Anything else is one
+    /// of the shell's own commands.
+    fn scripted<'l>(&mut self, line: &'l str) -> Option<&'l str> {
+        let line = line.trim_start();
+        let (first, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        if first == "sh" {
+            Some(rest)
+        } else if self.store.get(Kind::Alias, first).is_some() || self.store.get(Kind::Script, first).is_some() {
+            Some(line)
+        } else {
+            None
+        }
+    }
+
+    /// Runs a line of input, returning everything it printed. Errors are printed too, so there's
+    /// always something to show for a line that failed. Lines that aren't scripts go to the shell
+    /// exactly as they were typed.
+    pub fn run(&mut self, line: &str, host: &mut dyn Host) -> Option<String> {
+        let script = match self.scripted(line) {
+            Some(script) => script,
+            None => {
+                let result = host.run(line);
+                self.vars.insert(String::from("?"), String::from(if result.is_ok() { "0" } else { "1" }));
+                return match result {
+                    Ok(out) => out,
+                    Err(e) => Some(format!("{}: {:?}", line.split_whitespace().next().unwrap_or(""), e)),
+                };
+            }
+        };
+        let mut run = Run {
+            host,
+            steps: 0,
+            slept_ms: 0,
+            aliases: Vec::new(),
+        };
+        let (_, out) = self.script(script, &mut run, 0);
+        if out.is_empty() {
+            return None;
+        }
+        let mut text = out.join("\n");
+        if text.len() > MAX_OUTPUT {
+            let mut end = MAX_OUTPUT;
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text.truncate(end);
+            text.push_str("...");
+        }
+        Some(text)
+    }
+
+    fn script(&mut self, text: &str, run: &mut Run, depth: usize) -> Outcome {
+        match parse::parse(text) {
+            Ok(statements) => self.statements(&statements, run, depth),
+            Err(e) => fail(format!("syntax error: {}", e)),
+        }
+    }
+
+    fn statements(&mut self, statements: &[Statement], run: &mut Run, depth: usize) -> Outcome {
+        if depth > MAX_DEPTH {
+            return fail(String::from("nested too deeply"));
+        }
+        let mut ok = true;
+        let mut out = Vec::new();
+        for statement in statements {
+            if run.steps > MAX_STEPS {
+                return (false, out);
+            }
+            if statement.cond == Cond::IfOk && !ok {
+                continue;
+            }
+            let (pipeline_ok, pipeline_out) = self.pipeline(&statement.pipeline, run, depth);
+            ok = pipeline_ok;
+            out.extend(pipeline_out);
+            self.vars.insert(String::from("?"), String::from(if ok { "0" } else { "1" }));
+        }
+        (ok, out)
+    }
+
+    /// Each command's output goes on the end of the next one's arguments, as a single argument
+    fn pipeline(&mut self, commands: &[Command], run: &mut Run, depth: usize) -> Outcome {
+        let mut result = (true, Vec::new());
+        for (i, command) in commands.iter().enumerate() {
+            let mut args = self.expand(command);
+            if i > 0 {
+                let input = result.1.join(" ").replace('\n', " ");
+                if !input.is_empty() {
+                    args.push(Arg::Text(input));
+                }
+            }
+            result = self.command(args, run, depth);
+            if !result.0 {
+                break;
+            }
+        }
+        result
+    }
+
+    fn expand(&self, command: &[Word]) -> Vec<Arg> {
+        command
+            .iter()
+            .map(|word| match word {
+                Word::Block(b) => Arg::Block(b.clone()),
+                Word::Pieces(pieces) => Arg::Text(
+                    pieces
+                        .iter()
+                        .map(|p| match p {
+                            Piece::Text(t) => t.as_str(),
+                            Piece::Var(v) => self.vars.get(v).map(|s| s.as_str()).unwrap_or(""),
+                        })
+                        .collect(),
+                ),
+            })
+            .collect()
+    }
+
+    fn command(&mut self, args: Vec<Arg>, run: &mut Run, depth: usize) -> Outcome {
+        run.steps += 1;
+        if run.steps > MAX_STEPS {
+            return if run.steps == MAX_STEPS + 1 {
+                fail(format!("stopped after {} commands", MAX_STEPS))
+            } else {
+                (false, Vec::new())
+            };
+        }
+        let (verb, rest) = match args.split_first() {
+            Some((Arg::Text(verb), rest)) => (verb.clone(), rest),
+            Some((Arg::Block(body), [])) => return self.script(body, run, depth + 1),
+            Some((Arg::Block(_), _)) => return fail(String::from("a block takes no arguments")),
+            None => return (true, Vec::new()),
+        };
+        match verb.as_str() {
+            "set" => self.set(rest),
+            "unset" => {
+                for arg in rest {
+                    self.vars.remove(&arg.text());
+                }
+                (true, Vec::new())
+            }
+            "echo" => (true, vec![join(rest)]),
+            // anything else is for the shell's own `sleep`, if it has one
+            "sleep" if rest.len() == 1 && rest[0].text().parse::<usize>().is_ok() => {
+                let ms = rest[0].text().parse::<usize>().unwrap();
+                if run.slept_ms.saturating_add(ms) > MAX_SLEEP_MS {
+                    // use up the steps too, so the loops around us stop without saying so again
+                    run.steps = MAX_STEPS + 1;
+                    return fail(format!("stopped before sleeping more than {} s", MAX_SLEEP_MS / 1000));
+                }
+                run.slept_ms += ms;
+                run.host.sleep_ms(ms);
+                (true, Vec::new())
+            }
+            "repeat" => self.repeat(rest, run, depth),
+            "for" => self.for_each(rest, run, depth),
+            "alias" => self.define(Kind::Alias, rest),
+            "unalias" => match rest {
+                [name] => self.undefine(Kind::Alias, &name.text()),
+                _ => fail(String::from("unalias <name>")),
+            },
+            "script" => self.scripts(rest),
+            _ => {
+                if !run.aliases.contains(&verb) {
+                    if let Some(body) = self.store.get(Kind::Alias, &verb) {
+                        return self.alias(&verb, &body, rest, run, depth);
+                    }
+                }
+                if let Some(body) = self.store.get(Kind::Script, &verb) {
+                    return self.call(&body, rest, run, depth);
+                }
+                match run.host.run(&join(&args)) {
+                    Ok(Some(out)) => (true, vec![out]),
+                    Ok(None) => (true, Vec::new()),
+                    Err(e) => fail(format!("{}: {:?}", verb, e)),
+                }
+            }
+        }
+    }
+
+    /// `set` lists the variables, `set <name> <value...>` sets one
+    fn set(&mut self, args: &[Arg]) -> Outcome {
+        match args.split_first() {
+            None => {
+                let mut vars: Vec<String> = self.vars
+                    .iter()
+                    .filter(|(name, _)| name.as_str() != "?" && !POSITIONAL.contains(&name.as_str()))
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                vars.sort();
+                (true, vars)
+            }
+            Some((name, value)) => {
+                let name = name.text();
+                if !var_name_ok(&name) {
+                    return fail(format!("bad variable name: {}", name));
+                }
+                self.vars.insert(name, join(value));
+                (true, Vec::new())
+            }
+        }
+    }
+
+    /// Runs `body` once for each value, with `var` set to it
+    fn looped(&mut self, var: &str, values: Vec<String>, body: &str, run: &mut Run, depth: usize) -> Outcome {
+        let statements = match parse::parse(body) {
+            Ok(statements) => statements,
+            Err(e) => return fail(format!("syntax error: {}", e)),
+        };
+        let mut result = (true, Vec::new());
+        for value in values {
+            if run.steps > MAX_STEPS {
+                result.0 = false;
+                break;
+            }
+            self.vars.insert(String::from(var), value);
+            let (ok, out) = self.statements(&statements, run, depth + 1);
+            result.0 = ok;
+            result.1.extend(out);
+        }
+        result
+    }
+
+    /// `repeat <n> { ... }`, with `$i` counting from 0
+    fn repeat(&mut self, args: &[Arg], run: &mut Run, depth: usize) -> Outcome {
+        match args {
+            [Arg::Text(n), Arg::Block(body)] => match n.parse::<usize>() {
+                // the step limit stops runaway loops, so there's no need to cap the count
+                Ok(n) => self.looped("i", (0..n).map(|i| i.to_string()).collect(), body, run, depth),
+                Err(_) => fail(format!("not a count: {}", n)),
+            },
+            _ => fail(String::from("repeat <count> { <commands> }")),
+        }
+    }
+
+    /// `for <var> in <values...> { ... }`
+    fn for_each(&mut self, args: &[Arg], run: &mut Run, depth: usize) -> Outcome {
+        match args {
+            [Arg::Text(var), Arg::Text(keyword), values @ .., Arg::Block(body)] if keyword == "in" => {
+                if !var_name_ok(var) {
+                    return fail(format!("bad variable name: {}", var));
+                }
+                let values = values.iter().map(|v| v.text()).collect();
+                self.looped(var, values, body, run, depth)
+            }
+            _ => fail(String::from("for <var> in <values...> { <commands> }")),
+        }
+    }
+
+    /// Runs an alias: its arguments go on the end of its last command
+    fn alias(&mut self, name: &str, body: &str, args: &[Arg], run: &mut Run, depth: usize) -> Outcome {
+        let mut statements = match parse::parse(body) {
+            Ok(statements) => statements,
+            Err(e) => return fail(format!("{}: syntax error: {}", name, e)),
+        };
+        if let Some(command) = statements.last_mut().and_then(|s| s.pipeline.last_mut()) {
+            command.extend(args.iter().map(|a| a.word()));
+        }
+        run.aliases.push(String::from(name));
+        let result = self.statements(&statements, run, depth + 1);
+        run.aliases.pop();
+        result
+    }
+
+    /// Runs a script, with its arguments in `$1`..`$9`, and all of them in `$*`
+    fn call(&mut self, body: &str, args: &[Arg], run: &mut Run, depth: usize) -> Outcome {
+        let saved: Vec<Option<String>> = POSITIONAL.iter().map(|p| self.vars.remove(*p)).collect();
+        for (p, arg) in POSITIONAL[..9].iter().zip(args.iter()) {
+            self.vars.insert(String::from(*p), arg.text());
+        }
+        self.vars.insert(String::from("*"), join(args));
+        let result = self.script(body, run, depth + 1);
+        for (p, value) in POSITIONAL.iter().zip(saved) {
+            match value {
+                Some(value) => self.vars.insert(String::from(*p), value),
+                None => self.vars.remove(*p),
+            };
+        }
+        result
+    }
+
+    /// `alias` lists them, `alias <name>` shows one, `alias <name> <commands>` sets one. Braces
+    /// around the commands keep their variables from being filled in until the alias is run.
+    fn define(&mut self, kind: Kind, args: &[Arg]) -> Outcome {
+        match args {
+            [] => {
+                let names = self.store.names(kind);
+                let list = names
+                    .iter()
+                    .map(|name| format!("{} = {}", name, self.store.get(kind, name).unwrap_or_default()))
+                    .collect();
+                (true, list)
+            }
+            [name] => match self.store.get(kind, &name.text()) {
+                Some(body) => (true, vec![body]),
+                None => fail(format!("no such {}: {}", if kind == Kind::Alias { "alias" } else { "script" }, name.text())),
+            },
+            [name, Arg::Block(body)] => self.save(kind, &name.text(), body),
+            [name, body @ ..] => self.save(kind, &name.text(), &join(body)),
+        }
+    }
+
+    fn save(&mut self, kind: Kind, name: &str, body: &str) -> Outcome {
+        if let Err(e) = parse::parse(body) {
+            return fail(format!("syntax error: {}", e));
+        }
+        match self.store.put(kind, name, body) {
+            Ok(()) => (true, Vec::new()),
+            Err(e) => fail(e),
+        }
+    }
+
+    fn undefine(&mut self, kind: Kind, name: &str) -> Outcome {
+        match self.store.remove(kind, name) {
+            Ok(true) => (true, Vec::new()),
+            Ok(false) => fail(format!("no such {}: {}", if kind == Kind::Alias { "alias" } else { "script" }, name)),
+            Err(e) => fail(e),
+        }
+    }
+
+    /// `script` lists them; `script show|save|delete <name>` does the rest. Scripts are run by name.
+    fn scripts(&mut self, args: &[Arg]) -> Outcome {
+        let sub = args.first().map(|a| a.text()).unwrap_or_default();
+        match (sub.as_str(), &args[args.len().min(1)..]) {
+            ("", _) => (true, vec![self.store.names(Kind::Script).join(" ")]),
+            ("show", [name]) => self.define(Kind::Script, std::slice::from_ref(name)),
+            ("save", [name, Arg::Block(body)]) => self.save(Kind::Script, &name.text(), body),
+            ("delete", [name]) => self.undefine(Kind::Script, &name.text()),
+            _ => fail(String::from("script [show <name> | save <name> { <commands> } | delete <name>]")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for a shell: `upper` shouts its arguments, `fail` fails, and anything else is
+    /// echoed back.
+    #[derive(Default)]
+    struct TestHost {
+        lines: Vec<String>,
+        slept_ms: usize,
+    }
+    impl Host for TestHost {
+        fn run(&mut self, line: &str) -> Result<Option<String>, xous::Error> {
+            self.lines.push(String::from(line));
+            match line.split_once(' ') {
+                Some(("upper", rest)) => Ok(Some(rest.to_uppercase())),
+                _ if line.starts_with("fail") => Err(xous::Error::InternalError),
+                _ if line.starts_with("quiet") => Ok(None),
+                _ => Ok(Some(format!("<{}>", line))),
+            }
+        }
+        fn sleep_ms(&mut self, ms: usize) {
+            self.slept_ms += ms;
+        }
+    }
+
+    fn shell() -> Shell {
+        Shell {
+            vars: HashMap::new(),
+            store: Store::in_memory(),
+        }
+    }
+
+    #[test]
+    fn plain_commands_pass_through() {
+        let mut host = TestHost::default();
+        let mut sh = shell();
+        // none of this is touched unless it's asked to be run as a script
+        let lines = [
+            "wlan setpass my#pa$$ \\ \"quoted\" 'x'",
+            "ver  xous",
+            "a; b && c | d { e }",
+            "set x 5",
+            "echo $x",
+            "shout",
+            "",
+        ];
+        for line in lines.iter() {
+            assert_eq!(sh.run(line, &mut host), Some(format!("<{}>", line)));
+        }
+        assert_eq!(host.lines, lines);
+        assert_eq!(sh.run("quiet", &mut host), None);
+        assert_eq!(sh.run("fail now", &mut host).unwrap(), "fail: InternalError");
+        assert_eq!(sh.run("sh echo $?", &mut host).unwrap(), "1");
+        assert_eq!(sh.run("sh", &mut host), None);
+    }
+
+    #[test]
+    fn aliases_and_scripts_are_scripts() {
+        let mut host = TestHost::default();
+        let mut sh = shell();
+        sh.run("sh alias shout upper", &mut host);
+        sh.run("sh script save twice { echo $1; echo $1 }", &mut host);
+        assert_eq!(sh.run("shout  a; echo b", &mut host).unwrap(), "A\nb");
+        assert_eq!(sh.run("  twice x", &mut host).unwrap(), "x\nx");
+        // but only as the first word
+        assert_eq!(sh.run("upper shout", &mut host).unwrap(), "SHOUT");
+        assert_eq!(host.lines, ["upper a", "upper shout"]);
+    }
+
+    #[test]
+    fn sequences() {
+        let mut host = TestHost::default();
+        let mut sh = shell();
+        let out = sh.run("sh a; fail && b && c; d", &mut host).unwrap();
+        assert_eq!(host.lines, ["a", "fail", "d"]);
+        assert_eq!(out, "<a>\nfail: InternalError\n<d>");
+        assert_eq!(sh.run("sh fail; echo $?; echo $?", &mut host).unwrap(), "fail: InternalError\n1\n0");
+        assert!(sh.run("sh a &&", &mut host).unwrap().starts_with("syntax error"));
+    }
+
+    #[test]
+    fn variables() {
+        let mut host = TestHost::default();
+        let mut sh = shell();
+        assert_eq!(sh.run("sh set x 5; echo $x ${x}0 \"[$x y]\" '$x'", &mut host).unwrap(), "5 50 [5 y] $x");
+        assert_eq!(sh.run("sh set name a b; set", &mut host).unwrap(), "name=a b\nx=5");
+        assert_eq!(sh.run("sh unset x; echo [$x]", &mut host).unwrap(), "[]");
+        assert!(sh.run("sh set 1-2 x", &mut host).unwrap().starts_with("bad variable name"));
+    }
+
+    #[test]
+    fn pipes() {
+        let mut host = TestHost::default();
+        let mut sh = shell();
+        assert_eq!(sh.run("sh echo hello | upper", &mut host).unwrap(), "HELLO");
+        assert_eq!(sh.run("sh net | upper status:", &mut host).unwrap(), "STATUS: <NET>");
+        assert_eq!(host.lines, ["upper hello", "net", "upper status: <net>"]);
+        // a failure stops the pipeline
+        assert_eq!(sh.run("sh fail | upper", &mut host).unwrap(), "fail: InternalError");
+    }
+
+    #[test]
+    fn loops() {
+        let mut host = TestHost::default();
+        let mut sh = shell();
+        assert_eq!(sh.run("sh repeat 3 { sleep 250; echo $i }", &mut host).unwrap(), "0\n1\n2");
+        assert_eq!(host.slept_ms, 750);
+        assert_eq!(sh.run("sh for f in a 'b c' { echo [$f] }", &mut host).unwrap(), "[a]\n[b c]");
+        // the shell's own sleep command still gets anything that isn't a plain delay
+        assert_eq!(sh.run("sh sleep now", &mut host).unwrap(), "<sleep now>");
+        assert_eq!(sh.run("sh repeat 20000 { quiet }", &mut host).unwrap(), "stopped after 10000 commands");
+        // sleeping adds up over the whole line, and it stops before the sleep that goes over
+        host.slept_ms = 0;
+        assert_eq!(
+            sh.run("sh repeat 10000 { sleep 25000; echo $i }", &mut host).unwrap(),
+            "0\n1\nstopped before sleeping more than 60 s"
+        );
+        assert_eq!(host.slept_ms, 50_000);
+        assert_eq!(sh.run("sh sleep 60000", &mut host), None);
+        assert!(sh.run("sh repeat x { a }", &mut host).unwrap().starts_with("not a count"));
+        assert!(sh.run("sh repeat 3 a", &mut host).unwrap().starts_with("repeat <count>"));
+    }
+
+    #[test]
+    fn aliases() {
+        let mut host = TestHost::default();
+        let mut sh = shell();
+        assert_eq!(sh.run("sh alias shout upper loud", &mut host), None);
+        assert_eq!(sh.run("sh shout and clear", &mut host).unwrap(), "LOUD AND CLEAR");
+        // an alias can hide the command it stands for
+        assert_eq!(sh.run("sh alias net { net status }", &mut host), None);
+        assert_eq!(sh.run("sh net", &mut host).unwrap(), "<net status>");
+        assert_eq!(sh.run("sh alias", &mut host).unwrap(), "net = net status\nshout = upper loud");
+        assert_eq!(sh.run("sh unalias net; net", &mut host).unwrap(), "<net>");
+        assert!(sh.run("sh unalias net", &mut host).unwrap().starts_with("no such alias"));
+        assert!(sh.run("sh alias bad/name x", &mut host).unwrap().starts_with("bad name"));
+    }
+
+    #[test]
+    fn scripts() {
+        let mut host = TestHost::default();
+        let mut sh = shell();
+        assert_eq!(sh.run("sh script save greet { echo hi $1; echo all: $* }", &mut host), None);
+        assert_eq!(sh.run("sh set 1 outer; greet bob 'al ice'; echo $1", &mut host).unwrap(), "hi bob\nall: bob al ice\nouter");
+        assert_eq!(sh.run("sh script", &mut host).unwrap(), "greet");
+        assert_eq!(sh.run("sh script show greet", &mut host).unwrap(), "echo hi $1; echo all: $*");
+        assert!(sh.run("sh script save broken { a && }", &mut host).unwrap().starts_with("syntax error"));
+        assert_eq!(sh.run("sh script save forever { forever }; forever", &mut host).unwrap(), "nested too deeply");
+        assert_eq!(sh.run("sh script delete greet; script delete forever; script", &mut host).unwrap(), "");
+    }
+}
@@ -0,0 +1,324 @@
+//! Turns a line of script into statements.
+//!
+//! Words are split on spaces. `'...'` is taken literally, `"..."` keeps spaces but still expands
+//! variables, and `\` escapes the next character. `{...}` is a block: its text is kept as-is, to be
+//! run later by whatever command it's handed to. `#` starts a comment.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Piece {
+    Text(String),
+    /// `$name`, `${name}`, `$?`, `$*` or `$1`..`$9`
+    Var(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Word {
+    Pieces(Vec<Piece>),
+    Block(String),
+}
+
+impl Word {
+    pub fn literal(text: &str) -> Word {
+        Word::Pieces(vec![Piece::Text(String::from(text))])
+    }
+}
+
+/// A command and its arguments
+pub(crate) type Command = Vec<Word>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Cond {
+    /// at the start, or after `;` or a newline
+    Always,
+    /// after `&&`: only if the statement before it succeeded
+    IfOk,
+}
+
+/// Commands joined by `|`, each one's output going on the end of the next one's arguments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Statement {
+    pub cond: Cond,
+    pub pipeline: Vec<Command>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Word(Word),
+    Sep(Cond),
+    Pipe,
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Reads the variable after a `$`; returns None if it isn't one, and the `$` is just a `$`.
+fn variable(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<Option<String>, String> {
+    match chars.peek() {
+        Some('?') | Some('*') => Ok(Some(chars.next().unwrap().to_string())),
+        Some('{') => {
+            chars.next();
+            let mut name = String::new();
+            loop {
+                match chars.next() {
+                    Some('}') => break,
+                    Some(c) if is_name_char(c) => name.push(c),
+                    _ => return Err(String::from("bad ${...}")),
+                }
+            }
+            Ok(Some(name))
+        }
+        Some(c) if c.is_ascii_digit() => Ok(Some(chars.next().unwrap().to_string())),
+        Some(&c) if is_name_char(c) => {
+            let mut name = String::new();
+            while let Some(&c) = chars.peek() {
+                if !is_name_char(c) {
+                    break;
+                }
+                name.push(c);
+                chars.next();
+            }
+            Ok(Some(name))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Reads a block's text, up to its matching `}`. Quotes are respected, so a `}` inside one doesn't
+/// end the block.
+fn block(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, String> {
+    let mut text = String::new();
+    let mut depth = 1;
+    let mut quote: Option<char> = None;
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (Some('"'), '\\') | (None, '\\') => {
+                text.push(c);
+                match chars.next() {
+                    Some(escaped) => text.push(escaped),
+                    None => break,
+                }
+                continue;
+            }
+            (None, '{') => depth += 1,
+            (None, '}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(String::from(text.trim()));
+                }
+            }
+            _ => (),
+        }
+        text.push(c);
+    }
+    Err(String::from("missing }"))
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut pieces: Vec<Piece> = Vec::new();
+    let mut literal = String::new();
+    // a word is open, even if it's empty so far (as with "")
+    let mut in_word = false;
+
+    fn end_word(tokens: &mut Vec<Token>, pieces: &mut Vec<Piece>, literal: &mut String, in_word: &mut bool) {
+        if !literal.is_empty() {
+            pieces.push(Piece::Text(std::mem::take(literal)));
+        }
+        if *in_word {
+            tokens.push(Token::Word(Word::Pieces(std::mem::take(pieces))));
+        }
+        *in_word = false;
+    }
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\r' => end_word(&mut tokens, &mut pieces, &mut literal, &mut in_word),
+            '\n' | ';' => {
+                end_word(&mut tokens, &mut pieces, &mut literal, &mut in_word);
+                tokens.push(Token::Sep(Cond::Always));
+            }
+            '&' if chars.peek() == Some(&'&') => {
+                chars.next();
+                end_word(&mut tokens, &mut pieces, &mut literal, &mut in_word);
+                tokens.push(Token::Sep(Cond::IfOk));
+            }
+            '|' => {
+                end_word(&mut tokens, &mut pieces, &mut literal, &mut in_word);
+                tokens.push(Token::Pipe);
+            }
+            '#' if !in_word => {
+                while let Some(&c) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    chars.next();
+                }
+            }
+            '{' if !in_word => tokens.push(Token::Word(Word::Block(block(&mut chars)?))),
+            '}' if !in_word => return Err(String::from("unexpected }")),
+            '\\' => {
+                in_word = true;
+                literal.push(chars.next().ok_or_else(|| String::from("\\ at the end"))?);
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => literal.push(c),
+                        None => return Err(String::from("missing '")),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => literal.push(chars.next().ok_or_else(|| String::from("missing \""))?),
+                        Some('$') => match variable(&mut chars)? {
+                            Some(name) => {
+                                if !literal.is_empty() {
+                                    pieces.push(Piece::Text(std::mem::take(&mut literal)));
+                                }
+                                pieces.push(Piece::Var(name));
+                            }
+                            None => literal.push('$'),
+                        },
+                        Some(c) => literal.push(c),
+                        None => return Err(String::from("missing \"")),
+                    }
+                }
+            }
+            '$' => {
+                in_word = true;
+                match variable(&mut chars)? {
+                    Some(name) => {
+                        if !literal.is_empty() {
+                            pieces.push(Piece::Text(std::mem::take(&mut literal)));
+                        }
+                        pieces.push(Piece::Var(name));
+                    }
+                    None => literal.push('$'),
+                }
+            }
+            c => {
+                in_word = true;
+                literal.push(c);
+            }
+        }
+    }
+    end_word(&mut tokens, &mut pieces, &mut literal, &mut in_word);
+    Ok(tokens)
+}
+
+/// Splits a script into statements. Empty statements, as from `;;` or blank lines, are dropped.
+pub(crate) fn parse(text: &str) -> Result<Vec<Statement>, String> {
+    let mut statements = Vec::new();
+    let mut cond = Cond::Always;
+    let mut pipeline: Vec<Command> = Vec::new();
+    let mut command: Command = Vec::new();
+    for token in tokenize(text)? {
+        match token {
+            Token::Word(word) => command.push(word),
+            Token::Pipe => {
+                if command.is_empty() {
+                    return Err(String::from("nothing before |"));
+                }
+                pipeline.push(std::mem::take(&mut command));
+            }
+            Token::Sep(next) => {
+                if command.is_empty() {
+                    if !pipeline.is_empty() {
+                        return Err(String::from("nothing after |"));
+                    }
+                    if next == Cond::IfOk {
+                        return Err(String::from("nothing before &&"));
+                    }
+                    continue;
+                }
+                pipeline.push(std::mem::take(&mut command));
+                statements.push(Statement { cond, pipeline: std::mem::take(&mut pipeline) });
+                cond = next;
+            }
+        }
+    }
+    if command.is_empty() {
+        if !pipeline.is_empty() {
+            return Err(String::from("nothing after |"));
+        }
+        if cond == Cond::IfOk {
+            return Err(String::from("nothing after &&"));
+        }
+    } else {
+        pipeline.push(command);
+        statements.push(Statement { cond, pipeline });
+    }
+    Ok(statements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Piece {
+        Piece::Text(String::from(s))
+    }
+    fn var(s: &str) -> Piece {
+        Piece::Var(String::from(s))
+    }
+
+    #[test]
+    fn words_and_quotes() {
+        let s = parse(r#"echo  'a $b'  "c $d e" f\ g ${h}i """#).unwrap();
+        assert_eq!(s.len(), 1);
+        assert_eq!(s[0].pipeline, vec![vec![
+            Word::literal("echo"),
+            Word::Pieces(vec![text("a $b")]),
+            Word::Pieces(vec![text("c "), var("d"), text(" e")]),
+            Word::literal("f g"),
+            Word::Pieces(vec![var("h"), text("i")]),
+            Word::Pieces(vec![]),
+        ]]);
+        assert_eq!(parse("echo $? $* $12 $ 5$").unwrap()[0].pipeline[0][1..], [
+            Word::Pieces(vec![var("?")]),
+            Word::Pieces(vec![var("*")]),
+            Word::Pieces(vec![var("1"), text("2")]),
+            Word::literal("$"),
+            Word::literal("5$"),
+        ]);
+    }
+
+    #[test]
+    fn sequences_and_pipes() {
+        let s = parse("a 1; b && c | d\n\ne # comment; f\n;").unwrap();
+        let verbs: Vec<(Cond, Vec<&Word>)> = s.iter().map(|st| (st.cond, st.pipeline.iter().map(|c| &c[0]).collect())).collect();
+        assert_eq!(verbs, vec![
+            (Cond::Always, vec![&Word::literal("a")]),
+            (Cond::Always, vec![&Word::literal("b")]),
+            (Cond::IfOk, vec![&Word::literal("c"), &Word::literal("d")]),
+            (Cond::Always, vec![&Word::literal("e")]),
+        ]);
+        assert_eq!(parse(" ; ;\n").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn blocks() {
+        let s = parse("repeat 3 { echo \"}\" ; x { y } }; z").unwrap();
+        assert_eq!(s.len(), 2);
+        assert_eq!(s[0].pipeline[0][2], Word::Block(String::from("echo \"}\" ; x { y }")));
+        // a brace inside a word is just a brace
+        assert_eq!(parse("a{b}").unwrap()[0].pipeline[0][0], Word::literal("a{b}"));
+    }
+
+    #[test]
+    fn errors() {
+        for bad in ["a | ", "| a", "a &&", "&& a", "{ a", "a }", "'a", "\"a", "a \\", "${a"].iter() {
+            assert!(parse(bad).is_err(), "{} should not parse", bad);
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Aliases and scripts, kept in the PDDB: one key per name, holding the text.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+const ALIAS_DICT: &str = "shell.aliases";
+const SCRIPT_DICT: &str = "shell.scripts";
+/// longest alias or script name
+pub(crate) const MAX_NAME_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Alias,
+    Script,
+}
+
+impl Kind {
+    fn dict(&self) -> &'static str {
+        match self {
+            Kind::Alias => ALIAS_DICT,
+            Kind::Script => SCRIPT_DICT,
+        }
+    }
+}
+
+pub(crate) fn name_ok(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+pub(crate) struct Store {
+    /// None keeps everything in memory only
+    pddb: Option<pddb::Pddb>,
+    /// the PDDB has been read; until it's mounted, there are no aliases or scripts
+    loaded: bool,
+    aliases: BTreeMap<String, String>,
+    scripts: BTreeMap<String, String>,
+}
+
+impl Store {
+    pub fn new() -> Store {
+        Store {
+            pddb: Some(pddb::Pddb::new()),
+            loaded: false,
+            aliases: BTreeMap::new(),
+            scripts: BTreeMap::new(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn in_memory() -> Store {
+        Store {
+            pddb: None,
+            loaded: true,
+            aliases: BTreeMap::new(),
+            scripts: BTreeMap::new(),
+        }
+    }
+
+    fn map(&mut self, kind: Kind) -> &mut BTreeMap<String, String> {
+        match kind {
+            Kind::Alias => &mut self.aliases,
+            Kind::Script => &mut self.scripts,
+        }
+    }
+
+    /// Reads everything in once the PDDB is mounted; lookups happen for every command, so they're
+    /// served from memory.
+    fn load(&mut self) {
+        if self.loaded {
+            return;
+        }
+        let pddb = match self.pddb.as_mut() {
+            Some(pddb) if pddb.is_mounted() => pddb,
+            _ => return,
+        };
+        for kind in [Kind::Alias, Kind::Script].iter() {
+            let mut entries = BTreeMap::new();
+            for name in pddb.list_keys(kind.dict(), None).unwrap_or(Vec::new()) {
+                let mut text = String::new();
+                match pddb.get(kind.dict(), &name, None, false, false, None, None::<fn()>)
+                    .and_then(|mut key| key.read_to_string(&mut text))
+                {
+                    Ok(_) => {
+                        entries.insert(name, text);
+                    }
+                    Err(e) => log::warn!("couldn't read {}/{}: {:?}", kind.dict(), name, e),
+                }
+            }
+            match kind {
+                Kind::Alias => self.aliases = entries,
+                Kind::Script => self.scripts = entries,
+            }
+        }
+        log::info!("{} aliases and {} scripts loaded", self.aliases.len(), self.scripts.len());
+        self.loaded = true;
+    }
+
+    pub fn get(&mut self, kind: Kind, name: &str) -> Option<String> {
+        self.load();
+        self.map(kind).get(name).cloned()
+    }
+
+    pub fn names(&mut self, kind: Kind) -> Vec<String> {
+        self.load();
+        self.map(kind).keys().cloned().collect()
+    }
+
+    pub fn put(&mut self, kind: Kind, name: &str, text: &str) -> Result<(), String> {
+        if !name_ok(name) {
+            return Err(format!("bad name: {}", name));
+        }
+        self.load();
+        if !self.loaded {
+            return Err(String::from("the PDDB isn't mounted"));
+        }
+        if let Some(pddb) = self.pddb.as_mut() {
+            // the text may have gotten shorter; start the key over
+            pddb.delete_key(kind.dict(), name, None).ok();
+            pddb.get(kind.dict(), name, None, true, true, Some(text.len()), None::<fn()>)
+                .and_then(|mut key| key.write_all(text.as_bytes()))
+                .and_then(|_| pddb.sync())
+                .map_err(|e| format!("couldn't save {}: {:?}", name, e))?;
+        }
+        self.map(kind).insert(String::from(name), String::from(text));
+        Ok(())
+    }
+
+    /// Returns false if there was no such name
+    pub fn remove(&mut self, kind: Kind, name: &str) -> Result<bool, String> {
+        self.load();
+        if !self.loaded {
+            return Err(String::from("the PDDB isn't mounted"));
+        }
+        if self.map(kind).remove(name).is_none() {
+            return Ok(false);
+        }
+        if let Some(pddb) = self.pddb.as_mut() {
+            pddb.delete_key(kind.dict(), name, None)
+                .and_then(|_| pddb.sync())
+                .map_err(|e| format!("couldn't delete {}: {:?}", name, e))?;
+        }
+        Ok(true)
+    }
+}
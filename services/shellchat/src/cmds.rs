@@ -98,6 +98,8 @@ mod wlan;     use wlan::*;
 mod jtag_cmd; use jtag_cmd::*;
 mod net_cmd;  use net_cmd::*;
 mod pddb_cmd; use pddb_cmd::*;
+mod log_cmd;  use log_cmd::*;
+mod ps;       use ps::*;
 
 #[cfg(feature="tts")]
 mod tts;
@@ -138,6 +140,7 @@ pub struct CmdEnv {
     jtag_cmd: JtagCmd,
     net_cmd: NetCmd,
     pddb_cmd: PddbCmd,
+    log_cmd: LogCmd,
     wlan_cmd: Wlan,
 
     #[cfg(feature="tts")]
@@ -206,6 +209,7 @@ impl CmdEnv {
             jtag_cmd: JtagCmd::new(&xns),
             net_cmd: NetCmd::new(&xns),
             pddb_cmd: PddbCmd::new(&xns),
+            log_cmd: LogCmd::new(&xns),
             wlan_cmd: Wlan::new(),
 
             #[cfg(feature="tts")]
@@ -229,6 +233,7 @@ impl CmdEnv {
         let mut backlight_cmd = Backlight{};
         let mut accel_cmd = Accel{};
         let mut console_cmd = Console{};
+        let mut ps_cmd = Ps{};
         let commands: &mut [& mut dyn ShellCmdApi] = &mut [
             ///// 4. add your command to this array, so that it can be looked up and dispatched
             &mut echo_cmd,
@@ -252,6 +257,8 @@ impl CmdEnv {
             &mut self.jtag_cmd,
             &mut self.net_cmd,
             &mut self.pddb_cmd,
+            &mut self.log_cmd,
+            &mut ps_cmd,
 
             #[cfg(feature="tts")]
             &mut self.tts_cmd,
@@ -333,6 +340,17 @@ impl CmdEnv {
     }
 }
 
+/// lets scripts, aliases and pipelines run shell commands
+impl shell_script::Host for CmdEnv {
+    fn run(&mut self, line: &str) -> Result<Option<std::string::String>, xous::Error> {
+        let ret = self.dispatch(Some(&mut String::<1024>::from_str(line)), None)?;
+        Ok(ret.map(|r| std::string::String::from(r.as_str().unwrap_or("UTF-8 Error"))))
+    }
+    fn sleep_ms(&mut self, ms: usize) {
+        self.common_env.ticktimer.sleep_ms(ms).unwrap();
+    }
+}
+
 /// extract the first token, as delimited by spaces
 /// modifies the incoming line by removing the token and returning the remainder
 /// returns the found token
@@ -264,12 +264,31 @@ block_cipher_test!(aes128soft_test, "aes128", AES128_TESTS, Aes128Soft);
 block_cipher_test!(aes256_test, "aes256", AES256_TESTS, Aes256);
 block_cipher_test!(aes256soft_test, "aes256", AES256_TESTS, Aes256);
 
+/// CTR, CBC, GCM and GCM-SIV known answers, over the given AES-128 and AES-256 ciphers
+fn modes_test<C128, C256>(label: &str) -> String::<1024>
+where
+    C128: NewBlockCipher<KeySize = cipher::consts::U16> + BlockEncrypt + BlockDecrypt
+        + cipher::BlockCipher<BlockSize = cipher::consts::U16, ParBlocks = cipher::consts::U8>,
+    C256: NewBlockCipher<KeySize = cipher::consts::U32> + BlockEncrypt + BlockDecrypt
+        + cipher::BlockCipher<BlockSize = cipher::consts::U16, ParBlocks = cipher::consts::U8>,
+{
+    let mut ret = String::<1024>::new();
+    match aes::modes::selftest::check_aes128::<C128>() {
+        Ok(()) => match aes::modes::selftest::check_aes256::<C256>() {
+            Ok(()) => write!(ret, "[{}] AES modes passed", label).unwrap(),
+            Err(what) => write!(ret, "[{}] AES-256 {} FAILED", label, what).unwrap(),
+        },
+        Err(what) => write!(ret, "[{}] AES-128 {} FAILED", label, what).unwrap(),
+    }
+    ret
+}
+
 impl<'a> ShellCmdApi<'a> for Aes {
     cmd_api!(aes); // inserts boilerplate for command API
 
     fn process(&mut self, args: String::<1024>, env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
         let mut ret = String::<1024>::new();
-        let helpstring = "Aes [check128] [check128sw] [check256] [check256sw] [hwbench] [swbench] [susres]";
+        let helpstring = "Aes [check128] [check128sw] [check256] [check256sw] [checkmodes] [checkmodessw] [hwbench] [swbench] [susres]";
 
         let mut tokens = args.as_str().unwrap().split(' ');
 
@@ -287,6 +306,12 @@ impl<'a> ShellCmdApi<'a> for Aes {
                 "check256sw" => {
                     write!(ret, "{}", aes256soft_test()).unwrap();
                 }
+                "checkmodes" => {
+                    write!(ret, "{}", modes_test::<Aes128, Aes256>("hw")).unwrap();
+                }
+                "checkmodessw" => {
+                    write!(ret, "{}", modes_test::<Aes128Soft, Aes256Soft>("sw")).unwrap();
+                }
                 "hwbench" => {
                     let start = env.ticktimer.elapsed_ms();
                     self.start_time = Some(start);
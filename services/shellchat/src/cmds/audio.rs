@@ -2,13 +2,15 @@ use crate::{ShellCmdApi,CommonEnv};
 use xous_ipc::String;
 
 //use core::convert::TryFrom;
-use codec::*;
+use codec::ZERO_PCM;
+use mixer::*;
 use xous::MessageEnvelope;
 
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct Audio {
-    codec: codec::Codec,
+    mixer: Mixer,
+    stream: Option<MixerStream>,
     sample: xous::MemoryRange,
     header: Header,
     raw_data: *const u32,
@@ -40,7 +42,7 @@ impl Audio {
             xous::MemoryFlags::R,
         ).expect("couldn't map in the audio sample");
 
-        let codec = codec::Codec::new(xns).unwrap();
+        let mixer = Mixer::new(xns).unwrap();
         let samples: *const [u8; 16] = unsafe{sample.as_ptr().add(20)} as *const [u8; 16];
         let mut raw_header: [u8; 16] = [0; 16];
         for i in 0..16 {
@@ -54,7 +56,8 @@ impl Audio {
         ).expect("couldn't allocate record buffer");
 
         let audio = Audio {
-            codec,
+            mixer,
+            stream: None,
             sample,
             header: Header::from(raw_header),
             raw_data: unsafe{sample.as_ptr().add(44)} as *const u32,
@@ -86,15 +89,20 @@ impl<'a> ShellCmdApi<'a> for Audio {
         if let Some(sub_cmd) = tokens.next() {
             match sub_cmd {
                 "play" => {
-                    log::trace!("setting up codec hardware parameters");
-                    self.codec.setup_8k_stream().expect("couldn't set the CODEC to expected defaults");
-                    env.ticktimer.sleep_ms(50).unwrap();
-
-                    if self.callback_id.is_none() {
-                        let cb_id = env.register_handler(String::<256>::from_str(self.verb()));
+                    if self.stream.is_none() {
+                        log::trace!("opening a mixer stream");
+                        let mut stream = self.mixer.open_stream(StreamFormat::new(8000, Channels::Stereo, SampleFormat::I16), Priority::Normal, 100)
+                            .expect("couldn't open a mixer stream");
+                        // the microphone is recorded through the same stream, for "fromrec"
+                        stream.set_capture(true).unwrap();
+                        let cb_id = match self.callback_id {
+                            Some(id) => id,
+                            None => env.register_handler(String::<256>::from_str(self.verb())),
+                        };
                         log::trace!("hooking frame callback with ID {}", cb_id);
-                        self.codec.hook_frame_callback(cb_id, self.callback_conn).unwrap(); // any non-handled IDs get routed to our callback port
+                        stream.hook_frame_callback(cb_id, self.callback_conn).unwrap(); // any non-handled IDs get routed to our callback port
                         self.callback_id = Some(cb_id);
+                        self.stream = Some(stream);
                     }
 
                     if self.play_or_rec_n == true {
@@ -106,17 +114,17 @@ impl<'a> ShellCmdApi<'a> for Audio {
                     self.rec_ptr_words = 0;
 
                     log::info!("starting playback");
-                    self.codec.resume().unwrap();
+                    self.stream.as_ref().unwrap().resume().unwrap();
 
                     // we'll get a callback that demands the next data...
                 }
                 "stop" => {
-                    self.codec.abort().unwrap(); // this should stop callbacks from occurring too.
+                    // closing the stream stops the callbacks; the mixer powers down the CODEC once nothing is playing
+                    self.stream.take();
                     write!(ret, "Playback stopped at {} frames.", self.framecount).unwrap();
                     self.framecount = 0;
                     self.play_ptr_bytes = 0;
                     self.rec_ptr_words = 0;
-                    self.codec.power_off().unwrap();
                 }
                 "fromrec" => {
                     self.play_or_rec_n = false;
@@ -158,10 +166,14 @@ impl<'a> ShellCmdApi<'a> for Audio {
 
         log::debug!("audio callback");
         let mut ret = String::<1024>::new();
-        xous::msg_scalar_unpack!(msg, free_play, _avail_rec, _, _, {
-            if self.play_ptr_bytes + codec::FIFO_DEPTH *4 < self.raw_len_bytes as usize {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => return Ok(None), // a callback that was in flight when the stream was closed
+        };
+        xous::msg_scalar_unpack!(msg, free_play, _, _, _, {
+            if self.play_ptr_bytes + FIFO_DEPTH *4 < self.raw_len_bytes as usize {
                 log::debug!("{} extending playback", free_play);
-                let mut frames: FrameRing = FrameRing::new();
+                let mut frames = stream.frame_ring();
                 let frames_to_push = if frames.writeable_count() < free_play {
                     frames.writeable_count()
                 } else {
@@ -170,29 +182,30 @@ impl<'a> ShellCmdApi<'a> for Audio {
                 self.framecount += frames_to_push as u32;
                 log::debug!("f{} p{}", self.framecount, frames_to_push);
                 for _ in 0..frames_to_push {
-                    let mut frame: [u32; codec::FIFO_DEPTH] = [ZERO_PCM as u32 | (ZERO_PCM as u32) << 16; codec::FIFO_DEPTH];
+                    let mut frame: [u32; FIFO_DEPTH] = [ZERO_PCM as u32 | (ZERO_PCM as u32) << 16; FIFO_DEPTH];
                     if self.play_or_rec_n {
-                        for i in 0..codec::FIFO_DEPTH {
+                        for i in 0..FIFO_DEPTH {
                             frame[i] = unsafe{*self.raw_data.add(i + self.play_ptr_bytes/4)};
                         }
                     } else {
-                        for i in 0..codec::FIFO_DEPTH {
+                        for i in 0..FIFO_DEPTH {
                             frame[i] = unsafe{*self.rec_data.add(i + self.play_ptr_bytes/4)};
                         }
                     }
-                    self.play_ptr_bytes += codec::FIFO_DEPTH * 4;
+                    self.play_ptr_bytes += FIFO_DEPTH * 4;
                     frames.nq_frame(frame).unwrap();
 
                 }
-                self.codec.swap_frames(&mut frames).unwrap();
+                stream.write_frames(&mut frames).unwrap();
 
+                stream.read_frames(&mut frames).unwrap();
                 loop {
                     if let Some(frame) = frames.dq_frame() {
-                        if self.rec_ptr_words < (0x8_0000/4 - codec::FIFO_DEPTH) as u32 {
-                            for i in 0..codec::FIFO_DEPTH {
+                        if self.rec_ptr_words < (0x8_0000/4 - FIFO_DEPTH) as u32 {
+                            for i in 0..FIFO_DEPTH {
                                 unsafe{*self.rec_data.add(i + self.rec_ptr_words as usize) = frame[i]};
                             }
-                            self.rec_ptr_words += codec::FIFO_DEPTH as u32;
+                            self.rec_ptr_words += FIFO_DEPTH as u32;
                         } else {
                             // just silently toss any overrun for now
                         }
@@ -205,12 +218,11 @@ impl<'a> ShellCmdApi<'a> for Audio {
             } else {
                 log::debug!("stopping playback");
                 if self.framecount != 0 {
-                    self.codec.abort().unwrap(); // this should stop callbacks from occurring too.
+                    self.stream.take(); // this stops callbacks from occurring too.
                     write!(ret, "Playback of {} frames finished", self.framecount).unwrap();
                     self.framecount = 0;
                     self.play_ptr_bytes = 0;
                     self.rec_ptr_words = 0;
-                    self.codec.power_off().unwrap();
                 } else {
                     // we will get extra callbacks as the pipe clears
                     return Ok(None)
@@ -0,0 +1,173 @@
+use crate::{ShellCmdApi, CommonEnv};
+use xous_ipc::String;
+use std::io::Read;
+
+/// Marks the start and end of `log export` on the console; `tools/log_export.py` looks for these
+const EXPORT_BEGIN: &str = "=== xous log export begin ===";
+const EXPORT_END: &str = "=== xous log export end ===";
+
+pub struct LogCmd {
+    store: log_store::LogStore,
+    pddb: pddb::Pddb,
+}
+impl LogCmd {
+    pub fn new(xns: &xous_names::XousNames) -> LogCmd {
+        LogCmd {
+            store: log_store::LogStore::new(xns).unwrap(),
+            pddb: pddb::Pddb::new(),
+        }
+    }
+    /// The stored segments, oldest first
+    fn segments(&mut self) -> Vec<u32> {
+        let mut segments: Vec<u32> = self.pddb.list_keys(log_store::LOG_STORE_DICT, None)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|k| log_store::ring::parse_segment_key(k))
+            .collect();
+        segments.sort();
+        segments
+    }
+    fn read_segment(&mut self, segment: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        if let Ok(mut key) = self.pddb.get(log_store::LOG_STORE_DICT, &log_store::ring::segment_key(segment),
+            None, false, false, None, None::<fn()>) {
+            key.read_to_end(&mut data).ok();
+        }
+        data
+    }
+}
+
+fn parse_level(level: &str) -> Option<Option<log::LevelFilter>> {
+    match level {
+        "off" => Some(Some(log::LevelFilter::Off)),
+        "error" => Some(Some(log::LevelFilter::Error)),
+        "warn" => Some(Some(log::LevelFilter::Warn)),
+        "info" => Some(Some(log::LevelFilter::Info)),
+        "debug" => Some(Some(log::LevelFilter::Debug)),
+        "trace" => Some(Some(log::LevelFilter::Trace)),
+        "default" => Some(None),
+        _ => None,
+    }
+}
+
+impl<'a> ShellCmdApi<'a> for LogCmd {
+    cmd_api!(log); // inserts boilerplate for command API
+
+    fn process(&mut self, args: String::<1024>, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+        use core::fmt::Write;
+        let mut ret = String::<1024>::new();
+        let helpstring = "log [level <module|*> <off|error|warn|info|debug|trace|default>] [level clear] [format text|json] [persist on|off] [status] [tail [lines]] [export] [clear]";
+
+        let mut tokens = args.as_str().unwrap().split(' ');
+        if let Some(sub_cmd) = tokens.next() {
+            match sub_cmd {
+                "level" => {
+                    match (tokens.next(), tokens.next()) {
+                        (Some("clear"), None) => {
+                            match log_server::clear_module_levels() {
+                                Ok(()) => write!(ret, "Module levels cleared").unwrap(),
+                                Err(e) => write!(ret, "Couldn't clear module levels: {:?}", e).unwrap(),
+                            }
+                        }
+                        (Some(module), Some(level)) => {
+                            match parse_level(level) {
+                                Some(filter) => match log_server::set_module_level(module, filter) {
+                                    Ok(()) => write!(ret, "{} set to {}", module, level).unwrap(),
+                                    Err(e) => write!(ret, "Couldn't set {}: {:?}", module, e).unwrap(),
+                                },
+                                None => write!(ret, "Unknown level {}", level).unwrap(),
+                            }
+                        }
+                        _ => write!(ret, "log level <module|*> <off|error|warn|info|debug|trace|default>, or log level clear").unwrap(),
+                    }
+                }
+                "format" => {
+                    let format = match tokens.next() {
+                        Some("text") => Some(log_server::api::FORMAT_TEXT),
+                        Some("json") => Some(log_server::api::FORMAT_JSON_LINES),
+                        _ => None,
+                    };
+                    match format {
+                        Some(format) => match log_server::set_output_format(format) {
+                            Ok(()) => write!(ret, "Log format set").unwrap(),
+                            Err(e) => write!(ret, "Couldn't set the log format: {:?}", e).unwrap(),
+                        },
+                        None => write!(ret, "log format text|json").unwrap(),
+                    }
+                }
+                "persist" => {
+                    match tokens.next() {
+                        Some("on") => {
+                            self.store.set_enabled(true).unwrap();
+                            let (enabled, _) = self.store.status().unwrap();
+                            if enabled {
+                                write!(ret, "The log will be kept in the PDDB").unwrap();
+                            } else {
+                                write!(ret, "Couldn't keep the log: another process is taking it").unwrap();
+                            }
+                        }
+                        Some("off") => {
+                            self.store.set_enabled(false).unwrap();
+                            write!(ret, "The log won't be kept; use `log clear` to delete what's stored").unwrap();
+                        }
+                        _ => write!(ret, "log persist on|off").unwrap(),
+                    }
+                }
+                "status" => {
+                    let (enabled, bytes) = self.store.status().unwrap();
+                    write!(ret, "Persistent log {}, {} bytes in {} segments",
+                        if enabled { "on" } else { "off" }, bytes, self.segments().len()).unwrap();
+                }
+                "clear" => {
+                    self.store.clear().unwrap();
+                    write!(ret, "Stored log deleted").unwrap();
+                }
+                "tail" => {
+                    let lines = tokens.next().and_then(|n| n.parse::<usize>().ok()).unwrap_or(10);
+                    self.store.sync().unwrap();
+                    // the newest segments, enough to hold the lines asked for
+                    let mut text = Vec::new();
+                    for segment in self.segments().into_iter().rev() {
+                        let mut older = self.read_segment(segment);
+                        older.append(&mut text);
+                        text = older;
+                        if text.iter().filter(|&&b| b == b'\n').count() > lines {
+                            break;
+                        }
+                    }
+                    let text = std::string::String::from_utf8_lossy(&text);
+                    let all: Vec<&str> = text.lines().collect();
+                    // most recent last; whatever doesn't fit in the reply is cut from the front
+                    let mut tail = std::string::String::new();
+                    for line in all[all.len().saturating_sub(lines)..].iter().rev() {
+                        if tail.len() + line.len() + 1 > 1000 {
+                            break;
+                        }
+                        tail.insert_str(0, &format!("{}\n", line));
+                    }
+                    write!(ret, "{}", tail).unwrap();
+                }
+                "export" => {
+                    self.store.sync().unwrap();
+                    // straight to the console: stdout isn't kept, so this doesn't feed back into the log
+                    println!("{}", EXPORT_BEGIN);
+                    let mut total = 0;
+                    for segment in self.segments() {
+                        let data = self.read_segment(segment);
+                        total += data.len();
+                        print!("{}", std::string::String::from_utf8_lossy(&data));
+                    }
+                    println!("{}", EXPORT_END);
+                    write!(ret, "Exported {} bytes to the console", total).unwrap();
+                }
+                _ => {
+                    write!(ret, "{}", helpstring).unwrap();
+                }
+            }
+
+        } else {
+            write!(ret, "{}", helpstring).unwrap();
+        }
+        Ok(Some(ret))
+    }
+}
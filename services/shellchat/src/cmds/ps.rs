@@ -0,0 +1,65 @@
+use crate::{ShellCmdApi, CommonEnv};
+use xous_ipc::String;
+
+#[derive(Debug)]
+pub struct Ps {
+}
+
+impl<'a> ShellCmdApi<'a> for Ps {
+    cmd_api!(ps); // inserts boilerplate for command API
+
+    fn process(&mut self, args: String::<1024>, env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+        use core::fmt::Write;
+        let mut ret = String::<1024>::new();
+        let helpstring = "ps [dead | <part of a server name>]";
+
+        let services = match env.xns.list_services() {
+            Ok(services) => services,
+            Err(xous::Error::AccessDenied) => {
+                write!(ret, "Not allowed to list services").unwrap();
+                return Ok(Some(ret));
+            }
+            Err(e) => return Err(e),
+        };
+        let filter = args.as_str().unwrap().trim();
+        let shown: Vec<&xous_names::api::ServiceInfo> = match filter {
+            "" => services.iter().collect(),
+            "dead" => services.iter().filter(|s| !s.alive).collect(),
+            "help" => {
+                write!(ret, "{}", helpstring).unwrap();
+                return Ok(Some(ret));
+            }
+            _ => services.iter().filter(|s| s.name.contains(filter)).collect(),
+        };
+        let dead = services.iter().filter(|s| !s.alive).count();
+        write!(ret, "{} servers, {} dead\n", services.len(), dead).unwrap();
+
+        // one line each; the full details only fit if the list was narrowed down
+        let detailed = shown.len() <= 6;
+        for (i, s) in shown.iter().enumerate() {
+            let mut line = std::string::String::new();
+            let pid = s.pid.map(|p| p.get()).unwrap_or(0);
+            let state = if s.alive { "" } else { " DEAD" };
+            if detailed {
+                let limit = match s.conn_limit {
+                    Some(max) => format!("{}", max),
+                    None => "-".to_string(),
+                };
+                line.push_str(&format!("{} pid {}{}: {}/{} conns", s.name, pid, state, s.conns, limit));
+                if s.auth_limit > 0 {
+                    line.push_str(&format!(", {}/{} auth", s.auth_conns, s.auth_limit));
+                }
+                line.push_str(&format!(", registered at {}s\n", s.registered_ms / 1000));
+            } else {
+                line.push_str(&format!("{} {} {}{}\n", pid, s.conns, s.name, state));
+            }
+            // leave room for the note on what didn't fit
+            if ret.len() + line.len() > 1024 - 40 {
+                write!(ret, "...{} more, try ps <name>", shown.len() - i).unwrap();
+                break;
+            }
+            ret.append(&line)?;
+        }
+        Ok(Some(ret))
+    }
+}
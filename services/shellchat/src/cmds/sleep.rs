@@ -45,7 +45,7 @@ impl<'a> ShellCmdApi<'a> for Sleep {
         use core::fmt::Write;
 
         let mut ret = String::<1024>::new();
-        let helpstring = "sleep [now] [current] [ship] [kill] [coldboot] [killbounce] [sus] [stress] [crypton] [cryptoff] [wfioff] [wfion] [debugwfi]";
+        let helpstring = "sleep [now] [current] [ship] [kill] [coldboot] [killbounce] [sus] [sim ms] [stress] [crypton] [cryptoff] [wfioff] [wfion] [debugwfi]";
 
         let mut tokens = args.as_str().unwrap().split(' ');
 
@@ -68,6 +68,20 @@ impl<'a> ShellCmdApi<'a> for Sleep {
                     // the message below is sent after we wake up
                     write!(ret, "Resumed from sleep!").unwrap();
                 }
+                "sim" => {
+                    // hosted mode only: a whole suspend cycle, without powering anything down
+                    let sleep_ms = tokens.next().and_then(|t| t.parse::<u32>().ok()).unwrap_or(5000);
+                    match self.susres.simulate_suspend(sleep_ms) {
+                        Ok(report) => {
+                            if report.clean {
+                                write!(ret, "Simulated a clean suspend of {} ms", sleep_ms).unwrap();
+                            } else {
+                                write!(ret, "Simulated suspend of {} ms was forced: {} servers didn't report in", sleep_ms, report.late).unwrap();
+                            }
+                        }
+                        Err(_) => write!(ret, "Simulated suspend refused. It only works in hosted mode, with XOUS_SUSRES_SIM set.").unwrap(),
+                    }
+                }
                 "stress" => {
                     let _ = thread::spawn({
                         move || {
@@ -3,7 +3,8 @@ use crate::{ShellCmdApi,CommonEnv};
 use xous_ipc::String;
 use xous::{MessageEnvelope, Message};
 
-use codec::*;
+use codec::{VolumeOps, ZERO_PCM};
+use mixer::{Channels, FIFO_DEPTH, MixerStream, Priority, SampleFormat, StreamFormat};
 use spectrum_analyzer::{FrequencyLimit, FrequencySpectrum, samples_fft_to_spectrum};
 use spectrum_analyzer::windows::hann_window;
 use core::fmt::Write;
@@ -19,7 +20,9 @@ static AUDIO_OQC: AtomicBool = AtomicBool::new(false);
 pub struct Test {
     state: u32,
     // audio
-    codec: codec::Codec,
+    codec: codec::Codec, // only for picking the outputs; the tone itself goes through the mixer
+    mixer: mixer::Mixer,
+    stream: Option<MixerStream>,
     recbuf: xous::MemoryRange,
     callback_id: Option<u32>,
     callback_conn: u32,
@@ -43,6 +46,7 @@ pub struct Test {
 impl Test {
     pub fn new(xns: &xous_names::XousNames) -> Self {
         let codec = codec::Codec::new(xns).unwrap();
+        let mixer = mixer::Mixer::new(xns).unwrap();
 
         let recbuf = xous::syscall::map_memory(
             None,
@@ -55,6 +59,8 @@ impl Test {
 
         Test {
             codec,
+            mixer,
+            stream: None,
             recbuf,
             state: 0,
             callback_id: None,
@@ -77,6 +83,25 @@ impl Test {
             jtag: jtag::Jtag::new(&xns).unwrap(),
         }
     }
+    /// Opens a mixer stream for the test tone, recording the microphone alongside it, and starts it
+    fn start_audio(&mut self, env: &mut CommonEnv) {
+        if self.stream.is_none() {
+            let mut stream = self.mixer.open_stream(StreamFormat::new(SAMPLE_RATE_HZ as u32, Channels::Stereo, SampleFormat::I16), Priority::Normal, 100)
+                .expect("couldn't open a mixer stream");
+            stream.set_capture(true).unwrap();
+            let cb_id = match self.callback_id {
+                Some(id) => id,
+                None => env.register_handler(String::<256>::from_str(self.verb())),
+            };
+            log::trace!("hooking frame callback with ID {}", cb_id);
+            stream.hook_frame_callback(cb_id, self.callback_conn).unwrap(); // any non-handled IDs get routed to our callback port
+            self.callback_id = Some(cb_id);
+            self.stream = Some(stream);
+        }
+        self.play_sample = 0.0;
+        self.rec_sample = 0;
+        self.stream.as_ref().unwrap().resume().unwrap();
+    }
 }
 
 const SAMPLE_RATE_HZ: f32 = 8000.0;
@@ -326,9 +351,6 @@ impl<'a> ShellCmdApi<'a> for Test {
                         self.right_play = true;
                         self.speaker_play = true;
                     }
-                    self.codec.setup_8k_stream().expect("couldn't set the CODEC to expected defaults");
-                    env.ticktimer.sleep_ms(50).unwrap();
-
                     if self.speaker_play {
                         self.codec.set_speaker_volume(VolumeOps::RestoreDefault, None).unwrap();
                     } else {
@@ -340,27 +362,16 @@ impl<'a> ShellCmdApi<'a> for Test {
                         self.codec.set_headphone_volume(VolumeOps::Mute, None).unwrap();
                     }
 
-                    if self.callback_id.is_none() {
-                        let cb_id = env.register_handler(String::<256>::from_str(self.verb()));
-                        log::trace!("hooking frame callback with ID {}", cb_id);
-                        self.codec.hook_frame_callback(cb_id, self.callback_conn).unwrap(); // any non-handled IDs get routed to our callback port
-                        self.callback_id = Some(cb_id);
-                    }
-
-                    self.play_sample = 0.0;
-                    self.rec_sample = 0;
-
-                    self.codec.resume().unwrap();
+                    self.start_audio(env);
                     log::info!("{}|ASTART|{}|{}|{}|", SENTINEL, self.freq, self.left_play, self.right_play);
 
                 }
                 "astop" => {
-                    self.codec.abort().unwrap(); // this should stop callbacks from occurring too.
+                    self.stream.take(); // closing the stream stops callbacks from occurring too.
                     write!(ret, "Playback stopped at {} frames.", self.framecount).unwrap();
                     self.framecount = 0;
                     self.play_sample = 0.0;
                     self.rec_sample = 0;
-                    self.codec.power_off().unwrap();
 
                     // now do FFT analysis on the sample buffer
                     // analyze one channel at a time
@@ -553,20 +564,10 @@ impl<'a> ShellCmdApi<'a> for Test {
                     self.left_play = true;
                     self.right_play = true;
                     self.speaker_play = true;
-                    self.codec.setup_8k_stream().expect("couldn't set the CODEC to expected defaults");
-                    env.ticktimer.sleep_ms(50).unwrap();
                     self.codec.set_speaker_volume(VolumeOps::RestoreDefault, None).unwrap();
                     self.codec.set_headphone_volume(VolumeOps::RestoreDefault, None).unwrap();
-                    if self.callback_id.is_none() {
-                        let cb_id = env.register_handler(String::<256>::from_str(self.verb()));
-                        log::trace!("hooking frame callback with ID {}", cb_id);
-                        self.codec.hook_frame_callback(cb_id, self.callback_conn).unwrap(); // any non-handled IDs get routed to our callback port
-                        self.callback_id = Some(cb_id);
-                    }
-                    self.play_sample = 0.0;
-                    self.rec_sample = 0;
                     self.oqc_start = env.ticktimer.elapsed_ms();
-                    self.codec.resume().unwrap();
+                    self.start_audio(env);
 
                     env.llio.wfi_override(false).unwrap();
                 }
@@ -618,9 +619,13 @@ impl<'a> ShellCmdApi<'a> for Test {
         const AMPLITUDE: f32 = 0.8;
 
         match &msg.body {
-            Message::Scalar(xous::ScalarMessage{id: _, arg1: free_play, arg2: _avail_rec, arg3: _, arg4: _}) => {
+            Message::Scalar(xous::ScalarMessage{id: _, arg1: free_play, arg2: _, arg3: _, arg4: _}) => {
+                let stream = match self.stream.as_mut() {
+                    Some(stream) => stream,
+                    None => return Ok(None), // a callback that was in flight when the stream was closed
+                };
                 log::debug!("{} extending playback", free_play);
-                let mut frames: FrameRing = FrameRing::new();
+                let mut frames = stream.frame_ring();
                 let frames_to_push = if frames.writeable_count() < *free_play {
                     frames.writeable_count()
                 } else {
@@ -630,7 +635,7 @@ impl<'a> ShellCmdApi<'a> for Test {
 
                 log::debug!("f{} p{}", self.framecount, frames_to_push);
                 for _ in 0..frames_to_push {
-                    let mut frame: [u32; codec::FIFO_DEPTH] = [ZERO_PCM as u32 | (ZERO_PCM as u32) << 16; codec::FIFO_DEPTH];
+                    let mut frame: [u32; FIFO_DEPTH] = [ZERO_PCM as u32 | (ZERO_PCM as u32) << 16; FIFO_DEPTH];
                     // put the "expensive" f32 comparison outside the cosine wave table computation loop
                     let omega = self.freq * 2.0 * std::f32::consts::PI / SAMPLE_RATE_HZ;
                     for sample in frame.iter_mut() {
@@ -644,9 +649,10 @@ impl<'a> ShellCmdApi<'a> for Test {
                     frames.nq_frame(frame).unwrap();
 
                 }
-                self.codec.swap_frames(&mut frames).unwrap();
+                stream.write_frames(&mut frames).unwrap();
 
                 if !AUDIO_OQC.load(Ordering::Relaxed) {
+                    stream.read_frames(&mut frames).unwrap();
                     let rec_samples = self.recbuf.as_slice_mut::<u32>();
                     let rec_len = rec_samples.len();
                     loop {
@@ -675,7 +681,7 @@ impl<'a> ShellCmdApi<'a> for Test {
                         _ => self.freq = 659.25,
                     }
                     if elapsed - self.oqc_start > 6000 {
-                        self.codec.abort().unwrap();
+                        self.stream.take();
 
                         // put system automatically into ship mode at conclusion of test
                         env.gam.shipmode_blank_request().unwrap();
@@ -16,7 +16,7 @@ impl<'a> ShellCmdApi<'a> for TrngCmd {
     fn process(&mut self, args: String::<1024>, env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
         use core::fmt::Write;
         let mut ret = String::<1024>::new();
-        let helpstring = "trng [avnist] [ronist] [runs] [excur] [errs] [pump]";
+        let helpstring = "trng [avnist] [ronist] [runs] [excur] [errs] [pump] [health] [fault N]";
 
         let mut tokens = args.as_str().unwrap().split(' ');
 
@@ -70,6 +70,18 @@ impl<'a> ShellCmdApi<'a> for TrngCmd {
                 "errs" => {
                     write!(ret, "TRNG error stats: {:?}", env.trng.get_error_stats().unwrap()).unwrap();
                 }
+                "health" => {
+                    let hs = env.trng.get_health_status().unwrap();
+                    write!(ret, "{:?}: {} failures, {} refused, {} deferred\n{} reseeds, {} words served",
+                        hs.state, hs.failures, hs.refused, hs.deferred, hs.reseeds, hs.words_served
+                    ).unwrap();
+                }
+                "fault" => {
+                    // only does anything in hosted mode, where the noise source is simulated
+                    let which = tokens.next().and_then(|t| t.parse::<usize>().ok()).unwrap_or(0);
+                    env.trng.inject_fault(which).unwrap();
+                    write!(ret, "Injected TRNG fault {}", which).unwrap();
+                }
                 _ => {
                     write!(ret, "{}", helpstring).unwrap();
                 }
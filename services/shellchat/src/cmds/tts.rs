@@ -2,6 +2,7 @@ use crate::{ShellCmdApi, CommonEnv};
 use xous_ipc::String;
 use tts_frontend::*;
 use core::fmt::Write;
+use std::io::Write as IoWrite;
 
 #[derive(Debug)]
 pub struct Tts {
@@ -20,7 +21,7 @@ impl<'a> ShellCmdApi<'a> for Tts {
 
     fn process(&mut self, args: String::<1024>, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
         let mut ret = String::<1024>::new();
-        let helpstring = "tts options: speak";
+        let helpstring = "tts options: speak, say, spell, queue, stop, voice [wpm N] [pitch N] [volume N]";
 
         let mut tokens = args.as_str().unwrap().split(' ');
 
@@ -31,6 +32,67 @@ impl<'a> ShellCmdApi<'a> for Tts {
                     join_tokens(&mut text, &mut tokens);
                     self.fe.tts_simple(text.as_str().expect("not valid utf-8")).unwrap();
                 }
+                // like speak, with markup, e.g. `tts say one <break time="1s"/> two`
+                "say" => {
+                    let mut text = String::<1024>::new();
+                    join_tokens(&mut text, &mut tokens);
+                    let id = self.fe.speak_markup(text.as_str().expect("not valid utf-8"), Priority::Normal, QueueMode::Flush).unwrap();
+                    write!(ret, "utterance {}", id).unwrap();
+                }
+                "spell" => {
+                    let mut text = String::<1024>::new();
+                    join_tokens(&mut text, &mut tokens);
+                    let mut markup = std::string::String::from("<spell>");
+                    for c in text.as_str().expect("not valid utf-8").chars() {
+                        match c {
+                            '<' => markup.push_str("&lt;"),
+                            '&' => markup.push_str("&amp;"),
+                            _ => markup.push(c),
+                        }
+                    }
+                    markup.push_str("</spell>");
+                    self.fe.speak_markup(&markup, Priority::Normal, QueueMode::Flush).unwrap();
+                }
+                "queue" => {
+                    let mut text = String::<1024>::new();
+                    join_tokens(&mut text, &mut tokens);
+                    let id = self.fe.speak_markup(text.as_str().expect("not valid utf-8"), Priority::Low, QueueMode::Enqueue).unwrap();
+                    write!(ret, "queued utterance {}", id).unwrap();
+                }
+                "stop" => {
+                    self.fe.flush(Priority::Urgent).unwrap();
+                }
+                "voice" => {
+                    let mut voice = self.fe.voice().unwrap();
+                    let mut changed = false;
+                    while let (Some(param), Some(value)) = (tokens.next(), tokens.next()) {
+                        let value = match value.parse::<u32>() {
+                            Ok(v) => v,
+                            Err(_) => {
+                                write!(ret, "{} isn't a number", value).unwrap();
+                                return Ok(Some(ret));
+                            }
+                        };
+                        match param {
+                            "wpm" => voice.wpm = value,
+                            "pitch" => voice.pitch = value,
+                            "volume" => voice.volume = value,
+                            _ => {
+                                write!(ret, "{}", helpstring).unwrap();
+                                return Ok(Some(ret));
+                            }
+                        }
+                        changed = true;
+                    }
+                    if changed {
+                        self.fe.set_voice(voice.clamped()).unwrap();
+                        voice = self.fe.voice().unwrap();
+                        if let Err(e) = save_voice(&voice) {
+                            write!(ret, "couldn't save the voice settings: {:?}\n", e).unwrap();
+                        }
+                    }
+                    write!(ret, "wpm {}, pitch {}%, volume {}%", voice.wpm, voice.pitch, voice.volume).unwrap();
+                }
                 _ => {
                     write!(ret, "{}", helpstring).unwrap();
                 }
@@ -52,4 +114,14 @@ fn join_tokens<'a>(buf: &mut String<1024>, tokens: impl Iterator<Item = &'a str>
             write!(buf, " {}", tok).unwrap();
         }
     }
-}
\ No newline at end of file
+}
+
+/// Keeps the voice settings across reboots; the status bar hands them back to TTS on boot
+fn save_voice(voice: &VoiceParams) -> std::io::Result<()> {
+    let mut pddb = pddb::Pddb::new();
+    let text = voice.to_text();
+    pddb.delete_key(VOICE_SETTINGS_DICT, VOICE_SETTINGS_KEY, None).ok();
+    let mut key = pddb.get(VOICE_SETTINGS_DICT, VOICE_SETTINGS_KEY, None, true, true, Some(text.len()), None::<fn()>)?;
+    key.write_all(text.as_bytes())?;
+    pddb.sync()
+}
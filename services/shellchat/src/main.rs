@@ -82,6 +82,8 @@ struct Repl {
 
     // command environment
     env: CmdEnv,
+    // sequencing, pipes, variables, loops, aliases and scripts on top of the commands
+    script: shell_script::Shell,
 
     // our security token for making changes to our record on the GAM
     token: [u32; 4],
@@ -105,6 +107,7 @@ impl Repl{
             audioframe_id: None,
             rawkeys_id: None,
             focuschange_id: Some(ShellOpcode::ChangeFocus.to_u32().unwrap()),
+            lifecycle_id: None,
         }).expect("couldn't register Ux context for shellchat");
 
         let content = gam.request_content_canvas(token.unwrap()).expect("couldn't get content canvas");
@@ -125,6 +128,7 @@ impl Repl{
             bubble_radius: 4,
             bubble_space: 4,
             env: CmdEnv::new(xns),
+            script: shell_script::Shell::new(),
             token: token.unwrap(),
             #[cfg(feature="tts")]
             tts: TtsFrontend::new(xns).unwrap(),
@@ -174,15 +178,15 @@ impl Repl{
         // take the input and pass it on to the various command parsers, and attach result
         if let Some(local) = &self.input {
             log::trace!("processing line: {}", local);
-            if let Some(res) = self.env.dispatch(Some(&mut xous_ipc::String::<1024>::from_str(&local)), None).expect("command dispatch failed") {
+            if let Some(res) = self.script.run(local, &mut self.env) {
                 #[cfg(feature="tts")]
                 {
                     let mut output = t!("shellchat.output-tts", xous::LANG).to_string();
-                    output.push_str(res.as_str().unwrap_or("UTF-8 error"));
+                    output.push_str(&res);
                     self.tts.tts_simple(&output).unwrap();
                 }
                 let output_history = History {
-                    text: String::from(res.as_str().unwrap_or("UTF-8 Error")),
+                    text: res,
                     is_input: false
                 };
                 self.circular_push(output_history);
@@ -315,6 +319,10 @@ fn xmain() -> ! {
     log_server::init_wait().unwrap();
     log::set_max_level(log::LevelFilter::Info);
     info!("my PID is {}", xous::process::id());
+    // the `log` command sets levels and the format; only the boot set may
+    if !log_server::claim_control().unwrap_or(false) {
+        log::warn!("couldn't claim control of logging; `log level` and `log format` won't work");
+    }
 
     let xns = xous_names::XousNames::new().unwrap();
     // unlimited connections allowed, this is a user app and it's up to the app to decide its policy
@@ -1,9 +1,20 @@
 use gam::*;
 use num_traits::*;
-use keyboard::KeyMap;
+use keyboard::{KeyMap, KeyMapData};
+use locales::t;
+use std::io::{Read, Write};
 
 use crate::StatusOpcode;
 
+/// PDDB dictionary holding custom keymaps: each key is one map, in the text format described in `keyboard::keymap`
+pub const KEYMAP_DICT: &str = "kbd.keymaps";
+/// PDDB dictionary for keyboard settings
+const KBD_SETTINGS_DICT: &str = "kbd.settings";
+/// name of the custom map that was last selected
+const KBD_SETTINGS_CUSTOM: &str = "custom_map";
+/// the user's compose sequences, in the format described in `keyboard::compose`
+const KBD_SETTINGS_COMPOSE: &str = "compose";
+
 pub fn create_kbd_menu(status_conn: xous::CID, kbd_mgr: xous::SID) -> MenuMatic {
     let mut menu_items = Vec::<MenuItem>::new();
 
@@ -56,5 +67,113 @@ pub fn create_kbd_menu(status_conn: xous::CID, kbd_mgr: xous::SID) -> MenuMatic
         });
     }
 
+    menu_items.push(MenuItem {
+        name: xous_ipc::String::from_str(t!("kbd.custom", xous::LANG)),
+        action_conn: Some(status_conn),
+        action_opcode: StatusOpcode::SelectCustomKeyboard.to_u32().unwrap(),
+        action_payload: MenuPayload::Scalar([0, 0, 0, 0]),
+        close_on_select: true,
+    });
+
     menu_matic(menu_items, gam::KBD_MENU_NAME, Some(kbd_mgr)).expect("couldn't create MenuMatic manager")
 }
+
+/// The position of a map in the keyboard menu. This is the map's code, except for the custom map,
+/// which comes last and moves depending on whether the Braille entry is present.
+pub fn kbd_menu_index(map: KeyMap) -> usize {
+    match map {
+        KeyMap::Custom => if cfg!(feature="tts") { 5 } else { 4 },
+        _ => map.into(),
+    }
+}
+
+/// Lets the user pick a custom map out of the PDDB, then loads and selects it.
+pub fn select_custom_keymap(modals: &modals::Modals, kbd: &keyboard::Keyboard) {
+    let mut pddb = pddb::Pddb::new();
+    if !pddb.is_mounted() {
+        modals.show_notification(t!("kbd.custom_needs_pddb", xous::LANG)).expect("couldn't show notification");
+        return;
+    }
+    let names = pddb.list_keys(KEYMAP_DICT, None).unwrap_or(Vec::new());
+    if names.is_empty() {
+        modals.show_notification(t!("kbd.custom_none", xous::LANG)).expect("couldn't show notification");
+        return;
+    }
+    for name in names.iter() {
+        modals.add_list_item(name).expect("couldn't build radio item list");
+    }
+    let name = match modals.get_radiobutton(t!("kbd.custom_select", xous::LANG)) {
+        Ok(name) => name,
+        _ => {
+            log::error!("get_radiobutton failed");
+            return;
+        }
+    };
+    match load_custom_keymap(&mut pddb, kbd, &name) {
+        Ok(data) => {
+            kbd.set_keymap(KeyMap::Custom).expect("couldn't set keyboard mapping");
+            log::info!("selected custom keymap {} ({})", name, data.name);
+            // keys can't be truncated, so replace the setting instead of overwriting it
+            pddb.delete_key(KBD_SETTINGS_DICT, KBD_SETTINGS_CUSTOM, None).ok();
+            match pddb.get(KBD_SETTINGS_DICT, KBD_SETTINGS_CUSTOM, None, true, true, None, None::<fn()>) {
+                Ok(mut setting) => {
+                    setting.write(name.as_bytes()).ok();
+                }
+                Err(e) => log::error!("couldn't save custom keymap selection: {:?}", e),
+            }
+            pddb.sync().ok();
+        }
+        Err(e) => {
+            modals.show_notification(&format!("{}{}", t!("kbd.custom_error", xous::LANG), e)).expect("couldn't show notification");
+        }
+    }
+}
+
+/// The early boot setting only records that a custom map is in use, as the map itself lives in the PDDB.
+/// This waits for the PDDB to be mounted, and then asks the main loop to re-load the map that was last
+/// selected, along with the user's compose sequences.
+pub fn start_kbd_settings_restore(status_conn: xous::CID) {
+    std::thread::spawn(move || {
+        let pddb = pddb::Pddb::new();
+        pddb.is_mounted_blocking(None);
+        xous::send_message(status_conn,
+            xous::Message::new_scalar(StatusOpcode::RestoreKeyboardSettings.to_usize().unwrap(), 0, 0, 0, 0)
+        ).expect("couldn't request keyboard settings restore");
+    });
+}
+
+pub fn restore_kbd_settings(kbd: &keyboard::Keyboard) {
+    let mut pddb = pddb::Pddb::new();
+    if let Ok(mut key) = pddb.get(KBD_SETTINGS_DICT, KBD_SETTINGS_COMPOSE, None, false, false, None, None::<fn()>) {
+        let mut text = String::new();
+        if key.read_to_string(&mut text).is_ok() {
+            if let Err(e) = kbd.load_compose_table(&text) {
+                log::error!("couldn't load compose sequences: {}", e);
+            }
+        }
+    }
+    if kbd.get_keymap().unwrap_or(KeyMap::Qwerty) != KeyMap::Custom {
+        return;
+    }
+    let mut name = String::new();
+    match pddb.get(KBD_SETTINGS_DICT, KBD_SETTINGS_CUSTOM, None, false, false, None, None::<fn()>) {
+        Ok(mut setting) => {
+            setting.read_to_string(&mut name).ok();
+        }
+        Err(_) => {
+            log::warn!("custom keymap selected, but none was saved; staying with the fallback map");
+            return;
+        }
+    }
+    if let Err(e) = load_custom_keymap(&mut pddb, kbd, &name) {
+        log::error!("couldn't restore custom keymap {}: {}", name, e);
+    }
+}
+
+fn load_custom_keymap(pddb: &mut pddb::Pddb, kbd: &keyboard::Keyboard, name: &str) -> Result<KeyMapData, String> {
+    let mut text = String::new();
+    let mut key = pddb.get(KEYMAP_DICT, name, None, false, false, None, None::<fn()>)
+        .map_err(|e| format!("{:?}", e))?;
+    key.read_to_string(&mut text).map_err(|e| format!("{:?}", e))?;
+    kbd.load_custom_keymap(&text).map_err(|e| e.to_string())
+}
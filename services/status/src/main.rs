@@ -7,6 +7,9 @@ mod appmenu;
 use appmenu::*;
 mod kbdmenu;
 use kbdmenu::*;
+#[cfg(feature="tts")]
+mod voice;
+mod trng_health;
 mod app_autogen;
 mod time;
 
@@ -56,6 +59,10 @@ pub(crate) enum StatusOpcode {
 
     /// Set the keyboard map
     SetKeyboard,
+    /// Pick a custom keyboard map from the PDDB
+    SelectCustomKeyboard,
+    /// Re-load the selected custom keyboard map and compose sequences once the PDDB is mounted
+    RestoreKeyboardSettings,
 
     /// Suspend handler from the main menu
     TrySuspend,
@@ -340,6 +347,10 @@ fn xmain() -> ! {
     let kbd_mgr = xous::create_server().unwrap();
     let kbd_menumatic = create_kbd_menu(xous::connect(status_sid).unwrap(), kbd_mgr);
     let kbd = keyboard::Keyboard::new(&xns).unwrap();
+    start_kbd_settings_restore(xous::connect(status_sid).unwrap());
+    #[cfg(feature="tts")]
+    voice::start_voice_settings_restore();
+    trng_health::start_trng_health_monitor();
 
     log::debug!("subscribe to wifi updates");
     netmgr.wifi_state_subscribe(cb_cid, StatusOpcode::WifiStats.to_u32().unwrap()).unwrap();
@@ -603,7 +614,7 @@ fn xmain() -> ! {
                 log::debug!("getting keyboard map");
                 let map = kbd.get_keymap().expect("couldn't get key mapping");
                 log::info!("setting keymap index to {:?}", map);
-                kbd_menumatic.set_index(map.into());
+                kbd_menumatic.set_index(kbd_menu_index(map));
                 log::debug!("raising keyboard menu");
                 ticktimer.sleep_ms(100).ok(); // yield for a moment to allow the previous menu to close
                 gam.raise_menu(gam::KBD_MENU_NAME).expect("couldn't raise keyboard layout submenu");
@@ -612,6 +623,12 @@ fn xmain() -> ! {
                 let map = keyboard::KeyMap::from(code);
                 kbd.set_keymap(map).expect("couldn't set keyboard mapping");
             }),
+            Some(StatusOpcode::SelectCustomKeyboard) => {
+                select_custom_keymap(&modals, &kbd);
+            },
+            Some(StatusOpcode::RestoreKeyboardSettings) => {
+                restore_kbd_settings(&kbd);
+            },
             Some(StatusOpcode::SwitchToShellchat) => {
                 ticktimer.sleep_ms(100).ok();
                 sec_notes.lock().unwrap().remove(&"current_app".to_string());
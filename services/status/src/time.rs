@@ -223,6 +223,8 @@ pub fn start_time_server() {
             log::debug!("tz_key: {}", tz_offset_ms / 1000);
             log::debug!("start_rtc_secs: {}", start_rtc_secs);
             log::debug!("start_tt_ms: {}", start_tt_ms);
+            // the wall clock only becomes right now, so anything that scheduled by it has to catch up
+            alarms::notify_clock_changed(&xns);
             loop {
                 let msg = xous::receive_message(pub_sid).unwrap();
                 match FromPrimitive::from_usize(msg.body.id()) {
@@ -281,6 +283,7 @@ pub fn start_time_server() {
                         log::info!("setting offset to {} secs", offset / 1000);
                         assert_eq!(offset_key.write(&offset.to_le_bytes()).unwrap_or(0), 8, "couldn't commit UTC time offset to PDDB");
                         offset_key.flush().expect("couldn't flush PDDB");
+                        alarms::notify_clock_changed(&xns);
                     }),
                     Some(TimeOp::SetTzOffsetMs) => xous::msg_scalar_unpack!(msg, tz_hi_ms, tz_lo_ms, _, _, {
                         let tz_ms = ((tz_hi_ms as i64) << 32) | (tz_lo_ms as i64);
@@ -296,6 +299,7 @@ pub fn start_time_server() {
                             log::info!("setting tz offset to {} secs", tz_ms / 1000);
                             assert_eq!(tz_key.write(&tz_ms.to_le_bytes()).unwrap_or(0), 8, "couldn't commit TZ time offset to PDDB");
                             tz_key.flush().expect("couldn't flush PDDB");
+                            alarms::notify_clock_changed(&xns);
                         }
                     }),
                     Some(TimeOp::WallClockTimeInit) => xous::msg_blocking_scalar_unpack!(msg, _, _, _, _, {
@@ -0,0 +1,156 @@
+use std::io::{Read, Write};
+use locales::t;
+use num_traits::*;
+use trng::api::{HealthState, HealthStatus};
+
+/// Long-term TRNG health statistics are kept here, as `name=value` lines
+const TRNG_HEALTH_DICT: &'static str = "trng.health";
+const TRNG_HEALTH_KEY: &'static str = "stats";
+/// How often the statistics are saved when nothing else is going on
+const SAVE_INTERVAL_MS: usize = 10 * 60 * 1000;
+
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+enum HealthOp {
+    /// the TRNG reported a change in its health
+    Event,
+    /// time to save the statistics
+    Save,
+}
+
+/// Totals carried over from previous boots
+#[derive(Default, Copy, Clone)]
+struct Totals {
+    failures: u64,
+    refused: u64,
+    deferred: u64,
+    reseeds: u64,
+    words_served: u64,
+    latched_boots: u64,
+}
+impl Totals {
+    fn from_text(text: &str) -> Self {
+        let mut t = Totals::default();
+        for line in text.lines() {
+            if let Some((name, value)) = line.split_once('=') {
+                let value = value.trim().parse::<u64>().unwrap_or(0);
+                match name.trim() {
+                    "failures" => t.failures = value,
+                    "refused" => t.refused = value,
+                    "deferred" => t.deferred = value,
+                    "reseeds" => t.reseeds = value,
+                    "words_served" => t.words_served = value,
+                    "latched_boots" => t.latched_boots = value,
+                    _ => (),
+                }
+            }
+        }
+        t
+    }
+    /// the totals so far, counting this boot
+    fn with_session(&self, hs: &HealthStatus) -> String {
+        format!(
+            "failures={}\nrefused={}\ndeferred={}\nreseeds={}\nwords_served={}\nlatched_boots={}\n",
+            self.failures + hs.failures as u64,
+            self.refused + hs.refused as u64,
+            self.deferred + hs.deferred as u64,
+            self.reseeds + hs.reseeds as u64,
+            self.words_served + hs.words_served,
+            self.latched_boots + if hs.state == HealthState::Failed { 1 } else { 0 },
+        )
+    }
+}
+
+/// Watches the TRNG's health policy: tells the user when the TRNG stops serving entropy, and
+/// keeps a running count of how it has fared across boots in the PDDB.
+pub fn start_trng_health_monitor() {
+    std::thread::spawn(move || {
+        let xns = xous_names::XousNames::new().unwrap();
+        let sid = xous::create_server().unwrap();
+        let cid = xous::connect(sid).unwrap();
+        let mut trng = trng::Trng::new(&xns).unwrap();
+        trng.hook_error_callback(HealthOp::Event.to_u32().unwrap(), cid).expect("couldn't hook TRNG events");
+        let modals = modals::Modals::new(&xns).unwrap();
+        // the PDDB may take a while to mount, so wait for it on the side
+        let pddb_cid = cid;
+        std::thread::spawn(move || {
+            let pddb = pddb::Pddb::new();
+            pddb.is_mounted_blocking(None);
+            xous::send_message(pddb_cid, xous::Message::new_scalar(HealthOp::Save.to_usize().unwrap(), 0, 0, 0, 0)).ok();
+            let tt = ticktimer_server::Ticktimer::new().unwrap();
+            loop {
+                tt.sleep_ms(SAVE_INTERVAL_MS).unwrap();
+                xous::send_message(pddb_cid, xous::Message::new_scalar(HealthOp::Save.to_usize().unwrap(), 0, 0, 0, 0)).ok();
+            }
+        });
+
+        let mut pddb: Option<pddb::Pddb> = None;
+        let mut totals = Totals::default();
+        let mut last_saved = String::new();
+        let mut shown = HealthState::Healthy;
+        loop {
+            let msg = xous::receive_message(sid).unwrap();
+            let hs = match trng.get_health_status() {
+                Ok(hs) => hs,
+                Err(e) => {
+                    log::error!("couldn't get the TRNG health status: {:?}", e);
+                    continue;
+                }
+            };
+            match FromPrimitive::from_usize(msg.body.id()) {
+                Some(HealthOp::Event) => {
+                    log::warn!("TRNG health: {:?}, {} failures, last errors {:?}", hs.state, hs.failures, hs.last_errors);
+                    if hs.state != shown {
+                        if shown == HealthState::Recovering {
+                            modals.dynamic_notification_close().ok();
+                        }
+                        match hs.state {
+                            HealthState::Recovering => {
+                                modals.dynamic_notification(Some(t!("trng.health_title", xous::LANG)), Some(t!("trng.recovering", xous::LANG))).ok();
+                            }
+                            HealthState::Failed => {
+                                modals.show_notification(t!("trng.failed", xous::LANG)).ok();
+                            }
+                            // not something the health events report
+                            HealthState::Starting => {}
+                            HealthState::Healthy => {
+                                modals.show_notification(t!("trng.recovered", xous::LANG)).ok();
+                            }
+                        }
+                        shown = hs.state;
+                    }
+                }
+                Some(HealthOp::Save) => {
+                    if pddb.is_none() {
+                        let mut p = pddb::Pddb::new();
+                        if let Ok(mut key) = p.get(TRNG_HEALTH_DICT, TRNG_HEALTH_KEY, None, false, false, None, None::<fn()>) {
+                            let mut text = String::new();
+                            if key.read_to_string(&mut text).is_ok() {
+                                totals = Totals::from_text(&text);
+                            }
+                        }
+                        pddb = Some(p);
+                    }
+                }
+                None => {
+                    log::error!("unrecognized TRNG health message: {:?}", msg);
+                    continue;
+                }
+            }
+            // events are rare enough that the statistics are saved on every one
+            if let Some(p) = pddb.as_mut() {
+                let text = totals.with_session(&hs);
+                if text != last_saved {
+                    p.delete_key(TRNG_HEALTH_DICT, TRNG_HEALTH_KEY, None).ok();
+                    match p.get(TRNG_HEALTH_DICT, TRNG_HEALTH_KEY, None, true, true, Some(256), None::<fn()>) {
+                        Ok(mut key) => {
+                            key.write_all(text.as_bytes()).ok();
+                            p.sync().ok();
+                            last_saved = text;
+                        }
+                        Err(e) => log::warn!("couldn't save the TRNG health stats: {:?}", e),
+                    }
+                }
+            }
+        }
+    });
+}
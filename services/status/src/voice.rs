@@ -0,0 +1,24 @@
+use std::io::Read;
+
+/// TTS can't read the PDDB itself, as the PDDB depends on it. This waits for the PDDB to be
+/// mounted, and hands TTS the voice settings that were last saved.
+pub fn start_voice_settings_restore() {
+    std::thread::spawn(move || {
+        let mut pddb = pddb::Pddb::new();
+        pddb.is_mounted_blocking(None);
+        match pddb.get(tts_frontend::VOICE_SETTINGS_DICT, tts_frontend::VOICE_SETTINGS_KEY, None, false, false, None, None::<fn()>) {
+            Ok(mut key) => {
+                let mut text = String::new();
+                match key.read_to_string(&mut text) {
+                    Ok(_) => {
+                        let xns = xous_names::XousNames::new().unwrap();
+                        let tts = tts_frontend::TtsFrontend::new(&xns).unwrap();
+                        tts.set_voice(tts_frontend::VoiceParams::from_text(&text)).expect("couldn't restore voice settings");
+                    }
+                    Err(e) => log::error!("couldn't read the voice settings: {:?}", e),
+                }
+            }
+            Err(_) => log::debug!("no saved voice settings"),
+        }
+    });
+}
@@ -59,10 +59,29 @@ pub(crate) enum Opcode {
     /// not tested - reboot address
     RebootVector, //(u32),
 
+    /// hosted mode only: runs a whole suspend cycle with a simulated sleep of the given length
+    /// in ms, and replies once it has resumed
+    SimulateSuspend,
+    /// hosted mode only: total ms of simulated sleep since boot
+    SimulatedSleepMs,
+
     /// exit the server
     Quit,
 }
 
+/// Hosted mode only: turns on the suspend/resume simulation when set to anything but `0`.
+/// Without it, hosted servers don't hook suspend events at all.
+pub const SUSRES_SIM_VAR: &str = "XOUS_SUSRES_SIM";
+
+/// How a simulated suspend cycle went
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SimulatedSuspend {
+    /// every subscriber reported in before the timeout
+    pub clean: bool,
+    /// subscribers that didn't report in, and were suspended anyway
+    pub late: usize,
+}
+
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
 pub(crate) struct ScalarHook {
     pub sid: (u32, u32, u32, u32),
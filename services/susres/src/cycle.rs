@@ -0,0 +1,271 @@
+//! The bookkeeping for a suspend cycle: which stage is being notified, who has reported in, and
+//! who was late. It's kept apart from the IPC, so that a whole cycle can be run in a test.
+
+use crate::api::{SimulatedSuspend, SuspendOrder};
+use crate::ScalarCallback;
+
+/// What the server has to do next
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Step {
+    /// nothing, until more subscribers report in
+    Wait,
+    /// send the suspend event to the subscribers in this stage
+    Notify(SuspendOrder),
+    /// everyone has reported in, or nobody is left to notify: suspend
+    Suspend,
+}
+
+pub(crate) struct Cycle {
+    pub subscribers: Vec<ScalarCallback>,
+    /// the stage waiting to report in
+    stage: SuspendOrder,
+    requested: bool,
+    timeout_pending: bool,
+    /// numbers each suspend, so a timeout left over from an earlier one can't force the current one
+    number: usize,
+}
+
+impl Cycle {
+    pub fn new() -> Self {
+        Cycle {
+            subscribers: Vec::new(),
+            stage: SuspendOrder::Early,
+            requested: false,
+            timeout_pending: false,
+            number: 0,
+        }
+    }
+
+    /// Whether a suspend is underway
+    pub fn pending(&self) -> bool {
+        self.timeout_pending
+    }
+
+    /// Starts a suspend. Returns the number the timeout for it should carry, and the first stage
+    /// to notify.
+    pub fn start(&mut self) -> (usize, Step) {
+        self.requested = true;
+        for sub in self.subscribers.iter_mut() {
+            sub.ready_to_suspend = false;
+            sub.failed_to_suspend = false;
+        }
+        self.timeout_pending = true;
+        self.number = self.number.wrapping_add(1);
+        (self.number, self.advance(SuspendOrder::Early))
+    }
+
+    /// Records that the subscriber holding `token` is ready to suspend
+    pub fn ready(&mut self, token: usize) -> Step {
+        if !self.requested {
+            log::error!("received a SuspendReady message when a suspend wasn't pending from token {}", token);
+            return Step::Wait;
+        }
+        if token >= self.subscribers.len() {
+            panic!("received a SuspendReady token that's out of range");
+        }
+        let scb = &mut self.subscribers[token];
+        if scb.ready_to_suspend {
+            log::error!("received a duplicate SuspendReady token: {} from {:?}", token, scb);
+            return Step::Wait;
+        }
+        scb.ready_to_suspend = true;
+
+        if !self.stage_ready(self.stage) {
+            log::trace!("still waiting on callbacks, returning to main loop");
+            Step::Wait
+        } else if self.stage == SuspendOrder::Last {
+            log::info!("all callbacks reporting in, doing suspend");
+            self.finish()
+        } else {
+            log::debug!("finished with {:?} going to next round", self.stage);
+            self.advance(self.stage.next())
+        }
+    }
+
+    /// Handles the timeout for suspend number `cycle`. Returns true if the suspend has to be forced,
+    /// in which case everyone who hasn't reported in is marked late.
+    pub fn timeout(&mut self, cycle: usize) -> bool {
+        if self.timeout_pending && cycle == self.number {
+            log::info!("suspend call has timed out, forcing a suspend");
+            self.mark_late();
+            self.finish();
+            true
+        } else {
+            // this means we did a clean suspend, we've resumed, and the timeout came back after the resume
+            log::trace!("clean suspend timeout received, ignoring");
+            false
+        }
+    }
+
+    /// The first stage from `from` on that anyone is in; the `Last` stage suspends
+    fn advance(&mut self, from: SuspendOrder) -> Step {
+        self.stage = from;
+        loop {
+            if self.subscribers.iter().any(|scb| scb.order == self.stage) {
+                log::debug!("Now waiting on {:?} stage", self.stage);
+                return Step::Notify(self.stage);
+            }
+            if self.stage == SuspendOrder::Last {
+                return self.finish();
+            }
+            self.stage = self.stage.next();
+        }
+    }
+
+    fn finish(&mut self) -> Step {
+        self.requested = false;
+        self.timeout_pending = false;
+        Step::Suspend
+    }
+
+    /// Returns true once every subscriber in the `order` stage has reported in
+    fn stage_ready(&self, order: SuspendOrder) -> bool {
+        if let Some(scb) = self.subscribers.iter().find(|scb| scb.order == order && !scb.ready_to_suspend) {
+            log::trace!("not ready: {}", scb.token);
+            false
+        } else {
+            true
+        }
+    }
+
+    /// On a timeout, anyone who hasn't reported in is recorded as having failed to suspend
+    fn mark_late(&mut self) {
+        for scb in self.subscribers.iter_mut() {
+            scb.failed_to_suspend = !scb.ready_to_suspend;
+        }
+    }
+
+    pub fn was_clean(&self, token: u32) -> bool {
+        !self.subscribers.iter().any(|scb| scb.token == token && scb.failed_to_suspend)
+    }
+
+    /// How the last suspend went, counting the subscribers that were marked late by a timeout
+    pub fn outcome(&self) -> SimulatedSuspend {
+        let late = self.subscribers.iter().filter(|scb| scb.failed_to_suspend).count();
+        SimulatedSuspend { clean: late == 0, late }
+    }
+
+    /// A simulated suspend only exercises the protocol if someone takes part up to the `Last` stage;
+    /// without `XOUS_SUSRES_SIM`, nobody hooks in at all.
+    pub fn can_simulate(&self) -> bool {
+        self.subscribers.iter().any(|scb| scb.order == SuspendOrder::Last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::implementation::SusResHw;
+
+    fn subscribed(orders: &[SuspendOrder]) -> Cycle {
+        let mut cycle = Cycle::new();
+        cycle.subscribers = orders.iter().enumerate().map(|(token, &order)| ScalarCallback {
+            server_to_cb_cid: 0,
+            cb_to_client_cid: 0,
+            cb_to_client_id: 0,
+            ready_to_suspend: false,
+            token: token as u32,
+            failed_to_suspend: false,
+            order,
+        }).collect();
+        cycle
+    }
+
+    /// Runs suspends the way the server does, against the hosted hardware, which adds up the
+    /// simulated sleep that the ticktimer and RTC catch up with on resume
+    #[test]
+    fn simulated_suspend_cycles() {
+        // tokens are handed out in the order servers hook in, not the order they suspend in
+        let mut cycle = subscribed(&[SuspendOrder::Normal, SuspendOrder::Last, SuspendOrder::Early, SuspendOrder::Normal, SuspendOrder::Late]);
+        let mut hw = SusResHw::new();
+        assert!(cycle.can_simulate());
+
+        // a clean suspend: each stage is notified once the one before it has reported in
+        hw.set_simulated_sleep(1500);
+        let (first, step) = cycle.start();
+        assert_eq!(step, Step::Notify(SuspendOrder::Early));
+        assert!(cycle.pending());
+        assert_eq!(cycle.ready(2), Step::Notify(SuspendOrder::Normal));
+        assert_eq!(cycle.ready(0), Step::Wait);
+        assert_eq!(cycle.ready(0), Step::Wait);
+        assert_eq!(cycle.ready(3), Step::Notify(SuspendOrder::Late));
+        assert_eq!(cycle.ready(4), Step::Notify(SuspendOrder::Last));
+        assert_eq!(cycle.ready(1), Step::Suspend);
+        hw.do_suspend(false);
+        assert!(!hw.do_resume());
+        assert_eq!(cycle.outcome(), SimulatedSuspend { clean: true, late: 0 });
+        assert_eq!(hw.simulated_sleep_ms(), 1500);
+        // its timeout comes back after the resume, and changes nothing
+        assert!(!cycle.pending());
+        assert!(!cycle.timeout(first));
+        assert_eq!(cycle.ready(1), Step::Wait);
+
+        // a suspend that times out: the Normal stage never finishes
+        hw.set_simulated_sleep(500);
+        let (second, step) = cycle.start();
+        assert_ne!(second, first);
+        assert_eq!(step, Step::Notify(SuspendOrder::Early));
+        assert_eq!(cycle.ready(2), Step::Notify(SuspendOrder::Normal));
+        assert_eq!(cycle.ready(0), Step::Wait);
+        // a timeout left over from the first suspend can't force this one
+        assert!(!cycle.timeout(first));
+        assert!(cycle.pending());
+        assert!(cycle.timeout(second));
+        hw.do_suspend(true);
+        assert!(hw.do_resume());
+        // the straggler, and the stages that were never notified, are late
+        assert_eq!(cycle.outcome(), SimulatedSuspend { clean: false, late: 3 });
+        assert!(cycle.was_clean(0));
+        assert!(cycle.was_clean(2));
+        assert!(!cycle.was_clean(1));
+        assert!(!cycle.was_clean(3));
+        assert!(!cycle.was_clean(4));
+        assert_eq!(hw.simulated_sleep_ms(), 2000);
+        // the straggler reporting in after the fact changes nothing
+        assert_eq!(cycle.ready(3), Step::Wait);
+        assert!(!cycle.timeout(second));
+
+        // and the next suspend starts over
+        hw.set_simulated_sleep(250);
+        let (third, step) = cycle.start();
+        assert_ne!(third, second);
+        assert_eq!(step, Step::Notify(SuspendOrder::Early));
+        assert!(cycle.was_clean(3));
+        for &token in [2, 0, 3, 4].iter() {
+            assert_ne!(cycle.ready(token), Step::Suspend);
+        }
+        assert_eq!(cycle.ready(1), Step::Suspend);
+        hw.do_suspend(false);
+        assert!(!hw.do_resume());
+        assert!(cycle.outcome().clean);
+        assert_eq!(hw.simulated_sleep_ms(), 2250);
+    }
+
+    #[test]
+    fn empty_stages_are_skipped() {
+        let mut cycle = subscribed(&[SuspendOrder::Last, SuspendOrder::Early]);
+        assert_eq!(cycle.start().1, Step::Notify(SuspendOrder::Early));
+        assert_eq!(cycle.ready(1), Step::Notify(SuspendOrder::Last));
+        assert_eq!(cycle.ready(0), Step::Suspend);
+
+        // nobody in the Last stage: the suspend happens once the others are done
+        let mut cycle = subscribed(&[SuspendOrder::Normal]);
+        assert!(!cycle.can_simulate());
+        assert_eq!(cycle.start().1, Step::Notify(SuspendOrder::Normal));
+        assert_eq!(cycle.ready(0), Step::Suspend);
+
+        // or right away, if nobody hooked in at all
+        let mut cycle = Cycle::new();
+        assert!(!cycle.can_simulate());
+        assert_eq!(cycle.start().1, Step::Suspend);
+        assert!(!cycle.pending());
+    }
+
+    #[test]
+    #[should_panic]
+    fn unknown_tokens() {
+        let mut cycle = subscribed(&[SuspendOrder::Last]);
+        cycle.start();
+        cycle.ready(1);
+    }
+}
@@ -19,6 +19,27 @@ pub struct Susres {
 impl Susres {
     #[cfg(any(target_os = "none", target_os = "xous"))]
     pub fn new(order: Option<SuspendOrder>, xns: &xous_names::XousNames, cb_discriminant: u32, cid: CID) -> Result<Self, xous::Error> {
+        Susres::hook(order, xns, cb_discriminant, cid)
+    }
+    // suspend/resume is not implemented in hosted mode by default, and will break if you try to do it.
+    // the main reason this was doen is actually it seems hosted mode can't handle the level
+    // of concurrency introduced by suspend/resume, as its underlying IPC mechanisms are quite
+    // different and have a lot of overhead; it seems like the system goes into a form of deadlock
+    // during boot when all the hosted mode servers try to connect. This isn't an issue on real hardware.
+    // Setting XOUS_SUSRES_SIM opts in to hooking anyways, so the suspend protocol can be exercised.
+    #[cfg(not(any(target_os = "none", target_os = "xous")))]
+    pub fn new(ordering: Option<SuspendOrder>, xns: &xous_names::XousNames, cb_discriminant: u32, cid: CID) -> Result<Self, xous::Error> {
+        if simulation_enabled() {
+            return Susres::hook(ordering, xns, cb_discriminant, cid);
+        }
+        REFCOUNT.fetch_add(1, Ordering::Relaxed);
+        Ok(Susres {
+            conn: 0,
+            suspend_cb_sid: None,
+            execution_gate_conn: 0,
+        })
+    }
+    fn hook(order: Option<SuspendOrder>, xns: &xous_names::XousNames, cb_discriminant: u32, cid: CID) -> Result<Self, xous::Error> {
         REFCOUNT.fetch_add(1, Ordering::Relaxed);
         let conn = xns.request_connection_blocking(api::SERVER_NAME_SUSRES).expect("Can't connect to SUSRES");
         let execution_gate_conn = xns.request_connection_blocking(api::SERVER_NAME_EXEC_GATE).expect("Can't connect to the execution gate");
@@ -42,20 +63,6 @@ impl Susres {
             execution_gate_conn,
         })
     }
-    // suspend/resume is not implemented in hosted mode, and will break if you try to do it.
-    // the main reason this was doen is actually it seems hosted mode can't handle the level
-    // of concurrency introduced by suspend/resume, as its underlying IPC mechanisms are quite
-    // different and have a lot of overhead; it seems like the system goes into a form of deadlock
-    // during boot when all the hosted mode servers try to connect. This isn't an issue on real hardware.
-    #[cfg(not(any(target_os = "none", target_os = "xous")))]
-    pub fn new(_ordering: Option<SuspendOrder>, xns: &xous_names::XousNames, cb_discriminant: u32, cid: CID) -> Result<Self, xous::Error> {
-        REFCOUNT.fetch_add(1, Ordering::Relaxed);
-        Ok(Susres {
-            conn: 0,
-            suspend_cb_sid: None,
-            execution_gate_conn: 0,
-        })
-    }
     pub fn conn(&self) -> CID { self.conn }
     pub fn getop_suspend(&self) -> u32 { Opcode::SuspendRequest.to_u32().unwrap() }
 
@@ -104,6 +111,33 @@ impl Susres {
         }
     }
 
+    /// Hosted mode only: runs a suspend cycle as if the device had slept for `sleep_ms`, and
+    /// returns once everything has been told to resume. The ticktimer and RTC jump ahead by
+    /// the time slept. Only servers started with `XOUS_SUSRES_SIM` set take part.
+    pub fn simulate_suspend(&self, sleep_ms: u32) -> Result<SimulatedSuspend, xous::Error> {
+        let response = send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::SimulateSuspend.to_usize().unwrap(), sleep_ms as usize, 0, 0, 0)
+        )?;
+        match response {
+            xous::Result::Scalar2(clean, late) => Ok(SimulatedSuspend { clean: clean != 0, late }),
+            // the suspend was refused: not hosted, suspend denied, or a cycle already under way
+            xous::Result::Scalar1(_) => Err(xous::Error::AccessDenied),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+
+    /// Hosted mode only: the total time spent in simulated sleep since boot. Always 0 on hardware.
+    pub fn simulated_sleep_ms(&self) -> Result<u64, xous::Error> {
+        let response = send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::SimulatedSleepMs.to_usize().unwrap(), 0, 0, 0, 0)
+        )?;
+        if let xous::Result::Scalar2(lo, hi) = response {
+            Ok(lo as u64 | ((hi as u64) << 32))
+        } else {
+            Err(xous::Error::InternalError)
+        }
+    }
+
     pub fn set_suspendable(&mut self, allow_suspend: bool) -> Result<(), xous::Error> {
         if allow_suspend {
             send_message(self.conn,
@@ -134,6 +168,12 @@ impl Susres {
         }
     }
 }
+/// Whether hosted-mode servers should take part in suspend/resume; see `SUSRES_SIM_VAR`
+#[cfg(not(any(target_os = "none", target_os = "xous")))]
+pub fn simulation_enabled() -> bool {
+    std::env::var(api::SUSRES_SIM_VAR).map(|v| v != "0").unwrap_or(false)
+}
+
 fn drop_conn(sid: xous::SID) {
     let cid = xous::connect(sid).unwrap();
     xous::send_message(cid,
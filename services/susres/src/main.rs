@@ -4,6 +4,8 @@
 #![cfg_attr(target_os = "none", no_main)]
 
 mod murmur3;
+mod cycle;
+use cycle::{Cycle, Step};
 
 mod api;
 use api::{Opcode, ScalarHook, SuspendEventCallback, ExecGateOpcode};
@@ -428,18 +430,40 @@ mod implementation {
 mod implementation {
     use num_traits::ToPrimitive;
 
+    /// There's nothing to power down in hosted mode, so a suspend is simulated: time spent
+    /// "asleep" is added up, and the ticktimer and RTC add it to their view of the time.
     pub struct SusResHw {
+        /// length of the next simulated sleep
+        sleep_ms: u64,
+        /// total simulated sleep since boot
+        slept_ms: u64,
+        /// whether the last suspend was forced, as the bootloader would report it
+        forced: bool,
     }
     impl SusResHw {
         pub fn new() -> Self {
-            SusResHw {}
+            SusResHw {
+                sleep_ms: 0,
+                slept_ms: 0,
+                forced: false,
+            }
         }
         pub fn reboot(&self, _reboot_soc: bool) {}
         pub fn set_reboot_vector(&self, _vector: u32) {}
-        pub fn do_suspend(&mut self, _forced: bool) {
+        pub fn set_simulated_sleep(&mut self, ms: u64) {
+            self.sleep_ms = ms;
+        }
+        pub fn simulated_sleep_ms(&self) -> u64 {
+            self.slept_ms
+        }
+        pub fn do_suspend(&mut self, forced: bool) {
+            log::info!("simulating a {} suspend of {} ms", if forced { "forced" } else { "clean" }, self.sleep_ms);
+            self.forced = forced;
+            self.slept_ms += self.sleep_ms;
+            self.sleep_ms = 0;
         }
         pub fn do_resume(&mut self) -> bool {
-            false
+            self.forced
         }
         pub fn setup_timeout_csr(&mut self, cid: xous::CID) -> Result<(), xous::Error> {
             xous::send_message(cid,
@@ -488,7 +512,7 @@ pub fn timeout_thread(sid0: usize, sid1: usize, sid2: usize, sid3: usize) {
             Some(TimeoutOpcode::SetCsr) => msg_scalar_unpack!(msg, _base, _, _, _, {
                 // ignore the opcode in hosted mode
             }),
-            Some(TimeoutOpcode::Run) => {
+            Some(TimeoutOpcode::Run) => msg_scalar_unpack!(msg, cycle, _, _, _, {
                 #[cfg(any(target_os = "none", target_os = "xous"))]
                 {
                     // we have to re-implement the ticktimer time reading here because as we wait for the timeout,
@@ -507,15 +531,17 @@ pub fn timeout_thread(sid0: usize, sid1: usize, sid2: usize, sid3: usize) {
                         panic!("hardware CSR not sent to timeout_thread before it was instructed to run");
                     }
                 }
+                #[cfg(not(any(target_os = "none", target_os = "xous")))]
+                std::thread::sleep(std::time::Duration::from_millis(TIMEOUT_TIME.load(Ordering::Relaxed) as u64));
                 log::trace!("HW timeout reached");
                 match send_message(TIMEOUT_CONN.load(Ordering::Relaxed),
-                    Message::new_scalar(Opcode::SuspendTimeout.to_usize().unwrap(), 0, 0, 0, 0)
+                    Message::new_scalar(Opcode::SuspendTimeout.to_usize().unwrap(), cycle, 0, 0, 0)
                 ) {
                     Err(xous::Error::ServerNotFound) => break,
                     Ok(xous::Result::Ok) => {},
                     _ => panic!("unhandled error in status pump thread")
                 }
-            }
+            }),
             Some(TimeoutOpcode::Drop) => {
                 break
             }
@@ -593,13 +619,13 @@ fn xmain() -> ! {
     let timeout_outgoing_conn = xous::connect(timeout_sid).expect("couldn't connect to our timeout thread");
     susres_hw.setup_timeout_csr(timeout_outgoing_conn).expect("couldn't set hardware CSR for timeout thread");
 
-    let mut suspend_requested = false;
-    let mut timeout_pending = false;
     let mut reboot_requested: bool = false;
     let mut allow_suspend = true;
 
-    let mut suspend_subscribers = Vec::<ScalarCallback>::new();
-    let mut current_op_order = crate::api::SuspendOrder::Early;
+    // the caller of a simulated suspend, waiting to hear how it went
+    let mut sim_waiter: Option<xous::MessageSender> = None;
+
+    let mut cycle = Cycle::new();
     loop {
         let msg = xous::receive_message(susres_sid).unwrap();
         if reboot_requested {
@@ -629,118 +655,57 @@ fn xmain() -> ! {
                 Some(Opcode::SuspendEventSubscribe) => {
                     let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
                     let hookdata = buffer.to_original::<ScalarHook, _>().unwrap();
-                    do_hook(hookdata, &mut suspend_subscribers);
+                    do_hook(hookdata, &mut cycle.subscribers);
                 },
                 Some(Opcode::SuspendReady) => msg_scalar_unpack!(msg, token, _, _, _, {
                     //log::trace!("suspendready with token {}", token);
-                    if !suspend_requested {
-                        log::error!("received a SuspendReady message when a suspend wasn't pending from token {}", token);
-                        continue;
-                    }
-                    if token >= suspend_subscribers.len() {
-                        panic!("received a SuspendReady token that's out of range");
-                    }
-                    let scb = &mut suspend_subscribers[token];
-                    if scb.ready_to_suspend {
-                        log::error!("received a duplicate SuspendReady token: {} from {:?}", token, scb);
-                        continue;
-                    }
-                    scb.ready_to_suspend = true;
-
-                    let mut all_ready = true;
-                    for sub in suspend_subscribers.iter() {
-                        if sub.order == current_op_order {
-                            if !sub.ready_to_suspend {
-                                log::trace!("not ready: {}", sub.token);
-                                all_ready = false;
-                                break;
-                            }
-                        }
-                    }
                     // note: we must have at least one `Last` subscriber for this logic to work!
-                    if all_ready && current_op_order == crate::api::SuspendOrder::Last {
-                        log::info!("all callbacks reporting in, doing suspend");
-                        timeout_pending = false;
-                        // susres_hw.debug_delay(500); // let the messages print
-                        susres_hw.do_suspend(false);
-                        // when do_suspend() returns, it means we've resumed
-                        suspend_requested = false;
-                        log_server::resume(); // log server is a special case, in order to avoid circular dependencies
-                        if susres_hw.do_resume() {
-                            log::error!("We did a clean shut-down, but bootloader is saying previous suspend was forced. Some peripherals may be in an unclean state!");
-                        }
-                        // this now allows all other threads to commence
-                        log::trace!("low-level resume done, restoring execution");
-                        RESUME_EXEC.store(true, Ordering::Relaxed);
-                        susres_hw.restore_wfi();
-                    } else if all_ready {
-                        log::debug!("finished with {:?} going to next round", current_op_order);
-                        // the current order is finished, send the next tranche
-                        current_op_order = current_op_order.next();
-                        let mut at_least_one_event_sent = false;
-                        while !at_least_one_event_sent {
-                            let (send_success, next_op_order) = send_event(&suspend_subscribers, current_op_order);
-                            if !send_success {
-                                current_op_order = next_op_order;
-                            }
-                            at_least_one_event_sent = send_success;
+                    match cycle.ready(token) {
+                        Step::Wait => (),
+                        Step::Notify(order) => {
+                            send_event(&cycle.subscribers, order);
+                            // let the events fire
+                            xous::yield_slice();
                         }
-                        log::debug!("Now waiting on {:?} stage", current_op_order);
-                        // let the events fire
-                        xous::yield_slice();
-                    } else {
-                        log::trace!("still waiting on callbacks, returning to main loop");
+                        Step::Suspend => clean_suspend(&mut susres_hw, &mut sim_waiter, &cycle),
                     }
                 }),
                 Some(Opcode::SuspendRequest) => {
                     // if the 2-second timeout is still pending from a previous suspend, deny the suspend request.
                     // ...just don't suspend that quickly after resuming???
-                    if allow_suspend && !timeout_pending {
+                    if allow_suspend && !cycle.pending() {
                         susres_hw.ignore_wfi();
-                        suspend_requested = true;
                         // clear the resume gate
                         SHOULD_RESUME.store(false, Ordering::Relaxed);
                         RESUME_EXEC.store(false, Ordering::Relaxed);
-                        // clear the ready to suspend flag and failed to suspend flag
-                        for sub in suspend_subscribers.iter_mut() {
-                            sub.ready_to_suspend = false;
-                            sub.failed_to_suspend = false;
-                        }
                         // do we want to start the timeout before or after sending the notifications? hmm. 🤔
-                        timeout_pending = true;
+                        let (number, first) = cycle.start();
                         send_message(timeout_outgoing_conn,
-                            Message::new_scalar(TimeoutOpcode::Run.to_usize().unwrap(), 0, 0, 0, 0)
+                            Message::new_scalar(TimeoutOpcode::Run.to_usize().unwrap(), number, 0, 0, 0)
                         ).expect("couldn't initiate timeout before suspend!");
-
-                        current_op_order = crate::api::SuspendOrder::Early;
-                        let mut at_least_one_event_sent = false;
-                        while !at_least_one_event_sent {
-                            let (send_success, next_op_order) = send_event(&suspend_subscribers, current_op_order);
-                            if !send_success {
-                                current_op_order = next_op_order;
+                        match first {
+                            Step::Notify(order) => {
+                                send_event(&cycle.subscribers, order);
+                                // let the events fire
+                                xous::yield_slice();
                             }
-                            at_least_one_event_sent = send_success;
+                            Step::Suspend => clean_suspend(&mut susres_hw, &mut sim_waiter, &cycle),
+                            Step::Wait => (),
                         }
-                        // let the events fire
-                        xous::yield_slice();
                     } else {
-                        log::warn!("suspend requested, but the system was not allowed to suspend. Ignoring request.")
+                        log::warn!("suspend requested, but the system was not allowed to suspend. Ignoring request.");
+                        if let Some(sender) = sim_waiter.take() {
+                            xous::return_scalar(sender, 0).expect("couldn't refuse simulated suspend");
+                        }
                     }
                 },
-                Some(Opcode::SuspendTimeout) => {
-                    if timeout_pending {
-                        log::info!("suspend call has timed out, forcing a suspend");
-                        // record which tokens had not reported in
-                        for sub in suspend_subscribers.iter_mut() {
-                            sub.failed_to_suspend = !sub.ready_to_suspend;
-                        }
-                        timeout_pending = false;
+                Some(Opcode::SuspendTimeout) => msg_scalar_unpack!(msg, number, _, _, _, {
+                    if cycle.timeout(number) {
                         log::warn!("Suspend timed out, forcing an unclean suspend");
                         // susres_hw.debug_delay(500); // let the messages print
                         // force a suspend
                         susres_hw.do_suspend(true);
                         // when do_suspend() returns, it means we've resumed
-                        suspend_requested = false;
                         log_server::resume(); // log server is a special case, in order to avoid circular dependencies
                         if susres_hw.do_resume() {
                             log::error!("We forced a suspend, some peripherals may be in an unclean state!");
@@ -748,25 +713,44 @@ fn xmain() -> ! {
                             log::error!("We forced a suspend, but the bootloader is claiming we did a clean suspend. Internal state may be inconsistent.");
                         }
                         RESUME_EXEC.store(true, Ordering::Relaxed);
-                    } else {
-                        log::trace!("clean suspend timeout received, ignoring");
-                        // this means we did a clean suspend, we've resumed, and the timeout came back after the resume
-                        // just ignore the message.
+                        report_simulated(&mut sim_waiter, &cycle);
                     }
-                }
+                }),
                 Some(Opcode::WasSuspendClean) => msg_blocking_scalar_unpack!(msg, token, _, _, _, {
-                    let mut clean = true;
-                    for sub in suspend_subscribers.iter() {
-                        if sub.token == token as u32 && sub.failed_to_suspend {
-                            clean = false;
-                        }
-                    }
-                    if clean {
+                    if cycle.was_clean(token as u32) {
                         xous::return_scalar(msg.sender, 1).expect("couldn't return WasSuspendClean result");
                     } else {
                         xous::return_scalar(msg.sender, 0).expect("couldn't return WasSuspendClean result");
                     }
                 }),
+                Some(Opcode::SimulateSuspend) => msg_blocking_scalar_unpack!(msg, sleep_ms, _, _, _, {
+                    #[cfg(not(any(target_os = "none", target_os = "xous")))]
+                    {
+                        if allow_suspend && !cycle.pending() && sim_waiter.is_none() && cycle.can_simulate() {
+                            susres_hw.set_simulated_sleep(sleep_ms as u64);
+                            sim_waiter = Some(msg.sender);
+                            send_message(timeout_incoming_conn,
+                                Message::new_scalar(Opcode::SuspendRequest.to_usize().unwrap(), 0, 0, 0, 0)
+                            ).expect("couldn't start simulated suspend");
+                        } else {
+                            log::warn!("simulated suspend refused");
+                            xous::return_scalar(msg.sender, 0).expect("couldn't refuse simulated suspend");
+                        }
+                    }
+                    #[cfg(any(target_os = "none", target_os = "xous"))]
+                    {
+                        log::warn!("ignoring request to simulate {} ms of sleep on real hardware", sleep_ms);
+                        xous::return_scalar(msg.sender, 0).expect("couldn't refuse simulated suspend");
+                    }
+                }),
+                Some(Opcode::SimulatedSleepMs) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                    #[cfg(not(any(target_os = "none", target_os = "xous")))]
+                    let slept = susres_hw.simulated_sleep_ms();
+                    #[cfg(any(target_os = "none", target_os = "xous"))]
+                    let slept = 0u64;
+                    xous::return_scalar2(msg.sender, slept as u32 as usize, (slept >> 32) as usize)
+                        .expect("couldn't return simulated sleep time");
+                }),
                 Some(Opcode::SuspendAllow) => {
                     allow_suspend = true;
                 },
@@ -783,7 +767,7 @@ fn xmain() -> ! {
         }
     }
     // clean up our program
-    unhook(&mut suspend_subscribers);
+    unhook(&mut cycle.subscribers);
     log::trace!("main loop exit, destroying servers");
     xns.unregister_server(susres_sid).unwrap();
     xous::destroy_server(susres_sid).unwrap();
@@ -807,6 +791,29 @@ fn do_hook(hookdata: ScalarHook, cb_conns: &mut Vec::<ScalarCallback>) {
     log::trace!("hooking {:?}", cb_dat);
     cb_conns.push(cb_dat);
 }
+/// Suspends once everyone has reported in, and picks up again on resume
+fn clean_suspend(susres_hw: &mut implementation::SusResHw, sim_waiter: &mut Option<xous::MessageSender>, cycle: &Cycle) {
+    // susres_hw.debug_delay(500); // let the messages print
+    susres_hw.do_suspend(false);
+    // when do_suspend() returns, it means we've resumed
+    log_server::resume(); // log server is a special case, in order to avoid circular dependencies
+    if susres_hw.do_resume() {
+        log::error!("We did a clean shut-down, but bootloader is saying previous suspend was forced. Some peripherals may be in an unclean state!");
+    }
+    // this now allows all other threads to commence
+    log::trace!("low-level resume done, restoring execution");
+    RESUME_EXEC.store(true, Ordering::Relaxed);
+    susres_hw.restore_wfi();
+    report_simulated(sim_waiter, cycle);
+}
+/// Tells whoever asked for a simulated suspend how it went, once it's over
+fn report_simulated(waiter: &mut Option<xous::MessageSender>, cycle: &Cycle) {
+    if let Some(sender) = waiter.take() {
+        let outcome = cycle.outcome();
+        xous::return_scalar2(sender, if outcome.clean { 1 } else { 0 }, outcome.late)
+            .expect("couldn't report on simulated suspend");
+    }
+}
 fn unhook(cb_conns: &mut Vec::<ScalarCallback>) {
     for scb in cb_conns.iter() {
         xous::send_message(scb.server_to_cb_cid,
@@ -816,17 +823,36 @@ fn unhook(cb_conns: &mut Vec::<ScalarCallback>) {
     }
     cb_conns.clear();
 }
-fn send_event(cb_conns: &Vec::<ScalarCallback>, order: crate::api::SuspendOrder) -> (bool, crate::api::SuspendOrder) {
-    let mut at_least_one_event_sent = false;
+fn send_event(cb_conns: &[ScalarCallback], order: crate::api::SuspendOrder) {
     log::info!("Sending suspend to {:?} stage", order);
     for scb in cb_conns.iter() {
         if scb.order == order {
-            at_least_one_event_sent = true;
             xous::send_message(scb.server_to_cb_cid,
                 xous::Message::new_scalar(SuspendEventCallback::Event.to_usize().unwrap(),
                 scb.cb_to_client_cid as usize, scb.cb_to_client_id as usize, scb.token as usize, 0)
             ).unwrap();
         }
     }
-    (at_least_one_event_sent, order.next())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulated_sleep_adds_up() {
+        let mut hw = implementation::SusResHw::new();
+        hw.set_simulated_sleep(1500);
+        hw.do_suspend(false);
+        assert!(!hw.do_resume());
+        assert_eq!(hw.simulated_sleep_ms(), 1500);
+        hw.set_simulated_sleep(500);
+        hw.do_suspend(true);
+        // a forced suspend is reported by the resume, as the bootloader would
+        assert!(hw.do_resume());
+        assert_eq!(hw.simulated_sleep_ms(), 2000);
+        // each sleep length is only used once
+        hw.do_suspend(false);
+        assert_eq!(hw.simulated_sleep_ms(), 2000);
+    }
 }
@@ -48,6 +48,50 @@ pub(crate) enum Opcode {
     /// *arg1*: An integer of some sort, such as the address of the Condvar
     /// *arg2*: The number of conditions to notify
     NotifyCondition = 9,
+
+    /// Start a timer, described by a `TimerHook` in a memory message. The server fills in its ID.
+    RegisterTimer = 10,
+
+    /// Cancel a timer. Only the process that started it can cancel it.
+    ///
+    /// # Arguments
+    ///
+    /// *arg1*: The timer's ID
+    CancelTimer = 11,
+
+    /// Cancel every timer that reports to a callback server, before the server goes away
+    ///
+    /// # Arguments
+    ///
+    /// *arg1-4*: The callback server's SID
+    UnhookTimers = 12,
+}
+
+/// Messages from the ticktimer to the timer callback server in a client's process
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+pub(crate) enum TimerCallback {
+    /// A timer came due. arg1: the CID to forward to, arg2: the opcode, arg3: the timer's data,
+    /// arg4: how many expiries this stands for
+    Expired,
+    Drop,
+}
+
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct TimerHook {
+    /// the callback server in the client's process that forwards expiries
+    pub sid: (u32, u32, u32, u32),
+    /// caller-side connection the expiries are forwarded to, and the opcode they're sent with
+    pub cid: xous::CID,
+    pub opcode: u32,
+    /// passed back in arg1 of each expiry
+    pub data: u32,
+    pub delay_ms: u64,
+    /// 0 for a one-shot timer
+    pub period_ms: u64,
+    /// how late the timer may fire, so that it can share a wakeup with other timers
+    pub slack_ms: u64,
+    /// filled in by the server: the timer's ID, or 0 if there was no room for it
+    pub id: u32,
 }
 
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -2,18 +2,22 @@
 
 pub mod api;
 
-use num_traits::ToPrimitive;
+use num_traits::{FromPrimitive, ToPrimitive};
 use xous::{send_message, Error, CID};
+use xous_ipc::Buffer;
 
 #[derive(Debug)]
 pub struct Ticktimer {
     conn: CID,
+    /// the server that forwards timer expiries, set up on the first `register_timer()`
+    timer_sid: Option<xous::SID>,
+    timer_cb: Option<CID>,
 }
 impl Ticktimer {
     pub fn new() -> Result<Self, Error> {
         REFCOUNT.fetch_add(1, Ordering::Relaxed);
         let conn = xous::connect(xous::SID::from_bytes(b"ticktimer-server").unwrap())?;
-        Ok(Ticktimer { conn })
+        Ok(Ticktimer { conn, timer_sid: None, timer_cb: None })
     }
 
     /// note special case for elapsed_ms() is "infalliable". it really should never fail so get rid of the Error
@@ -167,12 +171,123 @@ impl Ticktimer {
         .map(|r| r == xous::Result::Scalar1(0))
         .expect("couldn't notify condition");
     }
+
+    /// Start a timer that sends a scalar message with `opcode` to `cid` after `delay_ms`, and then
+    /// every `period_ms` if that isn't 0. The message carries `data` in arg1 and, in arg2, how many
+    /// expiries it stands for; that's more than 1 if a periodic timer fell behind.
+    ///
+    /// The timer may fire up to `slack_ms` late, which lets the ticktimer serve several timers with
+    /// one wakeup. Timers stop when this object is dropped.
+    ///
+    /// Returns the timer's ID, for `cancel_timer()`.
+    pub fn register_timer(
+        &mut self,
+        cid: CID,
+        opcode: usize,
+        data: u32,
+        delay_ms: u64,
+        period_ms: u64,
+        slack_ms: u64,
+    ) -> Result<u32, Error> {
+        if self.timer_sid.is_none() {
+            let sid = xous::create_server()?;
+            self.timer_cb = Some(xous::connect(sid)?);
+            self.timer_sid = Some(sid);
+            let _ = std::thread::spawn(move || {
+                timer_cb_server(sid);
+            });
+        }
+        let s = self.timer_sid.unwrap().to_u32();
+        let hook = api::TimerHook {
+            sid: s,
+            cid,
+            opcode: opcode as u32,
+            data,
+            delay_ms,
+            period_ms,
+            slack_ms,
+            id: 0,
+        };
+        let mut buf = Buffer::into_buf(hook).or(Err(Error::InternalError))?;
+        buf.lend_mut(self.conn, api::Opcode::RegisterTimer.to_u32().unwrap())
+            .or(Err(Error::InternalError))?;
+        let hook = buf.to_original::<api::TimerHook, _>().or(Err(Error::InternalError))?;
+        if hook.id == 0 {
+            // the server's timer table is full
+            Err(Error::OutOfMemory)
+        } else {
+            Ok(hook.id)
+        }
+    }
+
+    /// Stop a timer. Returns false if it had already run out, or wasn't started by this process.
+    pub fn cancel_timer(&self, id: u32) -> Result<bool, Error> {
+        match send_message(
+            self.conn,
+            xous::Message::new_blocking_scalar(
+                api::Opcode::CancelTimer.to_usize().unwrap(),
+                id as usize,
+                0,
+                0,
+                0,
+            ),
+        )? {
+            xous::Result::Scalar1(cancelled) => Ok(cancelled != 0),
+            _ => Err(Error::InternalError),
+        }
+    }
+}
+
+/// Forwards timer expiries from the ticktimer server to wherever each timer asked for them
+fn timer_cb_server(sid: xous::SID) {
+    loop {
+        let msg = xous::receive_message(sid).unwrap();
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(api::TimerCallback::Expired) => xous::msg_scalar_unpack!(msg, cid, opcode, data, count, {
+                send_message(
+                    cid as CID,
+                    xous::Message::new_scalar(opcode, data, count, 0, 0),
+                )
+                .ok();
+            }),
+            Some(api::TimerCallback::Drop) => {
+                xous::return_scalar(msg.sender, 1).unwrap();
+                break;
+            }
+            None => {
+                log::error!("got unknown opcode: {:?}", msg);
+            }
+        }
+    }
+    xous::destroy_server(sid).unwrap();
 }
 
 use core::sync::atomic::{AtomicU32, Ordering};
 static REFCOUNT: AtomicU32 = AtomicU32::new(0);
 impl Drop for Ticktimer {
     fn drop(&mut self) {
+        if let Some(handler) = self.timer_cb.take() {
+            if let Some(sid) = self.timer_sid.take() {
+                let s = sid.to_u32();
+                send_message(
+                    self.conn,
+                    xous::Message::new_blocking_scalar(
+                        api::Opcode::UnhookTimers.to_usize().unwrap(),
+                        s.0 as usize,
+                        s.1 as usize,
+                        s.2 as usize,
+                        s.3 as usize,
+                    ),
+                )
+                .expect("couldn't unhook timers");
+            }
+            send_message(
+                handler,
+                xous::Message::new_blocking_scalar(api::TimerCallback::Drop.to_usize().unwrap(), 0, 0, 0, 0),
+            )
+            .ok();
+            unsafe { xous::disconnect(handler).ok() };
+        }
         // de-allocate myself. It's unsafe because we are responsible to make sure nobody else is using the connection.
         if REFCOUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
             unsafe {
@@ -3,6 +3,7 @@
 #![cfg_attr(target_os = "none", no_main)]
 
 mod api;
+mod timers;
 mod version;
 
 use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
@@ -15,6 +16,8 @@ type TimeoutExpiry = i64;
 pub enum RequestKind {
     Sleep = 0,
     Timeout = 1,
+    /// the next wakeup for the timers in `timers::TimerTable`; nobody is waiting on a reply
+    Timer = 2,
 }
 
 #[derive(Eq)]
@@ -84,15 +87,17 @@ mod implementation {
         // Safe because we're in an interrupt, and this interrupt is only
         // enabled when this value is not None.
         let response = xtt.current_response.take().unwrap();
-        xous::return_scalar(response.sender, response.kind as usize)
-            .expect("couldn't send response");
+        if response.kind != crate::RequestKind::Timer {
+            xous::return_scalar(response.sender, response.kind as usize)
+                .expect("couldn't send response");
+        }
 
         // Disable the timer
         xtt.csr.wfo(utra::ticktimer::EV_ENABLE_ALARM, 0);
         xtt.csr.wfo(utra::ticktimer::EV_PENDING_ALARM, 1);
 
-        // This is dangerous and may return an error if the queue is full.
-        // Which is fine, because the queue is always recalculated any time a message arrives.
+        // This can't block in an interrupt, so it's lost if the queue is full. That's fine: a full
+        // queue has messages in it, and the server re-arms and fires due timers after each one.
         use num_traits::ToPrimitive;
         xous::try_send_message(
             xtt.connection,
@@ -298,10 +303,14 @@ mod implementation {
             xous::MessageSender,
             i64, /* ms */
             u64, /* elapsed */
+            RequestKind,
+            usize, /* data */
         ),
     }
     pub struct XousTickTimer {
         start: std::time::Instant,
+        /// time spent in simulated suspends, which counts as elapsed
+        slept_ms: u64,
         sleep_comms: std::sync::mpsc::Sender<SleepComms>,
         time_remaining_receiver: std::sync::mpsc::Receiver<Option<TimerRequest>>,
     }
@@ -325,10 +334,13 @@ mod implementation {
                             let response = current_response.take().unwrap();
                             #[cfg(feature = "debug-print")]
                             log::info!("Returning scalar to {}", response.sender);
-                            xous::return_scalar(response.sender, response.kind as usize)
-                                .expect("couldn't send response");
+                            if response.kind != RequestKind::Timer {
+                                xous::return_scalar(response.sender, response.kind as usize)
+                                    .expect("couldn't send response");
+                            }
 
-                            // This is dangerous and may panic if the queue is full.
+                            // Not waited on, as the server may be waiting on us in `stop_interrupt()`.
+                            // If the queue's full, the server catches up after its next message.
                             xous::try_send_message(
                                 cid,
                                 xous::Message::Scalar(xous::ScalarMessage {
@@ -339,7 +351,7 @@ mod implementation {
                                     arg4: 0,
                                 }),
                             )
-                            .unwrap();
+                            .ok();
                             timeout = None;
                         }
                         Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
@@ -349,7 +361,7 @@ mod implementation {
                             timeout = None;
                             time_remaining_sender.send(current_response.take()).unwrap()
                         }
-                        Ok(SleepComms::StartSleep(new_sender, expiry, elapsed)) => {
+                        Ok(SleepComms::StartSleep(new_sender, expiry, elapsed, kind, data)) => {
                             let mut duration = expiry - (elapsed as i64);
                             if duration > 0 {
                                 #[cfg(feature = "debug-print")]
@@ -373,8 +385,8 @@ mod implementation {
                             current_response = Some(TimerRequest {
                                 sender: new_sender,
                                 msec: expiry,
-                                kind: RequestKind::Sleep,
-                                data: 0,
+                                kind,
+                                data,
                             });
                         }
                     }
@@ -384,6 +396,7 @@ mod implementation {
 
             XousTickTimer {
                 start: std::time::Instant::now(),
+                slept_ms: 0,
                 time_remaining_receiver,
                 sleep_comms: sleep_sender,
             }
@@ -394,7 +407,13 @@ mod implementation {
         }
 
         pub fn elapsed_ms(&self) -> u64 {
-            self.start.elapsed().as_millis().try_into().unwrap()
+            let elapsed: u64 = self.start.elapsed().as_millis().try_into().unwrap();
+            elapsed + self.slept_ms
+        }
+
+        /// Catches up with the total time `susres` has spent in simulated suspends
+        pub fn set_simulated_sleep(&mut self, slept_ms: u64) {
+            self.slept_ms = slept_ms;
         }
 
         pub fn stop_interrupt(&mut self) -> Option<TimerRequest> {
@@ -415,6 +434,8 @@ mod implementation {
                     request.sender,
                     request.msec as i64,
                     self.elapsed_ms(),
+                    request.kind,
+                    request.data,
                 ))
                 .unwrap();
         }
@@ -516,6 +537,55 @@ fn recalculate_sleep(
     start_sleep(ticktimer, sleep_heap);
 }
 
+/// Fires the timers that are due, then re-arms the wakeup: the next one for `timers` goes in the
+/// sleep heap, in place of whatever wakeup was there, and the earliest wakeup in the heap is
+/// scheduled. This runs when the timers change, and when a wakeup comes in; if the
+/// `RecalculateSleep` a wakeup sends doesn't make it onto a full queue, the timer it was for is
+/// overdue by the time the next message is done, and this runs then.
+fn service_timers(
+    ticktimer: &mut XousTickTimer,
+    sleep_heap: &mut BTreeMap<TimeoutExpiry, TimerRequest>,
+    timers: &mut timers::TimerTable,
+) {
+    stop_sleep(ticktimer, sleep_heap);
+    for expiry in timers.expire(ticktimer.elapsed_ms()) {
+        // never block here: a callback server that's backed up just gets a bigger count next time
+        if xous::try_send_message(
+            expiry.callback_cid,
+            xous::Message::new_scalar(
+                api::TimerCallback::Expired as usize,
+                expiry.cid as usize,
+                expiry.opcode as usize,
+                expiry.data as usize,
+                expiry.count,
+            ),
+        )
+        .is_err()
+            && !timers.undelivered(expiry.id, expiry.count)
+        {
+            log::warn!("timer {} expired, but its callback couldn't be delivered", expiry.id);
+        }
+    }
+    sleep_heap.retain(|_, request| request.kind != RequestKind::Timer);
+    let other = sleep_heap.keys().next().map(|&msec| msec.max(0) as u64);
+    if let Some(wakeup) = timers.next_wakeup(other) {
+        let mut msec = wakeup as TimeoutExpiry;
+        while sleep_heap.contains_key(&msec) {
+            msec += 1;
+        }
+        sleep_heap.insert(
+            msec,
+            TimerRequest {
+                msec,
+                sender: xous::MessageSender::from_usize(0),
+                kind: RequestKind::Timer,
+                data: 0,
+            },
+        );
+    }
+    start_sleep(ticktimer, sleep_heap);
+}
+
 #[xous::xous_main]
 fn xmain() -> ! {
     log_server::init_wait().unwrap();
@@ -587,7 +657,19 @@ fn xmain() -> ! {
     let mut mutex_hash: HashMap<Option<xous::PID>, HashMap<usize, VecDeque<xous::MessageSender>>> =
         HashMap::new();
 
+    // Timers started with `RegisterTimer`, and our connections to the callback servers they
+    // report to, along with the process that hooked each one up.
+    let mut timers = timers::TimerTable::new();
+    let mut timer_callbacks: HashMap<(u32, u32, u32, u32), (xous::CID, Option<xous::PID>)> =
+        HashMap::new();
+
+    let mut rearm = true;
     loop {
+        if rearm || timers.overdue(ticktimer.elapsed_ms()) {
+            service_timers(&mut ticktimer, &mut sleep_heap, &mut timers);
+            rearm = false;
+        }
+
         #[cfg(feature = "watchdog")]
         ticktimer.reset_wdt();
         //#[cfg(feature = "watchdog")] // for debugging the watchdog
@@ -650,14 +732,59 @@ fn xmain() -> ! {
                         }
                     }
                 }
-                recalculate_sleep(&mut ticktimer, &mut sleep_heap, None);
+                // a wakeup: due timers fire, and the next wakeup is armed, before the next message
+                rearm = true;
+            }
+            Some(api::Opcode::RegisterTimer) => {
+                let mut buffer = unsafe {
+                    xous_ipc::Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let mut hook = buffer.to_original::<api::TimerHook, _>().unwrap();
+                let callback_cid = match timer_callbacks.get(&hook.sid) {
+                    Some((cid, _)) => Some(*cid),
+                    None => match xous::connect(xous::SID::from_u32(hook.sid.0, hook.sid.1, hook.sid.2, hook.sid.3)) {
+                        Ok(cid) => {
+                            timer_callbacks.insert(hook.sid, (cid, msg.sender.pid()));
+                            Some(cid)
+                        }
+                        Err(e) => {
+                            log::error!("couldn't connect to a timer callback server: {:?}", e);
+                            None
+                        }
+                    },
+                };
+                hook.id = callback_cid
+                    .and_then(|cid| timers.add(ticktimer.elapsed_ms(), msg.sender.pid(), cid, &hook))
+                    .unwrap_or(0);
+                rearm = hook.id != 0;
+                buffer.replace(hook).unwrap();
             }
+            Some(api::Opcode::CancelTimer) => xous::msg_blocking_scalar_unpack!(msg, id, _, _, _, {
+                let cancelled = timers.cancel(id as u32, msg.sender.pid());
+                rearm = cancelled;
+                xous::return_scalar(msg.sender, if cancelled { 1 } else { 0 }).unwrap();
+            }),
+            Some(api::Opcode::UnhookTimers) => xous::msg_blocking_scalar_unpack!(msg, s0, s1, s2, s3, {
+                let sid = (s0 as u32, s1 as u32, s2 as u32, s3 as u32);
+                rearm = timers.unhook(sid, msg.sender.pid()) != 0;
+                if timer_callbacks.get(&sid).map_or(false, |(_, owner)| *owner == msg.sender.pid()) {
+                    let (cid, _) = timer_callbacks.remove(&sid).unwrap();
+                    unsafe { xous::disconnect(cid).ok() };
+                }
+                xous::return_scalar(msg.sender, 1).unwrap();
+            }),
             Some(api::Opcode::SuspendResume) => xous::msg_scalar_unpack!(msg, token, _, _, _, {
                 ticktimer.suspend();
                 susres
                     .suspend_until_resume(token)
                     .expect("couldn't execute suspend/resume");
                 ticktimer.resume();
+                // time moves on by however long the simulated sleep was; sleeps that
+                // would have ended meanwhile are due right away, as are timers, which fire
+                // once this message is done
+                #[cfg(not(any(target_os = "none", target_os = "xous")))]
+                ticktimer.set_simulated_sleep(susres.simulated_sleep_ms().unwrap_or(0));
+                rearm = true;
             }),
             Some(api::Opcode::PingWdt) => {
                 ticktimer.reset_wdt();
@@ -0,0 +1,262 @@
+//! Timers that clients start with `RegisterTimer`, and that come due as scalar messages.
+//!
+//! Deadlines are in ticktimer time. On hardware that stands still while the device is
+//! suspended, so a timer keeps the time it had left across a suspend; in hosted mode the
+//! simulated sleep counts, and anything that came due meanwhile fires on resume.
+//!
+//! Each timer may fire up to its slack late. The server wakes up at the earliest deadline,
+//! unless another deadline (or another wakeup the server already has) falls within that
+//! timer's slack, in which case it waits for that one and fires everything due by then. So
+//! timers with overlapping windows share a wakeup, and a timer is never late for nothing. A periodic timer that fell behind (or whose message
+//! couldn't be delivered) fires once, with a count of the periods it stands for.
+
+use crate::api::TimerHook;
+use std::collections::BTreeMap;
+
+/// most timers the server keeps at once
+pub(crate) const MAX_TIMERS: usize = 128;
+
+struct Timer {
+    owner: Option<xous::PID>,
+    callback: (u32, u32, u32, u32),
+    /// the server's connection to the callback server
+    callback_cid: xous::CID,
+    cid: xous::CID,
+    opcode: u32,
+    data: u32,
+    deadline: u64,
+    period: u64,
+    slack: u64,
+    /// expiries that couldn't be delivered, to be added to the next one
+    undelivered: usize,
+}
+
+/// A timer that came due, and where to report it
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Expiry {
+    pub id: u32,
+    pub callback_cid: xous::CID,
+    pub cid: xous::CID,
+    pub opcode: u32,
+    pub data: u32,
+    pub count: usize,
+}
+
+pub(crate) struct TimerTable {
+    timers: BTreeMap<u32, Timer>,
+    last_id: u32,
+}
+
+impl TimerTable {
+    pub(crate) fn new() -> TimerTable {
+        TimerTable { timers: BTreeMap::new(), last_id: 0 }
+    }
+
+    /// Starts the timer in `hook` at `now`. Returns its ID, which is never 0, or `None` if the table is full.
+    pub(crate) fn add(&mut self, now: u64, owner: Option<xous::PID>, callback_cid: xous::CID, hook: &TimerHook) -> Option<u32> {
+        if self.timers.len() >= MAX_TIMERS {
+            return None;
+        }
+        let mut id = self.last_id;
+        loop {
+            id = id.wrapping_add(1);
+            if id != 0 && !self.timers.contains_key(&id) {
+                break;
+            }
+        }
+        self.last_id = id;
+        self.timers.insert(
+            id,
+            Timer {
+                owner,
+                callback: hook.sid,
+                callback_cid,
+                cid: hook.cid,
+                opcode: hook.opcode,
+                data: hook.data,
+                deadline: now.saturating_add(hook.delay_ms),
+                period: hook.period_ms,
+                slack: hook.slack_ms,
+                undelivered: 0,
+            },
+        );
+        Some(id)
+    }
+
+    /// Cancels timer `id`, if `owner` started it
+    pub(crate) fn cancel(&mut self, id: u32, owner: Option<xous::PID>) -> bool {
+        if matches!(self.timers.get(&id), Some(t) if t.owner == owner) {
+            self.timers.remove(&id);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Cancels every timer `owner` has reporting to `callback`. Returns how many there were.
+    pub(crate) fn unhook(&mut self, callback: (u32, u32, u32, u32), owner: Option<xous::PID>) -> usize {
+        let before = self.timers.len();
+        self.timers.retain(|_, t| t.callback != callback || t.owner != owner);
+        before - self.timers.len()
+    }
+
+    /// When the server should wake up next for its timers. `other` is the next wakeup it has
+    /// for something else, which timers can share too.
+    pub(crate) fn next_wakeup(&self, other: Option<u64>) -> Option<u64> {
+        // no timer can wait past the end of the earliest slack window...
+        let latest = self.timers.values().map(|t| t.deadline.saturating_add(t.slack)).min()?;
+        // ...and waiting for the last deadline before then covers the most timers
+        self.timers.values().map(|t| t.deadline).chain(other).filter(|&d| d <= latest).max()
+    }
+
+    /// Whether a timer is past the end of its slack window at `now`, which means the wakeup for it
+    /// was missed
+    pub(crate) fn overdue(&self, now: u64) -> bool {
+        self.timers.values().any(|t| t.deadline.saturating_add(t.slack) < now)
+    }
+
+    /// Takes every timer that's due at `now`. One-shot timers are done; periodic ones move on to
+    /// their first deadline after `now`, keeping their phase.
+    pub(crate) fn expire(&mut self, now: u64) -> Vec<Expiry> {
+        let mut due = Vec::new();
+        for (&id, t) in self.timers.iter_mut() {
+            if t.deadline > now {
+                continue;
+            }
+            let periods = (now - t.deadline).checked_div(t.period).map_or(1, |n| n + 1);
+            due.push(Expiry {
+                id,
+                callback_cid: t.callback_cid,
+                cid: t.cid,
+                opcode: t.opcode,
+                data: t.data,
+                count: periods as usize + t.undelivered,
+            });
+            t.undelivered = 0;
+            if t.period != 0 {
+                t.deadline += periods * t.period;
+            }
+        }
+        self.timers.retain(|_, t| t.deadline > now);
+        due
+    }
+
+    /// Notes that an expiry couldn't be delivered, so a periodic timer reports it next time.
+    /// Returns false if the timer is gone, i.e. it was a one-shot.
+    pub(crate) fn undelivered(&mut self, id: u32, count: usize) -> bool {
+        match self.timers.get_mut(&id) {
+            Some(t) => {
+                t.undelivered += count;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(data: u32, delay_ms: u64, period_ms: u64, slack_ms: u64) -> TimerHook {
+        TimerHook { sid: (1, 2, 3, 4), cid: 7, opcode: 42, data, delay_ms, period_ms, slack_ms, id: 0 }
+    }
+
+    fn fired(expiries: &[Expiry]) -> Vec<(u32, usize)> {
+        expiries.iter().map(|e| (e.data, e.count)).collect()
+    }
+
+    #[test]
+    fn one_shot_and_periodic() {
+        let pid = xous::PID::new(5);
+        let mut table = TimerTable::new();
+        let once = table.add(1000, pid, 9, &hook(1, 50, 0, 0)).unwrap();
+        let tick = table.add(1000, pid, 9, &hook(2, 100, 100, 0)).unwrap();
+        assert_ne!(once, tick);
+        assert_eq!(table.next_wakeup(None), Some(1050));
+        assert!(table.expire(1049).is_empty());
+        assert_eq!(fired(&table.expire(1050)), vec![(1, 1)]);
+        assert_eq!(table.next_wakeup(None), Some(1100));
+        assert_eq!(fired(&table.expire(1100)), vec![(2, 1)]);
+        // 1200, 1300 and 1400 all went by: one message standing for all of them, and the phase is kept
+        assert_eq!(fired(&table.expire(1420)), vec![(2, 3)]);
+        assert_eq!(table.next_wakeup(None), Some(1500));
+        assert!(table.cancel(tick, pid));
+        assert!(!table.cancel(once, pid));
+        assert_eq!(table.next_wakeup(None), None);
+        assert_eq!(table.next_wakeup(Some(1600)), None);
+    }
+
+    #[test]
+    fn slack_shares_wakeups() {
+        let mut table = TimerTable::new();
+        table.add(0, None, 9, &hook(1, 100, 0, 50)).unwrap();
+        table.add(0, None, 9, &hook(2, 120, 0, 0)).unwrap();
+        table.add(0, None, 9, &hook(3, 200, 0, 10)).unwrap();
+        // the first timer can wait for the second, the third can't be pulled in early
+        assert_eq!(table.next_wakeup(None), Some(120));
+        assert_eq!(fired(&table.expire(120)), vec![(1, 1), (2, 1)]);
+        // with nothing to wait for, a timer fires at its deadline rather than the end of its slack
+        assert_eq!(table.next_wakeup(None), Some(200));
+        assert_eq!(fired(&table.expire(200)), vec![(3, 1)]);
+    }
+
+    #[test]
+    fn slack_shares_other_wakeups() {
+        let mut table = TimerTable::new();
+        table.add(0, None, 9, &hook(1, 100, 0, 50)).unwrap();
+        // a sleep ending within the slack is waited for; one ending after it, or before the
+        // deadline, isn't
+        assert_eq!(table.next_wakeup(Some(130)), Some(130));
+        assert_eq!(table.next_wakeup(Some(151)), Some(100));
+        assert_eq!(table.next_wakeup(Some(90)), Some(100));
+    }
+
+    #[test]
+    fn ownership_and_unhooking() {
+        let (a, b) = (xous::PID::new(5), xous::PID::new(6));
+        let mut table = TimerTable::new();
+        let id = table.add(0, a, 9, &hook(1, 100, 100, 0)).unwrap();
+        table.add(0, a, 9, &hook(2, 100, 0, 0)).unwrap();
+        let mut other = hook(3, 100, 0, 0);
+        other.sid = (5, 6, 7, 8);
+        table.add(0, b, 10, &other).unwrap();
+        assert!(!table.cancel(id, b));
+        // someone else can't take down a's timers
+        assert_eq!(table.unhook((1, 2, 3, 4), b), 0);
+        assert_eq!(table.unhook((1, 2, 3, 4), a), 2);
+        assert_eq!(fired(&table.expire(100)), vec![(3, 1)]);
+    }
+
+    #[test]
+    fn undelivered_expiries_carry_over() {
+        let mut table = TimerTable::new();
+        let tick = table.add(0, None, 9, &hook(1, 10, 10, 0)).unwrap();
+        let once = table.add(0, None, 9, &hook(2, 10, 0, 0)).unwrap();
+        assert_eq!(table.expire(10).len(), 2);
+        assert!(table.undelivered(tick, 1));
+        assert!(!table.undelivered(once, 1));
+        assert_eq!(fired(&table.expire(20)), vec![(1, 2)]);
+    }
+
+    #[test]
+    fn overdue_only_past_the_slack() {
+        let mut table = TimerTable::new();
+        assert!(!table.overdue(1000));
+        table.add(0, None, 9, &hook(1, 100, 0, 50)).unwrap();
+        // due, but still allowed to wait for a shared wakeup
+        assert!(!table.overdue(150));
+        assert!(table.overdue(151));
+        table.expire(151);
+        assert!(!table.overdue(151));
+    }
+
+    #[test]
+    fn full_table() {
+        let mut table = TimerTable::new();
+        for _ in 0..MAX_TIMERS {
+            assert!(table.add(0, None, 9, &hook(0, 10, 0, 0)).is_some());
+        }
+        assert_eq!(table.add(0, None, 9, &hook(0, 10, 0, 0)), None);
+    }
+}
@@ -1,6 +1,6 @@
 pub(crate) const SERVER_NAME_TRNG: &str = "_TRNG manager_";
 
-#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Default)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Default)]
 pub struct ExcursionTest {
     pub min: u16,
     pub max: u16,
@@ -24,7 +24,7 @@ pub struct HealthTests {
     pub ro_nist: [NistTests; 4],
 }
 
-#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Default)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Default)]
 pub struct TrngErrors {
     pub excursion_errs: [Option<ExcursionTest>; 2],
     pub av_repcount_errs: Option<u8>,
@@ -71,9 +71,55 @@ pub(crate) enum Opcode {
     /// Get Error stats
     ErrorStats,
 
+    /// Get the health policy's state, as a `HealthStatus`
+    HealthStatus,
+
+    /// Internal: time for a periodic health check
+    HealthCheck,
+
+    /// Hosted mode only: makes the fake noise source report a health test failure
+    InjectFault,
+
     Quit,
 }
 
+/// Whether the TRNG is serving entropy
+#[derive(Debug, Copy, Clone, Eq, PartialEq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum HealthState {
+    /// no health check has found the tests passing since boot; requests wait for the first one
+    Starting,
+    /// the health tests are passing
+    Healthy,
+    /// a health test failed; requests wait until enough checks in a row find the tests passing
+    Recovering,
+    /// the source failed too often; requests fail until reboot
+    Failed,
+}
+impl Default for HealthState {
+    fn default() -> Self {
+        HealthState::Healthy
+    }
+}
+
+/// The health policy, and what it's done since boot
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Default)]
+pub struct HealthStatus {
+    pub state: HealthState,
+    /// health test failures
+    pub failures: u32,
+    /// checks passed since the last failure, while recovering
+    pub recovery_passes: u32,
+    /// requests that were answered with an error, because the source had been given up on
+    pub refused: u32,
+    /// requests that had to wait for the source to pass its first health check, or to recover
+    pub deferred: u32,
+    /// times the DRBG was reseeded from the noise source
+    pub reseeds: u32,
+    pub words_served: u64,
+    /// what the hardware reported at the last failure
+    pub last_errors: TrngErrors,
+}
+
 #[derive(Debug, num_derive::FromPrimitive, num_derive::ToPrimitive)]
 pub(crate) enum EventCallback {
     Event,
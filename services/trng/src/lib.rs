@@ -21,6 +21,9 @@ impl Trng {
             error_sid: None,
         })
     }
+    /// Blocks while the health tests are failing, as do `get_u64()` and `fill_buf()`: entropy
+    /// is only handed out while the noise source is known to be good. If the source fails so
+    /// often that it's given up on, these return `xous::Error::InternalError` until reboot.
     pub fn get_u32(&self) -> Result<u32, xous::Error> {
         let response = send_message(
             self.conn,
@@ -35,6 +38,8 @@ impl Trng {
         .expect("TRNG|LIB: can't get_u32");
         if let xous::Result::Scalar2(trng, _) = response {
             Ok(trng as u32)
+        } else if let xous::Result::Scalar1(_) = response {
+            Err(xous::Error::InternalError)
         } else {
             panic!("unexpected return value: {:#?}", response);
         }
@@ -53,6 +58,8 @@ impl Trng {
         .expect("TRNG|LIB: can't get_u32");
         if let xous::Result::Scalar2(lo, hi) = response {
             Ok(lo as u64 | ((hi as u64) << 32))
+        } else if let xous::Result::Scalar1(_) = response {
+            Err(xous::Error::InternalError)
         } else {
             panic!("unexpected return value: {:#?}", response);
         }
@@ -110,6 +117,23 @@ impl Trng {
             .or(Err(xous::Error::InternalError))?;
         Ok(buf.to_original().unwrap())
     }
+    /// Whether entropy is being served, and how the health policy has fared since boot
+    pub fn get_health_status(&self) -> Result<api::HealthStatus, xous::Error> {
+        let hs = api::HealthStatus::default();
+        let mut buf = Buffer::into_buf(hs).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, api::Opcode::HealthStatus.to_u32().unwrap())
+            .or(Err(xous::Error::InternalError))?;
+        Ok(buf.to_original().unwrap())
+    }
+    /// Hosted mode only: has the fake noise source fail a health test, to exercise the policy.
+    /// `which` picks the test; see the hosted `Trng::inject_fault()`.
+    pub fn inject_fault(&self, which: usize) -> Result<(), xous::Error> {
+        send_message(
+            self.conn,
+            xous::Message::new_scalar(api::Opcode::InjectFault.to_usize().unwrap(), which, 0, 0, 0),
+        )
+        .map(|_| ())
+    }
     pub fn get_error_stats(&self) -> Result<api::TrngErrors, xous::Error> {
         let errs = api::TrngErrors::default();
         let mut buf = Buffer::into_buf(errs).or(Err(xous::Error::InternalError))?;
@@ -3,6 +3,10 @@
 
 mod api;
 use api::*;
+mod policy;
+#[cfg(not(any(target_os = "none", target_os = "xous")))]
+mod seed;
+use policy::{tests_passed, Drbg, Gate, HealthPolicy, SEED_WORDS};
 
 use num_traits::*;
 use xous::CID;
@@ -10,6 +14,9 @@ use xous_ipc::Buffer;
 
 use log::info;
 
+/// How often the health policy checks in on the source
+const HEALTH_CHECK_MS: usize = 1000;
+
 #[derive(Copy, Clone, Debug)]
 struct ScalarCallback {
     server_to_cb_cid: CID,
@@ -19,7 +26,7 @@ struct ScalarCallback {
 
 #[cfg(any(target_os = "none", target_os = "xous"))]
 mod implementation {
-    use crate::api::{ExcursionTest, HealthTests, MiniRunsTest, NistTests, TrngBuf, TrngErrors};
+    use crate::api::{ExcursionTest, HealthTests, MiniRunsTest, NistTests, TrngErrors};
     use num_traits::*;
     use susres::{RegManager, RegOrField, SuspendResume};
     use utralib::generated::*;
@@ -428,15 +435,14 @@ mod implementation {
             }
         }
 
-        pub fn get_buf(&mut self, len: u16) -> TrngBuf {
-            let mut tb = TrngBuf {
-                data: [0; 1024],
-                len,
-            };
-            for i in 0..len as usize {
-                tb.data[i] = self.get_data_eager();
+        /// Raw words from the noise sources, for seeding the DRBG
+        pub fn get_noise(&mut self, out: &mut [u32]) {
+            for w in out.iter_mut() {
+                while self.csr.rf(utra::trng_server::STATUS_AVAIL) == 0 {
+                    xous::yield_slice();
+                }
+                *w = self.csr.rf(utra::trng_server::DATA_DATA);
             }
-            tb
         }
 
         pub fn get_trng(&mut self, count: usize) -> [u32; 2] {
@@ -470,78 +476,73 @@ mod implementation {
     use rand_chacha::ChaCha8Rng;
     use rand_chacha::rand_core::SeedableRng;
     use rand_chacha::rand_core::RngCore;
-    use crate::api::{HealthTests, TrngBuf, TrngErrors};
+    use crate::api::{HealthTests, TrngErrors};
 
+    /// A fake noise source. It never fails on its own, but `inject_fault()` makes it report
+    /// a failure just as the hardware would, so the health policy can be exercised. Its stream
+    /// is set by a seed, chosen as described in `seed.rs` and logged at boot.
     pub struct Trng {
         rng: ChaCha8Rng,
-        seed: u32,
-        msgcount: u16, // re-print the message every time we rollover
+        errors: TrngErrors,
     }
 
     impl Trng {
         pub fn new(_xns: &xous_names::XousNames) -> Trng {
+            let setting = std::env::var(crate::seed::TRNG_SEED_VAR).ok();
+            let (seed, source) = crate::seed::choose(
+                setting.as_deref(),
+                xous::TESTING_RNG_SEED.load(core::sync::atomic::Ordering::SeqCst),
+                || rand::RngCore::next_u64(&mut rand::rngs::OsRng),
+            ).unwrap_or_else(|e| panic!("{}", e));
+            log::info!("hosted mode TRNG is *not* a TRNG: it is a ChaCha8 stream seeded with {:#x} ({:?})", seed, source);
+            log::info!("to replay this run, set {}={:#x}", crate::seed::TRNG_SEED_VAR, seed);
             Trng {
-                rng: ChaCha8Rng::seed_from_u64(xous::TESTING_RNG_SEED.load(core::sync::atomic::Ordering::SeqCst)),
-                seed: 0x1afe_cafe,
-                msgcount: 0,
+                rng: ChaCha8Rng::seed_from_u64(seed),
+                errors: TrngErrors::default(),
             }
         }
 
-        fn move_lfsr(&self, mut lfsr: u32) -> u32 {
-            lfsr ^= lfsr >> 7;
-            lfsr ^= lfsr << 9;
-            lfsr ^= lfsr >> 13;
-            lfsr
-        }
-
         #[allow(dead_code)]
         pub fn wait_full(&self) {}
 
-        pub fn get_buf(&mut self, len: u16) -> TrngBuf {
-            if self.msgcount < 3 {
-                log::info!("hosted mode TRNG is *not* random, it is a deterministic LFSR");
-            }
-            self.msgcount += 1;
-            let mut data = [0; 1024];
-            for d in data.iter_mut() {
+        pub fn get_noise(&mut self, out: &mut [u32]) {
+            for d in out.iter_mut() {
                 *d = self.rng.next_u32();
             }
-            TrngBuf {
-                data,
-                len,
-            }
         }
 
         pub fn get_trng(&mut self, _count: usize) -> [u32; 2] {
-            if self.msgcount < 3 {
-                log::info!("hosted mode TRNG is *not* random, it is a deterministic LFSR");
-            }
-            self.msgcount += 1;
-            let mut ret: [u32; 2] = [0; 2];
-            self.seed = self.move_lfsr(self.seed);
-            ret[0] = self.seed;
-            self.seed = self.move_lfsr(self.seed);
-            ret[1] = self.seed;
+            [self.rng.next_u32(), self.rng.next_u32()]
+        }
 
-            ret
+        /// Reports a failure of the kind `which`: 0 is an AV repetition count failure, 1 an AV
+        /// adaptive proportion failure, 2 an AV excursion, anything else an RO repetition count
+        /// failure
+        pub fn inject_fault(&mut self, which: usize) {
+            self.errors = TrngErrors::default();
+            match which {
+                0 => self.errors.av_repcount_errs = Some(1),
+                1 => self.errors.av_adaptive_errs = Some(1),
+                2 => self.errors.excursion_errs[0] = Some(crate::api::ExcursionTest { min: 0x800, max: 0x801 }),
+                _ => self.errors.ro_repcount_errs = Some(1),
+            }
+            self.errors.nist_errs = 1;
         }
         pub fn suspend(&self) {}
         pub fn resume(&self) {}
+        /// The fake source's tests always have fresh results, so only injected faults fail them
         pub fn get_tests(&self) -> HealthTests {
-            HealthTests::default()
+            let mut tests = HealthTests::default();
+            for t in tests.av_nist.iter_mut().chain(tests.ro_nist.iter_mut()) {
+                t.fresh = true;
+            }
+            for t in tests.ro_miniruns.iter_mut() {
+                t.fresh = true;
+            }
+            tests
         }
         pub fn get_errors(&self) -> TrngErrors {
-            TrngErrors {
-                excursion_errs: [None; 2],
-                av_repcount_errs: None,
-                av_adaptive_errs: None,
-                ro_repcount_errs: None,
-                ro_adaptive_errs: None,
-                kernel_underruns: 0,
-                server_underruns: 0,
-                nist_errs: 0,
-                pending_mask: 0,
-            }
+            self.errors
         }
         pub fn get_err_stats(&self) -> HealthTests {
             HealthTests::default()
@@ -792,15 +793,50 @@ fn xmain() -> ! {
     let mut susres = susres::Susres::new(Some(susres::SuspendOrder::Late), &xns, api::Opcode::SuspendResume as u32, sr_cid)
         .expect("couldn't create suspend/resume object");
 
+    // noise for the first seed. It's only used once a health check after it was drawn has
+    // passed, and nothing is served before then.
+    let mut policy = HealthPolicy::new();
+    let mut noise = [0u32; SEED_WORDS];
+    trng.get_noise(&mut noise);
+    let mut drbg = Drbg::new();
+    drbg.collect(&noise);
+
+    // periodic health checks: these open the gate at boot and after a failure, and commit seeds.
+    // The first one goes out right away, so callers at boot aren't kept waiting any longer than
+    // it takes.
+    let check_cid = xous::connect(trng_sid).expect("couldn't connect for health checks");
+    std::thread::spawn(move || {
+        let tt = ticktimer_server::Ticktimer::new().unwrap();
+        loop {
+            xous::send_message(check_cid,
+                xous::Message::new_scalar(api::Opcode::HealthCheck.to_usize().unwrap(), 0, 0, 0, 0)
+            ).expect("couldn't send health check");
+            tt.sleep_ms(HEALTH_CHECK_MS).unwrap();
+        }
+    });
+    let mut status = HealthStatus::default();
+    // requests that came in before the gate first opened, or while it was closed. Each one is a
+    // blocked caller, so there can't be more of them than there are threads.
+    let mut held: Vec<xous::MessageEnvelope> = Vec::new();
+    // what the hardware had reported as of the last health check
+    let mut last_errors = trng.get_errors();
+
     let mut error_cb_conns: [Option<ScalarCallback>; 32] = [None; 32];
     loop {
         let mut msg = xous::receive_message(trng_sid).unwrap();
         match FromPrimitive::from_usize(msg.body.id()) {
-            Some(api::Opcode::GetTrng) => xous::msg_blocking_scalar_unpack!(msg, count, _, _, _, {
-                let val: [u32; 2] = trng.get_trng(count);
-                xous::return_scalar2(msg.sender, val[0] as _, val[1] as _)
-                    .expect("couldn't return GetTrng request");
-            }),
+            Some(api::Opcode::GetTrng) | Some(api::Opcode::FillTrng) => {
+                // callers wait rather than get an error while the source may yet come good: they all
+                // expect entropy to be there. Once it's been given up on, they get an error.
+                match policy.gate() {
+                    Gate::Open if drbg.is_seeded() => serve(&mut msg, &mut drbg, &mut status),
+                    Gate::Latched => refuse(&mut msg, &mut status),
+                    _ => {
+                        status.deferred = status.deferred.saturating_add(1);
+                        held.push(msg);
+                    }
+                }
+            }
             Some(api::Opcode::SuspendResume) => xous::msg_scalar_unpack!(msg, token, _, _, _, {
                 trng.suspend();
                 susres
@@ -820,26 +856,94 @@ fn xmain() -> ! {
                     trng.get_errors()
                 );
                 log::error!("Stats: {:?}", trng.get_err_stats());
+                policy.failure();
+                // whatever was collected for a reseed may have come from the failing source
+                drbg.discard_pending();
+                status.last_errors = trng.get_errors();
+                if policy.gate() == Gate::Latched {
+                    log::error!("TRNG failed {} health tests; no more entropy will be served until reboot", policy.failures());
+                    for mut msg in held.drain(..) {
+                        refuse(&mut msg, &mut status);
+                    }
+                } else {
+                    log::warn!("TRNG output held until the health tests pass again");
+                }
                 send_event(&error_cb_conns);
             }
-            Some(api::Opcode::HealthStats) => {
+            Some(api::Opcode::HealthCheck) => xous::msg_scalar_unpack!(msg, _, _, _, _, {
+                let starting = policy.gate() == Gate::Starting;
+                if let Gate::Closed { .. } | Gate::Starting = policy.gate() {
+                    // nothing's drawing on the source, so run it for the tests to have something to look at
+                    trng.get_noise(&mut noise);
+                }
+                let errors = trng.get_errors();
+                let passed = tests_passed(&last_errors, &errors, &trng.get_tests());
+                last_errors = errors;
+                if !passed {
+                    drbg.discard_pending();
+                }
+                let reopened = policy.check(passed);
+                if policy.is_open() && passed {
+                    // noise collected before this check passed is good to use
+                    drbg.commit_pending();
+                }
+                if policy.is_open() && drbg.is_seeded() && !held.is_empty() {
+                    log::info!("TRNG health tests passing, serving {} held requests", held.len());
+                    for mut msg in held.drain(..) {
+                        serve(&mut msg, &mut drbg, &mut status);
+                    }
+                }
+                if reopened && !starting {
+                    send_event(&error_cb_conns);
+                }
+                // until the DRBG has its first seed, there's always noise waiting on the next check
+                if drbg.needs_reseed() && (policy.is_open() || !drbg.is_seeded()) && policy.gate() != Gate::Latched {
+                    trng.get_noise(&mut noise);
+                    drbg.collect(&noise);
+                }
+            }),
+            Some(api::Opcode::HealthStatus) => {
                 let mut buffer = unsafe {
                     Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
                 };
-                buffer.replace(trng.get_tests()).unwrap();
+                status.state = match policy.gate() {
+                    Gate::Starting => HealthState::Starting,
+                    Gate::Open if !drbg.is_seeded() => HealthState::Starting,
+                    Gate::Open => HealthState::Healthy,
+                    Gate::Closed { .. } => HealthState::Recovering,
+                    Gate::Latched => HealthState::Failed,
+                };
+                status.failures = policy.failures();
+                status.recovery_passes = match policy.gate() {
+                    Gate::Closed { passes } => passes,
+                    _ => 0,
+                };
+                status.reseeds = drbg.reseeds();
+                buffer.replace(status).unwrap();
             }
-            Some(api::Opcode::ErrorStats) => {
+            Some(api::Opcode::InjectFault) => xous::msg_scalar_unpack!(msg, which, _, _, _, {
+                #[cfg(not(any(target_os = "none", target_os = "xous")))]
+                {
+                    log::warn!("injecting a TRNG health test failure");
+                    trng.inject_fault(which);
+                    xous::send_message(check_cid,
+                        xous::Message::new_scalar(api::Opcode::ErrorNotification.to_usize().unwrap(), 0, 0, 0, 0)
+                    ).expect("couldn't inject fault");
+                }
+                #[cfg(any(target_os = "none", target_os = "xous"))]
+                log::warn!("ignoring fault injection request {} on real hardware", which);
+            }),
+            Some(api::Opcode::HealthStats) => {
                 let mut buffer = unsafe {
                     Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
                 };
-                buffer.replace(trng.get_errors()).unwrap();
+                buffer.replace(trng.get_tests()).unwrap();
             }
-            Some(api::Opcode::FillTrng) => {
+            Some(api::Opcode::ErrorStats) => {
                 let mut buffer = unsafe {
                     Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
                 };
-                let len = buffer.as_flat::<TrngBuf, _>().unwrap().len;
-                buffer.replace(trng.get_buf(len)).unwrap();
+                buffer.replace(trng.get_errors()).unwrap();
             }
             Some(api::Opcode::Quit) => break,
             None => {
@@ -856,6 +960,39 @@ fn xmain() -> ! {
     xous::terminate_process(0)
 }
 
+/// Answers a `GetTrng` or `FillTrng` request with an error, because the source has been given
+/// up on: a `Scalar1` instead of a `Scalar2`, or a `TrngBuf` with nothing in it
+fn refuse(msg: &mut xous::MessageEnvelope, status: &mut HealthStatus) {
+    status.refused = status.refused.saturating_add(1);
+    if msg.body.scalar_message().is_some() {
+        xous::return_scalar(msg.sender, 0).expect("couldn't refuse GetTrng request");
+    } else if let Some(mem) = msg.body.memory_message_mut() {
+        let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
+        let mut tb = buffer.to_original::<TrngBuf, _>().unwrap();
+        tb.len = 0;
+        buffer.replace(tb).unwrap();
+    }
+}
+
+/// Answers a `GetTrng` or `FillTrng` request from the DRBG
+fn serve(msg: &mut xous::MessageEnvelope, drbg: &mut Drbg, status: &mut HealthStatus) {
+    if let Some(scalar) = msg.body.scalar_message() {
+        let mut val = [0u32; 2];
+        let count = scalar.arg1.min(2).max(1);
+        drbg.fill(&mut val[..count]);
+        status.words_served += count as u64;
+        xous::return_scalar2(msg.sender, val[0] as _, val[1] as _)
+            .expect("couldn't return GetTrng request");
+    } else if let Some(mem) = msg.body.memory_message_mut() {
+        let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
+        let mut tb = buffer.to_original::<TrngBuf, _>().unwrap();
+        let len = (tb.len as usize).min(tb.data.len());
+        drbg.fill(&mut tb.data[..len]);
+        status.words_served += len as u64;
+        buffer.replace(tb).unwrap();
+    }
+}
+
 fn do_hook(hookdata: ScalarHook, cb_conns: &mut [Option<ScalarCallback>; 32]) {
     let (s0, s1, s2, s3) = hookdata.sid;
     let sid = xous::SID::from_u32(s0, s1, s2, s3);
@@ -0,0 +1,323 @@
+//! What the TRNG server does with its noise source: entropy is only handed out while the
+//! hardware health tests are passing, and it's handed out through a DRBG that is only ever
+//! seeded and reseeded with words drawn while the source was healthy.
+//!
+//! Nothing here touches hardware. The server feeds it health events and noise words, so the
+//! policy can be driven by a fake source in tests and in hosted mode.
+
+use crate::api::{HealthTests, TrngErrors};
+use rand_chacha::ChaCha20Rng;
+use rand_chacha::rand_core::{RngCore, SeedableRng};
+
+/// Health checks in a row that have to find the tests passing before output resumes after a failure
+pub const RECOVERY_CHECKS: u32 = 3;
+/// Failures in one boot after which the source is given up on until reboot
+pub const LATCH_FAILURES: u32 = 8;
+/// Words served between reseeds of the DRBG
+pub const RESEED_WORDS: u64 = 1 << 18;
+/// Words of noise that go into one reseed
+pub const SEED_WORDS: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Gate {
+    /// no health check has found the tests passing since boot; nothing is served yet
+    Starting,
+    /// the health tests are passing; entropy is served
+    Open,
+    /// a health test failed recently; nothing is served until `passes` checks in a row have found
+    /// the tests passing, and that reaches `RECOVERY_CHECKS`
+    Closed { passes: u32 },
+    /// the source failed too often; nothing is served until reboot
+    Latched,
+}
+
+#[derive(Debug)]
+pub struct HealthPolicy {
+    gate: Gate,
+    failures: u32,
+}
+
+impl Default for HealthPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HealthPolicy {
+    pub fn new() -> Self {
+        HealthPolicy { gate: Gate::Starting, failures: 0 }
+    }
+
+    pub fn gate(&self) -> Gate {
+        self.gate
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.gate == Gate::Open
+    }
+
+    /// A health test failed: stop serving
+    pub fn failure(&mut self) {
+        self.failures = self.failures.saturating_add(1);
+        self.gate = if self.failures >= LATCH_FAILURES { Gate::Latched } else { Gate::Closed { passes: 0 } };
+    }
+
+    /// A periodic check, which found the health tests passing or not (see `tests_passed()`).
+    /// Returns `true` if that reopens the gate. A check that doesn't pass starts recovery over.
+    pub fn check(&mut self, passed: bool) -> bool {
+        match self.gate {
+            Gate::Starting if passed => {
+                self.gate = Gate::Open;
+                return true;
+            }
+            Gate::Closed { .. } if !passed => self.gate = Gate::Closed { passes: 0 },
+            Gate::Closed { passes } if passes + 1 >= RECOVERY_CHECKS => {
+                self.gate = Gate::Open;
+                return true;
+            }
+            Gate::Closed { passes } => self.gate = Gate::Closed { passes: passes + 1 },
+            _ => (),
+        }
+        false
+    }
+}
+
+/// Whether the health tests have passed since the last check: they've run on fresh samples, and
+/// the hardware hasn't reported an error since `last_errors` was read. Time going by isn't enough,
+/// since a source that's stopped doesn't fail anything either.
+pub fn tests_passed(last_errors: &TrngErrors, errors: &TrngErrors, tests: &HealthTests) -> bool {
+    let fresh = tests.av_nist.iter().any(|t| t.fresh)
+        || tests.ro_nist.iter().any(|t| t.fresh)
+        || tests.ro_miniruns.iter().any(|t| t.fresh);
+    fresh && errors == last_errors
+}
+
+/// A ChaCha20 DRBG. Noise for the seed, and for each reseed, is collected first, and only
+/// used once a health check after it was drawn has passed; if a test fails in between, it's
+/// thrown away.
+pub struct Drbg {
+    /// `None` until the first noise is committed
+    rng: Option<ChaCha20Rng>,
+    since_reseed: u64,
+    pending: Option<[u8; 32]>,
+    reseeds: u32,
+}
+
+fn words_to_seed(words: &[u32]) -> [u8; 32] {
+    let mut seed = [0u8; 32];
+    for (chunk, w) in seed.chunks_exact_mut(4).zip(words.iter()) {
+        chunk.copy_from_slice(&w.to_le_bytes());
+    }
+    seed
+}
+
+impl Default for Drbg {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drbg {
+    /// A DRBG with no seed yet; it can't be used until noise has been collected and committed
+    pub fn new() -> Self {
+        Drbg { rng: None, since_reseed: 0, pending: None, reseeds: 0 }
+    }
+
+    pub fn is_seeded(&self) -> bool {
+        self.rng.is_some()
+    }
+
+    /// Whether noise should be collected, for the seed or for a reseed
+    pub fn needs_reseed(&self) -> bool {
+        (self.rng.is_none() || self.since_reseed >= RESEED_WORDS) && self.pending.is_none()
+    }
+
+    /// Holds `noise` until it's known to have come from a healthy source
+    pub fn collect(&mut self, noise: &[u32]) {
+        self.pending = Some(words_to_seed(noise));
+    }
+
+    pub fn discard_pending(&mut self) {
+        self.pending = None;
+    }
+
+    /// Seeds or reseeds with the noise collected, if any. A reseed's key is the DRBG's own next
+    /// output XORed with the noise, so a weak reseed can't make it any worse than it was.
+    pub fn commit_pending(&mut self) -> bool {
+        if let Some(noise) = self.pending.take() {
+            let key = match self.rng.as_mut() {
+                Some(rng) => {
+                    let mut key = [0u8; 32];
+                    rng.fill_bytes(&mut key);
+                    for (k, n) in key.iter_mut().zip(noise.iter()) {
+                        *k ^= n;
+                    }
+                    self.reseeds = self.reseeds.wrapping_add(1);
+                    key
+                }
+                None => noise,
+            };
+            self.rng = Some(ChaCha20Rng::from_seed(key));
+            self.since_reseed = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn reseeds(&self) -> u32 {
+        self.reseeds
+    }
+
+    pub fn fill(&mut self, out: &mut [u32]) {
+        let rng = self.rng.as_mut().expect("DRBG used before it was seeded");
+        for w in out.iter_mut() {
+            *w = rng.next_u32();
+        }
+        self.since_reseed += out.len() as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gate_opens_on_the_first_passing_check() {
+        let mut p = HealthPolicy::new();
+        assert_eq!(p.gate(), Gate::Starting);
+        assert!(!p.check(false));
+        assert!(!p.is_open());
+        assert!(p.check(true));
+        assert!(p.is_open());
+        // a failure before the first pass needs a full recovery
+        let mut p = HealthPolicy::new();
+        p.failure();
+        assert!(!p.check(true));
+        assert_eq!(p.gate(), Gate::Closed { passes: 1 });
+    }
+
+    #[test]
+    fn gate_closes_recovers_and_latches() {
+        let mut p = HealthPolicy::new();
+        assert!(p.check(true));
+        assert!(p.is_open());
+        assert!(!p.check(true));
+        p.failure();
+        assert_eq!(p.gate(), Gate::Closed { passes: 0 });
+        for _ in 0..RECOVERY_CHECKS - 1 {
+            assert!(!p.check(true));
+            assert!(!p.is_open());
+        }
+        // a failure part way through recovery starts it over
+        p.failure();
+        assert_eq!(p.gate(), Gate::Closed { passes: 0 });
+        for _ in 0..RECOVERY_CHECKS - 1 {
+            p.check(true);
+        }
+        // and so does a check that doesn't find the tests passing
+        assert!(!p.check(false));
+        assert_eq!(p.gate(), Gate::Closed { passes: 0 });
+        // however long it goes on for
+        for _ in 0..RECOVERY_CHECKS * 4 {
+            assert!(!p.check(false));
+        }
+        for _ in 0..RECOVERY_CHECKS - 1 {
+            p.check(true);
+        }
+        assert!(p.check(true));
+        assert!(p.is_open());
+        for _ in p.failures()..LATCH_FAILURES {
+            p.failure();
+        }
+        assert_eq!(p.gate(), Gate::Latched);
+        for _ in 0..RECOVERY_CHECKS * 2 {
+            assert!(!p.check(true));
+        }
+        assert!(!p.is_open());
+    }
+
+    #[test]
+    fn passing_means_fresh_results_and_no_new_errors() {
+        let mut tests = HealthTests::default();
+        let errors = TrngErrors { av_repcount_errs: Some(1), nist_errs: 1, ..Default::default() };
+        // the source hasn't produced anything for the tests to look at
+        assert!(!tests_passed(&errors, &errors, &tests));
+        tests.ro_nist[2].fresh = true;
+        assert!(tests_passed(&errors, &errors, &tests));
+        tests = HealthTests::default();
+        tests.av_nist[0].fresh = true;
+        assert!(tests_passed(&errors, &errors, &tests));
+        // an error came in since the last check, even if its interrupt hasn't been handled yet
+        let mut newer = errors;
+        newer.nist_errs = 2;
+        assert!(!tests_passed(&errors, &newer, &tests));
+        newer = errors;
+        newer.excursion_errs[1] = Some(crate::api::ExcursionTest { min: 0x10, max: 0x11 });
+        assert!(!tests_passed(&errors, &newer, &tests));
+    }
+
+    #[test]
+    fn drbg_is_seeded_only_with_committed_noise() {
+        let mut d = Drbg::new();
+        assert!(!d.is_seeded());
+        assert!(d.needs_reseed());
+        d.collect(&[1; SEED_WORDS]);
+        assert!(!d.needs_reseed());
+        d.discard_pending();
+        assert!(!d.commit_pending());
+        assert!(!d.is_seeded());
+        d.collect(&[1; SEED_WORDS]);
+        assert!(d.commit_pending());
+        assert!(d.is_seeded());
+        assert!(!d.needs_reseed());
+        // seeding isn't a reseed
+        assert_eq!(d.reseeds(), 0);
+    }
+
+    fn seeded(noise: &[u32]) -> Drbg {
+        let mut d = Drbg::new();
+        d.collect(noise);
+        d.commit_pending();
+        d
+    }
+
+    #[test]
+    fn drbg_reseeds_only_with_committed_noise() {
+        let noise = [1u32, 2, 3, 4, 5, 6, 7, 8];
+        let mut a = seeded(&noise);
+        let mut b = seeded(&noise);
+        let (mut x, mut y) = ([0u32; 16], [0u32; 16]);
+        a.fill(&mut x);
+        b.fill(&mut y);
+        assert_eq!(x, y);
+        assert_ne!(x[0], x[1]);
+
+        // collected and thrown away: no change
+        a.collect(&[9; SEED_WORDS]);
+        a.discard_pending();
+        assert!(!a.commit_pending());
+        a.fill(&mut x);
+        b.fill(&mut y);
+        assert_eq!(x, y);
+
+        // committed: the streams part ways
+        a.collect(&[9; SEED_WORDS]);
+        assert!(a.commit_pending());
+        assert_eq!(a.reseeds(), 1);
+        a.fill(&mut x);
+        b.fill(&mut y);
+        assert_ne!(x, y);
+
+        let mut big = vec![0u32; RESEED_WORDS as usize];
+        assert!(!b.needs_reseed());
+        b.fill(&mut big);
+        assert!(b.needs_reseed());
+        b.collect(&noise);
+        assert!(!b.needs_reseed());
+    }
+}
@@ -0,0 +1,63 @@
+//! Picks the seed for the hosted-mode noise source, so that a hosted run can be replayed.
+//!
+//! `XOUS_TRNG_SEED` takes a number (decimal, or hex with a `0x` prefix), or `os` to draw the
+//! seed from the host's entropy. Without it, the `XOUS_SEED` shared by the other hosted test
+//! generators is used, so runs stay deterministic by default.
+
+/// The environment variable the seed is read from; `cargo xtask run --trng-seed=` sets it
+pub const TRNG_SEED_VAR: &str = "XOUS_TRNG_SEED";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SeedSource {
+    /// given explicitly in `XOUS_TRNG_SEED`
+    Fixed(u64),
+    /// drawn from the host's entropy
+    Os,
+    /// taken from `XOUS_SEED`
+    Shared,
+}
+
+pub fn parse(setting: &str) -> Result<SeedSource, String> {
+    let setting = setting.trim();
+    if setting.eq_ignore_ascii_case("os") {
+        return Ok(SeedSource::Os);
+    }
+    let parsed = match setting.strip_prefix("0x").or(setting.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16),
+        None => setting.replace('_', "").parse::<u64>(),
+    };
+    parsed.map(SeedSource::Fixed).map_err(|_| format!("{}={} is neither a number nor `os`", TRNG_SEED_VAR, setting))
+}
+
+/// Settles on a seed. `shared` is the `XOUS_SEED` value; `os_entropy` is only called in `os` mode.
+pub fn choose(setting: Option<&str>, shared: u64, os_entropy: impl FnOnce() -> u64) -> Result<(u64, SeedSource), String> {
+    let source = match setting {
+        Some(s) => parse(s)?,
+        None => SeedSource::Shared,
+    };
+    let seed = match source {
+        SeedSource::Fixed(seed) => seed,
+        SeedSource::Os => os_entropy(),
+        SeedSource::Shared => shared,
+    };
+    Ok((seed, source))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seed_settings() {
+        assert_eq!(parse("1234"), Ok(SeedSource::Fixed(1234)));
+        assert_eq!(parse(" 0xdead_beef "), Ok(SeedSource::Fixed(0xdead_beef)));
+        assert_eq!(parse("OS"), Ok(SeedSource::Os));
+        assert!(parse("banana").is_err());
+        assert!(parse("").is_err());
+
+        assert_eq!(choose(None, 7, || unreachable!()), Ok((7, SeedSource::Shared)));
+        assert_eq!(choose(Some("42"), 7, || unreachable!()), Ok((42, SeedSource::Fixed(42))));
+        assert_eq!(choose(Some("os"), 7, || 99), Ok((99, SeedSource::Os)));
+        assert!(choose(Some("4x2"), 7, || 99).is_err());
+    }
+}
@@ -1,15 +1,40 @@
 pub(crate) const SERVER_NAME_TTS: &str     = "_Text to speech front end_";
 
+/// Where the voice settings are kept. The PDDB depends on TTS, so TTS can't read them itself:
+/// the status bar restores them once the PDDB is mounted, and whatever changes them saves them.
+pub const VOICE_SETTINGS_DICT: &str = "tts.settings";
+pub const VOICE_SETTINGS_KEY: &str = "voice";
+
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
 pub(crate) enum Opcode {
     /// Basic, interruptable conversion of a string to audible speech
     TextToSpeech,
     /// Non-interruptable conversion of a string to audible speech. Blocks until the phrase is finished.
     TextToSpeechBlocking,
-    /// Stops audio playback immediately. Does not stop wave generation.
+    /// Stops audio playback immediately, and drops everything queued.
     CodecStop,
     /// Set words per minute
     SetWordsPerMinute,
+    /// Queues an utterance; returns its ID
+    Speak,
+    /// Queues an utterance, blocking until it's finished or dropped
+    SpeakBlocking,
+    /// Drops one utterance by ID, stopping it if it's being spoken
+    Cancel,
+    /// Drops every utterance at or below a priority, stopping the current one if need be
+    Flush,
+    /// Registers for utterance events: started, finished, or dropped
+    UtteranceSubscribe,
+    /// Stops sending utterance events to the caller
+    UtteranceUnsubscribe,
+    /// Gets the voice settings
+    GetVoice,
+    /// Sets the voice settings
+    SetVoice,
+    /// Internal: the backend has synthesized everything it was given
+    SynthDone,
+    /// Internal: the last of an utterance has played out
+    PlaybackDone,
     /// Exits the server
     Quit,
 }
@@ -17,4 +42,109 @@ pub(crate) enum Opcode {
 #[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct TtsFrontendMsg {
     pub text: xous_ipc::String::<2048>,
-}
\ No newline at end of file
+}
+
+/// Who goes first when several utterances are queued
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd,
+    rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum Priority {
+    /// background chatter, e.g. progress reports
+    Low,
+    /// anything a user asked to hear
+    Normal,
+    /// prompts that need an answer
+    High,
+    /// warnings; these cut off anything less urgent
+    Urgent,
+}
+
+/// What to do with what's already queued
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug, Copy, Clone, Eq, PartialEq,
+    rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum QueueMode {
+    /// wait for everything of the same or higher priority to finish
+    Enqueue,
+    /// stop the current utterance, unless it's more important, and go next
+    Interrupt,
+    /// as `Interrupt`, also dropping everything queued at or below this priority
+    Flush,
+}
+
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct UtteranceRequest {
+    pub text: xous_ipc::String::<2048>,
+    pub priority: Priority,
+    pub mode: QueueMode,
+    /// the text is SSML-lite markup; see `markup.rs` for what's understood
+    pub markup: bool,
+    /// filled in by the server
+    pub id: u32,
+}
+
+/// Delivered as a scalar to subscribers: arg1 is the utterance ID, arg2 the event
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum UtteranceEvent {
+    Started,
+    Finished,
+    /// cancelled, flushed, or cut short by another utterance
+    Dropped,
+}
+
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+pub(crate) enum EventCallback {
+    Event,
+    Drop,
+}
+
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct ScalarHook {
+    pub sid: (u32, u32, u32, u32),
+    pub id: u32,  // ID of the scalar message to send through (e.g. the discriminant of the Enum on the caller's side API)
+    pub cid: u32,  // caller-side connection ID for the scalar message to route to. Created by the caller before hooking.
+}
+
+/// The voice, as the user has it set up. Markup adjusts these per utterance.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct VoiceParams {
+    pub wpm: u32,
+    /// percent of the voice's natural pitch, 50-200
+    pub pitch: u32,
+    /// percent, 0-200
+    pub volume: u32,
+}
+impl Default for VoiceParams {
+    fn default() -> Self {
+        VoiceParams { wpm: 350, pitch: 100, volume: 100 }
+    }
+}
+impl VoiceParams {
+    pub fn clamped(self) -> VoiceParams {
+        VoiceParams {
+            wpm: self.wpm.max(80).min(1000),
+            pitch: self.pitch.max(50).min(200),
+            volume: self.volume.min(200),
+        }
+    }
+    /// The settings as stored in the PDDB: one `key=value` per line
+    pub fn to_text(&self) -> String {
+        format!("wpm={}\npitch={}\nvolume={}\n", self.wpm, self.pitch, self.volume)
+    }
+    /// Reads back `to_text()`. Keys it doesn't know, or can't read, keep their defaults.
+    pub fn from_text(text: &str) -> VoiceParams {
+        let mut v = VoiceParams::default();
+        for line in text.lines() {
+            let mut kv = line.splitn(2, '=');
+            let (key, value) = match (kv.next(), kv.next().and_then(|v| v.trim().parse::<u32>().ok())) {
+                (Some(k), Some(v)) => (k.trim(), v),
+                _ => continue,
+            };
+            match key {
+                "wpm" => v.wpm = value,
+                "pitch" => v.pitch = value,
+                "volume" => v.volume = value,
+                _ => {}
+            }
+        }
+        v.clamped()
+    }
+}
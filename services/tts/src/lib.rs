@@ -4,18 +4,20 @@ pub mod api;
 pub use api::*;
 use xous::{CID, send_message, Message};
 use xous_ipc::Buffer;
-use num_traits::ToPrimitive;
+use num_traits::{ToPrimitive, FromPrimitive};
 
 #[derive(Debug)]
 pub struct TtsFrontend {
     conn: CID,
+    event_sid: Option<xous::SID>,
 }
 impl TtsFrontend {
     pub fn new(xns: &xous_names::XousNames) -> Result<Self, xous::Error> {
         REFCOUNT.fetch_add(1, Ordering::Relaxed);
         let conn = xns.request_connection_blocking(api::SERVER_NAME_TTS).expect("Can't connect to TtsFrontend server");
         Ok(TtsFrontend {
-            conn
+            conn,
+            event_sid: None,
         })
     }
     /// A fully synchronous text to speech call. The text is turned into speech and played immediately.
@@ -40,12 +42,120 @@ impl TtsFrontend {
             Message::new_scalar(Opcode::SetWordsPerMinute.to_usize().unwrap(), wpm as usize, 0, 0, 0)
         ).map(|_| ())
     }
+    /// Queues `text` to be spoken, returning an ID to follow it by. See `Priority` and `QueueMode`
+    /// for what happens to anything already queued.
+    pub fn speak(&self, text: &str, priority: Priority, mode: QueueMode) -> Result<u32, xous::Error> {
+        self.speak_inner(text, priority, mode, false)
+    }
+    /// As `speak()`, with `text` in SSML-lite markup: `<break time="300ms"/>`, `<emphasis>`,
+    /// `<prosody rate="slow" pitch="+10%" volume="loud">`, and `<say-as interpret-as="characters">`
+    /// or `<spell>` to spell something out.
+    pub fn speak_markup(&self, text: &str, priority: Priority, mode: QueueMode) -> Result<u32, xous::Error> {
+        self.speak_inner(text, priority, mode, true)
+    }
+    fn speak_inner(&self, text: &str, priority: Priority, mode: QueueMode, markup: bool) -> Result<u32, xous::Error> {
+        let req = UtteranceRequest {
+            text: xous_ipc::String::from_str(text),
+            priority,
+            mode,
+            markup,
+            id: 0,
+        };
+        let mut buf = Buffer::into_buf(req).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::Speak.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        Ok(buf.to_original::<UtteranceRequest, _>().unwrap().id)
+    }
+    /// Queues `text` and waits until it has been spoken, or dropped to make way for something else
+    pub fn speak_blocking(&self, text: &str, priority: Priority, mode: QueueMode, markup: bool) -> Result<(), xous::Error> {
+        let req = UtteranceRequest {
+            text: xous_ipc::String::from_str(text),
+            priority,
+            mode,
+            markup,
+            id: 0,
+        };
+        let buf = Buffer::into_buf(req).or(Err(xous::Error::InternalError))?;
+        buf.lend(self.conn, Opcode::SpeakBlocking.to_u32().unwrap()).map(|_| ())
+    }
+    /// Drops an utterance queued by this process, stopping it if it's being spoken. Utterances
+    /// queued by other processes are left alone.
+    pub fn cancel(&self, id: u32) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::Cancel.to_usize().unwrap(), id as usize, 0, 0, 0)
+        ).map(|_| ())
+    }
+    /// Drops everything queued at or below `priority`, stopping the current utterance if need be
+    pub fn flush(&self, priority: Priority) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::Flush.to_usize().unwrap(), priority.to_usize().unwrap(), 0, 0, 0)
+        ).map(|_| ())
+    }
+    pub fn voice(&self) -> Result<VoiceParams, xous::Error> {
+        let mut buf = Buffer::into_buf(VoiceParams::default()).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::GetVoice.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        Ok(buf.to_original::<VoiceParams, _>().unwrap())
+    }
+    /// Changes the voice for everything spoken from here on. To keep the change across reboots,
+    /// save `voice.to_text()` under `VOICE_SETTINGS_DICT`/`VOICE_SETTINGS_KEY` as well.
+    pub fn set_voice(&self, voice: VoiceParams) -> Result<(), xous::Error> {
+        let buf = Buffer::into_buf(voice).or(Err(xous::Error::InternalError))?;
+        buf.lend(self.conn, Opcode::SetVoice.to_u32().unwrap()).map(|_| ())
+    }
+    /// Asks for a scalar message with `id` to be sent to `cid` as utterances queued by this
+    /// process start, finish, or get dropped. arg1 is the utterance ID, and arg2 the
+    /// `UtteranceEvent`. A `Tts` can only be hooked once: hooking it again returns `MemoryInUse`.
+    /// The server keeps one subscription per process, so hooking through another `Tts` in the
+    /// same process takes over from the first one.
+    pub fn hook_utterance_callback(&mut self, id: u32, cid: CID) -> Result<(), xous::Error> {
+        if self.event_sid.is_none() {
+            let sid = xous::create_server().unwrap();
+            self.event_sid = Some(sid);
+            let sid_tuple = sid.to_u32();
+            xous::create_thread_4(event_cb_server, sid_tuple.0 as usize, sid_tuple.1 as usize, sid_tuple.2 as usize, sid_tuple.3 as usize).unwrap();
+            let hookdata = ScalarHook {
+                sid: sid_tuple,
+                id,
+                cid,
+            };
+            let buf = Buffer::into_buf(hookdata).or(Err(xous::Error::InternalError))?;
+            buf.lend(self.conn, Opcode::UtteranceSubscribe.to_u32().unwrap()).map(|_|())
+        } else {
+            Err(xous::Error::MemoryInUse) // can't hook it twice
+        }
+    }
+}
+
+/// handles utterance events, in the library user's process space.
+fn event_cb_server(sid0: usize, sid1: usize, sid2: usize, sid3: usize) {
+    let sid = xous::SID::from_u32(sid0 as u32, sid1 as u32, sid2 as u32, sid3 as u32);
+    loop {
+        let msg = xous::receive_message(sid).unwrap();
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(EventCallback::Event) => xous::msg_scalar_unpack!(msg, cid, id, utterance, event, {
+                // directly pass the scalar message onto the CID with the ID memorized in the original hook
+                send_message(cid as u32,
+                    Message::new_scalar(id, utterance, event, 0, 0)
+                ).unwrap();
+            }),
+            Some(EventCallback::Drop) => {
+                break; // this exits the loop and kills the thread
+            }
+            None => (),
+        }
+    }
+    xous::destroy_server(sid).unwrap();
 }
 
 use core::sync::atomic::{AtomicU32, Ordering};
 static REFCOUNT: AtomicU32 = AtomicU32::new(0);
 impl Drop for TtsFrontend {
     fn drop(&mut self) {
+        // the server tears down the event callback server, if there is one
+        if self.event_sid.take().is_some() {
+            send_message(self.conn,
+                Message::new_blocking_scalar(Opcode::UtteranceUnsubscribe.to_usize().unwrap(), 0, 0, 0, 0)
+            ).ok();
+        }
         // the connection to the server side must be reference counted, so that multiple instances of this object within
         // a single process do not end up de-allocating the CID on other threads before they go out of scope.
         // Note to future me: you want this. Don't get rid of it because you think, "nah, nobody will ever make more than one copy of this object".
@@ -3,20 +3,28 @@
 
 mod api;
 use api::*;
+mod markup;
+use markup::Segment;
+mod queue;
+use queue::{Entry, UtteranceQueue};
 
 use xous_ipc::Buffer;
-use xous::{msg_scalar_unpack, Message, send_message};
+use xous::{msg_scalar_unpack, msg_blocking_scalar_unpack, Message, MessageEnvelope, send_message, CID};
 use num_traits::*;
-use codec::{ZERO_PCM, VolumeOps, FrameRing};
+use codec::VolumeOps;
+use codec::resample::Resampler;
+use mixer::{Channels, SampleFormat, StreamFormat, MixerStream, FIFO_DEPTH};
 use xous_tts_backend::*;
 use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
 use std::collections::VecDeque;
 
-const DEFAULT_WPM: u32 = 350;
+const SAMPLE_RATE: u32 = 8000;
 const WAIT_INTERVAL: usize = 50; // milliseconds to wait before polling if a phrase is finished.
 const MAX_BUF_DEPTH: usize = (8000 * core::mem::size_of::<u16>()) * 3; // 8000 samples/s * num seconds to buffer
 const DRAIN_INTERVAL: usize = 100; // milliseconds to wait before checking if buffer has drained
+const SAMPLES_PER_FRAME: usize = FIFO_DEPTH * 2; // 8k mono packs two samples per word
+/// processes that can subscribe to utterance events at once
+const MAX_SUBSCRIBERS: usize = 8;
 
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
 pub(crate) enum WaveOp {
@@ -28,6 +36,250 @@ pub(crate) enum CallbackOp {
     Callback,
     Quit,
 }
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+pub(crate) enum DrainOp {
+    /// reports `PlaybackDone` once everything queued for a generation has played
+    Watch,
+    Quit,
+}
+
+/// Samples on their way from the synthesizer to the mixer
+struct Synth {
+    wavbuf: VecDeque<u16>,
+    /// the backend is working through some text
+    active: bool,
+    /// what the backend is working on was dropped: throw its samples away until it stops
+    discard: bool,
+    /// nothing more is coming for the current utterance
+    done: bool,
+    just_initiated: bool,
+    /// bumped every time an utterance starts or stops, so late reports can be told apart
+    generation: u32,
+    /// set per segment: a pitch shift, and a gain in percent
+    resampler: Option<Resampler>,
+    gain: u32,
+}
+impl Synth {
+    fn push_samples(&mut self, data: &[u16]) {
+        let samples: Vec<i16> = data.iter().map(|&d| d as i16).collect();
+        let mut shifted = Vec::new();
+        let samples = match self.resampler.as_mut() {
+            Some(r) => {
+                r.process(&samples, &mut shifted);
+                &shifted
+            }
+            None => &samples,
+        };
+        for &s in samples.iter() {
+            let s = (s as i32 * self.gain as i32 / 100).max(i16::MIN as i32).min(i16::MAX as i32);
+            self.wavbuf.push_back(s as i16 as u16);
+        }
+    }
+}
+
+struct Utterance {
+    segments: VecDeque<Segment>,
+    /// whoever queued it, for routing events
+    pid: Option<xous::PID>,
+    /// a blocking request, answered when this is dropped
+    blocking: Option<MessageEnvelope>,
+    started: bool,
+    /// waiting on the backend to finish a segment
+    synthesizing: bool,
+    /// everything is synthesized, waiting on it to play out
+    draining: bool,
+}
+
+struct Subscriber {
+    pid: xous::PID,
+    server_to_cb_cid: CID,
+    cb_to_client_cid: CID,
+    cb_to_client_id: u32,
+}
+
+/// Works through the utterance queue, a segment at a time
+struct Speaker {
+    queue: UtteranceQueue<Utterance>,
+    synth: Arc<Mutex<Synth>>,
+    stream: Arc<Mutex<MixerStream>>,
+    tts_be: TtsBackend,
+    wav_sid: xous::SID,
+    drain_cid: CID,
+    voice: VoiceParams,
+    /// the rate the backend is configured for
+    wpm: u32,
+    subscribers: [Option<Subscriber>; MAX_SUBSCRIBERS],
+}
+
+impl Speaker {
+    fn enqueue(&mut self, text: &str, markup: bool, priority: Priority, mode: QueueMode, pid: Option<xous::PID>, blocking: Option<MessageEnvelope>) -> u32 {
+        let segments = if markup { markup::parse(text) } else { markup::plain(text) };
+        let utterance = Utterance {
+            segments: segments.into_iter().collect(),
+            pid,
+            blocking,
+            started: false,
+            synthesizing: false,
+            draining: false,
+        };
+        let (id, dropped) = self.queue.push(priority, mode, utterance);
+        log::debug!("queued utterance {} ({:?}, {:?}), {} waiting", id, priority, mode, self.queue.len());
+        self.drop_entries(dropped);
+        self.pump();
+        id
+    }
+    /// Drops utterance `id`, if it was queued by `pid`: ids are handed out in sequence, so they
+    /// are easily guessed
+    fn cancel(&mut self, id: u32, pid: Option<xous::PID>) {
+        if let Some(entry) = self.queue.cancel(id, |u| pid.is_some() && u.pid == pid) {
+            self.drop_entries(vec![entry]);
+            self.pump();
+        }
+    }
+    fn flush(&mut self, priority: Priority) {
+        let dropped = self.queue.flush(priority);
+        self.drop_entries(dropped);
+        self.pump();
+    }
+    /// Tells whoever queued them that `dropped` won't be spoken, stopping the one that is
+    fn drop_entries(&mut self, dropped: Vec<Entry<Utterance>>) {
+        for entry in dropped {
+            if entry.payload.started {
+                self.stop_playback();
+            }
+            log::debug!("utterance {} dropped{}", entry.id, if entry.payload.blocking.is_some() { ", returning its caller" } else { "" });
+            self.notify(entry.id, entry.payload.pid, UtteranceEvent::Dropped);
+            // dropping the entry returns a blocking caller
+        }
+    }
+    fn stop_playback(&mut self) {
+        {
+            let mut synth = self.synth.lock().unwrap();
+            synth.generation = synth.generation.wrapping_add(1);
+            if synth.active {
+                synth.discard = true;
+            }
+            synth.wavbuf.clear();
+            synth.done = true;
+            synth.just_initiated = false;
+        }
+        let stream = self.stream.lock().unwrap();
+        stream.flush().unwrap();
+        stream.pause().unwrap();
+    }
+    /// Starts whatever is next: the next segment of the current utterance, or the next utterance
+    fn pump(&mut self) {
+        loop {
+            if self.queue.current().is_none() {
+                let (id, pid) = match self.queue.start_next() {
+                    Some(entry) => {
+                        entry.payload.started = true;
+                        (entry.id, entry.payload.pid)
+                    }
+                    None => return,
+                };
+                {
+                    let mut synth = self.synth.lock().unwrap();
+                    synth.generation = synth.generation.wrapping_add(1);
+                    synth.wavbuf.clear();
+                    synth.done = false;
+                    synth.just_initiated = true;
+                }
+                self.notify(id, pid, UtteranceEvent::Started);
+                log::trace!("resuming stream");
+                self.stream.lock().unwrap().resume().unwrap();
+            }
+            let entry = self.queue.current_mut().unwrap();
+            if entry.payload.synthesizing || entry.payload.draining {
+                return;
+            }
+            match entry.payload.segments.pop_front() {
+                Some(Segment::Speak { text, prosody }) => {
+                    let pitch = (self.voice.pitch * prosody.pitch / 100).max(50).min(200);
+                    // a higher pitch plays the samples back faster, so slow the speech down to match
+                    let wpm = (self.voice.wpm * prosody.rate / pitch).max(40);
+                    {
+                        let mut synth = self.synth.lock().unwrap();
+                        synth.resampler = if pitch == 100 {
+                            None
+                        } else {
+                            Some(Resampler::new(SAMPLE_RATE * pitch / 100, SAMPLE_RATE, 1))
+                        };
+                        synth.gain = self.voice.volume * prosody.volume / 100;
+                        synth.active = true;
+                    }
+                    if wpm != self.wpm {
+                        self.wpm = wpm;
+                        self.tts_be.tts_config(self.wav_sid.to_array(), WaveOp::Return.to_u32().unwrap(), None, Some(wpm)).unwrap();
+                    }
+                    entry.payload.synthesizing = true;
+                    log::debug!("speaking {} at {}wpm, pitch {}%", text, wpm, pitch);
+                    self.tts_be.tts_simple(&text).unwrap();
+                    return;
+                }
+                Some(Segment::Pause(ms)) => {
+                    let samples = (SAMPLE_RATE * ms / 1000) as usize;
+                    self.synth.lock().unwrap().wavbuf.extend(std::iter::repeat(0).take(samples));
+                }
+                None => {
+                    entry.payload.draining = true;
+                    let generation = {
+                        let mut synth = self.synth.lock().unwrap();
+                        synth.done = true;
+                        synth.generation
+                    };
+                    send_message(self.drain_cid,
+                        Message::new_scalar(DrainOp::Watch.to_usize().unwrap(), generation as usize, 0, 0, 0)
+                    ).unwrap();
+                    return;
+                }
+            }
+        }
+    }
+    fn synth_done(&mut self, generation: u32) {
+        if generation != self.synth.lock().unwrap().generation {
+            return;
+        }
+        if let Some(entry) = self.queue.current_mut() {
+            entry.payload.synthesizing = false;
+        }
+        self.pump();
+    }
+    fn playback_done(&mut self, generation: u32) {
+        if generation != self.synth.lock().unwrap().generation {
+            return;
+        }
+        if let Some(entry) = self.queue.finish_current() {
+            log::debug!("utterance {} finished{}", entry.id, if entry.payload.blocking.is_some() { ", returning its caller" } else { "" });
+            self.notify(entry.id, entry.payload.pid, UtteranceEvent::Finished);
+        }
+        if self.queue.len() == 0 {
+            self.stream.lock().unwrap().pause().unwrap();
+        }
+        self.pump();
+    }
+    fn notify(&self, id: u32, pid: Option<xous::PID>, event: UtteranceEvent) {
+        let sub = match self.subscribers.iter().flatten().find(|s| Some(s.pid) == pid) {
+            Some(sub) => sub,
+            None => return,
+        };
+        xous::send_message(sub.server_to_cb_cid,
+            xous::Message::new_scalar(EventCallback::Event.to_usize().unwrap(),
+                sub.cb_to_client_cid as usize, sub.cb_to_client_id as usize, id as usize, event.to_usize().unwrap())
+        ).unwrap();
+    }
+    fn unsubscribe(&mut self, pid: xous::PID) {
+        for slot in self.subscribers.iter_mut() {
+            if slot.as_ref().map(|s| s.pid) == Some(pid) {
+                let sub = slot.take().unwrap();
+                xous::send_message(sub.server_to_cb_cid,
+                    xous::Message::new_blocking_scalar(EventCallback::Drop.to_usize().unwrap(), 0, 0, 0, 0)
+                ).unwrap();
+                unsafe{xous::disconnect(sub.server_to_cb_cid).unwrap();}
+            }
+        }
+    }
+}
 
 #[xous::xous_main]
 fn xmain() -> ! {
@@ -37,22 +289,26 @@ fn xmain() -> ! {
 
     let xns = xous_names::XousNames::new().unwrap();
     let tts_sid = xns.register_name(api::SERVER_NAME_TTS, None).expect("can't register server");
-    // let tts_cid = xous::connect(tts_sid).unwrap();
+    let tts_cid = xous::connect(tts_sid).unwrap();
     log::trace!("registered with NS -- {:?}", tts_sid);
 
     let tts_be = TtsBackend::new(&xns).unwrap();
 
-    let tt = ticktimer_server::Ticktimer::new().unwrap();
-
+    let synth = Arc::new(Mutex::new(Synth {
+        wavbuf: VecDeque::new(),
+        active: false,
+        discard: false,
+        done: true,
+        just_initiated: false,
+        generation: 0,
+        resampler: None,
+        gain: 100,
+    }));
     let wav_sid = xous::create_server().unwrap();
     let wav_cid = xous::connect(wav_sid).unwrap();
-    let wavbuf = Arc::new(Mutex::new(VecDeque::<u16>::new()));
-    let synth_done = Arc::new(AtomicBool::new(false));
     std::thread::spawn({
         let wav_sid = wav_sid.clone();
-        let wavbuf = wavbuf.clone();
-        // let tts_cid = tts_cid.clone();
-        let synth_done = synth_done.clone();
+        let synth = synth.clone();
         move || {
             let tt = ticktimer_server::Ticktimer::new().unwrap();
             loop {
@@ -61,37 +317,34 @@ fn xmain() -> ! {
                     Some(WaveOp::Return) => {
                         // check to see if we need to apply backpressure on the synthesizer. If so, this is where we pause
                         let mut capacity = { // put this in a block of its own to ensure the lock goes out of scope after we have measured the length
-                            wavbuf.lock().unwrap().len()
+                            synth.lock().unwrap().wavbuf.len()
                         };
                         while capacity > MAX_BUF_DEPTH {
                             // this effectively stalls the tts engine because the buffer sent to us is a `lend`, which is blocking.
                             // by blocking this thread from copying the memory, we also block the synthesizer from generating more samples.
                             log::info!("synth backpressure");
                             tt.sleep_ms(DRAIN_INTERVAL).unwrap();
-                            capacity = wavbuf.lock().unwrap().len();
+                            capacity = synth.lock().unwrap().wavbuf.len();
                         }
                         let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
                         let wavdat = buffer.to_original::<TtsBackendData, _>().unwrap();
-                        let mut buf = wavbuf.lock().unwrap();
-                        for &d in wavdat.data[..wavdat.len as usize].iter() {
-                            buf.push_back(d);
-                        }
-                        match wavdat.control {
-                            Some(TtsBeControl::End) => {
-                                // the buffer can still be quite full at this point, we have to wait until it drains naturally
-                                synth_done.store(true, Ordering::SeqCst);
-                            }
-                            Some(TtsBeControl::Abort) => {
-                                // clear the playback buffer and indicate we're done, because we want to stop the playback too.
-                                log::info!("abort received");
-                                buf.clear();
-                                synth_done.store(true, Ordering::SeqCst);
-                            }
-                            None => {
-                                // more data can arrive after done is set true if a new synthesis was
-                                // kicked off that aborts the current run. reflect that in the synth_done state.
-                                synth_done.store(false, Ordering::SeqCst);
+                        let mut synth = synth.lock().unwrap();
+                        if synth.discard {
+                            // the run was dropped; its End or Abort means the next samples are wanted again
+                            if wavdat.control.is_some() {
+                                log::debug!("discarded run ended");
+                                synth.discard = false;
+                                synth.active = false;
                             }
+                            continue;
+                        }
+                        synth.push_samples(&wavdat.data[..wavdat.len as usize]);
+                        if wavdat.control.is_some() {
+                            // the buffer can still be quite full at this point; it drains on its own
+                            synth.active = false;
+                            send_message(tts_cid,
+                                Message::new_scalar(Opcode::SynthDone.to_usize().unwrap(), synth.generation as usize, 0, 0, 0)
+                            ).unwrap();
                         }
                     },
                     Some(WaveOp::Quit) => {
@@ -105,51 +358,53 @@ fn xmain() -> ! {
             }
         }
     });
+    // speech goes through the mixer, so it can play over (and duck) whatever else is playing
+    let mixer = mixer::Mixer::new(&xns).unwrap();
+    let stream = Arc::new(Mutex::new(
+        mixer.open_stream(StreamFormat::new(SAMPLE_RATE, Channels::Mono, SampleFormat::I16), mixer::Priority::Speech, 100)
+        .expect("couldn't open a mixer stream")
+    ));
     let cb_sid = xous::create_server().unwrap();
     let cb_cid = xous::connect(cb_sid).unwrap();
-    let just_initiated = Arc::new(AtomicBool::new(false));
     std::thread::spawn({
-        let wavbuf = wavbuf.clone();
-        let synth_done = synth_done.clone();
-        let just_initiated = just_initiated.clone();
+        let synth = synth.clone();
+        let stream = stream.clone();
         move || {
-            let mut codec = codec::Codec::new(&xous_names::XousNames::new().unwrap()).unwrap();
             let mut frame_count = 0;
             loop {
                 let msg = xous::receive_message(cb_sid).unwrap();
                 match FromPrimitive::from_usize(msg.body.id()) {
-                    Some(CallbackOp::Callback) => msg_scalar_unpack!(msg, free_play, _available_rec, _, routing_id, {
-                        if routing_id == codec::AUDIO_CB_ROUTING_ID {
-                            let mut frames: FrameRing = FrameRing::new();
-                            let frames_to_push = if frames.writeable_count() < free_play {
+                    Some(CallbackOp::Callback) => msg_scalar_unpack!(msg, free_frames, _, _, routing_id, {
+                        if routing_id == mixer::MIXER_CB_ROUTING_ID {
+                            let mut stream = stream.lock().unwrap();
+                            let mut frames = stream.frame_ring();
+                            let frames_to_push = if frames.writeable_count() < free_frames {
                                 frames.writeable_count()
                             } else {
-                                free_play
+                                free_frames
                             };
                             frame_count += frames_to_push as u32;
                             log::trace!("f{} p{}", frame_count, frames_to_push);
-                            let mut locked_buf = wavbuf.lock().unwrap();
-                            if just_initiated.load(Ordering::SeqCst) {
-                                // prevent stutter if the synth buffer isn't ready yet and we got an early fill request from the codec
-                                if locked_buf.len() < codec::FIFO_DEPTH {
-                                    for _ in 0..frames_to_push {
-                                        let frame: [u32; codec::FIFO_DEPTH] = [ZERO_PCM as u32 | (ZERO_PCM as u32) << 16; codec::FIFO_DEPTH];
-                                        frames.nq_frame(frame).unwrap();
+                            let mut synth = synth.lock().unwrap();
+                            if synth.just_initiated {
+                                // the synth buffer isn't ready yet; queue a frame of silence so the mixer keeps asking
+                                if synth.wavbuf.len() < SAMPLES_PER_FRAME && !synth.done {
+                                    if frames_to_push > 0 {
+                                        frames.nq_frame([0; FIFO_DEPTH]).unwrap();
+                                        stream.write_frames(&mut frames).unwrap();
                                     }
-                                    codec.swap_frames(&mut frames).unwrap();
                                     continue;
                                 } else {
-                                    just_initiated.store(false, Ordering::SeqCst);
+                                    synth.just_initiated = false;
                                 }
                             }
                             for _ in 0..frames_to_push {
-                                let mut frame: [u32; codec::FIFO_DEPTH] = [ZERO_PCM as u32 | (ZERO_PCM as u32) << 16; codec::FIFO_DEPTH];
-                                if locked_buf.len() >= frame.len() || synth_done.load(Ordering::SeqCst) {
-                                    for sample in frame.iter_mut() {
-                                        let samp = locked_buf.pop_front().unwrap_or(ZERO_PCM);
-                                        let left = samp as u16;
-                                        let right = samp as u16;
-                                        *sample = right as u32 | (left as u32) << 16;
+                                let mut frame: [u32; FIFO_DEPTH] = [0; FIFO_DEPTH];
+                                if synth.wavbuf.len() >= SAMPLES_PER_FRAME || (synth.done && synth.wavbuf.len() > 0) {
+                                    for word in frame.iter_mut() {
+                                        let earlier = synth.wavbuf.pop_front().unwrap_or(0);
+                                        let later = synth.wavbuf.pop_front().unwrap_or(0);
+                                        *word = earlier as u32 | (later as u32) << 16;
                                     }
                                     frames.nq_frame(frame).unwrap();
                                 } else {
@@ -157,10 +412,8 @@ fn xmain() -> ! {
                                     break;
                                 }
                             }
-                            codec.swap_frames(&mut frames).unwrap();
-                            // detect if the buffer is empty and the synthesizer has indicated it's finished
-                            if (locked_buf.len() == 0) && synth_done.load(Ordering::SeqCst) {
-                                codec.pause().unwrap();
+                            if !frames.is_empty() {
+                                stream.write_frames(&mut frames).unwrap();
                             }
                         }
                     }),
@@ -175,70 +428,175 @@ fn xmain() -> ! {
             }
         }
     });
-    let mut codec = codec::Codec::new(&xns).unwrap();
-    codec.setup_8k_stream().expect("couldn't setup stream");
-    tt.sleep_ms(50).unwrap();
+    stream.lock().unwrap().hook_frame_callback(CallbackOp::Callback.to_u32().unwrap(), cb_cid).unwrap();
+    // the mixer leaves the analog gain to whoever sets it; speech wants the speaker up full
+    let codec = codec::Codec::new(&xns).unwrap();
     codec.set_speaker_volume(VolumeOps::Set, Some(0.0)).unwrap();
     codec.set_headphone_volume(VolumeOps::RestoreDefault, None).unwrap();
-    codec.hook_frame_callback(CallbackOp::Callback.to_u32().unwrap(), cb_cid).unwrap();
 
-    let mut wpm = DEFAULT_WPM;
-    tts_be.tts_config(
+    // watches for each utterance to finish playing, so the main loop never has to wait on it
+    let drain_sid = xous::create_server().unwrap();
+    let drain_cid = xous::connect(drain_sid).unwrap();
+    std::thread::spawn({
+        let synth = synth.clone();
+        let stream = stream.clone();
+        move || {
+            let tt = ticktimer_server::Ticktimer::new().unwrap();
+            loop {
+                let msg = xous::receive_message(drain_sid).unwrap();
+                match FromPrimitive::from_usize(msg.body.id()) {
+                    Some(DrainOp::Watch) => msg_scalar_unpack!(msg, generation, _, _, _, {
+                        loop {
+                            // the frame callback locks the stream before the synth, so never hold both here
+                            let (current, buffered) = {
+                                let synth = synth.lock().unwrap();
+                                (synth.generation == generation as u32, synth.wavbuf.len())
+                            };
+                            if !current {
+                                break; // dropped while it played; the main loop has moved on
+                            }
+                            if buffered == 0 && stream.lock().unwrap().status().unwrap().0 == 0 {
+                                // a stale report is ignored by the main loop, so there's no need to check again
+                                send_message(tts_cid,
+                                    Message::new_scalar(Opcode::PlaybackDone.to_usize().unwrap(), generation, 0, 0, 0)
+                                ).unwrap();
+                                break;
+                            }
+                            tt.sleep_ms(WAIT_INTERVAL).unwrap();
+                        }
+                    }),
+                    Some(DrainOp::Quit) => {
+                        xous::return_scalar(msg.sender, 1).unwrap();
+                        break;
+                    },
+                    None => {
+                        log::error!("couldn't convert opcode: {:?}", msg);
+                    }
+                }
+            }
+        }
+    });
+
+    let mut speaker = Speaker {
+        queue: UtteranceQueue::new(),
+        synth,
+        stream,
+        tts_be,
+        wav_sid,
+        drain_cid,
+        voice: VoiceParams::default(),
+        wpm: VoiceParams::default().wpm,
+        subscribers: Default::default(),
+    };
+    speaker.tts_be.tts_config(
         wav_sid.to_array(),
         WaveOp::Return.to_u32().unwrap(),
         None,
-        Some(wpm)
+        Some(speaker.wpm)
     ).unwrap();
     loop {
-        let msg = xous::receive_message(tts_sid).unwrap();
+        let mut msg = xous::receive_message(tts_sid).unwrap();
         match FromPrimitive::from_usize(msg.body.id()) {
             Some(Opcode::TextToSpeech) => {
                 let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
-                let msg = buffer.to_original::<TtsFrontendMsg, _>().unwrap();
-                log::debug!("tts front end got string {}", msg.text.as_str().unwrap());
-                wavbuf.lock().unwrap().clear(); // this will truncate any buffered audio that is playing
-                synth_done.store(false, Ordering::SeqCst);
-                tts_be.tts_simple(msg.text.as_str().unwrap()).unwrap();
-                just_initiated.store(true, Ordering::SeqCst);
-                log::trace!("resuming codec");
-                codec.resume().unwrap();
+                let req = buffer.to_original::<TtsFrontendMsg, _>().unwrap();
+                log::debug!("tts front end got string {}", req.text.as_str().unwrap());
+                // this will truncate any buffered audio that is playing
+                speaker.enqueue(req.text.as_str().unwrap(), false, Priority::Normal, QueueMode::Flush, msg.sender.pid(), None);
             },
             Some(Opcode::TextToSpeechBlocking) => {
-                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
-                let msg = buffer.to_original::<TtsFrontendMsg, _>().unwrap();
-                log::debug!("tts blocking front end got string {}", msg.text.as_str().unwrap());
-                wavbuf.lock().unwrap().clear(); // this will truncate any buffered audio that is playing
-                synth_done.store(false, Ordering::SeqCst);
-                tts_be.tts_simple(msg.text.as_str().unwrap()).unwrap();
-                just_initiated.store(true, Ordering::SeqCst);
-                log::trace!("resuming codec (blocking)");
-                codec.resume().unwrap();
-                while !synth_done.load(Ordering::SeqCst) {
-                    // this is done fairly "fast" because the synth buf fills quickly
-                    tt.sleep_ms(WAIT_INTERVAL).unwrap();
-                }
-                while codec.is_running().unwrap() {
-                    // this actually waits until the playing is fully done
-                    tt.sleep_ms(WAIT_INTERVAL).unwrap();
-                }
+                let req = {
+                    let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                    buffer.to_original::<TtsFrontendMsg, _>().unwrap()
+                };
+                log::debug!("tts blocking front end got string {}", req.text.as_str().unwrap());
+                let pid = msg.sender.pid();
+                speaker.enqueue(req.text.as_str().unwrap(), false, Priority::Normal, QueueMode::Flush, pid, Some(msg));
+            },
+            Some(Opcode::Speak) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<UtteranceRequest, _>().unwrap();
+                req.id = speaker.enqueue(req.text.as_str().unwrap(), req.markup, req.priority, req.mode, msg.sender.pid(), None);
+                buffer.replace(req).unwrap();
             },
+            Some(Opcode::SpeakBlocking) => {
+                let req = {
+                    let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                    buffer.to_original::<UtteranceRequest, _>().unwrap()
+                };
+                let pid = msg.sender.pid();
+                speaker.enqueue(req.text.as_str().unwrap(), req.markup, req.priority, req.mode, pid, Some(msg));
+            },
+            Some(Opcode::Cancel) => msg_scalar_unpack!(msg, id, _, _, _, {
+                speaker.cancel(id as u32, msg.sender.pid());
+            }),
+            Some(Opcode::Flush) => msg_scalar_unpack!(msg, priority, _, _, _, {
+                speaker.flush(FromPrimitive::from_usize(priority).unwrap_or(Priority::Urgent));
+            }),
             Some(Opcode::CodecStop) => {
                 log::info!("stop called. Immediate stop and loss of audio data.");
-                codec.abort().unwrap();
+                speaker.flush(Priority::Urgent);
+            }
+            Some(Opcode::UtteranceSubscribe) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let hookdata = buffer.to_original::<ScalarHook, _>().unwrap();
+                let pid = match msg.sender.pid() {
+                    Some(pid) => pid,
+                    None => continue,
+                };
+                speaker.unsubscribe(pid);
+                match speaker.subscribers.iter_mut().find(|s| s.is_none()) {
+                    Some(slot) => {
+                        let (s0, s1, s2, s3) = hookdata.sid;
+                        *slot = Some(Subscriber {
+                            pid,
+                            server_to_cb_cid: xous::connect(xous::SID::from_u32(s0, s1, s2, s3)).unwrap(),
+                            cb_to_client_cid: hookdata.cid,
+                            cb_to_client_id: hookdata.id,
+                        });
+                    }
+                    None => log::warn!("too many utterance subscribers, ignoring {:?}", pid),
+                }
+            }
+            Some(Opcode::UtteranceUnsubscribe) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                if let Some(pid) = msg.sender.pid() {
+                    speaker.unsubscribe(pid);
+                }
+                xous::return_scalar(msg.sender, 0).unwrap();
+            }),
+            Some(Opcode::GetVoice) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                buffer.replace(speaker.voice).unwrap();
+            }
+            Some(Opcode::SetVoice) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                speaker.voice = buffer.to_original::<VoiceParams, _>().unwrap().clamped();
+                log::info!("voice set to {:?}", speaker.voice);
             }
             Some(Opcode::SetWordsPerMinute) => msg_scalar_unpack!(msg, wpm_arg, _, _, _, {
-                wpm = wpm_arg as u32;
-                tts_be.tts_config(wav_sid.to_array(), WaveOp::Return.to_u32().unwrap(), None, Some(wpm)).unwrap();
+                speaker.voice.wpm = wpm_arg as u32;
+                speaker.voice = speaker.voice.clamped();
+            }),
+            Some(Opcode::SynthDone) => msg_scalar_unpack!(msg, generation, _, _, _, {
+                speaker.synth_done(generation as u32);
+            }),
+            Some(Opcode::PlaybackDone) => msg_scalar_unpack!(msg, generation, _, _, _, {
+                speaker.playback_done(generation as u32);
             }),
             Some(Opcode::Quit) => {
+                speaker.flush(Priority::Urgent);
                 send_message(wav_cid,
                     Message::new_blocking_scalar(WaveOp::Quit.to_usize().unwrap(), 0, 0, 0, 0)
                 ).expect("couldn't send quit to callback handler");
                 send_message(cb_cid,
-                    Message::new_blocking_scalar(WaveOp::Quit.to_usize().unwrap(), 0, 0, 0, 0)
+                    Message::new_blocking_scalar(CallbackOp::Quit.to_usize().unwrap(), 0, 0, 0, 0)
                 ).expect("couldn't send quit to callback handler");
+                send_message(drain_cid,
+                    Message::new_blocking_scalar(DrainOp::Quit.to_usize().unwrap(), 0, 0, 0, 0)
+                ).expect("couldn't send quit to drain watcher");
                 unsafe{xous::disconnect(wav_cid).ok()};
                 unsafe{xous::disconnect(cb_cid).ok()};
+                unsafe{xous::disconnect(drain_cid).ok()};
                 log::warn!("Quit received, goodbye world!");
                 break;
             },
@@ -0,0 +1,453 @@
+//! A small subset of SSML, enough to pace and shape what's spoken:
+//!
+//! - `<break time="500ms"/>`, `<break time="1s"/>` or `<break strength="strong"/>` pauses.
+//! - `<emphasis level="strong|moderate|reduced">...</emphasis>` slows down, and raises the
+//!   pitch and volume, or the other way round for `reduced`.
+//! - `<prosody rate=".." pitch=".." volume="..">...</prosody>`, each either a percentage of
+//!   the current value (`80%`), a relative change (`+20%`, `-10%`) or a keyword (`slow`,
+//!   `high`, `loud`, ...).
+//! - `<say-as interpret-as="characters">...</say-as>`, or `<spell>...</spell>` for short, spells
+//!   the text out a character at a time.
+//! - `&lt;`, `&gt;`, `&amp;`, `&quot;`, `&apos;` and `&#NN;` for characters that would
+//!   otherwise be markup.
+//!
+//! Anything else in angle brackets, including `<speak>`, is ignored, keeping the text inside it.
+//! A `<` that doesn't start a tag is read as is.
+
+use std::fmt::Write;
+
+/// How to speak, relative to the voice settings, in percent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Prosody {
+    pub rate: u32,
+    pub pitch: u32,
+    pub volume: u32,
+}
+impl Default for Prosody {
+    fn default() -> Self {
+        Prosody { rate: 100, pitch: 100, volume: 100 }
+    }
+}
+impl Prosody {
+    fn clamped(self) -> Prosody {
+        Prosody {
+            rate: self.rate.max(25).min(400),
+            pitch: self.pitch.max(50).min(200),
+            volume: self.volume.min(200),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Segment {
+    Speak { text: String, prosody: Prosody },
+    /// silence, in milliseconds
+    Pause(u32),
+}
+
+/// The longest pause markup can ask for
+const MAX_PAUSE_MS: u32 = 10_000;
+const DEFAULT_BREAK_MS: u32 = 300;
+
+/// The whole of `text`, unmarked, as one segment
+pub(crate) fn plain(text: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    if !text.trim().is_empty() {
+        segments.push(Segment::Speak { text: text.to_string(), prosody: Prosody::default() });
+    }
+    segments
+}
+
+struct Parser {
+    segments: Vec<Segment>,
+    text: String,
+    /// the prosody in effect before each open tag
+    stack: Vec<(String, Prosody, bool)>,
+    prosody: Prosody,
+    spelling: bool,
+}
+
+impl Parser {
+    fn flush(&mut self) {
+        if self.text.trim().is_empty() {
+            self.text.clear();
+            return;
+        }
+        let text = std::mem::take(&mut self.text);
+        if let Some(Segment::Speak { text: last, prosody }) = self.segments.last_mut() {
+            if *prosody == self.prosody {
+                let text = text.trim();
+                if !text.starts_with(|c: char| c.is_ascii_punctuation()) {
+                    last.push(' ');
+                }
+                last.push_str(text);
+                return;
+            }
+        }
+        self.segments.push(Segment::Speak { text: text.trim().to_string(), prosody: self.prosody });
+    }
+    fn push_text(&mut self, text: &str) {
+        if self.spelling {
+            if !self.text.is_empty() && !text.is_empty() {
+                self.text.push_str(", ");
+            }
+            self.text.push_str(&spell_out(text));
+        } else {
+            self.text.push_str(text);
+        }
+    }
+    fn pause(&mut self, ms: u32) {
+        self.flush();
+        if ms == 0 {
+            return;
+        }
+        if let Some(Segment::Pause(last)) = self.segments.last_mut() {
+            *last = (*last + ms).min(MAX_PAUSE_MS);
+        } else {
+            self.segments.push(Segment::Pause(ms.min(MAX_PAUSE_MS)));
+        }
+    }
+    fn open(&mut self, name: &str, prosody: Prosody, spelling: bool) {
+        self.flush();
+        self.stack.push((name.to_string(), self.prosody, self.spelling));
+        self.prosody = prosody.clamped();
+        self.spelling = spelling;
+    }
+    fn close(&mut self, name: &str) {
+        // close back to the matching tag; a stray close tag is ignored
+        if let Some(pos) = self.stack.iter().rposition(|(n, _, _)| n == name) {
+            self.flush();
+            let (_, prosody, spelling) = self.stack[pos].clone();
+            self.stack.truncate(pos);
+            self.prosody = prosody;
+            self.spelling = spelling;
+        }
+    }
+    fn tag(&mut self, tag: &Tag) {
+        let current = self.prosody;
+        match (tag.name.as_str(), tag.closing) {
+            ("break", false) => {
+                let ms = match (tag.attr("time"), tag.attr("strength")) {
+                    (Some(t), _) => parse_time(t).unwrap_or(DEFAULT_BREAK_MS),
+                    (None, Some("none")) => 0,
+                    (None, Some("x-weak")) => 50,
+                    (None, Some("weak")) => 150,
+                    (None, Some("strong")) => 600,
+                    (None, Some("x-strong")) => 1000,
+                    _ => DEFAULT_BREAK_MS,
+                };
+                self.pause(ms);
+            }
+            ("emphasis", false) => {
+                let (rate, pitch, volume) = match tag.attr("level") {
+                    Some("strong") => (75, 115, 130),
+                    Some("reduced") => (110, 95, 80),
+                    Some("none") => (100, 100, 100),
+                    _ => (85, 110, 115),
+                };
+                if !tag.empty {
+                    self.open("emphasis", Prosody {
+                        rate: current.rate * rate / 100,
+                        pitch: current.pitch * pitch / 100,
+                        volume: current.volume * volume / 100,
+                    }, self.spelling);
+                }
+            }
+            ("prosody", false) => {
+                let mut p = current;
+                if let Some(v) = tag.attr("rate") {
+                    p.rate = relative(v, current.rate, &[("x-slow", 50), ("slow", 75), ("medium", 100), ("fast", 130), ("x-fast", 170)]);
+                }
+                if let Some(v) = tag.attr("pitch") {
+                    p.pitch = relative(v, current.pitch, &[("x-low", 70), ("low", 85), ("medium", 100), ("high", 115), ("x-high", 130)]);
+                }
+                if let Some(v) = tag.attr("volume") {
+                    p.volume = relative(v, current.volume, &[("silent", 0), ("x-soft", 40), ("soft", 70), ("medium", 100), ("loud", 130), ("x-loud", 160)]);
+                }
+                if !tag.empty {
+                    self.open("prosody", p, self.spelling);
+                }
+            }
+            ("say-as", false) => {
+                let spell = matches!(tag.attr("interpret-as"), Some("characters") | Some("spell-out") | Some("letters") | Some("digits"));
+                if !tag.empty {
+                    self.open("say-as", current, spell || self.spelling);
+                }
+            }
+            ("spell", false) => {
+                if !tag.empty {
+                    self.open("spell", current, true);
+                }
+            }
+            ("p", true) => {
+                self.pause(DEFAULT_BREAK_MS * 2);
+            }
+            (name, true) => self.close(name),
+            _ => {}
+        }
+    }
+}
+
+/// Splits marked up `text` into what to say and how
+pub(crate) fn parse(text: &str) -> Vec<Segment> {
+    let mut parser = Parser {
+        segments: Vec::new(),
+        text: String::new(),
+        stack: Vec::new(),
+        prosody: Prosody::default(),
+        spelling: false,
+    };
+    let mut rest = text;
+    while let Some(lt) = rest.find('<') {
+        parser.push_text(&decode_entities(&rest[..lt]));
+        match parse_tag(&rest[lt..]) {
+            Some((tag, len)) => {
+                parser.tag(&tag);
+                rest = &rest[lt + len..];
+            }
+            None => {
+                parser.push_text("<");
+                rest = &rest[lt + 1..];
+            }
+        }
+    }
+    parser.push_text(&decode_entities(rest));
+    parser.flush();
+    parser.segments
+}
+
+struct Tag {
+    name: String,
+    closing: bool,
+    /// `<tag/>`
+    empty: bool,
+    attrs: Vec<(String, String)>,
+}
+impl Tag {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+}
+
+/// Parses the tag at the start of `s`, returning it and its length
+fn parse_tag(s: &str) -> Option<(Tag, usize)> {
+    let end = s.find('>')?;
+    let inner = &s[1..end];
+    let (closing, inner) = match inner.strip_prefix('/') {
+        Some(i) => (true, i),
+        None => (false, inner),
+    };
+    let (empty, inner) = match inner.strip_suffix('/') {
+        Some(i) => (true, i),
+        None => (false, inner),
+    };
+    let name_len = inner.find(|c: char| c.is_whitespace()).unwrap_or(inner.len());
+    let name = &inner[..name_len];
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == ':') {
+        return None;
+    }
+    let mut attrs = Vec::new();
+    let mut rest = inner[name_len..].trim_start();
+    while !rest.is_empty() {
+        let eq = rest.find('=')?;
+        let key = rest[..eq].trim();
+        let after = rest[eq + 1..].trim_start();
+        let quote = after.chars().next().filter(|&c| c == '"' || c == '\'')?;
+        let close = after[1..].find(quote)?;
+        attrs.push((key.to_ascii_lowercase(), decode_entities(&after[1..1 + close])));
+        rest = after[close + 2..].trim_start();
+    }
+    Some((Tag { name: name.to_ascii_lowercase(), closing, empty, attrs }, end + 1))
+}
+
+fn decode_entities(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest.find(';').filter(|&semi| semi <= 8).and_then(|semi| {
+            let c = match &rest[1..semi] {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                num if num.starts_with("#x") => u32::from_str_radix(&num[2..], 16).ok().and_then(std::char::from_u32),
+                num if num.starts_with('#') => num[1..].parse().ok().and_then(std::char::from_u32),
+                _ => None,
+            };
+            c.map(|c| (c, semi + 1))
+        });
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// "250ms", "1.5s" or a plain number of milliseconds
+fn parse_time(s: &str) -> Option<u32> {
+    let s = s.trim();
+    let ms = if let Some(n) = s.strip_suffix("ms") {
+        n.trim().parse::<f32>().ok()?
+    } else if let Some(n) = s.strip_suffix('s') {
+        n.trim().parse::<f32>().ok()? * 1000.0
+    } else {
+        s.parse::<f32>().ok()?
+    };
+    if ms.is_finite() && ms >= 0.0 {
+        Some((ms as u32).min(MAX_PAUSE_MS))
+    } else {
+        None
+    }
+}
+
+/// A prosody value, applied to `current`
+fn relative(v: &str, current: u32, keywords: &[(&str, u32)]) -> u32 {
+    let v = v.trim();
+    if let Some(&(_, pct)) = keywords.iter().find(|(k, _)| *k == v) {
+        return current * pct / 100;
+    }
+    let number = v.strip_suffix('%').unwrap_or(v);
+    match number.parse::<i32>() {
+        Ok(n) if v.starts_with('+') || v.starts_with('-') => (current as i32 * (100 + n) / 100).max(0) as u32,
+        Ok(n) if n >= 0 => current * n as u32 / 100,
+        _ => current,
+    }
+}
+
+/// Names a character for spelling out, or `None` if it reads well enough as is
+fn char_name(c: char) -> Option<&'static str> {
+    Some(match c {
+        ' ' => "space",
+        '.' => "dot",
+        ',' => "comma",
+        ':' => "colon",
+        ';' => "semicolon",
+        '!' => "exclamation mark",
+        '?' => "question mark",
+        '@' => "at",
+        '#' => "hash",
+        '$' => "dollar",
+        '%' => "percent",
+        '&' => "ampersand",
+        '*' => "star",
+        '-' => "dash",
+        '_' => "underscore",
+        '+' => "plus",
+        '=' => "equals",
+        '/' => "slash",
+        '\\' => "backslash",
+        '\'' => "apostrophe",
+        '"' => "quote",
+        '(' => "open paren",
+        ')' => "close paren",
+        '[' => "open bracket",
+        ']' => "close bracket",
+        '{' => "open brace",
+        '}' => "close brace",
+        '<' => "less than",
+        '>' => "greater than",
+        '|' => "bar",
+        '~' => "tilde",
+        '^' => "caret",
+        '`' => "backtick",
+        _ => return None,
+    })
+}
+
+/// `text` a character at a time, e.g. "Ab1." as "capital A, b, 1, dot"
+pub(crate) fn spell_out(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        if c == '\n' || c == '\r' || c == '\t' {
+            continue;
+        }
+        if !out.is_empty() {
+            out.push_str(", ");
+        }
+        if c.is_uppercase() {
+            write!(out, "capital {}", c).unwrap();
+        } else if let Some(name) = char_name(c) {
+            out.push_str(name);
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn speak(text: &str, rate: u32, pitch: u32, volume: u32) -> Segment {
+        Segment::Speak { text: text.to_string(), prosody: Prosody { rate, pitch, volume } }
+    }
+
+    #[test]
+    fn breaks_and_plain_text() {
+        assert_eq!(parse("<speak>Hello <break time=\"500ms\"/> world</speak>"), vec![
+            speak("Hello", 100, 100, 100),
+            Segment::Pause(500),
+            speak("world", 100, 100, 100),
+        ]);
+        assert_eq!(parse("a<break/>b<break strength='strong'/><break time=\"1.5s\"/>c"), vec![
+            speak("a", 100, 100, 100),
+            Segment::Pause(300),
+            speak("b", 100, 100, 100),
+            Segment::Pause(2100),
+            speak("c", 100, 100, 100),
+        ]);
+        assert_eq!(parse("<break time=\"99s\"/>"), vec![Segment::Pause(MAX_PAUSE_MS)]);
+        assert_eq!(parse("   "), vec![]);
+        assert_eq!(plain("1 < 2 & <b>"), vec![speak("1 < 2 & <b>", 100, 100, 100)]);
+    }
+
+    #[test]
+    fn prosody_nests() {
+        let segments = parse("one <prosody rate=\"slow\" pitch=\"+20%\">two <emphasis>three</emphasis> four</prosody> five");
+        assert_eq!(segments, vec![
+            speak("one", 100, 100, 100),
+            speak("two", 75, 120, 100),
+            speak("three", 63, 132, 115),
+            speak("four", 75, 120, 100),
+            speak("five", 100, 100, 100),
+        ]);
+        // out of range values are clamped, and the same prosody merges into one segment
+        assert_eq!(parse("<prosody pitch=\"900%\" volume=\"x-loud\">a</prosody><prosody pitch=\"400%\">b</prosody>"), vec![
+            speak("a", 100, 200, 160),
+            speak("b", 100, 200, 100),
+        ]);
+        assert_eq!(parse("<prosody rate=\"50%\">a</prosody><prosody rate=\"-50%\">b</prosody>"), vec![
+            speak("a b", 50, 100, 100),
+        ]);
+    }
+
+    #[test]
+    fn spelling() {
+        assert_eq!(spell_out("Ab1.\n"), "capital A, b, 1, dot");
+        assert_eq!(parse("code <spell>x-9</spell>, ok"), vec![speak("code x, dash, 9, ok", 100, 100, 100)]);
+        assert_eq!(parse("<say-as interpret-as=\"characters\">hi</say-as>"), vec![speak("h, i", 100, 100, 100)]);
+        assert_eq!(parse("<say-as interpret-as=\"date\">today</say-as>"), vec![speak("today", 100, 100, 100)]);
+    }
+
+    #[test]
+    fn not_quite_markup() {
+        assert_eq!(parse("1 < 2 and 3 > 2"), vec![speak("1 < 2 and 3 > 2", 100, 100, 100)]);
+        assert_eq!(parse("&lt;b&gt; &amp; &#65;&#x42; &bogus; &"), vec![speak("<b> & AB &bogus; &", 100, 100, 100)]);
+        // unknown tags are dropped, stray closing tags too
+        assert_eq!(parse("<voice name=\"x\">hi</voice></emphasis> there"), vec![speak("hi there", 100, 100, 100)]);
+        // and a tag without its closing bracket is just text
+        assert_eq!(parse("a <break"), vec![speak("a <break", 100, 100, 100)]);
+    }
+}
@@ -0,0 +1,192 @@
+//! The order utterances get spoken in. Higher priorities go first; within a priority, first come
+//! first served. What gets interrupted or dropped is handed back to the caller, so it can tell
+//! whoever queued it.
+
+use std::collections::VecDeque;
+use crate::api::{Priority, QueueMode};
+
+#[derive(Debug)]
+pub(crate) struct Entry<T> {
+    pub id: u32,
+    pub priority: Priority,
+    pub payload: T,
+}
+
+#[derive(Debug)]
+pub(crate) struct UtteranceQueue<T> {
+    current: Option<Entry<T>>,
+    waiting: VecDeque<Entry<T>>,
+    next_id: u32,
+}
+
+impl<T> UtteranceQueue<T> {
+    pub fn new() -> Self {
+        UtteranceQueue { current: None, waiting: VecDeque::new(), next_id: 1 }
+    }
+
+    /// Queues `payload`, returning its ID and whatever it displaced. If the current utterance is
+    /// among those, `current()` is empty afterwards and the caller has to stop its playback.
+    ///
+    /// - `Enqueue` waits its turn, behind everything of the same or higher priority. An `Urgent`
+    ///   utterance does stop anything less than urgent that's playing, which is dropped.
+    /// - `Interrupt` stops the current utterance if it's not of a higher priority, and goes next.
+    ///   The rest of the queue stays.
+    /// - `Flush` is `Interrupt`, dropping everything queued at or below its priority as well.
+    pub fn push(&mut self, priority: Priority, mode: QueueMode, payload: T) -> (u32, Vec<Entry<T>>) {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        let mut dropped = Vec::new();
+        let preempts = match mode {
+            QueueMode::Enqueue => priority == Priority::Urgent,
+            QueueMode::Interrupt | QueueMode::Flush => true,
+        };
+        if preempts {
+            let stop = match self.current.as_ref() {
+                Some(c) => c.priority <= priority && !(mode == QueueMode::Enqueue && c.priority == Priority::Urgent),
+                None => false,
+            };
+            if stop {
+                dropped.push(self.current.take().unwrap());
+            }
+        }
+        if mode == QueueMode::Flush {
+            let (drop, keep): (VecDeque<_>, VecDeque<_>) = self.waiting.drain(..).partition(|e| e.priority <= priority);
+            dropped.extend(drop);
+            self.waiting = keep;
+        }
+        let entry = Entry { id, priority, payload };
+        let pos = match mode {
+            QueueMode::Enqueue => self.waiting.iter().position(|e| e.priority < priority),
+            _ => self.waiting.iter().position(|e| e.priority <= priority),
+        };
+        match pos {
+            Some(pos) => self.waiting.insert(pos, entry),
+            None => self.waiting.push_back(entry),
+        }
+        (id, dropped)
+    }
+
+    /// The utterance being spoken
+    pub fn current(&self) -> Option<&Entry<T>> {
+        self.current.as_ref()
+    }
+    pub fn current_mut(&mut self) -> Option<&mut Entry<T>> {
+        self.current.as_mut()
+    }
+
+    /// Moves the next utterance up, if nothing is being spoken. Returns it only if it's new.
+    pub fn start_next(&mut self) -> Option<&mut Entry<T>> {
+        if self.current.is_some() {
+            return None;
+        }
+        self.current = self.waiting.pop_front();
+        self.current.as_mut()
+    }
+
+    /// Takes the current utterance off the queue, when it's finished
+    pub fn finish_current(&mut self) -> Option<Entry<T>> {
+        self.current.take()
+    }
+
+    /// Takes the utterance `id` off the queue, wherever it is, provided `allowed` agrees that
+    /// its payload may be cancelled
+    pub fn cancel(&mut self, id: u32, allowed: impl Fn(&T) -> bool) -> Option<Entry<T>> {
+        if self.current.as_ref().map(|e| e.id == id && allowed(&e.payload)).unwrap_or(false) {
+            return self.current.take();
+        }
+        let pos = self.waiting.iter().position(|e| e.id == id && allowed(&e.payload))?;
+        self.waiting.remove(pos)
+    }
+
+    /// Takes everything at or below `priority` off the queue, the current utterance included
+    pub fn flush(&mut self, priority: Priority) -> Vec<Entry<T>> {
+        let mut dropped = Vec::new();
+        if self.current.as_ref().map(|c| c.priority <= priority).unwrap_or(false) {
+            dropped.push(self.current.take().unwrap());
+        }
+        let (drop, keep): (VecDeque<_>, VecDeque<_>) = self.waiting.drain(..).partition(|e| e.priority <= priority);
+        dropped.extend(drop);
+        self.waiting = keep;
+        dropped
+    }
+
+    pub fn len(&self) -> usize {
+        self.waiting.len() + self.current.is_some() as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(q: &mut UtteranceQueue<&'static str>) -> Vec<&'static str> {
+        let mut spoken = Vec::new();
+        while let Some(e) = q.start_next() {
+            spoken.push(e.payload);
+            q.finish_current();
+        }
+        spoken
+    }
+
+    #[test]
+    fn priority_then_arrival() {
+        let mut q = UtteranceQueue::new();
+        q.push(Priority::Normal, QueueMode::Enqueue, "n1");
+        q.push(Priority::Low, QueueMode::Enqueue, "l1");
+        q.push(Priority::High, QueueMode::Enqueue, "h1");
+        q.push(Priority::Normal, QueueMode::Enqueue, "n2");
+        assert_eq!(q.len(), 4);
+        assert_eq!(order(&mut q), vec!["h1", "n1", "n2", "l1"]);
+        assert_eq!(q.len(), 0);
+    }
+
+    #[test]
+    fn interrupt_and_flush() {
+        let mut q = UtteranceQueue::new();
+        let (first, _) = q.push(Priority::Normal, QueueMode::Enqueue, "n1");
+        q.push(Priority::Normal, QueueMode::Enqueue, "n2");
+        q.push(Priority::High, QueueMode::Enqueue, "h1");
+        assert_eq!(q.start_next().unwrap().payload, "h1");
+        // a normal interrupt doesn't stop something more important
+        let (_, dropped) = q.push(Priority::Normal, QueueMode::Interrupt, "n3");
+        assert!(dropped.is_empty());
+        assert_eq!(q.current().unwrap().payload, "h1");
+        // but it does go ahead of the other normal ones
+        q.finish_current();
+        assert_eq!(q.start_next().unwrap().payload, "n3");
+        let (_, dropped) = q.push(Priority::Normal, QueueMode::Interrupt, "n4");
+        assert_eq!(dropped.iter().map(|e| e.payload).collect::<Vec<_>>(), vec!["n3"]);
+        assert!(q.current().is_none());
+        assert_eq!(q.start_next().unwrap().payload, "n4");
+        q.push(Priority::High, QueueMode::Enqueue, "h2");
+        q.push(Priority::Low, QueueMode::Enqueue, "l1");
+        let (_, dropped) = q.push(Priority::Normal, QueueMode::Flush, "n5");
+        let mut dropped: Vec<_> = dropped.iter().map(|e| e.payload).collect();
+        dropped.sort();
+        assert_eq!(dropped, vec!["l1", "n1", "n2", "n4"]);
+        assert_eq!(order(&mut q), vec!["h2", "n5"]);
+        assert!(q.cancel(first, |_| true).is_none());
+    }
+
+    #[test]
+    fn urgent_and_cancel() {
+        let mut q = UtteranceQueue::new();
+        q.push(Priority::High, QueueMode::Enqueue, "h1");
+        let (n1, _) = q.push(Priority::Normal, QueueMode::Enqueue, "n1");
+        q.start_next();
+        let (u1, dropped) = q.push(Priority::Urgent, QueueMode::Enqueue, "u1");
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(q.start_next().unwrap().payload, "u1");
+        // one urgent message doesn't cut another short, unless it asks to
+        let (_, dropped) = q.push(Priority::Urgent, QueueMode::Enqueue, "u2");
+        assert!(dropped.is_empty());
+        assert_eq!(q.cancel(n1, |_| true).unwrap().payload, "n1");
+        // an utterance can be turned down for cancelling, and then stays where it is
+        assert!(q.cancel(u1, |&p| p != "u1").is_none());
+        assert_eq!(q.current().unwrap().payload, "u1");
+        assert_eq!(q.cancel(u1, |_| true).unwrap().payload, "u1");
+        assert!(q.current().is_none());
+        assert_eq!(q.flush(Priority::Normal).len(), 0);
+        assert_eq!(q.flush(Priority::Urgent).len(), 1);
+    }
+}
@@ -0,0 +1,34 @@
+// Bakes the authentication keys named by XOUS_AUTH_KEYS into the binary; see src/keytable.rs
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[path = "src/keytable.rs"]
+#[allow(dead_code)]
+mod keytable;
+
+// must agree with auth::MAX_AUTH_KEYS
+const MAX_AUTH_KEYS: usize = 8;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/keytable.rs");
+    println!("cargo:rerun-if-env-changed=XOUS_AUTH_KEYS");
+
+    let table = match env::var("XOUS_AUTH_KEYS") {
+        Ok(path) if !path.is_empty() => {
+            println!("cargo:rerun-if-changed={}", path);
+            let text = fs::read_to_string(&path).unwrap_or_else(|e| panic!("can't read key table {}: {}", path, e));
+            keytable::parse(&text, MAX_AUTH_KEYS).unwrap_or_else(|e| panic!("key table {}: {}", path, e))
+        }
+        _ => Vec::new(),
+    };
+
+    let mut out = String::from("pub(crate) const PROVISIONED: &[(&str, [u8; 32])] = &[\n");
+    for (name, key) in table.iter() {
+        out.push_str(&format!("    ({:?}, {:?}),\n", name, key));
+    }
+    out.push_str("];\n");
+    let dest = Path::new(&env::var("OUT_DIR").unwrap()).join("auth_keys.rs");
+    fs::write(dest, out).unwrap();
+}
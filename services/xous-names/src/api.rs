@@ -50,6 +50,14 @@ pub(crate) enum Opcode {
     /// }
     /// ```
     BlockingConnect = 6,
+
+    /// Set the policy for authenticated connections to a server, given its cryptographically unique SID
+    /// and an `AuthPolicy`. Replaces any earlier policy.
+    SetAuthPolicy = 7,
+
+    /// List the registered servers, a page at a time, in a `ServiceList`. Only processes that were
+    /// running before trusted init was done may ask.
+    ListServices = 8,
 }
 
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -87,18 +95,72 @@ pub(crate) struct Disconnect {
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub(crate) struct AuthenticatedLookup {
     pub name: xous_ipc::String<64>,
-    pub pubkey_id: [u8; 20], // 160-bit pubkey ID encoded in network order (big endian)
-    pub response: [u32; 8],
+    pub pubkey_id: [u8; 20], // 160-bit ID of the key the response is made with, see `auth::key_id()`
+    pub response: [u32; 8],  // HMAC-SHA256 of the challenge and name, see `auth::response()`
 }
 
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 #[repr(C)]
 pub(crate) struct AuthenticateRequest {
     pub name: xous_ipc::String<64>, // a copy of the originally requested lookup
-    pub pubkey_id: [u8; 20],        // 160-bit pubkey ID encoded in network order (big endian)
+    pub pubkey_id: [u8; 20],        // all zeros: any key in the server's policy will do
     pub challenge: [u32; 4],
 }
 
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct AuthPolicy {
+    pub sid: [u32; 4],
+    /// how many authenticated connections to allow, on top of the trusted ones
+    pub max_conns: u32,
+    pub key_count: u32,
+    pub keys: [[u8; 32]; crate::auth::MAX_AUTH_KEYS],
+}
+
+/// most servers returned by one `ListServices`
+pub(crate) const MAX_SERVICES_LISTED: usize = 16;
+
+#[derive(Debug, Copy, Clone, Default, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct ServiceEntry {
+    pub name: xous_ipc::String<64>,
+    pub pid: u8, // 0 if not known
+    pub conn_limit: Option<u32>,
+    pub conns: u32,
+    pub auth_limit: u32,
+    pub auth_conns: u32,
+    pub registered_ms: u64,
+    pub alive: bool,
+}
+
+/// One page of the name table, sorted by name, starting at entry `start`
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct ServiceList {
+    pub start: u32,
+    pub count: u32,
+    /// servers in the whole table
+    pub total: u32,
+    pub entries: [ServiceEntry; MAX_SERVICES_LISTED],
+    /// the caller isn't trusted to see the table
+    pub denied: bool,
+}
+
+/// A registered server, as listed by `list_services()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceInfo {
+    pub name: std::string::String,
+    /// the process that registered it
+    pub pid: Option<xous::PID>,
+    /// trusted connections allowed, if limited
+    pub conn_limit: Option<u32>,
+    pub conns: u32,
+    /// authenticated connections allowed on top of the trusted ones
+    pub auth_limit: u32,
+    pub auth_conns: u32,
+    /// ms after the name server started
+    pub registered_ms: u64,
+    /// the server still exists; a server whose process died without unregistering shows up as false
+    pub alive: bool,
+}
+
 //////////////////////////////////////////////////////////////////////////////////////////////
 // We keep XousServerName around because want to be able to index off the server name, without
 // burdening the Kernel String type with the Hash32 methods
@@ -0,0 +1,357 @@
+//! Challenge/response for authenticated lookups.
+//!
+//! A server that wants to admit vetted clients past its trusted connection limit publishes a policy:
+//! a set of 256-bit keys, and how many authenticated connections it will take. A client holding one
+//! of the keys asks for a connection, gets a one-time challenge, and answers with
+//! HMAC-SHA256(key, challenge || server name).
+//!
+//! `xous-names` can't use the `sha2` crate, because that's patched to the hardware hash engine, which
+//! itself needs `xous-names`; the few lookups that get here are done with the small software
+//! SHA-256 below.
+
+/// most keys a server can list in its policy; `build.rs` has a copy
+pub const MAX_AUTH_KEYS: usize = 8;
+/// most challenges that can be outstanding for one server; the oldest is dropped to make room
+const MAX_PENDING: usize = 16;
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    filled: usize,
+    len: u64,
+}
+
+impl Sha256 {
+    fn new() -> Self {
+        Sha256 {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: [0; 64],
+            filled: 0,
+            len: 0,
+        }
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, word) in self.block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+            *s = s.wrapping_add(*v);
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.len += data.len() as u64;
+        for &byte in data {
+            self.block[self.filled] = byte;
+            self.filled += 1;
+            if self.filled == 64 {
+                self.compress();
+                self.filled = 0;
+            }
+        }
+    }
+
+    fn finalize(mut self) -> [u8; 32] {
+        let bits = self.len * 8;
+        self.update(&[0x80]);
+        while self.filled != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        let mut digest = [0u8; 32];
+        for (out, s) in digest.chunks_exact_mut(4).zip(self.state.iter()) {
+            out.copy_from_slice(&s.to_be_bytes());
+        }
+        digest
+    }
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut block_key = [0u8; 64];
+    if key.len() > 64 {
+        block_key[..32].copy_from_slice(&sha256(key));
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+    let mut pad = [0u8; 64];
+    for (p, k) in pad.iter_mut().zip(block_key.iter()) {
+        *p = k ^ 0x36;
+    }
+    let mut inner = Sha256::new();
+    inner.update(&pad);
+    for part in parts {
+        inner.update(part);
+    }
+    for (p, k) in pad.iter_mut().zip(block_key.iter()) {
+        *p = k ^ 0x5c;
+    }
+    let mut outer = Sha256::new();
+    outer.update(&pad);
+    outer.update(&inner.finalize());
+    outer.finalize()
+}
+
+/// The ID a key goes by in lookups: the first 160 bits of its SHA-256 hash. It names the key without
+/// giving it away.
+pub fn key_id(key: &[u8; 32]) -> [u8; 20] {
+    let mut id = [0u8; 20];
+    id.copy_from_slice(&sha256(key)[..20]);
+    id
+}
+
+/// The answer to `challenge` from a client holding `key`, looking up `name`
+pub fn response(key: &[u8; 32], challenge: &[u32; 4], name: &str) -> [u32; 8] {
+    let mut challenge_bytes = [0u8; 16];
+    for (out, word) in challenge_bytes.chunks_exact_mut(4).zip(challenge.iter()) {
+        out.copy_from_slice(&word.to_le_bytes());
+    }
+    let mac = hmac_sha256(key, &[&challenge_bytes, name.as_bytes()]);
+    let mut response = [0u32; 8];
+    for (word, bytes) in response.iter_mut().zip(mac.chunks_exact(4)) {
+        *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    response
+}
+
+// the keys given to this build through XOUS_AUTH_KEYS
+include!(concat!(env!("OUT_DIR"), "/auth_keys.rs"));
+
+/// The keys baked in at build time for the server called `name`
+pub fn provisioned_keys(name: &str) -> Vec<[u8; 32]> {
+    PROVISIONED.iter().filter(|(n, _)| *n == name).map(|(_, key)| *key).collect()
+}
+
+struct Pending {
+    pid: u8,
+    challenge: [u32; 4],
+    issued_ms: u64,
+}
+
+/// A server's policy for authenticated connections, along with the challenges handed out under it
+pub struct Policy {
+    max_conns: u32,
+    /// the disconnection tokens of the connections admitted, one for each
+    tokens: Vec<[u32; 4]>,
+    keys: Vec<[u8; 32]>,
+    pending: Vec<Pending>,
+}
+
+// the keys stay out of the name table dumps
+impl core::fmt::Debug for Policy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Policy {{ {}/{} conns, {} keys, {} pending }}", self.tokens.len(), self.max_conns, self.keys.len(), self.pending.len())
+    }
+}
+
+impl Policy {
+    pub fn new(max_conns: u32, keys: &[[u8; 32]]) -> Policy {
+        Policy {
+            max_conns,
+            tokens: Vec::new(),
+            keys: keys.to_vec(),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn max_conns(&self) -> u32 {
+        self.max_conns
+    }
+
+    pub fn conns(&self) -> u32 {
+        self.tokens.len() as u32
+    }
+
+    /// Whether there's anything to challenge a client for
+    pub fn has_room(&self) -> bool {
+        !self.keys.is_empty() && self.conns() < self.max_conns
+    }
+
+    /// Records `challenge` as handed to `pid`. A process has one challenge at a time per server; a
+    /// new one replaces the last.
+    pub fn issue(&mut self, pid: u8, challenge: [u32; 4], now_ms: u64) {
+        self.pending.retain(|p| p.pid != pid);
+        if self.pending.len() >= MAX_PENDING {
+            self.pending.remove(0);
+        }
+        self.pending.push(Pending { pid, challenge, issued_ms: now_ms });
+    }
+
+    /// Checks `pid`'s answer to its challenge, made with the key that goes by `id`. The
+    /// challenge is used up either way; on success, the connection is counted against the policy
+    /// until `release()` is called with `token`.
+    pub fn admit(&mut self, pid: u8, id: &[u8; 20], answer: &[u32; 8], name: &str, now_ms: u64, token: [u32; 4]) -> bool {
+        let challenge = match self.pending.iter().position(|p| p.pid == pid) {
+            Some(i) => self.pending.remove(i),
+            None => return false,
+        };
+        if now_ms.saturating_sub(challenge.issued_ms) > crate::api::AUTHENTICATE_TIMEOUT as u64 || !self.has_room() {
+            return false;
+        }
+        // every key is tried, so the time taken doesn't tell which IDs are in the policy
+        let mut accepted = false;
+        for key in self.keys.iter() {
+            let expected = response(key, &challenge.challenge, name);
+            let same_id = key_id_matches(&key_id(key), id);
+            accepted |= same_id & constant_time_eq(&expected, answer);
+        }
+        if accepted {
+            self.tokens.push(token);
+        }
+        accepted
+    }
+
+    /// Frees the slot of the connection admitted with `token`. Tokens are good for one use.
+    pub fn release(&mut self, token: &[u32; 4]) -> bool {
+        match self.tokens.iter().position(|t| t == token) {
+            Some(i) => {
+                self.tokens.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn key_id_matches(a: &[u8; 20], b: &[u8; 20]) -> bool {
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn constant_time_eq(a: &[u32; 8], b: &[u32; 8]) -> bool {
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn sha256_vectors() {
+        assert_eq!(hex(&sha256(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            hex(&sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn hmac_vectors() {
+        // RFC 4231, test cases 2 and 6
+        assert_eq!(
+            hex(&hmac_sha256(b"Jefe", &[b"what do ya want ", b"for nothing?"])),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(&hmac_sha256(&[0xaa; 131], &[b"Test Using Larger Than Block-Size Key - Hash Key First"])),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    const KEY: [u8; 32] = [7; 32];
+    const OTHER: [u8; 32] = [9; 32];
+    const NAME: &str = "_Third-party gateway_";
+
+    #[test]
+    fn accepts_the_right_answer() {
+        let mut policy = Policy::new(2, &[OTHER, KEY]);
+        assert!(policy.has_room());
+        policy.issue(5, [1, 2, 3, 4], 1000);
+        assert!(policy.admit(5, &key_id(&KEY), &response(&KEY, &[1, 2, 3, 4], NAME), NAME, 2000, [10; 4]));
+        policy.issue(6, [5, 6, 7, 8], 3000);
+        assert!(policy.admit(6, &key_id(&OTHER), &response(&OTHER, &[5, 6, 7, 8], NAME), NAME, 3000, [11; 4]));
+        // both authenticated slots are taken now
+        assert!(!policy.has_room());
+        // until one of them is given back, once
+        assert!(!policy.release(&[12; 4]));
+        assert!(policy.release(&[10; 4]));
+        assert!(!policy.release(&[10; 4]));
+        assert_eq!(policy.conns(), 1);
+        assert!(policy.has_room());
+    }
+
+    #[test]
+    fn rejects_wrong_answers() {
+        let mut policy = Policy::new(4, &[KEY]);
+        let challenge = [1, 2, 3, 4];
+        let right = response(&KEY, &challenge, NAME);
+
+        // a key that isn't in the policy
+        policy.issue(5, challenge, 0);
+        assert!(!policy.admit(5, &key_id(&OTHER), &response(&OTHER, &challenge, NAME), NAME, 0, [1; 4]));
+        // the right answer, but the challenge was used up by the failed attempt
+        assert!(!policy.admit(5, &key_id(&KEY), &right, NAME, 0, [1; 4]));
+        // an answer for another server, or claiming another key's ID
+        policy.issue(5, challenge, 0);
+        assert!(!policy.admit(5, &key_id(&KEY), &response(&KEY, &challenge, "_Other_"), NAME, 0, [1; 4]));
+        policy.issue(5, challenge, 0);
+        assert!(!policy.admit(5, &key_id(&OTHER), &right, NAME, 0, [1; 4]));
+        // another process's challenge
+        policy.issue(5, challenge, 0);
+        assert!(!policy.admit(6, &key_id(&KEY), &right, NAME, 0, [1; 4]));
+        // too late
+        policy.issue(7, challenge, 0);
+        assert!(!policy.admit(7, &key_id(&KEY), &right, NAME, crate::api::AUTHENTICATE_TIMEOUT as u64 + 1, [1; 4]));
+        // a replaced challenge
+        policy.issue(8, challenge, 0);
+        policy.issue(8, [4, 3, 2, 1], 0);
+        assert!(!policy.admit(8, &key_id(&KEY), &right, NAME, 0, [1; 4]));
+        // and nothing got in
+        assert!(policy.has_room());
+        assert_eq!(policy.conns(), 0);
+    }
+
+    #[test]
+    fn no_keys_no_room() {
+        assert!(!Policy::new(4, &[]).has_room());
+        let mut full = Policy::new(0, &[KEY]);
+        full.issue(5, [1, 2, 3, 4], 0);
+        assert!(!full.admit(5, &key_id(&KEY), &response(&KEY, &[1, 2, 3, 4], NAME), NAME, 0, [1; 4]));
+    }
+}
@@ -0,0 +1,96 @@
+//! The build-time table of authentication keys.
+//!
+//! `build.rs` reads the file named by `XOUS_AUTH_KEYS`, and bakes its keys into the binary as
+//! `PROVISIONED`; see the README. Each line is a server name, then whitespace, then a 256-bit key in
+//! hex. Blank lines and lines starting with `#` are skipped.
+//!
+//! This file is shared with `build.rs`, so it only uses `std`.
+
+/// Parses a key table, allowing at most `max_keys` keys per server
+pub fn parse(text: &str, max_keys: usize) -> Result<Vec<(String, [u8; 32])>, String> {
+    let mut table: Vec<(String, [u8; 32])> = Vec::new();
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, hex) = match line.rsplit_once(char::is_whitespace) {
+            Some((name, hex)) => (name.trim(), hex),
+            None => return Err(format!("line {}: expected a server name and a key", lineno + 1)),
+        };
+        if name.is_empty() || name.len() > 64 {
+            return Err(format!("line {}: server names are 1 to 64 bytes long", lineno + 1));
+        }
+        let key = parse_key(hex).ok_or(format!("line {}: keys are 64 hex digits", lineno + 1))?;
+        if table.iter().any(|(n, k)| n == name && k == &key) {
+            return Err(format!("line {}: {} is listed twice for {}", lineno + 1, hex, name));
+        }
+        if table.iter().filter(|(n, _)| n == name).count() >= max_keys {
+            return Err(format!("line {}: {} has more than {} keys", lineno + 1, name, max_keys));
+        }
+        table.push((name.to_string(), key));
+    }
+    Ok(table)
+}
+
+fn parse_key(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut key = [0u8; 32];
+    for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(core::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{key_id, response, Policy, MAX_AUTH_KEYS};
+
+    const TABLE: &str = "
+# the gateway takes two apps' keys
+_Third-party gateway_ 0707070707070707070707070707070707070707070707070707070707070707
+_Third-party gateway_ 0909090909090909090909090909090909090909090909090909090909090909
+_Another server_  0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a
+";
+
+    fn keys_for(table: &[(String, [u8; 32])], name: &str) -> Vec<[u8; 32]> {
+        table.iter().filter(|(n, _)| n == name).map(|(_, k)| *k).collect()
+    }
+
+    #[test]
+    fn provisioned_keys_authenticate() {
+        let table = parse(TABLE, MAX_AUTH_KEYS).unwrap();
+        assert_eq!(table.len(), 3);
+        assert_eq!(keys_for(&table, "_Another server_"), vec![[0x0a; 32]]);
+
+        let name = "_Third-party gateway_";
+        let mut policy = Policy::new(1, &keys_for(&table, name));
+        // a key provisioned for another server is turned away
+        let elsewhere = [0x0a; 32];
+        policy.issue(5, [1, 2, 3, 4], 0);
+        assert!(!policy.admit(5, &key_id(&elsewhere), &response(&elsewhere, &[1, 2, 3, 4], name), name, 0, [1; 4]));
+        // and one provisioned for this one gets in
+        let key = [0x09; 32];
+        policy.issue(5, [5, 6, 7, 8], 0);
+        assert!(policy.admit(5, &key_id(&key), &response(&key, &[5, 6, 7, 8], name), name, 0, [1; 4]));
+    }
+
+    #[test]
+    fn bad_tables() {
+        assert_eq!(parse("", MAX_AUTH_KEYS), Ok(Vec::new()));
+        // no key, no name, a short key, a non-hex key
+        assert!(parse("_Server_", MAX_AUTH_KEYS).is_err());
+        assert!(parse(&format!("   {}", "07".repeat(32)), MAX_AUTH_KEYS).is_err());
+        assert!(parse("_Server_ 0707", MAX_AUTH_KEYS).is_err());
+        assert!(parse(&format!("_Server_ {}", "zz".repeat(32)), MAX_AUTH_KEYS).is_err());
+        // the same key twice, or more keys than a policy holds
+        let twice = format!("_Server_ {}\n_Server_ {}", "07".repeat(32), "07".repeat(32));
+        assert!(parse(&twice, MAX_AUTH_KEYS).is_err());
+        let three = format!("_Server_ {}\n_Server_ {}\n_Server_ {}", "07".repeat(32), "08".repeat(32), "09".repeat(32));
+        assert!(parse(&three, 2).is_err());
+        assert_eq!(parse(&three, 3).unwrap().len(), 3);
+    }
+}
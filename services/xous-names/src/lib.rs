@@ -3,6 +3,9 @@
 //! Detailed docs are parked under Structs/XousNames down below
 
 pub mod api;
+#[allow(dead_code)] // the policy side is only used by the server
+mod auth;
+pub use auth::MAX_AUTH_KEYS;
 
 use api::Disconnect;
 use core::fmt::Write;
@@ -93,7 +96,7 @@ impl XousNames {
 
         match buf.to_original().unwrap() {
             api::Return::CID((cid, token)) => Ok((cid, token)),
-            // api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
+            api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
             _ => Err(xous::Error::ServerNotFound),
         }
     }
@@ -123,7 +126,7 @@ impl XousNames {
 
         match buf.to_original().unwrap() {
             api::Return::CID((cid, _)) => Ok(cid),
-            // api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
+            api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
             _ => Err(xous::Error::ServerNotFound),
         }
     }
@@ -164,10 +167,145 @@ impl XousNames {
         }
     }
 
-    // todo:
-    // pub fn authenticated_connection(&self, name: &str, key: Authkey)
-    // this function will create an authenticated connection, if such are allowed
-    // it's intended for use by dynamically-loaded third-party apps. As of Xous 0.8 this isn't supported, so it's just a "todo"
+    /// Every registered server, sorted by name, with its connection counts and whether it's still
+    /// alive. Only processes that registered a server before trusted init was done may call this;
+    /// anybody else gets `AccessDenied`.
+    pub fn list_services(&self) -> Result<Vec<api::ServiceInfo>, xous::Error> {
+        let mut services = Vec::new();
+        loop {
+            let request = api::ServiceList {
+                start: services.len() as u32,
+                count: 0,
+                total: 0,
+                entries: [api::ServiceEntry::default(); api::MAX_SERVICES_LISTED],
+                denied: false,
+            };
+            let mut buf = Buffer::into_buf(request).or(Err(xous::Error::InternalError))?;
+            buf.lend_mut(self.conn, api::Opcode::ListServices.to_u32().unwrap())
+                .or(Err(xous::Error::InternalError))?;
+            let list = buf.to_original::<api::ServiceList, _>().or(Err(xous::Error::InternalError))?;
+            if list.denied {
+                return Err(xous::Error::AccessDenied);
+            }
+            for entry in list.entries[..(list.count as usize).min(api::MAX_SERVICES_LISTED)].iter() {
+                services.push(api::ServiceInfo {
+                    name: std::string::String::from(entry.name.as_str().unwrap_or("")),
+                    pid: xous::PID::new(entry.pid),
+                    conn_limit: entry.conn_limit,
+                    conns: entry.conns,
+                    auth_limit: entry.auth_limit,
+                    auth_conns: entry.auth_conns,
+                    registered_ms: entry.registered_ms,
+                    alive: entry.alive,
+                });
+            }
+            // the table can change between pages; stop on an empty one, rather than loop forever
+            if list.count == 0 || services.len() >= list.total as usize {
+                return Ok(services);
+            }
+        }
+    }
+
+    /// The name `pid` goes by, for servers that keep things on behalf of other processes: the first
+    /// name it registered that's still alive. Unlike a PID, it's the same from one boot to the next.
+    /// Processes that haven't registered a name have none. Like `list_services()`, only processes
+    /// that registered a server before trusted init was done may call this.
+    pub fn owner_name(&self, pid: xous::PID) -> Result<Option<std::string::String>, xous::Error> {
+        Ok(owner_name(pid, &self.list_services()?).map(std::string::String::from))
+    }
+
+    /// Connects to a server past its trusted connection limit, by proving knowledge of one of the keys
+    /// in the server's authentication policy. It's intended for vetted code that's loaded after boot,
+    /// such as third-party apps. Keys are normally provisioned at image build time; see
+    /// `request_provisioned_connection()`.
+    /// If the server still has a trusted slot free, that's taken instead. The connection holds its
+    /// slot for good; see `request_authenticated_connection_with_token()` to be able to give it back.
+    pub fn request_authenticated_connection(&self, name: &str, key: &[u8; 32]) -> Result<xous::CID, xous::Error> {
+        self.request_authenticated_connection_with_token(name, key).map(|(cid, _)| cid)
+    }
+    /// Like `request_authenticated_connection()`, but also returns the token that
+    /// `disconnect_with_token()` takes to free the connection's slot for someone else. There's only
+    /// a token for an authenticated connection, or for a trusted one to a single-connection server.
+    pub fn request_authenticated_connection_with_token(
+        &self,
+        name: &str,
+        key: &[u8; 32],
+    ) -> Result<(xous::CID, Option<[u32; 4]>), xous::Error> {
+        let mut lookup_name = xous_ipc::String::<64>::new();
+        write!(lookup_name, "{}", name).expect("name problably too long");
+        let mut buf = Buffer::into_buf(lookup_name).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, api::Opcode::Lookup.to_u32().unwrap())
+            .or(Err(xous::Error::InternalError))?;
+
+        let challenge = match buf.to_original().unwrap() {
+            api::Return::CID((cid, token)) => return Ok((cid, token)),
+            api::Return::AuthenticateRequest(request) => request.challenge,
+            _ => return Err(xous::Error::ServerNotFound),
+        };
+        let lookup = api::AuthenticatedLookup {
+            name: lookup_name,
+            pubkey_id: auth::key_id(key),
+            response: auth::response(key, &challenge, name),
+        };
+        let mut buf = Buffer::into_buf(lookup).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, api::Opcode::AuthenticatedLookup.to_u32().unwrap())
+            .or(Err(xous::Error::InternalError))?;
+
+        match buf.to_original().unwrap() {
+            api::Return::CID((cid, token)) => Ok((cid, token)),
+            _ => Err(xous::Error::AccessDenied),
+        }
+    }
+
+    /// Like `request_authenticated_connection()`, with the key this binary was built with for `name`
+    /// (see `XOUS_AUTH_KEYS` in the README). Without one, it's a plain `request_connection()`.
+    pub fn request_provisioned_connection(&self, name: &str) -> Result<xous::CID, xous::Error> {
+        match auth::provisioned_keys(name).first() {
+            Some(key) => self.request_authenticated_connection(name, key),
+            None => self.request_connection(name),
+        }
+    }
+
+    /// Lets clients that hold one of `keys` connect to the server registered as `sid` through
+    /// `request_authenticated_connection()`, up to `max_conns` of them on top of its trusted
+    /// connection limit. Replaces any earlier policy, which frees all of its slots; no keys turns
+    /// authenticated connections off.
+    pub fn set_auth_policy(&self, sid: xous::SID, max_conns: u32, keys: &[[u8; 32]]) -> Result<(), xous::Error> {
+        if keys.len() > MAX_AUTH_KEYS {
+            return Err(xous::Error::OutOfMemory);
+        }
+        let mut policy = api::AuthPolicy {
+            sid: sid.to_array(),
+            max_conns,
+            key_count: keys.len() as u32,
+            keys: [[0; 32]; MAX_AUTH_KEYS],
+        };
+        policy.keys[..keys.len()].copy_from_slice(keys);
+        let mut buf = Buffer::into_buf(policy).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, api::Opcode::SetAuthPolicy.to_u32().unwrap())
+            .or(Err(xous::Error::InternalError))?;
+
+        match buf.to_original().unwrap() {
+            api::Return::Success => Ok(()),
+            _ => Err(xous::Error::ServerNotFound),
+        }
+    }
+    /// Like `set_auth_policy()`, with the keys this binary was built with for the server called
+    /// `name` (see `XOUS_AUTH_KEYS` in the README). A build without any turns authenticated
+    /// connections off.
+    pub fn set_provisioned_auth_policy(&self, sid: xous::SID, name: &str, max_conns: u32) -> Result<(), xous::Error> {
+        self.set_auth_policy(sid, max_conns, &auth::provisioned_keys(name))
+    }
+}
+
+/// See `XousNames::owner_name()`. A server that's gone doesn't count, so whoever gets its PID next
+/// can't claim its name.
+pub fn owner_name(pid: xous::PID, services: &[api::ServiceInfo]) -> Option<&str> {
+    services
+        .iter()
+        .filter(|s| s.pid == Some(pid) && s.alive)
+        .min_by_key(|s| s.registered_ms)
+        .map(|s| s.name.as_str())
 }
 
 use core::sync::atomic::{AtomicU32, Ordering};
@@ -182,3 +320,36 @@ impl Drop for XousNames {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(name: &str, pid: u8, registered_ms: u64, alive: bool) -> api::ServiceInfo {
+        api::ServiceInfo {
+            name: std::string::String::from(name),
+            pid: xous::PID::new(pid),
+            conn_limit: None,
+            conns: 0,
+            auth_limit: 0,
+            auth_conns: 0,
+            registered_ms,
+            alive,
+        }
+    }
+
+    #[test]
+    fn owner_names() {
+        let services = [
+            service("messenger-ux", 9, 2000, true),
+            service("messenger", 9, 1500, true),
+            service("wallet", 10, 100, false),
+            service("wallet2", 10, 200, true),
+        ];
+        let pid = |p| xous::PID::new(p).unwrap();
+        assert_eq!(owner_name(pid(9), &services), Some("messenger"));
+        // a dead registration can't be claimed by whoever gets its PID next
+        assert_eq!(owner_name(pid(10), &services), Some("wallet2"));
+        assert_eq!(owner_name(pid(11), &services), None);
+    }
+}
@@ -3,6 +3,9 @@
 
 mod api;
 use api::*;
+mod auth;
+#[cfg(test)]
+mod keytable;
 
 use num_traits::FromPrimitive;
 use xous::{msg_blocking_scalar_unpack, MessageEnvelope};
@@ -104,14 +107,15 @@ I'll take safety over speed in this case.
 Eventually, we shall endeavor to remove Heapless entirely, once we have a `libstd` in place
 and we can use heap-allocated Rust primitives...
 */
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug)]
 struct Connection {
     pub sid: xous::SID,
     pub current_conns: u32, // number of unauthenticated (inherentely trusted) connections
     pub max_conns: Option<u32>, // if None, unlimited connections allowed
-    pub _allow_authenticate: bool,
-    pub _auth_conns: u32,        // number of authenticated connections
+    pub auth: Option<auth::Policy>, // if None, no authenticated connections allowed
     pub token: Option<[u32; 4]>, // a random number that must be presented to allow for disconnection for single-connection servers
+    pub pid: Option<xous::PID>, // the process that registered the server
+    pub registered_ms: u64,
 }
 #[derive(Debug)]
 struct CheckedHashMap {
@@ -128,6 +132,8 @@ impl CheckedHashMap {
         name: XousServerName,
         sid: xous::SID,
         max_conns: Option<u32>,
+        pid: Option<xous::PID>,
+        registered_ms: u64,
     ) -> Result<(), xous::Error> {
         let token = if max_conns == Some(1) {
             // for the special case of 1-connection servers, provision a one-time use token for disconnects
@@ -145,9 +151,10 @@ impl CheckedHashMap {
                 sid,
                 current_conns: 0,
                 max_conns,
-                _allow_authenticate: false, // for now, we don't support authenticated connections
-                _auth_conns: 0,
+                auth: None,
                 token,
+                pid,
+                registered_ms,
             },
         );
         Ok(())
@@ -205,6 +212,78 @@ impl CheckedHashMap {
         }
     }
 
+    /// Sets the authentication policy of the server whose SID is `sid`. Like `remove()`, this takes
+    /// the SID as proof that the caller is the server.
+    pub fn set_policy(&mut self, sid: xous::SID, policy: auth::Policy) -> Option<XousServerName> {
+        for (name, mapping) in self.map.iter_mut() {
+            if mapping.sid == sid {
+                mapping.auth = Some(policy);
+                return Some(*name);
+            }
+        }
+        None
+    }
+
+    /// If the server takes authenticated connections and has room for another, hands `pid` the
+    /// `fresh` random number as a challenge to answer.
+    pub fn challenge(&mut self, name: &XousServerName, pid: u8, now_ms: u64, fresh: [u32; 4]) -> Option<[u32; 4]> {
+        let policy = self.map.get_mut(name)?.auth.as_mut()?;
+        if !policy.has_room() {
+            return None;
+        }
+        policy.issue(pid, fresh, now_ms);
+        Some(fresh)
+    }
+
+    /// Checks `pid`'s answer to its challenge, returning the SID to connect it to if it's right. The
+    /// connection holds one of the server's authenticated slots until it's disconnected with `token`,
+    /// which should be a fresh random number.
+    pub fn authenticate(&mut self, name: &XousServerName, pid: u8, lookup: &AuthenticatedLookup, now_ms: u64, token: [u32; 4]) -> Option<xous::SID> {
+        let entry = self.map.get_mut(name)?;
+        let policy = entry.auth.as_mut()?;
+        if policy.admit(pid, &lookup.pubkey_id, &lookup.response, name.to_str(), now_ms, token) {
+            Some(entry.sid)
+        } else {
+            None
+        }
+    }
+
+    /// Describes the servers from the `start`th on, in name order, up to `MAX_SERVICES_LISTED` of them.
+    /// Returns them along with the number of servers in the table.
+    pub fn list(&self, start: usize) -> (Vec<ServiceEntry>, usize) {
+        let mut names: Vec<&XousServerName> = self.map.keys().collect();
+        names.sort_by(|a, b| a.to_str().cmp(b.to_str()));
+        let entries = names
+            .iter()
+            .skip(start)
+            .take(MAX_SERVICES_LISTED)
+            .map(|name| {
+                let entry = &self.map[*name];
+                let alive = is_alive(entry.sid);
+                ServiceEntry {
+                    name: String::<64>::from_str(name.to_str()),
+                    pid: entry.pid.map(|p| p.get()).unwrap_or(0),
+                    conn_limit: entry.max_conns,
+                    conns: entry.current_conns,
+                    auth_limit: entry.auth.as_ref().map(|a| a.max_conns()).unwrap_or(0),
+                    auth_conns: entry.auth.as_ref().map(|a| a.conns()).unwrap_or(0),
+                    registered_ms: entry.registered_ms,
+                    alive,
+                }
+            })
+            .collect();
+        (entries, names.len())
+    }
+
+    /// Whether `pid` is in the boot set: it registered a server by `closed_ms`, when trusted init was
+    /// done, and that server is still `alive`. Servers go away with their process, so a process that
+    /// gets a PID back after it's freed doesn't inherit anything from whoever had it before.
+    pub fn in_boot_set(&self, pid: u8, closed_ms: u64, alive: impl Fn(xous::SID) -> bool) -> bool {
+        self.map.values().any(|entry| {
+            entry.pid.map(|p| p.get()) == Some(pid) && entry.registered_ms <= closed_ms && alive(entry.sid)
+        })
+    }
+
     pub fn trusted_init_done(&self) -> bool {
         let mut trusted_done = true;
         for (name, entry) in self.map.iter() {
@@ -241,9 +320,15 @@ impl CheckedHashMap {
     }
 
     // this is a safer version of disconnect. we track servers that allow exactly one connection at a time
-    // and give them a one-time-use token that a connector can use to disconnect.
+    // and give them a one-time-use token that a connector can use to disconnect. Authenticated connections
+    // each get a token of their own, too.
     pub fn disconnect_with_token(&mut self, name: &XousServerName, token: [u32; 4]) -> bool {
         if let Some(entry) = self.map.get_mut(name) {
+            if let Some(policy) = entry.auth.as_mut() {
+                if policy.release(&token) {
+                    return true;
+                }
+            }
             if let Some(old_token) = entry.token {
                 if (token == old_token) && (entry.current_conns == 1) {
                     (*entry).current_conns = 0;
@@ -352,6 +437,21 @@ fn respond_connect_success(mut msg: MessageEnvelope, cid: xous::CID, disc: Optio
     mem.offset = None;
 }
 
+fn now_ms(start: &std::time::Instant) -> u64 {
+    start.elapsed().as_millis() as u64
+}
+
+/// A server that's gone can't be connected to; anything else gets disconnected right away
+fn is_alive(sid: xous::SID) -> bool {
+    match xous::try_connect(sid) {
+        Ok(cid) => {
+            unsafe { xous::disconnect(cid).ok() };
+            true
+        }
+        Err(_) => false,
+    }
+}
+
 #[xous::xous_main]
 fn xmain() -> ! {
     use implementation::*;
@@ -371,6 +471,12 @@ fn xmain() -> ! {
     // this limits the number of available servers to be requested to 128...!
     //let mut name_table = FnvIndexMap::<XousServerName, xous::SID, 128>::new();
     let mut name_table = CheckedHashMap::new();
+    // challenges for authenticated connections, and registrations, are timed from here
+    let start = std::time::Instant::now();
+    // Every process that registered a server before trusted init was done is part of the boot set, and
+    // may look at the name table. Untrusted code isn't to be run until trusted init is done, so the
+    // first time somebody is told it is, the set is closed.
+    let mut trusted_init_closed_ms: Option<u64> = None;
 
     info!("started");
     loop {
@@ -378,6 +484,7 @@ fn xmain() -> ! {
         log::trace!("received message: {:?}", msg);
         match FromPrimitive::from_usize(msg.body.id()) {
             Some(api::Opcode::Register) => {
+                let sender_pid = msg.sender.pid();
                 let mem = msg.body.memory_message_mut().unwrap();
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
                 let registration = buffer.to_original::<Registration, _>().unwrap();
@@ -396,7 +503,7 @@ fn xmain() -> ! {
                     let new_sid =
                         xous::create_server_id().expect("create server failed, maybe OOM?");
                     name_table
-                        .insert(name, new_sid, registration.conn_limit)
+                        .insert(name, new_sid, registration.conn_limit, sender_pid, now_ms(&start))
                         .expect("register name failure, maybe out of HashMap capacity?");
                     log::trace!("request successful, SID is {:?}", new_sid);
                     should_connect = true;
@@ -505,43 +612,119 @@ fn xmain() -> ! {
                         }
                     }
                 } else {
-                    log::debug!("Can't find request '{}' in table, dumping table:", name);
-                    for (_name, conn) in name_table.map.iter() {
-                        log::debug!("{:?}", conn);
+                    let sender_pid = msg
+                        .sender
+                        .pid()
+                        .expect("can't extract sender PID on Lookup");
+                    let fresh = xous::create_server_id().expect("couldn't create challenge").to_array();
+                    if let Some(challenge) = name_table.challenge(&name, sender_pid.get(), now_ms(&start), fresh) {
+                        // the trusted slots are taken, but the server takes authenticated connections
+                        log::trace!("Lookup for '{}' needs authentication", name);
+                        response = api::Return::AuthenticateRequest(AuthenticateRequest {
+                            name: name_string,
+                            pubkey_id: [0; 20],
+                            challenge,
+                        });
+                    } else {
+                        log::debug!("Can't find request '{}' in table, dumping table:", name);
+                        for (_name, conn) in name_table.map.iter() {
+                            log::debug!("{:?}", conn);
+                        }
+                        d11ctimeout.hosted_delay();
+                        response = api::Return::Failure
                     }
-                    // no authenticate remedy currently supported, but we'd put that code somewhere around here eventually.
-                    let (c1, c2, c3, c4) = xous::create_server_id().unwrap().to_u32();
-                    let auth_request = AuthenticateRequest {
-                        name: String::<64>::from_str(
-                            name_string
-                                .as_str()
-                                .expect("couldn't convert server name to string"),
-                        ),
-                        pubkey_id: [0; 20], // placeholder
-                        challenge: [c1, c2, c3, c4],
-                    };
-                    d11ctimeout.hosted_delay();
-                    response = api::Return::AuthenticateRequest(auth_request) // this code just exists to exercise the return path
                 }
                 buffer
                     .replace(response)
                     .expect("Lookup can't serialize return value");
             }
             Some(api::Opcode::AuthenticatedLookup) => {
+                let sender_pid = msg
+                    .sender
+                    .pid()
+                    .expect("can't extract sender PID on AuthenticatedLookup");
+                let mem = msg.body.memory_message_mut().unwrap();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
+                let auth_lookup = buffer.to_original::<AuthenticatedLookup, _>().unwrap();
+                let name = XousServerName::from_str(
+                    auth_lookup
+                        .name
+                        .as_str()
+                        .expect("couldn't convert server name to string"),
+                );
+                log::trace!("AuthenticatedLookup request for '{}'", name);
+                let mut response = api::Return::Failure;
+                let token = xous::create_server_id().expect("couldn't create token").to_array();
+                if let Some(server_sid) = name_table.authenticate(&name, sender_pid.get(), &auth_lookup, now_ms(&start), token) {
+                    match xous::connect_for_process(sender_pid, server_sid) {
+                        Ok(xous::Result::ConnectionID(connection_id)) => {
+                            info!("process {:?} authenticated to '{}'", sender_pid, name);
+                            response = api::Return::CID((connection_id, Some(token)))
+                        }
+                        result => {
+                            log::error!("error when making authenticated connection: {:?}", result);
+                            name_table.disconnect_with_token(&name, token);
+                        }
+                    }
+                } else {
+                    info!("authentication to '{}' failed, waiting for deterministic timeout", name);
+                    d11ctimeout.deterministic_busy_wait();
+                }
+                buffer
+                    .replace(response)
+                    .expect("AuthenticatedLookup can't serialize return value");
+            }
+            Some(api::Opcode::SetAuthPolicy) => {
                 let mem = msg.body.memory_message_mut().unwrap();
-                let buffer = unsafe { Buffer::from_memory_message_mut(mem) };
-                let auth_lookup: AuthenticatedLookup = buffer.to_original().unwrap();
-                info!("AuthenticatedLookup request {:?}", auth_lookup);
-                error!("AuthenticatedLookup not yet implemented");
-                unimplemented!("AuthenticatedLookup not yet implemented");
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
+                let request = buffer.to_original::<AuthPolicy, _>().unwrap();
+                let sid = xous::SID::from_array(request.sid);
+                let key_count = (request.key_count as usize).min(auth::MAX_AUTH_KEYS);
+                let policy = auth::Policy::new(request.max_conns, &request.keys[..key_count]);
+                let response = if let Some(name) = name_table.set_policy(sid, policy) {
+                    info!("{} takes up to {} authenticated connections with {} keys", name, request.max_conns, key_count);
+                    api::Return::Success
+                } else {
+                    log::error!("couldn't set an authentication policy for {:?}", sid);
+                    api::Return::Failure
+                };
+                buffer.replace(response).expect("SetAuthPolicy can't serialize return value");
             }
             Some(api::Opcode::TrustedInitDone) => {
                 if name_table.trusted_init_done() {
+                    if trusted_init_closed_ms.is_none() {
+                        info!("trusted init done, the boot set is closed");
+                        trusted_init_closed_ms = Some(now_ms(&start));
+                    }
                     xous::return_scalar(msg.sender, 1).expect("couldn't return trusted_init_done");
                 } else {
                     xous::return_scalar(msg.sender, 0).expect("couldn't return trusted_init_done");
                 }
             }
+            Some(api::Opcode::ListServices) => {
+                let trusted = match (msg.sender.pid(), trusted_init_closed_ms) {
+                    (Some(pid), Some(closed_ms)) => name_table.in_boot_set(pid.get(), closed_ms, is_alive),
+                    // until trusted init is done, only trusted code is running
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                };
+                let mem = msg.body.memory_message_mut().unwrap();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
+                let mut list = buffer.to_original::<ServiceList, _>().unwrap();
+                if trusted {
+                    let (entries, total) = name_table.list(list.start as usize);
+                    list.entries[..entries.len()].copy_from_slice(&entries);
+                    list.count = entries.len() as u32;
+                    list.total = total as u32;
+                    list.denied = false;
+                } else {
+                    log::warn!("ListServices request from untrusted process {:?} denied", msg.sender.pid());
+                    list.count = 0;
+                    list.total = 0;
+                    list.denied = true;
+                }
+                buffer.replace(list).expect("ListServices can't serialize return value");
+            }
             Some(api::Opcode::Disconnect) => {
                 let mem = msg.body.memory_message_mut().unwrap();
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
@@ -566,3 +749,102 @@ fn xmain() -> ! {
     log::trace!("quitting");
     xous::terminate_process(0);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+    const NAME: &str = "_Third-party gateway_";
+
+    /// A server whose two trusted slots are taken, with room for one authenticated connection
+    fn gateway() -> (CheckedHashMap, XousServerName, xous::SID) {
+        let mut table = CheckedHashMap::new();
+        let name = XousServerName::from_str(NAME);
+        let sid = xous::SID::from_u32(1, 2, 3, 4);
+        table.insert(name, sid, Some(2), None, 0).unwrap();
+        assert!(table.connect(&name).0.is_some());
+        assert!(table.connect(&name).0.is_some());
+        assert!(table.set_policy(sid, auth::Policy::new(1, &[KEY])).is_some());
+        (table, name, sid)
+    }
+
+    /// What a `Lookup` followed by an `AuthenticatedLookup` does, for process `pid` holding `key`
+    fn lookup(table: &mut CheckedHashMap, name: &XousServerName, pid: u8, key: &[u8; 32], fresh: [u32; 4], token: [u32; 4]) -> Option<xous::SID> {
+        assert_eq!(table.connect(name), (None, None));
+        let challenge = table.challenge(name, pid, 100, fresh)?;
+        let answer = AuthenticatedLookup {
+            name: String::<64>::from_str(NAME),
+            pubkey_id: auth::key_id(key),
+            response: auth::response(key, &challenge, NAME),
+        };
+        table.authenticate(name, pid, &answer, 200, token)
+    }
+
+    fn auth_conns(table: &CheckedHashMap, name: &XousServerName) -> u32 {
+        table.map[name].auth.as_ref().unwrap().conns()
+    }
+
+    #[test]
+    fn authenticated_slots_come_back_on_disconnect() {
+        let (mut table, name, sid) = gateway();
+        assert_eq!(lookup(&mut table, &name, 5, &KEY, [1; 4], [10; 4]), Some(sid));
+        assert_eq!(auth_conns(&table, &name), 1);
+        // the one slot is taken, so there's nothing to challenge for
+        assert_eq!(lookup(&mut table, &name, 6, &KEY, [2; 4], [11; 4]), None);
+
+        // a made-up token frees nothing, the real one frees the slot once
+        assert!(!table.disconnect_with_token(&name, [11; 4]));
+        assert!(table.disconnect_with_token(&name, [10; 4]));
+        assert!(!table.disconnect_with_token(&name, [10; 4]));
+        assert_eq!(auth_conns(&table, &name), 0);
+        // and the trusted connections are left alone
+        assert_eq!(table.map[&name].current_conns, 2);
+
+        assert_eq!(lookup(&mut table, &name, 6, &KEY, [3; 4], [12; 4]), Some(sid));
+        assert_eq!(auth_conns(&table, &name), 1);
+    }
+
+    #[test]
+    fn rejected_challenges_take_no_slot() {
+        let (mut table, name, sid) = gateway();
+        assert_eq!(lookup(&mut table, &name, 5, &[9; 32], [1; 4], [10; 4]), None);
+        assert_eq!(auth_conns(&table, &name), 0);
+        assert!(!table.disconnect_with_token(&name, [10; 4]));
+
+        // the challenge was used up by the wrong answer, so the right one is too late
+        let late = AuthenticatedLookup {
+            name: String::<64>::from_str(NAME),
+            pubkey_id: auth::key_id(&KEY),
+            response: auth::response(&KEY, &[1; 4], NAME),
+        };
+        assert_eq!(table.authenticate(&name, 5, &late, 200, [11; 4]), None);
+        // as is an answer from a process that wasn't challenged
+        table.challenge(&name, 5, 100, [2; 4]).unwrap();
+        let stolen = AuthenticatedLookup {
+            name: String::<64>::from_str(NAME),
+            pubkey_id: auth::key_id(&KEY),
+            response: auth::response(&KEY, &[2; 4], NAME),
+        };
+        assert_eq!(table.authenticate(&name, 6, &stolen, 200, [12; 4]), None);
+        assert_eq!(auth_conns(&table, &name), 0);
+
+        assert_eq!(lookup(&mut table, &name, 5, &KEY, [3; 4], [13; 4]), Some(sid));
+    }
+
+    #[test]
+    fn boot_set_is_live_servers_registered_at_boot() {
+        let mut table = CheckedHashMap::new();
+        let boot = xous::SID::from_u32(1, 1, 1, 1);
+        let late = xous::SID::from_u32(2, 2, 2, 2);
+        table.insert(XousServerName::from_str("_Boot_"), boot, None, xous::PID::new(3), 10).unwrap();
+        table.insert(XousServerName::from_str("_Late_"), late, None, xous::PID::new(4), 500).unwrap();
+        let all_alive = |_| true;
+        assert!(table.in_boot_set(3, 100, all_alive));
+        // registered after trusted init was done, or never registered at all
+        assert!(!table.in_boot_set(4, 100, all_alive));
+        assert!(!table.in_boot_set(5, 100, all_alive));
+        // once the boot process is gone, a process that gets its PID back isn't trusted
+        assert!(!table.in_boot_set(3, 100, |sid| sid != boot));
+    }
+}
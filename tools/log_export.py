@@ -0,0 +1,91 @@
+#! /usr/bin/env python3
+
+# Pulls the persistent log off a device. Run `log export` in shellchat while this is
+# listening on the serial console, or pass it a capture of the console with --input.
+
+import argparse
+import sys
+import time
+
+BEGIN = '=== xous log export begin ==='
+END = '=== xous log export end ==='
+
+def lines_from_serial(port, baud, timeout):
+    import serial
+    with serial.Serial(port, baud, timeout=1) as ser:
+        pending = b''
+        deadline = time.time() + timeout
+        while time.time() < deadline:
+            pending += ser.read(4096)
+            while b'\n' in pending:
+                line, pending = pending.split(b'\n', 1)
+                yield line.decode('utf-8', errors='replace').rstrip('\r')
+
+def lines_from_file(f):
+    for line in f:
+        yield line.rstrip('\r\n')
+
+def extract(lines, first_only=False):
+    """Returns the lines of the last complete export (or the first, if `first_only`), or None if there isn't one"""
+    export = None
+    current = None
+    for line in lines:
+        # the marker can follow other output on the same line
+        if line.endswith(BEGIN):
+            current = []
+        elif line.endswith(END) and current is not None:
+            # the end marker follows the last segment, which may not end in a newline
+            tail = line[:-len(END)]
+            if tail:
+                current.append(tail)
+            export = current
+            current = None
+            if first_only:
+                break
+        elif current is not None:
+            current.append(line)
+    return export
+
+def main():
+    parser = argparse.ArgumentParser(description="Save the persistent log exported by a device")
+    parser.add_argument(
+        "-p", "--port", help="serial port the console is on, e.g. /dev/ttyUSB0", type=str
+    )
+    parser.add_argument(
+        "-b", "--baud", help="serial port baud rate", default=115200, type=int
+    )
+    parser.add_argument(
+        "-t", "--timeout", help="seconds to wait for an export on the serial port", default=120, type=int
+    )
+    parser.add_argument(
+        "-i", "--input", help="a console capture to read instead of a serial port ('-' for stdin)", type=str
+    )
+    parser.add_argument(
+        "-o", "--output", help="file to save the log to; defaults to stdout", type=str
+    )
+    args = parser.parse_args()
+
+    if args.port is not None:
+        print("Waiting for `log export` on {}...".format(args.port), file=sys.stderr)
+        export = extract(lines_from_serial(args.port, args.baud, args.timeout), first_only=True)
+    elif args.input == '-' or args.input is None:
+        export = extract(lines_from_file(sys.stdin))
+    else:
+        with open(args.input, 'r', errors='replace') as f:
+            export = extract(lines_from_file(f))
+
+    if export is None:
+        print("No complete log export found", file=sys.stderr)
+        exit(1)
+
+    text = '\n'.join(export) + '\n'
+    if args.output is None:
+        sys.stdout.write(text)
+    else:
+        with open(args.output, 'w') as f:
+            f.write(text)
+        print("Saved {} lines to {}".format(len(export), args.output), file=sys.stderr)
+
+if __name__ == "__main__":
+    main()
+    exit(0)
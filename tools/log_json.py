@@ -0,0 +1,104 @@
+#! /usr/bin/env python3
+
+# Filters and summarizes a log written as JSON lines: from a hosted run started with
+# XOUS_LOG_FORMAT=json, a UART capture after `log format json`, or a `log export`.
+# Lines that aren't JSON records (panics, stdout, the log server's own notes) are skipped,
+# unless --keep-other is given.
+
+import argparse
+import json
+import sys
+from collections import Counter
+
+LEVELS = ['ERR', 'WARN', 'INFO', 'DBG', 'TRCE']
+
+def records(f, keep_other):
+    for line in f:
+        line = line.rstrip('\r\n')
+        # the record can follow other output on the same line
+        start = line.find('{"')
+        if start >= 0:
+            try:
+                yield json.loads(line[start:])
+                continue
+            except json.JSONDecodeError:
+                pass
+        if keep_other and line:
+            yield line
+
+def matches(r, args):
+    if args.process is not None and r.get('process') not in args.process:
+        return False
+    if args.pid is not None and r.get('pid') not in args.pid:
+        return False
+    if args.level is not None:
+        level = r.get('level', '')
+        if level in LEVELS and LEVELS.index(level) > LEVELS.index(args.level):
+            return False
+    if args.module is not None:
+        module = r.get('module', '')
+        if not any(module == m or module.startswith(m + '::') for m in args.module):
+            return False
+    for kv in args.kv or []:
+        key, _, value = kv.partition('=')
+        fields = r.get('kv', {})
+        if key not in fields or (value and fields[key] != value):
+            return False
+    if args.since is not None and r.get('ts', 0) < args.since:
+        return False
+    return True
+
+def to_text(r):
+    s = ''
+    if 'ts' in r:
+        s += '{}.{:03} '.format(r['ts'] // 1000, r['ts'] % 1000)
+    if 'pid' in r:
+        s += '{}[{}] '.format(r.get('process', ''), r['pid'])
+    s += '{}:{}: {}'.format(r.get('level', ''), r.get('module', ''), r.get('msg', ''))
+    for k, v in r.get('kv', {}).items():
+        s += ' {}={}'.format(k, json.dumps(v) if (' ' in v or v == '') else v)
+    s += ' ({}'.format(r.get('file', ''))
+    if 'line' in r:
+        s += ':{}'.format(r['line'])
+    return s + ')'
+
+def main():
+    parser = argparse.ArgumentParser(description="Filter and summarize a Xous log written as JSON lines")
+    parser.add_argument("input", nargs='?', help="log to read; defaults to stdin", type=str)
+    parser.add_argument("-p", "--process", help="only these processes", action='append', type=str)
+    parser.add_argument("--pid", help="only these PIDs", action='append', type=int)
+    parser.add_argument("-l", "--level", help="only this level and more severe", choices=LEVELS, type=str)
+    parser.add_argument("-m", "--module", help="only this module and those under it", action='append', type=str)
+    parser.add_argument("-k", "--kv", help="only records with this field, or KEY=VALUE for this value", action='append', type=str)
+    parser.add_argument("--since", help="only records stamped at or after this many ms", type=int)
+    parser.add_argument("--json", help="write matching records as JSON lines rather than text", action='store_true')
+    parser.add_argument("--stats", help="count matching records by process and level, rather than listing them", action='store_true')
+    parser.add_argument("--keep-other", help="pass through lines that aren't records", action='store_true')
+    args = parser.parse_args()
+
+    f = open(args.input, 'r', errors='replace') if args.input else sys.stdin
+    counts = Counter()
+    for r in records(f, args.keep_other and not args.stats):
+        if isinstance(r, str):
+            print(r)
+            continue
+        if not matches(r, args):
+            continue
+        if args.stats:
+            counts[(r.get('process', '?'), r.get('pid', 0), r.get('level', '?'))] += 1
+        elif args.json:
+            print(json.dumps(r))
+        else:
+            print(to_text(r))
+
+    if args.stats:
+        by_process = Counter()
+        for (process, pid, _), n in counts.items():
+            by_process[(process, pid)] += n
+        for (process, pid), total in by_process.most_common():
+            levels = ', '.join('{} {}'.format(counts[(process, pid, l)], l) for l in LEVELS if counts[(process, pid, l)])
+            print('{:>24}[{}]: {} records ({})'.format(process, pid, total, levels))
+
+if __name__ == "__main__":
+    main()
+    exit(0)
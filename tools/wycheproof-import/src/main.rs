@@ -5,22 +5,28 @@ use std::path::Path;
 use std::process::exit;
 
 use eyre::{bail, Result, WrapErr};
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 
 #[derive(Deserialize)]
-struct TestData {
+struct Header {
+    algorithm: String,
+}
+
+#[derive(Deserialize)]
+struct TestData<T> {
     #[serde(rename(deserialize = "testGroups"))]
-    test_groups: Vec<TestGroup>,
+    test_groups: Vec<TestGroup<T>>,
 }
 
 #[derive(Deserialize)]
-struct TestGroup {
-    tests: Vec<TestCase>,
+struct TestGroup<T> {
+    tests: Vec<T>,
 }
 
 #[serde_with::serde_as]
 #[derive(Debug, Deserialize)]
-struct TestCase {
+struct X25519TestCase {
     #[serde(rename(deserialize = "tcId"))]
     id: usize,
     #[serde_as(as = "serde_with::hex::Hex")]
@@ -32,6 +38,65 @@ struct TestCase {
     result: String,
 }
 
+#[serde_with::serde_as]
+#[derive(Debug, Deserialize)]
+struct HmacTestCase {
+    #[serde_as(as = "serde_with::hex::Hex")]
+    key: Vec<u8>,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    msg: Vec<u8>,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    tag: Vec<u8>,
+    result: String,
+}
+
+#[serde_with::serde_as]
+#[derive(Debug, Deserialize)]
+struct HkdfTestCase {
+    #[serde_as(as = "serde_with::hex::Hex")]
+    ikm: Vec<u8>,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    salt: Vec<u8>,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    info: Vec<u8>,
+    size: u32,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    okm: Vec<u8>,
+    result: String,
+}
+
+#[serde_with::serde_as]
+#[derive(Debug, Deserialize)]
+struct AeadTestCase {
+    #[serde_as(as = "serde_with::hex::Hex")]
+    key: Vec<u8>,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    iv: Vec<u8>,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    aad: Vec<u8>,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    msg: Vec<u8>,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    ct: Vec<u8>,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    tag: Vec<u8>,
+    result: String,
+}
+
+#[serde_with::serde_as]
+#[derive(Debug, Deserialize)]
+struct CbcTestCase {
+    #[serde_as(as = "serde_with::hex::Hex")]
+    key: Vec<u8>,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    iv: Vec<u8>,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    msg: Vec<u8>,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    ct: Vec<u8>,
+    result: String,
+}
+
 fn main() -> Result<()> {
     let args: Vec<String> = env::args().collect();
     if args.len() != 3 {
@@ -45,14 +110,30 @@ fn main() -> Result<()> {
         "Could not read test data from '{}'",
         input_file_path.to_string_lossy()
     ))?;
-    let test_data: TestData =
-        serde_json::from_str(&test_data).wrap_err("Error parsing test vectors")?;
+    let header: Header =
+        serde_json::from_str(&test_data).wrap_err("Error parsing test vector header")?;
 
-    let expected_results = vec!["valid".to_string(), "acceptable".to_string()];
     let mut output_file = std::fs::File::create(output_file_path).wrap_err(format!(
         "Error creating output file '{}'",
         output_file_path.to_string_lossy()
     ))?;
+
+    match header.algorithm.as_str() {
+        "XDH" => write_x25519(&parse(&test_data)?, &mut output_file),
+        "HMACSHA256" | "HMACSHA512" => write_hmac(&parse(&test_data)?, &mut output_file),
+        "HKDF-SHA-256" | "HKDF-SHA-512" => write_hkdf(&parse(&test_data)?, &mut output_file),
+        "AES-GCM" | "AES-GCM-SIV" => write_aead(&parse(&test_data)?, &mut output_file),
+        "AES-CBC-PKCS5" => write_cbc(&parse(&test_data)?, &mut output_file),
+        other => bail!("Don't know how to import '{}' test vectors", other),
+    }
+}
+
+fn parse<T: DeserializeOwned>(test_data: &str) -> Result<TestData<T>> {
+    serde_json::from_str(test_data).wrap_err("Error parsing test vectors")
+}
+
+fn write_x25519(test_data: &TestData<X25519TestCase>, output_file: &mut impl Write) -> Result<()> {
+    let expected_results = vec!["valid".to_string(), "acceptable".to_string()];
     let mut last_id = 0;
 
     for test_case in &test_data.test_groups[0].tests {
@@ -73,3 +154,92 @@ fn main() -> Result<()> {
     }
     Ok(())
 }
+
+/// The HMAC, HKDF and AES vectors come in all lengths, so each record is a result byte (1 for valid,
+/// 0 for invalid) followed by its fields, each preceded by its length as a little-endian u32.
+/// "acceptable" cases are left out, as there's no single right answer for them.
+fn write_record(output_file: &mut impl Write, result: &str, fields: &[&[u8]]) -> Result<()> {
+    let valid = match result {
+        "valid" => 1u8,
+        "invalid" => 0u8,
+        "acceptable" => return Ok(()),
+        other => bail!("Unexpected test case result '{}'", other),
+    };
+    output_file.write_all(&[valid])?;
+    for field in fields {
+        output_file.write_all(&(field.len() as u32).to_le_bytes())?;
+        output_file.write_all(field)?;
+    }
+    Ok(())
+}
+
+fn write_hmac(test_data: &TestData<HmacTestCase>, output_file: &mut impl Write) -> Result<()> {
+    for group in &test_data.test_groups {
+        for test_case in &group.tests {
+            write_record(output_file, &test_case.result, &[&test_case.key, &test_case.msg, &test_case.tag])?;
+        }
+    }
+    Ok(())
+}
+
+fn write_hkdf(test_data: &TestData<HkdfTestCase>, output_file: &mut impl Write) -> Result<()> {
+    for group in &test_data.test_groups {
+        for test_case in &group.tests {
+            write_record(
+                output_file,
+                &test_case.result,
+                &[
+                    &test_case.ikm,
+                    &test_case.salt,
+                    &test_case.info,
+                    &test_case.size.to_le_bytes(),
+                    &test_case.okm,
+                ],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn write_aead(test_data: &TestData<AeadTestCase>, output_file: &mut impl Write) -> Result<()> {
+    for group in &test_data.test_groups {
+        for test_case in &group.tests {
+            write_record(
+                output_file,
+                &test_case.result,
+                &[&test_case.key, &test_case.iv, &test_case.aad, &test_case.msg, &test_case.ct, &test_case.tag],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn write_cbc(test_data: &TestData<CbcTestCase>, output_file: &mut impl Write) -> Result<()> {
+    for group in &test_data.test_groups {
+        for test_case in &group.tests {
+            write_record(output_file, &test_case.result, &[&test_case.key, &test_case.iv, &test_case.msg, &test_case.ct])?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_read_back() {
+        let mut out = Vec::new();
+        write_record(&mut out, "valid", &[b"key", b"", b"tag"]).unwrap();
+        write_record(&mut out, "acceptable", &[b"k", b"m", b"t"]).unwrap();
+        write_record(&mut out, "invalid", &[b"k2", b"msg", &[0u8; 300]]).unwrap();
+        assert!(write_record(&mut out, "unknown", &[]).is_err());
+        assert_eq!(
+            wycheproof_records::parse(&out, 3),
+            vec![
+                (true, vec![b"key".to_vec(), vec![], b"tag".to_vec()]),
+                (false, vec![b"k2".to_vec(), b"msg".to_vec(), vec![0u8; 300]]),
+            ]
+        );
+    }
+}
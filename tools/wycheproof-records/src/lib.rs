@@ -0,0 +1,43 @@
+//! Reads back the HMAC, HKDF and AES test vectors that `wycheproof-import` compiles. The crates
+//! that test against them take this one as a dev-dependency.
+
+use std::path::Path;
+
+/// A test's expected result (true for valid) and its fields, in the order they were written
+pub type Record = (bool, Vec<Vec<u8>>);
+
+/// The records in `src/wycheproof/name` of the crate at `manifest_dir` (the crate being tested
+/// passes its `CARGO_MANIFEST_DIR`), each with `fields` fields. A missing or empty file fails the
+/// test, rather than letting it pass without checking anything.
+pub fn records(manifest_dir: &str, name: &str, fields: usize) -> Vec<Record> {
+    let path = Path::new(manifest_dir).join("src/wycheproof").join(name);
+    let data = std::fs::read(&path).unwrap_or_else(|_| {
+        panic!("{} not found; see tools/wycheproof-import/README.md to create it", path.display())
+    });
+    let records = parse(&data, fields);
+    assert!(!records.is_empty(), "{} has no records", path.display());
+    records
+}
+
+pub fn parse(data: &[u8], fields: usize) -> Vec<Record> {
+    let mut records = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let valid = data[pos] == 1;
+        pos += 1;
+        let mut record = Vec::new();
+        for _ in 0..fields {
+            let mut len = [0u8; 4];
+            len.copy_from_slice(&data[pos..pos + 4]);
+            let len = u32::from_le_bytes(len) as usize;
+            record.push(data[pos + 4..pos + 4 + len].to_vec());
+            pos += 4 + len;
+        }
+        records.push((valid, record));
+    }
+    records
+}
+
+pub fn unhex(s: &str) -> Vec<u8> {
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+}
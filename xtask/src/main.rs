@@ -47,6 +47,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "shellchat",
         "ime-frontend",
         "ime-plugin-shell",
+        "ime-plugin-predict",
+        "ime-plugin-cjk",
         "graphics-server",
         "ticktimer-server",
         "log-server",
@@ -57,6 +59,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "llio",
         "susres",
         "codec",
+        "mixer",
         "sha2:0.9.8",
         "engine-25519",
         "spinor",
@@ -65,7 +68,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "net",
         "dns",
         "pddb",
+        "log-store",
         "modals",
+        "ed25519-signer",
+        "alarms",
     ];
     let app_pkgs = [
         // "standard" demo apps
@@ -373,7 +379,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let mut args = env::args();
             args.nth(1);
             let mut pkgs = hw_pkgs.to_vec();
-            let mut apps: Vec<String> = args.collect();
+            let mut apps: Vec<String> = take_hosted_options(args.collect());
             if apps.len() == 0 {
                 // add the standard demo apps if none are specified
                 println!("No apps specified, adding default apps...");
@@ -388,7 +394,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
         Some("hosted-ci") => {
             let mut pkgs = hw_pkgs.to_vec();
-            let mut apps: Vec<String> = args.collect();
+            let mut apps: Vec<String> = take_hosted_options(args.collect());
             apps.push("ball".to_string());
             apps.push("repl".to_string());
             for app in &apps {
@@ -401,7 +407,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let mut args = env::args();
             args.nth(1);
             let mut pkgs = hw_pkgs.to_vec();
-            let mut apps: Vec<String> = args.collect();
+            let mut apps: Vec<String> = take_hosted_options(args.collect());
             if apps.len() == 0 {
                 // add the standard demo apps if none are specified
                 println!("No apps specified, adding default apps...");
@@ -576,6 +582,8 @@ Hardware images:
 
 Hosted emulation:
  run [app1] [..]         runs a release build using a hosted environment plus specified apps
+     [--trng-seed=<n|os>] seeds the hosted TRNG with n, or with OS entropy; the seed used is logged at boot
+     [--susres-sim]       hosted servers take part in suspend/resume; try it with `sleep sim <ms>` in the shell
 
 Renode emulation:
  renode-image            builds a functional image for renode
@@ -604,7 +612,9 @@ Various debug configurations:
  ro-test [soc.svd]       builds an image for ring oscillator only TRNG testing
  av-test [soc.svd]       builds an image for avalanche generater only TRNG testing
  sr-test [soc.svd]       builds the suspend/resume testing image
- wycheproof-import       generate binary test vectors for engine-25519 from whycheproof-import/x25519.json
+ wycheproof-import       generate binary test vectors for engine-25519 from whycheproof-import/x25519.json,
+                         and for HMAC/HKDF in engine-sha512 and the AES modes from any hmac_*/hkdf_*/aes_*
+                         vectors fetched alongside it
  pddb-dev                PDDB testing only for live hardware
  pddb-hosted             PDDB testing in a hosted environment
  pddb-ci                 PDDB config for CI testing (eg: TRNG->deterministic for reproducible errors)
@@ -959,6 +969,26 @@ fn renode_image(
     )
 }
 
+/// Pulls the hosted-mode options out of the app list, and hands them on through the environment
+/// the kernel (and so every process) inherits:
+///   `--trng-seed=<n|os>` seeds the hosted TRNG
+///   `--susres-sim` has the hosted servers take part in simulated suspend/resume cycles
+fn take_hosted_options(args: Vec<String>) -> Vec<String> {
+    let mut rest = Vec::new();
+    for arg in args {
+        if let Some(seed) = arg.strip_prefix("--trng-seed=") {
+            println!("Hosted TRNG seed: {}", seed);
+            env::set_var("XOUS_TRNG_SEED", seed);
+        } else if arg == "--susres-sim" {
+            println!("Simulating suspend/resume");
+            env::set_var("XOUS_SUSRES_SIM", "1");
+        } else {
+            rest.push(arg);
+        }
+    }
+    rest
+}
+
 fn run(
     debug: bool,
     init: &[&str],
@@ -1425,25 +1455,47 @@ fn generate_locales() -> Result<(), std::io::Error> {
 }
 
 fn whycheproof_import() -> Result<(), DynError> {
-    let input_file = "tools/wycheproof-import/x25519_test.json";
-    let output_file = "services/shellchat/src/cmds/x25519_test.bin";
-    let status = Command::new(cargo())
-        .current_dir(project_root())
-        .args(&[
-            "run",
-            "--package",
-            "wycheproof-import",
-            "--",
-            input_file,
-            output_file,
-        ])
-        .status()?;
-    if !status.success() {
-        return Err("wycheproof-import failed. If any, the output will not be usable.".into());
-    }
+    // (input, output, required): every set of vectors has to be there; the unit tests that use them
+    // fail without them.
+    let imports = [
+        ("tools/wycheproof-import/x25519_test.json", "services/shellchat/src/cmds/x25519_test.bin", true),
+        ("tools/wycheproof-import/hmac_sha256_test.json", "services/engine-sha512/src/wycheproof/hmac_sha256_test.bin", true),
+        ("tools/wycheproof-import/hmac_sha512_test.json", "services/engine-sha512/src/wycheproof/hmac_sha512_test.bin", true),
+        ("tools/wycheproof-import/hkdf_sha256_test.json", "services/engine-sha512/src/wycheproof/hkdf_sha256_test.bin", true),
+        ("tools/wycheproof-import/hkdf_sha512_test.json", "services/engine-sha512/src/wycheproof/hkdf_sha512_test.bin", true),
+        ("tools/wycheproof-import/aes_gcm_test.json", "services/aes/src/wycheproof/aes_gcm_test.bin", true),
+        ("tools/wycheproof-import/aes_gcm_siv_test.json", "services/aes/src/wycheproof/aes_gcm_siv_test.bin", true),
+        ("tools/wycheproof-import/aes_cbc_pkcs5_test.json", "services/aes/src/wycheproof/aes_cbc_pkcs5_test.bin", true),
+    ];
+    for (input_file, output_file, required) in imports.iter() {
+        if !project_root().join(input_file).exists() {
+            if *required {
+                return Err(format!("{} is missing", input_file).into());
+            }
+            println!("Skipping '{}', it hasn't been fetched.", input_file);
+            continue;
+        }
+        if let Some(dir) = project_root().join(output_file).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let status = Command::new(cargo())
+            .current_dir(project_root())
+            .args(&[
+                "run",
+                "--package",
+                "wycheproof-import",
+                "--",
+                input_file,
+                output_file,
+            ])
+            .status()?;
+        if !status.success() {
+            return Err("wycheproof-import failed. If any, the output will not be usable.".into());
+        }
 
-    println!();
-    println!("Wrote wycheproof x25519 testvectors to '{}'.", output_file);
+        println!();
+        println!("Wrote wycheproof testvectors from '{}' to '{}'.", input_file, output_file);
+    }
 
     return Ok(());
 }